@@ -0,0 +1,96 @@
+//! Renders the flow of a task as a graph: a node per step labelled with its action, an edge from each
+//! dependency to the steps waiting on it, and dashed links to the `on_error` action of a step.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use stroem_common::workflows_configuration::{FlowStep, Task};
+
+/// Steps sorted by id, and the distinct `on_error` actions they link to, so the output is stable.
+struct Graph<'a> {
+    steps: BTreeMap<&'a str, &'a FlowStep>,
+    error_actions: Vec<&'a str>,
+}
+
+impl<'a> Graph<'a> {
+    fn of(task: &'a Task) -> Self {
+        let steps: BTreeMap<&str, &FlowStep> = task.flow.iter().map(|(id, step)| (id.as_str(), step)).collect();
+        let mut error_actions: Vec<&str> = steps.values().filter_map(|step| step.on_error.as_deref()).collect();
+        error_actions.sort();
+        error_actions.dedup();
+        Graph { steps, error_actions }
+    }
+
+    fn label(id: &str, step: &FlowStep) -> (String, String) {
+        (step.name.clone().unwrap_or_else(|| id.to_string()), step.action.clone())
+    }
+
+    /// Dependencies of each step, sorted, as (dependency, step) pairs.
+    fn edges(&self) -> Vec<(&'a str, &'a str)> {
+        let mut edges: Vec<(&str, &str)> = self.steps.iter()
+            .flat_map(|(id, step)| step.depends_on.iter().flatten().map(move |dependency| (dependency.as_str(), *id)))
+            .collect();
+        edges.sort();
+        edges
+    }
+}
+
+pub fn dot(task_id: &str, task: &Task) -> String {
+    fn escape(s: &str) -> String {
+        s.replace('\\', "\\\\").replace('"', "\\\"")
+    }
+    fn quote(s: &str) -> String {
+        format!("\"{}\"", escape(s))
+    }
+
+    let graph = Graph::of(task);
+    let mut out = String::new();
+    let _ = writeln!(out, "digraph {} {{", quote(task_id));
+    let _ = writeln!(out, "    node [shape=box];");
+    for (id, step) in &graph.steps {
+        let (name, action) = Graph::label(id, step);
+        let _ = writeln!(out, "    {} [label=\"{}\\n{}\"];", quote(id), escape(&name), escape(&action));
+    }
+    for action in &graph.error_actions {
+        let _ = writeln!(out, "    {} [label={}, style=dashed];", quote(&format!("on_error:{}", action)), quote(action));
+    }
+    for (dependency, id) in graph.edges() {
+        let _ = writeln!(out, "    {} -> {};", quote(dependency), quote(id));
+    }
+    for (id, step) in &graph.steps {
+        if let Some(action) = &step.on_error {
+            let _ = writeln!(out, "    {} -> {} [style=dashed, label=\"on_error\"];", quote(id), quote(&format!("on_error:{}", action)));
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Mermaid ids can't hold every character a step id can, so nodes are numbered instead.
+pub fn mermaid(task: &Task) -> String {
+    fn label(s: &str) -> String {
+        format!("\"{}\"", s.replace('"', "#quot;"))
+    }
+
+    let graph = Graph::of(task);
+    let ids: BTreeMap<&str, String> = graph.steps.keys().enumerate().map(|(i, id)| (*id, format!("s{}", i))).collect();
+    let error_ids: BTreeMap<&str, String> = graph.error_actions.iter().enumerate().map(|(i, action)| (*action, format!("e{}", i))).collect();
+    let mut out = String::from("flowchart TD\n");
+    for (id, step) in &graph.steps {
+        let (name, action) = Graph::label(id, step);
+        let _ = writeln!(out, "    {}[{}]", ids[id], label(&format!("{}<br/>{}", name, action)));
+    }
+    for (action, node) in &error_ids {
+        let _ = writeln!(out, "    {}([{}])", node, label(action));
+    }
+    for (dependency, id) in graph.edges() {
+        // Non-existent dependencies are left out, `stroem validate` reports them
+        if let Some(from) = ids.get(dependency) {
+            let _ = writeln!(out, "    {} --> {}", from, ids[id]);
+        }
+    }
+    for (id, step) in &graph.steps {
+        if let Some(action) = &step.on_error {
+            let _ = writeln!(out, "    {} -. on_error .-> {}", ids[id], error_ids[action.as_str()]);
+        }
+    }
+    out
+}
@@ -2,58 +2,171 @@ use std::sync::Arc;
 use stroem_common::workspace_client::WorkspaceClient;
 use serde_json::Value;
 use tracing::error;
-use clap::{Parser, Subcommand};
-use std::path::PathBuf;
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::engine::{ArgValueCandidates, CompletionCandidate};
+use clap_complete::env::{CompleteEnv, Shells};
+use std::path::{Path, PathBuf};
 use stroem_common::log_collector::LogCollectorConsole;
 use stroem_common::runner::Runner;
-use std::fs;
+use stroem_common::workflows_configuration::WorkflowsConfiguration;
+use stroem_common::{JobSpec, WORKSPACE_ENV};
+use std::io::IsTerminal;
+use std::{fs, io};
+
+mod graph;
+mod prompt;
+mod scaffold;
+
+/// Environment variable the scripts from `stroem completions` call back into `stroem` with
+const COMPLETE_ENV: &str = "COMPLETE";
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(name = "stroem", author, version, about, long_about = None)]
 struct Args {
     #[command(subcommand)]
     command: Commands,
     #[arg(short, long)]
     verbose: bool,
-    #[arg(long, default_value = ".")]
+    #[arg(long, env = WORKSPACE_ENV, default_value = ".")]
     workspace: String,
 }
 
 #[derive(Debug, Subcommand)]
 enum Commands {
-    Validate {},
+    /// Check the workflows of the workspace, listing every problem with the file and entry it is in
+    Validate {
+        /// Print the JSON Schema of the workflow files instead, see `stroem schema`
+        #[arg(long)]
+        schema: bool,
+    },
     Run {
-        #[arg(long, conflicts_with = "action")]
+        #[arg(long, conflicts_with = "action", add = ArgValueCandidates::new(complete_tasks))]
         task: Option<String>,
-        #[arg(long, conflicts_with = "task")]
+        #[arg(long, conflicts_with = "task", add = ArgValueCandidates::new(complete_actions))]
         action: Option<String>,
+        /// Input as a JSON object. Without it or `--input-file`, declared input fields are prompted for on a terminal
         #[arg(long)]
         input: Option<String>,
-    }
+        /// Read the input from a YAML or JSON file instead
+        #[arg(long, conflicts_with = "input")]
+        input_file: Option<PathBuf>,
+        /// Run only this step of the task, with the task input. Outputs of other steps aren't available to it
+        #[arg(long, requires = "task", conflicts_with = "action")]
+        step: Option<String>,
+        /// Render every step and print the commands they would run, without running them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print the JSON Schema of the workflow files, for editors to complete and check them.
+    /// With the VS Code YAML extension, map it to `.workflows/**/*.yaml` in the `yaml.schemas` setting.
+    Schema {
+        /// Write the schema to this file instead
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Print the flow of a task as a graph, to review its steps, dependencies and error handlers before running it
+    Graph {
+        #[arg(long, add = ArgValueCandidates::new(complete_tasks))]
+        task: String,
+        #[arg(long, value_parser = ["dot", "mermaid"], default_value = "dot")]
+        format: String,
+    },
+    /// Create a starter workspace: example action, task and trigger files under `.workflows`, and a .gitignore
+    Init {
+        /// Folder of the new workspace, the `--workspace` folder by default
+        path: Option<PathBuf>,
+    },
+    /// Add a stub to the workspace
+    New {
+        #[command(subcommand)]
+        stub: NewStub,
+    },
+    /// Print the shell completion script, e.g. `source <(stroem completions bash)`.
+    /// Task and action names are completed from the workspace in the current folder or `STROEM_WORKSPACE`.
+    Completions {
+        #[arg(value_parser = ["bash", "elvish", "fish", "powershell", "zsh"])]
+        shell: String,
+    },
+    /// Print the man page
+    Man {},
+}
+
+#[derive(Debug, Subcommand)]
+enum NewStub {
+    /// Append an action to `.workflows/actions.yaml`; python actions run a script created under `scripts/`
+    Action {
+        name: String,
+        #[arg(long = "type", value_parser = ["shell", "python"], default_value = "shell")]
+        action_type: String,
+    },
 }
 
 #[tokio::main]
 async fn main() {
+    CompleteEnv::with_factory(Args::command).var(COMPLETE_ENV).complete();
     let args = Args::parse();
     // init_tracing(args.verbose);
 
-    let workspace_path = fs::canonicalize(args.workspace).unwrap();
-
-    let mut workspace = WorkspaceClient::new(PathBuf::from(&workspace_path)).await;
-
-    if let Err(e) = workspace.read_workflows() {
-        eprintln!("Failed to read workflows: {}", e);
-        std::process::exit(1);
-    };
-
-
     match args.command {
-        Commands::Validate {} => {
+        Commands::Completions { shell } => {
+            let shells = Shells::builtins();
+            let shell = shells.completer(&shell).unwrap();
+            let name = Args::command().get_name().to_string();
+            if let Err(e) = shell.write_registration(COMPLETE_ENV, &name, &name, &name, &mut io::stdout()) {
+                eprintln!("Failed to write completions: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Man {} => {
+            if let Err(e) = clap_mangen::Man::new(Args::command()).render(&mut io::stdout()) {
+                eprintln!("Failed to write man page: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Commands::Schema { output } => print_schema(output.as_deref()),
+        Commands::Validate { schema: true } => print_schema(None),
+        Commands::Init { path } => {
+            let path = path.unwrap_or_else(|| PathBuf::from(&args.workspace));
+            match scaffold::init(&path) {
+                Ok(files) => {
+                    for file in files {
+                        println!("Created {}", file.display());
+                    }
+                    println!("Try it with `stroem --workspace {} run --task hello`", path.display());
+                }
+                Err(e) => {
+                    eprintln!("Failed to create workspace: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::New { stub: NewStub::Action { name, action_type } } => {
+            match scaffold::new_action(&PathBuf::from(&args.workspace), &name, &action_type) {
+                Ok(files) => {
+                    for file in files {
+                        println!("Wrote {}", file.display());
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to add action: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Validate { .. } => {
+            let workspace = load_workspace(&args.workspace).await;
             if let Some(workflows) = workspace.workflows {
-                if let Err(e) = workflows.validate() {
-                    eprintln!("Failed to validate workflows: {}", e);
+                let errors = workflows.validation_errors();
+                if !errors.is_empty() {
+                    for error in &errors {
+                        eprintln!("Error: {}", error);
+                    }
+                    eprintln!("Workspace configuration has {} problem(s)", errors.len());
                     std::process::exit(1);
                 }
+                for warning in workflows.deprecation_warnings() {
+                    eprintln!("Warning: {}", warning);
+                }
             }
             else {
                 eprintln!("Cuuld not load workflows");
@@ -61,20 +174,71 @@ async fn main() {
             }
             println!("Workspace configuration is valid");
         }
-        Commands::Run { task, action, input } => {
-            let input: Option<Value> = input.as_ref()
+        Commands::Run { task, action, input, input_file, step, dry_run } => {
+            let workspace = load_workspace(&args.workspace).await;
+            let spec = JobSpec::new(task, action).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            });
+            let mut input: Option<Value> = input.as_ref()
                 .map(|s| serde_json::from_str(s).unwrap_or_else(|e| {
                     error!("Failed to parse input: {}", e);
                     std::process::exit(1);
                 }));
+            if let Some(path) = &input_file {
+                let parsed = config::Config::builder()
+                    .add_source(config::File::from(path.as_path()))
+                    .build()
+                    .and_then(|file| file.try_deserialize::<Value>());
+                match parsed {
+                    Ok(value) => input = Some(value),
+                    Err(e) => {
+                        eprintln!("Failed to read input file {}: {}", path.display(), e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+
+            let fields = workspace.workflows.as_ref().and_then(|workflows| match (spec.task(), spec.action()) {
+                (Some(task), _) => workflows.get_task(task).and_then(|task| task.input.clone()),
+                (None, Some(action)) => workflows.get_action(action).and_then(|action| action.input.clone()),
+                (None, None) => None,
+            });
+            if let Some(fields) = fields.filter(|fields| input.is_none() && !fields.is_empty() && io::stdin().is_terminal()) {
+                match prompt::prompt_input(&fields) {
+                    Ok(answers) => input = Some(Value::Object(answers)),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
 
+            if let Some(workflows) = &workspace.workflows
+                && let Err(e) = workflows.validate_run_input(spec.task(), spec.action(), &mut input) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
 
             let log_collector = Arc::new(LogCollectorConsole::new(None));
 
+            if let (Some(task), Some(step)) = (spec.task(), &step) {
+                let found = workspace.workflows.as_ref().and_then(|workflows| workflows.get_task(task)).is_some_and(|task| task.flow.contains_key(step));
+                if !found {
+                    eprintln!("Step '{}' not found in task '{}'", step, task);
+                    std::process::exit(1);
+                }
+            }
+
             let mut runner = Runner::new(None, None, None,
-                                         task, action, input,
-                                         workspace, None,
-                                         log_collector);
+                                         spec, input,
+                                         workspace, log_collector);
+            if let Some(step) = step {
+                runner.only_step(step);
+            }
+            if dry_run {
+                runner.dry_run();
+            }
 
             let (success, output) = runner.execute().await.unwrap_or_else(|e| {
                 eprintln!("Execution failed: {}", e);
@@ -85,12 +249,74 @@ async fn main() {
                 std::process::exit(1);
             }
 
+            if dry_run {
+                println!("Dry run complete, nothing was executed");
+                return;
+            }
             println!("Successfully executed");
             if let Some(output) = output {
                 println!("OUTPUT:{:?}", serde_json::to_string(&output));
             }
         }
+        Commands::Graph { task, format } => {
+            let workspace = load_workspace(&args.workspace).await;
+            let Some(task_config) = workspace.workflows.as_ref().and_then(|workflows| workflows.get_task(&task)) else {
+                eprintln!("Task '{}' not found", task);
+                std::process::exit(1);
+            };
+            match format.as_str() {
+                "mermaid" => print!("{}", graph::mermaid(task_config)),
+                _ => print!("{}", graph::dot(&task, task_config)),
+            }
+        }
     }
+}
 
+/// Reads the workflows of the workspace the task, action and validation commands work on.
+async fn load_workspace(path: &str) -> WorkspaceClient {
+    let workspace_path = fs::canonicalize(path).unwrap();
 
+    let mut workspace = WorkspaceClient::new(PathBuf::from(&workspace_path)).await;
+
+    if let Err(e) = workspace.read_workflows() {
+        eprintln!("Failed to read workflows: {}", e);
+        std::process::exit(1);
+    };
+    workspace
+}
+
+fn print_schema(output: Option<&Path>) {
+    let schema = serde_json::to_string_pretty(&WorkflowsConfiguration::json_schema()).unwrap();
+    match output {
+        Some(path) => {
+            if let Err(e) = fs::write(path, schema + "\n") {
+                eprintln!("Failed to write {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+        None => println!("{}", schema),
+    }
+}
+
+fn complete_tasks() -> Vec<CompletionCandidate> {
+    let workflows = completion_workspace();
+    workflows.tasks.iter().flatten()
+        .map(|(name, task)| CompletionCandidate::new(name).help(task.name.clone().map(Into::into)))
+        .collect()
+}
+
+fn complete_actions() -> Vec<CompletionCandidate> {
+    let workflows = completion_workspace();
+    workflows.actions.iter().flatten()
+        .map(|(name, action)| CompletionCandidate::new(name).help(action.name.clone().map(Into::into)))
+        .collect()
+}
+
+/// Workspace to complete names from; `--workspace` hasn't been parsed yet while completing.
+fn completion_workspace() -> WorkflowsConfiguration {
+    let path = std::env::var(WORKSPACE_ENV).unwrap_or_else(|_| ".".to_string());
+    match fs::canonicalize(path) {
+        Ok(path) => WorkflowsConfiguration::try_new_or_empty(path),
+        Err(_) => WorkflowsConfiguration::default(),
+    }
 }
\ No newline at end of file
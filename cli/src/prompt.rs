@@ -0,0 +1,118 @@
+//! Asks for the input of a task or action on the terminal, one declared field at a time.
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use anyhow::{bail, Error};
+use serde_json::{Map, Value};
+use stroem_common::workflows_configuration::{InputField, InputFieldType};
+
+/// Prompts for each field in `order`, then by id. An empty answer takes the default of the field,
+/// or leaves an optional field unset; invalid answers are asked again.
+pub fn prompt_input(fields: &HashMap<String, InputField>) -> Result<Map<String, Value>, Error> {
+    let mut names: Vec<&String> = fields.keys().collect();
+    names.sort_by_key(|name| (fields[*name].order.unwrap_or(i32::MAX), *name));
+
+    let mut input = Map::new();
+    for name in names {
+        if let Some(value) = prompt_field(name, &fields[name])? {
+            input.insert(name.clone(), value);
+        }
+    }
+    Ok(input)
+}
+
+fn prompt_field(name: &str, field: &InputField) -> Result<Option<Value>, Error> {
+    let required = field.required.unwrap_or(false);
+    let default = field.field_type.default_value();
+
+    if let Some(description) = &field.description {
+        eprintln!("{}", description);
+    }
+    if let InputFieldType::Enum { values, .. } = &field.field_type {
+        for (i, value) in values.iter().enumerate() {
+            eprintln!("  {}) {}", i + 1, value);
+        }
+    }
+    let mut prompt = format!("{} ({}{})", name, field.field_type.as_ref(), if required { ", required" } else { "" });
+    if let Some(default) = &default {
+        prompt.push_str(&format!(" [{}]", default.as_str().map(str::to_string).unwrap_or_else(|| default.to_string())));
+    }
+    if let InputFieldType::Text { .. } = &field.field_type {
+        prompt.push_str(", end with a line holding only '.'");
+    }
+
+    loop {
+        eprint!("{}: ", prompt);
+        io::stderr().flush()?;
+        let answer = match &field.field_type {
+            InputFieldType::Secret {} => read_secret()?,
+            InputFieldType::Text { .. } => read_text()?,
+            InputFieldType::Enum { values, .. } => {
+                let answer = read_line()?;
+                // Options can be picked by their number too
+                answer.parse::<usize>().ok()
+                    .and_then(|i| i.checked_sub(1))
+                    .and_then(|i| values.get(i).cloned())
+                    .unwrap_or(answer)
+            }
+            _ => read_line()?,
+        };
+
+        if answer.is_empty() {
+            if default.is_some() || !required {
+                return Ok(default);
+            }
+            eprintln!("'{}' is required", name);
+            continue;
+        }
+        let value = Value::String(answer);
+        match field.field_type.check(name, &value) {
+            Ok(converted) => return Ok(Some(converted.unwrap_or(value))),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+fn read_line() -> Result<String, Error> {
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        bail!("Input ended before all fields were answered");
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn read_text() -> Result<String, Error> {
+    let mut lines = Vec::new();
+    loop {
+        let line = read_line()?;
+        if line == "." {
+            return Ok(lines.join("\n"));
+        }
+        lines.push(line);
+    }
+}
+
+/// Reads a line without echoing it, where the terminal allows turning echo off.
+fn read_secret() -> Result<String, Error> {
+    let echo_off = set_echo(false);
+    let line = read_line();
+    if echo_off {
+        set_echo(true);
+        eprintln!();
+    }
+    line
+}
+
+#[cfg(unix)]
+fn set_echo(on: bool) -> bool {
+    std::process::Command::new("stty")
+        .arg(if on { "echo" } else { "-echo" })
+        .stdin(std::process::Stdio::inherit())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn set_echo(_on: bool) -> bool {
+    false
+}
@@ -0,0 +1,170 @@
+//! Generators for `stroem init` and `stroem new`: a starter workspace, and action stubs appended to it.
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, bail, Error};
+use stroem_common::workflows_configuration::WorkflowsConfiguration;
+
+const ACTIONS_FILE: &str = ".workflows/actions.yaml";
+
+const ACTIONS: &str = r#"# Actions are the commands steps run. Add more with `stroem new action <name>`.
+actions:
+  hello:
+    type: shell
+    cmd: "echo \"Hello {{ input.name }}\" && echo 'OUTPUT:{\"greeted\": \"{{ input.name }}\"}'"
+    input:
+      name:
+        type: string
+        required: true
+"#;
+
+const TASKS: &str = r#"# Tasks are flows of steps, each running an action. Run one locally with `stroem run --task hello`.
+tasks:
+  hello:
+    input:
+      name:
+        description: Who to greet
+        type: string
+        default: world
+    flow:
+      greet:
+        action: hello
+        input:
+          name: "{{ input.name }}"
+"#;
+
+const TRIGGERS: &str = r#"# Triggers start tasks on a schedule, a webhook or a message.
+triggers:
+  hello_daily:
+    enabled: false
+    type: scheduler
+    cron: "0 0 9 * * *"
+    task: hello
+    input:
+      name: scheduler
+"#;
+
+const GITIGNORE: &str = r#".env
+*.pyc
+__pycache__/
+.DS_Store
+"#;
+
+const SHELL_ACTION: &str = r#"
+  __NAME__:
+    type: shell
+    cmd: "echo \"Running __NAME__ with {{ input.value }}\" && echo 'OUTPUT:{\"result\": \"done\"}'"
+    input:
+      value:
+        type: string
+        required: true
+"#;
+
+const PYTHON_ACTION: &str = r#"
+  __NAME__:
+    type: shell
+    cmd: "python3 __SCRIPT__ \"{{ input.value }}\""
+    input:
+      value:
+        type: string
+        required: true
+"#;
+
+const PYTHON_SCRIPT: &str = r#"#!/usr/bin/env python3
+import json
+import sys
+
+value = sys.argv[1] if len(sys.argv) > 1 else ""
+print(f"Running __NAME__ with {value}")
+
+# A line starting with OUTPUT: sets the output of the step
+print("OUTPUT:" + json.dumps({"result": "done"}))
+"#;
+
+/// Writes a starter workspace into `path`, created if missing. Returns the files written.
+pub fn init(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let workflows = path.join(".workflows");
+    if workflows.exists() {
+        bail!("{} already exists", workflows.display());
+    }
+    fs::create_dir_all(&workflows)?;
+
+    let mut written = Vec::new();
+    for (file, content) in [(ACTIONS_FILE, ACTIONS), (".workflows/tasks.yaml", TASKS), (".workflows/triggers.yaml", TRIGGERS)] {
+        written.push(write_new(&path.join(file), content)?);
+    }
+    // An existing .gitignore is the user's, it's left alone
+    let gitignore = path.join(".gitignore");
+    if !gitignore.exists() {
+        written.push(write_new(&gitignore, GITIGNORE)?);
+    }
+    Ok(written)
+}
+
+/// Appends an action stub of `action_type`, `shell` or `python`, to the actions file of the workspace.
+/// Python actions get a script next to it under `scripts/`. Returns the files written.
+pub fn new_action(path: &Path, name: &str, action_type: &str) -> Result<Vec<PathBuf>, Error> {
+    if name.is_empty() || name.starts_with('.') || !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')) {
+        bail!("Invalid action name '{}', use letters, digits, '_', '-' and '.'", name);
+    }
+    if let Ok(workflows) = WorkflowsConfiguration::new(path.to_path_buf()) && workflows.get_action(name).is_some() {
+        bail!("Action '{}' already exists", name);
+    }
+
+    let script = format!("scripts/{}.py", name);
+    let stub = match action_type {
+        "shell" => SHELL_ACTION.replace("__NAME__", name),
+        "python" => PYTHON_ACTION.replace("__NAME__", name).replace("__SCRIPT__", &script),
+        other => bail!("Unknown action type '{}'", other),
+    };
+
+    let actions_file = path.join(ACTIONS_FILE);
+    let existing = match actions_file.exists() {
+        true => fs::read_to_string(&actions_file)?,
+        false => String::new(),
+    };
+    let existing = match existing.trim().is_empty() {
+        true => "actions:\n".to_string(),
+        false => existing,
+    };
+    // Entries can only be appended when `actions` is the sole, block style section of the file
+    let parsed: serde_json::Map<String, serde_json::Value> = parse_yaml(&existing)
+        .map_err(|e| anyhow!("{} is not valid YAML: {}", actions_file.display(), e))?;
+    if parsed.keys().any(|key| key != "actions") {
+        bail!("{} holds more than the actions section, add the action by hand", actions_file.display());
+    }
+    let mut content = existing;
+    if !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(&stub);
+    let updated = parse_yaml(&content).ok();
+    if updated.as_ref().and_then(|updated| updated.get("actions")).and_then(|actions| actions.get(name)).is_none() {
+        bail!("Could not append to {}, add the action by hand", actions_file.display());
+    }
+
+    let mut written = Vec::new();
+    if action_type == "python" {
+        let script_path = path.join(&script);
+        if script_path.exists() {
+            bail!("{} already exists", script_path.display());
+        }
+        fs::create_dir_all(path.join("scripts"))?;
+        written.push(write_new(&script_path, &PYTHON_SCRIPT.replace("__NAME__", name))?);
+    }
+    fs::create_dir_all(path.join(".workflows"))?;
+    fs::write(&actions_file, content)?;
+    written.push(actions_file);
+    Ok(written)
+}
+
+fn parse_yaml(content: &str) -> Result<serde_json::Map<String, serde_json::Value>, config::ConfigError> {
+    config::Config::builder()
+        .add_source(config::File::from_str(content, config::FileFormat::Yaml))
+        .build()?
+        .try_deserialize()
+}
+
+fn write_new(path: &Path, content: &str) -> Result<PathBuf, Error> {
+    fs::write(path, content).map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))?;
+    Ok(path.to_path_buf())
+}
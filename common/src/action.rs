@@ -1,4 +1,7 @@
+pub mod approval;
+pub mod sensor;
 pub mod shell;
+pub mod wait;
 
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -6,6 +9,7 @@ use anyhow::Error;
 use async_trait::async_trait;
 use serde_json::Value;
 use crate::log_collector::LogCollector;
+use crate::StepLink;
 
 #[async_trait]
 pub trait ActionExecutor {
@@ -15,5 +19,5 @@ pub trait ActionExecutor {
         input: &Option<Value>,
         workspace_path: &PathBuf,
         log_collector: Arc<dyn LogCollector + Send + Sync>,
-    ) -> Result<(bool, Option<Value>), Error>;
+    ) -> Result<(bool, Option<Value>, Vec<StepLink>), Error>;
 } 
\ No newline at end of file
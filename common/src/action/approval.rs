@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use crate::action::ActionExecutor;
+use crate::approval::ApprovalClient;
+use crate::log_collector::{LogCollector, LogEntry};
+use crate::runner::ENV_STEP_NAME;
+use crate::wait::Suspended;
+use crate::StepLink;
+
+/// The rendered approval action, the timeout comes back serialized as a plain `Duration`
+#[derive(Deserialize)]
+struct ApprovalSpec {
+    approvers: Option<Vec<String>>,
+    message: Option<String>,
+    timeout: Option<Duration>,
+    #[serde(default)]
+    env: HashMap<String, String>,
+}
+
+/// Asks for someone to approve or reject the step and suspends the job until then, or until the approval times out
+/// after the action or step `timeout`.
+#[derive(Clone)]
+pub struct ApprovalAction {
+    client: ApprovalClient,
+}
+
+impl ApprovalAction {
+    pub fn new(client: ApprovalClient) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl ActionExecutor for ApprovalAction {
+    async fn execute(
+        &self,
+        action: &Value,
+        _input: &Option<Value>,
+        _workspace_path: &PathBuf,
+        log_collector: Arc<dyn LogCollector + Send + Sync>,
+    ) -> Result<(bool, Option<Value>, Vec<StepLink>), Error> {
+        let spec: ApprovalSpec = serde_json::from_value(action.clone())
+            .map_err(|e| anyhow!("Invalid approval action: {}", e))?;
+        let step_name = spec.env.get(ENV_STEP_NAME).ok_or_else(|| anyhow!("Approval steps need a step name"))?;
+
+        let approval = self.client.request(step_name, &spec.approvers, &spec.message, spec.timeout).await?;
+        let decided_by = approval.decided_by.as_deref().unwrap_or_default();
+        let comment = approval.comment.as_ref().map(|comment| format!(": {}", comment)).unwrap_or_default();
+        match approval.status.as_str() {
+            "approved" => {
+                log_collector.log(LogEntry::new(format!("Approved by {}{}", decided_by, comment), false)).await?;
+                Ok((true, Some(json!({
+                    "approved_by": approval.decided_by,
+                    "comment": approval.comment,
+                    "waited_secs": approval.waited_secs(),
+                })), Vec::new()))
+            }
+            "rejected" => {
+                log_collector.log(LogEntry::new(format!("Rejected by {}{}", decided_by, comment), true)).await?;
+                Ok((false, Some(json!({
+                    "rejected_by": approval.decided_by,
+                    "comment": approval.comment,
+                    "waited_secs": approval.waited_secs(),
+                })), Vec::new()))
+            }
+            "timed_out" => {
+                log_collector.log(LogEntry::new(format!("Nobody decided on the approval within {}s", approval.waited_secs()), true)).await?;
+                Ok((false, Some(json!({"timed_out": true, "waited_secs": approval.waited_secs()})), Vec::new()))
+            }
+            _ => {
+                let approvers = spec.approvers.as_ref().map(|approvers| approvers.join(", ")).unwrap_or_else(|| "anyone who may run the task".to_string());
+                let message = spec.message.as_ref().map(|message| format!(": {}", message)).unwrap_or_default();
+                log_collector.log(LogEntry::new(format!("Waiting for approval by {}{}, the job is suspended until then", approvers, message), false)).await?;
+                Err(Suspended.into())
+            }
+        }
+    }
+}
+
@@ -0,0 +1,97 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::process::Command as TokioCommand;
+use crate::action::ActionExecutor;
+use crate::log_collector::{LogCollector, LogEntry};
+use crate::workflows_configuration::SensorCondition;
+use crate::{StepLink, WORKER_TOKEN_ENV};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+/// How long sensors without an action or step `timeout` poll
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+/// Time an HTTP poll may take, whatever the interval between polls
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The rendered sensor action, durations come back serialized as plain `Duration`s
+#[derive(Deserialize)]
+struct SensorSpec {
+    condition: SensorCondition,
+    interval: Option<Duration>,
+    timeout: Option<Duration>,
+}
+
+/// Polls the condition until it holds, and fails once the action or step `timeout`, or a day without one, passed.
+#[derive(Clone)]
+pub struct SensorAction;
+#[async_trait]
+impl ActionExecutor for SensorAction {
+    async fn execute(
+        &self,
+        action: &Value,
+        _input: &Option<Value>,
+        workspace_path: &PathBuf,
+        log_collector: Arc<dyn LogCollector + Send + Sync>,
+    ) -> Result<(bool, Option<Value>, Vec<StepLink>), Error> {
+        let spec: SensorSpec = serde_json::from_value(action.clone())
+            .map_err(|e| anyhow!("Invalid sensor action: {}", e))?;
+        let interval = spec.interval.unwrap_or(DEFAULT_INTERVAL);
+        let timeout = spec.timeout.unwrap_or(DEFAULT_TIMEOUT);
+        let client = Client::new();
+        let started = Instant::now();
+        let mut polls = 0;
+        loop {
+            polls += 1;
+            let (met, status, failed) = match poll(&spec.condition, &client, workspace_path).await {
+                Ok((met, status)) => (met, status, false),
+                Err(e) => (false, format!("check failed: {}", e), true),
+            };
+            log_collector.log(LogEntry::new(format!("Sensor poll {} after {}s: {}", polls, started.elapsed().as_secs(), status), failed)).await?;
+            if met {
+                return Ok((true, Some(json!({"polls": polls, "waited_secs": started.elapsed().as_secs()})), Vec::new()));
+            }
+            if started.elapsed() + interval > timeout {
+                log_collector.log(LogEntry::new(format!("Sensor gave up after {}s", started.elapsed().as_secs()), true)).await?;
+                return Ok((false, Some(json!({"polls": polls, "waited_secs": started.elapsed().as_secs(), "timed_out": true})), Vec::new()));
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+}
+
+/// Checks the condition once, returning whether it holds and a line describing what was seen.
+async fn poll(condition: &SensorCondition, client: &Client, workspace_path: &PathBuf) -> Result<(bool, String), Error> {
+    match condition {
+        SensorCondition::Http { url, status } => {
+            let response = client.get(url).timeout(REQUEST_TIMEOUT).send().await?;
+            let actual = response.status().as_u16();
+            Ok((actual == *status, format!("{} returned {}, waiting for {}", url, actual, status)))
+        }
+        SensorCondition::File { path } => {
+            let path = workspace_path.join(path);
+            let exists = tokio::fs::try_exists(&path).await?;
+            Ok((exists, format!("{} {}", path.display(), if exists { "exists" } else { "does not exist" })))
+        }
+        SensorCondition::Sql { connection, query, min_rows } => {
+            let output = TokioCommand::new("psql")
+                .args([connection.as_str(), "--no-psqlrc", "--tuples-only", "--no-align", "--command", query.as_str()])
+                .env_remove(WORKER_TOKEN_ENV)
+                .current_dir(workspace_path)
+                .kill_on_drop(true)
+                .output()
+                .await
+                .map_err(|e| anyhow!("Failed to run psql: {}", e))?;
+            if !output.status.success() {
+                return Err(anyhow!("{}", String::from_utf8_lossy(&output.stderr).trim()));
+            }
+            let rows = String::from_utf8_lossy(&output.stdout).lines().filter(|line| !line.is_empty()).count();
+            Ok((rows >= *min_rows, format!("query returned {} rows, waiting for {}", rows, min_rows)))
+        }
+    }
+}
+
@@ -1,11 +1,15 @@
+use std::fs;
 use std::path::PathBuf;
 use std::sync::Arc;
-use anyhow::Error;
+use anyhow::{anyhow, Error};
 use async_trait::async_trait;
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
 use serde_json::Value;
 use crate::action::ActionExecutor;
 use crate::log_collector::LogCollector;
-use crate::run;
+use crate::workflows_configuration::Shell;
+use crate::{run, StepLink};
 
 #[derive(Clone)]
 pub struct ShellAction;
@@ -17,10 +21,53 @@ impl ActionExecutor for ShellAction {
         _input: &Option<Value>,
         workspace_path: &PathBuf,
         log_collector: Arc<dyn LogCollector + Send + Sync>,
-    ) -> Result<(bool, Option<Value>), Error> {
+    ) -> Result<(bool, Option<Value>, Vec<StepLink>), Error> {
         let cmd = action["cmd"].as_str().unwrap();
-        let (exit_success, output) = run("sh", None, Some(cmd.to_string()), Some(&workspace_path), log_collector).await?;
+        let shell = serde_json::from_value::<Option<Shell>>(action["interpreter"].clone())?.unwrap_or_default();
+        let envs = serde_json::from_value(action["env"].clone()).ok();
+        match shell {
+            Shell::Sh | Shell::Bash => run(shell.as_ref(), None, Some(cmd.to_string()), Some(workspace_path), envs, log_collector).await,
+            Shell::Powershell | Shell::Pwsh => {
+                let args = ["-NoProfile", "-NonInteractive", "-EncodedCommand"].map(String::from).into_iter()
+                    .chain([encode_powershell(cmd)])
+                    .collect();
+                run(shell.as_ref(), Some(args), None, Some(workspace_path), envs, log_collector).await
+            }
+            Shell::Cmd => {
+                let script = BatchScript::create(cmd)?;
+                let args = vec!["/D".to_string(), "/Q".to_string(), "/C".to_string(), script.path.to_string_lossy().to_string()];
+                run("cmd", Some(args), None, Some(workspace_path), envs, log_collector).await
+            }
+        }
+    }
+}
+
+/// `-EncodedCommand` takes the script as base64 of UTF-16LE, which keeps quotes and newlines intact.
+/// Output is switched to UTF-8 for the log collector, and the script exits with the status of its last
+/// command like `sh` does.
+fn encode_powershell(cmd: &str) -> String {
+    let script = format!("[Console]::OutputEncoding = [System.Text.Encoding]::UTF8\n$ErrorActionPreference = 'Stop'\n{}\nexit $LASTEXITCODE", cmd);
+    let bytes: Vec<u8> = script.encode_utf16().flat_map(u16::to_le_bytes).collect();
+    BASE64.encode(bytes)
+}
+
+/// `cmd` can't read a script from stdin, so it runs from a temporary batch file, removed once dropped.
+struct BatchScript {
+    path: PathBuf,
+}
+
+impl BatchScript {
+    fn create(cmd: &str) -> Result<Self, Error> {
+        let path = std::env::temp_dir().join(format!("stroem-{}.cmd", uuid::Uuid::new_v4()));
+        // Code page 65001 makes the output UTF-8 for the log collector
+        let content = format!("@chcp 65001 >nul\r\n{}\r\n", cmd.lines().collect::<Vec<_>>().join("\r\n"));
+        fs::write(&path, content).map_err(|e| anyhow!("Failed to write batch script {}: {}", path.display(), e))?;
+        Ok(BatchScript { path })
+    }
+}
 
-        Ok((exit_success, output))
+impl Drop for BatchScript {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
     }
-}
\ No newline at end of file
+}
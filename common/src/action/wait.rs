@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use crate::action::ActionExecutor;
+use crate::log_collector::{LogCollector, LogEntry};
+use crate::runner::ENV_STEP_NAME;
+use crate::wait::{Suspended, WaitClient};
+use crate::StepLink;
+
+/// Shorter waits sleep in the runner, suspending the job costs a new lease and workspace sync
+const MIN_SUSPENDED_WAIT: Duration = Duration::from_secs(60);
+
+/// The rendered wait actions, durations come back serialized as plain `Duration`s
+#[derive(Deserialize)]
+struct WaitSpec {
+    duration: Option<Duration>,
+    key: Option<String>,
+    timeout: Option<Duration>,
+    #[serde(default)]
+    env: HashMap<String, String>,
+}
+
+impl WaitSpec {
+    fn parse(action: &Value) -> Result<Self, Error> {
+        serde_json::from_value(action.clone()).map_err(|e| anyhow!("Invalid wait action: {}", e))
+    }
+
+    fn step_name(&self) -> Result<&str, Error> {
+        self.env.get(ENV_STEP_NAME).map(String::as_str).ok_or_else(|| anyhow!("Wait steps need a step name"))
+    }
+}
+
+/// Waits for the `duration` of the step, suspending the job for long waits when it runs for a server.
+#[derive(Clone)]
+pub struct WaitAction {
+    client: Option<WaitClient>,
+}
+
+impl WaitAction {
+    pub fn new(client: Option<WaitClient>) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl ActionExecutor for WaitAction {
+    async fn execute(
+        &self,
+        action: &Value,
+        _input: &Option<Value>,
+        _workspace_path: &PathBuf,
+        log_collector: Arc<dyn LogCollector + Send + Sync>,
+    ) -> Result<(bool, Option<Value>, Vec<StepLink>), Error> {
+        let spec = WaitSpec::parse(action)?;
+        let duration = spec.duration.ok_or_else(|| anyhow!("Invalid wait action: missing duration"))?;
+        let client = match &self.client {
+            Some(client) if duration >= MIN_SUSPENDED_WAIT => client,
+            _ => {
+                log_collector.log(LogEntry::new(format!("Waiting {:?}", duration), false)).await?;
+                tokio::time::sleep(duration).await;
+                return Ok((true, Some(json!({"waited_secs": duration.as_secs()})), Vec::new()));
+            }
+        };
+        let wait = client.register(spec.step_name()?, None, Some(duration)).await?;
+        if wait.status == "waiting" {
+            log_collector.log(LogEntry::new(format!("Waiting {:?}, the job is suspended until then", duration), false)).await?;
+            return Err(Suspended.into());
+        }
+        log_collector.log(LogEntry::new(format!("Waited {}s", wait.waited_secs()), false)).await?;
+        Ok((true, Some(json!({"waited_secs": wait.waited_secs()})), Vec::new()))
+    }
+}
+
+/// Waits for an event posted to the server with the `key` of the step, suspending the job meanwhile.
+#[derive(Clone)]
+pub struct WaitForEventAction {
+    client: Option<WaitClient>,
+}
+
+impl WaitForEventAction {
+    pub fn new(client: Option<WaitClient>) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl ActionExecutor for WaitForEventAction {
+    async fn execute(
+        &self,
+        action: &Value,
+        _input: &Option<Value>,
+        _workspace_path: &PathBuf,
+        log_collector: Arc<dyn LogCollector + Send + Sync>,
+    ) -> Result<(bool, Option<Value>, Vec<StepLink>), Error> {
+        let spec = WaitSpec::parse(action)?;
+        let key = spec.key.as_deref().ok_or_else(|| anyhow!("Invalid wait_for_event action: missing key"))?;
+        let client = self.client.as_ref().ok_or_else(|| anyhow!("Steps of type wait_for_event need a server to post the event to"))?;
+        let wait = client.register(spec.step_name()?, Some(key), spec.timeout).await?;
+        match wait.status.as_str() {
+            "waiting" => {
+                let timeout = spec.timeout.map(|timeout| format!(" for up to {:?}", timeout)).unwrap_or_default();
+                log_collector.log(LogEntry::new(format!("Waiting for event '{}'{}, the job is suspended until then", key, timeout), false)).await?;
+                Err(Suspended.into())
+            }
+            "received" => {
+                log_collector.log(LogEntry::new(format!("Received event '{}' after {}s", key, wait.waited_secs()), false)).await?;
+                Ok((true, Some(json!({"key": key, "payload": wait.payload, "waited_secs": wait.waited_secs()})), Vec::new()))
+            }
+            _ => {
+                log_collector.log(LogEntry::new(format!("Timed out waiting for event '{}' after {}s", key, wait.waited_secs()), true)).await?;
+                Ok((false, Some(json!({"key": key, "timed_out": true, "waited_secs": wait.waited_secs()})), Vec::new()))
+            }
+        }
+    }
+}
+
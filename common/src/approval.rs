@@ -0,0 +1,70 @@
+// common/src/approval.rs
+//! Approvals that steps of type `approval` wait for. The runner records a pending approval of the step with the
+//! server and suspends the job, like the steps in [`crate::wait`]. Once someone approves or rejects it through the
+//! API, or it times out, the server queues the job again and the step picks up the outcome.
+use std::time::Duration;
+use anyhow::{anyhow, Error};
+use chrono::{DateTime, Utc};
+use reqwest::{header, Client, Url};
+use serde::Deserialize;
+use serde_json::json;
+use crate::artifacts::check_response;
+use crate::http_retry::send_with_retry;
+use crate::rfc3339;
+
+/// An approval as the server has it
+#[derive(Debug, Deserialize)]
+pub struct Approval {
+    /// `pending`, `approved`, `rejected` or `timed_out`
+    pub status: String,
+    pub decided_by: Option<String>,
+    pub comment: Option<String>,
+    #[serde(with = "rfc3339")]
+    pub requested: DateTime<Utc>,
+    #[serde(with = "rfc3339::option")]
+    pub decided_at: Option<DateTime<Utc>>,
+    #[serde(default, with = "rfc3339::option")]
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl Approval {
+    /// Seconds from the request to the decision, or to the timeout
+    pub fn waited_secs(&self) -> i64 {
+        self.decided_at.or(self.expires).map(|end| (end - self.requested).num_seconds()).unwrap_or_default()
+    }
+}
+
+/// Requests and checks the approvals of a job through the worker API of the server.
+#[derive(Clone)]
+pub struct ApprovalClient {
+    client: Client,
+    server: String,
+    job_id: String,
+    worker_id: String,
+    token: String,
+}
+
+impl ApprovalClient {
+    pub fn new(client: Client, server: String, job_id: String, worker_id: String, token: String) -> Self {
+        Self { client, server, job_id, worker_id, token }
+    }
+
+    fn url(&self, step_name: &str) -> Result<Url, Error> {
+        let mut url = Url::parse(&format!("{}/jobs/{}/approvals", self.server, self.job_id))?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("Invalid server URL: {}", self.server))?
+            .push(step_name);
+        Ok(url)
+    }
+
+    /// Records a pending approval of the step, timing out after `timeout` or the server's default; the server keeps
+    /// the one recorded when the step ran before. Returns the approval as it is now.
+    pub async fn request(&self, step_name: &str, approvers: &Option<Vec<String>>, message: &Option<String>, timeout: Option<Duration>) -> Result<Approval, Error> {
+        let response = send_with_retry(self.client.post(self.url(step_name)?)
+            .query(&[("worker_id", self.worker_id.as_str())])
+            .header(header::AUTHORIZATION, format!("Bearer {}", self.token))
+            .json(&json!({"approvers": approvers, "message": message, "timeout_secs": timeout.map(|timeout| timeout.as_secs_f64())})))
+            .await?;
+        Ok(check_response(response, "Failed to request approval").await?.json().await?)
+    }
+}
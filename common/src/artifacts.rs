@@ -0,0 +1,139 @@
+// common/src/artifacts.rs
+//! Files steps hand over to later steps and jobs. After a step ran, the runner uploads the workspace files
+//! matching its action's `artifacts` globs; before a step runs, it downloads the artifacts the step lists in
+//! `artifacts_from` into the workspace.
+use std::path::Path;
+use anyhow::{anyhow, bail, Context, Error};
+use globwalker::{FileType, GlobWalkerBuilder};
+use reqwest::{header, Client, Response, Url};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+use tokio_stream::StreamExt;
+use tracing::{debug, info};
+use crate::http_retry::send_with_retry;
+use crate::workflows_configuration::ArtifactDependency;
+
+/// An uploaded file, named by its path relative to the workspace
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Artifact {
+    pub step_name: String,
+    pub name: String,
+    /// In bytes
+    pub size: i64,
+}
+
+/// Whether `name` is a relative path with `/` separators that stays within the directory it is joined to.
+/// Backslashes and colons are refused as well, so names mean the same on Windows workers.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains(['\\', ':'])
+        && name.split('/').all(|segment| !segment.is_empty() && segment != "." && segment != "..")
+}
+
+/// Uploads and downloads the artifacts of a job through the worker API of the server.
+#[derive(Clone)]
+pub struct ArtifactClient {
+    client: Client,
+    server: String,
+    job_id: String,
+    worker_id: String,
+    token: String,
+}
+
+impl ArtifactClient {
+    pub fn new(client: Client, server: String, job_id: String, worker_id: String, token: String) -> Self {
+        Self { client, server, job_id, worker_id, token }
+    }
+
+    /// Uploads the files in `workspace` matching `patterns` as artifacts of the step, and returns their names.
+    pub async fn upload(&self, step_name: &str, workspace: &Path, patterns: &[String]) -> Result<Vec<String>, Error> {
+        let walker = GlobWalkerBuilder::from_patterns(workspace, patterns)
+            .follow_links(true)
+            .file_type(FileType::FILE)
+            .sort_by(|a, b| a.path().cmp(b.path()))
+            .build()
+            .map_err(|e| anyhow!("Invalid artifact pattern: {}", e))?;
+
+        let mut names = Vec::new();
+        for entry in walker.into_iter().filter_map(Result::ok) {
+            let name = entry.path().strip_prefix(workspace)?
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if !is_valid_name(&name) {
+                bail!("Invalid artifact name: {}", name);
+            }
+            let file = File::open(entry.path()).await
+                .with_context(|| format!("Failed to open artifact {}", entry.path().display()))?;
+            let size = file.metadata().await?.len();
+            debug!("Uploading artifact {} ({} bytes)", name, size);
+            // Streamed bodies are sent once, without retries
+            let response = send_with_retry(self.client.post(format!("{}/jobs/{}/artifacts", self.server, self.job_id))
+                .query(&[("worker_id", self.worker_id.as_str()), ("step", step_name), ("name", name.as_str())])
+                .header(header::AUTHORIZATION, format!("Bearer {}", self.token))
+                .header(header::CONTENT_LENGTH, size)
+                .body(file))
+                .await?;
+            check_response(response, &format!("Failed to upload artifact {}", name)).await?;
+            names.push(name);
+        }
+        info!("Uploaded {} artifacts of step '{}'", names.len(), step_name);
+        Ok(names)
+    }
+
+    /// Downloads the artifacts `dependency` refers to into `workspace`, and returns their names.
+    pub async fn download(&self, dependency: &ArtifactDependency, workspace: &Path) -> Result<Vec<String>, Error> {
+        let job_id = dependency.job.as_deref().unwrap_or(&self.job_id);
+        let response = send_with_retry(self.client.get(format!("{}/jobs/{}/artifacts", self.server, job_id))
+            .query(&[("worker_id", self.worker_id.as_str())])
+            .header(header::AUTHORIZATION, format!("Bearer {}", self.token)))
+            .await?;
+        let artifacts: Vec<Artifact> = check_response(response, &format!("Failed to list artifacts of job {}", job_id)).await?
+            .json()
+            .await?;
+
+        let mut names = Vec::new();
+        for artifact in artifacts.iter().filter(|artifact| dependency.matches(artifact)) {
+            if !is_valid_name(&artifact.name) {
+                bail!("Invalid artifact name: {}", artifact.name);
+            }
+            let mut url = Url::parse(&format!("{}/jobs/{}/artifacts", self.server, job_id))?;
+            url.path_segments_mut()
+                .map_err(|_| anyhow!("Invalid server URL: {}", self.server))?
+                .push(&artifact.step_name)
+                .extend(artifact.name.split('/'));
+            let response = send_with_retry(self.client.get(url)
+                .query(&[("worker_id", self.worker_id.as_str())])
+                .header(header::AUTHORIZATION, format!("Bearer {}", self.token)))
+                .await?;
+            let response = check_response(response, &format!("Failed to download artifact {}", artifact.name)).await?;
+
+            let path = workspace.join(&artifact.name);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            let mut file = File::create(&path).await
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            let mut body = response.bytes_stream();
+            while let Some(chunk) = body.next().await {
+                file.write_all(&chunk?).await?;
+            }
+            file.flush().await?;
+            debug!("Downloaded artifact {} of job {} to {}", artifact.name, job_id, path.display());
+            names.push(artifact.name.clone());
+        }
+        Ok(names)
+    }
+}
+
+/// The response if it's a success, an error with its status and body otherwise.
+pub(crate) async fn check_response(response: Response, message: &str) -> Result<Response, Error> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let body = response.text().await.unwrap_or_else(|_| "No response body".to_string());
+    Err(anyhow!("{}: {} - {}", message, status, body))
+}
@@ -0,0 +1,257 @@
+use anyhow::{Result, anyhow, bail};
+use serde_json::Value;
+
+/// A parsed `when` expression.
+///
+/// Supports `==`, `!=`, `<`, `<=`, `>`, `>=`, `&&`, `||`, `!`, parentheses, quoted strings, numbers,
+/// `true`/`false`/`null` and dotted paths into the template context, e.g.
+/// `input.env == 'prod' && !steps.check.output.skip`. `<`, `<=`, `>` and `>=` compare numbers
+/// and are false when either side isn't a number.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Literal(Value),
+    Path(Vec<String>),
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Eq(Box<Condition>, Box<Condition>),
+    Ne(Box<Condition>, Box<Condition>),
+    Compare(Box<Condition>, Comparison, Box<Condition>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(f64),
+    Eq,
+    Ne,
+    Cmp(Comparison),
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+impl Condition {
+    pub fn parse(expr: &str) -> Result<Condition> {
+        let tokens = tokenize(expr)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let condition = parser.or()?;
+        if parser.pos != parser.tokens.len() {
+            bail!("Unexpected token {:?} in condition '{}'", parser.tokens[parser.pos], expr);
+        }
+        Ok(condition)
+    }
+
+    pub fn evaluate(&self, context: &Value) -> bool {
+        is_truthy(&self.value(context))
+    }
+
+    fn value(&self, context: &Value) -> Value {
+        match self {
+            Condition::Literal(v) => v.clone(),
+            Condition::Path(path) => path.iter()
+                .try_fold(context, |v, key| v.get(key))
+                .cloned()
+                .unwrap_or(Value::Null),
+            Condition::Not(c) => Value::Bool(!c.evaluate(context)),
+            Condition::And(a, b) => Value::Bool(a.evaluate(context) && b.evaluate(context)),
+            Condition::Or(a, b) => Value::Bool(a.evaluate(context) || b.evaluate(context)),
+            Condition::Eq(a, b) => Value::Bool(loosely_equal(&a.value(context), &b.value(context))),
+            Condition::Ne(a, b) => Value::Bool(!loosely_equal(&a.value(context), &b.value(context))),
+            Condition::Compare(a, comparison, b) => {
+                let result = as_number(&a.value(context)).zip(as_number(&b.value(context)))
+                    .is_some_and(|(a, b)| match comparison {
+                        Comparison::Lt => a < b,
+                        Comparison::Le => a <= b,
+                        Comparison::Gt => a > b,
+                        Comparison::Ge => a >= b,
+                    });
+                Value::Bool(result)
+            }
+        }
+    }
+}
+
+/// Returns the expression inside `{{ ... }}` when the whole string is a single template expression.
+pub fn as_expression(when: &str) -> Option<&str> {
+    let inner = when.trim().strip_prefix("{{")?.strip_suffix("}}")?;
+    (!inner.contains("{{") && !inner.contains("}}")).then_some(inner)
+}
+
+/// Truthiness of a rendered value: null, false, 0, empty strings/collections and the strings "false" and "0" are false.
+pub fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().is_some_and(|n| n != 0.0),
+        Value::String(s) => {
+            let s = s.trim();
+            !(s.is_empty() || s.eq_ignore_ascii_case("false") || s == "0")
+        }
+        Value::Array(a) => !a.is_empty(),
+        Value::Object(o) => !o.is_empty(),
+    }
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Inputs are always strings, so `'1' == 1` and `'true' == true` compare equal.
+fn loosely_equal(a: &Value, b: &Value) -> bool {
+    fn text(v: &Value) -> String {
+        match v {
+            Value::String(s) => s.clone(),
+            v => v.to_string(),
+        }
+    }
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64() == y.as_f64(),
+        (Value::Number(x), Value::String(s)) | (Value::String(s), Value::Number(x)) => s.trim().parse::<f64>().ok() == x.as_f64(),
+        (Value::String(_), Value::Null) | (Value::Null, Value::String(_)) => false,
+        _ => a == b || text(a) == text(b),
+    }
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = expr.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '(' => { tokens.push(Token::LParen); i += 1; }
+            ')' => { tokens.push(Token::RParen); i += 1; }
+            '=' if chars.get(i + 1) == Some(&'=') => { tokens.push(Token::Eq); i += 2; }
+            '!' if chars.get(i + 1) == Some(&'=') => { tokens.push(Token::Ne); i += 2; }
+            '!' => { tokens.push(Token::Not); i += 1; }
+            '<' | '>' => {
+                let or_equal = chars.get(i + 1) == Some(&'=');
+                tokens.push(Token::Cmp(match (c, or_equal) {
+                    ('<', false) => Comparison::Lt,
+                    ('<', true) => Comparison::Le,
+                    ('>', false) => Comparison::Gt,
+                    _ => Comparison::Ge,
+                }));
+                i += if or_equal { 2 } else { 1 };
+            }
+            '&' if chars.get(i + 1) == Some(&'&') => { tokens.push(Token::And); i += 2; }
+            '|' if chars.get(i + 1) == Some(&'|') => { tokens.push(Token::Or); i += 2; }
+            '\'' | '"' => {
+                let end = chars[i + 1..].iter().position(|&q| q == c)
+                    .ok_or_else(|| anyhow!("Unterminated string in condition '{}'", expr))?;
+                tokens.push(Token::Str(chars[i + 1..i + 1 + end].iter().collect()));
+                i += end + 2;
+            }
+            // A minus only starts a number right before a digit, and not after a name (`step-a.output`)
+            c if c.is_ascii_digit() || (c == '-' && chars.get(i + 1).is_some_and(char::is_ascii_digit)
+                && !matches!(tokens.last(), Some(Token::Ident(_)))) => {
+                let start = i;
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let number: String = chars[start..i].iter().collect();
+                tokens.push(Token::Num(number.parse().map_err(|_| anyhow!("Invalid number '{}' in condition '{}'", number, expr))?));
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || matches!(chars[i], '_' | '-' | '.')) {
+                    i += 1;
+                }
+                tokens.push(Token::Ident(chars[start..i].iter().collect()));
+            }
+            c => bail!("Unexpected character '{}' in condition '{}'", c, expr),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next_if(&mut self, token: &Token) -> bool {
+        if self.tokens.get(self.pos) == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> Result<Condition> {
+        let mut left = self.and()?;
+        while self.next_if(&Token::Or) {
+            left = Condition::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Condition> {
+        let mut left = self.unary()?;
+        while self.next_if(&Token::And) {
+            left = Condition::And(Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Condition> {
+        if self.next_if(&Token::Not) {
+            return Ok(Condition::Not(Box::new(self.unary()?)));
+        }
+        let left = self.operand()?;
+        if self.next_if(&Token::Eq) {
+            Ok(Condition::Eq(Box::new(left), Box::new(self.operand()?)))
+        } else if self.next_if(&Token::Ne) {
+            Ok(Condition::Ne(Box::new(left), Box::new(self.operand()?)))
+        } else if let Some(Token::Cmp(comparison)) = self.tokens.get(self.pos).cloned() {
+            self.pos += 1;
+            Ok(Condition::Compare(Box::new(left), comparison, Box::new(self.operand()?)))
+        } else {
+            Ok(left)
+        }
+    }
+
+    fn operand(&mut self) -> Result<Condition> {
+        let token = self.tokens.get(self.pos).cloned()
+            .ok_or_else(|| anyhow!("Unexpected end of condition"))?;
+        self.pos += 1;
+        match token {
+            Token::LParen => {
+                let inner = self.or()?;
+                if !self.next_if(&Token::RParen) {
+                    bail!("Missing closing parenthesis in condition");
+                }
+                Ok(inner)
+            }
+            Token::Str(s) => Ok(Condition::Literal(Value::String(s))),
+            Token::Num(n) => Ok(Condition::Literal(serde_json::json!(n))),
+            Token::Ident(ident) => Ok(match ident.as_str() {
+                "true" => Condition::Literal(Value::Bool(true)),
+                "false" => Condition::Literal(Value::Bool(false)),
+                "null" => Condition::Literal(Value::Null),
+                _ => Condition::Path(ident.split('.').map(|s| s.to_string()).collect()),
+            }),
+            token => bail!("Unexpected token {:?} in condition", token),
+        }
+    }
+}
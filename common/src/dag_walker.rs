@@ -1,4 +1,4 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use anyhow::{Result, anyhow};
 use crate::workflows_configuration::FlowStep;
 
@@ -33,9 +33,8 @@ impl DagWalker {
             }
         }
 
-        // Check for cycles
-        if Self::has_cycle(&graph) {
-            return Err(anyhow!("Cycle detected in flow"));
+        if let Some(cycle) = find_cycles(flow).first() {
+            return Err(anyhow!("Cycle detected in flow: {}", cycle.join(" -> ")));
         }
 
         Ok(DagWalker {
@@ -46,47 +45,6 @@ impl DagWalker {
         })
     }
 
-    /// Detects cycles in the graph using DFS.
-    fn has_cycle(graph: &HashMap<String, Vec<String>>) -> bool {
-        let mut visited = HashSet::new();
-        let mut rec_stack = HashSet::new();
-
-        for start in graph.keys() {
-            if Self::dfs_cycle_check(start, graph, &mut visited, &mut rec_stack) {
-                return true;
-            }
-        }
-        false
-    }
-
-    fn dfs_cycle_check(
-        node: &str,
-        graph: &HashMap<String, Vec<String>>,
-        visited: &mut HashSet<String>,
-        rec_stack: &mut HashSet<String>,
-    ) -> bool {
-        if rec_stack.contains(node) {
-            return true; // Cycle detected
-        }
-        if visited.contains(node) {
-            return false; // Already fully explored
-        }
-
-        visited.insert(node.to_string());
-        rec_stack.insert(node.to_string());
-
-        if let Some(next_steps) = graph.get(node) {
-            for next in next_steps {
-                if Self::dfs_cycle_check(next, graph, visited, rec_stack) {
-                    return true;
-                }
-            }
-        }
-
-        rec_stack.remove(node);
-        false
-    }
-
     /// Returns the next step to execute based on the last completed step.
     /// If step_name is None, returns an initial step with no unmet dependencies that hasn’t been visited.
     /// Marks the completed step as visited and updates dependency counts.
@@ -125,4 +83,58 @@ impl DagWalker {
         self.flow.get(step_name)
     }
 
-}
\ No newline at end of file
+}
+
+/// The cycles in the `depends_on` of a flow, each as the steps it goes through from a step back to itself,
+/// e.g. `a -> b -> a` when `a` depends on `b` and `b` on `a`.
+pub fn find_cycles(flow: &HashMap<String, FlowStep>) -> Vec<Vec<String>> {
+    fn visit<'a>(step: &'a str, flow: &'a HashMap<String, FlowStep>, done: &mut HashSet<&'a str>, path: &mut Vec<&'a str>, cycles: &mut Vec<Vec<String>>) {
+        if let Some(start) = path.iter().position(|s| *s == step) {
+            cycles.push(path[start..].iter().chain([&step]).map(|s| s.to_string()).collect());
+            return;
+        }
+        if !done.insert(step) {
+            return;
+        }
+        path.push(step);
+        let mut dependencies: Vec<&str> = flow[step].depends_on.iter().flatten()
+            .map(String::as_str)
+            .filter(|dependency| flow.contains_key(*dependency))
+            .collect();
+        dependencies.sort();
+        for dependency in dependencies {
+            visit(dependency, flow, done, path, cycles);
+        }
+        path.pop();
+    }
+
+    let mut steps: Vec<&str> = flow.keys().map(String::as_str).collect();
+    steps.sort();
+    let mut done = HashSet::new();
+    let mut cycles = Vec::new();
+    for step in steps {
+        visit(step, flow, &mut done, &mut Vec::new(), &mut cycles);
+    }
+    cycles
+}
+
+/// Steps that can never start, as one of the steps they depend on, directly or through other steps,
+/// is in a cycle or doesn't exist.
+pub fn blocked_steps(flow: &HashMap<String, FlowStep>) -> BTreeSet<String> {
+    let mut waiting: HashMap<&str, usize> = flow.iter()
+        .map(|(name, step)| (name.as_str(), step.depends_on.as_ref().map_or(0, Vec::len)))
+        .collect();
+    let mut ready: Vec<&str> = waiting.iter().filter(|(_, count)| **count == 0).map(|(name, _)| *name).collect();
+    while let Some(done) = ready.pop() {
+        for (name, step) in flow {
+            for _ in step.depends_on.iter().flatten().filter(|dependency| *dependency == done) {
+                let count = waiting.get_mut(name.as_str()).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    ready.push(name);
+                }
+            }
+        }
+    }
+    waiting.into_iter().filter(|(_, count)| *count > 0).map(|(name, _)| name.to_string()).collect()
+}
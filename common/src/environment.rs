@@ -0,0 +1,71 @@
+use std::collections::{BTreeMap, HashMap};
+use std::process::Stdio;
+use std::time::Duration;
+use serde_json::{json, Value};
+use tokio::process::Command;
+use tracing::debug;
+use crate::workflows_configuration::EnvironmentCapture;
+use crate::WORKER_TOKEN_ENV;
+
+/// Tools that don't answer `--version` in time are recorded as unknown.
+const VERSION_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn allowed(name: &str, allowlist: &[String]) -> bool {
+    allowlist.iter().any(|pattern| match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == pattern,
+    })
+}
+
+/// Environment of a child process started like the step's, from `env -0`, so it matches what the step's commands
+/// see. The worker token and variables holding secrets are never recorded, whatever the allowlist.
+async fn capture_env(allowlist: &[String], step_env: &HashMap<String, String>, is_secret: &(dyn Fn(&str) -> bool + Sync)) -> BTreeMap<String, String> {
+    let mut command = Command::new("env");
+    command.arg("-0").env_remove(WORKER_TOKEN_ENV).envs(step_env).stdin(Stdio::null());
+    let output = match command.output().await {
+        Ok(output) => output,
+        Err(e) => {
+            debug!("Failed to run env -0: {}", e);
+            return BTreeMap::new();
+        }
+    };
+    output.stdout.split(|b| *b == 0)
+        .filter_map(|entry| String::from_utf8_lossy(entry).split_once('=').map(|(k, v)| (k.to_string(), v.to_string())))
+        .filter(|(name, value)| name != WORKER_TOKEN_ENV && !is_secret(value))
+        .filter(|(name, _)| allowed(name, allowlist))
+        .collect()
+}
+
+/// First line of `<binary> --version`, or `None` if it is missing or doesn't answer.
+async fn tool_version(binary: &str) -> Option<String> {
+    let command = Command::new(binary)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = match tokio::time::timeout(VERSION_TIMEOUT, command).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => {
+            debug!("Failed to get version of {}: {}", binary, e);
+            return None;
+        }
+        Err(_) => return None,
+    };
+    // Some tools (e.g. java) print their version on stderr
+    [&output.stdout, &output.stderr].iter()
+        .flat_map(|stream| String::from_utf8_lossy(stream).lines().map(str::trim).map(str::to_string).collect::<Vec<_>>())
+        .find(|line| !line.is_empty())
+}
+
+/// Captures the allowlisted environment variables the step runs with, on top of `step_env`, and the versions of the
+/// required tools. Variables whose value `is_secret` are left out.
+pub async fn capture(config: &EnvironmentCapture, requires: &[String], step_env: &HashMap<String, String>, is_secret: &(dyn Fn(&str) -> bool + Sync)) -> Value {
+    let mut tools = BTreeMap::new();
+    for binary in requires {
+        tools.insert(binary.clone(), tool_version(binary).await);
+    }
+    json!({
+        "env": capture_env(&config.env, step_env, is_secret).await,
+        "tools": tools,
+    })
+}
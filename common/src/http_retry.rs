@@ -0,0 +1,65 @@
+//! Retries for worker and runner calls to the server, so a network blip doesn't lose a job's outcome.
+//! Retried requests carry the same `Idempotency-Key`, which the server uses to skip ones it already applied.
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use reqwest::{header, RequestBuilder, Response, StatusCode};
+use tracing::warn;
+use uuid::Uuid;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+/// Longest `Retry-After` waited for between attempts, so a misbehaving server can't stall a worker
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Sends the request, retrying with exponential backoff on connection errors, timeouts and
+/// 408/429/502/503/504 responses, or after the `Retry-After` of the response if it has one.
+/// Other responses, including errors, are returned as is.
+pub async fn send_with_retry(request: RequestBuilder) -> Result<Response, reqwest::Error> {
+    send_with_key(request, &Uuid::new_v4().to_string()).await
+}
+
+/// Like `send_with_retry`, with a caller-chosen idempotency key, e.g. one persisted with the request.
+pub async fn send_with_key(request: RequestBuilder, idempotency_key: &str) -> Result<Response, reqwest::Error> {
+    let request = request.header(IDEMPOTENCY_KEY_HEADER, idempotency_key);
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        // Streaming bodies can't be cloned, those are sent once
+        let Some(retry) = request.try_clone().filter(|_| attempt < MAX_ATTEMPTS) else {
+            return request.send().await;
+        };
+        let delay = match retry.send().await {
+            Ok(response) if is_retryable_status(response.status()) => {
+                let delay = retry_after(&response).unwrap_or(backoff);
+                warn!("Server responded {}, retrying in {:?} (attempt {}/{})", response.status(), delay, attempt, MAX_ATTEMPTS);
+                delay
+            }
+            Err(e) if e.is_connect() || e.is_timeout() || e.is_request() => {
+                warn!("Request failed: {}, retrying in {:?} (attempt {}/{})", e, backoff, attempt, MAX_ATTEMPTS);
+                backoff
+            }
+            result => return result,
+        };
+        tokio::time::sleep(delay).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+        attempt += 1;
+    }
+}
+
+/// The wait the response asks for in `Retry-After`, as seconds or an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(header::RETRY_AFTER)?.to_str().ok()?.trim();
+    let delay = match value.parse::<u64>() {
+        Ok(secs) => Duration::from_secs(secs),
+        Err(_) => (DateTime::parse_from_rfc2822(value).ok()?.with_timezone(&Utc) - Utc::now()).to_std().unwrap_or_default(),
+    };
+    Some(delay.min(MAX_RETRY_AFTER))
+}
+
+/// Statuses worth retrying, including the ones a server sends when it's busy rather than refusing the request.
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(status, StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS | StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT)
+}
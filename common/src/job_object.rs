@@ -0,0 +1,56 @@
+// common/src/job_object.rs
+//! Windows Job Objects: `kill_on_drop` only terminates the direct child, so a timed out `cmd` script or a
+//! runner stopped by the worker would leave the processes it started running. Assigned to a job, they
+//! are all killed once the job's handle is closed.
+use std::ffi::c_void;
+use std::io;
+use std::mem::{size_of, zeroed};
+use std::ptr::null;
+use anyhow::{anyhow, Error};
+use tokio::process::Child;
+use windows_sys::Win32::Foundation::{CloseHandle, HANDLE};
+use windows_sys::Win32::System::JobObjects::{
+    AssignProcessToJobObject, CreateJobObjectW, JobObjectExtendedLimitInformation, SetInformationJobObject,
+    JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+};
+
+/// Kills the processes of the job when dropped.
+pub struct JobObject(HANDLE);
+
+// The handle is owned and only closed on drop
+unsafe impl Send for JobObject {}
+unsafe impl Sync for JobObject {}
+
+impl JobObject {
+    /// Puts `child` in a new job; the processes it starts from then on join the job as well.
+    pub fn assign(child: &Child) -> Result<Self, Error> {
+        let process = child.raw_handle().ok_or_else(|| anyhow!("Process already exited"))?;
+        // SAFETY: the job handle is checked before use and owned by the returned value, `info` outlives the call
+        unsafe {
+            let handle = CreateJobObjectW(null(), null());
+            if handle.is_null() {
+                return Err(anyhow!("Failed to create job object: {}", io::Error::last_os_error()));
+            }
+            let job = JobObject(handle);
+            let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = zeroed();
+            info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+            let info_ptr = &info as *const JOBOBJECT_EXTENDED_LIMIT_INFORMATION as *const c_void;
+            if SetInformationJobObject(job.0, JobObjectExtendedLimitInformation, info_ptr, size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32) == 0 {
+                return Err(anyhow!("Failed to configure job object: {}", io::Error::last_os_error()));
+            }
+            if AssignProcessToJobObject(job.0, process as HANDLE) == 0 {
+                return Err(anyhow!("Failed to assign process to job object: {}", io::Error::last_os_error()));
+            }
+            Ok(job)
+        }
+    }
+}
+
+impl Drop for JobObject {
+    fn drop(&mut self) {
+        // SAFETY: the handle was created by `assign` and is closed only here
+        unsafe {
+            CloseHandle(self.0);
+        }
+    }
+}
@@ -1,48 +1,189 @@
-use std::path::{PathBuf};
+use std::path::{Path, PathBuf};
 // common/src/lib.rs
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 use chrono::{DateTime, Utc};
 use tokio::io::{AsyncWriteExt, BufReader};
 use tokio::io::AsyncBufReadExt;
-use std::process::Stdio;
+use std::process::{ExitStatus, Stdio};
 use tracing::{error};
-use anyhow::{anyhow, Error};
+use anyhow::{anyhow, bail, Error};
+use std::convert::Infallible;
+use std::str::FromStr;
 use tokio::process::Command as TokioCommand;
 use tokio::sync::mpsc::{self};
 use serde_json::Value;
 use regex::Regex;
 use std::io;
 use std::sync::Arc;
+use std::collections::HashMap;
 use uuid;
 use globwalker::GlobWalkerBuilder;
 use tracing_subscriber::{self, filter::LevelFilter, fmt, prelude::*};
 
 pub mod log_collector;
 pub mod parameter_renderer;
+pub mod condition;
 pub mod dag_walker;
 pub mod workflows_configuration;
 pub mod workspace_client;
 pub mod runner;
+pub mod rfc3339;
+pub mod log_level;
+pub mod step_hook;
+pub mod secrets;
+pub mod environment;
+pub mod tls;
+pub mod http_retry;
+pub mod protocol;
+pub mod telemetry;
+pub mod shutdown;
+pub mod artifacts;
+pub mod step_cache;
+pub mod approval;
+pub mod wait;
 mod action;
+#[cfg(windows)]
+mod job_object;
 
 use log_collector::{LogCollector, LogEntry};
 
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// What a job runs: exactly one task or action.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobSpec {
+    Task { name: String },
+    Action { name: String },
+}
+
+impl JobSpec {
+    pub fn new(task: Option<String>, action: Option<String>) -> Result<Self, String> {
+        match (task, action) {
+            (Some(name), None) => Ok(JobSpec::Task { name }),
+            (None, Some(name)) => Ok(JobSpec::Action { name }),
+            (Some(_), Some(_)) => Err("A job can't specify both a task and an action".to_string()),
+            (None, None) => Err("A job must specify either a task or an action".to_string()),
+        }
+    }
+
+    pub fn task(&self) -> Option<&str> {
+        match self {
+            JobSpec::Task { name } => Some(name),
+            JobSpec::Action { .. } => None,
+        }
+    }
+
+    pub fn action(&self) -> Option<&str> {
+        match self {
+            JobSpec::Action { name } => Some(name),
+            JobSpec::Task { .. } => None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            JobSpec::Task { name } | JobSpec::Action { name } => name,
+        }
+    }
+}
+
+/// Serialized as separate `task` and `action` fields, of which exactly one must be set.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(try_from = "JobRequestFields", into = "JobRequestFields")]
 pub struct JobRequest {
-    pub task: Option<String>,
-    pub action: Option<String>,
+    pub spec: JobSpec,
     pub input: Option<serde_json::Value>,
     pub uuid: Option<uuid::Uuid>,
+    /// Jobs with a higher priority are dispatched first, defaults to 0
+    pub priority: Option<i32>,
+    /// Set when the job resumes a failed one from its failed step
+    pub resume: Option<ResumeState>,
+    /// W3C trace context of the span the job was enqueued in, set when traces are exported
+    pub traceparent: Option<String>,
+    /// Project whose workspace the job runs in, `None` for the default project
+    pub project: Option<String>,
+}
+
+/// Name of the project served from the `workspace` of the server config.
+pub const DEFAULT_PROJECT: &str = "default";
+
+/// Steps a resumed job takes over from the failed job it resumes, instead of running them again.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+pub struct ResumeState {
+    /// Job the steps completed in
+    pub job_id: String,
+    /// Output of each completed step, by step name
+    pub steps: HashMap<String, Option<Value>>,
+}
+
+impl JobRequest {
+    pub fn task(&self) -> Option<&str> {
+        self.spec.task()
+    }
+
+    pub fn action(&self) -> Option<&str> {
+        self.spec.action()
+    }
+
+    pub fn project(&self) -> &str {
+        self.project.as_deref().unwrap_or(DEFAULT_PROJECT)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct JobRequestFields {
+    #[serde(default = "protocol::legacy_protocol_version")]
+    protocol_version: u32,
+    task: Option<String>,
+    action: Option<String>,
+    input: Option<serde_json::Value>,
+    uuid: Option<uuid::Uuid>,
+    #[serde(default)]
+    priority: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    resume: Option<ResumeState>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    traceparent: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    project: Option<String>,
+}
+
+impl TryFrom<JobRequestFields> for JobRequest {
+    type Error = String;
+
+    fn try_from(fields: JobRequestFields) -> Result<Self, Self::Error> {
+        if fields.protocol_version > protocol::PROTOCOL_VERSION {
+            return Err(format!("Job uses protocol version {}, this build supports up to {}", fields.protocol_version, protocol::PROTOCOL_VERSION));
+        }
+        let spec = JobSpec::new(fields.task, fields.action)?;
+        Ok(JobRequest { spec, input: fields.input, uuid: fields.uuid, priority: fields.priority, resume: fields.resume, traceparent: fields.traceparent, project: fields.project })
+    }
+}
+
+impl From<JobRequest> for JobRequestFields {
+    fn from(job: JobRequest) -> Self {
+        let (task, action) = match job.spec {
+            JobSpec::Task { name } => (Some(name), None),
+            JobSpec::Action { name } => (None, Some(name)),
+        };
+        JobRequestFields { protocol_version: protocol::PROTOCOL_VERSION, task, action, input: job.input, uuid: job.uuid, priority: job.priority, resume: job.resume, traceparent: job.traceparent, project: job.project }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct JobResult {
+    /// See [`protocol`], results without one are from workers and runners predating versioning
+    #[serde(default = "protocol::legacy_protocol_version")]
+    pub protocol_version: u32,
     // pub worker_id: String, // --
     // pub job_id: String, // --
     pub success: bool,
     // pub logs: Vec<LogEntry>, // --
+    #[serde(with = "rfc3339")]
+    #[schemars(with = "DateTime<Utc>")]
     pub start_datetime: DateTime<Utc>,
+    #[serde(with = "rfc3339")]
+    #[schemars(with = "DateTime<Utc>")]
     pub end_datetime: DateTime<Utc>,
     // #[serde(default)]
     // pub task: Option<String>, // --
@@ -54,6 +195,105 @@ pub struct JobResult {
     pub output: Option<serde_json::Value>,
     #[serde(default)]
     pub revision: Option<String>,  // New field
+    #[serde(default)]
+    pub attempts: Option<u32>,
+    #[serde(default)]
+    pub status: Option<String>,
+    /// Links emitted by the step with `LINK:` lines
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub links: Option<Vec<StepLink>>,
+    /// Allowlisted environment variables and tool versions seen by the step
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<serde_json::Value>,
+    /// Why a job failed without running to completion, e.g. [`REASON_WORKER_SHUTDOWN`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Reason of jobs interrupted because their worker shut down before they finished.
+pub const REASON_WORKER_SHUTDOWN: &str = "worker_shutdown";
+
+/// Reason of jobs failed at lease because their worker speaks a protocol version too old to run them as intended.
+pub const REASON_WORKER_OUTDATED: &str = "worker_outdated";
+
+/// Reason of jobs failed at lease because their row can't be turned into a job request, e.g. naming neither a task nor an action.
+pub const REASON_INVALID_JOB: &str = "invalid_job";
+
+/// Prefix of link lines, e.g. `LINK: {"title": "Grafana", "url": "https://grafana.example.com/d/abc"}`
+pub const LINK_PREFIX: &str = "LINK:";
+
+/// A deep link produced while a step ran, shown with the step in the UI.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+pub struct StepLink {
+    pub title: String,
+    pub url: String,
+}
+
+impl StepLink {
+    /// Parses a `LINK:` line; only http(s) URLs are accepted.
+    pub fn from_line(line: &str) -> Option<StepLink> {
+        let link: StepLink = serde_json::from_str(line.strip_prefix(LINK_PREFIX)?.trim()).ok()?;
+        let url = link.url.to_ascii_lowercase();
+        (url.starts_with("https://") || url.starts_with("http://")).then_some(link)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct WorkerRegistration {
+    pub capacity: u32,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct WorkerHeartbeat {
+    pub running: u32,
+}
+
+/// Environment variable used to hand the worker token to the worker and runner processes.
+pub const WORKER_TOKEN_ENV: &str = "STROEM_WORKER_TOKEN";
+/// Workspace folder of the runner and the CLI; the worker sets it for its runners to the folder it keeps synced.
+pub const WORKSPACE_ENV: &str = "STROEM_WORKSPACE";
+
+/// A secret value that never shows up in debug output.
+#[derive(Clone)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(***)")
+    }
+}
+
+impl FromStr for Secret {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Secret(s.to_string()))
+    }
+}
+
+/// Resolves the worker token from either an explicit value (argument or environment) or a file.
+pub fn resolve_token(token: Option<Secret>, token_file: Option<&Path>) -> Result<Secret, Error> {
+    match (token, token_file) {
+        (Some(token), _) => Ok(token),
+        (None, Some(path)) => {
+            let content = std::fs::read_to_string(path)
+                .map_err(|e| anyhow!("Failed to read token file {}: {}", path.display(), e))?;
+            let token = content.trim();
+            if token.is_empty() {
+                bail!("Token file {} is empty", path.display());
+            }
+            Ok(Secret(token.to_string()))
+        }
+        (None, None) => bail!("A worker token is required: use --token, --token-file or {}", WORKER_TOKEN_ENV),
+    }
 }
 
 lazy_static::lazy_static! {
@@ -64,29 +304,45 @@ fn strip_ansi(input: &str) -> String {
     ANSI_REGEX.replace_all(input, "").to_string()
 }
 
-pub async fn run(cmd: &str, args: Option<Vec<String>>, stdin_content: Option<String>, cwd: Option<&PathBuf>, log_collector: Arc<dyn LogCollector + Send + Sync>) -> Result<(bool, Option<Value>), Error> {
+pub async fn run(cmd: &str, args: Option<Vec<String>>, stdin_content: Option<String>, cwd: Option<&PathBuf>, envs: Option<HashMap<String, String>>, log_collector: Arc<dyn LogCollector + Send + Sync>) -> Result<(bool, Option<Value>, Vec<StepLink>), Error> {
+    let (status, output, links) = run_with_status(cmd, args, stdin_content, cwd, envs, log_collector).await?;
+    Ok((status.success(), output, links))
+}
+
+/// Like `run`, with the exit status of the command instead of whether it succeeded.
+pub async fn run_with_status(cmd: &str, args: Option<Vec<String>>, stdin_content: Option<String>, cwd: Option<&PathBuf>, envs: Option<HashMap<String, String>>, log_collector: Arc<dyn LogCollector + Send + Sync>) -> Result<(ExitStatus, Option<Value>, Vec<StepLink>), Error> {
     let mut command = TokioCommand::new(cmd);
     if let Some(args) = args {
         command.args(args);
     }
+    // Never leak the worker token into spawned processes unless explicitly passed
+    command.env_remove(WORKER_TOKEN_ENV);
+    if let Some(envs) = envs {
+        command.envs(envs);
+    }
     if let Some(cwd) = cwd {
         command.current_dir(cwd);
     }
     command.stdout(Stdio::piped());
     command.stderr(Stdio::piped());
+    // Make sure the process does not outlive a cancelled (e.g. timed out) execution
+    command.kill_on_drop(true);
     if stdin_content.is_some() {
         command.stdin(Stdio::piped());
     }
 
     let mut child = command.spawn()
         .map_err(|e| anyhow!("Failed to spawn command: {}", e))?;
+    // Kills the processes the command started as well, once done or dropped
+    #[cfg(windows)]
+    let _job = job_object::JobObject::assign(&child)?;
 
     let stdout = child.stdout.take().unwrap();
     let stderr = child.stderr.take().unwrap();
-    if stdin_content.is_some() {
+    if let Some(content) = stdin_content {
         let mut stdin = child.stdin.take().unwrap();
 
-        stdin.write(stdin_content.unwrap().as_ref()).await?;
+        stdin.write_all(content.as_ref()).await?;
         stdin.flush().await?;
         stdin.shutdown().await?;
         drop(stdin);
@@ -97,6 +353,8 @@ pub async fn run(cmd: &str, args: Option<Vec<String>>, stdin_content: Option<Str
     // let (log_tx, mut log_rx) = mpsc::channel::<LogEntry>(100);
     // Channel for OUTPUT: lines
     let (output_tx, mut output_rx) = mpsc::channel::<String>(100);
+    // Channel for LINK: lines
+    let (link_tx, mut link_rx) = mpsc::channel::<StepLink>(100);
 
     // Stdout task
     let lc_stdout = log_collector.clone();
@@ -104,15 +362,13 @@ pub async fn run(cmd: &str, args: Option<Vec<String>>, stdin_content: Option<Str
         let mut stdout_reader = BufReader::new(stdout).lines();
         while let Some(line) = stdout_reader.next_line().await.unwrap_or(None) {
             let clean_line = strip_ansi(&line);
-            let entry = LogEntry {
-                timestamp: Utc::now(),
-                is_stderr: false,
-                message: clean_line,
-            };
+            let entry = LogEntry::from_line(clean_line, false);
             lc_stdout.log(entry).await.ok();
             // log_tx_stdout.send(entry).await.unwrap_or_else(|e| error!("Failed to send stdout log: {}", e));
             if line.starts_with("OUTPUT:") {
                 output_tx.send(line).await.unwrap_or_else(|e| error!("Failed to send output line: {}", e));
+            } else if let Some(link) = StepLink::from_line(&line) {
+                link_tx.send(link).await.unwrap_or_else(|e| error!("Failed to send link line: {}", e));
             }
         }
     });
@@ -123,11 +379,7 @@ pub async fn run(cmd: &str, args: Option<Vec<String>>, stdin_content: Option<Str
         let mut stderr_reader = BufReader::new(stderr).lines();
         while let Some(line) = stderr_reader.next_line().await.unwrap_or(None) {
             let clean_line = strip_ansi(&line);
-            let entry = LogEntry {
-                timestamp: Utc::now(),
-                is_stderr: true,
-                message: clean_line,
-            };
+            let entry = LogEntry::from_line(clean_line, true);
             lc_stderr.log(entry).await.ok();
             // log_tx_stderr.send(entry).await.unwrap_or_else(|e| error!("Failed to send stderr log: {}", e));
         }
@@ -149,11 +401,16 @@ pub async fn run(cmd: &str, args: Option<Vec<String>>, stdin_content: Option<Str
         }
     };
 
-    Ok((status.success(), output))
+    let mut links = Vec::new();
+    while let Some(link) = link_rx.recv().await {
+        links.push(link);
+    }
+
+    Ok((status, output, links))
 }
 
 
-pub fn init_tracing(verbose: bool) {
+pub fn init_tracing(verbose: bool, telemetry: &telemetry::Telemetry) {
     // Configure tracing with split output
     let stdout_writer = io::stdout; // For INFO and below
     let stderr_writer = io::stderr; // For WARN and above
@@ -174,6 +431,7 @@ pub fn init_tracing(verbose: bool) {
     tracing_subscriber::registry()
         .with(stderr_layer)
         .with(stdout_layer)
+        .with(telemetry.layer())
         .init();
 }
 
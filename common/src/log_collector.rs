@@ -1,23 +1,141 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+use std::io;
 use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use anyhow::{Error, anyhow};
 use chrono::{DateTime, Utc};
-use reqwest::{header, Client};
+use reqwest::{header, Body, Client};
 use serde::{Deserialize, Serialize};
-use tokio::sync::{mpsc, RwLock};
+use schemars::JsonSchema;
+use tokio::sync::{mpsc, Mutex, RwLock};
+use tokio_stream::wrappers::ReceiverStream;
 use tokio::task::JoinHandle;
 use tracing::{error, info, debug};
 use async_trait::async_trait;
-use serde_json::{json, Value};
+use serde_json::{json, Map, Value};
 use tokio::time::sleep;
 use crate::JobResult;
+use crate::http_retry::send_with_retry;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct LogEntry {
+    #[serde(with = "crate::rfc3339")]
+    #[schemars(with = "DateTime<Utc>")]
     pub timestamp: DateTime<Utc>,
     pub is_stderr: bool,
     pub message: String,
+    /// Step that was running when the line was collected, `None` for job-level logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attempt: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<LogLevel>,
+    /// Extra fields of a structured log line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Map<String, Value>>,
+}
+
+/// Maximum size in bytes of a log message, longer ones are truncated
+pub const MAX_LOG_ENTRY_SIZE_ENV: &str = "STROEM_MAX_LOG_ENTRY_SIZE";
+pub const DEFAULT_MAX_LOG_ENTRY_SIZE: usize = 64 * 1024;
+/// Content type of a log body streamed as one JSON entry per line
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+/// Streams log entries to the server as they're collected instead of posting them in batches
+pub const STREAM_LOGS_ENV: &str = "STROEM_STREAM_LOGS";
+/// Log streams of steps without entries for this long are closed, so finished steps don't keep requests open
+const STREAM_IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Keeps single huge lines, like a JSON dump, from bloating the log storage and the UI.
+#[derive(Debug, Clone)]
+pub struct LogLimits {
+    pub max_entry_size: usize,
+}
+
+impl Default for LogLimits {
+    fn default() -> Self {
+        Self { max_entry_size: DEFAULT_MAX_LOG_ENTRY_SIZE }
+    }
+}
+
+impl LogLimits {
+    /// Environment variables that carry these settings to a runner.
+    pub fn envs(&self) -> Vec<(String, String)> {
+        vec![(MAX_LOG_ENTRY_SIZE_ENV.to_string(), self.max_entry_size.to_string())]
+    }
+
+    /// Cuts the message down to `max_entry_size` bytes, marking how much was dropped.
+    pub fn apply(&self, entry: &mut LogEntry) {
+        if entry.message.len() <= self.max_entry_size {
+            return;
+        }
+        let mut end = self.max_entry_size;
+        while !entry.message.is_char_boundary(end) {
+            end -= 1;
+        }
+        let truncated = entry.message.len() - end;
+        entry.message.truncate(end);
+        entry.message.push_str(&format!("…[truncated {} bytes]", truncated));
+        let fields = entry.fields.get_or_insert_with(Map::new);
+        fields.insert("truncated_bytes".to_string(), json!(truncated));
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+/// Prefix of structured log lines, e.g. `LOG:{"level": "warn", "message": "disk almost full", "free_mb": 120}`
+pub const STRUCTURED_LOG_PREFIX: &str = "LOG:";
+
+impl LogEntry {
+    /// A plain message logged now, e.g. progress the runner reports itself.
+    pub fn new(message: String, is_stderr: bool) -> Self {
+        LogEntry {
+            timestamp: Utc::now(),
+            is_stderr,
+            message,
+            step_name: None,
+            attempt: None,
+            level: None,
+            fields: None,
+        }
+    }
+
+    /// Builds an entry from an output line, parsing it as a structured log line if it has the `LOG:` prefix.
+    /// Lines that are not valid structured logs are kept as plain messages.
+    pub fn from_line(line: String, is_stderr: bool) -> Self {
+        let mut entry = LogEntry::new(line, is_stderr);
+        let structured = entry.message.strip_prefix(STRUCTURED_LOG_PREFIX)
+            .and_then(|json| serde_json::from_str::<Map<String, Value>>(json.trim()).ok());
+        if let Some(mut fields) = structured {
+            entry.level = fields.get("level")
+                .and_then(|level| serde_json::from_value(level.clone()).ok());
+            if entry.level.is_some() {
+                fields.remove("level");
+            }
+            if let Some(message) = fields.remove("message").or_else(|| fields.remove("msg")) {
+                entry.message = match message {
+                    Value::String(message) => message,
+                    message => message.to_string(),
+                };
+            }
+            entry.fields = (!fields.is_empty()).then_some(fields);
+        }
+        entry
+    }
+
+    /// The level of the entry, plain lines are `info` on stdout and `error` on stderr.
+    pub fn effective_level(&self) -> LogLevel {
+        self.level.unwrap_or(if self.is_stderr { LogLevel::Error } else { LogLevel::Info })
+    }
 }
 
 #[async_trait]
@@ -25,6 +143,7 @@ pub trait LogCollector {
     async fn log(&self, entry: LogEntry) -> Result<(), Error>;
     async fn flush(&self) -> Result<(), Error>;
     async fn set_step_name(&self, step_name: Option<String>);
+    async fn set_attempt(&self, attempt: Option<u32>);
 
     async fn mark_start(&self, start: DateTime<Utc>, input: &Option<Value>) -> Result<(), Error> ;
     async fn store_results(&self, result: JobResult) -> Result<(), Error> ;
@@ -38,14 +157,18 @@ pub struct LogCollectorServer {
     token: String,
     client: Client,
     step_name: Arc<RwLock<Option<String>>>,
+    attempt: Arc<RwLock<Option<u32>>>,
     buffer: Arc<RwLock<VecDeque<LogEntry>>>,
     buffer_size: usize,
+    limits: LogLimits,
+    streaming: Arc<AtomicBool>,
+    streams: Arc<Mutex<HashMap<Option<String>, LogStream>>>,
     sender: mpsc::Sender<LogEntry>,
     handle: Arc<Option<JoinHandle<()>>>,
 }
 
 impl LogCollectorServer {
-    pub fn new(server: String, job_id: String, worker_id: String, token: String, step_name: Option<String>, buffer_size: Option<usize>) -> Self {
+    pub fn new(client: Client, server: String, job_id: String, worker_id: String, token: String, step_name: Option<String>, buffer_size: Option<usize>) -> Self {
         let buffer_size = buffer_size.unwrap_or(10);
         let (sender, mut receiver) = mpsc::channel::<LogEntry>(100);
 
@@ -55,10 +178,14 @@ impl LogCollectorServer {
             job_id,
             worker_id,
             token,
-            client: Client::new(),
+            client,
             step_name: Arc::new(RwLock::new(step_name)),
+            attempt: Arc::new(RwLock::new(None)),
             buffer: Arc::new(RwLock::new(VecDeque::with_capacity(buffer_size))),
             buffer_size,
+            limits: LogLimits::default(),
+            streaming: Arc::new(AtomicBool::new(false)),
+            streams: Arc::new(Mutex::new(HashMap::new())),
             sender,
             handle: Arc::new(None)
         };
@@ -71,6 +198,11 @@ impl LogCollectorServer {
                 tokio::select! {
                     entry = receiver.recv() => {
                         match entry {
+                            Some(entry) if lc.streaming.load(Ordering::Relaxed) => {
+                                if let Err(e) = lc.stream_entry(entry).await {
+                                    error!("Failed to stream logs: {}", e);
+                                }
+                            }
                             Some(entry) => {
                                 let mut buffer_guard = lc.buffer.write().await;
                                 buffer_guard.push_back(entry);
@@ -96,13 +228,88 @@ impl LogCollectorServer {
         s
     }
 
+    pub fn with_limits(mut self, limits: LogLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Streams entries as NDJSON over one request per step instead of posting them in batches.
+    /// The request of a step is closed on flush and when no entries came in for it for a while.
+    /// Streamed entries aren't resent if the request fails.
+    pub fn with_streaming(self, streaming: bool) -> Self {
+        self.streaming.store(streaming, Ordering::Relaxed);
+        self
+    }
+
+    async fn stream_entry(&self, entry: LogEntry) -> Result<(), Error> {
+        let mut streams = self.streams.lock().await;
+        let idle: Vec<Option<String>> = streams.iter()
+            .filter(|(step_name, stream)| **step_name != entry.step_name && stream.last_entry.elapsed() >= STREAM_IDLE_TIMEOUT)
+            .map(|(step_name, _)| step_name.clone())
+            .collect();
+        for step_name in idle {
+            let Some(stream) = streams.remove(&step_name) else { continue };
+            if let Err(e) = stream.close().await {
+                error!("Failed to stream logs: {}", e);
+            }
+        }
+        let stream = streams.entry(entry.step_name.clone())
+            .or_insert_with(|| self.open_stream(entry.step_name.as_deref()));
+        stream.last_entry = Instant::now();
+        let line = format!("{}\n", serde_json::to_string(&entry)?);
+        if stream.sender.send(Ok(line)).await.is_err() {
+            // The request ended early, its error tells why
+            if let Some(stream) = streams.remove(&entry.step_name) {
+                stream.close().await?;
+            }
+            return Err(anyhow!("Log stream closed by the server"));
+        }
+        Ok(())
+    }
+
+    fn open_stream(&self, step_name: Option<&str>) -> LogStream {
+        let (sender, receiver) = mpsc::channel(100);
+        let url = self.url_for(step_name, "logs");
+        debug!("Streaming logs to {}", url);
+        let request = send_with_retry(self.client.post(&url)
+            .header(header::AUTHORIZATION, format!("Bearer {}", self.token))
+            .header(header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)
+            .body(Body::wrap_stream(ReceiverStream::new(receiver))));
+        let request = tokio::spawn(async move {
+            let response = request.await?;
+            if !response.status().is_success() {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_else(|_| "No response body".to_string());
+                return Err(anyhow!("Failed to stream logs to {}: {} - {}", url, status, body));
+            }
+            Ok(())
+        });
+        LogStream { last_entry: Instant::now(), sender, request }
+    }
+
+    /// Sends the buffered entries to the logs endpoint of the step each entry was collected for.
     async fn send_logs(&self, buffer: &VecDeque<LogEntry>) -> Result<(), Error> {
-        let url = self.get_url("logs").await;
+        let mut result = Ok(());
+        let mut start = 0;
+        while start < buffer.len() {
+            let step_name = buffer[start].step_name.as_deref();
+            let end = buffer.iter().skip(start).position(|entry| entry.step_name.as_deref() != step_name)
+                .map_or(buffer.len(), |len| start + len);
+            let batch: Vec<&LogEntry> = buffer.range(start..end).collect();
+            if let Err(e) = self.send_log_batch(step_name, &batch).await {
+                result = Err(e);
+            }
+            start = end;
+        }
+        result
+    }
+
+    async fn send_log_batch(&self, step_name: Option<&str>, buffer: &[&LogEntry]) -> Result<(), Error> {
+        let url = self.url_for(step_name, "logs");
         debug!("Sending {} logs to {}", buffer.len(), url);
-        let response = self.client.post(&url)
+        let response = send_with_retry(self.client.post(&url)
             .header(header::AUTHORIZATION, format!("Bearer {}", self.token))
-            .json(&buffer)
-            .send()
+            .json(&buffer))
             .await;
 
         match response {
@@ -126,7 +333,11 @@ impl LogCollectorServer {
 
     async fn get_url(&self, url_type: &str) -> String {
         let step_name_guard = self.step_name.read().await;
-        match step_name_guard.as_ref() {
+        self.url_for(step_name_guard.as_deref(), url_type)
+    }
+
+    fn url_for(&self, step_name: Option<&str>, url_type: &str) -> String {
+        match step_name {
             Some(step) => format!("{}/jobs/{}/steps/{}/{}?worker_id={}", self.server, self.job_id, step, url_type, self.worker_id),
             None => format!("{}/jobs/{}/{}?worker_id={}", self.server, self.job_id, url_type, self.worker_id),
         }
@@ -134,6 +345,21 @@ impl LogCollectorServer {
 
 }
 
+/// An open NDJSON log request, fed line by line.
+struct LogStream {
+    last_entry: Instant,
+    sender: mpsc::Sender<Result<String, io::Error>>,
+    request: JoinHandle<Result<(), Error>>,
+}
+
+impl LogStream {
+    /// Ends the request body and waits for the server to have saved everything.
+    async fn close(self) -> Result<(), Error> {
+        drop(self.sender);
+        self.request.await?
+    }
+}
+
 impl Drop for LogCollectorServer {
     fn drop(&mut self) {
         if let Some(handle) = self.handle.as_ref() {
@@ -145,13 +371,24 @@ impl Drop for LogCollectorServer {
 #[async_trait]
 impl LogCollector for LogCollectorServer {
 
-    async fn log(&self, entry: LogEntry) -> Result<(), Error> {
-        // let entry = LogEntry { timestamp, is_stderr, message };
+    async fn log(&self, mut entry: LogEntry) -> Result<(), Error> {
+        // Tag the entry now, the current step may have changed by the time the buffer is sent
+        if entry.step_name.is_none() {
+            entry.step_name = self.step_name.read().await.clone();
+        }
+        if entry.attempt.is_none() {
+            entry.attempt = *self.attempt.read().await;
+        }
+        self.limits.apply(&mut entry);
         self.sender.send(entry).await?;
         Ok(())
     }
 
     async fn flush(&self) -> Result<(), Error> {
+        let streams: Vec<LogStream> = self.streams.lock().await.drain().map(|(_, stream)| stream).collect();
+        for stream in streams {
+            stream.close().await?;
+        }
         let mut buffer_guard = self.buffer.write().await;
         if !buffer_guard.is_empty() {
             debug!("Flushing {} remaining logs", buffer_guard.len());
@@ -167,6 +404,10 @@ impl LogCollector for LogCollectorServer {
         *step_name_guard = step_name;
     }
 
+    async fn set_attempt(&self, attempt: Option<u32>) {
+        *self.attempt.write().await = attempt;
+    }
+
     async fn mark_start(&self, start: DateTime<Utc>, input: &Option<Value>) -> Result<(), Error> {
         let start_payload = json!({
             "start_datetime": start.to_rfc3339(),
@@ -175,10 +416,9 @@ impl LogCollector for LogCollectorServer {
 
         let url = self.get_url("start").await;
 
-        let response = self.client.post(&url)
+        let response = send_with_retry(self.client.post(&url)
             .header(header::AUTHORIZATION, format!("Bearer {}", self.token))
-            .json(&start_payload)
-            .send()
+            .json(&start_payload))
             .await;
 
         match response {
@@ -201,10 +441,9 @@ impl LogCollector for LogCollectorServer {
 
     async fn store_results(&self, result: JobResult) -> Result<(), Error>  {
         let url = self.get_url("results").await;
-        let response = self.client.post(&url)
+        let response = send_with_retry(self.client.post(&url)
             .header(header::AUTHORIZATION, format!("Bearer {}", self.token))
-            .json(&result)
-            .send()
+            .json(&result))
             .await;
 
         match response {
@@ -256,6 +495,8 @@ impl LogCollector for LogCollectorConsole {
         *step_name_guard = step_name;
     }
 
+    async fn set_attempt(&self, _attempt: Option<u32>) {}
+
     async fn mark_start(&self, _start: DateTime<Utc>, input: &Option<Value>) -> Result<(), Error> {
         let step_name_guard = self.step_name.read().await;
         if let Some(step_name) = step_name_guard.as_ref() {
@@ -0,0 +1,70 @@
+use std::str::FromStr;
+use anyhow::{anyhow, Error};
+use tracing::info;
+use tracing_subscriber::{filter::LevelFilter, fmt, prelude::*, reload, Registry};
+use crate::telemetry::Telemetry;
+
+/// Handle to change the log level of a running process.
+#[derive(Clone)]
+pub struct LogLevelHandle {
+    handle: reload::Handle<LevelFilter, Registry>,
+    initial: LevelFilter,
+}
+
+impl LogLevelHandle {
+    pub fn current(&self) -> LevelFilter {
+        self.handle.clone_current().unwrap_or(self.initial)
+    }
+
+    pub fn set(&self, level: LevelFilter) -> Result<(), Error> {
+        self.handle.reload(level)
+            .map_err(|e| anyhow!("Failed to change log level: {}", e))?;
+        info!("Log level set to {}", level);
+        Ok(())
+    }
+
+    pub fn set_from_str(&self, level: &str) -> Result<LevelFilter, Error> {
+        let level = LevelFilter::from_str(level)
+            .map_err(|_| anyhow!("Invalid log level '{}', expected one of: off, error, warn, info, debug, trace", level))?;
+        self.set(level)?;
+        Ok(level)
+    }
+
+    /// Toggles between the startup level (INFO if started at TRACE) and TRACE every time the process receives SIGHUP.
+    #[cfg(unix)]
+    pub fn watch_sighup(&self) -> Result<(), Error> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = signal(SignalKind::hangup())?;
+        let handle = self.clone();
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                let level = match (handle.current(), handle.initial) {
+                    (LevelFilter::TRACE, LevelFilter::TRACE) => LevelFilter::INFO,
+                    (LevelFilter::TRACE, initial) => initial,
+                    _ => LevelFilter::TRACE,
+                };
+                if let Err(e) = handle.set(level) {
+                    tracing::error!("{}", e);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn watch_sighup(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Initializes tracing with a log level that can be changed at runtime, exporting spans if telemetry is configured.
+pub fn init_reloadable_tracing(level: LevelFilter, telemetry: &Telemetry) -> LogLevelHandle {
+    let (filter, handle) = reload::Layer::new(level);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .with(telemetry.layer())
+        .init();
+    LogLevelHandle { handle, initial: level }
+}
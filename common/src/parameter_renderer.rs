@@ -1,7 +1,18 @@
 use anyhow::{Result, anyhow};
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::{DateTime, SecondsFormat, Utc};
+use regex::Regex;
 use serde_json::{Map, Value};
-use std::process::Command;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write as _;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, RwLock};
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, warn};
 use upon::Engine;
+use crate::condition::{self, Condition};
+use crate::secrets::Redactor;
 
 pub struct ParameterRenderer {
     context: Value,
@@ -24,13 +35,31 @@ fn merge(a: &mut Value, b: &Value) {
 impl ParameterRenderer {
     /// Creates a new ParameterRenderer with an empty context.
     pub fn new() -> Self {
+        Self::build(None, ValsCache::default())
+    }
+
+    /// Creates a renderer that registers every value resolved through `vals` with the redactor.
+    /// References are looked up in `vals` first, see [`ValsCache::prefetch`].
+    pub fn with_redactor(redactor: Redactor, vals: ValsCache) -> Self {
+        Self::build(Some(redactor), vals)
+    }
+
+    fn build(redactor: Option<Redactor>, vals: ValsCache) -> Self {
+        // Only renderers of the runner have a redactor; the server's own environment must not leak into
+        // templates it renders, like webhook mappings
+        let with_env = redactor.is_some();
         let mut engine = Engine::new();
-        engine.add_function("vals", |vals_ref: &str| {
-            run_vals(vals_ref).unwrap_or_else(|e| {
+        engine.add_function("vals", move |vals_ref: &str| {
+            let value = vals.get_or_resolve(vals_ref).unwrap_or_else(|e| {
                 eprintln!("vals filter error: {}", e);
                 "".to_string() // Return empty string on error, consistent with upon's default
-            })
+            });
+            if let Some(redactor) = &redactor {
+                redactor.add(&value);
+            }
+            value
         });
+        add_standard_functions(&mut engine, with_env);
         // No need to configure strict mode; upon defaults to "" for missing values
         ParameterRenderer {
             context: Value::Object(Map::new()),
@@ -90,6 +119,168 @@ impl ParameterRenderer {
             v => Ok(v),
         }
     }
+
+    /// Evaluates a step `when` condition against the context.
+    /// A single `{{ ... }}` expression is evaluated as a condition, anything else is rendered and checked for truthiness.
+    pub fn evaluate_condition(&self, when: &str) -> Result<bool> {
+        match condition::as_expression(when) {
+            Some(expr) => Ok(Condition::parse(expr)?.evaluate(&self.context)),
+            None => Ok(condition::is_truthy(&self.render(Value::String(when.to_string()))?)),
+        }
+    }
+}
+
+/// Variables of Stroem itself, kept from `env`
+pub(crate) const RESERVED_ENV_PREFIX: &str = "STROEM_";
+
+/// Registers the filters and functions available to every template:
+/// - `default: x`, for missing (`input?.name`), null and empty values
+/// - `to_json`, `from_json`
+/// - `b64encode`, `b64decode`
+/// - `upper`, `lower`
+/// - `now()`, the current UTC time in RFC 3339, and `date: "%Y-%m-%d"` to format such a timestamp
+/// - `env("NAME")`, only when `with_env` is set, and not for `STROEM_` variables, which hold e.g. the worker token
+fn add_standard_functions(engine: &mut Engine<'static>, with_env: bool) {
+    engine.add_function("default", |value: &upon::Value, fallback: upon::Value| match value {
+        upon::Value::None => fallback,
+        upon::Value::String(s) if s.is_empty() => fallback,
+        value => value.clone(),
+    });
+    engine.add_function("to_json", |value: &upon::Value| to_json(value).to_string());
+    engine.add_function("from_json", |json: &str| {
+        serde_json::from_str::<Value>(json)
+            .map_err(|e| format!("invalid JSON: {}", e))
+            .and_then(|value| upon::to_value(value).map_err(|e| e.to_string()))
+    });
+    engine.add_function("b64encode", |s: &str| BASE64.encode(s));
+    engine.add_function("b64decode", |s: &str| {
+        BASE64.decode(s.trim())
+            .map_err(|e| format!("invalid base64: {}", e))
+            .and_then(|bytes| String::from_utf8(bytes).map_err(|_| "decoded base64 is not UTF-8".to_string()))
+    });
+    engine.add_function("upper", str::to_uppercase);
+    engine.add_function("lower", str::to_lowercase);
+    engine.add_function("now", || Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true));
+    engine.add_function("date", |timestamp: &str, format: &str| {
+        let dt = DateTime::parse_from_rfc3339(timestamp)
+            .map_err(|e| format!("invalid timestamp '{}': {}", timestamp, e))?;
+        // `to_string` panics on an invalid format, writing reports it
+        let mut formatted = String::new();
+        write!(formatted, "{}", dt.format(format)).map_err(|_| format!("invalid date format '{}'", format))?;
+        Ok::<_, String>(formatted)
+    });
+    if with_env {
+        engine.add_function("env", |name: &str| {
+            if name.starts_with(RESERVED_ENV_PREFIX) {
+                return Err(format!("environment variable '{}' is reserved", name));
+            }
+            Ok(std::env::var(name).unwrap_or_default())
+        });
+    }
+}
+
+fn to_json(value: &upon::Value) -> Value {
+    match value {
+        upon::Value::None => Value::Null,
+        upon::Value::Bool(b) => Value::Bool(*b),
+        upon::Value::Integer(n) => Value::from(*n),
+        upon::Value::Float(n) => Value::from(*n),
+        upon::Value::String(s) => Value::String(s.clone()),
+        upon::Value::List(list) => Value::Array(list.iter().map(to_json).collect()),
+        upon::Value::Map(map) => Value::Object(map.iter().map(|(k, v)| (k.clone(), to_json(v))).collect()),
+    }
+}
+
+lazy_static::lazy_static! {
+    /// Literal references passed to `vals`, as `vals("ref+...")` or `"ref+..." | vals`
+    static ref VALS_REFERENCE_REGEX: Regex = Regex::new(r#"vals\(\s*"(ref\+[^"]+)"\s*\)|"(ref\+[^"]+)"\s*\|\s*vals\b"#).unwrap();
+}
+
+/// Values of `vals` references, resolved once per run.
+#[derive(Clone, Default)]
+pub struct ValsCache {
+    values: Arc<RwLock<HashMap<String, String>>>,
+}
+
+impl ValsCache {
+    /// Resolves the literal `vals` references in the strings of `template` that aren't cached yet,
+    /// in a single `vals` call, so rendering doesn't block on them.
+    pub async fn prefetch(&self, template: &Value) -> Result<()> {
+        let references: Vec<String> = {
+            let values = self.values.read().map_err(|_| anyhow!("vals cache poisoned"))?;
+            vals_references(template).into_iter().filter(|r| !values.contains_key(r)).collect()
+        };
+        if references.is_empty() {
+            return Ok(());
+        }
+        debug!("Resolving {} vals reference(s)", references.len());
+        match run_vals_batch(&references).await {
+            Ok(resolved) => self.values.write().map_err(|_| anyhow!("vals cache poisoned"))?.extend(resolved),
+            // One bad reference fails the batch; rendering resolves them one by one and reports the failing one
+            Err(e) => warn!("Failed to prefetch vals references: {}", e),
+        }
+        Ok(())
+    }
+
+    /// References built at render time can't be prefetched; they are resolved synchronously.
+    fn get_or_resolve(&self, vals_ref: &str) -> Result<String> {
+        if let Some(value) = self.values.read().map_err(|_| anyhow!("vals cache poisoned"))?.get(vals_ref) {
+            return Ok(value.clone());
+        }
+        warn!("Resolving vals reference synchronously, it wasn't prefetched");
+        let value = run_vals(vals_ref)?;
+        self.values.write().map_err(|_| anyhow!("vals cache poisoned"))?.insert(vals_ref.to_string(), value.clone());
+        Ok(value)
+    }
+}
+
+fn vals_references(value: &Value) -> BTreeSet<String> {
+    fn collect(value: &Value, references: &mut BTreeSet<String>) {
+        match value {
+            Value::String(s) => references.extend(VALS_REFERENCE_REGEX.captures_iter(s)
+                .filter_map(|c| c.get(1).or(c.get(2)))
+                .map(|m| m.as_str().to_string())),
+            Value::Array(values) => values.iter().for_each(|v| collect(v, references)),
+            Value::Object(map) => map.values().for_each(|v| collect(v, references)),
+            _ => {}
+        }
+    }
+    let mut references = BTreeSet::new();
+    collect(value, &mut references);
+    references
+}
+
+/// Evaluates several references with one `vals eval`, passing them as a JSON document on stdin.
+async fn run_vals_batch(references: &[String]) -> Result<HashMap<String, String>> {
+    let document: Map<String, Value> = references.iter().enumerate()
+        .map(|(i, reference)| (format!("r{}", i), Value::String(reference.clone())))
+        .collect();
+    let mut child = tokio::process::Command::new("vals")
+        .args(["eval", "-f", "-", "-o", "json"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow!("Failed to execute vals: {}", e))?;
+    let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("Failed to open vals stdin"))?;
+    stdin.write_all(Value::Object(document).to_string().as_bytes()).await?;
+    drop(stdin);
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(anyhow!("vals eval failed: {}", String::from_utf8_lossy(&output.stderr)));
+    }
+
+    let resolved: Map<String, Value> = serde_json::from_slice(&output.stdout)
+        .map_err(|e| anyhow!("Failed to parse vals output: {}", e))?;
+    Ok(references.iter().enumerate()
+        .filter_map(|(i, reference)| {
+            let value = match resolved.get(&format!("r{}", i))? {
+                Value::String(s) => s.trim().to_string(),
+                value => value.to_string(),
+            };
+            Some((reference.clone(), value))
+        })
+        .collect())
 }
 
 /// Synchronously run the `vals eval` command to resolve a reference.
@@ -177,4 +368,59 @@ mod tests {
         let rendered = renderer.render(input).unwrap();
         assert_eq!(rendered, json!(42));
     }
+
+    #[test]
+    fn test_evaluate_condition() {
+        let mut renderer = ParameterRenderer::new();
+        renderer
+            .add_to_context(json!({"input": {"env": "prod", "count": "3"}, "steps": {"check": {"output": {"skip": false}}}}))
+            .unwrap();
+
+        assert!(renderer.evaluate_condition("{{ input.env == 'prod' }}").unwrap());
+        assert!(!renderer.evaluate_condition("{{ input.env != 'prod' }}").unwrap());
+        assert!(renderer.evaluate_condition("{{ input.count == 3 && !steps.check.output.skip }}").unwrap());
+        assert!(!renderer.evaluate_condition("{{ input.missing || steps.check.output.skip }}").unwrap());
+        assert!(renderer.evaluate_condition("{{ input.env }}").unwrap());
+        assert!(!renderer.evaluate_condition("false").unwrap());
+        assert!(Condition::parse("input.env == ").is_err());
+    }
+
+    #[test]
+    fn test_standard_functions() {
+        let mut renderer = ParameterRenderer::new();
+        renderer
+            .add_to_context(json!({"input": {"name": "Alice", "empty": "", "config": "{\"replicas\": 3}"}}))
+            .unwrap();
+
+        let render = |template: &str| renderer.render(json!(template)).unwrap();
+        assert_eq!(render("{{ input?.missing | default: \"none\" }}"), json!("none"));
+        assert_eq!(render("{{ input.empty | default: \"none\" }}"), json!("none"));
+        assert_eq!(render("{{ input.name | default: \"none\" }}"), json!("Alice"));
+        assert_eq!(render("{{ input | to_json }}"), json!(r#"{"config":"{\"replicas\": 3}","empty":"","name":"Alice"}"#));
+        assert_eq!(render("{% with input.config | from_json as config %}{{ config.replicas }}{% endwith %}"), json!("3"));
+        assert_eq!(render("{{ input.name | b64encode }}"), json!("QWxpY2U="));
+        assert_eq!(render("{{ \"QWxpY2U=\" | b64decode }}"), json!("Alice"));
+        assert_eq!(render("{{ input.name | upper }} {{ input.name | lower }}"), json!("ALICE alice"));
+        assert_eq!(render("{{ \"2024-05-01T10:00:00Z\" | date: \"%Y-%m-%d\" }}"), json!("2024-05-01"));
+        assert_eq!(render("{{ now() | date: \"%Y\" }}"), json!(Utc::now().format("%Y").to_string()));
+        assert!(renderer.render(json!("{{ \"2024-05-01T10:00:00Z\" | date: \"%Q\" }}")).is_err());
+        assert!(renderer.render(json!("{{ env(\"HOME\") }}")).is_err());
+
+        let renderer = ParameterRenderer::with_redactor(Redactor::default(), ValsCache::default());
+        assert!(renderer.render(json!("{{ env(\"HOME\") }}")).is_ok());
+        assert!(renderer.render(json!("{{ env(\"STROEM_WORKER_TOKEN\") }}")).is_err());
+    }
+
+    #[test]
+    fn test_vals_references() {
+        let template = json!({
+            "a": "{{ vals(\"ref+vault://app#/password\") }}",
+            "b": ["{{ \"ref+awsssm://db/user\" | vals }}", "{{ vals(\"ref+vault://app#/password\") }}"],
+            "c": "ref+file://not/in/a/template",
+        });
+        assert_eq!(
+            vals_references(&template).into_iter().collect::<Vec<_>>(),
+            vec!["ref+awsssm://db/user".to_string(), "ref+vault://app#/password".to_string()]
+        );
+    }
 }
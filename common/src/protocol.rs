@@ -0,0 +1,72 @@
+//! Versioning of the payloads exchanged between the server, workers and runners, so they can be
+//! upgraded one at a time. The worker announces its version when polling, the server answers with
+//! the version it serves the jobs in and shapes them for it.
+//!
+//! Versions:
+//! 1. Unversioned payloads, from before versioning was introduced
+//! 2. `protocol_version` carried by jobs and results
+//! 3. `resume`, `traceparent` and `project` carried by jobs
+use serde_json::Value;
+use crate::JobRequest;
+
+pub const PROTOCOL_VERSION: u32 = 3;
+/// Oldest version this build still serves and accepts
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+/// Version assumed for payloads and workers that don't carry one
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+/// Query parameter the worker announces its version with
+pub const PROTOCOL_VERSION_PARAM: &str = "protocol_version";
+/// Response header with the version the server picked
+pub const PROTOCOL_VERSION_HEADER: &str = "Stroem-Protocol-Version";
+/// Header with the current workspace revision, on the workspace tarball and on job polls
+pub const REVISION_HEADER: &str = "X-Revision";
+/// Query parameter asking the server to hold a job poll up to this many seconds until a job is queued
+pub const WAIT_PARAM: &str = "wait";
+/// Query parameter of the workspace routes selecting the project, the default project without it
+pub const PROJECT_PARAM: &str = "project";
+/// Query parameter limiting a job poll to the jobs of these projects, comma separated. Polls without it, or from
+/// workers before version 3, only get jobs of the default project.
+pub const PROJECTS_PARAM: &str = "projects";
+/// Project a worker polls with to take jobs of every project
+pub const ALL_PROJECTS: &str = "*";
+/// Response header with the seconds the server was willing to hold the poll, absent on servers that don't
+pub const WAIT_HEADER: &str = "Stroem-Wait";
+
+pub(crate) fn legacy_protocol_version() -> u32 { LEGACY_PROTOCOL_VERSION }
+
+/// Picks the version to talk to a peer announcing `requested`, `None` meaning a peer from before versioning.
+pub fn negotiate(requested: Option<u32>) -> Result<u32, String> {
+    let requested = requested.unwrap_or(LEGACY_PROTOCOL_VERSION);
+    if requested < MIN_PROTOCOL_VERSION {
+        return Err(format!("Protocol version {} is no longer supported, the oldest supported version is {}", requested, MIN_PROTOCOL_VERSION));
+    }
+    Ok(requested.min(PROTOCOL_VERSION))
+}
+
+/// Oldest version a peer must speak to run `job` as intended: older peers would run every step of a resumed job
+/// again, or run the job of another project against the default workspace.
+pub fn required_version(job: &JobRequest) -> u32 {
+    if job.resume.is_some() || job.project.is_some() {
+        3
+    } else {
+        MIN_PROTOCOL_VERSION
+    }
+}
+
+/// Serializes `job` in the shape a peer speaking `version` expects.
+pub fn encode_job(job: &JobRequest, version: u32) -> Result<Value, serde_json::Error> {
+    let mut value = serde_json::to_value(job)?;
+    if let Some(fields) = value.as_object_mut() {
+        if version < 3 {
+            for field in ["resume", "traceparent", "project"] {
+                fields.remove(field);
+            }
+        }
+        if version < 2 {
+            fields.remove("protocol_version");
+        } else {
+            fields.insert("protocol_version".to_string(), Value::from(version));
+        }
+    }
+    Ok(value)
+}
@@ -0,0 +1,41 @@
+//! Serde helpers that write timestamps as RFC 3339 in UTC with millisecond precision
+//! (`2024-05-01T12:00:00.000Z`), so every API response uses the same format.
+//! Deserializing accepts any RFC 3339 timestamp and converts it to UTC.
+//!
+//! Use with `#[serde(with = "stroem_common::rfc3339")]`, or `rfc3339::option` for `Option<DateTime<Utc>>`.
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Deserializer, Serializer};
+
+pub fn format(datetime: &DateTime<Utc>) -> String {
+    datetime.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+pub fn serialize<S: Serializer>(datetime: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format(datetime))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    DateTime::parse_from_rfc3339(&value)
+        .map(|datetime| datetime.with_timezone(&Utc))
+        .map_err(serde::de::Error::custom)
+}
+
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(datetime: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match datetime {
+            Some(datetime) => super::serialize(datetime, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|value| DateTime::parse_from_rfc3339(&value)
+                .map(|datetime| datetime.with_timezone(&Utc))
+                .map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
@@ -1,61 +1,162 @@
 use crate::LogCollector;
+use crate::log_collector::LogEntry;
 use tracing::{info, error, debug};
-use crate::workflows_configuration::{WorkflowsConfiguration, Action, FlowStep};
+use crate::workflows_configuration::{secret_fields, WorkflowsConfiguration, Action, ArtifactDependency, FlowStep, RetryOn};
 use reqwest::Client;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use serde_json::{json, Value};
 use std::collections::HashMap;
-use crate::JobResult;
+use crate::{JobResult, JobSpec, ResumeState};
+use crate::protocol::PROTOCOL_VERSION;
 use anyhow::anyhow;
-use crate::parameter_renderer::ParameterRenderer;
+use crate::parameter_renderer::{ParameterRenderer, ValsCache};
 use crate::dag_walker::DagWalker;
 use std::sync::Arc;
 use crate::action::ActionExecutor;
+use crate::artifacts::ArtifactClient;
+use crate::step_cache::{self, CachedOutput, StepCacheClient};
+use crate::action::approval::ApprovalAction;
+use crate::approval::ApprovalClient;
+use crate::action::sensor::SensorAction;
 use crate::action::shell::ShellAction;
+use crate::action::wait::{WaitAction, WaitForEventAction};
+use crate::wait::{Suspended, WaitClient};
 use crate::workspace_client::WorkspaceClient;
+use crate::step_hook::{StepEvent, StepHook, StepOutcome};
+use crate::secrets::{RedactingLogCollector, Redactor, SecretsResolver};
+use crate::environment;
+use tokio::time::sleep;
+use std::time::Duration;
 
 
 pub struct Runner {
     _server: Option<String>,
     job_id: Option<String>,
     worker_id: Option<String>,
-    task: Option<String>,
-    action: Option<String>,
+    spec: JobSpec,
     input: Option<Value>,
     workspace: WorkspaceClient,
-    _workspace_revision: Option<String>,
+    workspace_revision: Option<String>,
     _client: Client,
     log_collector: Arc<dyn LogCollector + Send + Sync>,
     action_executors: HashMap<String, Box<dyn ActionExecutor>>,
+    hooks: Vec<Arc<dyn StepHook>>,
+    redactor: Redactor,
+    secrets: SecretsResolver,
+    vals: ValsCache,
+    resume: Option<ResumeState>,
+    artifacts: Option<ArtifactClient>,
+    cache: Option<StepCacheClient>,
+    waits: Option<WaitClient>,
+    only_step: Option<String>,
+    dry_run: bool,
 }
 
 impl Runner {
-    pub fn new(server: Option<String>, job_id: Option<String>, worker_id: Option<String>, task: Option<String>, action: Option<String>, input: Option<Value>, workspace: WorkspaceClient, workspace_revision: Option<String>, log_collector: Arc<dyn LogCollector + Send + Sync>) -> Self {
+    pub fn new(server: Option<String>, job_id: Option<String>, worker_id: Option<String>, spec: JobSpec, input: Option<Value>, workspace: WorkspaceClient, log_collector: Arc<dyn LogCollector + Send + Sync>) -> Self {
         let mut action_executors: HashMap<String, Box<dyn ActionExecutor>> = HashMap::new();
         action_executors.insert("shell".to_string(), Box::new(ShellAction));
+        action_executors.insert("sensor".to_string(), Box::new(SensorAction));
+        action_executors.insert("wait".to_string(), Box::new(WaitAction::new(None)));
+        action_executors.insert("wait_for_event".to_string(), Box::new(WaitForEventAction::new(None)));
+        let redactor = Redactor::default();
+        let log_collector = Arc::new(RedactingLogCollector::new(log_collector, redactor.clone()));
         Runner {
             _server: server,
             job_id,
             worker_id,
-            task,
-            action,
+            spec,
             input,
             workspace,
-            _workspace_revision: workspace_revision,
+            workspace_revision: None,
             _client: Client::new(),
             log_collector,
             action_executors,
+            hooks: Vec::new(),
+            secrets: SecretsResolver::new(&[], redactor.clone()).unwrap(),
+            redactor,
+            vals: ValsCache::default(),
+            resume: None,
+            artifacts: None,
+            cache: None,
+            waits: None,
+            only_step: None,
+            dry_run: false,
         }
     }
 
+    /// The workspace revision the job runs at, stored with the step results and part of step cache keys.
+    pub fn with_revision(&mut self, revision: String) {
+        self.workspace_revision = Some(revision);
+    }
+
+    pub fn add_hook(&mut self, hook: Arc<dyn StepHook>) {
+        self.hooks.push(hook);
+    }
+
+    /// Uploads and downloads the artifacts steps declare through `client`; steps declaring any fail without it.
+    pub fn with_artifacts(&mut self, client: ArtifactClient) {
+        self.artifacts = Some(client);
+    }
+
+    /// Looks up and stores the outputs of steps with `cache: true` through `client`; they always run without it.
+    pub fn with_cache(&mut self, client: StepCacheClient) {
+        self.cache = Some(client);
+    }
+
+    /// Runs steps of type `approval` through `client`; they fail as unsupported without it.
+    pub fn with_approvals(&mut self, client: ApprovalClient) {
+        self.action_executors.insert("approval".to_string(), Box::new(ApprovalAction::new(client)));
+    }
+
+    /// Suspends the job on long steps of type `wait` and on `wait_for_event` steps through `client`. Without it,
+    /// `wait` steps sleep and `wait_for_event` steps fail.
+    pub fn with_waits(&mut self, client: WaitClient) {
+        self.action_executors.insert("wait".to_string(), Box::new(WaitAction::new(Some(client.clone()))));
+        self.action_executors.insert("wait_for_event".to_string(), Box::new(WaitForEventAction::new(Some(client.clone()))));
+        self.waits = Some(client);
+    }
+
+    /// Takes over the completed steps of a failed job instead of running them again.
+    pub fn resume_from(&mut self, resume: ResumeState) {
+        self.resume = Some(resume);
+    }
+
+    /// Runs only `step` of the task, regardless of its `when` condition. The other steps don't run, so
+    /// templates of the step referring to their outputs fail to render.
+    pub fn only_step(&mut self, step: String) {
+        self.only_step = Some(step);
+    }
+
+    /// Walks the flow and renders each step, logging the command it would run instead of running it.
+    /// Steps that fail to render are logged and fail the run, without stopping it.
+    pub fn dry_run(&mut self) {
+        self.dry_run = true;
+    }
+
     pub async fn execute(&mut self) -> anyhow::Result<(bool, Option<Value>)> {
         let success;
         let mut output = None;
 
         let workflows = self.workspace.workflows.as_ref().unwrap();
+        if let Some(secrets) = &workflows.secrets {
+            self.redactor.add_all(secrets);
+        }
+        if let Some(globals) = &workflows.globals {
+            self.secrets = SecretsResolver::new(&globals.secrets_providers, self.redactor.clone())?;
+        }
+        let input_fields = match &self.spec {
+            JobSpec::Task { name } => workflows.get_task(name).and_then(|t| t.input.as_ref()),
+            JobSpec::Action { name } => workflows.get_action(name).and_then(|a| a.input.as_ref()),
+        };
+        for name in secret_fields(input_fields) {
+            if let Some(value) = self.input.as_ref().and_then(|input| input.get(name)) {
+                self.redactor.add_all(value);
+            }
+        }
 
-        match (self.task.clone(), self.action.clone()) {
-            (Some(task), None) => {
+        match self.spec.clone() {
+            JobSpec::Task { name: task } => {
                 info!("Running task: {}", task);
                 if let Some(task_def) = workflows.get_task(&task) {
                     (success, output) = self.execute_task(&task_def.flow, workflows).await?;
@@ -64,10 +165,14 @@ impl Runner {
                     success = false;
                 }
             }
-            (None, Some(action_name)) => {
+            JobSpec::Action { name: action_name } => {
                 info!("Running action: {}", action_name);
                 if let Some(action_def) = workflows.get_action(&action_name) {
-                    let (action_success, action_output) = self.execute_action(&action_name, action_def, self.input.clone()).await?;
+                    let result = match self.execute_action(&action_name, action_def, self.input.clone(), 1, &StepOptions::default()).await {
+                        Err(e) if e.is::<Suspended>() => return Err(self.suspend_on(&action_name, e, HashMap::new()).await),
+                        result => result,
+                    };
+                    let (action_success, action_output) = timed_out_as_failure(result)?;
                     success = action_success;
                     output = action_output;
                 } else {
@@ -76,14 +181,9 @@ impl Runner {
                     output = None;
                 }
             }
-            _ => {
-                error!("Must specify either --task or --action");
-                success = false;
-                output = None;
-            }
         }
 
-        if !success {
+        if !success && !self.dry_run {
             self.handle_error(None).await?;
         }
 
@@ -95,21 +195,21 @@ impl Runner {
         let error_input = json!({
                 "job_id": self.job_id,
                 "worker_id": self.worker_id,
-                "task": self.task,
-                "action": self.action,
+                "task": self.spec.task(),
+                "action": self.spec.action(),
                 "step_name": step_name,
             });
 
         let workflows = self.workspace.workflows.as_ref().unwrap();
 
-        if let Some(task) = &self.task {
+        if let Some(task) = self.spec.task() {
             let task = workflows.get_task(task).unwrap();
             let step = task.flow.get(step_name.unwrap()).unwrap();
 
             if let Some(on_error_name) = &step.on_error {
                 if let Some(error_action) = workflows.get_action(on_error_name) {
                     debug!("Running step-specific error handler: {}", on_error_name);
-                    let _ = self.execute_action("step_error_handler", error_action, Some(error_input)).await?;
+                    let _ = self.execute_action("step_error_handler", error_action, Some(error_input), 1, &StepOptions::default()).await?;
                     return Ok(());
                 } else {
                     debug!("Step-specific error handler '{}' not found", on_error_name);
@@ -121,7 +221,7 @@ impl Runner {
         if let Some(error_handler_name) = &workflows.globals.as_ref().unwrap().error_handler {
             debug!("Running global error handler: {}", error_handler_name);
             let action = workflows.get_action(error_handler_name.as_str());
-            let _ = self.execute_action("global_error_handler", action.unwrap(), Some(error_input)).await?;
+            let _ = self.execute_action("global_error_handler", action.unwrap(), Some(error_input), 1, &StepOptions::default()).await?;
         }
         Ok(())
     }
@@ -130,8 +230,10 @@ impl Runner {
         let mut dag = DagWalker::new(flow)?; // Rename from DagExecutor
         let mut success = true;
         let mut last_step_output: Option<Value> = None;
+        // Outputs of the steps done so far, taken over when a suspended job continues
+        let mut finished: HashMap<String, Option<Value>> = HashMap::new();
 
-        let mut renderer = ParameterRenderer::new();
+        let mut renderer = ParameterRenderer::with_redactor(self.redactor.clone(), self.vals.clone());
         renderer.add_to_context(json!({"secrets": config.secrets}))?;
 
         if let Some(input_value) = &self.input {
@@ -142,17 +244,85 @@ impl Runner {
         let mut next_step = dag.get_next_step(None);
         while let Some(step_name) = next_step {
             if let Some(step) = dag.get_step(&step_name) {
-                info!("Executing step: {}", step_name);
+                if let Some(output) = self.resume.as_ref().and_then(|resume| resume.steps.get(&step_name)) {
+                    let resumed_job = self.resume.as_ref().map(|resume| resume.job_id.as_str()).unwrap_or_default();
+                    // A suspended job continuing keeps the results its steps stored before
+                    if self.job_id.as_deref() != Some(resumed_job) {
+                        info!("Taking over step '{}' from job {}", step_name, resumed_job);
+                        self.record_not_run(&step_name, STATUS_RESUMED, output.clone()).await?;
+                    }
+                    finished.insert(step_name.clone(), output.clone());
+                    last_step_output = output.clone();
+                    if let Some(output_value) = output {
+                        renderer.add_to_context(json!({"steps": {step_name.clone(): {"output": output_value.clone()}}}))?;
+                        renderer.add_to_context(json!({step_name.clone(): {"output": output_value}}))?;
+                    }
+                    next_step = dag.get_next_step(Some(step_name));
+                    continue;
+                }
 
-                let step_value = serde_json::to_value(&step.input)?;
-                debug!("Step input before rendering: {}", step_value);
-                let step_input = Some(renderer.render(step_value)?);
-                debug!("Step input after rendering: {:?}", step_input);
+                if self.only_step.as_ref().is_some_and(|only| only != &step_name) {
+                    next_step = dag.get_next_step(Some(step_name));
+                    continue;
+                }
 
-                let (step_success, step_output) = self.execute_action(&step_name, config.get_action(&step.action).unwrap(), step_input).await?;
+                let prepared = match self.prepare_step(step, &mut renderer, config).await {
+                    Err(e) if self.dry_run => {
+                        self.log_render_error(&step_name, e).await?;
+                        success = false;
+                        next_step = dag.get_next_step(Some(step_name));
+                        continue;
+                    }
+                    result => result?,
+                };
+                let Some((step_input, options)) = prepared else {
+                    info!("Skipping step '{}', condition not met: {}", step_name, step.when.as_deref().unwrap_or_default());
+                    self.record_not_run(&step_name, STATUS_SKIPPED, None).await?;
+                    finished.insert(step_name.clone(), None);
+                    next_step = dag.get_next_step(Some(step_name));
+                    continue;
+                };
+
+                let action = config.get_action(&step.action).unwrap();
+                if self.dry_run {
+                    if let Err(e) = self.execute_action(&step_name, action, step_input, 1, &options).await {
+                        self.log_render_error(&step_name, e).await?;
+                        success = false;
+                    }
+                    next_step = dag.get_next_step(Some(step_name));
+                    continue;
+                }
+                let mut attempt = 1;
+                let (step_success, step_output) = loop {
+                    let result = match self.execute_action(&step_name, action, step_input.clone(), attempt, &options).await {
+                        Err(e) if e.is::<Suspended>() => return Err(self.suspend_on(&step_name, e, finished).await),
+                        result => result,
+                    };
+                    let condition = match &result {
+                        Ok((true, _)) => None,
+                        Ok((false, _)) => Some(RetryOn::Failure),
+                        Err(e) if e.is::<StepTimedOut>() => Some(RetryOn::Timeout),
+                        Err(_) => Some(RetryOn::Error),
+                    };
+                    match (&step.retry, condition) {
+                        (Some(retry), Some(condition)) if retry.should_retry(attempt, &condition) => {
+                            let delay = retry.delay_after(attempt);
+                            match &result {
+                                Err(e) => info!("Step '{}' attempt {}/{} errored: {}, retrying in {:?}", step_name, attempt, retry.max_attempts, e, delay),
+                                _ => info!("Step '{}' attempt {}/{} failed, retrying in {:?}", step_name, attempt, retry.max_attempts, delay),
+                            }
+                            sleep(delay).await;
+                            attempt += 1;
+                        }
+                        _ => break timed_out_as_failure(result)?,
+                    }
+                };
                 if step_success {
+                    finished.insert(step_name.clone(), step_output.clone());
                     last_step_output = step_output.clone();
                     if let Some(output_value) = step_output {
+                        renderer.add_to_context(json!({"steps": {step_name.clone(): {"output": output_value.clone()}}}))?;
+                        // Deprecated: top-level `<step>.output`, kept until templates move to `steps.<step>.output`
                         renderer.add_to_context(json!({step_name.clone(): {"output": output_value}}))?;
                     }
                 }
@@ -163,6 +333,7 @@ impl Runner {
                         success = false;
                         break;
                     }
+                    finished.insert(step_name.clone(), None);
                 }
 
                 next_step = dag.get_next_step(Some(step_name));
@@ -176,50 +347,419 @@ impl Runner {
         Ok((success, last_step_output))
     }
 
-    async fn execute_action(&self, step_name: &str, action: &Action, step_input: Option<Value>) -> anyhow::Result<(bool, Option<Value>)> {
+    /// Hands the job over to the server until the wait of `step_name` is over, with the outputs of the `finished`
+    /// steps. Returns the `Suspended` error to exit with, or the error suspending failed with.
+    async fn suspend_on(&self, step_name: &str, suspended: anyhow::Error, finished: HashMap<String, Option<Value>>) -> anyhow::Error {
+        let (Some(waits), Some(job_id)) = (&self.waits, &self.job_id) else {
+            return anyhow!("Step '{}' can't suspend the job without a server", step_name);
+        };
+        if let Err(e) = self.log_collector.flush().await {
+            return e;
+        }
+        let resume = ResumeState { job_id: job_id.clone(), steps: finished };
+        match waits.suspend(step_name, &resume).await {
+            Ok(()) => {
+                info!("Suspended the job on step '{}'", step_name);
+                suspended
+            }
+            Err(e) => anyhow!("Failed to suspend the job on step '{}': {}", step_name, e),
+        }
+    }
+
+    /// Evaluates the `when` condition of the step and renders its input and options, `None` when the
+    /// condition doesn't hold. The condition is left out when only this step runs.
+    async fn prepare_step<'a>(&self, step: &'a FlowStep, renderer: &mut ParameterRenderer, config: &WorkflowsConfiguration) -> anyhow::Result<Option<(Option<Value>, StepOptions<'a>)>> {
+        if let Some(when) = step.when.as_ref().filter(|_| self.only_step.is_none()) {
+            self.vals.prefetch(&Value::String(when.clone())).await?;
+            if !renderer.evaluate_condition(when)? {
+                return Ok(None);
+            }
+        }
+
+        info!("Executing step: {}", step.id);
+
+        let step_value = serde_json::to_value(&step.input)?;
+        debug!("Step input before rendering: {}", step_value);
+        renderer.add_to_context(self.secrets.context_for(&step_value, config.secrets.as_ref()).await?)?;
+        self.vals.prefetch(&step_value).await?;
+        let step_input = Some(renderer.render(step_value)?);
+        debug!("Step input after rendering: {:?}", step_input.as_ref().map(|input| self.redactor.redact_value(input)));
+
+        let env_value = serde_json::to_value(&step.env)?;
+        renderer.add_to_context(self.secrets.context_for(&env_value, config.secrets.as_ref()).await?)?;
+        self.vals.prefetch(&env_value).await?;
+        let options = StepOptions {
+            timeout: step.timeout,
+            assertions: step.assertions.as_deref().unwrap_or_default(),
+            env: serde_json::from_value(renderer.render(env_value)?)?,
+            artifacts_from: serde_json::from_value::<Option<_>>(renderer.render(serde_json::to_value(&step.artifacts_from)?)?)?.unwrap_or_default(),
+            cache: step.cache.unwrap_or(false),
+        };
+        Ok(Some((step_input, options)))
+    }
+
+    /// Stores a result for a step that was not run, because its `when` condition was false or
+    /// because it completed in the job this one resumes.
+    async fn record_not_run(&self, step_name: &str, status: &str, output: Option<Value>) -> anyhow::Result<()> {
+        let now = Utc::now();
+        self.log_collector.set_step_name(Some(step_name.to_string())).await;
+        self.log_collector.set_attempt(None).await;
+        self.log_collector.mark_start(now, &None).await?;
+        self.log_collector.store_results(JobResult {
+            protocol_version: PROTOCOL_VERSION,
+            success: true,
+            start_datetime: now,
+            end_datetime: now,
+            input: None,
+            output,
+            revision: self.workspace_revision.clone(),
+            attempts: Some(0),
+            status: Some(status.to_string()),
+            links: None,
+            environment: None,
+            reason: None,
+        }).await
+    }
+
+    /// Executes a single action, as configured by the step's `options`.
+    /// Returns a `StepTimedOut` error, after storing the result, when the action ran out of time.
+    #[tracing::instrument(name = "step", skip_all, fields(step = step_name, attempt))]
+    async fn execute_action(&self, step_name: &str, action: &Action, step_input: Option<Value>, attempt: u32, options: &StepOptions<'_>) -> anyhow::Result<(bool, Option<Value>)> {
         // Send start with step-specific input
         let start_time = Utc::now();
 
         let log_collector = self.log_collector.clone();
         log_collector.set_step_name(Some(step_name.to_string())).await;
+        log_collector.set_attempt(Some(attempt)).await;
 
         log_collector.mark_start(start_time, &step_input).await?;
 
+        let event = StepEvent {
+            job_id: self.job_id.clone(),
+            task: self.spec.task().map(str::to_string),
+            step_name: step_name.to_string(),
+            action: action.id.clone(),
+            attempt,
+        };
+        for hook in &self.hooks {
+            hook.on_step_start(&event).await;
+        }
+
         // Initialize ParameterRenderer
-        let mut renderer = ParameterRenderer::new();
+        let mut renderer = ParameterRenderer::with_redactor(self.redactor.clone(), self.vals.clone());
         if let Some(input_value) = &step_input {
             // Add step_input to context (assuming it’s an object)
             renderer.add_to_context(json!({"input": input_value}))?;
         }
 
+        let timeout = options.timeout.or(action.timeout);
         let executor = self.action_executors.get(action.action_type.as_ref())
             .ok_or_else(|| anyhow!("Unsupported action type: {}", action.action_type.as_ref()))?;
 
         let action_value = serde_json::to_value(action)?;
         debug!("Action: {:?}", action_value);
-        let action = renderer.render(action_value)?;
+        let workspace_secrets = self.workspace.workflows.as_ref().and_then(|w| w.secrets.as_ref());
+        renderer.add_to_context(json!({"secrets": workspace_secrets}))?;
+        renderer.add_to_context(self.secrets.context_for(&action_value, workspace_secrets).await?)?;
+        self.vals.prefetch(&action_value).await?;
+        let requires = action.requires.as_deref().unwrap_or_default();
+        // A cached output comes without the artifacts of the run, later steps would find none to download
+        let cache = options.cache && action.artifacts.is_none();
+        if options.cache && !cache {
+            info!("Step '{}': uploads artifacts, running it without the cache", step_name);
+        }
+        let mut action = renderer.render(action_value)?;
+        // Built-in variables differ per job, so the key is computed before they're added
+        let cache_key = cache.then(|| step_cache::cache_key(&json!({
+            "action": action,
+            "input": step_input,
+            "env": options.env,
+            "artifacts_from": options.artifacts_from,
+            "revision": self.workspace_revision,
+        })));
+        let cached = match &cache_key {
+            Some(key) => self.cached_output(step_name, key).await,
+            None => None,
+        };
+        action["env"] = json!(self.action_env(step_name, &action["env"], &options.env));
+        action["timeout"] = json!(timeout);
+
+        let capture = self.workspace.workflows.as_ref()
+            .and_then(|w| w.globals.as_ref())
+            .and_then(|g| g.capture_environment.as_ref());
+        let environment = match capture {
+            Some(capture) => {
+                let step_env: HashMap<String, String> = serde_json::from_value(action["env"].clone()).unwrap_or_default();
+                let redactor = self.redactor.clone();
+                let is_secret = move |value: &str| redactor.redact(value) != value;
+                Some(environment::capture(capture, requires, &step_env, &is_secret).await)
+            }
+            None => None,
+        };
 
-        debug!("Step input: {:?}", step_input);
+        debug!("Step input: {:?}", step_input.as_ref().map(|input| self.redactor.redact_value(input)));
 
 
-        let cmd = action["cmd"].as_str().unwrap();
-        debug!("Executing command: {}", cmd);
+        if let Some(cmd) = action["cmd"].as_str() {
+            debug!("Executing command: {}", self.redactor.redact(cmd));
+        }
+
+        if self.dry_run {
+            return self.log_dry_run(&action, start_time, step_input).await;
+        }
 
-        let (exit_success, output) = executor.execute(&action, &step_input, &self.workspace.path, log_collector).await?;
+        if let Some(cached) = &cached {
+            log_collector.log(LogEntry::new(format!("Reusing the cached output of step '{}' of job {}", cached.step_name, cached.job_id), false)).await?;
+        }
+        let execution = async {
+            // Later steps may use the downloaded files as well, they're there for cached steps too
+            self.download_artifacts(&options.artifacts_from).await?;
+            if let Some(cached) = &cached {
+                return Ok((true, cached.output.clone(), Vec::new()));
+            }
+            executor.execute(&action, &step_input, &self.workspace.path, log_collector.clone()).await
+        };
+        let (exit_success, output, links, timed_out) = match timeout {
+            // Dropping the execution future kills the spawned process
+            Some(timeout) => match tokio::time::timeout(timeout, execution).await {
+                Ok(result) => {
+                    let (exit_success, output, links) = result?;
+                    (exit_success, output, links, false)
+                }
+                Err(_) => {
+                    error!("Step '{}' timed out after {:?}", step_name, timeout);
+                    log_collector.log(LogEntry::new(format!("Step timed out after {:?}", timeout), true)).await?;
+                    (false, None, Vec::new(), true)
+                }
+            },
+            None => {
+                let (exit_success, output, links) = execution.await?;
+                (exit_success, output, links, false)
+            }
+        };
+        let mut exit_success = exit_success;
+        let mut assertion_failed = false;
+        if exit_success && !options.assertions.is_empty() {
+            renderer.add_to_context(json!({"output": output}))?;
+            for assertion in options.assertions {
+                let message = match renderer.evaluate_condition(assertion) {
+                    Ok(true) => continue,
+                    Ok(false) => format!("Assertion failed: {}", assertion),
+                    Err(e) => format!("Assertion failed: {}: {}", assertion, e),
+                };
+                error!("Step '{}': {}", step_name, message);
+                log_collector.log(LogEntry::new(message, true)).await?;
+                exit_success = false;
+                assertion_failed = true;
+                break;
+            }
+        }
+        let patterns = action.get("artifacts").filter(|patterns| !patterns.is_null());
+        if let Some(patterns) = patterns {
+            let patterns: Vec<String> = serde_json::from_value(patterns.clone())?;
+            let (is_stderr, message) = match self.upload_artifacts(step_name, &patterns).await {
+                Ok(names) => (false, format!("Uploaded artifacts: {}", names.join(", "))),
+                Err(e) => {
+                    error!("Step '{}': failed to upload artifacts: {}", step_name, e);
+                    exit_success = false;
+                    (true, format!("Failed to upload artifacts: {}", e))
+                }
+            };
+            log_collector.log(LogEntry::new(message, is_stderr)).await?;
+        }
+        if let (true, None, Some(key)) = (exit_success, &cached, &cache_key) {
+            self.save_cached_output(step_name, key, &output).await;
+        }
         let end_time = Utc::now();
 
         self.log_collector.flush().await?;
 
         let result = JobResult {
+            protocol_version: PROTOCOL_VERSION,
             success: exit_success,
             start_datetime: start_time,
             end_datetime: end_time,
             input: step_input.clone(), // Probably not needed, but kept for now
             output: output.clone(),
-            revision: None,
+            revision: self.workspace_revision.clone(),
+            attempts: Some(attempt),
+            status: if timed_out {
+                Some(STATUS_TIMED_OUT.to_string())
+            } else if assertion_failed {
+                Some(STATUS_ASSERTION_FAILED.to_string())
+            } else if cached.is_some() {
+                Some(STATUS_CACHED.to_string())
+            } else {
+                None
+            },
+            links: (!links.is_empty()).then_some(links),
+            environment,
+            reason: None,
         };
 
         self.log_collector.store_results(result).await?;
+
+        let outcome = StepOutcome {
+            start: start_time,
+            end: end_time,
+            success: exit_success,
+            timed_out,
+        };
+        for hook in &self.hooks {
+            hook.on_step_finish(&event, &outcome).await;
+        }
+        if let (true, Some(timeout)) = (timed_out, timeout) {
+            return Err(StepTimedOut(timeout).into());
+        }
         Ok((exit_success, output))
     }
+
+    /// Logs a step of a dry run that failed to render, the walk goes on with the next step.
+    async fn log_render_error(&self, step_name: &str, e: anyhow::Error) -> anyhow::Result<()> {
+        error!("Step '{}' could not be rendered: {}", step_name, e);
+        self.log_collector.set_step_name(Some(step_name.to_string())).await;
+        self.log_collector.log(LogEntry::new(format!("Could not render step '{}': {}", step_name, e), true)).await
+    }
+
+    /// Logs what the rendered `action` would run, and stores a result for the step without running it.
+    async fn log_dry_run(&self, action: &Value, start_time: DateTime<Utc>, step_input: Option<Value>) -> anyhow::Result<(bool, Option<Value>)> {
+        let mut messages = match action["cmd"].as_str() {
+            Some(cmd) => vec![format!("Would run: {}", cmd)],
+            None => vec![format!("Would run {} action: {}", action["type"].as_str().unwrap_or_default(), action)],
+        };
+        // The built-in variables differ per run, only the configured ones are shown
+        let mut env: Vec<(String, String)> = serde_json::from_value::<HashMap<String, String>>(action["env"].clone()).unwrap_or_default()
+            .into_iter()
+            .filter(|(name, _)| ![ENV_WORKSPACE, ENV_JOB_ID, ENV_STEP_NAME].contains(&name.as_str()))
+            .collect();
+        env.sort();
+        messages.extend(env.into_iter().map(|(name, value)| format!("With {}={}", name, value)));
+        for message in messages {
+            self.log_collector.log(LogEntry::new(message, false)).await?;
+        }
+        self.log_collector.store_results(JobResult {
+            protocol_version: PROTOCOL_VERSION,
+            success: true,
+            start_datetime: start_time,
+            end_datetime: Utc::now(),
+            input: step_input,
+            output: None,
+            revision: self.workspace_revision.clone(),
+            attempts: Some(0),
+            status: Some(STATUS_DRY_RUN.to_string()),
+            links: None,
+            environment: None,
+            reason: None,
+        }).await?;
+        Ok((true, None))
+    }
+
+    async fn download_artifacts(&self, dependencies: &[ArtifactDependency]) -> anyhow::Result<()> {
+        if dependencies.is_empty() {
+            return Ok(());
+        }
+        let artifacts = self.artifacts.as_ref().ok_or_else(|| anyhow!("Artifacts can't be downloaded without a server"))?;
+        for dependency in dependencies {
+            let names = artifacts.download(dependency, &self.workspace.path).await?;
+            info!("Downloaded {} artifacts of job {}", names.len(), dependency.job.as_deref().or(self.job_id.as_deref()).unwrap_or_default());
+        }
+        Ok(())
+    }
+
+    /// A failed lookup is logged and the step runs as if nothing was cached.
+    async fn cached_output(&self, step_name: &str, key: &str) -> Option<CachedOutput> {
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => {
+                info!("Step '{}': no server to look up cached outputs, running it", step_name);
+                return None;
+            }
+        };
+        match cache.get(key).await {
+            Ok(cached) => cached,
+            Err(e) => {
+                error!("Step '{}': failed to look up cached output: {}", step_name, e);
+                None
+            }
+        }
+    }
+
+    /// A failure to store the output doesn't fail the step, it's logged instead. Outputs holding a secret aren't
+    /// cached: the server would store the secret in plain text, and a redacted copy is no use to later jobs.
+    async fn save_cached_output(&self, step_name: &str, key: &str, output: &Option<Value>) {
+        if output.as_ref().is_some_and(|output| &self.redactor.redact_value(output) != output) {
+            info!("Step '{}': not caching its output, it contains a secret", step_name);
+            return;
+        }
+        if let Some(cache) = &self.cache {
+            match cache.save(key, step_name, output).await {
+                Ok(()) => debug!("Step '{}': cached output as {}", step_name, key),
+                Err(e) => error!("Step '{}': failed to cache output: {}", step_name, e),
+            }
+        }
+    }
+
+    async fn upload_artifacts(&self, step_name: &str, patterns: &[String]) -> anyhow::Result<Vec<String>> {
+        let artifacts = self.artifacts.as_ref().ok_or_else(|| anyhow!("Artifacts can't be uploaded without a server"))?;
+        artifacts.upload(step_name, &self.workspace.path, patterns).await
+    }
+
+    /// Environment of the action's process: the rendered `env` of the action, overridden by the step's,
+    /// and the built-in `STROEM_*` variables.
+    fn action_env(&self, step_name: &str, action_env: &Value, step_env: &Option<HashMap<String, String>>) -> HashMap<String, String> {
+        let mut env: HashMap<String, String> = serde_json::from_value(action_env.clone()).unwrap_or_default();
+        env.extend(step_env.iter().flatten().map(|(name, value)| (name.clone(), value.clone())));
+        env.insert(ENV_WORKSPACE.to_string(), self.workspace.path.to_string_lossy().to_string());
+        env.insert(ENV_STEP_NAME.to_string(), step_name.to_string());
+        if let Some(job_id) = &self.job_id {
+            env.insert(ENV_JOB_ID.to_string(), job_id.clone());
+        }
+        env
+    }
+}
+
+/// How a step runs its action, on top of the action's own configuration.
+#[derive(Default)]
+struct StepOptions<'a> {
+    /// Overrides the action's timeout
+    timeout: Option<Duration>,
+    /// A successful run fails if one of them doesn't hold for its input and output
+    assertions: &'a [String],
+    /// Rendered `env` of the step
+    env: Option<HashMap<String, String>>,
+    /// Rendered `artifacts_from` of the step
+    artifacts_from: Vec<ArtifactDependency>,
+    /// Reuse a cached output instead of running the action, and cache the output of a successful run
+    cache: bool,
+}
+
+pub const STATUS_TIMED_OUT: &str = "timed_out";
+pub const STATUS_SKIPPED: &str = "skipped";
+pub const STATUS_RESUMED: &str = "resumed";
+pub const STATUS_ASSERTION_FAILED: &str = "assertion_failed";
+pub const STATUS_CACHED: &str = "cached";
+pub const STATUS_DRY_RUN: &str = "dry_run";
+
+/// Built-in environment variables of action processes
+pub const ENV_WORKSPACE: &str = "STROEM_WORKSPACE";
+pub const ENV_JOB_ID: &str = "STROEM_JOB_ID";
+pub const ENV_STEP_NAME: &str = "STROEM_STEP_NAME";
+
+#[derive(Debug)]
+pub struct StepTimedOut(pub Duration);
+
+impl std::fmt::Display for StepTimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Step timed out after {:?}", self.0)
+    }
+}
+
+impl std::error::Error for StepTimedOut {}
+
+/// Turns a timed out execution into a regular failure, so it goes through the error handlers.
+fn timed_out_as_failure(result: anyhow::Result<(bool, Option<Value>)>) -> anyhow::Result<(bool, Option<Value>)> {
+    match result {
+        Err(e) if e.is::<StepTimedOut>() => Ok((false, None)),
+        result => result,
+    }
 }
\ No newline at end of file
@@ -0,0 +1,287 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use anyhow::{anyhow, bail, Error};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use reqwest::Client;
+use serde_json::{json, Map, Value};
+use tokio::sync::{Mutex, OnceCell};
+use tracing::debug;
+use crate::{JobResult, StepLink};
+use crate::log_collector::{LogCollector, LogEntry};
+use crate::parameter_renderer::RESERVED_ENV_PREFIX;
+use crate::workflows_configuration::SecretsProviderConfig;
+
+/// Replacement for secret values in logs, inputs and outputs.
+pub const REDACTED: &str = "***";
+
+/// Secret values shorter than this are not redacted, they would mangle unrelated output.
+const MIN_REDACTED_LENGTH: usize = 4;
+
+lazy_static::lazy_static! {
+    static ref SECRET_REFERENCE_REGEX: Regex = Regex::new(r"secrets\.([A-Za-z0-9_\-]+)").unwrap();
+}
+
+/// A source of secrets looked up by key.
+#[async_trait]
+pub trait SecretsProvider: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<String>, Error>;
+}
+
+pub struct SecretsProviderFactory {}
+impl SecretsProviderFactory {
+    pub fn from_config(config: &SecretsProviderConfig) -> Result<Arc<dyn SecretsProvider>, Error> {
+        match config {
+            SecretsProviderConfig::Env { prefix } if prefix.starts_with(RESERVED_ENV_PREFIX) => {
+                bail!("Secrets provider prefix '{}' would expose {} variables", prefix, RESERVED_ENV_PREFIX)
+            }
+            SecretsProviderConfig::Env { prefix } => Ok(Arc::new(EnvSecretsProvider { prefix: prefix.clone() })),
+            SecretsProviderConfig::File { path } => Ok(Arc::new(FileSecretsProvider { path: PathBuf::from(path) })),
+            SecretsProviderConfig::Vault { address, mount, path, token_env } => {
+                let address = match address {
+                    Some(address) => address.clone(),
+                    None => std::env::var("VAULT_ADDR")
+                        .map_err(|_| anyhow!("Vault secrets provider needs an address or VAULT_ADDR"))?,
+                };
+                let token = std::env::var(token_env)
+                    .map_err(|_| anyhow!("Vault secrets provider needs a token in {}", token_env))?;
+                Ok(Arc::new(VaultSecretsProvider {
+                    client: Client::new(),
+                    url: format!("{}/v1/{}/data/{}", address.trim_end_matches('/'), mount, path.trim_start_matches('/')),
+                    token,
+                    data: OnceCell::new(),
+                }))
+            }
+        }
+    }
+}
+
+pub struct EnvSecretsProvider {
+    prefix: String,
+}
+
+#[async_trait]
+impl SecretsProvider for EnvSecretsProvider {
+    async fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        let name = format!("{}{}", self.prefix, key.to_uppercase().replace('-', "_"));
+        // The worker token and other variables of Stroem itself, as `env()` in templates
+        if name.starts_with(RESERVED_ENV_PREFIX) {
+            bail!("Secret '{}' would read the reserved environment variable {}", key, name);
+        }
+        Ok(std::env::var(name).ok())
+    }
+}
+
+pub struct FileSecretsProvider {
+    path: PathBuf,
+}
+
+#[async_trait]
+impl SecretsProvider for FileSecretsProvider {
+    async fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        if key.contains(['/', '\\']) || key.starts_with('.') {
+            bail!("Invalid secret name '{}'", key);
+        }
+        match tokio::fs::read_to_string(self.path.join(key)).await {
+            Ok(content) => Ok(Some(content.trim_end_matches(['\r', '\n']).to_string())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(anyhow!("Failed to read secret '{}': {}", key, e)),
+        }
+    }
+}
+
+/// Reads keys of a single HashiCorp Vault KV v2 secret, fetched once per run.
+pub struct VaultSecretsProvider {
+    client: Client,
+    url: String,
+    token: String,
+    data: OnceCell<Map<String, Value>>,
+}
+
+#[async_trait]
+impl SecretsProvider for VaultSecretsProvider {
+    async fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        let data = self.data.get_or_try_init(|| async {
+            debug!("Fetching secrets from {}", self.url);
+            let response: Value = self.client.get(&self.url)
+                .header("X-Vault-Token", &self.token)
+                .send()
+                .await?
+                .error_for_status()
+                .map_err(|e| anyhow!("Failed to read Vault secret: {}", e))?
+                .json()
+                .await?;
+            match response.pointer("/data/data") {
+                Some(Value::Object(data)) => Ok::<_, Error>(data.clone()),
+                _ => bail!("Unexpected Vault response from {}", self.url),
+            }
+        }).await?;
+        Ok(data.get(key).map(|value| match value {
+            Value::String(s) => s.clone(),
+            value => value.to_string(),
+        }))
+    }
+}
+
+/// Resolves `secrets.*` references through the configured providers, caching the results for the run.
+pub struct SecretsResolver {
+    providers: Vec<Arc<dyn SecretsProvider>>,
+    cache: Mutex<HashMap<String, Option<String>>>,
+    redactor: Redactor,
+}
+
+impl SecretsResolver {
+    pub fn new(configs: &[SecretsProviderConfig], redactor: Redactor) -> Result<Self, Error> {
+        let providers = configs.iter()
+            .map(SecretsProviderFactory::from_config)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(SecretsResolver { providers, cache: Mutex::new(HashMap::new()), redactor })
+    }
+
+    /// Returns the secret from the first provider that has it.
+    pub async fn resolve(&self, key: &str) -> Result<Option<String>, Error> {
+        let mut cache = self.cache.lock().await;
+        if let Some(value) = cache.get(key) {
+            return Ok(value.clone());
+        }
+        let mut value = None;
+        for provider in &self.providers {
+            if let Some(secret) = provider.get(key).await? {
+                self.redactor.add(&secret);
+                value = Some(secret);
+                break;
+            }
+        }
+        cache.insert(key.to_string(), value.clone());
+        Ok(value)
+    }
+
+    /// Builds the `secrets` context for the references in `template`; keys already in `known` are left alone.
+    pub async fn context_for(&self, template: &Value, known: Option<&Value>) -> Result<Value, Error> {
+        let mut secrets = Map::new();
+        if self.providers.is_empty() {
+            return Ok(json!({"secrets": secrets}));
+        }
+        for key in secret_references(template) {
+            if known.and_then(|known| known.get(&key)).is_some() {
+                continue;
+            }
+            if let Some(value) = self.resolve(&key).await? {
+                secrets.insert(key, Value::String(value));
+            }
+        }
+        Ok(json!({"secrets": secrets}))
+    }
+}
+
+/// Names of the secrets referenced as `secrets.<name>` in the strings of `value`.
+pub fn secret_references(value: &Value) -> BTreeSet<String> {
+    fn collect(value: &Value, keys: &mut BTreeSet<String>) {
+        match value {
+            Value::String(s) => keys.extend(SECRET_REFERENCE_REGEX.captures_iter(s).map(|c| c[1].to_string())),
+            Value::Array(values) => values.iter().for_each(|v| collect(v, keys)),
+            Value::Object(map) => map.values().for_each(|v| collect(v, keys)),
+            _ => {}
+        }
+    }
+    let mut keys = BTreeSet::new();
+    collect(value, &mut keys);
+    keys
+}
+
+/// Shared list of secret values to mask.
+#[derive(Clone, Default)]
+pub struct Redactor {
+    values: Arc<std::sync::RwLock<Vec<String>>>,
+}
+
+impl Redactor {
+    pub fn add(&self, secret: &str) {
+        let mut values = self.values.write().unwrap();
+        // Logs are collected line by line, so multi-line secrets (keys, certificates) are also masked per line
+        let lines = secret.lines().map(str::trim).filter(|line| *line != secret);
+        for value in std::iter::once(secret).chain(lines) {
+            if value.len() >= MIN_REDACTED_LENGTH && !values.iter().any(|v| v == value) {
+                values.push(value.to_string());
+            }
+        }
+        // Longest first, so a secret containing another one is masked as a whole
+        values.sort_by_key(|v| std::cmp::Reverse(v.len()));
+    }
+
+    /// Adds every string in `value`, e.g. the SOPS secrets of the workspace.
+    pub fn add_all(&self, value: &Value) {
+        match value {
+            Value::String(s) => self.add(s),
+            Value::Array(values) => values.iter().for_each(|v| self.add_all(v)),
+            Value::Object(map) => map.values().for_each(|v| self.add_all(v)),
+            _ => {}
+        }
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        let values = self.values.read().unwrap();
+        values.iter().fold(text.to_string(), |text, secret| text.replace(secret.as_str(), REDACTED))
+    }
+
+    pub fn redact_value(&self, value: &Value) -> Value {
+        match value {
+            Value::String(s) => Value::String(self.redact(s)),
+            Value::Array(values) => Value::Array(values.iter().map(|v| self.redact_value(v)).collect()),
+            Value::Object(map) => Value::Object(map.iter().map(|(k, v)| (k.clone(), self.redact_value(v))).collect()),
+            value => value.clone(),
+        }
+    }
+}
+
+/// Masks secret values before passing logs, inputs and outputs on to another collector.
+pub struct RedactingLogCollector {
+    inner: Arc<dyn LogCollector + Send + Sync>,
+    redactor: Redactor,
+}
+
+impl RedactingLogCollector {
+    pub fn new(inner: Arc<dyn LogCollector + Send + Sync>, redactor: Redactor) -> Self {
+        Self { inner, redactor }
+    }
+}
+
+#[async_trait]
+impl LogCollector for RedactingLogCollector {
+    async fn log(&self, mut entry: LogEntry) -> Result<(), Error> {
+        entry.message = self.redactor.redact(&entry.message);
+        entry.fields = entry.fields.map(|fields| fields.into_iter()
+            .map(|(k, v)| (k, self.redactor.redact_value(&v)))
+            .collect());
+        self.inner.log(entry).await
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        self.inner.flush().await
+    }
+
+    async fn set_step_name(&self, step_name: Option<String>) {
+        self.inner.set_step_name(step_name).await
+    }
+
+    async fn set_attempt(&self, attempt: Option<u32>) {
+        self.inner.set_attempt(attempt).await
+    }
+
+    async fn mark_start(&self, start: DateTime<Utc>, input: &Option<Value>) -> Result<(), Error> {
+        let input = input.as_ref().map(|input| self.redactor.redact_value(input));
+        self.inner.mark_start(start, &input).await
+    }
+
+    async fn store_results(&self, mut result: JobResult) -> Result<(), Error> {
+        result.input = result.input.map(|input| self.redactor.redact_value(&input));
+        result.output = result.output.map(|output| self.redactor.redact_value(&output));
+        result.environment = result.environment.map(|environment| self.redactor.redact_value(&environment));
+        result.links = result.links.map(|links| links.into_iter()
+            .map(|link| StepLink { title: self.redactor.redact(&link.title), url: self.redactor.redact(&link.url) })
+            .collect());
+        self.inner.store_results(result).await
+    }
+}
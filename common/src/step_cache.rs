@@ -0,0 +1,75 @@
+// common/src/step_cache.rs
+//! Outputs of steps with `cache: true`. Before such a step runs, the runner hashes its rendered action, input,
+//! `env`, `artifacts_from` and the workspace revision into a key, and reuses the output the server stored under
+//! it instead of running the action. The output of a successful run is stored under its key, unless it holds a
+//! secret. The server keeps the keys of each project apart, and only takes lookups and outputs of jobs running
+//! on the asking worker.
+use anyhow::Error;
+use blake2::{Blake2b512, Digest};
+use reqwest::{header, Client};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use crate::artifacts::check_response;
+use crate::http_retry::send_with_retry;
+
+/// Output stored under a cache key, with the job and step that produced it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedOutput {
+    pub output: Option<Value>,
+    pub job_id: String,
+    pub step_name: String,
+}
+
+/// Hex digest of `value`, the same whatever the order of the keys of its objects.
+pub fn cache_key(value: &Value) -> String {
+    let mut hasher = Blake2b512::new();
+    hasher.update(canonical(value).to_string().as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// `value` with the keys of its objects sorted, as maps keep their insertion order.
+fn canonical(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            Value::Object(keys.into_iter().map(|key| (key.clone(), canonical(&map[key]))).collect::<Map<_, _>>())
+        }
+        Value::Array(values) => Value::Array(values.iter().map(canonical).collect()),
+        value => value.clone(),
+    }
+}
+
+/// Looks up and stores cached step outputs through the worker API of the server.
+#[derive(Clone)]
+pub struct StepCacheClient {
+    client: Client,
+    server: String,
+    job_id: String,
+    worker_id: String,
+    token: String,
+}
+
+impl StepCacheClient {
+    pub fn new(client: Client, server: String, job_id: String, worker_id: String, token: String) -> Self {
+        Self { client, server, job_id, worker_id, token }
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<CachedOutput>, Error> {
+        let response = send_with_retry(self.client.get(format!("{}/cache/{}", self.server, key))
+            .query(&[("worker_id", self.worker_id.as_str()), ("job_id", self.job_id.as_str())])
+            .header(header::AUTHORIZATION, format!("Bearer {}", self.token)))
+            .await?;
+        Ok(check_response(response, "Failed to look up cached output").await?.json().await?)
+    }
+
+    pub async fn save(&self, key: &str, step_name: &str, output: &Option<Value>) -> Result<(), Error> {
+        let response = send_with_retry(self.client.post(format!("{}/cache/{}", self.server, key))
+            .query(&[("worker_id", self.worker_id.as_str())])
+            .header(header::AUTHORIZATION, format!("Bearer {}", self.token))
+            .json(&json!({"job_id": self.job_id, "step_name": step_name, "output": output})))
+            .await?;
+        check_response(response, "Failed to store cached output").await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+use std::sync::Arc;
+use anyhow::Error;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::Client;
+use tokio::net::UdpSocket;
+use tracing::{debug, error};
+
+/// Environment variables used by the worker to hand the metrics settings to the runner.
+pub const STATSD_ENV: &str = "STROEM_STATSD";
+pub const STATSD_PREFIX_ENV: &str = "STROEM_STATSD_PREFIX";
+pub const PUSHGATEWAY_ENV: &str = "STROEM_PUSHGATEWAY";
+
+#[derive(Debug, Clone)]
+pub struct StepEvent {
+    pub job_id: Option<String>,
+    pub task: Option<String>,
+    pub step_name: String,
+    pub action: String,
+    pub attempt: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct StepOutcome {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub success: bool,
+    pub timed_out: bool,
+}
+
+impl StepOutcome {
+    pub fn status(&self) -> &'static str {
+        match (self.success, self.timed_out) {
+            (_, true) => "timed_out",
+            (true, false) => "success",
+            (false, false) => "failure",
+        }
+    }
+
+    pub fn duration_secs(&self) -> f64 {
+        (self.end - self.start).num_milliseconds() as f64 / 1000.0
+    }
+}
+
+/// Called by the runner around every step execution.
+/// Hooks must not fail the step, so errors are expected to be logged and swallowed.
+#[async_trait]
+pub trait StepHook: Send + Sync {
+    async fn on_step_start(&self, _step: &StepEvent) {}
+    async fn on_step_finish(&self, step: &StepEvent, outcome: &StepOutcome);
+}
+
+/// Sends step timings and result counters to a StatsD daemon.
+pub struct StatsdHook {
+    socket: UdpSocket,
+    prefix: String,
+}
+
+impl StatsdHook {
+    pub async fn new(address: &str, prefix: &str) -> Result<Self, Error> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket.connect(address).await?;
+        Ok(Self { socket, prefix: prefix.to_string() })
+    }
+}
+
+#[async_trait]
+impl StepHook for StatsdHook {
+    async fn on_step_finish(&self, step: &StepEvent, outcome: &StepOutcome) {
+        let metric = format!("{}.step.{}", self.prefix, sanitize(&step.action));
+        let payload = format!(
+            "{}.duration:{}|ms\n{}.{}:1|c",
+            metric, (outcome.end - outcome.start).num_milliseconds(),
+            metric, outcome.status(),
+        );
+        if let Err(e) = self.socket.send(payload.as_bytes()).await {
+            error!("Failed to send StatsD metrics: {}", e);
+        }
+    }
+}
+
+/// Pushes the last run of every step to a Prometheus Pushgateway, grouped by task and step.
+pub struct PrometheusPushHook {
+    client: Client,
+    url: String,
+}
+
+impl PrometheusPushHook {
+    pub fn new(url: &str) -> Self {
+        Self { client: Client::new(), url: url.trim_end_matches('/').to_string() }
+    }
+}
+
+#[async_trait]
+impl StepHook for PrometheusPushHook {
+    async fn on_step_finish(&self, step: &StepEvent, outcome: &StepOutcome) {
+        let group = step.task.as_deref().unwrap_or(&step.action);
+        let url = format!("{}/metrics/job/stroem/task/{}/step/{}", self.url, sanitize(group), sanitize(&step.step_name));
+        let labels = format!("action=\"{}\",status=\"{}\"", step.action, outcome.status());
+        let body = format!(
+            "# TYPE stroem_step_duration_seconds gauge\nstroem_step_duration_seconds{{{labels}}} {}\n\
+             # TYPE stroem_step_attempts gauge\nstroem_step_attempts{{{labels}}} {}\n\
+             # TYPE stroem_step_success gauge\nstroem_step_success{{{labels}}} {}\n\
+             # TYPE stroem_step_last_run_timestamp_seconds gauge\nstroem_step_last_run_timestamp_seconds{{{labels}}} {}\n",
+            outcome.duration_secs(), step.attempt, outcome.success as u8, outcome.end.timestamp(),
+        );
+        match self.client.post(&url).body(body).send().await {
+            Ok(resp) if !resp.status().is_success() => error!("Pushgateway rejected metrics: {}", resp.status()),
+            Ok(_) => debug!("Pushed metrics for step '{}'", step.step_name),
+            Err(e) => error!("Failed to push metrics: {}", e),
+        }
+    }
+}
+
+/// Builds the built-in hooks from the optional StatsD address and Pushgateway URL.
+pub async fn metrics_hooks(statsd: Option<&str>, statsd_prefix: &str, pushgateway: Option<&str>) -> Result<Vec<Arc<dyn StepHook>>, Error> {
+    let mut hooks: Vec<Arc<dyn StepHook>> = Vec::new();
+    if let Some(address) = statsd {
+        hooks.push(Arc::new(StatsdHook::new(address, statsd_prefix).await?));
+    }
+    if let Some(url) = pushgateway {
+        hooks.push(Arc::new(PrometheusPushHook::new(url)));
+    }
+    Ok(hooks)
+}
+
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .collect()
+}
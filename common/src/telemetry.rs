@@ -0,0 +1,87 @@
+//! OpenTelemetry trace export over OTLP/HTTP, and W3C trace context propagation between
+//! the server, the worker and the runner, so a job can be followed end-to-end.
+use std::collections::HashMap;
+use anyhow::{anyhow, Error};
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::{TraceContextExt, TracerProvider};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use reqwest::RequestBuilder;
+use tracing::Span;
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Environment variables used by the worker to hand the OTLP endpoint and the job's trace context to the runner.
+pub const OTLP_ENDPOINT_ENV: &str = "STROEM_OTLP_ENDPOINT";
+pub const TRACEPARENT_ENV: &str = "STROEM_TRACEPARENT";
+/// W3C trace context header, also sent by the worker so the server's request spans join the job's trace
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Exports spans when an OTLP endpoint is configured, and does nothing otherwise.
+pub struct Telemetry {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Telemetry {
+    /// `endpoint` is the OTLP/HTTP base URL of the collector, e.g. `http://localhost:4318`.
+    pub fn new(endpoint: Option<&str>, service_name: &'static str) -> Result<Self, Error> {
+        let Some(endpoint) = endpoint else {
+            return Ok(Telemetry { provider: None });
+        };
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+            .build()
+            .map_err(|e| anyhow!("Failed to set up OTLP exporter for {}: {}", endpoint, e))?;
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name(service_name).build())
+            .build();
+        Ok(Telemetry { provider: Some(provider) })
+    }
+
+    pub fn layer<S>(&self) -> Option<OpenTelemetryLayer<S, SdkTracer>>
+    where
+        S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+    {
+        self.provider.as_ref()
+            .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer("stroem")))
+    }
+
+    /// Flushes the spans still buffered, call before exiting.
+    pub fn shutdown(&self) {
+        if let Some(Err(e)) = self.provider.as_ref().map(|provider| provider.shutdown()) {
+            eprintln!("Failed to flush traces: {}", e);
+        }
+    }
+}
+
+/// Trace context of the current span as a `traceparent` value, if spans are exported.
+pub fn current_traceparent() -> Option<String> {
+    let context = Span::current().context();
+    if !context.span().span_context().is_valid() {
+        return None;
+    }
+    let mut carrier = HashMap::new();
+    TraceContextPropagator::new().inject_context(&context, &mut carrier);
+    carrier.remove(TRACEPARENT_HEADER)
+}
+
+/// Adds the `traceparent` header of the current span to the request, if spans are exported.
+pub fn propagate(request: RequestBuilder) -> RequestBuilder {
+    match current_traceparent() {
+        Some(traceparent) => request.header(TRACEPARENT_HEADER, traceparent),
+        None => request,
+    }
+}
+
+/// Makes the span a child of the span the `traceparent` value refers to.
+pub fn set_parent(span: &Span, traceparent: &str) {
+    let carrier = HashMap::from([(TRACEPARENT_HEADER.to_string(), traceparent.to_string())]);
+    let context = TraceContextPropagator::new().extract(&carrier);
+    if context.span().span_context().is_valid() {
+        let _ = span.set_parent(context);
+    }
+}
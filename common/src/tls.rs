@@ -0,0 +1,73 @@
+//! TLS settings for the HTTP clients workers and runners use to talk to the server.
+//! The worker passes them on to its runners through the environment variables below.
+use std::fs;
+use std::path::PathBuf;
+use anyhow::{anyhow, Error};
+use reqwest::{Certificate, Client, Identity};
+use tracing::warn;
+
+/// PEM bundle of CA certificates trusted in addition to the system roots
+pub const CA_CERT_ENV: &str = "STROEM_CA_CERT";
+/// PEM client certificate, may also contain the private key
+pub const CLIENT_CERT_ENV: &str = "STROEM_CLIENT_CERT";
+/// PEM private key of the client certificate
+pub const CLIENT_KEY_ENV: &str = "STROEM_CLIENT_KEY";
+/// Disables certificate verification
+pub const TLS_INSECURE_ENV: &str = "STROEM_TLS_INSECURE";
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    pub ca_cert: Option<PathBuf>,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    /// Accept any server certificate, only meant for labs without a usable CA
+    pub insecure: bool,
+}
+
+impl TlsConfig {
+    pub fn client(&self) -> Result<Client, Error> {
+        let mut builder = Client::builder();
+
+        if let Some(path) = &self.ca_cert {
+            let pem = fs::read(path).map_err(|e| anyhow!("Failed to read CA bundle {}: {}", path.display(), e))?;
+            for cert in Certificate::from_pem_bundle(&pem)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert), key) => {
+                let mut pem = fs::read(cert).map_err(|e| anyhow!("Failed to read client certificate {}: {}", cert.display(), e))?;
+                if let Some(key) = key {
+                    pem.push(b'\n');
+                    pem.extend(fs::read(key).map_err(|e| anyhow!("Failed to read client key {}: {}", key.display(), e))?);
+                }
+                builder = builder.identity(Identity::from_pem(&pem)?);
+            }
+            (None, Some(_)) => return Err(anyhow!("A client key requires a client certificate")),
+            (None, None) => {}
+        }
+
+        if self.insecure {
+            warn!("TLS certificate verification is disabled, connections to the server can be intercepted");
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+
+        Ok(builder.build()?)
+    }
+
+    /// Environment variables that carry these settings to a runner.
+    pub fn envs(&self) -> Vec<(String, String)> {
+        let mut envs = vec![];
+        let paths = [(CA_CERT_ENV, &self.ca_cert), (CLIENT_CERT_ENV, &self.client_cert), (CLIENT_KEY_ENV, &self.client_key)];
+        for (name, path) in paths {
+            if let Some(path) = path {
+                envs.push((name.to_string(), path.to_string_lossy().to_string()));
+            }
+        }
+        if self.insecure {
+            envs.push((TLS_INSECURE_ENV.to_string(), "true".to_string()));
+        }
+        envs
+    }
+}
@@ -0,0 +1,93 @@
+// common/src/wait.rs
+//! Steps of type `wait` and `wait_for_event` that suspend the job. The runner registers what the step waits for
+//! with the server, hands over the outputs of the steps done so far and exits, freeing the worker. Once the wait
+//! is over, the server queues the job again; its runner takes over the finished steps and runs the wait step
+//! again, which now gets the outcome from the server.
+use std::fmt;
+use std::time::Duration;
+use anyhow::{anyhow, Error};
+use chrono::{DateTime, Utc};
+use reqwest::{header, Client, Url};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use crate::artifacts::check_response;
+use crate::http_retry::send_with_retry;
+use crate::{rfc3339, ResumeState};
+
+/// Exit code of a runner that suspended its job, the worker doesn't report a result for it.
+pub const EXIT_SUSPENDED: i32 = 75;
+
+/// Status of the result a worker sends for a suspended job, which the server doesn't store.
+pub const STATUS_SUSPENDED: &str = "suspended";
+
+/// Returned by a wait step whose wait isn't over yet, the runner suspends the job on it.
+#[derive(Debug)]
+pub struct Suspended;
+
+impl fmt::Display for Suspended {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Job suspended")
+    }
+}
+
+impl std::error::Error for Suspended {}
+
+/// A wait as the server has it
+#[derive(Debug, Deserialize)]
+pub struct Wait {
+    /// `waiting`, `elapsed`, `received` or `timed_out`
+    pub status: String,
+    pub payload: Option<Value>,
+    #[serde(with = "rfc3339")]
+    pub created: DateTime<Utc>,
+    #[serde(with = "rfc3339::option")]
+    pub resolved: Option<DateTime<Utc>>,
+}
+
+impl Wait {
+    pub fn waited_secs(&self) -> i64 {
+        self.resolved.map(|resolved| (resolved - self.created).num_seconds()).unwrap_or_default()
+    }
+}
+
+/// Registers the waits of a job and suspends it through the worker API of the server.
+#[derive(Clone)]
+pub struct WaitClient {
+    client: Client,
+    server: String,
+    job_id: String,
+    worker_id: String,
+    token: String,
+}
+
+impl WaitClient {
+    pub fn new(client: Client, server: String, job_id: String, worker_id: String, token: String) -> Self {
+        Self { client, server, job_id, worker_id, token }
+    }
+
+    /// Records what the step waits for, the first time it runs; `timeout` counts from then. Returns the wait as it
+    /// is now, e.g. `received` when the event was posted before.
+    pub async fn register(&self, step_name: &str, event_key: Option<&str>, timeout: Option<Duration>) -> Result<Wait, Error> {
+        let mut url = Url::parse(&format!("{}/jobs/{}/waits", self.server, self.job_id))?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("Invalid server URL: {}", self.server))?
+            .push(step_name);
+        let response = send_with_retry(self.client.post(url)
+            .query(&[("worker_id", self.worker_id.as_str())])
+            .header(header::AUTHORIZATION, format!("Bearer {}", self.token))
+            .json(&json!({"event_key": event_key, "timeout_secs": timeout.map(|timeout| timeout.as_secs_f64())})))
+            .await?;
+        Ok(check_response(response, "Failed to register wait").await?.json().await?)
+    }
+
+    /// Hands the job back to the server until the wait of `step_name` is over, with the outputs of the finished steps.
+    pub async fn suspend(&self, step_name: &str, steps: &ResumeState) -> Result<(), Error> {
+        let response = send_with_retry(self.client.post(format!("{}/jobs/{}/suspend", self.server, self.job_id))
+            .query(&[("worker_id", self.worker_id.as_str())])
+            .header(header::AUTHORIZATION, format!("Bearer {}", self.token))
+            .json(&json!({"step_name": step_name, "resume": steps})))
+            .await?;
+        check_response(response, "Failed to suspend job").await?;
+        Ok(())
+    }
+}
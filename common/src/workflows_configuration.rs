@@ -1,22 +1,75 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::path::PathBuf;
-use anyhow::{anyhow, bail, Error};
+use anyhow::{bail, Error};
 use config::Config;
 use globwalker::GlobWalkerBuilder;
+use schemars::{json_schema, JsonSchema, Schema, SchemaGenerator};
+use schemars::generate::SchemaSettings;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use tracing::{debug, error};
+use serde_json::{Map, Value};
+use tracing::{debug, error, warn};
 use std::process::Command;
 use strum::{AsRefStr};
+use std::time::Duration;
+use chrono::NaiveDate;
+use duration_str::{deserialize_duration, deserialize_option_duration};
+use crate::artifacts::Artifact;
+use crate::condition::{self, Condition};
+use crate::dag_walker;
+use crate::parameter_renderer::{ParameterRenderer, RESERVED_ENV_PREFIX};
+use crate::secrets::secret_references;
 
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct Globals {
     pub base_path: Option<String>,
     pub error_handler: Option<String>,
+    /// Providers consulted, in order, for `secrets.*` references not found in the SOPS secrets
+    #[serde(default)]
+    pub secrets_providers: Vec<SecretsProviderConfig>,
+    /// Records the environment and tool versions of every step, to compare runs across workers
+    pub capture_environment: Option<EnvironmentCapture>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default)]
+pub struct EnvironmentCapture {
+    /// Environment variables to record; a trailing `*` matches a prefix, e.g. `PYTHON*`
+    #[serde(default)]
+    pub env: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, AsRefStr)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum SecretsProviderConfig {
+    /// `secrets.db_password` is read from `<prefix>DB_PASSWORD`; `STROEM_` variables are never read
+    Env {
+        #[serde(default = "default_secrets_env_prefix")]
+        prefix: String,
+    },
+    /// `secrets.db_password` is read from the file `<path>/db_password`
+    File {
+        path: String,
+    },
+    /// `secrets.db_password` is the `db_password` key of a KV v2 secret
+    Vault {
+        /// Defaults to `VAULT_ADDR`
+        address: Option<String>,
+        #[serde(default = "default_vault_mount")]
+        mount: String,
+        path: String,
+        /// Environment variable holding the Vault token
+        #[serde(default = "default_vault_token_env")]
+        token_env: String,
+    },
+}
+
+fn default_secrets_env_prefix() -> String { "SECRET_".to_string() }
+fn default_vault_mount() -> String { "secret".to_string() }
+fn default_vault_token_env() -> String { "VAULT_TOKEN".to_string() }
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct Action {
     #[serde(skip_deserializing, default = "default_id")]
     pub id: String,
@@ -24,26 +77,148 @@ pub struct Action {
     pub description: Option<String>,
     pub input: Option<HashMap<String, InputField>>,
     pub output: Option<OutputSpec>,
+    pub deprecated: Option<Deprecation>,
+    #[serde(default, deserialize_with = "deserialize_option_duration")]
+    #[schemars(with = "Option<DurationSchema>")]
+    pub timeout: Option<Duration>,
+    /// Binaries the action needs; their `--version` is recorded when environment capture is on
+    pub requires: Option<Vec<String>>,
+    /// Environment variables of the action's process, rendered like `cmd`. Steps can add to and override them.
+    pub env: Option<HashMap<String, String>>,
+    /// Globs of workspace files uploaded as artifacts once the action ran, e.g. `dist/*.tar.gz`
+    pub artifacts: Option<Vec<String>>,
     #[serde(flatten)]
     pub action_type: ActionType,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, AsRefStr)]
+impl Action {
+    /// Checks `input` against the declared input fields, see [`Task::validate_input`].
+    pub fn validate_input(&self, input: &mut Map<String, Value>) -> Result<(), Error> {
+        let Some(fields) = &self.input else { return Ok(()) };
+        let errors = check_input(fields, input);
+        if !errors.is_empty() {
+            bail!("Invalid input for action '{}': {}", self.id, errors.join(", "));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Deprecation {
+    pub message: Option<String>,
+    pub replacement: Option<String>,
+    /// Date after which new runs referencing the action are refused
+    pub sunset_date: Option<NaiveDate>,
+}
+
+impl Deprecation {
+    pub fn is_sunset(&self, today: NaiveDate) -> bool {
+        self.sunset_date.map(|date| today > date).unwrap_or(false)
+    }
+
+    pub fn describe(&self, action_id: &str) -> String {
+        let mut msg = format!("Action '{}' is deprecated", action_id);
+        if let Some(message) = &self.message {
+            msg.push_str(&format!(": {}", message));
+        }
+        if let Some(replacement) = &self.replacement {
+            msg.push_str(&format!(" (use '{}' instead)", replacement));
+        }
+        if let Some(sunset_date) = &self.sunset_date {
+            msg.push_str(&format!(", sunset on {}", sunset_date));
+        }
+        msg
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, AsRefStr)]
 #[strum(serialize_all = "snake_case")]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum ActionType {
     Shell {
         cmd: Option<String>,
+        /// Interpreter of `cmd`, `sh` by default, or `cmd` on Windows workers
+        interpreter: Option<Shell>,
     },
     RemoteShell {}, // TODO
-    Docker {}, // TODO
+    Docker {
+        /// Pre-pulled by workers in warm standby
+        image: Option<String>,
+    }, // TODO
     Pod {}, // TODO
     Python {
         script: Option<String>,
     }, // TODO
+    /// Polls `condition` until it holds, so dependent steps wait for it. Fails after the action or step `timeout`, a
+    /// day without one.
+    Sensor {
+        condition: SensorCondition,
+        /// Time between polls, defaults to 30s
+        #[serde(default, deserialize_with = "deserialize_option_duration")]
+        #[schemars(with = "Option<DurationSchema>")]
+        interval: Option<Duration>,
+    },
+    /// Waits until someone approves the step through the API, and fails if it's rejected or nobody decides within
+    /// the action or step `timeout`, a week without one. The job is suspended meanwhile instead of holding the worker.
+    Approval {
+        /// User emails or `role:<role>` that may decide, anyone who may run the task when unset
+        approvers: Option<Vec<String>>,
+        /// Shown to the approvers
+        message: Option<String>,
+    },
+    /// Waits for `duration`. Longer waits suspend the job on the server instead of holding the worker.
+    Wait {
+        #[serde(deserialize_with = "deserialize_duration")]
+        #[schemars(with = "DurationSchema")]
+        duration: Duration,
+    },
+    /// Suspends the job until an event is posted to `/api/events/{key}`, its body becomes the output of the step.
+    /// Fails once the action or step `timeout` passes without one, waits for good without it.
+    #[serde(rename = "wait_for_event")]
+    WaitForEvent {
+        key: String,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Default, AsRefStr)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    #[cfg_attr(not(windows), default)]
+    Sh,
+    Bash,
+    #[cfg_attr(windows, default)]
+    Cmd,
+    /// Windows PowerShell
+    Powershell,
+    /// PowerShell 7 and later
+    Pwsh,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum SensorCondition {
+    /// A GET on `url` returns `status`
+    Http {
+        url: String,
+        #[serde(default = "default_sensor_status")]
+        status: u16,
+    },
+    /// `path` exists, relative to the workspace
+    File { path: String },
+    /// `query` returns at least `min_rows` rows, run with `psql` against the PostgreSQL `connection` URL
+    Sql {
+        connection: String,
+        query: String,
+        #[serde(default = "default_sensor_min_rows")]
+        min_rows: usize,
+    },
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+fn default_sensor_status() -> u16 { 200 }
+fn default_sensor_min_rows() -> usize { 1 }
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct InputField {
     #[serde(skip_deserializing, default = "default_id")]
     pub id: String,
@@ -55,7 +230,7 @@ pub struct InputField {
     pub field_type: InputFieldType,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, AsRefStr)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, AsRefStr)]
 #[strum(serialize_all = "lowercase")]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum InputFieldType {
@@ -64,21 +239,131 @@ pub enum InputFieldType {
     },
     Int {
         default: Option<i32>,
+    },
+    Float {
+        default: Option<f64>,
+    },
+    Boolean {
+        default: Option<bool>,
+    },
+    /// One of `values`
+    Enum {
+        values: Vec<String>,
+        default: Option<String>,
+    },
+    /// A string that is masked in the UI and redacted from logs
+    Secret {},
+    /// A multi-line string
+    Text {
+        default: Option<String>,
+    },
+}
+
+impl InputFieldType {
+    /// The default value of the field, if it has one.
+    pub fn default_value(&self) -> Option<Value> {
+        match self {
+            InputFieldType::String { default } | InputFieldType::Text { default } | InputFieldType::Enum { default, .. } => default.clone().map(Value::String),
+            InputFieldType::Int { default } => default.map(Value::from),
+            InputFieldType::Float { default } => default.and_then(serde_json::Number::from_f64).map(Value::Number),
+            InputFieldType::Boolean { default } => default.map(Value::Bool),
+            InputFieldType::Secret {} => None,
+        }
+    }
+
+    /// Checks a set value against the type, returning the converted value if it needs converting.
+    /// Form and template values arrive as strings, so strings are parsed for the non-string types.
+    pub fn check(&self, name: &str, value: &Value) -> Result<Option<Value>, String> {
+        match self {
+            InputFieldType::String { .. } | InputFieldType::Text { .. } | InputFieldType::Secret {} => match value {
+                Value::String(_) => Ok(None),
+                Value::Number(_) | Value::Bool(_) => Ok(Some(Value::String(value.to_string()))),
+                value => Err(format!("'{}' must be a string, got {}", name, value)),
+            },
+            InputFieldType::Int { .. } => match value {
+                Value::Number(n) if n.is_i64() => Ok(None),
+                Value::String(s) => s.trim().parse::<i64>()
+                    .map(|n| Some(Value::from(n)))
+                    .map_err(|_| format!("'{}' must be an int, got '{}'", name, s)),
+                value => Err(format!("'{}' must be an int, got {}", name, value)),
+            },
+            InputFieldType::Float { .. } => match value {
+                Value::Number(_) => Ok(None),
+                Value::String(s) => s.trim().parse::<f64>().ok()
+                    .and_then(serde_json::Number::from_f64)
+                    .map(|n| Some(Value::Number(n)))
+                    .ok_or_else(|| format!("'{}' must be a number, got '{}'", name, s)),
+                value => Err(format!("'{}' must be a number, got {}", name, value)),
+            },
+            InputFieldType::Boolean { .. } => match value {
+                Value::Bool(_) => Ok(None),
+                Value::String(s) => match s.trim().to_lowercase().as_str() {
+                    "true" | "on" | "yes" | "1" => Ok(Some(Value::Bool(true))),
+                    "false" | "off" | "no" | "0" => Ok(Some(Value::Bool(false))),
+                    _ => Err(format!("'{}' must be a boolean, got '{}'", name, s)),
+                },
+                value => Err(format!("'{}' must be a boolean, got {}", name, value)),
+            },
+            InputFieldType::Enum { values, .. } => match value {
+                Value::String(s) if values.contains(s) => Ok(None),
+                value => Err(format!("'{}' must be one of {}, got {}", name, values.join(", "), value)),
+            },
+        }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Checks `input` against the declared input fields, collecting an error per invalid field.
+/// Values that need converting (see [`InputFieldType::check`]) are replaced in `input`.
+fn check_input(fields: &HashMap<String, InputField>, input: &mut Map<String, Value>) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut names: Vec<&String> = fields.keys().collect();
+    names.sort();
+    for name in names {
+        let field = &fields[name];
+        let required = field.required.unwrap_or(false);
+        match input.get(name) {
+            None | Some(Value::Null) => {
+                if required {
+                    errors.push(format!("'{}' is required", name));
+                }
+            }
+            Some(Value::String(s)) if s.is_empty() => {
+                // Empty form fields count as unset
+                if required {
+                    errors.push(format!("'{}' is required", name));
+                }
+            }
+            Some(value) => match field.field_type.check(name, value) {
+                Ok(Some(converted)) => { input.insert(name.clone(), converted); }
+                Ok(None) => {}
+                Err(e) => errors.push(e),
+            },
+        }
+    }
+    errors
+}
+
+/// Names of the `secret` fields among `fields`.
+pub fn secret_fields(fields: Option<&HashMap<String, InputField>>) -> Vec<&str> {
+    fields.into_iter()
+        .flatten()
+        .filter(|(_, field)| matches!(field.field_type, InputFieldType::Secret {}))
+        .map(|(name, _)| name.as_str())
+        .collect()
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct OutputSpec {
     pub properties: HashMap<String, OutputProperty>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct OutputProperty {
     #[serde(rename = "type")]
     pub property_type: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct Task {
     #[serde(skip_deserializing, default = "default_id")]
     pub id: String,
@@ -86,61 +371,472 @@ pub struct Task {
     pub description: Option<String>,
     pub input: Option<HashMap<String, InputField>>,
     pub flow: HashMap<String, FlowStep>,
+    /// Maximum number of independent steps of this task running at the same time, at least 1. The runner
+    /// executes steps one at a time for now, which keeps to any limit; the key is accepted so workflows can set it
+    /// ahead of parallel execution.
+    pub parallelism: Option<usize>,
+    pub acl: Option<TaskAcl>,
+    /// Spaces out the dispatch of this task's jobs
+    pub rate_limit: Option<RateLimit>,
+    /// URLs the server posts a summary of this task's jobs to
+    pub webhooks: Option<TaskWebhooks>,
+    /// Notification targets, `<channel>` or `<channel>:<recipient>`, e.g. `slack:#ops`
+    pub notify: Option<TaskNotify>,
+    /// How long this task's jobs are expected to take; the server records the jobs that take longer
+    pub sla: Option<TaskSla>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default)]
+pub struct TaskWebhooks {
+    /// Called when a worker starts the job
+    pub on_start: Option<String>,
+    pub on_success: Option<String>,
+    pub on_failure: Option<String>,
+}
+
+impl TaskWebhooks {
+    pub fn urls(&self) -> impl Iterator<Item = &String> {
+        [&self.on_start, &self.on_success, &self.on_failure].into_iter().flatten()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default)]
+pub struct TaskNotify {
+    pub on_success: Option<Vec<String>>,
+    pub on_failure: Option<Vec<String>>,
+    /// When a job breaches the task's SLA, as soon as it does
+    pub on_sla_breach: Option<Vec<String>>,
+}
+
+impl TaskNotify {
+    pub fn targets(&self, success: bool) -> &[String] {
+        let targets = if success { &self.on_success } else { &self.on_failure };
+        targets.as_deref().unwrap_or_default()
+    }
+
+    pub fn sla_breach_targets(&self) -> &[String] {
+        self.on_sla_breach.as_deref().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default)]
+pub struct TaskSla {
+    /// Longest a job may run once a worker started it
+    #[serde(default, deserialize_with = "deserialize_option_duration")]
+    #[schemars(with = "Option<DurationSchema>")]
+    pub max_duration: Option<Duration>,
+    /// Longest from queueing a job to its completion, the wait for a worker included
+    #[serde(default, deserialize_with = "deserialize_option_duration")]
+    #[schemars(with = "Option<DurationSchema>")]
+    pub deadline: Option<Duration>,
+}
+
+/// At most `max` jobs with the same key are dispatched per `per`; the others wait in the queue.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct RateLimit {
+    /// Rendered with `input`, e.g. `{{ input.tenant }}`. Jobs of any task with the same key share the limit.
+    /// Defaults to the task id.
+    pub key: Option<String>,
+    #[serde(deserialize_with = "deserialize_duration")]
+    #[schemars(with = "DurationSchema")]
+    pub per: Duration,
+    pub max: u32,
+}
+
+impl RateLimit {
+    pub fn key_for(&self, task_id: &str, input: Option<&Value>) -> Result<String, Error> {
+        let Some(key) = &self.key else { return Ok(task_id.to_string()) };
+        let mut renderer = ParameterRenderer::new();
+        renderer.add_to_context(serde_json::json!({"input": input}))?;
+        match renderer.render(Value::String(key.clone()))? {
+            Value::String(key) => Ok(key),
+            key => Ok(key.to_string()),
+        }
+    }
+}
+
+/// Restricts who can see and run a task. Entries are user emails or `role:<role>`;
+/// a missing list leaves that permission to the user's role.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct TaskAcl {
+    pub run: Option<Vec<String>>,
+    pub view: Option<Vec<String>>,
 }
 
 fn default_id() -> String { "".to_string() }
 
+/// Whether `text` names `reference` as a whole: not as part of a longer identifier (`rebuild.output`,
+/// `build.outputs`) nor below a namespace (`steps.build.output`).
+fn contains_reference(text: &str, reference: &str) -> bool {
+    let is_identifier = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    text.match_indices(reference).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + reference.len()..].chars().next();
+        !before.is_some_and(|c| is_identifier(c) || c == '.') && !after.is_some_and(is_identifier)
+    })
+}
+
+/// Names that would shadow the top-level namespaces of the template context.
+pub const RESERVED_STEP_NAMES: [&str; 3] = ["input", "secrets", "steps"];
+
 impl Task {
     pub fn get_step(&self, name: &str) -> Option<&FlowStep> {
         self.flow.get(name)
     }
+
+    /// Returns the names of steps whose output `step` reads through the deprecated `<name>.output` form.
+    pub fn legacy_output_references(&self, step: &FlowStep) -> Vec<&str> {
+        let Some(input) = &step.input else { return vec![] };
+        let mut referenced: Vec<&str> = self.flow.keys()
+            .filter(|name| {
+                let legacy = format!("{}.output", name);
+                input.values().any(|value| contains_reference(value, &legacy))
+            })
+            .map(|name| name.as_str())
+            .collect();
+        referenced.sort();
+        referenced
+    }
+
+    /// Checks `input` against the declared input fields, converting form and template strings to the field types.
+    pub fn validate_input(&self, input: &mut Map<String, Value>) -> Result<(), Error> {
+        let Some(fields) = &self.input else { return Ok(()) };
+        let errors = check_input(fields, input);
+        if !errors.is_empty() {
+            bail!("Invalid input for task '{}': {}", self.id, errors.join(", "));
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct FlowStep {
     #[serde(skip_deserializing, default = "default_id")]
     pub id: String,
     pub name: Option<String>,
+    /// Name of a workspace `templates` entry the step inherits unset fields from
+    pub extends: Option<String>,
+    #[serde(default)]
     pub action: String,
     pub input: Option<HashMap<String, String>>,
     pub depends_on: Option<Vec<String>>,
     #[serde(default)]  // Ensures continue_on_fail defaults to false
     pub continue_on_fail: Option<bool>,
     pub on_error: Option<String>,  // Action name reference
+    pub retry: Option<RetryPolicy>,
+    /// Overrides the timeout of the referenced action
+    #[serde(default, deserialize_with = "deserialize_option_duration")]
+    #[schemars(with = "Option<DurationSchema>")]
+    pub timeout: Option<Duration>,
+    /// Condition deciding whether the step runs, e.g. `{{ input.env == 'prod' }}`
+    pub when: Option<String>,
+    /// Conditions checked against the step's `input` and `output` after it succeeded,
+    /// e.g. `{{ output.count > 0 }}`; the step fails on the first one that doesn't hold
+    #[serde(rename = "assert")]
+    pub assertions: Option<Vec<String>>,
+    /// Environment variables added to the action's, rendered like `input`
+    pub env: Option<HashMap<String, String>>,
+    /// Artifacts downloaded into the workspace before the action runs, rendered like `input`
+    pub artifacts_from: Option<Vec<ArtifactDependency>>,
+    /// Reuse the output of an earlier successful run with the same rendered action, input and workspace
+    /// revision instead of running the action, steps whose action uploads `artifacts` always run
+    pub cache: Option<bool>,
+}
+
+/// Artifacts of a step, of this job or of another one.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ArtifactDependency {
+    /// Job that uploaded them, e.g. `{{ input.build_job }}`; this job if not set
+    pub job: Option<String>,
+    /// Step that uploaded them; all steps if not set
+    pub step: Option<String>,
+    /// Name of the artifact, or of a directory of them; all artifacts if not set
+    pub name: Option<String>,
+}
+
+impl ArtifactDependency {
+    pub fn matches(&self, artifact: &Artifact) -> bool {
+        if self.step.as_ref().is_some_and(|step| step != &artifact.step_name) {
+            return false;
+        }
+        match self.name.as_deref().map(|name| name.trim_end_matches('/')) {
+            Some(name) => artifact.name == name || artifact.name.starts_with(&format!("{}/", name)),
+            None => true,
+        }
+    }
+}
+
+/// Step fields shared by the steps that `extends` the template.
+/// Fields set on the step take precedence, `input` and `env` are merged per key.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct StepTemplate {
+    pub action: Option<String>,
+    pub input: Option<HashMap<String, String>>,
+    pub depends_on: Option<Vec<String>>,
+    pub continue_on_fail: Option<bool>,
+    pub on_error: Option<String>,
+    pub retry: Option<RetryPolicy>,
+    #[serde(default, deserialize_with = "deserialize_option_duration")]
+    #[schemars(with = "Option<DurationSchema>")]
+    pub timeout: Option<Duration>,
+    pub when: Option<String>,
+    #[serde(rename = "assert")]
+    pub assertions: Option<Vec<String>>,
+    pub env: Option<HashMap<String, String>>,
+    pub artifacts_from: Option<Vec<ArtifactDependency>>,
+    pub cache: Option<bool>,
+}
+
+impl FlowStep {
+    fn apply_template(&mut self, template: &StepTemplate) {
+        if self.action.is_empty() {
+            self.action = template.action.clone().unwrap_or_default();
+        }
+        if let Some(template_input) = &template.input {
+            let input = self.input.get_or_insert_with(HashMap::new);
+            for (key, value) in template_input {
+                input.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+        if let Some(template_env) = &template.env {
+            let env = self.env.get_or_insert_with(HashMap::new);
+            for (key, value) in template_env {
+                env.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+        self.depends_on = self.depends_on.take().or_else(|| template.depends_on.clone());
+        self.continue_on_fail = self.continue_on_fail.or(template.continue_on_fail);
+        self.on_error = self.on_error.take().or_else(|| template.on_error.clone());
+        self.retry = self.retry.take().or_else(|| template.retry.clone());
+        self.timeout = self.timeout.or(template.timeout);
+        self.when = self.when.take().or_else(|| template.when.clone());
+        self.assertions = self.assertions.take().or_else(|| template.assertions.clone());
+        self.artifacts_from = self.artifacts_from.take().or_else(|| template.artifacts_from.clone());
+        self.cache = self.cache.or(template.cache);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct RetryPolicy {
+    #[serde(default = "default_retry_max_attempts")]
+    pub max_attempts: u32,
+    pub backoff: Option<Backoff>,
+    /// Conditions that trigger a retry; retries on any failure when not set
+    pub retry_on: Option<Vec<RetryOn>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Backoff {
+    /// Delay before the first retry, in seconds
+    #[serde(default = "default_backoff_delay")]
+    pub delay: u64,
+    #[serde(default)]
+    pub strategy: BackoffStrategy,
+    /// Upper bound for the delay between attempts, in seconds
+    pub max_delay: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Default, PartialEq, AsRefStr)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum BackoffStrategy {
+    #[default]
+    Fixed,
+    Exponential,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq, AsRefStr)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum RetryOn {
+    /// The action finished with a non-zero exit status
+    Failure,
+    /// The action could not be executed at all (spawn errors and similar)
+    Error,
+    /// The action was killed after exceeding its timeout
+    Timeout,
+}
+
+fn default_retry_max_attempts() -> u32 { 3 }
+fn default_backoff_delay() -> u64 { 1 }
+
+impl RetryPolicy {
+    pub fn should_retry(&self, attempt: u32, condition: &RetryOn) -> bool {
+        if attempt >= self.max_attempts {
+            return false;
+        }
+        match &self.retry_on {
+            Some(conditions) => conditions.contains(condition),
+            None => true,
+        }
+    }
+
+    /// Returns how long to wait after the given (1-based) failed attempt.
+    pub fn delay_after(&self, attempt: u32) -> Duration {
+        let Some(backoff) = &self.backoff else { return Duration::ZERO };
+        let delay = match backoff.strategy {
+            BackoffStrategy::Fixed => backoff.delay,
+            BackoffStrategy::Exponential => backoff.delay.saturating_mul(2u64.saturating_pow(attempt.saturating_sub(1))),
+        };
+        let delay = match backoff.max_delay {
+            Some(max_delay) => delay.min(max_delay),
+            None => delay,
+        };
+        Duration::from_secs(delay)
+    }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct Trigger {
     #[serde(skip_deserializing, default = "default_id")]
     pub id: String,
     pub task: String,
     pub input: Option<HashMap<String, String>>,
     pub enabled: Option<bool>,
+    /// Priority of the jobs enqueued by this trigger
+    pub priority: Option<i32>,
 
     #[serde(flatten)]
     pub trigger_type: TriggerType,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, AsRefStr)]
+/// Missed runs are detected from the last run the server recorded for the trigger.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, AsRefStr)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum CatchupPolicy {
+    /// Missed runs are skipped
+    #[default]
+    None,
+    /// A single run is enqueued for any number of missed runs
+    FireOnce,
+    /// Every missed run is enqueued, up to a limit
+    FireAll,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, AsRefStr)]
 #[strum(serialize_all = "snake_case")]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum TriggerType {
     Scheduler {
         cron: String,
+        /// IANA time zone the cron expression is evaluated in, e.g. `Europe/Oslo`. Defaults to UTC.
+        timezone: Option<String>,
+        /// Upper bound of a random delay added to each run, e.g. `5m`
+        #[serde(default, deserialize_with = "deserialize_option_duration")]
+        #[schemars(with = "Option<DurationSchema>")]
+        jitter: Option<Duration>,
+        /// What to do with runs missed while the server was down
+        #[serde(default)]
+        catchup: CatchupPolicy,
+    },
+    /// Runs the task on `POST /hooks/<trigger id>`. The trigger `input` is rendered with
+    /// `payload` (the JSON body) and `headers` (lowercase names, `-` as `_`); without it the payload is the input.
+    Webhook {
+        /// Expected in the `X-Webhook-Token` header or `token` query parameter
+        secret: Option<String>,
     },
+    /// Runs the task for every message published on a NATS subject. The trigger `input` is rendered with
+    /// `payload` (the message, parsed as JSON if possible) and `subject`; without it the payload is the input.
+    Nats {
+        /// e.g. `nats://nats.example.com:4222`, or `tls://` for a TLS connection
+        url: String,
+        /// May contain wildcards, e.g. `deploys.>`
+        subject: String,
+        /// Servers subscribing in the same queue group share the messages, so each enqueues a job once
+        queue_group: Option<String>,
+        token: Option<String>,
+        user: Option<String>,
+        password: Option<String>,
+    },
+}
+
+/// Schema of the durations in workflow files, which are parsed by `duration_str` rather than serde.
+struct DurationSchema;
+
+impl JsonSchema for DurationSchema {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "Duration".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "type": ["string", "integer"],
+            "description": "A number of seconds, or a duration like `30s`, `5m` or `1h 30m`",
+        })
+    }
+}
+
+/// A problem with the workflow files, with the file and entry it is in where known.
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationError {
+    /// Workflow file, relative to the workspace
+    pub file: Option<String>,
+    /// Entry the problem is in, e.g. `tasks.deploy.flow.build`
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl ValidationError {
+    fn in_file(file: &str, message: String) -> Self {
+        ValidationError { file: Some(file.to_string()), key: None, message }
+    }
+
+    /// A problem in `<section>.<id>`, or in `path` below it if not empty.
+    fn in_entry(sources: &HashMap<String, String>, section: &str, id: &str, path: &str, message: String) -> Self {
+        let entry = format!("{}.{}", section, id);
+        ValidationError {
+            file: sources.get(&entry).cloned(),
+            key: Some(if path.is_empty() { entry } else { format!("{}.{}", entry, path) }),
+            message,
+        }
+    }
+
+    /// The error [`WorkflowsConfiguration::new`] failed with, without file context if it has none.
+    pub fn of_load_error(error: Error) -> Self {
+        error.downcast::<ValidationError>()
+            .unwrap_or_else(|error| ValidationError { file: None, key: None, message: format!("{:#}", error) })
+    }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}: {}", file, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone)]
 #[derive(Default)]
 pub struct WorkflowsConfiguration {
     pub globals: Option<Globals>,
     pub actions: Option<HashMap<String, Action>>,
     pub tasks: Option<HashMap<String, Task>>,
     pub triggers: Option<HashMap<String, Trigger>>,
+    /// Shared step configuration, see [`StepTemplate`]
+    pub templates: Option<HashMap<String, StepTemplate>>,
     pub secrets: Option<Value>,
+    /// File each action, task, trigger and template was last defined in, by `<section>.<id>`
+    #[serde(skip)]
+    sources: HashMap<String, String>,
 }
 
+/// Sections of the workflow files whose entries are tracked back to their file.
+const SOURCE_SECTIONS: [&str; 4] = ["actions", "tasks", "triggers", "templates"];
+
 impl WorkflowsConfiguration {
+    /// JSON Schema of a workflow file, for editors to complete and check them. Draft 7, the one YAML
+    /// language servers support best.
+    pub fn json_schema() -> Value {
+        let mut schema = SchemaSettings::draft07().into_generator().into_root_schema_for::<Self>();
+        schema.insert("title".to_string(), Value::String("Strøm workflows".to_string()));
+        schema.to_value()
+    }
+
     pub fn new(workspace_path: PathBuf) -> Result<Self, Error> {
         let workflows_path = workspace_path.join(".workflows");
         if !workflows_path.exists() {
@@ -159,18 +855,26 @@ impl WorkflowsConfiguration {
         };
 
         let mut config_builder = Config::builder();
+        let mut sources = HashMap::new();
 
-        // Process each file from the glob walker asynchronously
+        // Each file is parsed on its own first, so errors and entries can be traced back to it
         for entry in gw.into_iter().filter_map(Result::ok) {
             let path = entry.path();
-            let _ = if path.extension().and_then(|s| s.to_str()) == Some("sops.yaml") {
-                // Decrypt SOPS file asynchronously
-                let decrypted_content = decrypt_sops_file(path)?;
-                config_builder = config_builder.add_source(config::File::from_str(&decrypted_content, config::FileFormat::Yaml));
+            let file = path.strip_prefix(&workspace_path).unwrap_or(path).to_string_lossy().to_string();
+            let content = if path.extension().and_then(|s| s.to_str()) == Some("sops.yaml") {
+                decrypt_sops_file(path).map_err(|e| ValidationError::in_file(&file, e.to_string()))?
             } else {
-                // Regular YAML file
-                config_builder = config_builder.add_source(config::File::from(path));
+                std::fs::read_to_string(path).map_err(|e| ValidationError::in_file(&file, format!("Could not read file: {}", e)))?
             };
+            let source = config::File::from_str(&content, config::FileFormat::Yaml);
+            let parsed = Config::builder().add_source(source.clone()).build()
+                .map_err(|e| ValidationError::in_file(&file, format!("Invalid YAML: {}", e)))?;
+            for section in SOURCE_SECTIONS {
+                for id in parsed.get_table(section).unwrap_or_default().into_keys() {
+                    sources.insert(format!("{}.{}", section, id), file.clone());
+                }
+            }
+            config_builder = config_builder.add_source(source);
         }
 
         // Build the config
@@ -203,6 +907,17 @@ impl WorkflowsConfiguration {
                 task.id = id.clone();
                 for (step_id, step) in &mut task.flow {
                     step.id = step_id.clone();
+                    if let Some(template_name) = &step.extends {
+                        let template = cfg.templates.as_ref()
+                            .and_then(|templates| templates.get(template_name))
+                            .ok_or_else(|| ValidationError::in_entry(&sources, "tasks", id, &format!("flow.{}.extends", step_id),
+                                format!("Step '{}' in task '{}' extends non-existent template '{}'", step_id, id, template_name)))?;
+                        step.apply_template(template);
+                    }
+                    if step.action.is_empty() {
+                        return Err(ValidationError::in_entry(&sources, "tasks", id, &format!("flow.{}", step_id),
+                            format!("Step '{}' in task '{}' has no action", step_id, id)).into());
+                    }
                 }
                 if let Some(inputs) = &mut task.input {
                     for (input_id, input) in inputs {
@@ -218,6 +933,7 @@ impl WorkflowsConfiguration {
             }
         }
 
+        cfg.sources = sources;
         Ok(cfg)
     }
 
@@ -228,38 +944,219 @@ impl WorkflowsConfiguration {
         })
     }
 
+    /// Fails with the first problem [`Self::validation_errors`] finds, and logs deprecation warnings.
     pub fn validate(&self) -> Result<(), Error> {
-        // Validate triggers if present
-        if let Some(triggers) = &self.triggers {
-            for (trigger_name, trigger) in triggers {
-                let _ = self.get_task(&trigger.task)
-                    .ok_or_else(|| anyhow!("Trigger '{}' references non-existent task '{}'", trigger_name, trigger.task))?;
+        if let Some(error) = self.validation_errors().into_iter().next() {
+            return Err(error.into());
+        }
+        for warning in self.deprecation_warnings() {
+            warn!("{}", warning);
+        }
+        Ok(())
+    }
+
+    /// Every problem with the workflows, sorted by file and entry.
+    pub fn validation_errors(&self) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        let mut error_in = |section: &str, id: &str, path: &str, message: String| {
+            errors.push(ValidationError::in_entry(&self.sources, section, id, path, message));
+        };
+
+        for (trigger_name, trigger) in self.triggers.iter().flatten() {
+            if self.get_task(&trigger.task).is_none() {
+                error_in("triggers", trigger_name, "task", format!("Trigger '{}' references non-existent task '{}'", trigger_name, trigger.task));
+            }
+            let timezone = match &trigger.trigger_type {
+                TriggerType::Scheduler { timezone, .. } => timezone.as_ref(),
+                _ => None,
+            };
+            if let Some(timezone) = timezone.filter(|timezone| timezone.parse::<chrono_tz::Tz>().is_err()) {
+                error_in("triggers", trigger_name, "timezone", format!("Trigger '{}' has an unknown timezone '{}'", trigger_name, timezone));
+            }
+            // The rendered input is stored with the job and shown by the API, secrets belong in the task's steps
+            let input = trigger.input.iter().flatten().map(|(name, value)| (name.clone(), Value::String(value.clone()))).collect();
+            if !secret_references(&Value::Object(input)).is_empty() {
+                error_in("triggers", trigger_name, "input", format!("Trigger '{}' references secrets in its input, reference them in the task's steps instead", trigger_name));
+            }
+        }
+
+        let task_inputs = self.tasks.iter().flatten().map(|(name, task)| ("tasks", "task", name, &task.input));
+        let action_inputs = self.actions.iter().flatten().map(|(name, action)| ("actions", "action", name, &action.input));
+        for (section, kind, owner, fields) in task_inputs.chain(action_inputs) {
+            for (name, field) in fields.iter().flatten() {
+                if let InputFieldType::Enum { values, default } = &field.field_type {
+                    let path = format!("input.{}", name);
+                    if values.is_empty() {
+                        error_in(section, owner, &path, format!("Enum input '{}' of {} '{}' has no values", name, kind, owner));
+                    } else if default.as_ref().is_some_and(|default| !values.contains(default)) {
+                        error_in(section, owner, &path, format!("Enum input '{}' of {} '{}' has a default that is not one of its values", name, kind, owner));
+                    }
+                }
             }
         }
 
-        // Validate tasks and their steps if present
+        for (task_name, task) in self.tasks.iter().flatten() {
+            if task.parallelism == Some(0) {
+                error_in("tasks", task_name, "parallelism", format!("Task '{}' has parallelism set to 0", task_name));
+            }
+            if task.rate_limit.as_ref().is_some_and(|rate_limit| rate_limit.max == 0 || rate_limit.per.is_zero()) {
+                error_in("tasks", task_name, "rate_limit", format!("Task '{}' has a rate limit that never allows a run", task_name));
+            }
+            if let Some(sla) = &task.sla {
+                let limits = [sla.max_duration, sla.deadline];
+                if limits.iter().all(Option::is_none) || limits.iter().flatten().any(Duration::is_zero) {
+                    error_in("tasks", task_name, "sla", format!("Task '{}' has an SLA without a max_duration or deadline above zero", task_name));
+                }
+            }
+            for url in task.webhooks.iter().flat_map(|webhooks| webhooks.urls()) {
+                if let Err(e) = reqwest::Url::parse(url) {
+                    error_in("tasks", task_name, "webhooks", format!("Task '{}' has an invalid webhook URL '{}': {}", task_name, url, e));
+                }
+            }
+            for (step_name, step) in &task.flow {
+                let path = format!("flow.{}", step_name);
+                if RESERVED_STEP_NAMES.contains(&step_name.as_str()) {
+                    error_in("tasks", task_name, &path, format!("Step '{}' in task '{}' uses a reserved name, step names must not be one of: {}", step_name, task_name, RESERVED_STEP_NAMES.join(", ")));
+                }
+                if self.get_action(&step.action).is_none() {
+                    error_in("tasks", task_name, &format!("{}.action", path), format!("Step '{}' in task '{}' references non-existent action '{}'", step_name, task_name, step.action));
+                }
+                if let Some(on_error) = step.on_error.as_ref().filter(|on_error| self.get_action(on_error).is_none()) {
+                    error_in("tasks", task_name, &format!("{}.on_error", path), format!("Step '{}' in task '{}' has on_error '{}' referencing non-existent action", step_name, task_name, on_error));
+                }
+                for dependency in step.depends_on.iter().flatten() {
+                    if !task.flow.contains_key(dependency) {
+                        error_in("tasks", task_name, &format!("{}.depends_on", path), format!("Step '{}' in task '{}' depends on non-existent step '{}'", step_name, task_name, dependency));
+                    }
+                }
+                if let Some(Err(e)) = step.when.as_deref().and_then(condition::as_expression).map(Condition::parse) {
+                    error_in("tasks", task_name, &format!("{}.when", path), format!("Step '{}' in task '{}' has an invalid when condition: {}", step_name, task_name, e));
+                }
+                for assertion in step.assertions.iter().flatten() {
+                    if let Some(Err(e)) = condition::as_expression(assertion).map(Condition::parse) {
+                        error_in("tasks", task_name, &format!("{}.assertions", path), format!("Step '{}' in task '{}' has an invalid assertion '{}': {}", step_name, task_name, assertion, e));
+                    }
+                }
+                if step.retry.as_ref().is_some_and(|retry| retry.max_attempts == 0) {
+                    error_in("tasks", task_name, &format!("{}.retry", path), format!("Step '{}' in task '{}' has retry.max_attempts set to 0", step_name, task_name));
+                }
+            }
+            let cycles = dag_walker::find_cycles(&task.flow);
+            for cycle in &cycles {
+                error_in("tasks", task_name, &format!("flow.{}.depends_on", cycle[0]), format!("Steps in task '{}' depend on each other in a cycle: {}", task_name, cycle.join(" -> ")));
+            }
+            // Steps in a cycle or with a non-existent dependency are reported above
+            let blocked = dag_walker::blocked_steps(&task.flow);
+            for step_name in &blocked {
+                let step = &task.flow[step_name];
+                let dependencies: Vec<&String> = step.depends_on.iter().flatten().collect();
+                if cycles.iter().any(|cycle| cycle.contains(step_name)) || dependencies.iter().any(|dependency| !task.flow.contains_key(*dependency)) {
+                    continue;
+                }
+                let waits_on: Vec<String> = dependencies.iter().filter(|dependency| blocked.contains(**dependency)).map(|dependency| format!("'{}'", dependency)).collect();
+                error_in("tasks", task_name, &format!("flow.{}.depends_on", step_name), format!("Step '{}' in task '{}' is unreachable, it depends on {} which can never run", step_name, task_name, waits_on.join(", ")));
+            }
+        }
+
+        let error_handler = self.globals.as_ref().and_then(|globals| globals.error_handler.as_ref());
+        if let Some(error_handler) = error_handler.filter(|error_handler| self.get_action(error_handler).is_none()) {
+            errors.push(ValidationError {
+                file: None,
+                key: Some("globals.error_handler".to_string()),
+                message: format!("Global error handler '{}' references non-existent action", error_handler),
+            });
+        }
+
+        let secrets_providers = self.globals.iter().flat_map(|globals| &globals.secrets_providers).enumerate();
+        for (index, provider) in secrets_providers {
+            if let SecretsProviderConfig::Env { prefix } = provider && prefix.starts_with(RESERVED_ENV_PREFIX) {
+                errors.push(ValidationError {
+                    file: None,
+                    key: Some(format!("globals.secrets_providers.{}.prefix", index)),
+                    message: format!("Secrets provider prefix '{}' would expose Stroem's own {} variables", prefix, RESERVED_ENV_PREFIX),
+                });
+            }
+        }
+
+        errors.sort_by(|a, b| (&a.file, &a.key).cmp(&(&b.file, &b.key)));
+        errors
+    }
+
+    /// Lists deprecated actions that are still referenced by task steps.
+    pub fn deprecation_warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
         if let Some(tasks) = &self.tasks {
             for (task_name, task) in tasks {
                 for (step_name, step) in &task.flow {
-                    self.get_action(&step.action)
-                        .ok_or_else(|| anyhow!("Step '{}' in task '{}' references non-existent action '{}'", step_name, task_name, step.action))?;
-                    if let Some(on_error) = &step.on_error {
-                        self.get_action(on_error)
-                            .ok_or_else(|| anyhow!("Step '{}' in task '{}' has on_error '{}' referencing non-existent action", step_name, task_name, on_error))?;
+                    if let Some(deprecation) = self.get_action(&step.action).and_then(|a| a.deprecated.as_ref()) {
+                        warnings.push(format!("Step '{}' in task '{}': {}", step_name, task_name, deprecation.describe(&step.action)));
+                    }
+                    for referenced in task.legacy_output_references(step) {
+                        warnings.push(format!("Step '{}' in task '{}' references '{}.output', which is deprecated, use 'steps.{}.output' instead", step_name, task_name, referenced, referenced));
                     }
                 }
             }
         }
+        warnings.sort();
+        warnings
+    }
+
+    /// Returns the deprecations of sunset actions a run of the given task or action would use.
+    pub fn sunset_actions(&self, task: Option<&str>, action: Option<&str>, today: NaiveDate) -> Vec<String> {
+        let mut action_names: Vec<&str> = Vec::new();
+        if let Some(task) = task.and_then(|t| self.get_task(t)) {
+            action_names.extend(task.flow.values().map(|step| step.action.as_str()));
+        }
+        if let Some(action) = action {
+            action_names.push(action);
+        }
+        action_names.sort();
+        action_names.dedup();
+
+        action_names.into_iter()
+            .filter_map(|name| {
+                self.get_action(name)
+                    .and_then(|a| a.deprecated.as_ref())
+                    .filter(|d| d.is_sunset(today))
+                    .map(|d| d.describe(name))
+            })
+            .collect()
+    }
 
-        // Validate global error handler if present
-        if let Some(globals) = &self.globals {
-            if let Some(error_handler) = &globals.error_handler {
-                self.get_action(error_handler)
-                    .ok_or_else(|| anyhow!("Global error handler '{}' references non-existent action", error_handler))?;
+    /// Validates the input of a run of the given task or action, see [`Task::validate_input`].
+    /// Unknown tasks and actions are left for the runner to report.
+    pub fn validate_run_input(&self, task: Option<&str>, action: Option<&str>, input: &mut Option<Value>) -> Result<(), Error> {
+        let mut map = match input.take() {
+            None | Some(Value::Null) => Map::new(),
+            Some(Value::Object(map)) => map,
+            Some(value) => {
+                *input = Some(value);
+                bail!("Input must be an object");
             }
+        };
+        let result = match (task.and_then(|t| self.get_task(t)), action.and_then(|a| self.get_action(a))) {
+            (Some(task), _) => task.validate_input(&mut map),
+            (None, Some(action)) => action.validate_input(&mut map),
+            (None, None) => Ok(()),
+        };
+        if !map.is_empty() {
+            *input = Some(Value::Object(map));
         }
+        result
+    }
 
-        Ok(())
+    /// Docker images declared by the actions, sorted and deduplicated.
+    pub fn docker_images(&self) -> Vec<&str> {
+        let mut images: Vec<&str> = self.actions.iter()
+            .flat_map(|actions| actions.values())
+            .filter_map(|action| match &action.action_type {
+                ActionType::Docker { image } => image.as_deref(),
+                _ => None,
+            })
+            .collect();
+        images.sort();
+        images.dedup();
+        images
     }
 
     pub fn get_action(&self, name: &str) -> Option<&Action> {
@@ -269,6 +1166,10 @@ impl WorkflowsConfiguration {
     pub fn get_task(&self, name: &str) -> Option<&Task> {
         self.tasks.as_ref()?.get(name)
     }
+
+    pub fn get_trigger(&self, name: &str) -> Option<&Trigger> {
+        self.triggers.as_ref()?.get(name)
+    }
 }
 
 /// Decrypt a SOPS-encrypted YAML file using the `sops` command-line tool.
@@ -1,41 +1,115 @@
+//! Workspaces of runners on a worker. Each revision is unpacked once, under `<workspace>/revisions/<revision>`,
+//! and each job runs in its own copy of it, `<workspace>/jobs/<job_id>`, so concurrent jobs on different
+//! revisions don't overwrite each other's files. A runner holds the lock on `<job_id>.lock` while its job runs,
+//! and removes the copy when done; copies whose runner is gone without doing so are removed by the next `sync`.
+//! A new revision is built from the previous one and the files that changed, as listed by the manifests of both,
+//! falling back to the full tarball. Workspaces of projects other than the default one are kept the same way
+//! under `<workspace>/projects/<project>`.
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::fs;
+use std::io;
+use std::sync::Arc;
 use anyhow::{anyhow, bail, Error};
-use tracing::info;
+use blake2::{Blake2b512, Digest};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use tracing::{debug, error, info};
 use tar::{Archive};
 use std::fs::{File};
-use std::io::{Read, Write};
 use flate2::read::GzDecoder;
-use reqwest::{header, Client};
+use reqwest::{header, Client, IntoUrl, Method, RequestBuilder, Url};
 use fs2::FileExt;
+use crate::artifacts::is_valid_name;
 use crate::workflows_configuration::WorkflowsConfiguration;
+use crate::http_retry::send_with_retry;
+use crate::protocol::{PROJECT_PARAM, REVISION_HEADER};
 
+/// Files of a workspace revision, by path with `/` separators, and the digest of their content.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct WorkspaceManifest {
+    pub revision: String,
+    pub files: BTreeMap<String, String>,
+    /// Unix permission bits of the files, by the same paths, empty for servers on other platforms
+    #[serde(default)]
+    pub modes: BTreeMap<String, u32>,
+}
+
+/// Hex digest of the content of the file, as listed in manifests.
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Blake2b512::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Permission bits of the file, as listed in manifests.
+#[cfg(unix)]
+pub fn file_mode(path: &Path) -> io::Result<Option<u32>> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(Some(fs::metadata(path)?.permissions().mode() & 0o7777))
+}
+
+#[cfg(not(unix))]
+pub fn file_mode(_path: &Path) -> io::Result<Option<u32>> {
+    Ok(None)
+}
+
+#[cfg(unix)]
+fn set_file_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_file_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
 
 #[derive(Clone)]
 pub struct WorkspaceClient {
+    /// Directory actions run in: the job's copy of the workspace once synced
     pub path: PathBuf,
     pub workflows: Option<WorkflowsConfiguration>,
     pub revision: Option<String>,
+    root: PathBuf,
+    project: Option<String>,
+    job_id: Option<String>,
+    job: Option<Arc<JobWorkspace>>,
 }
 
 impl WorkspaceClient {
     pub async fn new(path: PathBuf) -> Self {
         fs::create_dir_all(&path).unwrap_or_default();
         Self {
+            root: path.clone(),
             path,
             workflows: None,
             revision: None,
+            project: None,
+            job_id: None,
+            job: None,
         }
     }
 
-    pub async fn sync(&mut self, server: &str, token: &str) -> Result<String, Error> {
-        let client = Client::new();
+    /// Syncs the workspace of the project instead of the default project's.
+    pub fn with_project(mut self, project: &str) -> Self {
+        self.root = self.root.join("projects").join(dir_name(project));
+        self.project = Some(project.to_string());
+        self
+    }
+
+    /// Syncs into a copy of the workspace for the job alone, instead of the shared one of the revision.
+    pub fn with_job(mut self, job_id: &str) -> Self {
+        self.job_id = Some(job_id.to_string());
+        self
+    }
+
+    pub async fn sync(&mut self, client: &Client, server: &str, token: &str) -> Result<String, Error> {
+        let files = FilesApi { client, server, token, project: self.project.as_deref() };
         let url = format!("{}/files/workspace.tar.gz", server);
 
         // Check revision with HEAD request
-        let head_response = client.head(&url)
-            .header(header::AUTHORIZATION, format!("Bearer {}", token))
-            .send()
+        let head_response = send_with_retry(files.request(Method::HEAD, &url))
             .await
             .map_err(|e| anyhow!("Failed to fetch workspace revision: {}", e))?;
 
@@ -44,83 +118,90 @@ impl WorkspaceClient {
         }
 
         let revision = head_response.headers()
-            .get("X-Revision")
+            .get(REVISION_HEADER)
             .and_then(|v| v.to_str().ok())
             .map(|s| s.to_string())
             .unwrap_or_else(|| "unknown".to_string());
 
-        let rev_file = format!("{}.rev", self.path.to_string_lossy());
-        let should_download = if Path::new(&rev_file).exists() {
-            let mut current_rev = String::new();
-            File::open(&rev_file)
-                .and_then(|mut f| f.read_to_string(&mut current_rev))
-                .map(|_| current_rev.trim() != revision)
-                .unwrap_or(true)
-        } else {
-            true
-        };
-
-        if !should_download {
-            info!("Workspace already up-to-date with revision {}", revision);
-            self.revision = Some(revision.clone());
-            return Ok(revision);
-        }
-
-        // Use file lock to ensure exclusive access across processes
-        let lock_file = PathBuf::from(format!("{}.lock", self.path.to_string_lossy()));
-        fs::create_dir_all(&self.path)
+        let revisions = self.root.join("revisions");
+        fs::create_dir_all(&revisions)
             .map_err(|e| anyhow!("Failed to create workspace dir: {}", e))?;
+
+        // Unpacking, copying and pruning are exclusive across the runners of the worker
+        let lock_file = self.root.join("revisions.lock");
         let lock = File::create(&lock_file)
             .map_err(|e| anyhow!("Failed to create lock file {}: {}", lock_file.display(), e))?;
         lock.lock_exclusive()
             .map_err(|e| anyhow!("Failed to acquire lock on {}: {}", lock_file.display(), e))?;
 
-        // Re-check after locking to avoid race conditions
-        let should_download = if Path::new(&rev_file).exists() {
-            let mut current_rev = String::new();
-            File::open(&rev_file)
-                .and_then(|mut f| f.read_to_string(&mut current_rev))
-                .map(|_| current_rev.trim() != revision)
-                .unwrap_or(true)
+        let revision_path = revisions.join(dir_name(&revision));
+        if revision_path.exists() {
+            info!("Workspace already up-to-date with revision {}", revision);
         } else {
-            true
-        };
-
-        if !should_download {
-            info!("Workspace already up-to-date with revision {} after lock", revision);
-            fs2::FileExt::unlock(&lock)
-                .map_err(|e| anyhow!("Failed to release lock on {}: {}", lock_file.display(), e))?;
-            return Ok(revision);
-        }
-
-        fs::create_dir_all(&self.path)
-            .map_err(|e| anyhow!("Failed to create workspace dir: {}", e))?;
-
-        let response = client.get(&url)
-            .header(header::AUTHORIZATION, format!("Bearer {}", token))
-            .send()
-            .await
-            .map_err(|e| anyhow!("Failed to fetch workspace tar: {}", e))?;
-
-        if !response.status().is_success() {
-            bail!("Server returned error: {}", response.status());
+            // Unpacked next to it first, so an interrupted sync isn't taken for the revision
+            let unpack_path = revisions.join(format!("{}.tmp", dir_name(&revision)));
+            remove_dir(&unpack_path)?;
+            let manifest = match fetch_manifest(&files).await {
+                Ok(manifest) if manifest.revision == revision => Some(manifest),
+                Ok(manifest) => {
+                    info!("Workspace manifest is of revision {}, downloading the full tarball", manifest.revision);
+                    None
+                }
+                Err(e) => {
+                    info!("No workspace manifest, downloading the full tarball: {}", e);
+                    None
+                }
+            };
+            let synced = match (&manifest, previous_revision(&revisions, &revision_path)) {
+                (Some(manifest), Some((previous_path, previous))) => {
+                    match download_changes(&files, &previous_path, &previous, manifest, &unpack_path).await {
+                        Ok(count) => {
+                            info!("Workspace revision {} built from revision {} and {} changed files", revision, previous.revision, count);
+                            true
+                        }
+                        Err(e) => {
+                            error!("Failed to sync the workspace changes, downloading the full tarball: {}", e);
+                            remove_dir(&unpack_path)?;
+                            false
+                        }
+                    }
+                }
+                _ => false,
+            };
+            if !synced {
+                download_tarball(&files, &url, &unpack_path).await?;
+                info!("Workspace tarball unpacked with revision {}", revision);
+            }
+            fs::rename(&unpack_path, &revision_path)
+                .map_err(|e| anyhow!("Failed to move workspace to {:?}: {}", &revision_path, e))?;
+            if let Some(manifest) = &manifest {
+                let manifest_path = sibling(&revision_path, "manifest");
+                fs::write(&manifest_path, serde_json::to_vec(manifest)?)
+                    .map_err(|e| anyhow!("Failed to write manifest {}: {}", manifest_path.display(), e))?;
+            }
         }
-        let tar_gz = response.bytes()
-            .await
-            .map_err(|e| anyhow!("Failed to read tarball bytes: {}", e))?;
-        let tar = GzDecoder::new(&tar_gz[..]);
-        let mut archive = Archive::new(tar);
-        archive.unpack(&self.path)
-            .map_err(|e| anyhow!("Failed to unpack workspace tar to {:?}: {}", &self.path, e))?;
+        prune_revisions(&revisions, &revision_path);
 
-        File::create(&rev_file)
-            .and_then(|mut f| f.write_all(revision.as_bytes()))
-            .map_err(|e| anyhow!("Failed to write revision file {}: {}", rev_file, e))?;
+        self.path = match &self.job_id {
+            Some(job_id) => {
+                let jobs = self.root.join("jobs");
+                fs::create_dir_all(&jobs)
+                    .map_err(|e| anyhow!("Failed to create workspace dir: {}", e))?;
+                prune_jobs(&jobs);
+                let job = JobWorkspace::create(jobs.join(job_id))?;
+                copy_dir(&revision_path, &job.path)
+                    .map_err(|e| anyhow!("Failed to copy workspace to {:?}: {}", &job.path, e))?;
+                debug!("Copied workspace revision {} to {:?}", revision, &job.path);
+                let path = job.path.clone();
+                self.job = Some(Arc::new(job));
+                path
+            }
+            None => revision_path,
+        };
 
         fs2::FileExt::unlock(&lock)
             .map_err(|e| anyhow!("Failed to release lock on {}: {}", lock_file.display(), e))?;
 
-        info!("Workspace tarball unpacked to {:?} with revision {}", &self.path, revision);
         self.revision = Some(revision.clone());
         Ok(revision)
     }
@@ -134,3 +215,221 @@ impl WorkspaceClient {
     }
 
 }
+
+/// The workspace routes of the server, for the files of one project.
+struct FilesApi<'a> {
+    client: &'a Client,
+    server: &'a str,
+    token: &'a str,
+    /// The server serves the default project's files without one
+    project: Option<&'a str>,
+}
+
+impl FilesApi<'_> {
+    fn request(&self, method: Method, url: impl IntoUrl) -> RequestBuilder {
+        let request = self.client.request(method, url)
+            .header(header::AUTHORIZATION, format!("Bearer {}", self.token));
+        match self.project {
+            Some(project) => request.query(&[(PROJECT_PARAM, project)]),
+            None => request,
+        }
+    }
+}
+
+async fn download_tarball(files: &FilesApi<'_>, url: &str, target: &Path) -> Result<(), Error> {
+    let response = send_with_retry(files.request(Method::GET, url))
+        .await
+        .map_err(|e| anyhow!("Failed to fetch workspace tar: {}", e))?;
+
+    if !response.status().is_success() {
+        bail!("Server returned error: {}", response.status());
+    }
+    let tar_gz = response.bytes()
+        .await
+        .map_err(|e| anyhow!("Failed to read tarball bytes: {}", e))?;
+    let tar = GzDecoder::new(&tar_gz[..]);
+    let mut archive = Archive::new(tar);
+    archive.unpack(target)
+        .map_err(|e| anyhow!("Failed to unpack workspace tar to {:?}: {}", target, e))?;
+    Ok(())
+}
+
+async fn fetch_manifest(files: &FilesApi<'_>) -> Result<WorkspaceManifest, Error> {
+    let response = send_with_retry(files.request(Method::GET, format!("{}/files/workspace.manifest", files.server)))
+        .await?;
+    if !response.status().is_success() {
+        bail!("Server returned error: {}", response.status());
+    }
+    Ok(response.json().await?)
+}
+
+/// The revision kept from the previous sync, with its manifest, to build the new one from.
+fn previous_revision(revisions: &Path, current: &Path) -> Option<(PathBuf, WorkspaceManifest)> {
+    fs::read_dir(revisions).ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path != current && path.is_dir())
+        .find_map(|path| {
+            let manifest = fs::read(sibling(&path, "manifest")).ok()?;
+            Some((path, serde_json::from_slice(&manifest).ok()?))
+        })
+}
+
+/// Copies the `previous` revision into `target`, then removes the files `manifest` doesn't list, downloads
+/// the ones that changed and applies the modes of the manifest. Returns the number of files downloaded.
+async fn download_changes(files: &FilesApi<'_>, previous_path: &Path, previous: &WorkspaceManifest, manifest: &WorkspaceManifest, target: &Path) -> Result<usize, Error> {
+    copy_dir(previous_path, target)?;
+    for name in previous.files.keys().filter(|name| !manifest.files.contains_key(*name)) {
+        if is_valid_name(name) {
+            fs::remove_file(target.join(name))?;
+        }
+    }
+    let mut count = 0;
+    for (name, hash) in &manifest.files {
+        if !is_valid_name(name) {
+            bail!("Invalid workspace file name: {}", name);
+        }
+        let mode = manifest.modes.get(name);
+        if previous.files.get(name) == Some(hash) {
+            // Copies keep the mode of the previous revision
+            if let Some(mode) = mode.filter(|mode| previous.modes.get(name) != Some(*mode)) {
+                set_file_mode(&target.join(name), *mode)?;
+            }
+            continue;
+        }
+        let mut url = Url::parse(&format!("{}/files/workspace", files.server))?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("Invalid server URL: {}", files.server))?
+            .extend(name.split('/'));
+        let response = send_with_retry(files.request(Method::GET, url))
+            .await?;
+        if !response.status().is_success() {
+            bail!("Failed to download workspace file {}: {}", name, response.status());
+        }
+        let content = response.bytes().await?;
+        // The revision may have changed since the manifest was built
+        if format!("{:x}", Blake2b512::digest(&content)) != *hash {
+            bail!("Workspace file {} doesn't match the manifest", name);
+        }
+        let path = target.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, &content)?;
+        if let Some(mode) = mode {
+            set_file_mode(&path, *mode)?;
+        }
+        debug!("Downloaded workspace file {}", name);
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// A job's copy of the workspace, locked while the job runs and removed once dropped.
+struct JobWorkspace {
+    path: PathBuf,
+    lock: Option<File>,
+}
+
+impl JobWorkspace {
+    fn create(path: PathBuf) -> Result<Self, Error> {
+        let lock_file = sibling(&path, "lock");
+        let lock = File::create(&lock_file)
+            .map_err(|e| anyhow!("Failed to create lock file {}: {}", lock_file.display(), e))?;
+        lock.try_lock_exclusive()
+            .map_err(|e| anyhow!("Workspace of the job is in use, {}: {}", lock_file.display(), e))?;
+        // Left over by an earlier runner of the job
+        remove_dir(&path)?;
+        Ok(Self { path, lock: Some(lock) })
+    }
+}
+
+impl Drop for JobWorkspace {
+    fn drop(&mut self) {
+        if let Err(e) = remove_dir(&self.path) {
+            error!("Failed to remove job workspace: {}", e);
+        }
+        // Released before the lock file is removed, which Windows refuses for open files
+        drop(self.lock.take());
+        let _ = fs::remove_file(sibling(&self.path, "lock"));
+    }
+}
+
+/// Removes the revisions other than `current`, with their manifests. No runner uses them outside the lock,
+/// as jobs run in copies.
+fn prune_revisions(revisions: &Path, current: &Path) {
+    let entries = match fs::read_dir(revisions) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Failed to list workspace revisions: {}", e);
+            return;
+        }
+    };
+    let current_manifest = sibling(current, "manifest");
+    for path in entries.filter_map(Result::ok).map(|entry| entry.path()).filter(|path| path != current && *path != current_manifest) {
+        let removed = match path.is_dir() {
+            true => remove_dir(&path),
+            false => fs::remove_file(&path).map_err(Error::from),
+        };
+        match removed {
+            Ok(()) => debug!("Removed workspace {:?}", path),
+            Err(e) => error!("Failed to remove workspace: {}", e),
+        }
+    }
+}
+
+/// Removes the copies of jobs whose runner is gone, as their lock is no longer held.
+fn prune_jobs(jobs: &Path) {
+    let entries = match fs::read_dir(jobs) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Failed to list job workspaces: {}", e);
+            return;
+        }
+    };
+    for lock_file in entries.filter_map(Result::ok).map(|entry| entry.path()).filter(|path| path.extension().is_some_and(|ext| ext == "lock")) {
+        let acquired = File::open(&lock_file).is_ok_and(|lock| lock.try_lock_exclusive().is_ok());
+        if acquired {
+            info!("Removing workspace left over by job {}", lock_file.file_stem().unwrap_or_default().to_string_lossy());
+            drop(JobWorkspace { path: lock_file.with_extension(""), lock: None });
+        }
+    }
+}
+
+/// Revisions are commit hashes or digests, anything else is kept from escaping the revisions directory.
+fn dir_name(revision: &str) -> String {
+    revision.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+/// File next to `path`, e.g. `<job_id>.lock`. Built from the path as is, so non UTF-8 paths and Windows
+/// paths with a trailing separator work too.
+fn sibling(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.components().collect::<PathBuf>().into_os_string();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+fn remove_dir(path: &Path) -> Result<(), Error> {
+    match fs::remove_dir_all(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(anyhow!("Failed to remove {}: {}", path.display(), e)),
+        _ => Ok(()),
+    }
+}
+
+/// Copies the files of `from` into `to`, following symlinks.
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.path().is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
@@ -1,13 +1,20 @@
 use clap::Parser;
-use tracing::{info, error};
+use tracing::{info, error, info_span, Instrument};
 use serde_json::{Value};
 use std::fs;
-use stroem_common::{init_tracing};
+use stroem_common::{init_tracing, resolve_token, JobSpec, ResumeState, Secret, WORKSPACE_ENV};
 use std::path::{PathBuf};
 use std::sync::{Arc};
-use stroem_common::log_collector::LogCollectorServer;
+use stroem_common::log_collector::{LogCollectorServer, LogLimits, DEFAULT_MAX_LOG_ENTRY_SIZE, MAX_LOG_ENTRY_SIZE_ENV, STREAM_LOGS_ENV};
 use stroem_common::workspace_client::WorkspaceClient;
+use stroem_common::artifacts::ArtifactClient;
+use stroem_common::step_cache::StepCacheClient;
+use stroem_common::approval::ApprovalClient;
+use stroem_common::wait::{Suspended, WaitClient, EXIT_SUSPENDED};
 use stroem_common::runner::Runner;
+use stroem_common::step_hook::{metrics_hooks, PUSHGATEWAY_ENV, STATSD_ENV, STATSD_PREFIX_ENV};
+use stroem_common::tls::{TlsConfig, CA_CERT_ENV, CLIENT_CERT_ENV, CLIENT_KEY_ENV, TLS_INSECURE_ENV};
+use stroem_common::telemetry::{self, Telemetry, OTLP_ENDPOINT_ENV, TRACEPARENT_ENV};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -24,12 +31,54 @@ struct Args {
     action: Option<String>,
     #[arg(long)]
     input: Option<String>,
+    /// Reads the steps taken over from the job this one resumes from stdin, as JSON. They can outgrow the
+    /// limits on command line arguments.
+    #[arg(long, conflicts_with = "action")]
+    resume_stdin: bool,
     #[arg(long, required = true)]
     worker_id: String,
-    #[arg(short, long, required = true)]
-    token: String,
-    #[arg(long, default_value = "/tmp/workspace")]
+    #[arg(short, long, env = "STROEM_WORKER_TOKEN", hide_env_values = true)]
+    token: Option<Secret>,
+    /// File containing the worker token
+    #[arg(long, conflicts_with = "token")]
+    token_file: Option<PathBuf>,
+    #[arg(long, env = WORKSPACE_ENV, default_value = "/tmp/workspace")]
     workspace: String,
+    /// Project whose workspace the job runs in, the server's default project without it
+    #[arg(long)]
+    project: Option<String>,
+    /// StatsD address (host:port) to send per-step metrics to
+    #[arg(long, env = STATSD_ENV)]
+    statsd: Option<String>,
+    #[arg(long, env = STATSD_PREFIX_ENV, default_value = "stroem")]
+    statsd_prefix: String,
+    /// Prometheus Pushgateway URL to push per-step metrics to
+    #[arg(long, env = PUSHGATEWAY_ENV)]
+    pushgateway: Option<String>,
+    /// PEM bundle of extra CA certificates to trust for the server
+    #[arg(long, env = CA_CERT_ENV)]
+    ca_cert: Option<PathBuf>,
+    /// PEM client certificate for mutual TLS, may include the key
+    #[arg(long, env = CLIENT_CERT_ENV)]
+    client_cert: Option<PathBuf>,
+    /// PEM private key of the client certificate
+    #[arg(long, env = CLIENT_KEY_ENV, requires = "client_cert")]
+    client_key: Option<PathBuf>,
+    /// Skip verifying the server certificate, for air-gapped labs only
+    #[arg(long, env = TLS_INSECURE_ENV)]
+    tls_insecure: bool,
+    /// Log messages longer than this many bytes are truncated
+    #[arg(long, env = MAX_LOG_ENTRY_SIZE_ENV, default_value_t = DEFAULT_MAX_LOG_ENTRY_SIZE)]
+    max_log_entry_size: usize,
+    /// Stream logs to the server as NDJSON instead of posting them in batches
+    #[arg(long, env = STREAM_LOGS_ENV)]
+    stream_logs: bool,
+    /// OTLP/HTTP base URL of the collector to export trace spans to
+    #[arg(long, env = OTLP_ENDPOINT_ENV)]
+    otlp_endpoint: Option<String>,
+    /// Trace context of the worker's job span, which the runner's spans join
+    #[arg(long, env = TRACEPARENT_ENV)]
+    traceparent: Option<String>,
 }
 
 
@@ -38,7 +87,16 @@ struct Args {
 async fn main() {
     let args = Args::parse();
 
-    init_tracing(args.verbose);
+    let telemetry = Telemetry::new(args.otlp_endpoint.as_deref(), "stroem-runner").unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    init_tracing(args.verbose, &telemetry);
+    let token = resolve_token(args.token, args.token_file.as_deref()).unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(1);
+    });
+    let token = token.expose().to_string();
     /*
     let log_level = if args.verbose { tracing::Level::TRACE } else { tracing::Level::INFO };
     tracing_subscriber::fmt()
@@ -52,14 +110,39 @@ async fn main() {
 
     info!("Runner started for job_id: {}, worker_id: {}", args.job_id, args.worker_id);
 
+    let spec = JobSpec::new(args.task, args.action).unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(1);
+    });
+
     let input: Option<Value> = args.input.as_ref()
         .map(|s| serde_json::from_str(s).unwrap_or_else(|e| {
             error!("Failed to parse input: {}", e);
             std::process::exit(1);
         }));
 
+    let resume: Option<ResumeState> = args.resume_stdin
+        .then(|| serde_json::from_reader(std::io::stdin().lock()).unwrap_or_else(|e| {
+            error!("Failed to parse resume state: {}", e);
+            std::process::exit(1);
+        }));
+
+    let client = TlsConfig {
+        ca_cert: args.ca_cert,
+        client_cert: args.client_cert,
+        client_key: args.client_key,
+        insecure: args.tls_insecure,
+    }.client().unwrap_or_else(|e| {
+        error!("Failed to set up HTTP client: {}", e);
+        std::process::exit(1);
+    });
+
     let mut workspace = WorkspaceClient::new(PathBuf::from(&workspace_path)).await;
-    let revision = workspace.sync(&args.server, &args.token).await.unwrap_or_else(|e| {
+    if let Some(project) = &args.project {
+        workspace = workspace.with_project(project);
+    }
+    let mut workspace = workspace.with_job(&args.job_id);
+    let revision = workspace.sync(&client, &args.server, &token).await.unwrap_or_else(|e| {
         error!("Failed to get workspace: {}", e);
         std::process::exit(1);
     });
@@ -68,20 +151,59 @@ async fn main() {
         std::process::exit(1);
     };
 
+    let artifacts = ArtifactClient::new(client.clone(), args.server.clone(), args.job_id.clone(), args.worker_id.clone(), token.clone());
+    let cache = StepCacheClient::new(client.clone(), args.server.clone(), args.job_id.clone(), args.worker_id.clone(), token.clone());
+    let approvals = ApprovalClient::new(client.clone(), args.server.clone(), args.job_id.clone(), args.worker_id.clone(), token.clone());
+    let waits = WaitClient::new(client.clone(), args.server.clone(), args.job_id.clone(), args.worker_id.clone(), token.clone());
     let log_collector = Arc::new(LogCollectorServer::new(
+        client,
         args.server.clone(),
         args.job_id.clone(),
         args.worker_id.clone(),
-        args.token.clone(),
+        token.clone(),
         None,
         Some(10)
-    ));
+    ).with_limits(LogLimits {
+        max_entry_size: args.max_log_entry_size,
+    }).with_streaming(args.stream_logs));
+
+    let hooks = metrics_hooks(args.statsd.as_deref(), &args.statsd_prefix, args.pushgateway.as_deref()).await.unwrap_or_else(|e| {
+        error!("Failed to set up metrics: {}", e);
+        vec![]
+    });
 
-    let mut runner = Runner::new(Some(args.server), Some(args.job_id), Some(args.worker_id), args.task, args.action, input, workspace, Some(revision), log_collector);
-    let (success, output) = runner.execute().await.unwrap_or_else(|e| {
-        error!("Execution failed: {}", e);
+    let span = info_span!("runner", job_id = %args.job_id, worker_id = %args.worker_id);
+    if let Some(traceparent) = &args.traceparent {
+        telemetry::set_parent(&span, traceparent);
+    }
+    let mut runner = Runner::new(Some(args.server), Some(args.job_id), Some(args.worker_id), spec, input, workspace, log_collector);
+    runner.with_revision(revision);
+    for hook in hooks {
+        runner.add_hook(hook);
+    }
+    runner.with_artifacts(artifacts);
+    runner.with_cache(cache);
+    runner.with_approvals(approvals);
+    runner.with_waits(waits);
+    if let Some(resume) = resume {
+        runner.resume_from(resume);
+    }
+    let mut suspended = false;
+    let (success, output) = runner.execute().instrument(span).await.unwrap_or_else(|e| {
+        suspended = e.is::<Suspended>();
+        if !suspended {
+            error!("Execution failed: {}", e);
+        }
         (false, None)
     });
+    telemetry.shutdown();
+    // Removes the job's copy of the workspace, exiting skips destructors
+    drop(runner);
+
+    if suspended {
+        info!("Job suspended, it continues once its wait is over");
+        std::process::exit(EXIT_SUSPENDED);
+    }
 
     if !success {
         std::process::exit(1);
@@ -0,0 +1 @@
+ALTER TABLE job_step ADD COLUMN IF NOT EXISTS attempts INTEGER NOT NULL DEFAULT 1;
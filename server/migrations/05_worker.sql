@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS worker (
+    worker_id TEXT PRIMARY KEY,
+    labels JSONB,
+    capacity INTEGER NOT NULL,
+    running INTEGER NOT NULL DEFAULT 0,
+    registered_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    last_heartbeat TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_worker_last_heartbeat ON worker (last_heartbeat);
@@ -0,0 +1,3 @@
+ALTER TABLE job ADD COLUMN IF NOT EXISTS priority INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX IF NOT EXISTS idx_job_queued_priority ON job (priority DESC, queued ASC) WHERE status = 'queued';
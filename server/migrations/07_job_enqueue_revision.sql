@@ -0,0 +1 @@
+ALTER TABLE job ADD COLUMN IF NOT EXISTS enqueue_revision TEXT;
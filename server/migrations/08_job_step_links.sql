@@ -0,0 +1 @@
+ALTER TABLE job_step ADD COLUMN IF NOT EXISTS links JSONB;
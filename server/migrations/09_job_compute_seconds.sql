@@ -0,0 +1,6 @@
+ALTER TABLE job ADD COLUMN IF NOT EXISTS compute_seconds DOUBLE PRECISION;
+
+UPDATE job SET compute_seconds = EXTRACT(EPOCH FROM end_datetime - start_datetime)
+WHERE compute_seconds IS NULL AND end_datetime IS NOT NULL AND start_datetime IS NOT NULL;
+
+CREATE INDEX IF NOT EXISTS idx_job_end_datetime ON job (end_datetime);
@@ -0,0 +1,6 @@
+ALTER TABLE "user" ADD COLUMN IF NOT EXISTS role TEXT NOT NULL DEFAULT 'operator';
+
+-- The first user (normally the configured initial user) administers existing installations
+UPDATE "user" SET role = 'admin'
+WHERE user_id = (SELECT user_id FROM "user" ORDER BY created_at ASC LIMIT 1)
+  AND NOT EXISTS (SELECT 1 FROM "user" WHERE role = 'admin');
@@ -0,0 +1,5 @@
+ALTER TABLE job ADD COLUMN IF NOT EXISTS rate_limit_key TEXT;
+ALTER TABLE job ADD COLUMN IF NOT EXISTS rate_limit_max INTEGER;
+ALTER TABLE job ADD COLUMN IF NOT EXISTS rate_limit_per_secs DOUBLE PRECISION;
+
+CREATE INDEX IF NOT EXISTS idx_job_rate_limit ON job (rate_limit_key, picked) WHERE rate_limit_key IS NOT NULL;
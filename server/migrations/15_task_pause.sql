@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS task_pause (
+    task_id TEXT PRIMARY KEY,
+    paused_by TEXT,
+    paused_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
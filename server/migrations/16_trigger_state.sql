@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS trigger_state (
+    trigger_name TEXT PRIMARY KEY,
+    last_run TIMESTAMP WITH TIME ZONE NOT NULL
+);
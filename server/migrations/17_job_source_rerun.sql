@@ -0,0 +1,2 @@
+ALTER TABLE job DROP CONSTRAINT IF EXISTS job_source_type_check;
+ALTER TABLE job ADD CONSTRAINT job_source_type_check CHECK (source_type IN ('trigger', 'user', 'webhook', 'rerun'));
@@ -0,0 +1,5 @@
+-- Outputs of the steps a resumed job takes over from the failed job it resumes
+ALTER TABLE job ADD COLUMN IF NOT EXISTS resume JSONB;
+
+ALTER TABLE job DROP CONSTRAINT IF EXISTS job_source_type_check;
+ALTER TABLE job ADD CONSTRAINT job_source_type_check CHECK (source_type IN ('trigger', 'user', 'webhook', 'rerun', 'resume'));
@@ -0,0 +1,2 @@
+-- W3C trace context the job was enqueued in, handed to the worker so its spans join the same trace
+ALTER TABLE job ADD COLUMN IF NOT EXISTS traceparent TEXT;
@@ -0,0 +1,14 @@
+-- Soft-deleted jobs are hidden from the API; retention prunes them like any other job
+ALTER TABLE job ADD COLUMN IF NOT EXISTS deleted TIMESTAMP WITH TIME ZONE;
+ALTER TABLE job ADD COLUMN IF NOT EXISTS deleted_by TEXT;
+
+CREATE TABLE IF NOT EXISTS audit_log (
+    audit_id BIGSERIAL PRIMARY KEY,
+    time TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    actor TEXT NOT NULL,
+    action TEXT NOT NULL,
+    target TEXT NOT NULL,
+    details JSONB
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_time ON audit_log (time DESC);
@@ -0,0 +1,2 @@
+-- Why a job failed without running to completion, e.g. `worker_shutdown`
+ALTER TABLE job ADD COLUMN IF NOT EXISTS failure_reason TEXT;
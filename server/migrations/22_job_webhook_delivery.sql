@@ -0,0 +1,16 @@
+-- Deliveries of the webhooks tasks declare, one row per event and URL, updated after every attempt
+CREATE TABLE IF NOT EXISTS job_webhook_delivery (
+    delivery_id BIGSERIAL PRIMARY KEY,
+    job_id UUID NOT NULL,
+    event TEXT NOT NULL,
+    url TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    response_status INTEGER,
+    error TEXT,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (job_id) REFERENCES job (job_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_job_webhook_delivery_job_id ON job_webhook_delivery (job_id);
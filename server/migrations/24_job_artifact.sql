@@ -0,0 +1,9 @@
+-- Files the steps of a job uploaded, kept in the artifact storage under `<job_id>/<step_name>/<name>`
+CREATE TABLE IF NOT EXISTS job_artifact (
+    job_id UUID NOT NULL REFERENCES job (job_id) ON DELETE CASCADE,
+    step_name TEXT NOT NULL,
+    name TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (job_id, step_name, name)
+);
@@ -0,0 +1,11 @@
+-- Outputs of successful steps with `cache: true`, by a hash of their rendered action, input and workspace revision.
+-- Entries go with the job that produced them.
+CREATE TABLE IF NOT EXISTS step_cache (
+    cache_key TEXT PRIMARY KEY,
+    output JSONB,
+    job_id UUID NOT NULL REFERENCES job (job_id) ON DELETE CASCADE,
+    step_name TEXT NOT NULL,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_step_cache_job_id ON step_cache (job_id);
@@ -0,0 +1,8 @@
+-- Job events too large for a NOTIFY payload, the notification only carries their id
+CREATE TABLE IF NOT EXISTS job_event (
+    id BIGSERIAL PRIMARY KEY,
+    payload TEXT NOT NULL,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_job_event_created ON job_event (created);
@@ -0,0 +1,18 @@
+-- Alerts raised by the alert rules, one row from firing until resolved. A rule fires at most one open alert per task.
+CREATE TABLE IF NOT EXISTS alert (
+    alert_id BIGSERIAL PRIMARY KEY,
+    rule TEXT NOT NULL,
+    task TEXT,
+    status TEXT NOT NULL DEFAULT 'firing' CHECK (status IN ('firing', 'acknowledged', 'resolved')),
+    message TEXT NOT NULL,
+    value DOUBLE PRECISION NOT NULL,
+    threshold DOUBLE PRECISION NOT NULL,
+    started TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    acknowledged_at TIMESTAMP WITH TIME ZONE,
+    acknowledged_by TEXT,
+    resolved_at TIMESTAMP WITH TIME ZONE,
+    resolved_by TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_alert_open ON alert (rule, task) WHERE status <> 'resolved';
@@ -0,0 +1,15 @@
+-- Jobs that took longer than the SLA of their task allows, recorded once per job and limit as soon as they do
+CREATE TABLE IF NOT EXISTS sla_breach (
+    breach_id BIGSERIAL PRIMARY KEY,
+    job_id UUID NOT NULL,
+    project_id TEXT NOT NULL,
+    task TEXT NOT NULL,
+    kind TEXT NOT NULL CHECK (kind IN ('max_duration', 'deadline')),
+    limit_secs DOUBLE PRECISION NOT NULL,
+    elapsed_secs DOUBLE PRECISION NOT NULL,
+    detected TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    UNIQUE (job_id, kind),
+    FOREIGN KEY (job_id) REFERENCES job (job_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_sla_breach_detected ON sla_breach (detected);
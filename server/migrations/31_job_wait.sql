@@ -0,0 +1,29 @@
+-- Set while a job waits in a `wait` or `wait_for_event` step without holding a worker
+ALTER TABLE job ADD COLUMN IF NOT EXISTS suspended TIMESTAMP WITH TIME ZONE;
+
+-- What the wait steps of a job wait for, one per step
+CREATE TABLE IF NOT EXISTS job_wait (
+    job_id UUID NOT NULL,
+    step_name TEXT NOT NULL,
+    -- Event the step waits for, unset for a `wait`
+    event_key TEXT,
+    -- When a `wait` is over or a `wait_for_event` times out, unset for no timeout
+    resume_at TIMESTAMP WITH TIME ZONE,
+    status TEXT NOT NULL DEFAULT 'waiting' CHECK (status IN ('waiting', 'elapsed', 'received', 'timed_out')),
+    -- Body of the received event
+    payload JSONB,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    resolved TIMESTAMP WITH TIME ZONE,
+    PRIMARY KEY (job_id, step_name),
+    FOREIGN KEY (job_id) REFERENCES job (job_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_job_wait_event_key ON job_wait (event_key) WHERE status = 'waiting';
+CREATE INDEX IF NOT EXISTS idx_job_wait_resume_at ON job_wait (resume_at) WHERE status = 'waiting';
+
+-- Latest event posted per key, so a step that starts waiting after its callback came still gets it
+CREATE TABLE IF NOT EXISTS external_event (
+    event_key TEXT PRIMARY KEY,
+    payload JSONB,
+    received TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
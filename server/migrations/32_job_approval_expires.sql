@@ -0,0 +1,4 @@
+-- When a pending approval times out, its step then fails; approval steps suspend the job meanwhile
+ALTER TABLE job_approval ADD COLUMN IF NOT EXISTS expires TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX IF NOT EXISTS idx_job_approval_expires ON job_approval (expires) WHERE status = 'pending';
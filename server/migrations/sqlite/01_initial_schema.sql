@@ -0,0 +1,164 @@
+-- Schema of the Postgres migrations up to 22_job_webhook_delivery, for SQLite.
+-- Timestamps are RFC 3339 text in UTC, UUIDs 16 byte blobs and JSON text.
+CREATE TABLE IF NOT EXISTS job (
+  job_id BLOB PRIMARY KEY,
+  task_name TEXT,
+  action_name TEXT,
+  input TEXT,
+  revision TEXT,
+  worker_id TEXT,
+  queued TEXT NOT NULL,
+  picked TEXT,
+  start_datetime TEXT,
+  end_datetime TEXT,
+  output TEXT,
+  success BOOLEAN,
+  status TEXT NOT NULL DEFAULT 'queued' CHECK (status IN ('queued', 'running', 'completed', 'failed')),
+  source_type TEXT NOT NULL CHECK (source_type IN ('trigger', 'user', 'webhook', 'rerun', 'resume')),
+  source_id TEXT,
+  priority INTEGER NOT NULL DEFAULT 0,
+  enqueue_revision TEXT,
+  compute_seconds REAL,
+  rate_limit_key TEXT,
+  rate_limit_max INTEGER,
+  rate_limit_per_secs REAL,
+  -- Outputs of the steps a resumed job takes over from the failed job it resumes
+  resume TEXT,
+  -- W3C trace context the job was enqueued in, handed to the worker so its spans join the same trace
+  traceparent TEXT,
+  -- Soft-deleted jobs are hidden from the API; retention prunes them like any other job
+  deleted TEXT,
+  deleted_by TEXT,
+  -- Why a job failed without running to completion, e.g. `worker_shutdown`
+  failure_reason TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_job_status ON job (status);
+CREATE INDEX IF NOT EXISTS idx_job_worker_id ON job (worker_id);
+CREATE INDEX IF NOT EXISTS idx_job_queued ON job (queued);
+CREATE INDEX IF NOT EXISTS idx_job_queued_priority ON job (priority DESC, queued ASC) WHERE status = 'queued';
+CREATE INDEX IF NOT EXISTS idx_job_end_datetime ON job (end_datetime);
+CREATE INDEX IF NOT EXISTS idx_job_rate_limit ON job (rate_limit_key, picked) WHERE rate_limit_key IS NOT NULL;
+
+CREATE TABLE IF NOT EXISTS job_step (
+  job_id BLOB NOT NULL,
+  step_name TEXT NOT NULL,
+  input TEXT,
+  output TEXT,
+  success BOOLEAN,
+  start_datetime TEXT NOT NULL,
+  end_datetime TEXT,
+  attempts INTEGER NOT NULL DEFAULT 1,
+  status TEXT,
+  links TEXT,
+  environment TEXT,
+  PRIMARY KEY (job_id, step_name),
+  FOREIGN KEY (job_id) REFERENCES job (job_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_job_steps_job_id ON job_step (job_id);
+
+CREATE TABLE IF NOT EXISTS "user" (
+  user_id BLOB PRIMARY KEY,
+  name TEXT,
+  email TEXT NOT NULL UNIQUE,
+  password_hash TEXT,
+  role TEXT NOT NULL DEFAULT 'operator',
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+  updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'))
+);
+
+CREATE TABLE IF NOT EXISTS user_auth_link (
+  user_id BLOB NOT NULL,
+  auth_id TEXT NOT NULL,
+  identifier TEXT,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+  PRIMARY KEY (user_id, auth_id),
+  FOREIGN KEY (user_id) REFERENCES "user" (user_id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS refresh_token (
+  user_id BLOB NOT NULL,
+  auth_id TEXT NOT NULL,
+  token_hash TEXT NOT NULL UNIQUE,
+  expires_at TEXT NOT NULL,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+  revoked_at TEXT,
+  PRIMARY KEY (user_id, auth_id),
+  FOREIGN KEY (user_id) REFERENCES "user" (user_id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS api_token (
+  token_id BLOB PRIMARY KEY,
+  user_id BLOB NOT NULL,
+  name TEXT NOT NULL,
+  token_hash TEXT NOT NULL UNIQUE,
+  scope TEXT NOT NULL CHECK (scope IN ('read', 'run')),
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+  expires_at TEXT,
+  last_used_at TEXT,
+  revoked_at TEXT,
+  FOREIGN KEY (user_id) REFERENCES "user" (user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_api_token_user_id ON api_token (user_id);
+
+CREATE TABLE IF NOT EXISTS worker (
+  worker_id TEXT PRIMARY KEY,
+  labels TEXT,
+  capacity INTEGER NOT NULL,
+  running INTEGER NOT NULL DEFAULT 0,
+  registered_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+  last_heartbeat TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_worker_last_heartbeat ON worker (last_heartbeat);
+
+CREATE TABLE IF NOT EXISTS worker_credential (
+  credential_id BLOB PRIMARY KEY,
+  name TEXT NOT NULL,
+  token_hash TEXT NOT NULL UNIQUE,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+  expires_at TEXT,
+  last_used_at TEXT,
+  revoked_at TEXT
+);
+
+CREATE TABLE IF NOT EXISTS task_pause (
+  task_id TEXT PRIMARY KEY,
+  paused_by TEXT,
+  paused_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'))
+);
+
+CREATE TABLE IF NOT EXISTS trigger_state (
+  trigger_name TEXT PRIMARY KEY,
+  last_run TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS audit_log (
+  audit_id INTEGER PRIMARY KEY AUTOINCREMENT,
+  time TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+  actor TEXT NOT NULL,
+  action TEXT NOT NULL,
+  target TEXT NOT NULL,
+  details TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_time ON audit_log (time DESC);
+
+-- Deliveries of the webhooks tasks declare, one row per event and URL, updated after every attempt
+CREATE TABLE IF NOT EXISTS job_webhook_delivery (
+  delivery_id INTEGER PRIMARY KEY AUTOINCREMENT,
+  job_id BLOB NOT NULL,
+  event TEXT NOT NULL,
+  url TEXT NOT NULL,
+  status TEXT NOT NULL DEFAULT 'pending',
+  attempts INTEGER NOT NULL DEFAULT 0,
+  response_status INTEGER,
+  error TEXT,
+  created TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+  updated TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+  FOREIGN KEY (job_id) REFERENCES job (job_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_job_webhook_delivery_job_id ON job_webhook_delivery (job_id);
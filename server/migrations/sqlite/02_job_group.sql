@@ -0,0 +1,11 @@
+-- Groups related jobs, e.g. a fan-out batch or a job and its re-runs, so they can be followed as one run
+CREATE TABLE IF NOT EXISTS job_group (
+  group_id BLOB PRIMARY KEY,
+  name TEXT NOT NULL,
+  source TEXT NOT NULL,
+  created TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'))
+);
+
+ALTER TABLE job ADD COLUMN group_id BLOB REFERENCES job_group (group_id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_job_group_id ON job (group_id);
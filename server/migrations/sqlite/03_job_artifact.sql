@@ -0,0 +1,10 @@
+-- Files the steps of a job uploaded, kept in the artifact storage under `<job_id>/<step_name>/<name>`
+CREATE TABLE IF NOT EXISTS job_artifact (
+  job_id BLOB NOT NULL,
+  step_name TEXT NOT NULL,
+  name TEXT NOT NULL,
+  size INTEGER NOT NULL,
+  created TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+  PRIMARY KEY (job_id, step_name, name),
+  FOREIGN KEY (job_id) REFERENCES job (job_id) ON DELETE CASCADE
+);
@@ -0,0 +1,12 @@
+-- Outputs of successful steps with `cache: true`, by a hash of their rendered action, input and workspace revision.
+-- Entries go with the job that produced them.
+CREATE TABLE IF NOT EXISTS step_cache (
+  cache_key TEXT PRIMARY KEY,
+  output TEXT,
+  job_id BLOB NOT NULL,
+  step_name TEXT NOT NULL,
+  created TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+  FOREIGN KEY (job_id) REFERENCES job (job_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_step_cache_job_id ON step_cache (job_id);
@@ -0,0 +1,4 @@
+-- Project whose workspace the job runs in, jobs from before projects ran in the default one
+ALTER TABLE job ADD COLUMN project_id TEXT NOT NULL DEFAULT 'default';
+
+CREATE INDEX IF NOT EXISTS idx_job_project_id ON job (project_id);
@@ -0,0 +1,18 @@
+-- Alerts raised by the alert rules, one row from firing until resolved. A rule fires at most one open alert per task.
+CREATE TABLE IF NOT EXISTS alert (
+  alert_id INTEGER PRIMARY KEY AUTOINCREMENT,
+  rule TEXT NOT NULL,
+  task TEXT,
+  status TEXT NOT NULL DEFAULT 'firing' CHECK (status IN ('firing', 'acknowledged', 'resolved')),
+  message TEXT NOT NULL,
+  value REAL NOT NULL,
+  threshold REAL NOT NULL,
+  started TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+  updated TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+  acknowledged_at TEXT,
+  acknowledged_by TEXT,
+  resolved_at TEXT,
+  resolved_by TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_alert_open ON alert (rule, task) WHERE status <> 'resolved';
@@ -0,0 +1,15 @@
+-- Jobs that took longer than the SLA of their task allows, recorded once per job and limit as soon as they do
+CREATE TABLE IF NOT EXISTS sla_breach (
+  breach_id INTEGER PRIMARY KEY AUTOINCREMENT,
+  job_id BLOB NOT NULL,
+  project_id TEXT NOT NULL,
+  task TEXT NOT NULL,
+  kind TEXT NOT NULL CHECK (kind IN ('max_duration', 'deadline')),
+  limit_secs REAL NOT NULL,
+  elapsed_secs REAL NOT NULL,
+  detected TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+  UNIQUE (job_id, kind),
+  FOREIGN KEY (job_id) REFERENCES job (job_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_sla_breach_detected ON sla_breach (detected);
@@ -0,0 +1,17 @@
+-- Sign-offs that approval steps wait for, one per step of a job
+CREATE TABLE IF NOT EXISTS job_approval (
+  job_id BLOB NOT NULL,
+  step_name TEXT NOT NULL,
+  -- JSON array of user emails or `role:<role>`, anyone who may run the task without it
+  approvers TEXT,
+  message TEXT,
+  status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'approved', 'rejected')),
+  requested TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+  decided_at TEXT,
+  decided_by TEXT,
+  comment TEXT,
+  PRIMARY KEY (job_id, step_name),
+  FOREIGN KEY (job_id) REFERENCES job (job_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_job_approval_pending ON job_approval (requested) WHERE status = 'pending';
@@ -0,0 +1,2 @@
+-- Who posted the event; a step that starts waiting for it later only takes it if they may run the step's task
+ALTER TABLE external_event ADD COLUMN posted_by BLOB;
@@ -0,0 +1,6 @@
+-- Project of the task an alert is about, tasks of the same name in different projects alert on their own
+ALTER TABLE alert ADD COLUMN project_id TEXT;
+UPDATE alert SET project_id = 'default' WHERE task IS NOT NULL;
+
+DROP INDEX IF EXISTS idx_alert_open;
+CREATE INDEX IF NOT EXISTS idx_alert_open ON alert (rule, project_id, task) WHERE status <> 'resolved';
@@ -0,0 +1,12 @@
+-- Log lines of jobs for the search, with messages cut short; the log storage keeps the full logs.
+-- Lines logged before this table existed aren't searchable. Lines go with their job.
+CREATE TABLE IF NOT EXISTS job_log_line (
+  job_id BLOB NOT NULL,
+  step_name TEXT,
+  timestamp TEXT NOT NULL,
+  message TEXT NOT NULL,
+  FOREIGN KEY (job_id) REFERENCES job (job_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_job_log_line_job_id ON job_log_line (job_id);
+CREATE INDEX IF NOT EXISTS idx_job_log_line_timestamp ON job_log_line (timestamp);
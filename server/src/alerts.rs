@@ -0,0 +1,249 @@
+// workflow-server/src/alerts.rs
+//! Checks the configured alert rules periodically. A breached rule fires an alert, kept up to date while the
+//! breach lasts and resolved once it's over. Alerts can be acknowledged or resolved by hand through the API;
+//! a rule still breached after being resolved by hand fires a new alert.
+use std::collections::HashMap;
+use std::time::Duration;
+use anyhow::Error;
+use chrono::Utc;
+use tokio::sync::watch;
+use tokio::time;
+use tracing::{error, info, warn};
+use stroem_common::DEFAULT_PROJECT;
+use crate::repository::{AlertRepository, JobRepository, TaskHealth, WorkerRepository};
+use crate::server_config::{AlertMetric, AlertRule, AlertsConfig};
+
+/// A rule over its threshold, for the whole server or one task.
+struct Breach<'a> {
+    rule: &'a str,
+    project: Option<String>,
+    task: Option<String>,
+    value: f64,
+    threshold: f64,
+    message: String,
+}
+
+impl TaskHealth {
+    /// Name of the task in the rules and messages, prefixed by the project unless it's the default one.
+    fn scoped_task(&self) -> String {
+        match self.project_id.as_str() {
+            DEFAULT_PROJECT => self.task.clone(),
+            project => format!("{}/{}", project, self.task),
+        }
+    }
+}
+
+impl AlertRule {
+    /// Threshold of the rule for a task, `None` if the task is left out.
+    fn task_threshold(&self, task: &str) -> Option<f64> {
+        match self.tasks.get(task) {
+            Some(threshold) => *threshold,
+            None => Some(self.threshold),
+        }
+    }
+}
+
+pub struct AlertEvaluator {
+    job_repository: JobRepository,
+    worker_repository: WorkerRepository,
+    alert_repository: AlertRepository,
+    config: AlertsConfig,
+    worker_stale_after: Duration,
+    task: Option<tokio::task::JoinHandle<()>>,
+    cancel_tx: watch::Sender<bool>,
+}
+
+impl AlertEvaluator {
+    pub fn new(job_repository: JobRepository, worker_repository: WorkerRepository, alert_repository: AlertRepository, config: AlertsConfig, worker_stale_after: Duration) -> Self {
+        let (cancel_tx, _) = watch::channel(false);
+        Self {
+            job_repository,
+            worker_repository,
+            alert_repository,
+            config,
+            worker_stale_after,
+            task: None,
+            cancel_tx,
+        }
+    }
+
+    pub async fn run(&mut self) {
+        if !self.config.enabled {
+            info!("Alert rules disabled");
+            return;
+        }
+        if self.task.is_some() {
+            info!("Alert evaluator already running");
+            return;
+        }
+
+        let mut cancel_rx = self.cancel_tx.subscribe();
+        let evaluation = Evaluation {
+            job_repository: self.job_repository.clone(),
+            worker_repository: self.worker_repository.clone(),
+            alert_repository: self.alert_repository.clone(),
+            config: self.config.clone(),
+            worker_stale_after: self.worker_stale_after,
+        };
+
+        let task = tokio::spawn(async move {
+            let mut interval = time::interval(evaluation.config.check_interval);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {},
+                    _ = cancel_rx.changed() => {
+                        if *cancel_rx.borrow() {
+                            info!("Alert evaluator stopping due to cancellation signal");
+                            break;
+                        }
+                    }
+                }
+                if let Err(e) = evaluation.evaluate().await {
+                    error!("Failed to evaluate alert rules: {}", e);
+                }
+            }
+        });
+
+        self.task = Some(task);
+        info!("Alert evaluator started with {} rules", self.config.rules.len());
+    }
+
+    pub async fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            if let Err(e) = self.cancel_tx.send(true) {
+                error!("Failed to send cancellation signal: {}", e);
+            }
+            let _ = task.await;
+            info!("Alert evaluator stopped");
+        }
+    }
+}
+
+struct Evaluation {
+    job_repository: JobRepository,
+    worker_repository: WorkerRepository,
+    alert_repository: AlertRepository,
+    config: AlertsConfig,
+    worker_stale_after: Duration,
+}
+
+impl Evaluation {
+    async fn evaluate(&self) -> Result<(), Error> {
+        let breaches = self.breaches().await?;
+        let mut open: HashMap<(String, Option<String>, Option<String>), i64> = self.alert_repository.get_alerts(false, i64::MAX).await?
+            .into_iter()
+            .map(|alert| ((alert.rule, alert.project_id, alert.task), alert.alert_id))
+            .collect();
+
+        for breach in breaches {
+            match open.remove(&(breach.rule.to_string(), breach.project.clone(), breach.task.clone())) {
+                Some(alert_id) => self.alert_repository.update(alert_id, &breach.message, breach.value, breach.threshold).await?,
+                None => {
+                    warn!("Alert {} fired: {}", breach.rule, breach.message);
+                    self.alert_repository.fire(breach.rule, breach.project.as_deref(), breach.task.as_deref(), &breach.message, breach.value, breach.threshold).await?;
+                }
+            }
+        }
+        // Alerts of rules no longer breached, or no longer configured
+        for ((rule, project, task), alert_id) in open {
+            let task = match (project, task) {
+                (Some(project), Some(task)) if project != DEFAULT_PROJECT => Some(format!("{}/{}", project, task)),
+                (_, task) => task,
+            };
+            info!("Alert {}{} resolved", rule, task.map(|task| format!(" of {}", task)).unwrap_or_default());
+            self.alert_repository.resolve(alert_id, None).await?;
+        }
+        Ok(())
+    }
+
+    async fn breaches(&self) -> Result<Vec<Breach<'_>>, Error> {
+        let rules = &self.config.rules;
+        let needs = |check: fn(&AlertMetric) -> bool| rules.iter().any(|rule| check(&rule.metric));
+
+        let health = if needs(|metric| matches!(metric, AlertMetric::FailureRate { .. } | AlertMetric::JobDuration)) {
+            let window = chrono::Duration::from_std(self.config.window)?;
+            self.job_repository.get_task_health(Utc::now() - window).await?
+        } else {
+            Vec::new()
+        };
+        let queued = if needs(|metric| matches!(metric, AlertMetric::QueueDepth)) {
+            self.job_repository.get_queue_stats().await?.queued
+        } else {
+            0
+        };
+        let workers = if needs(|metric| matches!(metric, AlertMetric::WorkerCount)) {
+            self.worker_repository.get_workers(self.worker_stale_after).await?
+                .iter()
+                .filter(|worker| worker.status != "stale")
+                .count()
+        } else {
+            0
+        };
+
+        let mut breaches = Vec::new();
+        for rule in rules {
+            match &rule.metric {
+                AlertMetric::FailureRate { min_jobs } => {
+                    for task in health.iter().filter(|task| task.finished > 0 && task.finished >= *min_jobs) {
+                        let rate = task.failed as f64 / task.finished as f64;
+                        let name = task.scoped_task();
+                        if let Some(threshold) = rule.task_threshold(&name).filter(|threshold| rate > *threshold) {
+                            breaches.push(Breach {
+                                rule: &rule.name,
+                                project: Some(task.project_id.clone()),
+                                task: Some(task.task.clone()),
+                                value: rate,
+                                threshold,
+                                message: format!("{} of the last {} jobs of {} failed", task.failed, task.finished, name),
+                            });
+                        }
+                    }
+                }
+                AlertMetric::JobDuration => {
+                    for task in &health {
+                        let Some(running) = task.longest_running_secs else { continue };
+                        let name = task.scoped_task();
+                        if let Some(threshold) = rule.task_threshold(&name).filter(|threshold| running > *threshold) {
+                            breaches.push(Breach {
+                                rule: &rule.name,
+                                project: Some(task.project_id.clone()),
+                                task: Some(task.task.clone()),
+                                value: running,
+                                threshold,
+                                message: format!("A job of {} has been running for {:.0}s", name, running),
+                            });
+                        }
+                    }
+                }
+                AlertMetric::QueueDepth => {
+                    if queued as f64 > rule.threshold {
+                        breaches.push(Breach {
+                            rule: &rule.name,
+                            project: None,
+                            task: None,
+                            value: queued as f64,
+                            threshold: rule.threshold,
+                            message: format!("{} jobs waiting for a worker", queued),
+                        });
+                    }
+                }
+                AlertMetric::WorkerCount => {
+                    if (workers as f64) < rule.threshold {
+                        breaches.push(Breach {
+                            rule: &rule.name,
+                            project: None,
+                            task: None,
+                            value: workers as f64,
+                            threshold: rule.threshold,
+                            message: match workers {
+                                0 => "No active workers".to_string(),
+                                workers => format!("Only {} active workers", workers),
+                            },
+                        });
+                    }
+                }
+            }
+        }
+        Ok(breaches)
+    }
+}
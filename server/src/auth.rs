@@ -1,6 +1,11 @@
 
 mod internal;
 mod oidc;
+mod rbac;
+mod api_token;
+
+pub use rbac::Role;
+pub use api_token::{TokenScope, API_TOKEN_PREFIX};
 
 use std::option::Option;
 use serde::{Deserialize, Serialize};
@@ -9,12 +14,13 @@ use std::sync::Arc;
 use anyhow::{bail, Error};
 use async_trait::async_trait;
 use serde_json::{json, Value};
-use sqlx::{PgPool, Row};
+use sqlx::Row;
 use uuid::Uuid;
 use chrono::{Utc, DateTime};
 use jsonwebtoken::{encode, Header, EncodingKey, DecodingKey, Validation, decode};
 use crate::auth::internal::{hash_password, AuthProviderInternal};
 use crate::auth::oidc::AuthProviderOIDC;
+use crate::repository::{with_pool, DbPool};
 use crate::server_config::{AuthConfig, AuthProviderType};
 use sha3::Sha3_256;
 use hmac::{Hmac, Mac};
@@ -26,17 +32,19 @@ pub struct User {
     pub user_id: Uuid,
     pub name: Option<String>,
     pub email: String,
+    #[serde(default)]
+    pub role: Role,
 }
 
 #[derive(Clone)]
 pub struct AuthService {
     config: AuthConfig,
-    pool: PgPool,
+    pool: DbPool,
     providers: HashMap<String, Arc<dyn AuthProviderImpl>>
 }
 
 impl AuthService {
-    pub async fn new(config: AuthConfig, pool: PgPool, public_url: Url) -> Self {
+    pub async fn new(config: AuthConfig, pool: DbPool, public_url: Url) -> Self {
         let mut providers = HashMap::new();
         for (id, provider) in &config.providers {
             if !provider.enabled {
@@ -117,9 +125,9 @@ impl AuthService {
 
     pub async fn add_initial_user(&self) -> Result<(), Error> {
         // Check if user table is empty
-        let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM \"user\"")
-            .fetch_one(&self.pool)
-            .await?;
+        let count: (i64,) = with_pool!(&self.pool, pool => sqlx::query_as("SELECT COUNT(*) FROM \"user\"")
+            .fetch_one(pool)
+            .await)?;
 
         if count.0 > 0 {
             debug!("Users already exist, skipping initial user creation.");
@@ -139,6 +147,7 @@ impl AuthService {
             config.name.as_deref(),
             config.password.as_deref(),
         ).await?;
+        self.set_role(&user_id, Role::Admin).await?;
 
         provider.create_link(&config.provider_id, &user_id, None).await?;
 
@@ -147,23 +156,65 @@ impl AuthService {
         Ok(())
     }
 
+    pub async fn set_role(&self, user_id: &Uuid, role: Role) -> Result<(), Error> {
+        let query = self.pool.sql(
+            "UPDATE \"user\" SET role = $1, updated_at = NOW() WHERE user_id = $2",
+            "UPDATE \"user\" SET role = $1, updated_at = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now') WHERE user_id = $2",
+        );
+        with_pool!(&self.pool, pool => {
+            sqlx::query(query)
+                .bind(role)
+                .bind(user_id)
+                .execute(pool)
+                .await?;
+        });
+        Ok(())
+    }
+
+    /// The user with the id, `None` if there is none (anymore).
+    pub async fn get_user(&self, user_id: &Uuid) -> Result<Option<User>, Error> {
+        let user = with_pool!(&self.pool, pool => {
+            let row = sqlx::query("SELECT user_id, name, email, role FROM \"user\" WHERE user_id = $1")
+                .bind(user_id)
+                .fetch_optional(pool)
+                .await?;
+            match row {
+                Some(row) => Some(User {
+                    user_id: row.try_get("user_id")?,
+                    name: row.try_get("name")?,
+                    email: row.try_get("email")?,
+                    role: row.try_get("role")?,
+                }),
+                None => None,
+            }
+        });
+        Ok(user)
+    }
+
     pub async fn logout_user(&self, user_id: &Uuid) -> Result<(), Error> {
-        sqlx::query(
+        let query = self.pool.sql(
             "UPDATE refresh_token
              SET revoked_at = NOW()
-             WHERE user_id = $1 AND revoked_at IS NULL"
-        )
-            .bind(user_id)
-            .execute(&self.pool)
-            .await?;
+             WHERE user_id = $1 AND revoked_at IS NULL",
+            "UPDATE refresh_token
+             SET revoked_at = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')
+             WHERE user_id = $1 AND revoked_at IS NULL",
+        );
+        with_pool!(&self.pool, pool => {
+            sqlx::query(query)
+                .bind(user_id)
+                .execute(pool)
+                .await?;
+        });
 
         Ok(())
     }
 
-    pub async fn issue_jwt(&self, user_id: &Uuid, email: &String) -> Result<String, Error> {
+    pub async fn issue_jwt(&self, user: &User) -> Result<String, Error> {
         let claims = Claims {
-            sub: user_id.to_string(),
-            email: email.clone(),
+            sub: user.user_id.to_string(),
+            email: user.email.clone(),
+            role: user.role,
             exp: (Utc::now() + self.config.jwt_expiration).timestamp() as usize,
         };
         let jwt = encode(
@@ -188,17 +239,19 @@ impl AuthService {
         let refresh_hash = hash_token(&refresh_token, &self.config.refresh_token_secret)?;
         let expires_at = Utc::now() + self.config.refresh_token_expiration;
 
-        sqlx::query(
-            "INSERT INTO refresh_token (user_id, auth_id, token_hash, expires_at)
-                     VALUES ($1, $2, $3, $4)
-                     ON CONFLICT (user_id, auth_id) DO UPDATE
-                     SET token_hash = $3, expires_at = $4, revoked_at = NULL")
-            .bind(user_id)
-            .bind(auth_id)
-            .bind(refresh_hash)
-            .bind(expires_at)
-            .execute(&self.pool)
-            .await?;
+        with_pool!(&self.pool, pool => {
+            sqlx::query(
+                "INSERT INTO refresh_token (user_id, auth_id, token_hash, expires_at)
+                         VALUES ($1, $2, $3, $4)
+                         ON CONFLICT (user_id, auth_id) DO UPDATE
+                         SET token_hash = $3, expires_at = $4, revoked_at = NULL")
+                .bind(user_id)
+                .bind(auth_id)
+                .bind(&refresh_hash)
+                .bind(expires_at)
+                .execute(pool)
+                .await?;
+        });
 
         Ok((refresh_token, expires_at))
     }
@@ -208,35 +261,38 @@ impl AuthService {
     ) -> Result<(String, User), Error> {
         let token_hash = hash_token(&refresh_token, &self.config.refresh_token_secret)?;
         
-        let row = sqlx::query(
-            "SELECT rt.user_id, rt.auth_id, rt.expires_at, rt.revoked_at, u.email, u.name
-             FROM refresh_token rt
-             JOIN \"user\" u ON rt.user_id = u.user_id
-             WHERE rt.token_hash = $1"
-        )
-            .bind(&token_hash)
-            .fetch_optional(&self.pool)
-            .await?;
-
-        let row = match row {
-            Some(row) => row,
-            None => bail!("Invalid refresh token"),
-        };
+        let user = with_pool!(&self.pool, pool => {
+            let row = sqlx::query(
+                "SELECT rt.user_id, rt.auth_id, rt.expires_at, rt.revoked_at, u.email, u.name, u.role
+                 FROM refresh_token rt
+                 JOIN \"user\" u ON rt.user_id = u.user_id
+                 WHERE rt.token_hash = $1"
+            )
+                .bind(&token_hash)
+                .fetch_optional(pool)
+                .await?;
+
+            let row = match row {
+                Some(row) => row,
+                None => bail!("Invalid refresh token"),
+            };
 
-        let expires_at: DateTime<Utc> = row.try_get("expires_at")?;
-        let revoked_at: Option<DateTime<Utc>> = row.try_get("revoked_at")?;
+            let expires_at: DateTime<Utc> = row.try_get("expires_at")?;
+            let revoked_at: Option<DateTime<Utc>> = row.try_get("revoked_at")?;
 
-        if revoked_at.is_some() || expires_at < Utc::now() {
-            bail!("Refresh token expired or revoked");
-        }
-        
-        let user = User {
-            user_id: row.try_get("user_id")?,
-            name: row.try_get("name")?,
-            email: row.try_get("email")?
-        };
+            if revoked_at.is_some() || expires_at < Utc::now() {
+                bail!("Refresh token expired or revoked");
+            }
+
+            User {
+                user_id: row.try_get("user_id")?,
+                name: row.try_get("name")?,
+                email: row.try_get("email")?,
+                role: row.try_get("role")?,
+            }
+        });
 
-        let jwt = self.issue_jwt(&user.user_id, &user.email).await?;
+        let jwt = self.issue_jwt(&user).await?;
         Ok((jwt, user))
     }
 }
@@ -252,22 +308,27 @@ pub enum AuthResponse {
 pub struct Claims {
     pub sub: String, // user_id
     pub email: String,
+    /// Tokens issued before roles existed get the default role
+    #[serde(default)]
+    pub role: Role,
     pub exp: usize,
 }
 
 #[async_trait]
 pub trait AuthProviderImpl: Send + Sync {
-    fn get_pool(&self) -> &PgPool;
+    fn get_pool(&self) -> &DbPool;
     async fn authenticate(&self, payload: &HashMap<String, String>, auto_signup: bool) -> Result<AuthResponse, Error>;
     async fn create_link(&self, auth_id: &str, user_id: &Uuid, identifier: Option<&str>) -> Result<(), Error> {
-        sqlx::query(
-            "INSERT INTO user_auth_link (user_id, auth_id, identifier) VALUES ($1, $2, $3)
-                  ON CONFLICT (user_id, auth_id) DO UPDATE SET identifier=$3")
-            .bind(&user_id)
-            .bind(auth_id)
-            .bind(identifier)
-            .execute(self.get_pool())
-            .await?;
+        with_pool!(self.get_pool(), pool => {
+            sqlx::query(
+                "INSERT INTO user_auth_link (user_id, auth_id, identifier) VALUES ($1, $2, $3)
+                      ON CONFLICT (user_id, auth_id) DO UPDATE SET identifier=$3")
+                .bind(user_id)
+                .bind(auth_id)
+                .bind(identifier)
+                .execute(pool)
+                .await?;
+        });
         Ok(())
     }
 
@@ -277,14 +338,16 @@ pub trait AuthProviderImpl: Send + Sync {
             password_hash = Some(hash_password(password)?);
         }
         let user_id  = Uuid::new_v4();
-        sqlx::query(
-            "INSERT INTO \"user\" (user_id, name, email, password_hash) VALUES ($1, $2, $3, $4)")
-            .bind(&user_id)
-            .bind(name)
-            .bind(email)
-            .bind(password_hash)
-            .execute(self.get_pool())
-            .await?;
+        with_pool!(self.get_pool(), pool => {
+            sqlx::query(
+                "INSERT INTO \"user\" (user_id, name, email, password_hash) VALUES ($1, $2, $3, $4)")
+                .bind(user_id)
+                .bind(name)
+                .bind(email)
+                .bind(&password_hash)
+                .execute(pool)
+                .await?;
+        });
         Ok(user_id)
     }
 }
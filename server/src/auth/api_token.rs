@@ -0,0 +1,145 @@
+use anyhow::{bail, Error};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use sqlx::Row;
+use uuid::Uuid;
+use stroem_common::rfc3339;
+use crate::auth::{hash_token, AuthService, User};
+use crate::repository::{with_pool, DbPool};
+
+/// Personal and service tokens start with this, so they can be told apart from JWTs.
+pub const API_TOKEN_PREFIX: &str = "stroem_";
+
+/// What an API token may be used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum TokenScope {
+    /// Read endpoints: tasks, actions, jobs, logs, ...
+    Read,
+    /// Only `/api/run`
+    Run,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct ApiToken {
+    pub token_id: Uuid,
+    pub name: String,
+    pub scope: TokenScope,
+    #[serde(with = "rfc3339")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "rfc3339::option")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(with = "rfc3339::option")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(with = "rfc3339::option")]
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl AuthService {
+    /// Creates a token acting as `user_id`. The secret is only returned here, just its hash is stored.
+    pub async fn create_api_token(&self, user_id: &Uuid, name: &str, scope: TokenScope, expires_at: Option<DateTime<Utc>>) -> Result<(ApiToken, String), Error> {
+        let secret = format!("{}{}{}", API_TOKEN_PREFIX, Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let token_hash = hash_token(&secret, &self.config.refresh_token_secret)?;
+
+        let token = with_pool!(&self.pool, pool => sqlx::query_as(
+            "INSERT INTO api_token (token_id, user_id, name, token_hash, scope, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             RETURNING token_id, name, scope, created_at, expires_at, last_used_at, revoked_at",
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(name)
+        .bind(&token_hash)
+        .bind(scope)
+        .bind(expires_at)
+        .fetch_one(pool)
+        .await)?;
+        Ok((token, secret))
+    }
+
+    pub async fn list_api_tokens(&self, user_id: &Uuid) -> Result<Vec<ApiToken>, Error> {
+        let tokens = with_pool!(&self.pool, pool => sqlx::query_as(
+            "SELECT token_id, name, scope, created_at, expires_at, last_used_at, revoked_at
+             FROM api_token
+             WHERE user_id = $1
+             ORDER BY created_at DESC",
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await)?;
+        Ok(tokens)
+    }
+
+    /// Returns false if the user has no such active token.
+    pub async fn revoke_api_token(&self, user_id: &Uuid, token_id: &Uuid) -> Result<bool, Error> {
+        let query = self.pool.sql(
+            "UPDATE api_token SET revoked_at = NOW() WHERE token_id = $1 AND user_id = $2 AND revoked_at IS NULL",
+            "UPDATE api_token SET revoked_at = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now') WHERE token_id = $1 AND user_id = $2 AND revoked_at IS NULL",
+        );
+        let rows_affected = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(token_id)
+            .bind(user_id)
+            .execute(pool)
+            .await?
+            .rows_affected());
+        Ok(rows_affected > 0)
+    }
+
+    /// Looks up the owner of an active token; the token has the owner's role.
+    pub async fn authenticate_api_token(&self, secret: &str) -> Result<(User, TokenScope), Error> {
+        let token_hash = hash_token(secret, &self.config.refresh_token_secret)?;
+        match &self.pool {
+            DbPool::Postgres(pool) => {
+                let row = sqlx::query(
+                    "UPDATE api_token t SET last_used_at = NOW()
+                     FROM \"user\" u
+                     WHERE t.token_hash = $1 AND u.user_id = t.user_id
+                       AND t.revoked_at IS NULL AND (t.expires_at IS NULL OR t.expires_at > NOW())
+                     RETURNING u.user_id, u.name, u.email, u.role, t.scope",
+                )
+                .bind(token_hash)
+                .fetch_optional(pool)
+                .await?;
+                let Some(row) = row else {
+                    bail!("Unknown, expired or revoked API token");
+                };
+
+                let user = User {
+                    user_id: row.try_get("user_id")?,
+                    name: row.try_get("name")?,
+                    email: row.try_get("email")?,
+                    role: row.try_get("role")?,
+                };
+                Ok((user, row.try_get("scope")?))
+            }
+            // SQLite's RETURNING only sees the updated table
+            DbPool::Sqlite(pool) => {
+                let token: Option<(Uuid, TokenScope)> = sqlx::query_as(
+                    "UPDATE api_token SET last_used_at = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')
+                     WHERE token_hash = $1 AND revoked_at IS NULL AND (expires_at IS NULL OR expires_at > strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'))
+                     RETURNING user_id, scope",
+                )
+                .bind(token_hash)
+                .fetch_optional(pool)
+                .await?;
+                let Some((user_id, scope)) = token else {
+                    bail!("Unknown, expired or revoked API token");
+                };
+
+                let row = sqlx::query("SELECT name, email, role FROM \"user\" WHERE user_id = $1")
+                    .bind(user_id)
+                    .fetch_one(pool)
+                    .await?;
+                let user = User {
+                    user_id,
+                    name: row.try_get("name")?,
+                    email: row.try_get("email")?,
+                    role: row.try_get("role")?,
+                };
+                Ok((user, scope))
+            }
+        }
+    }
+}
@@ -2,8 +2,8 @@ use std::collections::HashMap;
 use uuid::Uuid;
 use anyhow::{Error, anyhow};
 use serde::{Deserialize, Serialize};
-use sqlx::{PgPool, Row};
-use crate::auth::{AuthProviderImpl, AuthResponse, User};
+use crate::auth::{AuthProviderImpl, AuthResponse, Role, User};
+use crate::repository::{with_pool, DbPool};
 use argon2::{
     Argon2,
     PasswordHash,
@@ -19,11 +19,11 @@ use async_trait::async_trait;
 #[derive(Clone)]
 pub struct AuthProviderInternal {
     id: String,
-    pool: PgPool
+    pool: DbPool
 }
 
 impl AuthProviderInternal {
-    pub fn new(id: String, pool: PgPool) -> Self {
+    pub fn new(id: String, pool: DbPool) -> Self {
         Self { id, pool }
     }
 }
@@ -36,7 +36,7 @@ pub struct AuthInternalCredentials {
 
 #[async_trait]
 impl AuthProviderImpl for AuthProviderInternal {
-    fn get_pool(&self) -> &PgPool {
+    fn get_pool(&self) -> &DbPool {
         &self.pool
     }
 
@@ -51,14 +51,14 @@ impl AuthProviderImpl for AuthProviderInternal {
             _ => return Ok(AuthResponse::WrongCredentials),
         };
 
-        let user = sqlx::query("SELECT user_id, name, password_hash FROM \"user\" WHERE email = $1")
+        let user: Option<(Uuid, Option<String>, Option<String>, Role)> = with_pool!(&self.pool, pool => sqlx::query_as(
+            "SELECT user_id, name, password_hash, role FROM \"user\" WHERE email = $1")
             .bind(&email)
-            .fetch_optional(&self.pool)
-            .await?;
+            .fetch_optional(pool)
+            .await)?;
 
         match user {
-            Some(u) => {
-                let password_hash: Option<String> = u.try_get("password_hash")?;
+            Some((user_id, name, password_hash, role)) => {
                 let hash = match password_hash {
                     Some(hash) => hash,
                     None => return Ok(AuthResponse::WrongCredentials),
@@ -67,9 +67,10 @@ impl AuthProviderImpl for AuthProviderInternal {
                     return Ok(AuthResponse::WrongCredentials);
                 }
                 let user = User {
-                    user_id: u.get::<Uuid, &str>("user_id").clone(),
-                    name: u.get::<Option<String>, &str>("name").clone(),
-                    email: email.to_string()
+                    user_id,
+                    name,
+                    email: email.to_string(),
+                    role,
                 };
                 self.create_link(&self.id, &user.user_id, None).await?;
                 Ok(AuthResponse::Success(user))
@@ -82,6 +83,7 @@ impl AuthProviderImpl for AuthProviderInternal {
                         user_id,
                         name: None,
                         email: email.to_string(),
+                        role: Role::default(),
                     };
                     self.create_link(&self.id, &user.user_id, None).await?;
                     return Ok(AuthResponse::Success(user));
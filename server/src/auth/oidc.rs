@@ -2,8 +2,8 @@ use std::collections::HashMap;
 use anyhow::Error;
 use async_trait::async_trait;
 
-use sqlx::{PgPool, Row};
-use crate::auth::{AuthProviderImpl, AuthResponse, User};
+use crate::auth::{AuthProviderImpl, AuthResponse, Role, User};
+use crate::repository::{with_pool, DbPool};
 use openid;
 use openid::{DiscoveredClient, Options, StandardClaimsSubject, Token};
 use openid::error::StandardClaimsSubjectMissing;
@@ -17,7 +17,7 @@ type OpenIDClient = openid::Client<openid::Discovered, openid::StandardClaims>;
 #[derive(Clone)]
 pub struct AuthProviderOIDC {
     id: String,
-    pool: PgPool,
+    pool: DbPool,
     client: OpenIDClient,
     scopes: String,
     name_claim: String,
@@ -45,7 +45,7 @@ impl StandardClaimsSubject for CustomUserinfo {
 impl openid::CompactJson for CustomUserinfo {}
 
 impl AuthProviderOIDC {
-    pub async fn new(id: String, pool: PgPool,
+    pub async fn new(id: String, pool: DbPool,
                      issuer_url: String,
                      client_id: String,
                      client_secret: Option<String>,
@@ -67,7 +67,7 @@ impl AuthProviderOIDC {
 
 #[async_trait]
 impl AuthProviderImpl for AuthProviderOIDC {
-    fn get_pool(&self) -> &PgPool {
+    fn get_pool(&self) -> &DbPool {
         &self.pool
     }
 
@@ -86,28 +86,31 @@ impl AuthProviderImpl for AuthProviderOIDC {
             let sub = userinfo.sub()?;
             info!("email: {:?}, name: {:?}, ident: {:?}", email, name, sub);
 
-            let user = sqlx::query("SELECT user_id, name FROM \"user\" WHERE email = $1")
+            let user: Option<(Uuid, Option<String>, Role)> = with_pool!(&self.pool, pool => sqlx::query_as(
+                "SELECT user_id, name, role FROM \"user\" WHERE email = $1")
                 .bind(&email)
-                .fetch_optional(&self.pool)
-                .await?;
+                .fetch_optional(pool)
+                .await)?;
 
             return match user {
-                Some(u) => {
+                Some((user_id, old_name, role)) => {
                     // We need to update name for the user if it changed
-                    let old_name = u.get::<Option<&str>, &str>("name").clone();
-                    if name != old_name {
-                        sqlx::query(
-                            "UPDATE \"user\" SET name = $1 WHERE email = $2")
-                            .bind(&name)
-                            .bind(&email)
-                            .execute(self.get_pool())
-                            .await?;
+                    if name != old_name.as_deref() {
+                        with_pool!(self.get_pool(), pool => {
+                            sqlx::query(
+                                "UPDATE \"user\" SET name = $1 WHERE email = $2")
+                                .bind(name)
+                                .bind(email)
+                                .execute(pool)
+                                .await?;
+                        });
                     }
 
                     let user = User {
-                        user_id: u.get::<Uuid, &str>("user_id").clone(),
+                        user_id,
                         name: name.map(str::to_owned),
-                        email: email.to_string()
+                        email: email.to_string(),
+                        role,
                     };
                     self.create_link(&self.id, &user.user_id, Some(sub)).await?;
                     Ok(AuthResponse::Success(user))
@@ -119,6 +122,7 @@ impl AuthProviderImpl for AuthProviderOIDC {
                             user_id,
                             name: name.map(str::to_owned),
                             email: email.to_string(),
+                            role: Role::default(),
                         };
                         self.create_link(&self.id, &user.user_id, Some(sub)).await?;
                         return Ok(AuthResponse::Success(user));
@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+use stroem_common::workflows_configuration::{Task, TaskAcl};
+use crate::auth::User;
+
+/// What a user may do, from least to most privileged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum Role {
+    /// Can see tasks, jobs and logs
+    Viewer,
+    /// Can also run tasks and actions
+    #[default]
+    Operator,
+    /// Can do anything, regardless of task ACLs
+    Admin,
+}
+
+impl User {
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+
+    /// ACL entries are user emails or `role:<role>`.
+    fn matches(&self, principals: &[String]) -> bool {
+        principals.iter().any(|principal| match principal.strip_prefix("role:") {
+            Some(role) => serde_json::from_value::<Role>(serde_json::Value::String(role.to_string())).is_ok_and(|role| role == self.role),
+            None => principal.eq_ignore_ascii_case(&self.email),
+        })
+    }
+
+    pub fn can_view_task(&self, task: Option<&Task>) -> bool {
+        self.can_view_project(task.and_then(|task| task.acl.as_ref()))
+    }
+
+    pub fn can_run_task(&self, task: Option<&Task>) -> bool {
+        self.role >= Role::Operator && self.can_run_project(task.and_then(|task| task.acl.as_ref()))
+    }
+
+    /// A project ACL applies to all tasks and actions of the project, on top of their own.
+    pub fn can_view_project(&self, acl: Option<&TaskAcl>) -> bool {
+        match acl {
+            _ if self.is_admin() => true,
+            Some(TaskAcl { view: Some(view), run }) => self.matches(view) || run.as_ref().is_some_and(|run| self.matches(run)),
+            _ => true,
+        }
+    }
+
+    pub fn can_run_project(&self, acl: Option<&TaskAcl>) -> bool {
+        match acl.and_then(|acl| acl.run.as_ref()) {
+            _ if self.is_admin() => true,
+            Some(run) => self.matches(run),
+            None => true,
+        }
+    }
+
+    /// Approvers listed by an approval step, admins may decide on any approval.
+    pub fn can_approve(&self, approvers: &[String]) -> bool {
+        self.is_admin() || (self.role >= Role::Operator && self.matches(approvers))
+    }
+
+    /// Running a single action directly bypasses task ACLs, so it is limited by role only.
+    pub fn can_run_action(&self) -> bool {
+        self.role >= Role::Operator
+    }
+}
@@ -0,0 +1,172 @@
+// workflow-server/src/autoscale.rs
+use std::time::Duration;
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::json;
+use tokio::sync::watch;
+use tokio::time;
+use tracing::{debug, error, info};
+use stroem_common::rfc3339;
+use crate::repository::{JobRepository, QueueStats, WorkerRepository};
+use crate::server_config::AutoscaleConfig;
+
+/// Recommended worker count for external autoscalers (KEDA, ASG, ...).
+#[derive(Debug, Serialize, Clone)]
+pub struct Recommendation {
+    pub desired_workers: u32,
+    pub current_workers: u32,
+    pub queued: i64,
+    pub running: i64,
+    pub avg_job_duration_secs: Option<f64>,
+    pub oldest_wait_secs: Option<f64>,
+    pub target_wait_secs: u64,
+    /// The oldest queued job has waited longer than the target
+    pub sla_breached: bool,
+    #[serde(with = "rfc3339")]
+    pub computed_at: DateTime<Utc>,
+}
+
+/// Computes how many workers are needed to start every queued job within the target wait,
+/// assuming queued jobs take as long as the recently finished ones.
+pub async fn recommend(
+    job_repository: &JobRepository,
+    worker_repository: &WorkerRepository,
+    config: &AutoscaleConfig,
+    worker_stale_after: Duration,
+) -> Result<Recommendation, Error> {
+    let stats = job_repository.get_queue_stats().await?;
+    let workers: Vec<_> = worker_repository.get_workers(worker_stale_after).await?
+        .into_iter()
+        .filter(|worker| worker.status != "stale")
+        .collect();
+    let capacity_per_worker = if workers.is_empty() {
+        1.0
+    } else {
+        (workers.iter().map(|worker| worker.capacity.max(1) as f64).sum::<f64>() / workers.len() as f64).max(1.0)
+    };
+    Ok(compute(&stats, workers.len() as u32, capacity_per_worker, config))
+}
+
+fn compute(stats: &QueueStats, current_workers: u32, capacity_per_worker: f64, config: &AutoscaleConfig) -> Recommendation {
+    let target_wait = config.target_wait.as_secs_f64().max(1.0);
+    // Without history, assume a job keeps a slot busy for the whole target wait
+    let avg_duration = stats.avg_duration_secs.filter(|d| *d > 0.0).unwrap_or(target_wait);
+    let jobs_per_slot = (target_wait / avg_duration).floor().max(1.0);
+    let needed_slots = stats.running as f64 + (stats.queued as f64 / jobs_per_slot).ceil();
+
+    let mut desired_workers = (needed_slots / capacity_per_worker).ceil() as u32;
+    desired_workers = desired_workers.max(config.min_workers);
+    if let Some(max_workers) = config.max_workers {
+        desired_workers = desired_workers.min(max_workers);
+    }
+
+    Recommendation {
+        desired_workers,
+        current_workers,
+        queued: stats.queued,
+        running: stats.running,
+        avg_job_duration_secs: stats.avg_duration_secs,
+        oldest_wait_secs: stats.oldest_wait_secs,
+        target_wait_secs: config.target_wait.as_secs(),
+        sla_breached: stats.oldest_wait_secs.is_some_and(|wait| wait > target_wait),
+        computed_at: Utc::now(),
+    }
+}
+
+/// Periodically recomputes the recommendation and pushes a scale event to the webhook when it changes.
+pub struct Autoscaler {
+    job_repository: JobRepository,
+    worker_repository: WorkerRepository,
+    config: AutoscaleConfig,
+    worker_stale_after: Duration,
+    task: Option<tokio::task::JoinHandle<()>>,
+    cancel_tx: watch::Sender<bool>,
+}
+
+impl Autoscaler {
+    pub fn new(job_repository: JobRepository, worker_repository: WorkerRepository, config: AutoscaleConfig, worker_stale_after: Duration) -> Self {
+        let (cancel_tx, _) = watch::channel(false);
+        Self {
+            job_repository,
+            worker_repository,
+            config,
+            worker_stale_after,
+            task: None,
+            cancel_tx,
+        }
+    }
+
+    pub async fn run(&mut self) {
+        let Some(webhook_url) = self.config.webhook_url.clone() else {
+            debug!("No autoscale webhook configured");
+            return;
+        };
+        if self.task.is_some() {
+            info!("Autoscaler already running");
+            return;
+        }
+
+        let mut cancel_rx = self.cancel_tx.subscribe();
+        let job_repository = self.job_repository.clone();
+        let worker_repository = self.worker_repository.clone();
+        let config = self.config.clone();
+        let worker_stale_after = self.worker_stale_after;
+
+        let task = tokio::spawn(async move {
+            let client = Client::new();
+            let mut interval = time::interval(config.check_interval);
+            let mut last_desired = None;
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {},
+                    _ = cancel_rx.changed() => {
+                        if *cancel_rx.borrow() {
+                            info!("Autoscaler stopping due to cancellation signal");
+                            break;
+                        }
+                    }
+                }
+
+                let recommendation = match recommend(&job_repository, &worker_repository, &config, worker_stale_after).await {
+                    Ok(recommendation) => recommendation,
+                    Err(e) => {
+                        error!("Failed to compute autoscale recommendation: {}", e);
+                        continue;
+                    }
+                };
+                if last_desired == Some(recommendation.desired_workers) {
+                    continue;
+                }
+
+                let event = json!({
+                    "event": "scale",
+                    "previous_desired_workers": last_desired,
+                    "recommendation": &recommendation,
+                });
+                match client.post(webhook_url.clone()).json(&event).send().await.and_then(|r| r.error_for_status()) {
+                    Ok(_) => {
+                        info!("Pushed scale event: {} -> {} workers", recommendation.current_workers, recommendation.desired_workers);
+                        last_desired = Some(recommendation.desired_workers);
+                    }
+                    // Not recorded, so the event is retried on the next check
+                    Err(e) => error!("Failed to push scale event: {}", e),
+                }
+            }
+        });
+
+        self.task = Some(task);
+        info!("Autoscaler started");
+    }
+
+    pub async fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            if let Err(e) = self.cancel_tx.send(true) {
+                error!("Failed to send cancellation signal: {}", e);
+            }
+            let _ = task.await;
+            info!("Autoscaler stopped");
+        }
+    }
+}
@@ -0,0 +1,132 @@
+// workflow-server/src/check.rs
+use std::path::Path;
+use std::process::Command;
+use anyhow::{anyhow, bail, Error};
+use stroem_common::workflows_configuration::WorkflowsConfiguration;
+use crate::repository::{with_pool, LogRepositoryFactory};
+use crate::server_config::{ServerConfig, WorkspaceSourceConfig};
+use crate::workspace_source::WorkspaceSourceFactory;
+
+/// Checks everything the server needs before it can start, printing one line per check.
+/// Returns whether all of them passed, so deploy pipelines can gate on the exit code.
+pub async fn run(cfg: &ServerConfig) -> bool {
+    let mut ok = true;
+    ok &= report("database", check_database(cfg).await);
+    ok &= report("log storage", check_log_storage(cfg).await);
+    if let Some(tls) = &cfg.tls {
+        ok &= report("tls", crate::load_tls(tls).await.map(|_| ()));
+    }
+    let projects = cfg.projects.iter().map(|(name, project)| (format!(" of project {}", name), &project.workspace));
+    for (suffix, workspace) in std::iter::once((String::new(), &cfg.workspace)).chain(projects) {
+        match sync_workspace(workspace).await {
+            Ok(()) => {
+                ok &= report(&format!("workspace sync{}", suffix), Ok(()));
+                ok &= report(&format!("secret tools{}", suffix), check_secret_tools(&workspace.folder));
+                ok &= report(&format!("workspace{}", suffix), check_workspace(&workspace.folder));
+            }
+            Err(e) => ok &= report(&format!("workspace sync{}", suffix), Err(e)),
+        }
+    }
+    ok
+}
+
+fn report(name: &str, result: Result<(), Error>) -> bool {
+    match result {
+        Ok(()) => {
+            println!("ok    {}", name);
+            true
+        }
+        Err(e) => {
+            println!("FAIL  {}: {:#}", name, e);
+            false
+        }
+    }
+}
+
+async fn check_database(cfg: &ServerConfig) -> Result<(), Error> {
+    let db_pool = crate::connect_db(cfg).await?;
+    let applied: Vec<i64> = with_pool!(&db_pool, pool => {
+        sqlx::query("SELECT 1").execute(pool).await?;
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(pool)
+            .await
+            .unwrap_or_default()
+    });
+    let pending = db_pool.migrator().iter().filter(|migration| !applied.contains(&migration.version)).count();
+    if pending > 0 {
+        println!("      {} migrations will be applied on startup", pending);
+    }
+    Ok(())
+}
+
+/// Round-trips a probe archive through the cache folder and log storage.
+async fn check_log_storage(cfg: &ServerConfig) -> Result<(), Error> {
+    let repository = LogRepositoryFactory::new(&cfg.log_storage).await?;
+    let probe_id = format!("stroem-check-{}", uuid::Uuid::new_v4());
+    let cache_folder = repository.get_cache_folder();
+    tokio::fs::create_dir_all(&cache_folder).await
+        .map_err(|e| anyhow!("Failed to create cache folder {}: {}", cache_folder.display(), e))?;
+    let probe = cache_folder.join(format!("{}.tgz", probe_id));
+    tokio::fs::write(&probe, b"probe").await
+        .map_err(|e| anyhow!("Cache folder {} is not writable: {}", cache_folder.display(), e))?;
+    let result = async {
+        repository.upload_archive_to_storage(&probe_id, &probe).await?;
+        repository.delete_archive_from_storage(&probe_id).await
+    }.await;
+    let _ = tokio::fs::remove_file(&probe).await;
+    result
+}
+
+async fn sync_workspace(workspace: &WorkspaceSourceConfig) -> Result<(), Error> {
+    std::fs::create_dir_all(&workspace.folder)?;
+    let source = WorkspaceSourceFactory::new(workspace).await?;
+    source.sync()?;
+    Ok(())
+}
+
+/// Checks that `sops` and `vals` can be run, if the workspace uses them.
+fn check_secret_tools(workspace: &Path) -> Result<(), Error> {
+    let workflows_path = workspace.join(".workflows");
+    if !workflows_path.exists() {
+        return Ok(());
+    }
+    let files = stroem_common::walk_workspace_files(&workflows_path);
+    let mut uses_sops = false;
+    let mut uses_vals = false;
+    for file in &files {
+        let path = file.path();
+        uses_sops |= path.to_string_lossy().ends_with(".sops.yaml");
+        if let Ok(content) = std::fs::read_to_string(path) {
+            uses_vals |= content.contains("vals(") || content.contains("ref+");
+        }
+    }
+    if uses_sops {
+        run_tool("sops", &["--version"])?;
+    }
+    if uses_vals {
+        run_tool("vals", &["version"])?;
+    }
+    Ok(())
+}
+
+fn run_tool(tool: &str, args: &[&str]) -> Result<(), Error> {
+    let output = Command::new(tool).args(args).output()
+        .map_err(|e| anyhow!("The workspace uses {}, but it can't be run: {}", tool, e))?;
+    if !output.status.success() {
+        bail!("{} {} failed: {}", tool, args.join(" "), String::from_utf8_lossy(&output.stderr));
+    }
+    Ok(())
+}
+
+fn check_workspace(workspace: &Path) -> Result<(), Error> {
+    let workflows = WorkflowsConfiguration::new(workspace.to_path_buf())?;
+    for warning in workflows.deprecation_warnings() {
+        println!("      warning: {}", warning);
+    }
+    let errors = workflows.validation_errors();
+    if !errors.is_empty() {
+        let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        bail!("{} problem(s)\n      {}", errors.len(), errors.join("\n      "));
+    }
+    Ok(())
+}
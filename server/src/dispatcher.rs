@@ -0,0 +1,88 @@
+// workflow-server/src/dispatcher.rs
+//! Push mode dispatch: workers hold their job poll open, and are handed jobs as soon as they're queued
+//! instead of on their next poll.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use anyhow::Error;
+use stroem_common::JobRequest;
+use tokio::sync::{watch, Notify};
+use tokio::time::{self, Instant};
+use crate::repository::JobRepository;
+
+/// Upper bound for how long a worker poll is held
+pub const MAX_WAIT: Duration = Duration::from_secs(60);
+/// Held polls also look at the queue this often, for jobs that become available without being enqueued:
+/// rate limits opening up, or notifications lost while the listener reconnects.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Tracks the workers waiting for jobs and their free slots; cheap to clone.
+#[derive(Clone)]
+pub struct Dispatcher {
+    enqueued: Arc<Notify>,
+    waiting: Arc<Mutex<HashMap<String, usize>>>,
+    stopped: watch::Sender<bool>,
+}
+
+impl Dispatcher {
+    pub fn new(enqueued: Arc<Notify>) -> Self {
+        Dispatcher {
+            enqueued,
+            waiting: Default::default(),
+            stopped: watch::Sender::new(false),
+        }
+    }
+
+    /// Answers the held polls right away, so they don't hold up the server shutting down.
+    pub fn stop(&self) {
+        self.stopped.send_replace(true);
+    }
+
+    /// Leases up to `count` jobs of `projects`, or of any project without them, to the worker, waiting up to `wait`
+    /// for one to be queued. A worker that disconnects drops the future, and so stops being tracked.
+    pub async fn next_jobs(&self, job_repository: &JobRepository, worker_id: &str, projects: &[String], count: usize, wait: Duration) -> Result<Vec<JobRequest>, Error> {
+        let deadline = Instant::now() + wait.min(MAX_WAIT);
+        let _waiting = Waiting::new(self, worker_id, count);
+        let mut stopped = self.stopped.subscribe();
+        loop {
+            // Registered before looking at the queue, so a job enqueued in between isn't missed
+            let enqueued = self.enqueued.notified();
+            tokio::pin!(enqueued);
+            enqueued.as_mut().enable();
+
+            let jobs = job_repository.get_next_jobs(worker_id, projects, count).await?;
+            if !jobs.is_empty() || Instant::now() >= deadline || *stopped.borrow() {
+                return Ok(jobs);
+            }
+            tokio::select! {
+                _ = enqueued => {}
+                _ = stopped.changed() => {}
+                _ = time::sleep_until(deadline.min(Instant::now() + RECHECK_INTERVAL)) => {}
+            }
+        }
+    }
+
+    /// Free slots of the workers currently waiting for jobs, by worker id.
+    pub fn waiting(&self) -> HashMap<String, usize> {
+        self.waiting.lock().unwrap().clone()
+    }
+}
+
+/// Keeps a worker in the waiting list while its poll is held.
+struct Waiting<'a> {
+    dispatcher: &'a Dispatcher,
+    worker_id: String,
+}
+
+impl<'a> Waiting<'a> {
+    fn new(dispatcher: &'a Dispatcher, worker_id: &str, slots: usize) -> Self {
+        dispatcher.waiting.lock().unwrap().insert(worker_id.to_string(), slots);
+        Waiting { dispatcher, worker_id: worker_id.to_string() }
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.dispatcher.waiting.lock().unwrap().remove(&self.worker_id);
+    }
+}
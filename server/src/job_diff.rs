@@ -0,0 +1,154 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+use crate::repository::{Job, JobStep};
+
+/// Structured comparison of two runs of the same task or action.
+#[derive(Debug, Serialize)]
+pub struct JobDiff {
+    pub left: Uuid,
+    pub right: Uuid,
+    pub task: Option<String>,
+    pub action: Option<String>,
+    pub status: Change<Option<String>>,
+    pub duration: DurationDiff,
+    pub input: Vec<ValueChange>,
+    pub output: Vec<ValueChange>,
+    pub steps: Vec<StepDiff>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Change<T> {
+    pub left: T,
+    pub right: T,
+    pub changed: bool,
+}
+
+impl<T: PartialEq> Change<T> {
+    fn new(left: T, right: T) -> Self {
+        let changed = left != right;
+        Change { left, right, changed }
+    }
+}
+
+/// Durations in milliseconds, `None` while a run hasn't finished.
+#[derive(Debug, Serialize)]
+pub struct DurationDiff {
+    pub left_ms: Option<i64>,
+    pub right_ms: Option<i64>,
+    pub delta_ms: Option<i64>,
+}
+
+impl DurationDiff {
+    fn new(left_ms: Option<i64>, right_ms: Option<i64>) -> Self {
+        let delta_ms = left_ms.zip(right_ms).map(|(l, r)| r - l);
+        DurationDiff { left_ms, right_ms, delta_ms }
+    }
+}
+
+/// A single differing value; `left`/`right` is absent when the path only exists on one side.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ValueChange {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub left: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub right: Option<Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StepDiff {
+    pub name: String,
+    /// False if the step only ran in one of the jobs
+    pub in_left: bool,
+    pub in_right: bool,
+    pub status: Change<Option<String>>,
+    pub success: Change<Option<bool>>,
+    pub attempts: Change<Option<i32>>,
+    pub duration: DurationDiff,
+    pub input: Vec<ValueChange>,
+    pub output: Vec<ValueChange>,
+}
+
+impl StepDiff {
+    fn new(name: &str, left: Option<&JobStep>, right: Option<&JobStep>) -> Self {
+        StepDiff {
+            name: name.to_string(),
+            in_left: left.is_some(),
+            in_right: right.is_some(),
+            status: Change::new(left.and_then(|s| s.status.clone()), right.and_then(|s| s.status.clone())),
+            success: Change::new(left.map(|s| s.success), right.map(|s| s.success)),
+            attempts: Change::new(left.map(|s| s.attempts), right.map(|s| s.attempts)),
+            duration: DurationDiff::new(
+                left.map(|s| duration_ms(s.start_datetime, s.end_datetime)),
+                right.map(|s| duration_ms(s.start_datetime, s.end_datetime)),
+            ),
+            input: diff_values(left.and_then(|s| s.input.as_ref()), right.and_then(|s| s.input.as_ref())),
+            output: diff_values(left.and_then(|s| s.output.as_ref()), right.and_then(|s| s.output.as_ref())),
+        }
+    }
+}
+
+impl JobDiff {
+    pub fn between(left: &Job, right: &Job) -> Self {
+        let mut steps: Vec<StepDiff> = left.steps.iter()
+            .map(|step| StepDiff::new(&step.name, Some(step), right.steps.iter().find(|s| s.name == step.name)))
+            .collect();
+        steps.extend(right.steps.iter()
+            .filter(|step| !left.steps.iter().any(|s| s.name == step.name))
+            .map(|step| StepDiff::new(&step.name, None, Some(step))));
+
+        JobDiff {
+            left: left.job_id,
+            right: right.job_id,
+            task: left.task.clone(),
+            action: left.action.clone(),
+            status: Change::new(left.status.clone(), right.status.clone()),
+            duration: DurationDiff::new(job_duration_ms(left), job_duration_ms(right)),
+            input: diff_values(left.input.as_ref(), right.input.as_ref()),
+            output: diff_values(left.output.as_ref(), right.output.as_ref()),
+            steps,
+        }
+    }
+}
+
+fn duration_ms(start: DateTime<Utc>, end: DateTime<Utc>) -> i64 {
+    (end - start).num_milliseconds()
+}
+
+fn job_duration_ms(job: &Job) -> Option<i64> {
+    Some(duration_ms(job.start_datetime?, job.end_datetime?))
+}
+
+/// Lists the leaf values that differ between two JSON documents, using dotted paths (`a.b.0`).
+pub fn diff_values(left: Option<&Value>, right: Option<&Value>) -> Vec<ValueChange> {
+    let mut changes = Vec::new();
+    collect_changes(String::new(), left, right, &mut changes);
+    changes
+}
+
+fn collect_changes(path: String, left: Option<&Value>, right: Option<&Value>, changes: &mut Vec<ValueChange>) {
+    let child = |key: &str| if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) };
+    match (left, right) {
+        (Some(Value::Object(l)), Some(Value::Object(r))) => {
+            for (key, value) in l {
+                collect_changes(child(key), Some(value), r.get(key), changes);
+            }
+            for (key, value) in r.iter().filter(|(key, _)| !l.contains_key(*key)) {
+                collect_changes(child(key), None, Some(value), changes);
+            }
+        }
+        (Some(Value::Array(l)), Some(Value::Array(r))) => {
+            for i in 0..l.len().max(r.len()) {
+                collect_changes(child(&i.to_string()), l.get(i), r.get(i), changes);
+            }
+        }
+        (l, r) if l != r => changes.push(ValueChange {
+            path,
+            left: l.cloned(),
+            right: r.cloned(),
+        }),
+        _ => {}
+    }
+}
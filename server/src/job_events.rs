@@ -0,0 +1,211 @@
+// workflow-server/src/job_events.rs
+//! Live events of jobs, for the SSE and WebSocket streams. A worker reports to any one server, so with several
+//! servers the events are relayed through Postgres or Redis to all of them, each passing them to its own streams.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use anyhow::{bail, Error};
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{broadcast, watch};
+use tokio::time::{self, Duration};
+use tracing::{debug, error, info};
+use crate::repository::DbPool;
+use crate::server_config::EventsConfig;
+
+mod postgres;
+use postgres::PostgresRelay;
+
+#[cfg(feature = "redis-events")]
+mod redis;
+#[cfg(feature = "redis-events")]
+use self::redis::RedisRelay;
+
+/// Events buffered per job before a slow stream lags behind
+const CHANNEL_CAPACITY: usize = 100;
+
+/// Delay before subscribing again after the relay lost its connection
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct JobEvent {
+    pub event_name: String,
+    pub data: Value,
+}
+
+/// A job event on its way between servers.
+#[derive(Serialize, Deserialize)]
+pub struct RelayedEvent {
+    pub job_id: String,
+    #[serde(flatten)]
+    pub event: JobEvent,
+}
+
+/// Carries job events between servers. Every subscribed server receives each event, the publishing one included.
+#[async_trait]
+pub trait EventRelay: Send + Sync {
+    async fn publish(&self, event: &RelayedEvent) -> Result<(), Error>;
+    /// The events published by any server from now on, ends when the connection is lost.
+    async fn subscribe(&self) -> Result<BoxStream<'static, Result<RelayedEvent, Error>>, Error>;
+}
+
+pub struct EventRelayFactory {}
+impl EventRelayFactory {
+    pub async fn from_config(config: &EventsConfig, pool: DbPool) -> Result<Option<Arc<dyn EventRelay>>, Error> {
+        match config {
+            EventsConfig::Local => Ok(None),
+            EventsConfig::Postgres => match pool {
+                DbPool::Postgres(pool) => Ok(Some(Arc::new(PostgresRelay::new(pool)))),
+                DbPool::Sqlite(_) => bail!("Postgres event relay requires a Postgres database"),
+            },
+            #[cfg(feature = "redis-events")]
+            EventsConfig::Redis { url, channel } => Ok(Some(Arc::new(RedisRelay::new(url, channel).await?))),
+            #[cfg(not(feature = "redis-events"))]
+            EventsConfig::Redis { .. } => bail!("Redis event relay requires the server to be built with the `redis-events` feature"),
+        }
+    }
+}
+
+/// The channels of the jobs streamed from this server, and the relay that events go through first, if any.
+#[derive(Clone)]
+pub struct JobEvents {
+    channels: Arc<Mutex<HashMap<String, broadcast::Sender<JobEvent>>>>,
+    relay: Option<Arc<dyn EventRelay>>,
+}
+
+impl JobEvents {
+    pub fn new(relay: Option<Arc<dyn EventRelay>>) -> Self {
+        Self {
+            channels: Arc::new(Mutex::new(HashMap::new())),
+            relay,
+        }
+    }
+
+    pub fn subscribe(&self, job_id: &str) -> broadcast::Receiver<JobEvent> {
+        let mut channels = self.channels.lock().unwrap();
+        match channels.get(job_id) {
+            Some(tx) => tx.subscribe(),
+            None => {
+                let (tx, rx) = broadcast::channel(CHANNEL_CAPACITY);
+                channels.insert(job_id.to_string(), tx);
+                rx
+            }
+        }
+    }
+
+    /// Removes the channel of the job once no receivers are left but the `held` ones of the caller.
+    pub fn release(&self, job_id: &str, held: usize) {
+        let mut channels = self.channels.lock().unwrap();
+        if channels.get(job_id).is_some_and(|tx| tx.receiver_count() <= held) {
+            channels.remove(job_id);
+            debug!("Removed channel for job_id: {}", job_id);
+        }
+    }
+
+    /// Passes the event to the streams of the job, on all servers when there's a relay.
+    pub async fn send(&self, job_id: &str, event_name: &str, data: Value) {
+        let event = JobEvent {
+            event_name: event_name.to_string(),
+            data,
+        };
+        let Some(relay) = &self.relay else {
+            return self.deliver(job_id, event);
+        };
+        let relayed = RelayedEvent { job_id: job_id.to_string(), event };
+        if let Err(e) = relay.publish(&relayed).await {
+            error!("Failed to relay {} event of job {}, only streams on this server get it: {}", relayed.event.event_name, job_id, e);
+            self.deliver(job_id, relayed.event);
+        }
+    }
+
+    fn deliver(&self, job_id: &str, event: JobEvent) {
+        let channels = self.channels.lock().unwrap();
+        if let Some(tx) = channels.get(job_id) {
+            let _ = tx.send(event);
+        }
+    }
+}
+
+/// Passes the events coming in through the relay to the streams on this server.
+pub struct EventListener {
+    job_events: JobEvents,
+    task: Option<tokio::task::JoinHandle<()>>,
+    cancel_tx: watch::Sender<bool>,
+}
+
+impl EventListener {
+    pub fn new(job_events: JobEvents) -> Self {
+        let (cancel_tx, _) = watch::channel(false);
+        Self {
+            job_events,
+            task: None,
+            cancel_tx,
+        }
+    }
+
+    pub async fn run(&mut self) {
+        if self.task.is_some() {
+            info!("Event listener already running");
+            return;
+        }
+        let Some(relay) = self.job_events.relay.clone() else {
+            return;
+        };
+
+        let mut cancel_rx = self.cancel_tx.subscribe();
+        let job_events = self.job_events.clone();
+
+        let task = tokio::spawn(async move {
+            loop {
+                let mut events = match relay.subscribe().await {
+                    Ok(events) => events,
+                    Err(e) => {
+                        error!("Failed to subscribe to job events: {}", e);
+                        tokio::select! {
+                            _ = time::sleep(RETRY_DELAY) => continue,
+                            _ = cancel_rx.changed() => break,
+                        }
+                    }
+                };
+                // Events published while the subscription is down are lost, streams catch up when their clients reconnect
+                loop {
+                    tokio::select! {
+                        event = events.next() => match event {
+                            Some(Ok(RelayedEvent { job_id, event })) => job_events.deliver(&job_id, event),
+                            Some(Err(e)) => error!("Failed to receive a job event: {}", e),
+                            None => {
+                                error!("Lost the subscription to job events");
+                                break;
+                            }
+                        },
+                        _ = cancel_rx.changed() => {
+                            if *cancel_rx.borrow() {
+                                info!("Event listener stopping due to cancellation signal");
+                                return;
+                            }
+                        }
+                    }
+                }
+                tokio::select! {
+                    _ = time::sleep(RETRY_DELAY) => {}
+                    _ = cancel_rx.changed() => break,
+                }
+            }
+        });
+
+        self.task = Some(task);
+        info!("Event listener started");
+    }
+
+    pub async fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            if let Err(e) = self.cancel_tx.send(true) {
+                error!("Failed to send cancellation signal: {}", e);
+            }
+            let _ = task.await;
+            info!("Event listener stopped");
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use anyhow::Error;
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use super::{EventRelay, RelayedEvent};
+
+const JOB_EVENT_CHANNEL: &str = "stroem_job_event";
+
+/// NOTIFY payloads must stay below 8000 bytes
+const MAX_PAYLOAD: usize = 7900;
+
+/// Every server has read a stored event well within this time
+const STORED_EVENT_TTL: &str = "5 minutes";
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Notification {
+    Stored { stored: i64 },
+    Inline(RelayedEvent),
+}
+
+/// Relays through LISTEN/NOTIFY. Events too large for a payload are stored in `job_event`,
+/// and the notification only carries their id.
+pub struct PostgresRelay {
+    pool: PgPool,
+}
+
+impl PostgresRelay {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl EventRelay for PostgresRelay {
+    async fn publish(&self, event: &RelayedEvent) -> Result<(), Error> {
+        let mut payload = serde_json::to_string(event)?;
+        if payload.len() > MAX_PAYLOAD {
+            let id: i64 = sqlx::query_scalar("INSERT INTO job_event (payload) VALUES ($1) RETURNING id")
+                .bind(&payload)
+                .fetch_one(&self.pool)
+                .await?;
+            sqlx::query(&format!("DELETE FROM job_event WHERE created < NOW() - INTERVAL '{}'", STORED_EVENT_TTL))
+                .execute(&self.pool)
+                .await?;
+            payload = json!({"stored": id}).to_string();
+        }
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(JOB_EVENT_CHANNEL)
+            .bind(payload)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Holds one connection of the pool.
+    async fn subscribe(&self) -> Result<BoxStream<'static, Result<RelayedEvent, Error>>, Error> {
+        let mut listener = PgListener::connect_with(&self.pool).await?;
+        listener.listen(JOB_EVENT_CHANNEL).await?;
+        let pool = self.pool.clone();
+        let events = listener.into_stream().then(move |notification| {
+            let pool = pool.clone();
+            async move {
+                match serde_json::from_str(notification?.payload())? {
+                    Notification::Inline(event) => Ok(event),
+                    Notification::Stored { stored } => {
+                        let payload: String = sqlx::query_scalar("SELECT payload FROM job_event WHERE id = $1")
+                            .bind(stored)
+                            .fetch_one(&pool)
+                            .await?;
+                        Ok(serde_json::from_str(&payload)?)
+                    }
+                }
+            }
+        });
+        Ok(events.boxed())
+    }
+}
@@ -0,0 +1,41 @@
+use anyhow::Error;
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use redis::AsyncCommands;
+use redis::aio::MultiplexedConnection;
+use super::{EventRelay, RelayedEvent};
+
+/// Relays through a Redis pub/sub channel.
+pub struct RedisRelay {
+    client: redis::Client,
+    connection: MultiplexedConnection,
+    channel: String,
+}
+
+impl RedisRelay {
+    pub async fn new(url: &str, channel: &str) -> Result<Self, Error> {
+        let client = redis::Client::open(url)?;
+        let connection = client.get_multiplexed_async_connection().await?;
+        Ok(Self { client, connection, channel: channel.to_string() })
+    }
+}
+
+#[async_trait]
+impl EventRelay for RedisRelay {
+    async fn publish(&self, event: &RelayedEvent) -> Result<(), Error> {
+        let mut connection = self.connection.clone();
+        connection.publish::<_, _, ()>(&self.channel, serde_json::to_string(event)?).await?;
+        Ok(())
+    }
+
+    async fn subscribe(&self) -> Result<BoxStream<'static, Result<RelayedEvent, Error>>, Error> {
+        let mut pubsub = self.client.get_async_pubsub().await?;
+        pubsub.subscribe(&self.channel).await?;
+        let events = pubsub.into_on_message().map(|message| {
+            let payload: String = message.get_payload()?;
+            Ok(serde_json::from_str(&payload)?)
+        });
+        Ok(events.boxed())
+    }
+}
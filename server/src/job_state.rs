@@ -0,0 +1,64 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+use stroem_common::rfc3339;
+use crate::repository::{JobTimeline, StepTimeline};
+
+/// State of a job and its steps at a point in time, reconstructed from the recorded start and end times.
+#[derive(Debug, Serialize)]
+pub struct JobState {
+    pub job_id: Uuid,
+    #[serde(with = "rfc3339")]
+    pub at: DateTime<Utc>,
+    /// `not_queued`, `queued`, `running`, `completed` or `failed`
+    pub status: &'static str,
+    /// Steps that had started by `at`, in start order
+    pub steps: Vec<StepState>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StepState {
+    pub name: String,
+    /// `running`, or the step's final status once it had ended by `at`
+    pub status: String,
+    #[serde(with = "rfc3339")]
+    pub start_datetime: DateTime<Utc>,
+    /// Only set when the step had ended by `at`
+    #[serde(with = "rfc3339::option")]
+    pub end_datetime: Option<DateTime<Utc>>,
+}
+
+impl JobState {
+    pub fn at(timeline: &JobTimeline, at: DateTime<Utc>) -> Self {
+        let ended = timeline.end_datetime.filter(|end| *end <= at);
+        let status = if at < timeline.queued {
+            "not_queued"
+        } else if ended.is_some() {
+            if timeline.success == Some(true) { "completed" } else { "failed" }
+        } else if timeline.start_datetime.is_some_and(|start| start <= at) {
+            "running"
+        } else {
+            "queued"
+        };
+
+        let steps = timeline.steps.iter()
+            .filter(|step| step.start_datetime <= at)
+            .map(|step| StepState::at(step, at))
+            .collect();
+
+        JobState { job_id: timeline.job_id, at, status, steps }
+    }
+}
+
+impl StepState {
+    fn at(step: &StepTimeline, at: DateTime<Utc>) -> Self {
+        let end_datetime = step.end_datetime.filter(|end| *end <= at);
+        let status = match end_datetime {
+            None => "running".to_string(),
+            Some(_) => step.status.clone().unwrap_or_else(|| {
+                if step.success == Some(true) { "completed" } else { "failed" }.to_string()
+            }),
+        };
+        StepState { name: step.name.clone(), status, start_datetime: step.start_datetime, end_datetime }
+    }
+}
@@ -0,0 +1,164 @@
+// workflow-server/src/lineage.rs
+use std::sync::Arc;
+use anyhow::{anyhow, Error};
+use chrono::Utc;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, Url};
+use serde_json::{json, Value};
+use tracing::{debug, error, info};
+use stroem_common::rfc3339;
+use crate::repository::{Job, JobRepository};
+use crate::projects::Projects;
+use crate::server_config::LineageConfig;
+
+const PRODUCER: &str = concat!("https://github.com/stroem-hub/stroem/tree/v", env!("CARGO_PKG_VERSION"));
+const RUN_EVENT_SCHEMA: &str = "https://openlineage.io/spec/2-0-2/OpenLineage.json#/definitions/RunEvent";
+const ERROR_MESSAGE_FACET_SCHEMA: &str = "https://openlineage.io/spec/facets/1-0-1/ErrorMessageRunFacet.json#/$defs/ErrorMessageRunFacet";
+/// Custom facet with what stroem knows about the run, including its input and output
+const JOB_FACET_SCHEMA: &str = "https://github.com/stroem-hub/stroem/blob/main/server/schemas/StroemJobRunFacet.json#/$defs/StroemJobRunFacet";
+
+#[derive(Clone, Copy, Debug)]
+enum EventType {
+    Start,
+    Complete,
+    Fail,
+}
+
+impl EventType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EventType::Start => "START",
+            EventType::Complete => "COMPLETE",
+            EventType::Fail => "FAIL",
+        }
+    }
+}
+
+struct Emitter {
+    client: Client,
+    config: LineageConfig,
+    headers: HeaderMap,
+}
+
+/// Sends OpenLineage run events to the configured endpoint; cheap to clone.
+#[derive(Clone)]
+pub struct Lineage {
+    emitter: Option<Arc<Emitter>>,
+    job_repository: JobRepository,
+    projects: Projects,
+    public_url: Url,
+}
+
+impl Lineage {
+    pub fn new(config: Option<&LineageConfig>, job_repository: JobRepository, projects: Projects, public_url: Url) -> Result<Self, Error> {
+        let emitter = config.map(|config| {
+            let headers = config.headers.iter()
+                .map(|(name, value)| Ok((HeaderName::try_from(name.as_str())?, HeaderValue::try_from(value.as_str())?)))
+                .collect::<Result<HeaderMap, Error>>()
+                .map_err(|e| anyhow!("Invalid lineage header for {}: {}", config.url, e))?;
+            info!("Sending OpenLineage events to {}", config.url);
+            Ok::<_, Error>(Arc::new(Emitter { client: Client::new(), config: config.clone(), headers }))
+        }).transpose()?;
+        Ok(Lineage { emitter, job_repository, projects, public_url })
+    }
+
+    pub fn job_started(&self, job_id: &str) {
+        self.emit(job_id, None);
+    }
+
+    pub fn job_done(&self, job_id: &str, success: bool) {
+        self.emit(job_id, Some(success));
+    }
+
+    /// Sends the event in the background, so the worker reporting the job isn't held up.
+    /// Failures are only logged.
+    fn emit(&self, job_id: &str, success: Option<bool>) {
+        let Some(emitter) = self.emitter.clone() else { return };
+        let this = self.clone();
+        let job_id = job_id.to_string();
+        tokio::spawn(async move {
+            let mut job = match this.job_repository.get_job(&job_id).await {
+                Ok(job) => job,
+                Err(e) => {
+                    error!("Failed to load job {} for lineage: {}", job_id, e);
+                    return;
+                }
+            };
+            // Secret inputs don't leave the server
+            this.projects.mask_secret_inputs(&mut job);
+            let event_type = match success {
+                None => EventType::Start,
+                Some(true) => EventType::Complete,
+                Some(false) => EventType::Fail,
+            };
+            let event = this.run_event(&job, event_type, &emitter.config.namespace);
+            let result = emitter.client.post(emitter.config.url.clone())
+                .headers(emitter.headers.clone())
+                .timeout(emitter.config.timeout)
+                .json(&event)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            match result {
+                Ok(_) => debug!("Sent lineage {} event for job {}", event_type.as_str(), job_id),
+                Err(e) => error!("Failed to send lineage {} event for job {}: {}", event_type.as_str(), job_id, e),
+            }
+        });
+    }
+
+    fn run_event(&self, job: &Job, event_type: EventType, namespace: &str) -> Value {
+        let event_time = match event_type {
+            EventType::Start => job.start_datetime,
+            EventType::Complete | EventType::Fail => job.end_datetime,
+        }.unwrap_or_else(Utc::now);
+        let name = job.task.as_deref().or(job.action.as_deref()).unwrap_or("unknown");
+
+        let mut run_facets = json!({
+            "stroem_job": {
+                "_producer": PRODUCER,
+                "_schemaURL": JOB_FACET_SCHEMA,
+                "kind": if job.task.is_some() { "task" } else { "action" },
+                "input": job.input,
+                "output": job.output,
+                "source_type": job.source_type,
+                "source_id": job.source_id,
+                "worker_id": job.worker_id,
+                "revision": job.revision,
+                "url": format!("{}/jobs/{}", self.public_url.as_str().trim_end_matches('/'), job.job_id),
+            },
+        });
+        if let EventType::Fail = event_type {
+            let failed: Vec<&str> = job.steps.iter()
+                .filter(|step| !step.success)
+                .map(|step| step.name.as_str())
+                .collect();
+            let message = match failed.is_empty() {
+                true => format!("Job {} failed", job.job_id),
+                false => format!("Steps failed: {}", failed.join(", ")),
+            };
+            run_facets["errorMessage"] = json!({
+                "_producer": PRODUCER,
+                "_schemaURL": ERROR_MESSAGE_FACET_SCHEMA,
+                "message": message,
+                "programmingLanguage": "stroem",
+            });
+        }
+
+        json!({
+            "eventType": event_type.as_str(),
+            "eventTime": rfc3339::format(&event_time),
+            "producer": PRODUCER,
+            "schemaURL": RUN_EVENT_SCHEMA,
+            "run": {
+                "runId": job.job_id,
+                "facets": run_facets,
+            },
+            "job": {
+                "namespace": namespace,
+                "name": name,
+            },
+            "inputs": [],
+            "outputs": [],
+        })
+    }
+}
@@ -0,0 +1,199 @@
+// workflow-server/src/log_sink.rs
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{anyhow, bail, Error};
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use reqwest::{Client, Url};
+use serde_json::{json, Map, Value};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+use stroem_common::log_collector::{LogEntry, LogLevel};
+use stroem_common::rfc3339;
+use crate::server_config::LogSinkConfig;
+
+/// Batches buffered per sink before new ones are dropped, so a slow sink never holds up workers.
+const LOG_SINK_BUFFER: usize = 1000;
+
+/// Log entries of one job (step) as they were received from a worker.
+pub struct LogBatch {
+    pub job_id: String,
+    pub step_name: Option<String>,
+    pub logs: Vec<LogEntry>,
+}
+
+impl LogBatch {
+    /// The entry as a flat JSON document, tagged with the job and step.
+    fn document(&self, entry: &LogEntry) -> Map<String, Value> {
+        let mut document = entry.fields.clone().unwrap_or_default();
+        document.insert("timestamp".to_string(), json!(rfc3339::format(&entry.timestamp)));
+        document.insert("job_id".to_string(), json!(self.job_id));
+        document.insert("step_name".to_string(), json!(entry.step_name.as_ref().or(self.step_name.as_ref())));
+        document.insert("attempt".to_string(), json!(entry.attempt));
+        document.insert("level".to_string(), json!(level_of(entry)));
+        document.insert("stderr".to_string(), json!(entry.is_stderr));
+        document.insert("message".to_string(), json!(entry.message));
+        document
+    }
+}
+
+fn level_of(entry: &LogEntry) -> LogLevel {
+    entry.level.unwrap_or(if entry.is_stderr { LogLevel::Error } else { LogLevel::Info })
+}
+
+/// External log tooling receiving entries in addition to the log repository.
+#[async_trait]
+pub trait LogSink: Send + Sync {
+    async fn send(&self, batch: &LogBatch) -> Result<(), Error>;
+}
+
+pub struct LogSinkFactory {}
+impl LogSinkFactory {
+    pub fn from_config(config: &LogSinkConfig) -> Result<Arc<dyn LogSink>, Error> {
+        match config {
+            LogSinkConfig::Loki { url, labels, headers, timeout } => Ok(Arc::new(LokiLogSink {
+                url: url.join("loki/api/v1/push")?,
+                labels: labels.clone(),
+                headers: header_map(headers, url)?,
+                timeout: *timeout,
+                client: Client::new(),
+            })),
+            LogSinkConfig::Elasticsearch { url, index, headers, timeout } => Ok(Arc::new(ElasticsearchLogSink {
+                url: url.join("_bulk")?,
+                index: index.clone(),
+                headers: header_map(headers, url)?,
+                timeout: *timeout,
+                client: Client::new(),
+            })),
+        }
+    }
+}
+
+fn header_map(headers: &HashMap<String, String>, url: &Url) -> Result<HeaderMap, Error> {
+    headers.iter()
+        .map(|(name, value)| Ok((HeaderName::try_from(name.as_str())?, HeaderValue::try_from(value.as_str())?)))
+        .collect::<Result<HeaderMap, Error>>()
+        .map_err(|e| anyhow!("Invalid log sink header for {}: {}", url, e))
+}
+
+pub struct LokiLogSink {
+    client: Client,
+    url: Url,
+    labels: HashMap<String, String>,
+    headers: HeaderMap,
+    timeout: Duration,
+}
+
+#[async_trait]
+impl LogSink for LokiLogSink {
+    async fn send(&self, batch: &LogBatch) -> Result<(), Error> {
+        // Job ids would make a stream per job, so they go into the line instead of the labels
+        let mut streams: BTreeMap<LogLevel, Vec<Value>> = BTreeMap::new();
+        for entry in &batch.logs {
+            let timestamp = entry.timestamp.timestamp_nanos_opt().unwrap_or_default().to_string();
+            let line = Value::Object(batch.document(entry)).to_string();
+            streams.entry(level_of(entry)).or_default().push(json!([timestamp, line]));
+        }
+        let streams: Vec<Value> = streams.into_iter()
+            .map(|(level, values)| {
+                let mut labels = self.labels.clone();
+                labels.insert("level".to_string(), json!(level).as_str().unwrap_or_default().to_string());
+                json!({"stream": labels, "values": values})
+            })
+            .collect();
+        self.client.post(self.url.clone())
+            .headers(self.headers.clone())
+            .timeout(self.timeout)
+            .json(&json!({"streams": streams}))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+pub struct ElasticsearchLogSink {
+    client: Client,
+    url: Url,
+    index: String,
+    headers: HeaderMap,
+    timeout: Duration,
+}
+
+#[async_trait]
+impl LogSink for ElasticsearchLogSink {
+    async fn send(&self, batch: &LogBatch) -> Result<(), Error> {
+        let action = json!({"index": {"_index": self.index}}).to_string();
+        let mut body = String::new();
+        for entry in &batch.logs {
+            body.push_str(&action);
+            body.push('\n');
+            body.push_str(&Value::Object(batch.document(entry)).to_string());
+            body.push('\n');
+        }
+        let response: Value = self.client.post(self.url.clone())
+            .headers(self.headers.clone())
+            .header(CONTENT_TYPE, "application/x-ndjson")
+            .timeout(self.timeout)
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        // The bulk API answers 200 even if some documents were rejected
+        if response.get("errors").and_then(Value::as_bool).unwrap_or(false) {
+            bail!("Elasticsearch rejected some log entries");
+        }
+        Ok(())
+    }
+}
+
+/// The configured log sinks; cheap to clone. Each sink is fed by its own background task,
+/// so batches reach it in order and a slow or failing sink doesn't affect the others.
+#[derive(Clone)]
+pub struct LogSinks {
+    senders: Arc<Vec<(String, mpsc::Sender<Arc<LogBatch>>)>>,
+}
+
+impl LogSinks {
+    pub fn new(configs: &[LogSinkConfig]) -> Result<Self, Error> {
+        let mut senders = Vec::new();
+        for config in configs {
+            let sink = LogSinkFactory::from_config(config)?;
+            let name = config.as_ref().to_string();
+            let (tx, mut rx) = mpsc::channel::<Arc<LogBatch>>(LOG_SINK_BUFFER);
+            let task_name = name.clone();
+            tokio::spawn(async move {
+                while let Some(batch) = rx.recv().await {
+                    if let Err(e) = sink.send(&batch).await {
+                        error!("Failed to send logs of job {} to {} log sink: {}", batch.job_id, task_name, e);
+                    }
+                }
+            });
+            senders.push((name, tx));
+        }
+        if !senders.is_empty() {
+            info!("Streaming logs to {} log sink(s)", senders.len());
+        }
+        Ok(LogSinks { senders: Arc::new(senders) })
+    }
+
+    /// Queues the entries for every sink without waiting for them.
+    pub fn send(&self, job_id: &str, step_name: Option<&str>, logs: &[LogEntry]) {
+        if self.senders.is_empty() || logs.is_empty() {
+            return;
+        }
+        let batch = Arc::new(LogBatch {
+            job_id: job_id.to_string(),
+            step_name: step_name.map(str::to_string),
+            logs: logs.to_vec(),
+        });
+        for (name, sender) in self.senders.iter() {
+            if sender.try_send(batch.clone()).is_err() {
+                warn!("The {} log sink is falling behind, dropped {} log entries of job {}", name, batch.logs.len(), job_id);
+            }
+        }
+    }
+}
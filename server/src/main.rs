@@ -1,97 +1,286 @@
-use std::fs::create_dir_all;
 // workflow-server/src/main.rs
-use clap::Parser;
-use tracing::{info, Level};
-use tracing_subscriber;
-use tokio::signal;
+use clap::{CommandFactory, Parser, Subcommand};
+use clap::error::ErrorKind;
+use tracing::{error, info, warn};
+use tracing_subscriber::filter::LevelFilter;
+use stroem_common::log_level::init_reloadable_tracing;
+use stroem_common::shutdown;
+use stroem_common::telemetry::Telemetry;
+use tokio::sync::watch;
+use tokio::time::{self, Duration};
 use std::path::PathBuf;
-use anyhow::Error;
+use anyhow::{anyhow, Error};
+use axum_server::tls_rustls::RustlsConfig;
 use sqlx::postgres::PgPoolOptions;
-use sqlx::migrate::Migrator;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
 
 
+mod check;
 mod scheduler;
+mod message_triggers;
+mod job_diff;
+mod job_state;
+mod search;
+mod alerts;
+mod sla;
+mod waits;
+mod autoscale;
+mod metrics;
+mod retention;
+mod usage;
+mod post_process;
+mod lineage;
+mod task_webhooks;
+mod notifications;
+mod dispatcher;
+mod queue_listener;
+mod job_events;
+mod log_sink;
 mod repository;
 mod error;
 mod server_config;
 pub mod workspace_server;
+mod projects;
 mod workspace_source;
 mod web;
 mod auth;
 
-use workspace_server::WorkspaceServer;
+use projects::Projects;
 use scheduler::Scheduler;
-use repository::JobRepository;
+use message_triggers::MessageTriggers;
+use alerts::AlertEvaluator;
+use sla::SlaMonitor;
+use waits::WaitMonitor;
+use autoscale::Autoscaler;
+use retention::Retention;
+use queue_listener::QueueListener;
+use job_events::{EventListener, EventRelayFactory, JobEvents};
+use post_process::PostProcessors;
+use lineage::Lineage;
+use task_webhooks::TaskWebhookSender;
+use notifications::Notifications;
+use log_sink::LogSinks;
+use repository::{with_pool, AlertRepository, ApprovalRepository, ArtifactRepository, ArtifactStorageFactory, AuditRepository, DbPool, JobGroupRepository, JobRepository, QueueBackendFactory, SlaRepository, TaskRepository, TriggerRepository, WaitRepository, WebhookDeliveryRepository, WorkerRepository};
 use crate::repository::LogRepositoryFactory;
-use std::sync::Arc;
 use crate::auth::{AuthService};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    #[arg(short, long, required = true)]
-    config: String,
+    #[command(subcommand)]
+    command: Option<Commands>,
+    /// Required; global so it can also follow the subcommand, which rules out clap's `required`
+    #[arg(short, long, global = true)]
+    config: Option<String>,
+    /// Environment whose overlay is layered over the config, e.g. `prod` for `config.prod.yaml`
+    #[arg(short, long, env = "STROEM_ENV")]
+    environment: Option<String>,
+    /// Print the merged config, with secrets masked, and exit
+    #[arg(long)]
+    print_effective_config: bool,
     #[arg(short, long)]
     verbose: bool,
 }
 
-// embed_migrations!("migrations");
-static MIGRATOR: Migrator = sqlx::migrate!();
+#[derive(Debug, Subcommand)]
+enum Commands {
+    /// Check the config, database, log storage and workspace, exiting non-zero if any check fails
+    Check {},
+}
+
+/// How long open connections get to close on shutdown
+const HTTP_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[tokio::main]
 async fn main() -> Result<(), Error>{
     let args = Args::parse();
-    let log_level = if args.verbose { Level::TRACE } else { Level::INFO };
-    tracing_subscriber::fmt()
-        .with_max_level(log_level)
-        .init();
+    let Some(config) = args.config else {
+        Args::command().error(ErrorKind::MissingRequiredArgument, "the following required arguments were not provided:\n  --config <CONFIG>").exit();
+    };
+    if args.print_effective_config {
+        println!("{}", server_config::ServerConfig::effective(PathBuf::from(&config), args.environment.as_deref())?);
+        return Ok(());
+    }
+    if let Some(Commands::Check {}) = args.command {
+        let ok = match server_config::ServerConfig::new(PathBuf::from(&config), args.environment.as_deref()) {
+            Ok(cfg) => {
+                println!("ok    config");
+                check::run(&cfg).await
+            }
+            Err(e) => {
+                println!("FAIL  config: {:#}", e);
+                false
+            }
+        };
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+
+    let cfg = server_config::ServerConfig::new(PathBuf::from(&config), args.environment.as_deref())?;
 
-    let cfg = server_config::ServerConfig::new(PathBuf::from(args.config))?;
+    let telemetry = Telemetry::new(cfg.otlp.as_ref().map(|otlp| otlp.endpoint.as_str()), "stroem-server")?;
+    let log_level = if args.verbose { LevelFilter::TRACE } else { LevelFilter::INFO };
+    let log_level = init_reloadable_tracing(log_level, &telemetry);
+    if let Err(e) = log_level.watch_sighup() {
+        error!("Failed to listen for SIGHUP: {}", e);
+    }
 
-    let db_pool = PgPoolOptions::new()
-        .max_connections(5) // Adjust as needed, default max connections
-        .connect(&format!(
-            "postgres://{}:{}@{}:{}/{}",
-            cfg.db.username, cfg.db.password, cfg.db.host, cfg.db.port, cfg.db.database
-        ))
-        .await?;
+    let db_pool = connect_db(&cfg).await?;
 
-    MIGRATOR.run(&db_pool).await?;
+    with_pool!(&db_pool, pool => db_pool.migrator().run(pool).await)?;
 
     // let mut db_client = db_pool.get().await.context("Could not connect to DB server")?;
     // migrations::runner()
     //     .run_async(db_client.deref_mut().deref_mut()) // Get to the tokio_postgresql object
     //     .await?;
 
-    let workspace_dir = &cfg.workspace.folder;
-    create_dir_all(workspace_dir)?;
-
-    let workspace = Arc::new(WorkspaceServer::new(cfg.workspace).await);
-    let revision = workspace.sync().await?;
-    info!("Workspace sync complete, revision: {}", revision.unwrap_or("unknown".to_string()));
-    workspace.read_workflows()?;
-    workspace.clone().watch().await;
-
+    let projects = Projects::load(&cfg).await?;
 
-    let job_repo = JobRepository::new(db_pool.clone());
+    let queue = QueueBackendFactory::from_config(&cfg.queue, db_pool.clone()).await?;
+    info!("Using {} queue backend", cfg.queue.as_ref());
+    let job_repo = JobRepository::new(db_pool.clone(), queue);
+    let worker_repo = WorkerRepository::new(db_pool.clone());
+    let audit_repo = AuditRepository::new(db_pool.clone());
+    let task_repo = TaskRepository::new(db_pool.clone());
     let logs_repo = LogRepositoryFactory::new(&cfg.log_storage).await?;
+    let artifact_repo = match &cfg.artifact_storage {
+        Some(artifact_storage) => ArtifactRepository::new(db_pool.clone(), Some(ArtifactStorageFactory::from_config(artifact_storage).await?), artifact_storage.max_size),
+        None => ArtifactRepository::new(db_pool.clone(), None, 0),
+    };
     let auth_service = AuthService::new(cfg.auth.clone(), db_pool.clone(), cfg.public_url.clone()).await;
     auth_service.add_initial_user().await?;
 
-    // Create Scheduler
-    let mut scheduler = Scheduler::new(job_repo.clone(), workspace.subscribe());
-    scheduler.run().await;
+    // Triggers fire per project
+    let mut schedulers = Vec::new();
+    let mut message_triggers = Vec::new();
+    for project in projects.iter() {
+        let mut scheduler = Scheduler::new(job_repo.clone(), task_repo.clone(), TriggerRepository::new(db_pool.clone()), project.clone(), cfg.enforce_action_sunset);
+        scheduler.run().await;
+        schedulers.push(scheduler);
+
+        let mut project_message_triggers = MessageTriggers::new(job_repo.clone(), task_repo.clone(), project.clone(), cfg.enforce_action_sunset);
+        project_message_triggers.run().await;
+        message_triggers.push(project_message_triggers);
+    }
+
+    let mut autoscaler = Autoscaler::new(job_repo.clone(), worker_repo.clone(), cfg.autoscale.clone(), cfg.worker_stale_after);
+    autoscaler.run().await;
+    let alert_repo = AlertRepository::new(db_pool.clone());
+    let mut alert_evaluator = AlertEvaluator::new(job_repo.clone(), worker_repo.clone(), alert_repo.clone(), cfg.alerts.clone(), cfg.worker_stale_after);
+    alert_evaluator.run().await;
+
+    let mut retention = Retention::new(job_repo.clone(), logs_repo.clone(), artifact_repo.clone(), cfg.retention.clone());
+    retention.run().await;
+
+    // Only Postgres is shared between servers, a SQLite database wakes held polls directly
+    let mut queue_listener = QueueListener::new(job_repo.clone());
+    if let DbPool::Postgres(_) = db_pool {
+        queue_listener.run().await;
+    }
+
+    let event_relay = EventRelayFactory::from_config(&cfg.events, db_pool.clone()).await?;
+    info!("Using {} job events", cfg.events.as_ref());
+    let job_events = JobEvents::new(event_relay);
+    let mut event_listener = EventListener::new(job_events.clone());
+    event_listener.run().await;
+
+    if cfg.worker_token.is_some() {
+        warn!("worker_token is deprecated, mint a worker credential per worker through /api/admin/worker-credentials instead");
+    }
+
+    let post_processors = PostProcessors::new(&cfg.post_processors, job_repo.clone(), cfg.public_url.clone())?;
+    let log_sinks = LogSinks::new(&cfg.log_storage.sinks)?;
+    let lineage = Lineage::new(cfg.lineage.as_ref(), job_repo.clone(), projects.clone(), cfg.public_url.clone())?;
+    let task_webhooks = TaskWebhookSender::new(&cfg.task_webhooks, projects.clone(), job_repo.clone(), WebhookDeliveryRepository::new(db_pool.clone()), cfg.public_url.clone());
+    let notifications = Notifications::new(&cfg.notifications, projects.clone(), job_repo.clone(), cfg.public_url.clone())?;
+    let sla_repo = SlaRepository::new(db_pool.clone());
+    let mut sla_monitor = SlaMonitor::new(projects.clone(), sla_repo.clone(), notifications.clone(), cfg.sla.clone());
+    sla_monitor.run().await;
+    let wait_repo = WaitRepository::new(db_pool.clone(), cfg.waits.event_retention);
+    let mut wait_monitor = WaitMonitor::new(job_repo.clone(), wait_repo.clone(), cfg.waits.clone());
+    wait_monitor.run().await;
 
     // Create Api
-    let state = web::WebState::new(workspace, job_repo, logs_repo, auth_service, cfg.public_url.clone(), cfg.worker_token.clone());
-    tokio::spawn(async move {
-        web::run(state, "0.0.0.0:8080").await;
+    let upcoming_runs = schedulers.iter().map(Scheduler::subscribe).collect();
+    let state = web::WebState::new(projects, job_repo, logs_repo, auth_service, cfg.public_url.clone(), cfg.worker_token.clone(), cfg.enforce_action_sunset, cfg.job_list.clone(), worker_repo, task_repo, cfg.worker_stale_after, log_level, cfg.autoscale.clone(), cfg.accounting.clone(), post_processors, log_sinks, lineage, upcoming_runs, retention.subscribe(), audit_repo, JobGroupRepository::new(db_pool.clone()), artifact_repo, task_webhooks, job_events, notifications, alert_repo, sla_repo, ApprovalRepository::new(db_pool.clone()), wait_repo);
+    let access_log = cfg.access_log.clone();
+    let rate_limit = cfg.rate_limit.clone();
+    let metrics = cfg.metrics.clone();
+    let listen_addr = cfg.listen_addr;
+    let tls = match &cfg.tls {
+        Some(tls) => Some(load_tls(tls).await?),
+        None => None,
+    };
+    let dispatcher = state.dispatcher.clone();
+    let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
+    let web = tokio::spawn(async move {
+        let shutdown = async move {
+            let _ = shutdown_rx.wait_for(|shutdown| *shutdown).await;
+        };
+        web::run(state, listen_addr, tls, &access_log, &rate_limit, &metrics, shutdown).await;
     });
 
-    // Empty loop with graceful shutdown
     info!("Server running, waiting for shutdown signal...");
-    signal::ctrl_c().await.expect("Failed to listen for shutdown signal");
-    info!("Received shutdown signal, shutting down gracefully...");
-    scheduler.stop().await;
+    shutdown::signal().await;
+    info!("Shutting down gracefully...");
+    // Nothing new gets queued, while workers can still report on running jobs until the listener stops
+    for scheduler in &mut schedulers {
+        scheduler.stop().await;
+    }
+    for project_message_triggers in &mut message_triggers {
+        project_message_triggers.stop().await;
+    }
+    autoscaler.stop().await;
+    alert_evaluator.stop().await;
+    sla_monitor.stop().await;
+    wait_monitor.stop().await;
+    retention.stop().await;
+    let _ = shutdown_tx.send(true);
+    dispatcher.stop();
+    // SSE and WebSocket streams of running jobs don't end by themselves
+    if time::timeout(HTTP_DRAIN_TIMEOUT, web).await.is_err() {
+        warn!("Open connections not closed after {:?}, exiting anyway", HTTP_DRAIN_TIMEOUT);
+    }
+    queue_listener.stop().await;
+    event_listener.stop().await;
+    telemetry.shutdown();
+    info!("Server stopped");
     Ok(())
-}
\ No newline at end of file
+}
+
+async fn load_tls(tls: &server_config::TlsConfig) -> Result<RustlsConfig, Error> {
+    // Dependencies enable both the ring and aws-lc-rs providers, so rustls can't pick a default by itself
+    let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+    RustlsConfig::from_pem_file(&tls.cert, &tls.key).await
+        .map_err(|e| anyhow!("Failed to load TLS certificate {} and key {}: {}", tls.cert.display(), tls.key.display(), e))
+}
+
+async fn connect_db(cfg: &server_config::ServerConfig) -> Result<DbPool, Error> {
+    match &cfg.db {
+        server_config::DbConfig::Postgres { host, port, database, username, password } => {
+            let db_pool = PgPoolOptions::new()
+                .max_connections(7) // Adjust as needed, one is held by the queue listener and one by the Postgres event relay
+                // Day boundaries in queries (e.g. `::date`, `date_trunc`) are always UTC, regardless of the database server's time zone
+                .after_connect(|conn, _meta| Box::pin(async move {
+                    sqlx::query("SET TIME ZONE 'UTC'").execute(conn).await?;
+                    Ok(())
+                }))
+                .connect(&format!("postgres://{}:{}@{}:{}/{}", username, password, host, port, database))
+                .await?;
+            Ok(DbPool::Postgres(db_pool))
+        }
+        server_config::DbConfig::Sqlite { path } => {
+            let options = SqliteConnectOptions::new()
+                .filename(path)
+                .create_if_missing(true)
+                // Readers don't block the writer, and writers wait for each other instead of failing
+                .journal_mode(SqliteJournalMode::Wal)
+                .busy_timeout(Duration::from_secs(10));
+            let db_pool = SqlitePoolOptions::new()
+                .max_connections(6)
+                .connect_with(options)
+                .await
+                .map_err(|e| anyhow!("Failed to open SQLite database {}: {}", path.display(), e))?;
+            Ok(DbPool::Sqlite(db_pool))
+        }
+    }
+}
@@ -0,0 +1,218 @@
+// workflow-server/src/message_triggers.rs
+use std::collections::HashMap;
+use std::sync::Arc;
+use anyhow::{anyhow, Error};
+use chrono::Utc;
+use serde_json::{json, Map, Value};
+use stroem_common::{JobRequest, JobSpec};
+use stroem_common::parameter_renderer::ParameterRenderer;
+use stroem_common::workflows_configuration::{Trigger, TriggerType, WorkflowsConfiguration};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration};
+use tracing::{error, info, warn};
+use crate::projects::Project;
+use crate::repository::{JobRepository, TaskRepository};
+
+mod nats;
+
+use nats::{NatsAuth, NatsMessage, NatsSubscription};
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Keeps a subscription per message trigger, next to the Scheduler for cron triggers.
+pub struct MessageTriggers {
+    context: Context,
+    task: Option<JoinHandle<()>>,
+    cancel_tx: watch::Sender<bool>,
+    config_rx: watch::Receiver<Option<WorkflowsConfiguration>>,
+}
+
+#[derive(Clone)]
+struct Context {
+    job_repository: JobRepository,
+    task_repository: TaskRepository,
+    project: Arc<Project>,
+    enforce_action_sunset: bool,
+}
+
+/// A running subscription and the trigger definition it was started for.
+struct Subscription {
+    definition: Value,
+    handle: JoinHandle<()>,
+}
+
+impl MessageTriggers {
+    /// Subscribes the message triggers of the project.
+    pub fn new(job_repository: JobRepository, task_repository: TaskRepository, project: Arc<Project>, enforce_action_sunset: bool) -> Self {
+        let (cancel_tx, _) = watch::channel(false);
+        let config_rx = project.workspace.subscribe();
+        Self {
+            context: Context { job_repository, task_repository, project, enforce_action_sunset },
+            task: None,
+            cancel_tx,
+            config_rx,
+        }
+    }
+
+    /// Starts subscriptions for new or changed triggers and stops the ones that are gone.
+    fn load_config(config: Option<WorkflowsConfiguration>, subscriptions: &mut HashMap<String, Subscription>, context: &Context) {
+        let triggers: HashMap<String, Trigger> = config.and_then(|config| config.triggers).unwrap_or_default()
+            .into_iter()
+            .filter(|(_, trigger)| trigger.enabled.unwrap_or(true) && matches!(trigger.trigger_type, TriggerType::Nats { .. }))
+            .collect();
+
+        subscriptions.retain(|trigger_name, subscription| {
+            let unchanged = triggers.get(trigger_name)
+                .and_then(|trigger| serde_json::to_value(trigger).ok())
+                .is_some_and(|definition| definition == subscription.definition);
+            if !unchanged {
+                info!("Stopping subscription of trigger '{}'", trigger_name);
+                subscription.handle.abort();
+            }
+            unchanged
+        });
+
+        for (trigger_name, trigger) in triggers {
+            if subscriptions.contains_key(&trigger_name) {
+                continue;
+            }
+            let definition = serde_json::to_value(&trigger).unwrap_or_default();
+            let handle = tokio::spawn(Self::subscribe(trigger_name.clone(), trigger, context.clone()));
+            subscriptions.insert(trigger_name, Subscription { definition, handle });
+        }
+    }
+
+    /// Keeps the trigger subscribed, reconnecting with a backoff when the connection drops.
+    async fn subscribe(trigger_name: String, trigger: Trigger, context: Context) {
+        let TriggerType::Nats { url, subject, queue_group, token, user, password } = &trigger.trigger_type else { return };
+        let auth = NatsAuth { token: token.as_deref(), user: user.as_deref(), password: password.as_deref() };
+        let mut delay = MIN_RECONNECT_DELAY;
+        loop {
+            match NatsSubscription::connect(url, subject, queue_group.as_deref(), &auth).await {
+                Ok(mut subscription) => {
+                    info!("Trigger '{}' subscribed to '{}' on {}", trigger_name, subject, url);
+                    delay = MIN_RECONNECT_DELAY;
+                    loop {
+                        match subscription.next().await {
+                            Ok(message) => Self::handle_message(&trigger_name, &trigger, message, &context).await,
+                            Err(e) => {
+                                warn!("Subscription of trigger '{}' lost: {}", trigger_name, e);
+                                break;
+                            }
+                        }
+                    }
+                }
+                Err(e) => error!("Failed to subscribe trigger '{}' to '{}' on {}: {}", trigger_name, subject, url, e),
+            }
+            time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    }
+
+    async fn handle_message(trigger_name: &str, trigger: &Trigger, message: NatsMessage, context: &Context) {
+        let result = async {
+            let job = Self::build_job(trigger, message, context)?;
+            if context.task_repository.is_paused(&context.project.scoped_name(&trigger.task)).await? {
+                info!("Skipping message for trigger '{}', task is paused", trigger_name);
+                return Ok(());
+            }
+            let rate_limit = context.project.workspace.rate_limit_for(&job)?;
+            let job_id = context.job_repository.enqueue_job(&job, "trigger", Some(trigger_name), context.project.workspace.get_revision().as_deref(), rate_limit.as_ref(), None).await?;
+            info!("Enqueued job {} for trigger '{}'", job_id, trigger_name);
+            Ok::<_, Error>(())
+        }.await;
+        if let Err(e) = result {
+            error!("Failed to enqueue job for trigger '{}': {}", trigger_name, e);
+        }
+    }
+
+    fn build_job(trigger: &Trigger, message: NatsMessage, context: &Context) -> Result<JobRequest, Error> {
+        let payload = serde_json::from_slice(&message.payload)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&message.payload).to_string()));
+        let mut input = match &trigger.input {
+            Some(mapping) => {
+                let mut renderer = ParameterRenderer::new();
+                renderer.add_to_context(json!({"payload": payload, "subject": message.subject}))?;
+                match renderer.render(serde_json::to_value(mapping)?)? {
+                    Value::Object(input) => input,
+                    _ => return Err(anyhow!("Trigger input mapping must render to an object")),
+                }
+            }
+            None => match payload {
+                Value::Object(map) => map,
+                payload => Map::from_iter([("payload".to_string(), payload)]),
+            },
+        };
+
+        let workflows_guard = context.project.workspace.workflows.read().map_err(|_| anyhow!("Could not read workspace"))?;
+        let workflows = workflows_guard.as_ref().ok_or_else(|| anyhow!("Workspace not loaded"))?;
+        let task = workflows.get_task(&trigger.task)
+            .ok_or_else(|| anyhow!("Trigger references non-existent task '{}'", trigger.task))?;
+        task.validate_input(&mut input)?;
+        if context.enforce_action_sunset {
+            let sunset = workflows.sunset_actions(Some(&trigger.task), None, Utc::now().date_naive());
+            if !sunset.is_empty() {
+                return Err(anyhow!("Refusing to run past sunset date: {}", sunset.join("; ")));
+            }
+        }
+
+        Ok(JobRequest {
+            spec: JobSpec::Task { name: trigger.task.clone() },
+            input: Some(Value::Object(input)),
+            uuid: None,
+            priority: trigger.priority,
+            resume: None,
+            traceparent: None,
+            project: context.project.job_project(),
+        })
+    }
+
+    pub async fn run(&mut self) {
+        if self.task.is_some() {
+            info!("Message triggers already running");
+            return;
+        }
+
+        let mut cancel_rx = self.cancel_tx.subscribe();
+        let mut config_rx = self.config_rx.clone();
+        let context = self.context.clone();
+
+        let task = tokio::spawn(async move {
+            let mut subscriptions = HashMap::new();
+            loop {
+                Self::load_config(config_rx.borrow().clone(), &mut subscriptions, &context);
+                tokio::select! {
+                    changed = config_rx.changed() => {
+                        if changed.is_err() {
+                            break;
+                        }
+                        info!("Reloading message triggers due to workspace config change");
+                    }
+                    _ = cancel_rx.changed() => {
+                        if *cancel_rx.borrow() {
+                            break;
+                        }
+                    }
+                }
+            }
+            for subscription in subscriptions.values() {
+                subscription.handle.abort();
+            }
+        });
+
+        self.task = Some(task);
+        info!("Message triggers of project '{}' started", self.context.project.name);
+    }
+
+    pub async fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            if let Err(e) = self.cancel_tx.send(true) {
+                error!("Failed to send cancellation signal: {}", e);
+            }
+            let _ = task.await;
+            info!("Message triggers of project '{}' stopped", self.context.project.name);
+        }
+    }
+}
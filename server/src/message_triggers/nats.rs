@@ -0,0 +1,59 @@
+//! Subscriptions of message triggers, on the NATS client: TLS (`tls://` urls or servers requiring it), keep-alive
+//! pings and reconnects come with it.
+use anyhow::{anyhow, Error};
+use async_nats::{Client, ConnectOptions, Subscriber};
+use futures::StreamExt;
+use tokio::time::Duration;
+
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+/// A server that stops answering pings is noticed after a couple of intervals, and reconnected to
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+pub struct NatsMessage {
+    pub subject: String,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Default)]
+pub struct NatsAuth<'a> {
+    pub token: Option<&'a str>,
+    pub user: Option<&'a str>,
+    pub password: Option<&'a str>,
+}
+
+pub struct NatsSubscription {
+    subscriber: Subscriber,
+    // The subscription ends with the last handle of the client
+    _client: Client,
+}
+
+impl NatsSubscription {
+    pub async fn connect(url: &str, subject: &str, queue_group: Option<&str>, auth: &NatsAuth<'_>) -> Result<Self, Error> {
+        let mut options = ConnectOptions::new()
+            .name("stroem")
+            .connection_timeout(CONNECTION_TIMEOUT)
+            .ping_interval(PING_INTERVAL);
+        if let Some(token) = auth.token {
+            options = options.token(token.to_string());
+        }
+        if let (Some(user), Some(password)) = (auth.user, auth.password) {
+            options = options.user_and_password(user.to_string(), password.to_string());
+        }
+        let client = options.connect(url).await
+            .map_err(|e| anyhow!("Failed to connect to NATS server: {}", e))?;
+        let subscriber = match queue_group {
+            Some(queue_group) => client.queue_subscribe(subject.to_string(), queue_group.to_string()).await,
+            None => client.subscribe(subject.to_string()).await,
+        }.map_err(|e| anyhow!("NATS server refused subscription: {}", e))?;
+        // Returns once the server processed the subscription
+        client.flush().await?;
+        Ok(NatsSubscription { subscriber, _client: client })
+    }
+
+    /// Waits for the next message; the client reconnects and subscribes again in between when the connection drops.
+    pub async fn next(&mut self) -> Result<NatsMessage, Error> {
+        let message = self.subscriber.next().await
+            .ok_or_else(|| anyhow!("NATS connection closed"))?;
+        Ok(NatsMessage { subject: message.subject.to_string(), payload: message.payload.to_vec() })
+    }
+}
@@ -0,0 +1,73 @@
+// workflow-server/src/metrics.rs
+//! Prometheus metrics, in the default registry. Histograms are observed where things happen,
+//! gauges are refreshed from the database when `/metrics` is scraped.
+use std::sync::LazyLock;
+use prometheus::{
+    exponential_buckets, register_histogram, register_histogram_vec, register_int_gauge, register_int_gauge_vec,
+    Histogram, HistogramVec, IntGauge, IntGaugeVec,
+};
+
+pub static JOBS_QUEUED: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("stroem_jobs_queued", "Jobs waiting for a worker").unwrap()
+});
+
+pub static JOBS_RUNNING: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("stroem_jobs_running", "Jobs picked up by a worker and not finished yet").unwrap()
+});
+
+/// Per task, or per action for jobs running a single action
+pub static JOB_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "stroem_job_duration_seconds",
+        "Duration of finished jobs",
+        &["task", "status"],
+        exponential_buckets(1.0, 2.0, 16).unwrap()
+    ).unwrap()
+});
+
+pub static SCHEDULER_TICK_LAG: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "stroem_scheduler_tick_lag_seconds",
+        "How late the scheduler fired cron triggers, jitter included",
+        vec![0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 15.0, 60.0]
+    ).unwrap()
+});
+
+/// Labelled with the route pattern rather than the path, to keep job ids out of the labels
+pub static HTTP_REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "stroem_http_request_duration_seconds",
+        "Latency of API, worker and webhook requests",
+        &["method", "route", "status"]
+    ).unwrap()
+});
+
+pub static DB_POOL_CONNECTIONS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!("stroem_db_pool_connections", "Database pool connections", &["state"]).unwrap()
+});
+
+pub static WORKERS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!("stroem_workers", "Registered workers", &["status"]).unwrap()
+});
+
+/// Workers holding a job poll open in push mode, on this server
+pub static DISPATCH_WAITING_WORKERS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("stroem_dispatch_waiting_workers", "Workers waiting for jobs to be pushed to them").unwrap()
+});
+
+pub static DISPATCH_FREE_SLOTS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("stroem_dispatch_free_slots", "Free job slots of the workers waiting for jobs").unwrap()
+});
+
+/// Registers every metric, so histograms are exported before their first observation.
+pub fn init() {
+    LazyLock::force(&JOBS_QUEUED);
+    LazyLock::force(&JOBS_RUNNING);
+    LazyLock::force(&JOB_DURATION);
+    LazyLock::force(&SCHEDULER_TICK_LAG);
+    LazyLock::force(&HTTP_REQUEST_DURATION);
+    LazyLock::force(&DB_POOL_CONNECTIONS);
+    LazyLock::force(&WORKERS);
+    LazyLock::force(&DISPATCH_WAITING_WORKERS);
+    LazyLock::force(&DISPATCH_FREE_SLOTS);
+}
@@ -0,0 +1,269 @@
+// workflow-server/src/notifications.rs
+//! Tells people about finished jobs, and jobs breaching the SLA of their task, through the channels the server configures: Slack and Teams incoming
+//! webhooks, email and plain HTTP webhooks. Targets name a channel, optionally with a recipient, like `slack:#ops`.
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, Url};
+use serde_json::{json, Value};
+use stroem_common::parameter_renderer::ParameterRenderer;
+use stroem_common::workflows_configuration::TaskNotify;
+use tracing::{debug, error, warn};
+use crate::post_process::JobSummary;
+use crate::projects::Projects;
+use crate::repository::JobRepository;
+use crate::server_config::{NotificationChannelConfig, NotificationsConfig};
+
+mod email;
+use email::EmailChannel;
+
+/// A finished job or SLA breach, rendered for people.
+pub struct Notification {
+    pub subject: String,
+    pub text: String,
+    pub job: JobSummary,
+}
+
+#[async_trait]
+pub trait NotificationChannel: Send + Sync {
+    /// `recipient` is what the target names after the channel, if anything
+    async fn send(&self, notification: &Notification, recipient: Option<&str>) -> Result<(), Error>;
+}
+
+pub struct NotificationChannelFactory {}
+impl NotificationChannelFactory {
+    pub fn from_config(config: &NotificationChannelConfig, client: Client, timeout: Duration) -> Result<Arc<dyn NotificationChannel>, Error> {
+        match config {
+            NotificationChannelConfig::Slack { webhook_url } => Ok(Arc::new(SlackChannel {
+                client,
+                webhook_url: webhook_url.clone(),
+                timeout,
+            })),
+            NotificationChannelConfig::Teams { webhook_url } => Ok(Arc::new(TeamsChannel {
+                client,
+                webhook_url: webhook_url.clone(),
+                timeout,
+            })),
+            NotificationChannelConfig::Webhook { url, headers } => {
+                let mut header_map = HeaderMap::new();
+                for (name, value) in headers {
+                    header_map.insert(HeaderName::try_from(name.as_str())?, HeaderValue::from_str(value)?);
+                }
+                Ok(Arc::new(WebhookChannel {
+                    client,
+                    url: url.clone(),
+                    headers: header_map,
+                    timeout,
+                }))
+            }
+            NotificationChannelConfig::Email(smtp) => Ok(Arc::new(EmailChannel::from_config(smtp, timeout)?)),
+        }
+    }
+}
+
+struct SlackChannel {
+    client: Client,
+    webhook_url: Url,
+    timeout: Duration,
+}
+
+#[async_trait]
+impl NotificationChannel for SlackChannel {
+    async fn send(&self, notification: &Notification, recipient: Option<&str>) -> Result<(), Error> {
+        let mut body = json!({"text": notification.text});
+        if let Some(channel) = recipient {
+            body["channel"] = Value::String(channel.to_string());
+        }
+        post(self.client.post(self.webhook_url.clone()).timeout(self.timeout).json(&body)).await
+    }
+}
+
+struct TeamsChannel {
+    client: Client,
+    webhook_url: Url,
+    timeout: Duration,
+}
+
+#[async_trait]
+impl NotificationChannel for TeamsChannel {
+    async fn send(&self, notification: &Notification, _recipient: Option<&str>) -> Result<(), Error> {
+        let body = json!({"title": notification.subject, "text": notification.text});
+        post(self.client.post(self.webhook_url.clone()).timeout(self.timeout).json(&body)).await
+    }
+}
+
+struct WebhookChannel {
+    client: Client,
+    url: Url,
+    headers: HeaderMap,
+    timeout: Duration,
+}
+
+#[async_trait]
+impl NotificationChannel for WebhookChannel {
+    async fn send(&self, notification: &Notification, recipient: Option<&str>) -> Result<(), Error> {
+        let body = json!({
+            "subject": notification.subject,
+            "text": notification.text,
+            "recipient": recipient,
+            "job": notification.job,
+        });
+        post(self.client.post(self.url.clone()).timeout(self.timeout).headers(self.headers.clone()).json(&body)).await
+    }
+}
+
+async fn post(request: reqwest::RequestBuilder) -> Result<(), Error> {
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("Responded {}", response.status()));
+    }
+    Ok(())
+}
+
+/// What a notification is about.
+enum Event {
+    Done { success: bool },
+    /// The job took longer than its task's SLA allows, and may still be running
+    SlaBreach { kind: String, limit_secs: f64, elapsed_secs: f64 },
+}
+
+impl Event {
+    fn targets<'a>(&self, notify: &'a TaskNotify) -> &'a [String] {
+        match self {
+            Event::Done { success } => notify.targets(*success),
+            Event::SlaBreach { .. } => notify.sla_breach_targets(),
+        }
+    }
+}
+
+/// Sends the notifications of finished jobs and SLA breaches; cheap to clone.
+#[derive(Clone)]
+pub struct Notifications {
+    config: Arc<NotificationsConfig>,
+    channels: Arc<HashMap<String, Arc<dyn NotificationChannel>>>,
+    projects: Projects,
+    job_repository: JobRepository,
+    public_url: Url,
+}
+
+impl Notifications {
+    pub fn new(config: &NotificationsConfig, projects: Projects, job_repository: JobRepository, public_url: Url) -> Result<Self, Error> {
+        let client = Client::new();
+        let channels = config.channels.iter()
+            .map(|(name, channel)| {
+                NotificationChannelFactory::from_config(channel, client.clone(), config.timeout)
+                    .map(|channel| (name.clone(), channel))
+                    .map_err(|e| anyhow!("Invalid notification channel '{}': {}", name, e))
+            })
+            .collect::<Result<HashMap<_, _>, Error>>()?;
+        let notify = &config.notify;
+        for target in notify.targets(true).iter().chain(notify.targets(false)).chain(notify.sla_breach_targets()) {
+            if !channels.contains_key(parse_target(target).0) {
+                return Err(anyhow!("Notification target '{}' refers to an unknown channel", target));
+            }
+        }
+        Ok(Notifications {
+            config: Arc::new(config.clone()),
+            channels: Arc::new(channels),
+            projects,
+            job_repository,
+            public_url,
+        })
+    }
+
+    /// Notifies in the background, so the worker reporting the job isn't held up.
+    pub fn job_done(&self, job_id: &str, success: bool) {
+        self.spawn(job_id, Event::Done { success });
+    }
+
+    /// Notifies in the background about a job taking longer than the `kind` of SLA of its task allows.
+    pub fn sla_breach(&self, job_id: &str, kind: &str, limit_secs: f64, elapsed_secs: f64) {
+        self.spawn(job_id, Event::SlaBreach { kind: kind.to_string(), limit_secs, elapsed_secs });
+    }
+
+    fn spawn(&self, job_id: &str, event: Event) {
+        if self.channels.is_empty() {
+            return;
+        }
+        let this = self.clone();
+        let job_id = job_id.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = this.notify(&job_id, event).await {
+                error!("Failed to send notifications for job {}: {}", job_id, e);
+            }
+        });
+    }
+
+    async fn notify(&self, job_id: &str, event: Event) -> Result<(), Error> {
+        let job = self.job_repository.get_job(job_id).await?;
+        let mut targets = event.targets(&self.config.notify).to_vec();
+        let task_notify = match (job.task.as_deref(), self.projects.get(&job.project_id)) {
+            (Some(task), Some(project)) => project.workspace.task_notify(task)?,
+            _ => None,
+        };
+        if let Some(notify) = task_notify {
+            targets.extend_from_slice(event.targets(&notify));
+        }
+        let mut seen = HashSet::new();
+        targets.retain(|target| seen.insert(target.clone()));
+        if targets.is_empty() {
+            return Ok(());
+        }
+
+        let notification = self.render(JobSummary::new(job, &self.public_url), &event)?;
+        for target in &targets {
+            let (name, recipient) = parse_target(target);
+            let Some(channel) = self.channels.get(name) else {
+                warn!("Notification target '{}' of job {} refers to an unknown channel", target, job_id);
+                continue;
+            };
+            match channel.send(&notification, recipient).await {
+                Ok(()) => debug!("Notified {} of job {}", target, job_id),
+                Err(e) => error!("Failed to notify {} of job {}: {}", target, job_id, e),
+            }
+        }
+        Ok(())
+    }
+
+    fn render(&self, job: JobSummary, event: &Event) -> Result<Notification, Error> {
+        let name = job.task.clone().or_else(|| job.action.clone()).unwrap_or_else(|| job.job_id.to_string());
+        let duration = job.duration_secs.map(format_duration).unwrap_or_else(|| "unknown time".to_string());
+        let (subject, template, sla) = match event {
+            Event::Done { .. } => (&self.config.subject, &self.config.template, Value::Null),
+            Event::SlaBreach { kind, limit_secs, elapsed_secs } => (
+                &self.config.sla_breach_subject,
+                &self.config.sla_breach_template,
+                json!({"kind": kind, "limit": format_duration(*limit_secs), "elapsed": format_duration(*elapsed_secs)}),
+            ),
+        };
+        let mut renderer = ParameterRenderer::new();
+        renderer.add_to_context(json!({"job": job, "name": name, "duration": duration, "sla": sla}))?;
+        let rendered = renderer.render(json!({"subject": subject, "text": template}))?;
+        Ok(Notification {
+            subject: rendered["subject"].as_str().unwrap_or_default().to_string(),
+            text: rendered["text"].as_str().unwrap_or_default().to_string(),
+            job,
+        })
+    }
+}
+
+/// Splits `<channel>[:<recipient>]`
+fn parse_target(target: &str) -> (&str, Option<&str>) {
+    match target.split_once(':') {
+        Some((name, recipient)) => (name, Some(recipient)),
+        None => (target, None),
+    }
+}
+
+/// `1h 2m 3s`, `2m 3s` or `3.2s`
+fn format_duration(secs: f64) -> String {
+    let whole = secs as u64;
+    match (whole / 3600, whole % 3600 / 60, whole % 60) {
+        (0, 0, _) => format!("{:.1}s", secs),
+        (0, minutes, seconds) => format!("{}m {}s", minutes, seconds),
+        (hours, minutes, seconds) => format!("{}h {}m {}s", hours, minutes, seconds),
+    }
+}
@@ -0,0 +1,63 @@
+use std::time::Duration;
+use anyhow::{anyhow, bail, Error};
+use async_trait::async_trait;
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use super::{Notification, NotificationChannel};
+use crate::server_config::{SmtpConfig, SmtpTls};
+
+/// Mails through an SMTP server, one connection per notification.
+pub struct EmailChannel {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<String>,
+}
+
+impl EmailChannel {
+    pub fn from_config(config: &SmtpConfig, timeout: Duration) -> Result<Self, Error> {
+        let builder = match config.tls {
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?,
+            // Credentials would go over the network in plain text
+            SmtpTls::None if config.username.is_some() => bail!("Refusing to authenticate to SMTP server {} without TLS", config.host),
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+        };
+        let mut builder = builder.port(config.port).timeout(Some(timeout));
+        if let Some(username) = &config.username {
+            let password = config.password.clone().unwrap_or_default();
+            builder = builder.credentials(Credentials::new(username.clone(), password));
+        }
+        let from = config.from.parse()
+            .map_err(|e| anyhow!("Invalid sender '{}': {}", config.from, e))?;
+        Ok(Self { transport: builder.build(), from, to: config.to.clone() })
+    }
+
+    fn message(&self, notification: &Notification, to: &[&str]) -> Result<Message, Error> {
+        let mut builder = Message::builder()
+            .from(self.from.clone())
+            .subject(notification.subject.replace(['\r', '\n'], " "))
+            .header(ContentType::TEXT_PLAIN);
+        for recipient in to {
+            let mailbox: Mailbox = recipient.parse().map_err(|e| anyhow!("Invalid recipient '{}': {}", recipient, e))?;
+            builder = builder.to(mailbox);
+        }
+        Ok(builder.body(notification.text.clone())?)
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for EmailChannel {
+    async fn send(&self, notification: &Notification, recipient: Option<&str>) -> Result<(), Error> {
+        let to: Vec<&str> = match recipient {
+            Some(recipient) => recipient.split(',').map(str::trim).collect(),
+            None => self.to.iter().map(String::as_str).collect(),
+        };
+        if to.is_empty() {
+            bail!("No recipients, set `to` or name them in the target");
+        }
+        let message = self.message(notification, &to)?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+// workflow-server/src/post_process.rs
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, Url};
+use serde::Serialize;
+use serde_json::Value;
+use tracing::{debug, error, info};
+use uuid::Uuid;
+use stroem_common::rfc3339;
+use crate::repository::{Job, JobRepository};
+use crate::server_config::{PostProcessorConfig, PostProcessorType};
+
+/// What post-processors receive once a job has finished.
+#[derive(Debug, Serialize)]
+pub struct JobSummary {
+    pub job_id: Uuid,
+    pub task: Option<String>,
+    pub action: Option<String>,
+    pub status: Option<String>,
+    pub success: Option<bool>,
+    pub source_type: Option<String>,
+    pub source_id: Option<String>,
+    pub worker_id: Option<String>,
+    pub revision: Option<String>,
+    #[serde(with = "rfc3339::option")]
+    pub start_datetime: Option<DateTime<Utc>>,
+    #[serde(with = "rfc3339::option")]
+    pub end_datetime: Option<DateTime<Utc>>,
+    pub duration_secs: Option<f64>,
+    pub output: Option<Value>,
+    pub steps: Vec<StepSummary>,
+    /// Link to the job in the UI
+    pub url: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StepSummary {
+    pub name: String,
+    pub success: bool,
+    pub status: Option<String>,
+    pub attempts: i32,
+    pub links: Option<Value>,
+}
+
+impl JobSummary {
+    pub fn new(job: Job, public_url: &Url) -> Self {
+        let duration_secs = job.start_datetime.zip(job.end_datetime)
+            .map(|(start, end)| (end - start).num_milliseconds() as f64 / 1000.0);
+        JobSummary {
+            url: format!("{}/jobs/{}", public_url.as_str().trim_end_matches('/'), job.job_id),
+            job_id: job.job_id,
+            task: job.task,
+            action: job.action,
+            status: job.status,
+            success: job.success,
+            source_type: job.source_type,
+            source_id: job.source_id,
+            worker_id: job.worker_id,
+            revision: job.revision,
+            start_datetime: job.start_datetime,
+            end_datetime: job.end_datetime,
+            duration_secs,
+            output: job.output,
+            steps: job.steps.into_iter().map(|step| StepSummary {
+                name: step.name,
+                success: step.success,
+                status: step.status,
+                attempts: step.attempts,
+                links: step.links,
+            }).collect(),
+        }
+    }
+}
+
+/// Integration hook run after a job has finished, e.g. to update a deploy tracker.
+#[async_trait]
+pub trait PostProcessor: Send + Sync {
+    async fn process(&self, summary: &JobSummary) -> Result<(), Error>;
+}
+
+pub struct PostProcessorFactory {}
+impl PostProcessorFactory {
+    pub fn from_config(config: &PostProcessorType) -> Result<Arc<dyn PostProcessor>, Error> {
+        match config {
+            PostProcessorType::Http { url, headers, timeout } => Ok(Arc::new(HttpPostProcessor::new(url.clone(), headers, *timeout)?)),
+        }
+    }
+}
+
+pub struct HttpPostProcessor {
+    client: Client,
+    url: Url,
+    headers: HeaderMap,
+    timeout: Duration,
+}
+
+impl HttpPostProcessor {
+    pub fn new(url: Url, headers: &std::collections::HashMap<String, String>, timeout: Duration) -> Result<Self, Error> {
+        let headers = headers.iter()
+            .map(|(name, value)| Ok((HeaderName::try_from(name.as_str())?, HeaderValue::try_from(value.as_str())?)))
+            .collect::<Result<HeaderMap, Error>>()
+            .map_err(|e| anyhow!("Invalid post-processor header for {}: {}", url, e))?;
+        Ok(HttpPostProcessor { client: Client::new(), url, headers, timeout })
+    }
+}
+
+#[async_trait]
+impl PostProcessor for HttpPostProcessor {
+    async fn process(&self, summary: &JobSummary) -> Result<(), Error> {
+        self.client.post(self.url.clone())
+            .headers(self.headers.clone())
+            .timeout(self.timeout)
+            .json(summary)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+struct ConfiguredPostProcessor {
+    name: String,
+    on: Option<Vec<String>>,
+    tasks: Option<Vec<String>>,
+    processor: Arc<dyn PostProcessor>,
+}
+
+impl ConfiguredPostProcessor {
+    fn applies_to(&self, summary: &JobSummary) -> bool {
+        let listed = |list: &Option<Vec<String>>, value: &Option<String>| match list {
+            Some(list) => value.as_ref().is_some_and(|value| list.contains(value)),
+            None => true,
+        };
+        listed(&self.on, &summary.status) && listed(&self.tasks, &summary.task)
+    }
+}
+
+/// The configured post-processors; cheap to clone.
+#[derive(Clone)]
+pub struct PostProcessors {
+    processors: Arc<Vec<ConfiguredPostProcessor>>,
+    job_repository: JobRepository,
+    public_url: Url,
+}
+
+impl PostProcessors {
+    pub fn new(configs: &[PostProcessorConfig], job_repository: JobRepository, public_url: Url) -> Result<Self, Error> {
+        let processors = configs.iter()
+            .map(|config| Ok(ConfiguredPostProcessor {
+                name: match &config.post_processor_type {
+                    PostProcessorType::Http { url, .. } => url.to_string(),
+                },
+                on: config.on.clone(),
+                tasks: config.tasks.clone(),
+                processor: PostProcessorFactory::from_config(&config.post_processor_type)?,
+            }))
+            .collect::<Result<Vec<_>, Error>>()?;
+        if !processors.is_empty() {
+            info!("Using {} job post-processor(s)", processors.len());
+        }
+        Ok(PostProcessors { processors: Arc::new(processors), job_repository, public_url })
+    }
+
+    /// Runs the matching post-processors in the background, so the worker reporting the result isn't held up.
+    /// Failures are only logged; the job result is already stored.
+    pub fn job_done(&self, job_id: &str) {
+        if self.processors.is_empty() {
+            return;
+        }
+        let this = self.clone();
+        let job_id = job_id.to_string();
+        tokio::spawn(async move {
+            let job = match this.job_repository.get_job(&job_id).await {
+                Ok(job) => job,
+                Err(e) => {
+                    error!("Failed to load job {} for post-processing: {}", job_id, e);
+                    return;
+                }
+            };
+            let summary = JobSummary::new(job, &this.public_url);
+            for processor in this.processors.iter().filter(|processor| processor.applies_to(&summary)) {
+                match processor.processor.process(&summary).await {
+                    Ok(()) => debug!("Post-processor {} done for job {}", processor.name, job_id),
+                    Err(e) => error!("Post-processor {} failed for job {}: {}", processor.name, job_id, e),
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,107 @@
+// workflow-server/src/projects.rs
+//! Projects: workspaces served side by side, each with its own source, workflows, triggers and permissions.
+//! The `workspace` of the server config is the default project. Jobs carry the name of their project, task
+//! pauses and trigger state of the other projects are stored under names scoped to the project.
+use std::collections::BTreeMap;
+use std::fs::create_dir_all;
+use std::sync::Arc;
+use anyhow::{anyhow, Error};
+use tracing::info;
+use stroem_common::{JobRequest, DEFAULT_PROJECT};
+use serde_json::Value;
+use stroem_common::secrets::REDACTED;
+use stroem_common::workflows_configuration::{secret_fields, TaskAcl};
+use crate::repository::Job;
+use crate::server_config::{ServerConfig, WorkspaceSourceConfig};
+use crate::workspace_server::WorkspaceServer;
+
+pub struct Project {
+    pub name: String,
+    pub workspace: Arc<WorkspaceServer>,
+    /// Who can see and run the project's tasks, on top of the ACL of each task
+    pub acl: Option<TaskAcl>,
+}
+
+impl Project {
+    /// Syncs the workspace and watches it for changes.
+    async fn load(name: &str, config: WorkspaceSourceConfig, acl: Option<TaskAcl>) -> Result<Self, Error> {
+        create_dir_all(&config.folder)?;
+        let workspace = Arc::new(WorkspaceServer::new(config).await);
+        let revision = workspace.sync().await?;
+        info!("Workspace sync of project '{}' complete, revision: {}", name, revision.unwrap_or("unknown".to_string()));
+        workspace.read_workflows()?;
+        workspace.clone().watch().await;
+        Ok(Project { name: name.to_string(), workspace, acl })
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.name == DEFAULT_PROJECT
+    }
+
+    /// `project` of the jobs of the project, left out for the default project.
+    pub fn job_project(&self) -> Option<String> {
+        (!self.is_default()).then(|| self.name.clone())
+    }
+
+    /// Name a task pause or trigger state of the project is stored under. Unchanged for the default project,
+    /// so state from before projects carries over.
+    pub fn scoped_name(&self, name: &str) -> String {
+        match self.is_default() {
+            true => name.to_string(),
+            false => format!("{}/{}", self.name, name),
+        }
+    }
+}
+
+/// The projects of the server by name, the default one included; cheap to clone.
+#[derive(Clone)]
+pub struct Projects {
+    projects: Arc<BTreeMap<String, Arc<Project>>>,
+}
+
+impl Projects {
+    pub async fn load(cfg: &ServerConfig) -> Result<Self, Error> {
+        let mut projects = BTreeMap::new();
+        projects.insert(DEFAULT_PROJECT.to_string(), Arc::new(Project::load(DEFAULT_PROJECT, cfg.workspace.clone(), None).await?));
+        for (name, project) in &cfg.projects {
+            projects.insert(name.clone(), Arc::new(Project::load(name, project.workspace.clone(), project.acl.clone()).await?));
+        }
+        Ok(Projects { projects: Arc::new(projects) })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<Project>> {
+        self.projects.get(name)
+    }
+
+    pub fn default_project(&self) -> &Arc<Project> {
+        &self.projects[DEFAULT_PROJECT]
+    }
+
+    /// By name, the default project among them.
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Project>> {
+        self.projects.values()
+    }
+
+    /// The project the job runs in.
+    pub fn of_job(&self, job: &JobRequest) -> Result<&Arc<Project>, Error> {
+        self.get(job.project()).ok_or_else(|| anyhow!("Project '{}' not found", job.project()))
+    }
+
+    /// Masks the values of `secret` input fields of the job, which are only redacted by the worker once it starts.
+    pub fn mask_secret_inputs(&self, job: &mut Job) {
+        let Some(Value::Object(input)) = job.input.as_mut() else { return };
+        let Some(project) = self.get(&job.project_id) else { return };
+        let Ok(workflows_guard) = project.workspace.workflows.read() else { return };
+        let Some(workflows) = workflows_guard.as_ref() else { return };
+        let fields = match (&job.task, &job.action) {
+            (Some(task), _) => workflows.get_task(task).and_then(|t| t.input.as_ref()),
+            (None, Some(action)) => workflows.get_action(action).and_then(|a| a.input.as_ref()),
+            (None, None) => None,
+        };
+        for name in secret_fields(fields) {
+            if let Some(value) = input.get_mut(name) {
+                *value = Value::String(REDACTED.to_string());
+            }
+        }
+    }
+}
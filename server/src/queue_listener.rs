@@ -0,0 +1,90 @@
+// workflow-server/src/queue_listener.rs
+//! Postgres LISTEN for enqueued jobs, so held worker polls wake up within milliseconds, whichever server
+//! the job was enqueued through.
+use tokio::sync::watch;
+use tokio::time::{self, Duration};
+use tracing::{debug, error, info};
+use crate::repository::JobRepository;
+
+/// Delay before trying again after the listener lost its connection
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+pub struct QueueListener {
+    job_repository: JobRepository,
+    task: Option<tokio::task::JoinHandle<()>>,
+    cancel_tx: watch::Sender<bool>,
+}
+
+impl QueueListener {
+    pub fn new(job_repository: JobRepository) -> Self {
+        let (cancel_tx, _) = watch::channel(false);
+        Self {
+            job_repository,
+            task: None,
+            cancel_tx,
+        }
+    }
+
+    pub async fn run(&mut self) {
+        if self.task.is_some() {
+            info!("Queue listener already running");
+            return;
+        }
+
+        let mut cancel_rx = self.cancel_tx.subscribe();
+        let job_repository = self.job_repository.clone();
+
+        let task = tokio::spawn(async move {
+            loop {
+                let mut listener = match job_repository.listen_enqueued().await {
+                    Ok(listener) => listener,
+                    Err(e) => {
+                        error!("Failed to listen for enqueued jobs: {}", e);
+                        tokio::select! {
+                            _ = time::sleep(RETRY_DELAY) => continue,
+                            _ = cancel_rx.changed() => break,
+                        }
+                    }
+                };
+                // Reconnects by itself, notifications sent meanwhile are lost
+                loop {
+                    tokio::select! {
+                        notification = listener.recv() => match notification {
+                            Ok(notification) => {
+                                debug!("Job {} queued", notification.payload());
+                                job_repository.notify_enqueued();
+                            }
+                            Err(e) => {
+                                error!("Lost the listener for enqueued jobs: {}", e);
+                                break;
+                            }
+                        },
+                        _ = cancel_rx.changed() => {
+                            if *cancel_rx.borrow() {
+                                info!("Queue listener stopping due to cancellation signal");
+                                return;
+                            }
+                        }
+                    }
+                }
+                tokio::select! {
+                    _ = time::sleep(RETRY_DELAY) => {}
+                    _ = cancel_rx.changed() => break,
+                }
+            }
+        });
+
+        self.task = Some(task);
+        info!("Queue listener started");
+    }
+
+    pub async fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            if let Err(e) = self.cancel_tx.send(true) {
+                error!("Failed to send cancellation signal: {}", e);
+            }
+            let _ = task.await;
+            info!("Queue listener stopped");
+        }
+    }
+}
@@ -1,5 +1,36 @@
+//! All timestamps are stored as `TIMESTAMP WITH TIME ZONE` (UTC text in SQLite) and handled as UTC.
+//! Database sessions run with `TIME ZONE 'UTC'`, so day-boundary grouping in queries is done in UTC,
+//! matching the UTC dates used on the Rust side (e.g. `Utc::now().date_naive()`).
+//! API responses serialize timestamps through `stroem_common::rfc3339`.
+mod alert;
+mod approval;
+mod artifact;
+mod audit;
+mod db;
+mod delivery;
+mod group;
 mod job;
 mod log;
+mod worker;
+mod queue;
+mod sla;
+mod task;
+mod trigger;
+mod wait;
 
 pub use log::*;
-pub use job::JobRepository;
\ No newline at end of file
+pub use alert::{Alert, AlertRepository};
+pub use approval::{ApprovalRepository, JobApproval};
+pub use artifact::{ArtifactRepository, ArtifactStorage, ArtifactStorageFactory, JobArtifact};
+pub use audit::AuditRepository;
+pub use db::DbPool;
+pub(crate) use db::with_pool;
+pub use delivery::{WebhookDelivery, WebhookDeliveryRepository};
+pub use group::{JobGroup, JobGroupRepository, JobGroupStatus};
+pub use job::{CachedStepOutput, Job, JobFilter, JobRateLimit, JobRepository, JobStep, JobTimeline, StepTimeline, QueueStats, TaskHealth, UsageGroup, UsageRow, WorkerThroughput};
+pub use worker::WorkerRepository;
+pub use queue::{QueueBackend, QueueBackendFactory};
+pub use sla::{SlaBreach, SlaBreachTrend, SlaRepository};
+pub use task::{TaskPause, TaskRepository};
+pub use trigger::TriggerRepository;
+pub use wait::{JobWait, WaitRepository};
@@ -0,0 +1,143 @@
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use stroem_common::rfc3339;
+use super::{with_pool, DbPool};
+
+const ALERT_COLUMNS: &str = "alert_id, rule, project_id, task, status, message, value, threshold, started, updated,
+    acknowledged_at, acknowledged_by, resolved_at, resolved_by";
+
+/// An alert raised by a rule, open until the rule no longer breaches its threshold or someone resolves it.
+#[derive(sqlx::FromRow, Debug, Clone, Serialize, JsonSchema)]
+pub struct Alert {
+    pub alert_id: i64,
+    pub rule: String,
+    /// Project of the task, for rules evaluated per task
+    pub project_id: Option<String>,
+    /// Task the alert is about, for rules evaluated per task
+    pub task: Option<String>,
+    /// `firing`, `acknowledged` or `resolved`
+    pub status: String,
+    pub message: String,
+    /// Latest value of the rule's metric
+    pub value: f64,
+    pub threshold: f64,
+    #[serde(with = "rfc3339")]
+    #[schemars(with = "DateTime<Utc>")]
+    pub started: DateTime<Utc>,
+    #[serde(with = "rfc3339")]
+    #[schemars(with = "DateTime<Utc>")]
+    pub updated: DateTime<Utc>,
+    #[serde(with = "rfc3339::option")]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    pub acknowledged_at: Option<DateTime<Utc>>,
+    pub acknowledged_by: Option<String>,
+    #[serde(with = "rfc3339::option")]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    pub resolved_at: Option<DateTime<Utc>>,
+    /// Unset when the alert resolved by itself
+    pub resolved_by: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct AlertRepository {
+    pool: DbPool,
+}
+
+impl AlertRepository {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    /// Newest first, the resolved ones only if asked for.
+    pub async fn get_alerts(&self, include_resolved: bool, limit: i64) -> Result<Vec<Alert>, Error> {
+        let query = format!(
+            "SELECT {ALERT_COLUMNS} FROM alert WHERE $1 OR status <> 'resolved' ORDER BY alert_id DESC LIMIT $2"
+        );
+        let alerts = with_pool!(&self.pool, pool => sqlx::query_as(&query)
+            .bind(include_resolved)
+            .bind(limit)
+            .fetch_all(pool)
+            .await)?;
+        Ok(alerts)
+    }
+
+    pub async fn get_alert(&self, alert_id: i64) -> Result<Option<Alert>, Error> {
+        let query = format!("SELECT {ALERT_COLUMNS} FROM alert WHERE alert_id = $1");
+        let alert = with_pool!(&self.pool, pool => sqlx::query_as(&query)
+            .bind(alert_id)
+            .fetch_optional(pool)
+            .await)?;
+        Ok(alert)
+    }
+
+    pub async fn fire(&self, rule: &str, project_id: Option<&str>, task: Option<&str>, message: &str, value: f64, threshold: f64) -> Result<i64, Error> {
+        let alert_id = with_pool!(&self.pool, pool => sqlx::query_scalar(
+            "INSERT INTO alert (rule, project_id, task, message, value, threshold) VALUES ($1, $2, $3, $4, $5, $6) RETURNING alert_id",
+        )
+        .bind(rule)
+        .bind(project_id)
+        .bind(task)
+        .bind(message)
+        .bind(value)
+        .bind(threshold)
+        .fetch_one(pool)
+        .await)?;
+        Ok(alert_id)
+    }
+
+    /// Records the latest value of an open alert.
+    pub async fn update(&self, alert_id: i64, message: &str, value: f64, threshold: f64) -> Result<(), Error> {
+        let query = self.pool.sql(
+            "UPDATE alert SET message = $2, value = $3, threshold = $4, updated = NOW() WHERE alert_id = $1",
+            "UPDATE alert SET message = $2, value = $3, threshold = $4, updated = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now') WHERE alert_id = $1",
+        );
+        with_pool!(&self.pool, pool => {
+            sqlx::query(query)
+                .bind(alert_id)
+                .bind(message)
+                .bind(value)
+                .bind(threshold)
+                .execute(pool)
+                .await?;
+        });
+        Ok(())
+    }
+
+    /// Returns false if the alert isn't firing.
+    pub async fn acknowledge(&self, alert_id: i64, by: &str) -> Result<bool, Error> {
+        let query = self.pool.sql(
+            "UPDATE alert SET status = 'acknowledged', acknowledged_at = NOW(), acknowledged_by = $2, updated = NOW()
+             WHERE alert_id = $1 AND status = 'firing'",
+            "UPDATE alert SET status = 'acknowledged', acknowledged_at = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'),
+                acknowledged_by = $2, updated = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')
+             WHERE alert_id = $1 AND status = 'firing'",
+        );
+        let rows = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(alert_id)
+            .bind(by)
+            .execute(pool)
+            .await?
+            .rows_affected());
+        Ok(rows > 0)
+    }
+
+    /// Returns false if the alert is resolved already. `by` is unset when the rule stopped breaching.
+    pub async fn resolve(&self, alert_id: i64, by: Option<&str>) -> Result<bool, Error> {
+        let query = self.pool.sql(
+            "UPDATE alert SET status = 'resolved', resolved_at = NOW(), resolved_by = $2, updated = NOW()
+             WHERE alert_id = $1 AND status <> 'resolved'",
+            "UPDATE alert SET status = 'resolved', resolved_at = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'),
+                resolved_by = $2, updated = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')
+             WHERE alert_id = $1 AND status <> 'resolved'",
+        );
+        let rows = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(alert_id)
+            .bind(by)
+            .execute(pool)
+            .await?
+            .rows_affected());
+        Ok(rows > 0)
+    }
+}
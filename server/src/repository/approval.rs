@@ -0,0 +1,132 @@
+use std::time::Duration;
+use anyhow::{anyhow, Error};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::Value;
+use stroem_common::rfc3339;
+use uuid::Uuid;
+use super::{with_pool, DbPool};
+
+/// Pending approvals past `expires` read as `timed_out`
+const APPROVAL_COLUMNS: &str = "job_id, step_name, approvers, message,
+    CASE WHEN status = 'pending' AND expires <= NOW() THEN 'timed_out' ELSE status END AS status,
+    requested, decided_at, decided_by, comment, expires";
+const SQLITE_APPROVAL_COLUMNS: &str = "job_id, step_name, approvers, message,
+    CASE WHEN status = 'pending' AND julianday(expires) <= julianday('now') THEN 'timed_out' ELSE status END AS status,
+    requested, decided_at, decided_by, comment, expires";
+
+/// The sign-off an approval step of a job waits for.
+#[derive(sqlx::FromRow, Debug, Clone, Serialize, JsonSchema)]
+pub struct JobApproval {
+    pub job_id: Uuid,
+    pub step_name: String,
+    /// User emails or `role:<role>` that may decide, anyone who may run the task when unset
+    #[schemars(with = "Option<Vec<String>>")]
+    pub approvers: Option<Value>,
+    pub message: Option<String>,
+    /// `pending`, `approved`, `rejected` or `timed_out`
+    pub status: String,
+    #[serde(with = "rfc3339")]
+    #[schemars(with = "DateTime<Utc>")]
+    pub requested: DateTime<Utc>,
+    #[serde(with = "rfc3339::option")]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    pub decided_at: Option<DateTime<Utc>>,
+    pub decided_by: Option<String>,
+    pub comment: Option<String>,
+    /// When the approval times out if nobody decided on it
+    #[serde(with = "rfc3339::option")]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl JobApproval {
+    pub fn approvers(&self) -> Option<Vec<String>> {
+        self.approvers.clone().and_then(|approvers| serde_json::from_value(approvers).ok())
+    }
+}
+
+#[derive(Clone)]
+pub struct ApprovalRepository {
+    pool: DbPool,
+}
+
+impl ApprovalRepository {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    fn columns(&self) -> &'static str {
+        self.pool.sql(APPROVAL_COLUMNS, SQLITE_APPROVAL_COLUMNS)
+    }
+
+    /// Records a pending approval for the step, timing out after `timeout`, or returns the one recorded before,
+    /// e.g. before the job was suspended on it.
+    pub async fn request(&self, job_id: &Uuid, step_name: &str, approvers: Option<&[String]>, message: Option<&str>, timeout: Duration) -> Result<JobApproval, Error> {
+        let approvers = approvers.map(serde_json::to_value).transpose()?;
+        let query = self.pool.sql(
+            "INSERT INTO job_approval (job_id, step_name, approvers, message, expires)
+             VALUES ($1, $2, $3, $4, NOW() + make_interval(secs => $5))
+             ON CONFLICT (job_id, step_name) DO NOTHING",
+            "INSERT INTO job_approval (job_id, step_name, approvers, message, expires)
+             VALUES ($1, $2, $3, $4, strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now', '+' || $5 || ' seconds'))
+             ON CONFLICT (job_id, step_name) DO NOTHING",
+        );
+        with_pool!(&self.pool, pool => {
+            sqlx::query(query)
+            .bind(job_id)
+            .bind(step_name)
+            .bind(&approvers)
+            .bind(message)
+            .bind(timeout.as_secs_f64())
+            .execute(pool)
+            .await?;
+        });
+        self.get_approval(job_id, step_name).await?
+            .ok_or_else(|| anyhow!("Approval of step {} of job {} not found", step_name, job_id))
+    }
+
+    pub async fn get_approval(&self, job_id: &Uuid, step_name: &str) -> Result<Option<JobApproval>, Error> {
+        let query = format!("SELECT {} FROM job_approval WHERE job_id = $1 AND step_name = $2", self.columns());
+        let approval = with_pool!(&self.pool, pool => sqlx::query_as(&query)
+            .bind(job_id)
+            .bind(step_name)
+            .fetch_optional(pool)
+            .await)?;
+        Ok(approval)
+    }
+
+    /// Approvals of a job, in the order they were requested.
+    pub async fn get_approvals(&self, job_id: &Uuid) -> Result<Vec<JobApproval>, Error> {
+        let query = format!("SELECT {} FROM job_approval WHERE job_id = $1 ORDER BY requested", self.columns());
+        let approvals = with_pool!(&self.pool, pool => sqlx::query_as(&query)
+            .bind(job_id)
+            .fetch_all(pool)
+            .await)?;
+        Ok(approvals)
+    }
+
+    /// Sets a pending approval of an unfinished job to `approved` or `rejected`, returns false if there is none or
+    /// it timed out.
+    pub async fn decide(&self, job_id: &Uuid, step_name: &str, approved: bool, by: &str, comment: Option<&str>) -> Result<bool, Error> {
+        let query = self.pool.sql(
+            "UPDATE job_approval SET status = $3, decided_at = NOW(), decided_by = $4, comment = $5
+             WHERE job_id = $1 AND step_name = $2 AND status = 'pending' AND (expires IS NULL OR expires > NOW())
+               AND EXISTS (SELECT 1 FROM job WHERE job.job_id = job_approval.job_id AND job.end_datetime IS NULL)",
+            "UPDATE job_approval SET status = $3, decided_at = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'), decided_by = $4, comment = $5
+             WHERE job_id = $1 AND step_name = $2 AND status = 'pending' AND (expires IS NULL OR julianday(expires) > julianday('now'))
+               AND EXISTS (SELECT 1 FROM job WHERE job.job_id = job_approval.job_id AND job.end_datetime IS NULL)",
+        );
+        let rows = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(job_id)
+            .bind(step_name)
+            .bind(if approved { "approved" } else { "rejected" })
+            .bind(by)
+            .bind(comment)
+            .execute(pool)
+            .await?
+            .rows_affected());
+        Ok(rows > 0)
+    }
+}
@@ -0,0 +1,155 @@
+use std::path::Path;
+use std::sync::Arc;
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use stroem_common::rfc3339;
+use tokio::io::AsyncRead;
+use uuid::Uuid;
+use crate::server_config::{ArtifactStorageConfig, LogStorageType};
+use super::{with_pool, DbPool};
+
+mod local;
+use local::ArtifactStorageLocal;
+
+mod aws_s3;
+use aws_s3::ArtifactStorageAWSS3;
+
+/// A file a step of the job uploaded, see `stroem_common::artifacts`.
+#[derive(sqlx::FromRow, Debug, Clone, Serialize, JsonSchema)]
+pub struct JobArtifact {
+    pub job_id: Uuid,
+    pub step_name: String,
+    /// Path relative to the workspace, with `/` separators
+    pub name: String,
+    /// In bytes
+    pub size: i64,
+    #[serde(with = "rfc3339")]
+    #[schemars(with = "DateTime<Utc>")]
+    pub created: DateTime<Utc>,
+}
+
+impl JobArtifact {
+    fn key(&self) -> String {
+        artifact_key(&self.job_id, &self.step_name, &self.name)
+    }
+}
+
+fn artifact_key(job_id: &Uuid, step_name: &str, name: &str) -> String {
+    format!("{}/{}/{}", job_id, step_name, name)
+}
+
+/// Where the artifact files are kept, by key.
+#[async_trait]
+pub trait ArtifactStorage: Send + Sync {
+    /// Stores the file at `path` under `key`, replacing any stored before.
+    async fn store(&self, key: &str, path: &Path) -> Result<(), Error>;
+    async fn open(&self, key: &str) -> Result<Box<dyn AsyncRead + Send + Unpin>, Error>;
+    /// Deletes the files whose keys start with `prefix`.
+    async fn delete_prefix(&self, prefix: &str) -> Result<(), Error>;
+}
+
+pub struct ArtifactStorageFactory {}
+impl ArtifactStorageFactory {
+    pub async fn from_config(config: &ArtifactStorageConfig) -> Result<Arc<dyn ArtifactStorage>, Error> {
+        match &config.storage_type {
+            LogStorageType::Local { folder } => Ok(Arc::new(ArtifactStorageLocal::new(folder.clone()))),
+            LogStorageType::S3 {
+                aws_access_key_id,
+                aws_secret_access_key,
+                aws_region,
+                bucket,
+                prefix,
+                endpoint,
+            } => {
+                let client = super::s3_client(aws_access_key_id.clone(), aws_secret_access_key.clone(), aws_region.clone(), endpoint.clone()).await;
+                Ok(Arc::new(ArtifactStorageAWSS3::new(client, bucket.clone(), prefix.clone())))
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ArtifactRepository {
+    pool: DbPool,
+    storage: Option<Arc<dyn ArtifactStorage>>,
+    max_size: u64,
+}
+
+impl ArtifactRepository {
+    pub fn new(pool: DbPool, storage: Option<Arc<dyn ArtifactStorage>>, max_size: u64) -> Self {
+        Self { pool, storage, max_size }
+    }
+
+    /// Artifacts are only accepted with a storage configured
+    pub fn is_enabled(&self) -> bool {
+        self.storage.is_some()
+    }
+
+    /// Largest artifact accepted, in bytes
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    fn storage(&self) -> Result<&dyn ArtifactStorage, Error> {
+        self.storage.as_deref().ok_or_else(|| anyhow!("No artifact storage configured"))
+    }
+
+    /// Stores the file at `path` as an artifact of the step, replacing one with the same name, e.g. of an earlier attempt.
+    pub async fn save_artifact(&self, job_id: &Uuid, step_name: &str, name: &str, path: &Path, size: i64) -> Result<(), Error> {
+        self.storage()?.store(&artifact_key(job_id, step_name, name), path).await?;
+        let query = self.pool.sql(
+            "INSERT INTO job_artifact (job_id, step_name, name, size) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (job_id, step_name, name) DO UPDATE SET size = EXCLUDED.size, created = NOW()",
+            "INSERT INTO job_artifact (job_id, step_name, name, size) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (job_id, step_name, name) DO UPDATE SET size = excluded.size, created = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')",
+        );
+        with_pool!(&self.pool, pool => {
+            sqlx::query(query)
+                .bind(job_id)
+                .bind(step_name)
+                .bind(name)
+                .bind(size)
+                .execute(pool)
+                .await?;
+        });
+        Ok(())
+    }
+
+    /// In the order they were uploaded.
+    pub async fn get_artifacts(&self, job_id: &Uuid) -> Result<Vec<JobArtifact>, Error> {
+        let artifacts = with_pool!(&self.pool, pool => sqlx::query_as(
+            "SELECT job_id, step_name, name, size, created FROM job_artifact WHERE job_id = $1 ORDER BY created, step_name, name",
+        )
+        .bind(job_id)
+        .fetch_all(pool)
+        .await)?;
+        Ok(artifacts)
+    }
+
+    pub async fn get_artifact(&self, job_id: &Uuid, step_name: &str, name: &str) -> Result<Option<JobArtifact>, Error> {
+        let artifact = with_pool!(&self.pool, pool => sqlx::query_as(
+            "SELECT job_id, step_name, name, size, created FROM job_artifact WHERE job_id = $1 AND step_name = $2 AND name = $3",
+        )
+        .bind(job_id)
+        .bind(step_name)
+        .bind(name)
+        .fetch_optional(pool)
+        .await)?;
+        Ok(artifact)
+    }
+
+    pub async fn open_artifact(&self, artifact: &JobArtifact) -> Result<Box<dyn AsyncRead + Send + Unpin>, Error> {
+        self.storage()?.open(&artifact.key()).await
+    }
+
+    /// Removes the files of the job's artifacts from storage; their rows are deleted with the job.
+    pub async fn delete_artifacts(&self, job_id: &str) -> Result<(), Error> {
+        match &self.storage {
+            Some(storage) => storage.delete_prefix(&format!("{}/", job_id)).await,
+            None => Ok(()),
+        }
+    }
+}
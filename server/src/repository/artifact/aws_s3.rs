@@ -0,0 +1,87 @@
+use async_trait::async_trait;
+use std::path::Path;
+use anyhow::{Context, Error};
+use aws_sdk_s3::Client;
+use aws_sdk_s3::primitives::ByteStream;
+use tokio::io::AsyncRead;
+use crate::repository::ArtifactStorage;
+
+#[derive(Clone)]
+pub struct ArtifactStorageAWSS3 {
+    client: Client,
+    bucket: String,
+    prefix: Option<String>,
+}
+
+impl ArtifactStorageAWSS3 {
+    pub fn new(client: Client, bucket: String, prefix: Option<String>) -> Self {
+        Self { client, bucket, prefix }
+    }
+
+    fn get_s3_key(&self, key: &str) -> String {
+        match &self.prefix {
+            Some(prefix) => format!("{}/{}", prefix.trim_end_matches('/'), key),
+            None => key.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl ArtifactStorage for ArtifactStorageAWSS3 {
+    async fn store(&self, key: &str, path: &Path) -> Result<(), Error> {
+        let key = self.get_s3_key(key);
+        let body = ByteStream::from_path(path).await
+            .with_context(|| format!("Failed to stream file {}", path.display()))?;
+
+        self.client.put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .body(body)
+            .send()
+            .await
+            .with_context(|| format!("Failed to upload artifact {} to S3", key))?;
+
+        Ok(())
+    }
+
+    async fn open(&self, key: &str) -> Result<Box<dyn AsyncRead + Send + Unpin>, Error> {
+        let key = self.get_s3_key(key);
+        let resp = self.client.get_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+            .with_context(|| format!("Failed to retrieve artifact {} from S3", key))?;
+
+        Ok(Box::new(resp.body.into_async_read()))
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> Result<(), Error> {
+        let prefix = self.get_s3_key(prefix);
+        let mut continuation_token = None;
+        loop {
+            let resp = self.client.list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(&prefix)
+                .set_continuation_token(continuation_token)
+                .send()
+                .await
+                .with_context(|| format!("Failed to list artifacts {} in S3", prefix))?;
+
+            for key in resp.contents().iter().filter_map(|object| object.key()) {
+                self.client.delete_object()
+                    .bucket(&self.bucket)
+                    .key(key)
+                    .send()
+                    .await
+                    .with_context(|| format!("Failed to delete artifact {} from S3", key))?;
+            }
+
+            match resp.next_continuation_token() {
+                Some(token) => continuation_token = Some(token.to_string()),
+                None => break,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+use crate::repository::ArtifactStorage;
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Error};
+use tokio::fs;
+use tokio::io::AsyncRead;
+
+#[derive(Clone)]
+pub struct ArtifactStorageLocal {
+    storage_dir: PathBuf,
+}
+
+impl ArtifactStorageLocal {
+    pub fn new(storage_dir: PathBuf) -> Self {
+        Self { storage_dir }
+    }
+}
+
+#[async_trait]
+impl ArtifactStorage for ArtifactStorageLocal {
+    async fn store(&self, key: &str, path: &Path) -> Result<(), Error> {
+        let target = self.storage_dir.join(key);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::copy(path, &target).await
+            .with_context(|| format!("Failed to store artifact {}", target.display()))?;
+        Ok(())
+    }
+
+    async fn open(&self, key: &str) -> Result<Box<dyn AsyncRead + Send + Unpin>, Error> {
+        let path = self.storage_dir.join(key);
+        let file = fs::File::open(&path).await
+            .with_context(|| format!("Failed to open artifact {}", path.display()))?;
+        Ok(Box::new(file))
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> Result<(), Error> {
+        // Prefixes are whole directories, `<job_id>/`
+        match fs::remove_dir_all(self.storage_dir.join(prefix)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
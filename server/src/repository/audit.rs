@@ -0,0 +1,60 @@
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use stroem_common::rfc3339;
+use tracing::info;
+use super::{with_pool, DbPool};
+
+/// Record of a destructive action taken through the API.
+#[derive(sqlx::FromRow, Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    pub audit_id: i64,
+    #[serde(with = "rfc3339")]
+    pub time: DateTime<Utc>,
+    /// Email of the user
+    pub actor: String,
+    /// e.g. `job.delete`
+    pub action: String,
+    pub target: String,
+    pub details: Option<Value>,
+}
+
+#[derive(Clone)]
+pub struct AuditRepository {
+    pool: DbPool,
+}
+
+impl AuditRepository {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn record(&self, actor: &str, action: &str, target: &str, details: Option<Value>) -> Result<(), Error> {
+        with_pool!(&self.pool, pool => {
+            sqlx::query("INSERT INTO audit_log (actor, action, target, details) VALUES ($1, $2, $3, $4)")
+                .bind(actor)
+                .bind(action)
+                .bind(target)
+                .bind(&details)
+                .execute(pool)
+                .await?;
+        });
+        info!("Audit: {} by {} on {}", action, actor, target);
+        Ok(())
+    }
+
+    /// Most recent entries first.
+    pub async fn get_entries(&self, limit: i64) -> Result<Vec<AuditEntry>, Error> {
+        let entries = with_pool!(&self.pool, pool => sqlx::query_as(
+            "SELECT audit_id, time, actor, action, target, details
+             FROM audit_log
+             ORDER BY time DESC, audit_id DESC
+             LIMIT $1",
+        )
+        .bind(limit)
+        .fetch_all(pool)
+        .await)?;
+        Ok(entries)
+    }
+}
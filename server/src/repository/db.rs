@@ -0,0 +1,59 @@
+// workflow-server/src/repository/db.rs
+//! The database behind the repositories: Postgres, or a single SQLite file for evaluations and edge
+//! deployments. Queries both accept are written once and run through `with_pool!`, the others pick
+//! their SQL with `DbPool::sql`.
+//!
+//! SQLite stores timestamps as RFC 3339 text in UTC, so they compare and sort as text; its queries use
+//! `strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')` for `NOW()`. UUIDs are stored as 16 byte blobs and JSON as text.
+use sqlx::migrate::Migrator;
+use sqlx::{PgPool, SqlitePool};
+use uuid::Uuid;
+
+/// Runs `$body` with `$pool` bound to the concrete pool; the body is compiled once per database,
+/// so the same query building code serves both.
+macro_rules! with_pool {
+    ($db:expr, $pool:ident => $body:expr) => {
+        match $db {
+            $crate::repository::DbPool::Postgres($pool) => $body,
+            $crate::repository::DbPool::Sqlite($pool) => $body,
+        }
+    };
+}
+pub(crate) use with_pool;
+
+static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("./migrations/sqlite");
+
+#[derive(Clone, Debug)]
+pub enum DbPool {
+    Postgres(PgPool),
+    Sqlite(SqlitePool),
+}
+
+impl DbPool {
+    /// Picks the SQL for this database, for queries where the dialects differ.
+    pub fn sql<'a>(&self, postgres: &'a str, sqlite: &'a str) -> &'a str {
+        match self {
+            DbPool::Postgres(_) => postgres,
+            DbPool::Sqlite(_) => sqlite,
+        }
+    }
+
+    pub fn migrator(&self) -> &'static Migrator {
+        match self {
+            DbPool::Postgres(_) => &POSTGRES_MIGRATOR,
+            DbPool::Sqlite(_) => &SQLITE_MIGRATOR,
+        }
+    }
+
+    /// Connections of the pool, total and idle.
+    pub fn stats(&self) -> (u32, usize) {
+        with_pool!(self, pool => (pool.size(), pool.num_idle()))
+    }
+}
+
+/// Binds a list of UUIDs for SQLite, which has no arrays: `job_id IN (SELECT unhex(value) FROM json_each($1))`
+/// stands in for Postgres' `job_id = ANY($1)`.
+pub(crate) fn uuid_list(ids: &[Uuid]) -> String {
+    serde_json::Value::from(ids.iter().map(|id| id.simple().to_string()).collect::<Vec<_>>()).to_string()
+}
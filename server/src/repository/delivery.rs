@@ -0,0 +1,85 @@
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use stroem_common::rfc3339;
+use uuid::Uuid;
+use super::{with_pool, DbPool};
+
+/// One webhook call for a job event, with the outcome of its latest attempt.
+#[derive(sqlx::FromRow, Debug, Clone, Serialize)]
+pub struct WebhookDelivery {
+    pub delivery_id: i64,
+    pub job_id: Uuid,
+    /// `on_start`, `on_success` or `on_failure`
+    pub event: String,
+    pub url: String,
+    /// `pending`, `delivered` or `failed`
+    pub status: String,
+    pub attempts: i32,
+    /// HTTP status of the latest response, if one was received
+    pub response_status: Option<i32>,
+    pub error: Option<String>,
+    #[serde(with = "rfc3339")]
+    pub created: DateTime<Utc>,
+    #[serde(with = "rfc3339")]
+    pub updated: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub struct WebhookDeliveryRepository {
+    pool: DbPool,
+}
+
+impl WebhookDeliveryRepository {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn create(&self, job_id: &Uuid, event: &str, url: &str) -> Result<i64, Error> {
+        let delivery_id = with_pool!(&self.pool, pool => sqlx::query_scalar(
+            "INSERT INTO job_webhook_delivery (job_id, event, url) VALUES ($1, $2, $3) RETURNING delivery_id",
+        )
+        .bind(job_id)
+        .bind(event)
+        .bind(url)
+        .fetch_one(pool)
+        .await)?;
+        Ok(delivery_id)
+    }
+
+    pub async fn record_attempt(&self, delivery_id: i64, status: &str, attempts: i32, response_status: Option<i32>, error: Option<&str>) -> Result<(), Error> {
+        let query = self.pool.sql(
+            "UPDATE job_webhook_delivery
+             SET status = $2, attempts = $3, response_status = $4, error = $5, updated = NOW()
+             WHERE delivery_id = $1",
+            "UPDATE job_webhook_delivery
+             SET status = $2, attempts = $3, response_status = $4, error = $5, updated = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')
+             WHERE delivery_id = $1",
+        );
+        with_pool!(&self.pool, pool => {
+            sqlx::query(query)
+                .bind(delivery_id)
+                .bind(status)
+                .bind(attempts)
+                .bind(response_status)
+                .bind(error)
+                .execute(pool)
+                .await?;
+        });
+        Ok(())
+    }
+
+    /// Oldest first.
+    pub async fn get_deliveries(&self, job_id: &Uuid) -> Result<Vec<WebhookDelivery>, Error> {
+        let deliveries = with_pool!(&self.pool, pool => sqlx::query_as(
+            "SELECT delivery_id, job_id, event, url, status, attempts, response_status, error, created, updated
+             FROM job_webhook_delivery
+             WHERE job_id = $1
+             ORDER BY delivery_id",
+        )
+        .bind(job_id)
+        .fetch_all(pool)
+        .await)?;
+        Ok(deliveries)
+    }
+}
@@ -0,0 +1,116 @@
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use stroem_common::rfc3339;
+use uuid::Uuid;
+use super::{with_pool, DbPool, Job};
+
+/// Related jobs followed as one run, e.g. a fan-out batch, the sub-tasks a job enqueued, or a job and its re-runs.
+#[derive(sqlx::FromRow, Debug, Clone, Serialize, JsonSchema)]
+pub struct JobGroup {
+    pub group_id: Uuid,
+    pub name: String,
+    /// What started the group: `user`, or `rerun` for a job and its re-runs
+    pub source: String,
+    #[serde(with = "rfc3339")]
+    #[schemars(with = "DateTime<Utc>")]
+    pub created: DateTime<Utc>,
+}
+
+/// Job counts of a group by status, and the status of the group as a whole.
+#[derive(Debug, Default, Serialize)]
+pub struct JobGroupStatus {
+    /// `failed` if any job failed, `running` while jobs are queued or running, `succeeded` once all completed,
+    /// `empty` without jobs
+    pub status: &'static str,
+    pub queued: usize,
+    pub running: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+impl JobGroupStatus {
+    pub fn of(jobs: &[Job]) -> Self {
+        let mut counts = JobGroupStatus::default();
+        for job in jobs {
+            match job.status.as_deref() {
+                Some("running") => counts.running += 1,
+                Some("completed") => counts.succeeded += 1,
+                Some("failed") => counts.failed += 1,
+                _ => counts.queued += 1,
+            }
+        }
+        counts.status = if counts.failed > 0 {
+            "failed"
+        } else if counts.queued + counts.running > 0 {
+            "running"
+        } else if counts.succeeded > 0 {
+            "succeeded"
+        } else {
+            "empty"
+        };
+        counts
+    }
+}
+
+#[derive(Clone)]
+pub struct JobGroupRepository {
+    pool: DbPool,
+}
+
+impl JobGroupRepository {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn create_group(&self, name: &str, source: &str) -> Result<JobGroup, Error> {
+        let group = with_pool!(&self.pool, pool => sqlx::query_as(
+            "INSERT INTO job_group (group_id, name, source) VALUES ($1, $2, $3) RETURNING group_id, name, source, created",
+        )
+        .bind(Uuid::new_v4())
+        .bind(name)
+        .bind(source)
+        .fetch_one(pool)
+        .await)?;
+        Ok(group)
+    }
+
+    pub async fn get_group(&self, group_id: &Uuid) -> Result<Option<JobGroup>, Error> {
+        let group = with_pool!(&self.pool, pool => sqlx::query_as(
+            "SELECT group_id, name, source, created FROM job_group WHERE group_id = $1",
+        )
+        .bind(group_id)
+        .fetch_optional(pool)
+        .await)?;
+        Ok(group)
+    }
+
+    /// Jobs of the group in the order they were queued, without soft-deleted ones.
+    pub async fn get_group_jobs(&self, group_id: &Uuid) -> Result<Vec<Job>, Error> {
+        let jobs = with_pool!(&self.pool, pool => sqlx::query_as(
+            "SELECT
+                job_id, success, task_name, action_name, input, output, worker_id,
+                status, source_type, source_id, start_datetime, end_datetime, revision, priority, enqueue_revision, failure_reason, deleted, group_id, project_id, suspended
+             FROM job
+             WHERE group_id = $1 AND deleted IS NULL
+             ORDER BY queued",
+        )
+        .bind(group_id)
+        .fetch_all(pool)
+        .await)?;
+        Ok(jobs)
+    }
+
+    /// Puts the job in the group unless it's already in one, and returns the group it ends up in.
+    pub async fn join_group(&self, job_id: &Uuid, group_id: &Uuid) -> Result<Option<Uuid>, Error> {
+        let group_id = with_pool!(&self.pool, pool => sqlx::query_scalar(
+            "UPDATE job SET group_id = COALESCE(group_id, $1) WHERE job_id = $2 RETURNING group_id",
+        )
+        .bind(group_id)
+        .bind(job_id)
+        .fetch_optional(pool)
+        .await)?;
+        Ok(group_id.flatten())
+    }
+}
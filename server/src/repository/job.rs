@@ -1,30 +1,52 @@
 use anyhow::{Error, bail};
 use chrono::{DateTime, Utc};
 use serde_json::Value;
-use sqlx::PgPool;
-use sqlx::Row;
-use tracing::{debug, error, info};
+use sqlx::postgres::PgListener;
+use tracing::{debug, error, info, info_span};
 
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 use uuid::Uuid;
-use stroem_common::{JobRequest, JobResult};
+use stroem_common::log_collector::LogEntry;
+use stroem_common::{protocol, rfc3339, telemetry, JobRequest, JobResult, JobSpec, ResumeState, DEFAULT_PROJECT, REASON_INVALID_JOB};
+use std::sync::Arc;
+use tokio::sync::Notify;
+use std::collections::HashMap;
+use super::QueueBackend;
+use super::{with_pool, DbPool};
+use super::db::uuid_list;
+use crate::metrics;
+use crate::workspace_source::CommitInfo;
 
-#[derive(sqlx::FromRow, Debug, Serialize, Deserialize)]
+#[derive(sqlx::FromRow, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct JobStep {
     pub success: bool,
     pub name: String,
     pub input: Option<Value>,
     pub output: Option<Value>,
+    #[serde(with = "rfc3339")]
+    #[schemars(with = "DateTime<Utc>")]
     pub start_datetime: DateTime<Utc>,
+    #[serde(with = "rfc3339")]
+    #[schemars(with = "DateTime<Utc>")]
     pub end_datetime: DateTime<Utc>,
+    pub attempts: i32,
+    pub status: Option<String>,
+    pub links: Option<Value>,
+    /// Environment variables and tool versions recorded at step start, if enabled
+    pub environment: Option<Value>,
 }
 
-#[derive(sqlx::FromRow, Debug, Serialize, Deserialize)]
+#[derive(sqlx::FromRow, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Job {
     pub worker_id: Option<String>,
     pub job_id: Uuid,
     pub success: Option<bool>,
+    #[serde(with = "rfc3339::option")]
+    #[schemars(with = "Option<DateTime<Utc>>")]
     pub start_datetime: Option<DateTime<Utc>>,
+    #[serde(with = "rfc3339::option")]
+    #[schemars(with = "Option<DateTime<Utc>>")]
     pub end_datetime: Option<DateTime<Utc>>,
     #[sqlx(rename = "task_name")]
     pub task: Option<String>,
@@ -35,19 +57,270 @@ pub struct Job {
     pub source_type: Option<String>,
     pub source_id: Option<String>,
     pub status: Option<String>,
+    /// Project whose workspace the job runs in
+    pub project_id: String,
+    /// Workspace revision the runner used
     pub revision: Option<String>,
+    /// Workspace revision of the server when the job was enqueued
+    pub enqueue_revision: Option<String>,
+    /// Workspace revision of the server right now, filled in by the API
+    #[sqlx(skip)]
+    pub current_revision: Option<String>,
+    /// Commit of `revision` for git workspaces, filled in by the API
+    #[sqlx(skip)]
+    pub commit: Option<CommitInfo>,
+    pub priority: i32,
+    /// Why the job failed without running to completion, e.g. `worker_shutdown`
+    pub failure_reason: Option<String>,
+    /// Group of related jobs the job belongs to, e.g. the re-runs of one job
+    pub group_id: Option<Uuid>,
+    /// Set when the job was soft-deleted; the API treats it as gone
+    #[serde(with = "rfc3339::option")]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    pub deleted: Option<DateTime<Utc>>,
+    /// Set while the job waits for a `wait` or `wait_for_event` step without a worker
+    #[serde(with = "rfc3339::option")]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    pub suspended: Option<DateTime<Utc>>,
     #[sqlx(skip)]
     pub steps: Vec<JobStep>,
 }
 
+/// Which jobs to list; unset fields don't filter.
+#[derive(Debug, Default)]
+pub struct JobFilter {
+    /// Queued at or after
+    pub from: Option<DateTime<Utc>>,
+    /// Queued before
+    pub to: Option<DateTime<Utc>>,
+    pub status: Option<String>,
+    pub task: Option<String>,
+    pub source_type: Option<String>,
+    pub worker_id: Option<String>,
+    /// Projects to list jobs of, all of them if unset
+    pub projects: Option<Vec<String>>,
+    /// Tasks to leave out, as `<project>/<task>`
+    pub hidden_tasks: Vec<String>,
+}
+
+/// Snapshot of the queue used for autoscaling decisions.
+#[derive(sqlx::FromRow, Debug, Serialize)]
+pub struct QueueStats {
+    pub queued: i64,
+    pub running: i64,
+    /// Average duration of the jobs finished in the last hour
+    pub avg_duration_secs: Option<f64>,
+    /// How long the oldest queued job has been waiting
+    pub oldest_wait_secs: Option<f64>,
+}
+
+/// Jobs of one task, or of one action run on its own, waiting for or running on a worker.
+#[derive(sqlx::FromRow, Debug, Serialize, JsonSchema)]
+pub struct TaskQueueDepth {
+    pub project: String,
+    pub task: Option<String>,
+    pub queued: i64,
+    pub running: i64,
+    /// How long the oldest queued job has been waiting
+    pub oldest_wait_secs: Option<f64>,
+}
+
+/// How long jobs waited in the queue before a worker started them.
+#[derive(sqlx::FromRow, Debug, Serialize, JsonSchema)]
+pub struct QueueWait {
+    /// Unset for the jobs of all tasks
+    pub project: Option<String>,
+    /// Unset for the jobs of all tasks
+    pub task: Option<String>,
+    pub jobs: i64,
+    pub p50_secs: Option<f64>,
+    pub p95_secs: Option<f64>,
+    pub max_secs: Option<f64>,
+}
+
+/// Jobs one worker finished in a time bucket.
+#[derive(sqlx::FromRow, Debug, Serialize, JsonSchema)]
+pub struct WorkerThroughput {
+    pub worker_id: String,
+    /// Start of the bucket
+    #[serde(with = "rfc3339")]
+    #[schemars(with = "DateTime<Utc>")]
+    pub bucket: DateTime<Utc>,
+    pub jobs: i64,
+    pub failed: i64,
+    pub compute_seconds: f64,
+}
+
+/// Recent jobs of one task, or of one action run on its own, for alert rules.
+#[derive(sqlx::FromRow, Debug)]
+pub struct TaskHealth {
+    pub project_id: String,
+    pub task: String,
+    /// Jobs finished in the window
+    pub finished: i64,
+    pub failed: i64,
+    /// How long the longest running job has been running
+    pub longest_running_secs: Option<f64>,
+}
+
+/// A job deleted by retention pruning.
+#[derive(sqlx::FromRow, Debug)]
+pub struct PrunedJob {
+    pub job_id: String,
+    pub steps: i64,
+}
+
+/// Characters of a log message kept for the search, longer messages are only found by their start
+const INDEXED_LOG_MESSAGE_LENGTH: usize = 1000;
+/// Timestamps as SQLite stores them, see `strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')`
+const SQLITE_TIMESTAMP: &str = "%Y-%m-%dT%H:%M:%.3f+00:00";
+
+/// A log line found by `JobRepository::search_logs`.
+#[derive(sqlx::FromRow, Debug)]
+pub struct JobLogLine {
+    pub job_id: Uuid,
+    pub step_name: Option<String>,
+    pub timestamp: DateTime<Utc>,
+    pub message: String,
+}
+
+/// Output of a step stored under its cache key, see `stroem_common::step_cache`.
+#[derive(sqlx::FromRow, Debug, Serialize, JsonSchema)]
+pub struct CachedStepOutput {
+    pub output: Option<Value>,
+    /// Job and step that produced the output
+    pub job_id: Uuid,
+    pub step_name: String,
+}
+
+/// Compute time of finished jobs for one group and worker.
+#[derive(sqlx::FromRow, Debug)]
+pub struct UsageRow {
+    pub key: Option<String>,
+    pub worker_labels: Option<Value>,
+    pub jobs: i64,
+    pub compute_seconds: f64,
+}
+
+/// What usage is aggregated by.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UsageGroup {
+    Task,
+    Action,
+    Worker,
+    Source,
+    /// A worker label, e.g. `team`
+    Label(String),
+}
+
+/// SQL condition on a suspended `job` row: none of its waits is still waiting and none of its approvals is still
+/// pending, approvals past `expires` time out.
+const NOTHING_AWAITED: &str = "NOT EXISTS (SELECT 1 FROM job_wait WHERE job_wait.job_id = job.job_id AND job_wait.status = 'waiting')
+    AND NOT EXISTS (SELECT 1 FROM job_approval a WHERE a.job_id = job.job_id AND a.status = 'pending' AND (a.expires IS NULL OR a.expires > NOW()))";
+const SQLITE_NOTHING_AWAITED: &str = "NOT EXISTS (SELECT 1 FROM job_wait WHERE job_wait.job_id = job.job_id AND job_wait.status = 'waiting')
+    AND NOT EXISTS (SELECT 1 FROM job_approval a WHERE a.job_id = job.job_id AND a.status = 'pending'
+        AND (a.expires IS NULL OR julianday(a.expires) > julianday('now')))";
+
+/// The columns of a leased job that make up its `JobRequest`.
+#[derive(sqlx::FromRow)]
+struct LeasedJob {
+    job_id: Uuid,
+    task_name: Option<String>,
+    action_name: Option<String>,
+    input: Option<Value>,
+    priority: i32,
+    resume: Option<Value>,
+    traceparent: Option<String>,
+    project_id: String,
+}
+
+/// `query` with the wildcards of `LIKE` escaped, to match it literally.
+fn like_escape(query: &str) -> String {
+    query.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// Binds of the visibility part of a `JobFilter`: the projects as a JSON list (or NULL) and the hidden tasks as one.
+fn visibility_binds(filter: &JobFilter) -> Result<(Option<String>, String), Error> {
+    let projects = filter.projects.as_ref().map(serde_json::to_string).transpose()?;
+    let hidden_tasks = serde_json::to_string(&filter.hidden_tasks)?;
+    Ok((projects, hidden_tasks))
+}
+
+fn leased_request(row: LeasedJob) -> Result<JobRequest, Error> {
+    Ok(JobRequest {
+        uuid: Some(row.job_id),
+        spec: JobSpec::new(row.task_name, row.action_name).map_err(Error::msg)?,
+        input: row.input,
+        priority: Some(row.priority),
+        resume: row.resume.map(serde_json::from_value).transpose()?,
+        traceparent: row.traceparent,
+        // Left out for the default project, which workers from before projects run
+        project: (row.project_id != DEFAULT_PROJECT).then_some(row.project_id),
+    })
+}
+
+/// Timestamps of a job and its steps, used to reconstruct the job's state at a point in time.
+#[derive(sqlx::FromRow, Debug)]
+pub struct JobTimeline {
+    pub job_id: Uuid,
+    pub queued: DateTime<Utc>,
+    pub start_datetime: Option<DateTime<Utc>>,
+    pub end_datetime: Option<DateTime<Utc>>,
+    pub success: Option<bool>,
+    #[sqlx(skip)]
+    pub steps: Vec<StepTimeline>,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct StepTimeline {
+    pub name: String,
+    pub start_datetime: DateTime<Utc>,
+    pub end_datetime: Option<DateTime<Utc>>,
+    pub success: Option<bool>,
+    pub status: Option<String>,
+}
+
+/// Resolved `rate_limit` of a task, stored with each job so dispatch doesn't need the workspace.
+#[derive(Debug, Clone)]
+pub struct JobRateLimit {
+    pub key: String,
+    pub per: std::time::Duration,
+    pub max: u32,
+}
+
+/// Postgres channel notified of every enqueued job, with the job id as payload
+pub const JOB_QUEUED_CHANNEL: &str = "stroem_job_queued";
+
 #[derive(Clone)]
 pub struct JobRepository {
-    pool: PgPool,
+    pool: DbPool,
+    queue: Arc<dyn QueueBackend>,
+    enqueued: Arc<Notify>,
 }
 
 impl JobRepository {
-    pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+    pub fn new(pool: DbPool, queue: Arc<dyn QueueBackend>) -> Self {
+        Self { pool, queue, enqueued: Default::default() }
+    }
+
+    /// Notified whenever a job is enqueued, so held worker polls can pick it up right away.
+    pub fn enqueued(&self) -> Arc<Notify> {
+        self.enqueued.clone()
+    }
+
+    /// Wakes the held worker polls, on a notification from `listen_enqueued`.
+    pub fn notify_enqueued(&self) {
+        self.enqueued.notify_waiters();
+    }
+
+    /// Listens for the jobs enqueued by any server. Holds one connection of the pool.
+    pub async fn listen_enqueued(&self) -> Result<PgListener, Error> {
+        let DbPool::Postgres(pool) = &self.pool else {
+            bail!("Listening for enqueued jobs requires a Postgres database");
+        };
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener.listen(JOB_QUEUED_CHANNEL).await?;
+        Ok(listener)
     }
 
     pub async fn enqueue_job(
@@ -55,104 +328,725 @@ impl JobRepository {
         job: &JobRequest,
         source_type: &str,
         source_id: Option<&str>,
+        revision: Option<&str>,
+        rate_limit: Option<&JobRateLimit>,
+        group_id: Option<&Uuid>,
     ) -> Result<String, Error> {
         let job_uuid = job.uuid.unwrap_or_else(|| uuid::Uuid::new_v4());
-        sqlx::query(
-            "INSERT INTO job (job_id, task_name, action_name, input, queued, status, source_type, source_id)
-             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"
-        )
-            .bind(&job_uuid)
-            .bind(&job.task)
-            .bind(&job.action)
-            .bind(&job.input)
-            .bind(Utc::now())
-            .bind("queued")
-            .bind(source_type)
-            .bind(source_id)
-            .execute(&self.pool)
-            .await?;
+        let queued = Utc::now();
+        let priority = job.priority.unwrap_or(0);
+        // Root of the job's trace, or a child of the request or trigger that enqueued it
+        let traceparent = info_span!("enqueue_job", job_id = %job_uuid, name = job.spec.name(), source_type)
+            .in_scope(telemetry::current_traceparent);
+        let resume = job.resume.as_ref().map(serde_json::to_value).transpose()?;
+        with_pool!(&self.pool, pool => {
+            sqlx::query(
+                "INSERT INTO job (
+                    job_id, task_name, action_name, input, queued, status, source_type, source_id, priority, enqueue_revision,
+                    rate_limit_key, rate_limit_max, rate_limit_per_secs, resume, traceparent, group_id, project_id
+                 )
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)"
+            )
+                .bind(job_uuid)
+                .bind(job.task())
+                .bind(job.action())
+                .bind(&job.input)
+                .bind(queued)
+                .bind("queued")
+                .bind(source_type)
+                .bind(source_id)
+                .bind(priority)
+                .bind(revision)
+                .bind(rate_limit.map(|r| &r.key))
+                .bind(rate_limit.map(|r| r.max as i32))
+                .bind(rate_limit.map(|r| r.per.as_secs_f64()))
+                .bind(&resume)
+                .bind(&traceparent)
+                .bind(group_id)
+                .bind(job.project())
+                .execute(pool)
+                .await?;
+        });
+        self.queue.push(&job_uuid, priority, queued).await?;
+        self.announce_queued(&job_uuid).await;
 
         Ok(job_uuid.to_string())
     }
 
-    pub async fn get_next_job(&self, worker_id: &str) -> Result<Option<JobRequest>, Error> {
-        let row = sqlx::query(
+    /// Wakes the held worker polls of every server for a job that was queued.
+    async fn announce_queued(&self, job_id: &Uuid) {
+        match &self.pool {
+            // The job is queued either way, a lost notification only delays it until the next poll
+            DbPool::Postgres(pool) => {
+                if let Err(e) = sqlx::query("SELECT pg_notify($1, $2)")
+                    .bind(JOB_QUEUED_CHANNEL)
+                    .bind(job_id.to_string())
+                    .execute(pool)
+                    .await
+                {
+                    error!("Failed to notify that job {} was queued: {}", job_id, e);
+                }
+            }
+            // A SQLite database has a single server
+            DbPool::Sqlite(_) => self.notify_enqueued(),
+        }
+    }
+
+    /// Hands a running job back from its worker until the wait of a step is over. The runner takes over the
+    /// steps in `resume` once the job is queued again; the time it ran so far is added to its compute time.
+    /// Returns false if the job doesn't run on the worker.
+    pub async fn suspend(&self, job_id: &Uuid, worker_id: &str, resume: &ResumeState) -> Result<bool, Error> {
+        let resume = serde_json::to_value(resume)?;
+        let query = self.pool.sql(
             "UPDATE job
-             SET worker_id = $1, picked = NOW(), status = 'running'
-             WHERE job_id = (
-                 SELECT job_id
-                 FROM job
-                 WHERE status = 'queued' AND worker_id IS NULL AND picked IS NULL
-                 ORDER BY queued ASC
-                 LIMIT 1
-             )
-             RETURNING job_id, task_name, action_name, input",
-        )
-        .bind(worker_id)
-        .fetch_optional(&self.pool)
-        .await?;
-
-        if let Some(row) = row {
-            let job_uuid: uuid::Uuid = row.try_get("job_id")?;
-            let job = JobRequest {
-                uuid: Some(job_uuid),
-                task: row.try_get("task_name")?,
-                action: row.try_get("action_name")?,
-                input: row.try_get("input")?,
-            };
-            debug!("Assigned job {} to worker {}", job_uuid, worker_id);
-            return Ok(Some(job));
+             SET suspended = NOW(), worker_id = NULL, resume = $3,
+                 compute_seconds = COALESCE(compute_seconds, 0) + COALESCE(EXTRACT(EPOCH FROM NOW() - picked)::float8, 0)
+             WHERE job_id = $1 AND worker_id = $2 AND status = 'running' AND end_datetime IS NULL",
+            "UPDATE job
+             SET suspended = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'), worker_id = NULL, resume = $3,
+                 compute_seconds = COALESCE(compute_seconds, 0) + COALESCE((julianday('now') - julianday(picked)) * 86400, 0)
+             WHERE job_id = $1 AND worker_id = $2 AND status = 'running' AND end_datetime IS NULL",
+        );
+        let rows = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(job_id)
+            .bind(worker_id)
+            .bind(&resume)
+            .execute(pool)
+            .await?
+            .rows_affected());
+        if rows > 0 {
+            info!("Suspended job {} of worker {}", job_id, worker_id);
+        }
+        Ok(rows > 0)
+    }
+
+    /// Queues a suspended job again once none of its waits is still waiting and none of its approvals is still
+    /// pending, returns whether it was queued.
+    pub async fn requeue_suspended(&self, job_id: &Uuid) -> Result<bool, Error> {
+        let query = format!(
+            "UPDATE job SET status = 'queued', suspended = NULL, picked = NULL
+             WHERE job_id = $1 AND suspended IS NOT NULL AND end_datetime IS NULL AND {}
+             RETURNING priority, queued",
+            self.pool.sql(NOTHING_AWAITED, SQLITE_NOTHING_AWAITED),
+        );
+        let requeued: Option<(i32, DateTime<Utc>)> = with_pool!(&self.pool, pool => sqlx::query_as(&query)
+            .bind(job_id)
+            .fetch_optional(pool)
+            .await)?;
+        let Some((priority, queued)) = requeued else {
+            return Ok(false);
+        };
+        self.queue.push(job_id, priority, queued).await?;
+        self.announce_queued(job_id).await;
+        info!("Queued suspended job {} again", job_id);
+        Ok(true)
+    }
+
+    /// Suspended jobs that no longer wait for anything, e.g. because an approval timed out or the server stopped
+    /// before queuing them.
+    pub async fn get_ready_suspended(&self) -> Result<Vec<Uuid>, Error> {
+        let query = format!(
+            "SELECT job_id FROM job WHERE suspended IS NOT NULL AND end_datetime IS NULL AND {}",
+            self.pool.sql(NOTHING_AWAITED, SQLITE_NOTHING_AWAITED),
+        );
+        let job_ids = with_pool!(&self.pool, pool => sqlx::query_scalar(&query)
+            .fetch_all(pool)
+            .await)?;
+        Ok(job_ids)
+    }
+
+    pub async fn get_next_job(&self, worker_id: &str, projects: &[String]) -> Result<Option<JobRequest>, Error> {
+        Ok(self.get_next_jobs(worker_id, projects, 1).await?.pop())
+    }
+
+    /// Leases up to `count` jobs to the worker, in dispatch order. With `projects`, only jobs of those projects.
+    pub async fn get_next_jobs(&self, worker_id: &str, projects: &[String], count: usize) -> Result<Vec<JobRequest>, Error> {
+        let mut job_ids = self.queue.pop(worker_id, projects, count).await?;
+        let released = self.release_rate_limited(&job_ids).await?;
+        job_ids.retain(|job_id| !released.contains(job_id));
+        if job_ids.is_empty() {
+            debug!("No jobs available for worker {}", worker_id);
+            return Ok(vec![]);
+        }
+
+        let rows: Vec<LeasedJob> = match &self.pool {
+            DbPool::Postgres(pool) => sqlx::query_as("SELECT job_id, task_name, action_name, input, priority, resume, traceparent, project_id FROM job WHERE job_id = ANY($1)")
+                .bind(&job_ids)
+                .fetch_all(pool)
+                .await?,
+            DbPool::Sqlite(pool) => sqlx::query_as("SELECT job_id, task_name, action_name, input, priority, resume, traceparent, project_id FROM job WHERE job_id IN (SELECT unhex(value) FROM json_each($1))")
+                .bind(uuid_list(&job_ids))
+                .fetch_all(pool)
+                .await?,
+        };
+        let mut jobs = HashMap::new();
+        for row in rows {
+            let job_id = row.job_id;
+            match leased_request(row) {
+                Ok(job) => {
+                    jobs.insert(job_id, job);
+                }
+                // The job can't run anywhere, it fails alone rather than the whole poll with it stuck as running
+                Err(e) => {
+                    error!("Failing job {}, it can't be leased: {}", job_id, e);
+                    self.fail_invalid(&job_id).await?;
+                }
+            }
+        }
+        debug!("Assigned {} job(s) to worker {}", jobs.len(), worker_id);
+        Ok(job_ids.iter().filter_map(|job_id| jobs.remove(job_id)).collect())
+    }
+
+    async fn fail_invalid(&self, job_id: &Uuid) -> Result<(), Error> {
+        let now = Utc::now();
+        self.update_job_result(&job_id.to_string(), &JobResult {
+            protocol_version: protocol::PROTOCOL_VERSION,
+            success: false,
+            start_datetime: now,
+            end_datetime: now,
+            input: None,
+            output: None,
+            revision: None,
+            attempts: None,
+            status: None,
+            links: None,
+            environment: None,
+            reason: Some(REASON_INVALID_JOB.to_string()),
+        }).await?;
+        Ok(())
+    }
+
+    /// Puts leased jobs back in the queue if their rate limit was exceeded, which the dispatch filter
+    /// can't prevent for a batch of jobs with the same key or for concurrent polls.
+    /// Per key, the earliest picks within the window are kept, so concurrent callers agree on which ones to release.
+    async fn release_rate_limited(&self, job_ids: &[Uuid]) -> Result<Vec<Uuid>, Error> {
+        let released: Vec<(Uuid, i32, DateTime<Utc>)> = match &self.pool {
+            DbPool::Postgres(pool) => {
+                let keys: Vec<String> = sqlx::query_scalar(
+                    "SELECT DISTINCT rate_limit_key FROM job WHERE job_id = ANY($1) AND rate_limit_key IS NOT NULL",
+                )
+                .bind(job_ids)
+                .fetch_all(pool)
+                .await?;
+                if keys.is_empty() {
+                    return Ok(vec![]);
+                }
+
+                let mut tx = pool.begin().await?;
+                let mut released = Vec::new();
+                for key in keys {
+                    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+                        .bind(&key)
+                        .execute(&mut *tx)
+                        .await?;
+                    released.extend(sqlx::query_as::<_, (Uuid, i32, DateTime<Utc>)>(
+                        "WITH ranked AS (
+                            SELECT job_id, rate_limit_max, ROW_NUMBER() OVER (ORDER BY picked, job_id) AS n
+                            FROM job
+                            WHERE rate_limit_key = $1 AND picked > NOW() - make_interval(secs => rate_limit_per_secs)
+                         )
+                         UPDATE job SET status = 'queued', worker_id = NULL, picked = NULL
+                         FROM ranked
+                         WHERE job.job_id = ranked.job_id AND ranked.n > ranked.rate_limit_max AND job.job_id = ANY($2)
+                         RETURNING job.job_id, job.priority, job.queued",
+                    )
+                    .bind(&key)
+                    .bind(job_ids)
+                    .fetch_all(&mut *tx)
+                    .await?);
+                }
+                tx.commit().await?;
+                released
+            }
+            // Writes are serialized in SQLite, so concurrent polls can't pick the same window apart
+            DbPool::Sqlite(pool) => sqlx::query_as(
+                "WITH ranked AS (
+                    SELECT job_id, rate_limit_max,
+                        ROW_NUMBER() OVER (PARTITION BY rate_limit_key ORDER BY picked, job_id) AS n
+                    FROM job
+                    WHERE rate_limit_key IN (
+                        SELECT rate_limit_key FROM job WHERE job_id IN (SELECT unhex(value) FROM json_each($1))
+                    )
+                      AND picked > strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now', '-' || rate_limit_per_secs || ' seconds')
+                 )
+                 UPDATE job SET status = 'queued', worker_id = NULL, picked = NULL
+                 FROM ranked
+                 WHERE job.job_id = ranked.job_id AND ranked.n > ranked.rate_limit_max
+                   AND job.job_id IN (SELECT unhex(value) FROM json_each($1))
+                 RETURNING job_id, priority, queued",
+            )
+            .bind(uuid_list(job_ids))
+            .fetch_all(pool)
+            .await?,
+        };
+
+        for (job_id, priority, queued) in &released {
+            debug!("Job {} exceeds its rate limit, back to the queue", job_id);
+            self.queue.push(job_id, *priority, *queued).await?;
         }
-        debug!("No jobs available for worker {}", worker_id);
-        Ok(None)
+        Ok(released.into_iter().map(|(job_id, _, _)| job_id).collect())
     }
 
-    pub async fn get_jobs(&self) -> Result<Vec<Job>, Error> {
-        let list = sqlx::query_as(
+    /// Condition leaving the jobs of the projects bound at `$projects` and not of the tasks bound at `$hidden_tasks`,
+    /// see `visibility_binds`.
+    fn visible_condition(&self, projects: usize, hidden_tasks: usize) -> String {
+        let (postgres, sqlite) = (
+            format!("(${projects}::jsonb IS NULL OR project_id IN (SELECT jsonb_array_elements_text(${projects}::jsonb)))
+               AND project_id || '/' || COALESCE(task_name, '') NOT IN (SELECT jsonb_array_elements_text(${hidden_tasks}::jsonb))"),
+            format!("(${projects} IS NULL OR project_id IN (SELECT value FROM json_each(${projects})))
+               AND project_id || '/' || COALESCE(task_name, '') NOT IN (SELECT value FROM json_each(${hidden_tasks}))"),
+        );
+        self.pool.sql(&postgres, &sqlite).to_string()
+    }
+
+    /// A page of the most recent jobs matching the filter, and the number of jobs matching it in total.
+    pub async fn get_jobs(&self, filter: &JobFilter, limit: i64, offset: i64) -> Result<(Vec<Job>, i64), Error> {
+        let (projects, hidden_tasks) = visibility_binds(filter)?;
+        let visible = self.visible_condition(7, 8);
+        let (postgres, sqlite) = (
+            format!("deleted IS NULL
+               AND ($1::timestamptz IS NULL OR queued >= $1)
+               AND ($2::timestamptz IS NULL OR queued < $2)
+               AND ($3::text IS NULL OR status = $3)
+               AND ($4::text IS NULL OR task_name = $4)
+               AND ($5::text IS NULL OR source_type = $5)
+               AND ($6::text IS NULL OR worker_id = $6)
+               AND {visible}"),
+            format!("deleted IS NULL
+               AND ($1 IS NULL OR queued >= $1)
+               AND ($2 IS NULL OR queued < $2)
+               AND ($3 IS NULL OR status = $3)
+               AND ($4 IS NULL OR task_name = $4)
+               AND ($5 IS NULL OR source_type = $5)
+               AND ($6 IS NULL OR worker_id = $6)
+               AND {visible}"),
+        );
+        let conditions = self.pool.sql(&postgres, &sqlite);
+        let order = self.pool.sql("start_datetime DESC, job_id", "start_datetime DESC NULLS FIRST, job_id");
+        let list_query = format!(
             "SELECT
                 job_id, success, task_name, action_name, input, output, worker_id,
-                status, source_type, source_id, start_datetime, end_datetime, revision
+                status, source_type, source_id, start_datetime, end_datetime, revision, priority, enqueue_revision, failure_reason, deleted, group_id, project_id, suspended
              FROM job
-             ORDER BY start_datetime DESC
-             LIMIT 20",
-        )
-        .fetch_all(&self.pool)
-        .await?;
+             WHERE {}
+             ORDER BY {}
+             LIMIT $9 OFFSET $10",
+            conditions, order,
+        );
+        let count_query = format!("SELECT COUNT(*) FROM job WHERE {}", conditions);
+
+        let list = with_pool!(&self.pool, pool => sqlx::query_as(&list_query)
+            .bind(filter.from)
+            .bind(filter.to)
+            .bind(&filter.status)
+            .bind(&filter.task)
+            .bind(&filter.source_type)
+            .bind(&filter.worker_id)
+            .bind(&projects)
+            .bind(&hidden_tasks)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(pool)
+            .await)?;
+        let total: i64 = with_pool!(&self.pool, pool => sqlx::query_scalar(&count_query)
+            .bind(filter.from)
+            .bind(filter.to)
+            .bind(&filter.status)
+            .bind(&filter.task)
+            .bind(&filter.source_type)
+            .bind(&filter.worker_id)
+            .bind(&projects)
+            .bind(&hidden_tasks)
+            .fetch_one(pool)
+            .await)?;
+        Ok((list, total))
+    }
+
+    /// Connections of the database pool, total and idle.
+    pub fn pool_stats(&self) -> (u32, usize) {
+        self.pool.stats()
+    }
+
+    pub async fn get_queue_stats(&self) -> Result<QueueStats, Error> {
+        let query = self.pool.sql(
+            "SELECT
+                COUNT(*) FILTER (WHERE status = 'queued') AS queued,
+                COUNT(*) FILTER (WHERE status = 'running' AND suspended IS NULL) AS running,
+                AVG(EXTRACT(EPOCH FROM end_datetime - start_datetime)::float8)
+                    FILTER (WHERE end_datetime > NOW() - INTERVAL '1 hour') AS avg_duration_secs,
+                EXTRACT(EPOCH FROM NOW() - MIN(queued) FILTER (WHERE status = 'queued'))::float8 AS oldest_wait_secs
+             FROM job
+             WHERE status IN ('queued', 'running') OR end_datetime > NOW() - INTERVAL '1 hour'",
+            "SELECT
+                COUNT(*) FILTER (WHERE status = 'queued') AS queued,
+                COUNT(*) FILTER (WHERE status = 'running' AND suspended IS NULL) AS running,
+                AVG((julianday(end_datetime) - julianday(start_datetime)) * 86400)
+                    FILTER (WHERE end_datetime > strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now', '-1 hour')) AS avg_duration_secs,
+                (julianday('now') - julianday(MIN(queued) FILTER (WHERE status = 'queued'))) * 86400 AS oldest_wait_secs
+             FROM job
+             WHERE status IN ('queued', 'running') OR end_datetime > strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now', '-1 hour')",
+        );
+        let stats = with_pool!(&self.pool, pool => sqlx::query_as(query)
+            .fetch_one(pool)
+            .await)?;
+        Ok(stats)
+    }
+
+    /// Queued and running jobs per project and task the filter leaves visible, the most queued first.
+    pub async fn get_queue_depth(&self, filter: &JobFilter) -> Result<Vec<TaskQueueDepth>, Error> {
+        let visible = self.visible_condition(1, 2);
+        let (postgres, sqlite) = (
+            format!(
+                "SELECT
+                    project_id AS project, COALESCE(task_name, action_name) AS task,
+                    COUNT(*) FILTER (WHERE status = 'queued') AS queued,
+                    COUNT(*) FILTER (WHERE status = 'running') AS running,
+                    EXTRACT(EPOCH FROM NOW() - MIN(queued) FILTER (WHERE status = 'queued'))::float8 AS oldest_wait_secs
+                 FROM job
+                 WHERE status IN ('queued', 'running') AND {visible}
+                 GROUP BY 1, 2
+                 ORDER BY 3 DESC, 1, 2"
+            ),
+            format!(
+                "SELECT
+                    project_id AS project, COALESCE(task_name, action_name) AS task,
+                    COUNT(*) FILTER (WHERE status = 'queued') AS queued,
+                    COUNT(*) FILTER (WHERE status = 'running') AS running,
+                    (julianday('now') - julianday(MIN(queued) FILTER (WHERE status = 'queued'))) * 86400 AS oldest_wait_secs
+                 FROM job
+                 WHERE status IN ('queued', 'running') AND {visible}
+                 GROUP BY 1, 2
+                 ORDER BY 3 DESC, 1, 2"
+            ),
+        );
+        let query = self.pool.sql(&postgres, &sqlite);
+        let (projects, hidden_tasks) = visibility_binds(filter)?;
+        let depth = with_pool!(&self.pool, pool => sqlx::query_as(query)
+            .bind(&projects)
+            .bind(&hidden_tasks)
+            .fetch_all(pool)
+            .await)?;
+        Ok(depth)
+    }
+
+    /// Queue wait percentiles of the jobs started since `since` the filter leaves visible, per project and task or
+    /// for all of them in one row.
+    pub async fn get_queue_wait(&self, since: DateTime<Utc>, per_task: bool, filter: &JobFilter) -> Result<Vec<QueueWait>, Error> {
+        let (project, key, group) = if per_task {
+            ("project_id", "COALESCE(task_name, action_name)", "GROUP BY 1, 2 ORDER BY 1, 2")
+        } else {
+            (self.pool.sql("NULL::text", "NULL"), self.pool.sql("NULL::text", "NULL"), "")
+        };
+        let visible = self.visible_condition(2, 3);
+        // Nearest-rank percentiles, which SQLite has to work out from row numbers
+        let (postgres, sqlite) = (
+            format!(
+                "SELECT
+                    {project} AS project, {key} AS task, COUNT(*) AS jobs,
+                    percentile_disc(0.5) WITHIN GROUP (ORDER BY EXTRACT(EPOCH FROM start_datetime - queued)::float8) AS p50_secs,
+                    percentile_disc(0.95) WITHIN GROUP (ORDER BY EXTRACT(EPOCH FROM start_datetime - queued)::float8) AS p95_secs,
+                    MAX(EXTRACT(EPOCH FROM start_datetime - queued)::float8) AS max_secs
+                 FROM job
+                 WHERE start_datetime >= $1 AND start_datetime >= queued AND {visible}
+                 {group}"
+            ),
+            format!(
+                "WITH ranked AS (
+                    SELECT
+                        {project} AS project, {key} AS task, (julianday(start_datetime) - julianday(queued)) * 86400 AS wait,
+                        ROW_NUMBER() OVER (PARTITION BY {project}, {key} ORDER BY julianday(start_datetime) - julianday(queued)) AS n,
+                        COUNT(*) OVER (PARTITION BY {project}, {key}) AS total
+                    FROM job
+                    WHERE start_datetime >= $1 AND start_datetime >= queued AND {visible}
+                 )
+                 SELECT
+                    project, task, COUNT(*) AS jobs,
+                    MIN(wait) FILTER (WHERE n >= 0.5 * total) AS p50_secs,
+                    MIN(wait) FILTER (WHERE n >= 0.95 * total) AS p95_secs,
+                    MAX(wait) AS max_secs
+                 FROM ranked
+                 {group}"
+            ),
+        );
+        let query = self.pool.sql(&postgres, &sqlite);
+        let (projects, hidden_tasks) = visibility_binds(filter)?;
+        let waits = with_pool!(&self.pool, pool => sqlx::query_as(query)
+            .bind(since)
+            .bind(&projects)
+            .bind(&hidden_tasks)
+            .fetch_all(pool)
+            .await)?;
+        Ok(waits)
+    }
+
+    /// Jobs each worker finished since `since`, in buckets of `bucket_secs` seconds.
+    pub async fn get_worker_throughput(&self, since: DateTime<Utc>, bucket_secs: i64, filter: &JobFilter) -> Result<Vec<WorkerThroughput>, Error> {
+        let visible = self.visible_condition(3, 4);
+        let (postgres, sqlite) = (
+            format!("SELECT
+                worker_id,
+                to_timestamp(FLOOR(EXTRACT(EPOCH FROM end_datetime)::float8 / $2) * $2) AS bucket,
+                COUNT(*) AS jobs,
+                COUNT(*) FILTER (WHERE success = FALSE) AS failed,
+                COALESCE(SUM(compute_seconds), 0)::float8 AS compute_seconds
+             FROM job
+             WHERE end_datetime >= $1 AND worker_id IS NOT NULL AND {visible}
+             GROUP BY 1, 2
+             ORDER BY 2, 1"),
+            format!("SELECT
+                worker_id,
+                strftime('%Y-%m-%dT%H:%M:%S+00:00', CAST(strftime('%s', end_datetime) AS INTEGER) / $2 * $2, 'unixepoch') AS bucket,
+                COUNT(*) AS jobs,
+                COUNT(*) FILTER (WHERE success = FALSE) AS failed,
+                CAST(COALESCE(SUM(compute_seconds), 0) AS REAL) AS compute_seconds
+             FROM job
+             WHERE end_datetime >= $1 AND worker_id IS NOT NULL AND {visible}
+             GROUP BY 1, 2
+             ORDER BY 2, 1"),
+        );
+        let query = self.pool.sql(&postgres, &sqlite);
+        let (projects, hidden_tasks) = visibility_binds(filter)?;
+        let throughput = with_pool!(&self.pool, pool => sqlx::query_as(query)
+            .bind(since)
+            .bind(bucket_secs)
+            .bind(&projects)
+            .bind(&hidden_tasks)
+            .fetch_all(pool)
+            .await)?;
+        Ok(throughput)
+    }
+
+    /// Per project and task, the jobs finished since `since` and the ones still running.
+    pub async fn get_task_health(&self, since: DateTime<Utc>) -> Result<Vec<TaskHealth>, Error> {
+        let query = self.pool.sql(
+            "SELECT
+                project_id, COALESCE(task_name, action_name) AS task,
+                COUNT(*) FILTER (WHERE end_datetime >= $1) AS finished,
+                COUNT(*) FILTER (WHERE end_datetime >= $1 AND success = FALSE) AS failed,
+                EXTRACT(EPOCH FROM NOW() - MIN(start_datetime) FILTER (WHERE status = 'running'))::float8 AS longest_running_secs
+             FROM job
+             WHERE COALESCE(task_name, action_name) IS NOT NULL AND (status = 'running' OR end_datetime >= $1)
+             GROUP BY 1, 2",
+            "SELECT
+                project_id, COALESCE(task_name, action_name) AS task,
+                COUNT(*) FILTER (WHERE end_datetime >= $1) AS finished,
+                COUNT(*) FILTER (WHERE end_datetime >= $1 AND success = FALSE) AS failed,
+                (julianday('now') - julianday(MIN(start_datetime) FILTER (WHERE status = 'running'))) * 86400 AS longest_running_secs
+             FROM job
+             WHERE COALESCE(task_name, action_name) IS NOT NULL AND (status = 'running' OR end_datetime >= $1)
+             GROUP BY 1, 2",
+        );
+        let health = with_pool!(&self.pool, pool => sqlx::query_as(query)
+            .bind(since)
+            .fetch_all(pool)
+            .await)?;
+        Ok(health)
+    }
+
+    /// Sums the compute time of jobs finished since `since`, per group and worker.
+    pub async fn get_usage(&self, since: DateTime<Utc>, group: &UsageGroup) -> Result<Vec<UsageRow>, Error> {
+        let key = match group {
+            UsageGroup::Task => "COALESCE(j.task_name, j.action_name)",
+            UsageGroup::Action => "j.action_name",
+            UsageGroup::Worker => "j.worker_id",
+            UsageGroup::Source => "COALESCE(j.source_id, j.source_type)",
+            UsageGroup::Label(_) => "w.labels ->> $2",
+        };
+        let query = format!(
+            "SELECT
+                {key} AS key, j.worker_id, w.labels AS worker_labels,
+                COUNT(*) AS jobs, CAST(COALESCE(SUM(j.compute_seconds), 0) AS {float}) AS compute_seconds
+             FROM job j
+             LEFT JOIN worker w ON w.worker_id = j.worker_id
+             WHERE j.end_datetime >= $1
+             GROUP BY 1, j.worker_id, w.labels",
+            float = self.pool.sql("float8", "REAL"),
+        );
+        Ok(with_pool!(&self.pool, pool => {
+            let mut query = sqlx::query_as(&query).bind(since);
+            if let UsageGroup::Label(label) = group {
+                query = query.bind(label);
+            }
+            query.fetch_all(pool).await
+        })?)
+    }
+
+    /// Finds recent jobs the filter leaves visible whose id starts with `query`, or whose task, action or
+    /// source id contains it.
+    pub async fn search_jobs(&self, query: &str, filter: &JobFilter, limit: i64) -> Result<Vec<Job>, Error> {
+        let escaped = like_escape(query);
+        let (projects, hidden_tasks) = visibility_binds(filter)?;
+        let visible = self.visible_condition(3, 4);
+        let (postgres, sqlite) = (
+            format!("SELECT
+                job_id, success, task_name, action_name, input, output, worker_id,
+                status, source_type, source_id, start_datetime, end_datetime, revision, priority, enqueue_revision, failure_reason, deleted, group_id, project_id, suspended
+             FROM job
+             WHERE (job_id::text ILIKE $1 || '%'
+                OR task_name ILIKE '%' || $1 || '%'
+                OR action_name ILIKE '%' || $1 || '%'
+                OR source_id ILIKE '%' || $1 || '%')
+                AND deleted IS NULL
+                AND {visible}
+             ORDER BY queued DESC
+             LIMIT $2"),
+            // Ids are blobs, matched as hex without the dashes; LIKE ignores ASCII case
+            format!("SELECT
+                job_id, success, task_name, action_name, input, output, worker_id,
+                status, source_type, source_id, start_datetime, end_datetime, revision, priority, enqueue_revision, failure_reason, deleted, group_id, project_id, suspended
+             FROM job
+             WHERE (hex(job_id) LIKE replace($1, '-', '') || '%' ESCAPE '\\'
+                OR task_name LIKE '%' || $1 || '%' ESCAPE '\\'
+                OR action_name LIKE '%' || $1 || '%' ESCAPE '\\'
+                OR source_id LIKE '%' || $1 || '%' ESCAPE '\\')
+                AND deleted IS NULL
+                AND {visible}
+             ORDER BY queued DESC
+             LIMIT $2"),
+        );
+        let query = self.pool.sql(&postgres, &sqlite);
+        let list = with_pool!(&self.pool, pool => sqlx::query_as(query)
+            .bind(&escaped)
+            .bind(limit)
+            .bind(&projects)
+            .bind(&hidden_tasks)
+            .fetch_all(pool)
+            .await)?;
         Ok(list)
     }
 
+    /// Adds log lines of the job to the ones searched by `search_logs`. Entries without a step of their own are
+    /// taken as lines of `step_name`.
+    pub async fn index_logs(&self, job_id: &str, step_name: Option<&str>, logs: &[LogEntry]) -> Result<(), Error> {
+        let job_id = Uuid::parse_str(job_id)?;
+        let step_names: Vec<Option<&str>> = logs.iter().map(|entry| entry.step_name.as_deref().or(step_name)).collect();
+        let messages: Vec<String> = logs.iter().map(|entry| entry.message.chars().take(INDEXED_LOG_MESSAGE_LENGTH).collect()).collect();
+        match &self.pool {
+            DbPool::Postgres(pool) => {
+                let timestamps: Vec<DateTime<Utc>> = logs.iter().map(|entry| entry.timestamp).collect();
+                sqlx::query(
+                    "INSERT INTO job_log_line (job_id, step_name, timestamp, message)
+                     SELECT $1, * FROM UNNEST($2::text[], $3::timestamptz[], $4::text[])",
+                )
+                .bind(job_id)
+                .bind(&step_names)
+                .bind(&timestamps)
+                .bind(&messages)
+                .execute(pool)
+                .await?;
+            }
+            // No arrays in SQLite, the lines are bound as a JSON array of `[step_name, timestamp, message]`
+            DbPool::Sqlite(pool) => {
+                let lines: Vec<Value> = logs.iter().zip(step_names).zip(messages)
+                    .map(|((entry, step_name), message)| serde_json::json!([step_name, entry.timestamp.format(SQLITE_TIMESTAMP).to_string(), message]))
+                    .collect();
+                sqlx::query(
+                    "INSERT INTO job_log_line (job_id, step_name, timestamp, message)
+                     SELECT $1, json_extract(value, '$[0]'), json_extract(value, '$[1]'), json_extract(value, '$[2]')
+                     FROM json_each($2)",
+                )
+                .bind(job_id)
+                .bind(Value::from(lines).to_string())
+                .execute(pool)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The most recent log lines containing `query`, of jobs the filter leaves visible queued at or after `from`.
+    pub async fn search_logs(&self, query: &str, filter: &JobFilter, from: DateTime<Utc>, limit: i64) -> Result<Vec<JobLogLine>, Error> {
+        let escaped = like_escape(query);
+        let (projects, hidden_tasks) = visibility_binds(filter)?;
+        let visible = self.visible_condition(4, 5);
+        let (postgres, sqlite) = (
+            format!("SELECT l.job_id, l.step_name, l.timestamp, l.message
+             FROM job_log_line l
+             JOIN job ON job.job_id = l.job_id
+             WHERE l.message ILIKE '%' || $1 || '%'
+                AND deleted IS NULL
+                AND queued >= $2
+                AND {visible}
+             ORDER BY l.timestamp DESC
+             LIMIT $3"),
+            format!("SELECT l.job_id, l.step_name, l.timestamp, l.message
+             FROM job_log_line l
+             JOIN job ON job.job_id = l.job_id
+             WHERE l.message LIKE '%' || $1 || '%' ESCAPE '\\'
+                AND deleted IS NULL
+                AND queued >= $2
+                AND {visible}
+             ORDER BY l.timestamp DESC
+             LIMIT $3"),
+        );
+        let query = self.pool.sql(&postgres, &sqlite);
+        let lines = with_pool!(&self.pool, pool => sqlx::query_as(query)
+            .bind(&escaped)
+            .bind(from)
+            .bind(limit)
+            .bind(&projects)
+            .bind(&hidden_tasks)
+            .fetch_all(pool)
+            .await)?;
+        Ok(lines)
+    }
+
     pub async fn get_job(&self, job_id: &str) -> Result<Job, Error> {
         let job_id = Uuid::parse_str(job_id)?;
-        let mut job: Job = sqlx::query_as(
+        let mut job: Job = with_pool!(&self.pool, pool => sqlx::query_as(
             "SELECT
                 job_id, success, task_name, action_name, input, output, worker_id,
-                status, source_type, source_id, start_datetime, end_datetime, revision
+                status, source_type, source_id, start_datetime, end_datetime, revision, priority, enqueue_revision, failure_reason, deleted, group_id, project_id, suspended
              FROM job
              WHERE job_id = $1
             ",
         )
         .bind(job_id)
-        .fetch_one(&self.pool)
-        .await?;
+        .fetch_one(pool)
+        .await)?;
 
         // Fetch the associated job steps
-        let steps: Vec<JobStep> = sqlx::query_as(
+        let steps: Vec<JobStep> = with_pool!(&self.pool, pool => sqlx::query_as(
             "SELECT
                 success, step_name AS name, input, output,
-                start_datetime, end_datetime
+                start_datetime, end_datetime, attempts, status, links, environment
              FROM job_step
              WHERE job_id = $1
              ORDER BY start_datetime ASC", // Optional: order steps by start time
         )
         .bind(job_id)
-        .fetch_all(&self.pool) // Fetch all steps for this job
-        .await?;
+        .fetch_all(pool) // Fetch all steps for this job
+        .await)?;
 
         job.steps = steps;
 
         Ok(job)
     }
 
+    pub async fn get_job_timeline(&self, job_id: &str) -> Result<JobTimeline, Error> {
+        let job_id = Uuid::parse_str(job_id)?;
+        let mut timeline: JobTimeline = with_pool!(&self.pool, pool => sqlx::query_as(
+            "SELECT job_id, queued, start_datetime, end_datetime, success
+             FROM job
+             WHERE job_id = $1 AND deleted IS NULL",
+        )
+        .bind(job_id)
+        .fetch_one(pool)
+        .await)?;
+
+        timeline.steps = with_pool!(&self.pool, pool => sqlx::query_as(
+            "SELECT step_name AS name, start_datetime, end_datetime, success, status
+             FROM job_step
+             WHERE job_id = $1
+             ORDER BY start_datetime ASC",
+        )
+        .bind(job_id)
+        .fetch_all(pool)
+        .await)?;
+
+        Ok(timeline)
+    }
+
     pub async fn update_start_time(
         &self,
         job_id: &str,
@@ -161,18 +1055,18 @@ impl JobRepository {
         input: &Option<Value>,
     ) -> Result<(), Error> {
         let job_id = Uuid::parse_str(job_id)?;
-        let rows_affected = sqlx::query(
+        let rows_affected = with_pool!(&self.pool, pool => sqlx::query(
             "UPDATE job
-             SET start_datetime = $1, input = $2
+             SET start_datetime = COALESCE(start_datetime, $1), input = $2
              WHERE job_id = $3 AND worker_id = $4 AND status = 'running'",
         )
         .bind(start_time)
         .bind(input)
         .bind(job_id)
         .bind(worker_id)
-        .execute(&self.pool)
+        .execute(pool)
         .await?
-        .rows_affected();
+        .rows_affected());
 
         if rows_affected == 0 {
             let msg = format!(
@@ -199,20 +1093,26 @@ impl JobRepository {
         input: &Option<Value>,
     ) -> Result<(), Error> {
         let job_id = Uuid::parse_str(job_id)?;
-        let rows_affected = sqlx::query(
+        let query = self.pool.sql(
             "INSERT INTO job_step (job_id, step_name, start_datetime, input)
              VALUES ($1, $2, $3, $4)
              ON CONFLICT (job_id, step_name)
              DO UPDATE SET start_datetime = NOW()
              WHERE job_step.job_id = $1 AND job_step.step_name = $2",
-        )
-        .bind(job_id)
-        .bind(step_name)
-        .bind(start_time)
-        .bind(input)
-        .execute(&self.pool)
-        .await?
-        .rows_affected();
+            "INSERT INTO job_step (job_id, step_name, start_datetime, input)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (job_id, step_name)
+             DO UPDATE SET start_datetime = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')
+             WHERE job_step.job_id = $1 AND job_step.step_name = $2",
+        );
+        let rows_affected = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(job_id)
+            .bind(step_name)
+            .bind(start_time)
+            .bind(input)
+            .execute(pool)
+            .await?
+            .rows_affected());
 
         if rows_affected == 0 {
             let msg = format!(
@@ -230,17 +1130,31 @@ impl JobRepository {
         Ok(())
     }
 
+    /// Returns false if the result of this attempt was already stored, e.g. for a resubmission.
     pub async fn update_step_result(
         &self,
         job_id: &str,
         step_name: &str,
         result: &JobResult,
-    ) -> Result<(), Error> {
+    ) -> Result<bool, Error> {
         let job_id = Uuid::parse_str(job_id)?;
-        let rows_affected = sqlx::query(
+        if let Some(revision) = &result.revision {
+            with_pool!(&self.pool, pool => {
+                sqlx::query("UPDATE job SET revision = $1 WHERE job_id = $2")
+                    .bind(revision)
+                    .bind(job_id)
+                    .execute(pool)
+                    .await?;
+            });
+        }
+        let links = result.links.as_ref().map(serde_json::to_value).transpose()?;
+        let rows_affected = with_pool!(&self.pool, pool => sqlx::query(
             "UPDATE job_step
-             SET start_datetime = $1, end_datetime = $2, output = $3, success = $4
-             WHERE job_id = $5 AND step_name = $6",
+             SET start_datetime = $1, end_datetime = $2, output = $3, success = $4, attempts = COALESCE($7, attempts),
+                 status = COALESCE($8, CASE WHEN $4 THEN 'completed' ELSE 'failed' END), links = $9,
+                 environment = $10
+             WHERE job_id = $5 AND step_name = $6
+               AND (end_datetime IS NULL OR attempts IS DISTINCT FROM COALESCE($7, attempts))",
         )
         .bind(&result.start_datetime)
         .bind(&result.end_datetime)
@@ -248,57 +1162,202 @@ impl JobRepository {
         .bind(&result.success)
         .bind(job_id)
         .bind(step_name)
-        .execute(&self.pool)
+        .bind(result.attempts.map(|a| a as i32))
+        .bind(&result.status)
+        .bind(&links)
+        .bind(&result.environment)
+        .execute(pool)
         .await?
-        .rows_affected();
+        .rows_affected());
 
         if rows_affected == 0 {
             let msg = format!(
-                "Failed to update step result for job_id {}, step_name {}: step not found or job not running",
+                "Failed to update step result for job_id {}, step_name {}: step not found, job not running or already stored",
                 job_id, step_name
             );
             error!("{}", msg);
-            return Ok(()); // Kept your original behavior
+            return Ok(false); // Kept your original behavior
         }
 
         info!(
             "Updated result for job_id {}, step_name {}",
             job_id, step_name
         );
+        Ok(true)
+    }
+
+    pub async fn get_cached_output(&self, cache_key: &str) -> Result<Option<CachedStepOutput>, Error> {
+        let cached = with_pool!(&self.pool, pool => sqlx::query_as(
+            "SELECT output, job_id, step_name FROM step_cache WHERE cache_key = $1",
+        )
+        .bind(cache_key)
+        .fetch_optional(pool)
+        .await)?;
+        Ok(cached)
+    }
+
+    /// Stores the output of a successful step under `cache_key`, replacing the one stored before.
+    pub async fn save_cached_output(&self, cache_key: &str, job_id: &Uuid, step_name: &str, output: &Option<Value>) -> Result<(), Error> {
+        let query = self.pool.sql(
+            "INSERT INTO step_cache (cache_key, output, job_id, step_name) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (cache_key) DO UPDATE SET output = EXCLUDED.output, job_id = EXCLUDED.job_id,
+                 step_name = EXCLUDED.step_name, created = NOW()",
+            "INSERT INTO step_cache (cache_key, output, job_id, step_name) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (cache_key) DO UPDATE SET output = excluded.output, job_id = excluded.job_id,
+                 step_name = excluded.step_name, created = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')",
+        );
+        with_pool!(&self.pool, pool => {
+            sqlx::query(query)
+                .bind(cache_key)
+                .bind(output)
+                .bind(job_id)
+                .bind(step_name)
+                .execute(pool)
+                .await?;
+        });
+        debug!("Cached output of step {} of job {} as {}", step_name, job_id, cache_key);
         Ok(())
     }
 
-    pub async fn update_job_result(&self, job_id: &str, result: &JobResult) -> Result<(), Error> {
+    /// Only the first result of a job is stored, returns false for any later one.
+    pub async fn update_job_result(&self, job_id: &str, result: &JobResult) -> Result<bool, Error> {
         let job_id = Uuid::parse_str(job_id)?;
-        let rows_affected = sqlx::query(
-            "UPDATE job
-             SET start_datetime = $1, end_datetime = $2, output = $3, success = $4, status = $5
-             WHERE job_id = $6",
-        )
-        .bind(&result.start_datetime)
-        .bind(&result.end_datetime)
-        .bind(&result.output)
-        .bind(&result.success)
-        .bind(if result.success {
+        let status = if result.success {
             "completed"
         } else {
             "failed"
-        })
-        .bind(job_id)
-        .execute(&self.pool)
-        .await?
-        .rows_affected();
+        };
+        let query = self.pool.sql(
+            "UPDATE job
+             SET start_datetime = COALESCE(start_datetime, $1), end_datetime = $2, output = $3, success = $4, status = $5,
+                 revision = COALESCE($7, revision), compute_seconds = COALESCE(compute_seconds, 0) + EXTRACT(EPOCH FROM $2 - $1), failure_reason = $8,
+                 suspended = NULL
+             WHERE job_id = $6 AND end_datetime IS NULL
+             RETURNING COALESCE(task_name, action_name)",
+            "UPDATE job
+             SET start_datetime = COALESCE(start_datetime, $1), end_datetime = $2, output = $3, success = $4, status = $5,
+                 revision = COALESCE($7, revision), compute_seconds = COALESCE(compute_seconds, 0) + (julianday($2) - julianday($1)) * 86400, failure_reason = $8,
+                 suspended = NULL
+             WHERE job_id = $6 AND end_datetime IS NULL
+             RETURNING COALESCE(task_name, action_name)",
+        );
+        let updated: Option<Option<String>> = with_pool!(&self.pool, pool => sqlx::query_scalar(query)
+            .bind(result.start_datetime)
+            .bind(result.end_datetime)
+            .bind(&result.output)
+            .bind(result.success)
+            .bind(status)
+            .bind(job_id)
+            .bind(&result.revision)
+            .bind(&result.reason)
+            .fetch_optional(pool)
+            .await)?;
 
-        if rows_affected == 0 {
+        let Some(name) = updated else {
+            let exists: Option<bool> = with_pool!(&self.pool, pool => sqlx::query_scalar("SELECT true FROM job WHERE job_id = $1")
+                .bind(job_id)
+                .fetch_optional(pool)
+                .await)?;
+            if exists.is_some() {
+                info!("Job result already stored: job_id={}", job_id);
+                return Ok(false);
+            }
             let msg = format!(
                 "Failed to update job result for job_id {}: not found",
                 job_id
             );
             error!("{}", msg);
             bail!(msg);
-        }
+        };
 
+        let duration = (result.end_datetime - result.start_datetime).num_milliseconds() as f64 / 1000.0;
+        metrics::JOB_DURATION.with_label_values(&[name.as_deref().unwrap_or_default(), status]).observe(duration.max(0.0));
         info!("Stored job result: job_id={}", job_id);
-        Ok(())
+        Ok(true)
+    }
+
+    /// Hides the job from the API, keeping it and its logs until retention prunes them.
+    /// Returns false if it was already deleted.
+    pub async fn soft_delete_job(&self, job_id: &Uuid, deleted_by: &str) -> Result<bool, Error> {
+        let query = self.pool.sql(
+            "UPDATE job SET deleted = NOW(), deleted_by = $2 WHERE job_id = $1 AND deleted IS NULL",
+            "UPDATE job SET deleted = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'), deleted_by = $2 WHERE job_id = $1 AND deleted IS NULL",
+        );
+        let rows_affected = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(job_id)
+            .bind(deleted_by)
+            .execute(pool)
+            .await?
+            .rows_affected());
+        Ok(rows_affected > 0)
+    }
+
+    /// Deletes the job and its steps right away. Returns false if it didn't exist.
+    pub async fn purge_job(&self, job_id: &Uuid) -> Result<bool, Error> {
+        let rows_affected = with_pool!(&self.pool, pool => sqlx::query("DELETE FROM job WHERE job_id = $1")
+            .bind(job_id)
+            .execute(pool)
+            .await?
+            .rows_affected());
+        Ok(rows_affected > 0)
+    }
+
+    /// Deletes up to `limit` finished jobs queued before `before`, or beyond the most recent
+    /// `keep_per_task` of their task or action. Their steps go with them.
+    pub async fn prune_jobs(&self, before: Option<DateTime<Utc>>, keep_per_task: Option<i64>, limit: i64) -> Result<Vec<PrunedJob>, Error> {
+        let pruned = match &self.pool {
+            DbPool::Postgres(pool) => sqlx::query_as(
+            "WITH ranked AS (
+                SELECT job_id, queued,
+                    ROW_NUMBER() OVER (PARTITION BY COALESCE(task_name, action_name) ORDER BY queued DESC) AS rank
+                FROM job
+                WHERE status IN ('completed', 'failed')
+             ), doomed AS (
+                SELECT job_id, (SELECT COUNT(*) FROM job_step s WHERE s.job_id = ranked.job_id) AS steps
+                FROM ranked
+                WHERE queued < $1 OR rank > $2
+                LIMIT $3
+             )
+             DELETE FROM job j
+             USING doomed d
+             WHERE j.job_id = d.job_id
+             RETURNING j.job_id::text AS job_id, d.steps",
+        )
+        .bind(before)
+        .bind(keep_per_task)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?,
+            // No DELETE ... USING in SQLite
+            DbPool::Sqlite(pool) => {
+                let mut tx = pool.begin().await?;
+                let doomed: Vec<(Uuid, i64)> = sqlx::query_as(
+                    "WITH ranked AS (
+                        SELECT job_id, queued,
+                            ROW_NUMBER() OVER (PARTITION BY COALESCE(task_name, action_name) ORDER BY queued DESC) AS rank
+                        FROM job
+                        WHERE status IN ('completed', 'failed')
+                     )
+                     SELECT job_id, (SELECT COUNT(*) FROM job_step s WHERE s.job_id = ranked.job_id) AS steps
+                     FROM ranked
+                     WHERE queued < $1 OR rank > $2
+                     LIMIT $3",
+                )
+                .bind(before)
+                .bind(keep_per_task)
+                .bind(limit)
+                .fetch_all(&mut *tx)
+                .await?;
+                for (job_id, _) in &doomed {
+                    sqlx::query("DELETE FROM job WHERE job_id = $1")
+                        .bind(job_id)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                tx.commit().await?;
+                doomed.into_iter().map(|(job_id, steps)| PrunedJob { job_id: job_id.to_string(), steps }).collect()
+            }
+        };
+        Ok(pruned)
     }
 }
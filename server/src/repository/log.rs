@@ -24,6 +24,7 @@ use local::LogRepositoryLocal;
 
 mod aws_s3;
 use aws_s3::LogRepositoryAWSS3;
+pub(crate) use aws_s3::s3_client;
 
 
 
@@ -190,6 +191,21 @@ pub trait LogRepository: Send + Sync {
 
     async fn upload_archive_to_storage(&self, job_id: &str, archive_name: &PathBuf) -> Result<(), anyhow::Error>;
     async fn retrieve_archive_from_storage(&self, job_id: &str, archive_name: &PathBuf) -> Result<(), anyhow::Error>;
+    async fn delete_archive_from_storage(&self, job_id: &str) -> Result<(), anyhow::Error>;
+
+    /// Removes the job's logs from the cache and from storage.
+    async fn delete_logs(&self, job_id: &str) -> Result<(), anyhow::Error> {
+        let mut entries = fs::read_dir(self.get_cache_folder()).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.file_name().and_then(|f| f.to_str()).is_some_and(|file_name| file_name.starts_with(job_id)) {
+                fs::remove_file(&path)
+                    .await
+                    .with_context(|| format!("Failed to delete cached log file: {}", path.display()))?;
+            }
+        }
+        self.delete_archive_from_storage(job_id).await
+    }
 
     async fn clean_cache(&self) -> Result<(), anyhow::Error> {
         let cutoff = Utc::now() - Duration::days(15);
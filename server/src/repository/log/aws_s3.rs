@@ -28,35 +28,7 @@ impl LogRepositoryAWSS3 {
         prefix: Option<String>,
         endpoint: Option<String>,
     ) -> Result<Self, Error> {
-
-        // Configure region or endpoint
-        let region_provider = RegionProviderChain::first_try(aws_region.map(Region::new))
-            .or_default_provider();
-
-        let mut config_loader = aws_config::defaults(BehaviorVersion::latest()).region(region_provider);
-
-        // If credentials were explicitly passed, override provider
-        if aws_access_key_id.is_some() && aws_secret_access_key.is_some() {
-            let credentials = Credentials::new(
-                aws_access_key_id.unwrap(),
-                aws_secret_access_key.unwrap(),
-                None,
-                None,
-                "log_repository",
-            );
-            config_loader = config_loader.credentials_provider(credentials);
-        }
-
-        let shared_config = config_loader.load().await;
-
-        let mut config = aws_sdk_s3::config::Builder::from(&shared_config);
-
-        if let Some(endpoint_url) = endpoint {
-            config = config.endpoint_url(endpoint_url);
-        }
-
-        let client = Client::from_conf(config.build());
-
+        let client = s3_client(aws_access_key_id, aws_secret_access_key, aws_region, endpoint).await;
 
         Ok(Self {
             cache_dir,
@@ -74,6 +46,42 @@ impl LogRepositoryAWSS3 {
     }
 }
 
+/// S3 client with the given credentials, region and endpoint, or the ones of the environment for those not set.
+pub(crate) async fn s3_client(
+    aws_access_key_id: Option<String>,
+    aws_secret_access_key: Option<String>,
+    aws_region: Option<String>,
+    endpoint: Option<String>,
+) -> Client {
+    // Configure region or endpoint
+    let region_provider = RegionProviderChain::first_try(aws_region.map(Region::new))
+        .or_default_provider();
+
+    let mut config_loader = aws_config::defaults(BehaviorVersion::latest()).region(region_provider);
+
+    // If credentials were explicitly passed, override provider
+    if aws_access_key_id.is_some() && aws_secret_access_key.is_some() {
+        let credentials = Credentials::new(
+            aws_access_key_id.unwrap(),
+            aws_secret_access_key.unwrap(),
+            None,
+            None,
+            "log_repository",
+        );
+        config_loader = config_loader.credentials_provider(credentials);
+    }
+
+    let shared_config = config_loader.load().await;
+
+    let mut config = aws_sdk_s3::config::Builder::from(&shared_config);
+
+    if let Some(endpoint_url) = endpoint {
+        config = config.endpoint_url(endpoint_url);
+    }
+
+    Client::from_conf(config.build())
+}
+
 #[async_trait]
 impl LogRepository for LogRepositoryAWSS3 {
     fn get_cache_folder(&self) -> PathBuf {
@@ -112,4 +120,16 @@ impl LogRepository for LogRepositoryAWSS3 {
 
         Ok(())
     }
+
+    async fn delete_archive_from_storage(&self, job_id: &str) -> Result<(), anyhow::Error> {
+        let key = self.get_s3_key(job_id);
+        self.client.delete_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+            .with_context(|| format!("Failed to delete archive {} from S3", key))?;
+
+        Ok(())
+    }
 }
\ No newline at end of file
@@ -39,4 +39,11 @@ impl LogRepository for LogRepositoryLocal {
         fs::copy(self.storage_dir.join(filename), archive_name).await?;
         Ok(())
     }
+
+    async fn delete_archive_from_storage(&self, job_id: &str) -> Result<(), Error> {
+        match fs::remove_file(self.storage_dir.join(format!("{}.tgz", job_id))).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
 }
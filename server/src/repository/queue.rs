@@ -0,0 +1,65 @@
+use std::sync::Arc;
+use anyhow::Error;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::server_config::QueueConfig;
+use super::DbPool;
+
+mod postgres;
+use postgres::PostgresQueue;
+
+#[cfg(feature = "redis-queue")]
+mod redis;
+#[cfg(feature = "redis-queue")]
+use self::redis::RedisQueue;
+
+/// The dispatch path of the job queue. Job metadata always lives in the `job` table,
+/// the backend only decides which queued job is handed to a worker next.
+#[async_trait]
+pub trait QueueBackend: Send + Sync {
+    /// Makes a job available for dispatch; its row in the `job` table must already exist.
+    async fn push(&self, job_id: &Uuid, priority: i32, queued: DateTime<Utc>) -> Result<(), Error>;
+    /// Leases up to `count` jobs to `worker_id` in dispatch order, marking them as running in the `job` table.
+    /// With `projects`, only jobs of those projects are leased.
+    async fn pop(&self, worker_id: &str, projects: &[String], count: usize) -> Result<Vec<Uuid>, Error>;
+}
+
+/// SQL condition on a `job` row aliased `j`: the job has no rate limit, or its key has room for another dispatch.
+pub(crate) const RATE_LIMIT_OPEN: &str = "(j.rate_limit_key IS NULL OR (
+    SELECT COUNT(*) FROM job r
+    WHERE r.rate_limit_key = j.rate_limit_key AND r.picked > NOW() - make_interval(secs => j.rate_limit_per_secs)
+) < j.rate_limit_max)";
+
+/// `RATE_LIMIT_OPEN` for SQLite.
+pub(crate) const SQLITE_RATE_LIMIT_OPEN: &str = "(j.rate_limit_key IS NULL OR (
+    SELECT COUNT(*) FROM job r
+    WHERE r.rate_limit_key = j.rate_limit_key
+      AND r.picked > strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now', '-' || j.rate_limit_per_secs || ' seconds')
+) < j.rate_limit_max)";
+
+/// SQL condition on a `job` row aliased `j`, with `projects` bound from parameter `$first` on: the job is of one
+/// of them. Empty when the worker takes jobs of every project.
+pub(crate) fn project_filter(projects: &[String], first: usize) -> String {
+    if projects.is_empty() {
+        return String::new();
+    }
+    let params: Vec<String> = (first..first + projects.len()).map(|n| format!("${}", n)).collect();
+    format!(" AND j.project_id IN ({})", params.join(", "))
+}
+
+pub struct QueueBackendFactory {}
+impl QueueBackendFactory {
+    pub async fn from_config(config: &QueueConfig, pool: DbPool) -> Result<Arc<dyn QueueBackend>, Error> {
+        match config {
+            QueueConfig::Postgres => Ok(Arc::new(PostgresQueue::new(pool))),
+            #[cfg(feature = "redis-queue")]
+            QueueConfig::Redis { url, key } => match pool {
+                DbPool::Postgres(pool) => Ok(Arc::new(RedisQueue::new(url, key, pool).await?)),
+                DbPool::Sqlite(_) => anyhow::bail!("Redis queue backend requires a Postgres database"),
+            },
+            #[cfg(not(feature = "redis-queue"))]
+            QueueConfig::Redis { .. } => anyhow::bail!("Redis queue backend requires the server to be built with the `redis-queue` feature"),
+        }
+    }
+}
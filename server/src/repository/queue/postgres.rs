@@ -0,0 +1,69 @@
+use anyhow::Error;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::Row;
+use uuid::Uuid;
+use super::{project_filter, QueueBackend, RATE_LIMIT_OPEN, SQLITE_RATE_LIMIT_OPEN};
+use crate::repository::{with_pool, DbPool};
+
+/// Dispatches straight from the `job` table, the queue is the set of rows with status `queued`.
+/// Also serves SQLite databases, which have no row locks but run one write at a time.
+pub struct PostgresQueue {
+    pool: DbPool,
+}
+
+impl PostgresQueue {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl QueueBackend for PostgresQueue {
+    async fn push(&self, _job_id: &Uuid, _priority: i32, _queued: DateTime<Utc>) -> Result<(), Error> {
+        // The inserted row is the queue entry
+        Ok(())
+    }
+
+    async fn pop(&self, worker_id: &str, projects: &[String], count: usize) -> Result<Vec<Uuid>, Error> {
+        let project_filter = project_filter(projects, 3);
+        // SKIP LOCKED lets concurrent workers lease disjoint batches instead of waiting on each other
+        let query = match &self.pool {
+            DbPool::Postgres(_) => format!(
+                "UPDATE job
+                 SET worker_id = $1, picked = NOW(), status = 'running'
+                 WHERE job_id IN (
+                     SELECT job_id
+                     FROM job j
+                     WHERE status = 'queued' AND worker_id IS NULL AND picked IS NULL AND {RATE_LIMIT_OPEN}{project_filter}
+                     ORDER BY priority DESC, queued ASC
+                     LIMIT $2
+                     FOR UPDATE SKIP LOCKED
+                 )
+                 RETURNING job_id, priority, queued",
+            ),
+            DbPool::Sqlite(_) => format!(
+                "UPDATE job
+                 SET worker_id = $1, picked = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'), status = 'running'
+                 WHERE job_id IN (
+                     SELECT job_id
+                     FROM job j
+                     WHERE status = 'queued' AND worker_id IS NULL AND picked IS NULL AND {SQLITE_RATE_LIMIT_OPEN}{project_filter}
+                     ORDER BY priority DESC, queued ASC
+                     LIMIT $2
+                 )
+                 RETURNING job_id, priority, queued",
+            ),
+        };
+        let mut jobs = with_pool!(&self.pool, pool => projects.iter()
+            .fold(sqlx::query(&query).bind(worker_id).bind(count as i64), |query, project| query.bind(project))
+            .fetch_all(pool)
+            .await?
+            .iter()
+            .map(|row| Ok((row.try_get::<Uuid, _>("job_id")?, row.try_get::<i32, _>("priority")?, row.try_get::<DateTime<Utc>, _>("queued")?)))
+            .collect::<Result<Vec<_>, sqlx::Error>>())?;
+        // RETURNING does not keep the order of the subquery
+        jobs.sort_by(|a, b| b.1.cmp(&a.1).then(a.2.cmp(&b.2)));
+        Ok(jobs.into_iter().map(|(job_id, _, _)| job_id).collect())
+    }
+}
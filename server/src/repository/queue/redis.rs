@@ -0,0 +1,135 @@
+use anyhow::Error;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use redis::AsyncCommands;
+use redis::aio::MultiplexedConnection;
+use sqlx::{PgPool, Row};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+use super::{project_filter, QueueBackend, RATE_LIMIT_OPEN};
+
+/// What became of a job popped from Redis.
+enum Lease {
+    Leased,
+    /// Still queued, but its rate limit is exhausted or it's of a project the worker doesn't take
+    Deferred,
+    /// No longer queued, e.g. cancelled
+    Gone,
+}
+
+/// Dispatches from a Redis sorted set, Postgres is only touched to mark the popped job as running.
+pub struct RedisQueue {
+    connection: MultiplexedConnection,
+    key: String,
+    pool: PgPool,
+}
+
+impl RedisQueue {
+    /// Connects to Redis and re-adds all jobs that are queued in Postgres, so no job is lost if Redis was flushed.
+    pub async fn new(url: &str, key: &str, pool: PgPool) -> Result<Self, Error> {
+        let client = redis::Client::open(url)?;
+        let connection = client.get_multiplexed_async_connection().await?;
+        let queue = Self { connection, key: key.to_string(), pool };
+
+        let rows = sqlx::query("SELECT job_id, priority, queued FROM job WHERE status = 'queued' AND worker_id IS NULL")
+            .fetch_all(&queue.pool)
+            .await?;
+        let mut pipe = redis::pipe();
+        for row in &rows {
+            let job_id: Uuid = row.try_get("job_id")?;
+            let priority: i32 = row.try_get("priority")?;
+            let queued: DateTime<Utc> = row.try_get("queued")?;
+            pipe.cmd("ZADD").arg(&queue.key).arg("NX").arg(score(priority, queued)).arg(job_id.to_string()).ignore();
+        }
+        pipe.query_async::<()>(&mut queue.connection.clone()).await?;
+        info!("Redis queue '{}' ready, {} queued jobs restored", queue.key, rows.len());
+        Ok(queue)
+    }
+
+    /// Puts popped entries back with their scores.
+    async fn restore(&self, connection: &mut MultiplexedConnection, entries: Vec<(String, f64)>) -> Result<(), Error> {
+        for (entry, score) in entries {
+            connection.zadd::<_, _, _, ()>(&self.key, entry, score).await?;
+        }
+        Ok(())
+    }
+
+    async fn lease(&self, claim: &str, worker_id: &str, projects: &[String], job_id: Uuid) -> Result<Lease, Error> {
+        let claimed = projects.iter()
+            .fold(sqlx::query(claim).bind(worker_id).bind(job_id), |query, project| query.bind(project))
+            .execute(&self.pool)
+            .await?;
+        if claimed.rows_affected() == 1 {
+            return Ok(Lease::Leased);
+        }
+        let still_queued: Option<bool> = sqlx::query_scalar("SELECT status = 'queued' AND worker_id IS NULL FROM job WHERE job_id = $1")
+            .bind(job_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(if still_queued == Some(true) { Lease::Deferred } else { Lease::Gone })
+    }
+}
+
+/// Lower scores are popped first: higher priority wins, then the oldest job.
+/// Exact for priorities within ±400, beyond that jobs of neighbouring priorities may interleave.
+fn score(priority: i32, queued: DateTime<Utc>) -> f64 {
+    -(priority as f64) * 1e13 + queued.timestamp_millis() as f64
+}
+
+#[async_trait]
+impl QueueBackend for RedisQueue {
+    async fn push(&self, job_id: &Uuid, priority: i32, queued: DateTime<Utc>) -> Result<(), Error> {
+        let mut connection = self.connection.clone();
+        connection.zadd::<_, _, _, ()>(&self.key, job_id.to_string(), score(priority, queued)).await?;
+        Ok(())
+    }
+
+    async fn pop(&self, worker_id: &str, projects: &[String], count: usize) -> Result<Vec<Uuid>, Error> {
+        let mut connection = self.connection.clone();
+        let mut leased = Vec::with_capacity(count);
+        // Jobs whose rate limit is exhausted, or of projects the worker doesn't take, go back in after this poll,
+        // so they don't block the ones behind them
+        let mut deferred = Vec::new();
+        let claim = format!(
+            "UPDATE job j
+             SET worker_id = $1, picked = NOW(), status = 'running'
+             WHERE j.job_id = $2 AND j.status = 'queued' AND j.worker_id IS NULL AND {RATE_LIMIT_OPEN}{}",
+            project_filter(projects, 3),
+        );
+        while leased.len() < count {
+            let popped: Vec<(String, f64)> = connection.zpopmin(&self.key, (count - leased.len()) as isize).await?;
+            if popped.is_empty() {
+                break;
+            }
+            let mut popped = popped.into_iter();
+            while let Some((entry, score)) = popped.next() {
+                let Ok(job_id) = Uuid::parse_str(&entry) else {
+                    warn!("Dropping invalid entry '{}' from Redis queue '{}'", entry, self.key);
+                    continue;
+                };
+                match self.lease(&claim, worker_id, projects, job_id).await {
+                    Ok(Lease::Leased) => leased.push(job_id),
+                    Ok(Lease::Deferred) => {
+                        debug!("Deferring job {}, its rate limit is exhausted or it's of another project", job_id);
+                        deferred.push((entry, score));
+                    }
+                    Ok(Lease::Gone) => debug!("Skipping job {} from Redis queue, no longer queued", job_id),
+                    Err(e) => {
+                        // The popped jobs not leased yet go back in, they'd be lost from the queue otherwise
+                        deferred.push((entry, score));
+                        deferred.extend(popped);
+                        self.restore(&mut connection, deferred).await?;
+                        if leased.is_empty() {
+                            return Err(e);
+                        }
+                        // The leased ones are marked running already, hand them out rather than orphan them
+                        warn!("Failed to lease job {} from Redis queue '{}': {}", job_id, self.key, e);
+                        return Ok(leased);
+                    }
+                }
+            }
+        }
+        self.restore(&mut connection, deferred).await?;
+        Ok(leased)
+    }
+}
@@ -0,0 +1,134 @@
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use stroem_common::rfc3339;
+use uuid::Uuid;
+use super::{with_pool, DbPool};
+
+/// A job that took longer than the SLA of its task allows.
+#[derive(sqlx::FromRow, Debug, Clone, Serialize, JsonSchema)]
+pub struct SlaBreach {
+    pub breach_id: i64,
+    pub job_id: Uuid,
+    pub project_id: String,
+    pub task: String,
+    /// `max_duration` or `deadline`
+    pub kind: String,
+    pub limit_secs: f64,
+    /// How long the job had been running, or since it was queued for a deadline, when the breach was detected
+    pub elapsed_secs: f64,
+    #[serde(with = "rfc3339")]
+    #[schemars(with = "DateTime<Utc>")]
+    pub detected: DateTime<Utc>,
+    /// Status of the job now, `running` if it hasn't finished since
+    pub job_status: String,
+}
+
+/// Breaches detected in a time bucket, for one task.
+#[derive(sqlx::FromRow, Debug, Serialize, JsonSchema)]
+pub struct SlaBreachTrend {
+    pub project_id: String,
+    pub task: String,
+    /// Start of the bucket
+    #[serde(with = "rfc3339")]
+    #[schemars(with = "DateTime<Utc>")]
+    pub bucket: DateTime<Utc>,
+    pub breaches: i64,
+}
+
+/// A breach just recorded.
+#[derive(sqlx::FromRow, Debug)]
+pub struct NewSlaBreach {
+    pub job_id: Uuid,
+    pub elapsed_secs: f64,
+    /// Unset while the job runs
+    pub end_datetime: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone)]
+pub struct SlaRepository {
+    pool: DbPool,
+}
+
+impl SlaRepository {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    /// Records the jobs of a task running or finished since `since` that took longer than `limit_secs`, each once
+    /// per kind. A `max_duration` counts from the start of the job, a `deadline` from queueing it.
+    pub async fn record_breaches(&self, project_id: &str, task: &str, kind: &str, limit_secs: f64, since: DateTime<Utc>) -> Result<Vec<NewSlaBreach>, Error> {
+        let from = if kind == "deadline" { "queued" } else { "start_datetime" };
+        let elapsed = self.pool.sql(
+            "EXTRACT(EPOCH FROM COALESCE(end_datetime, NOW()) - {from})::float8",
+            "(julianday(COALESCE(end_datetime, strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'))) - julianday({from})) * 86400",
+        ).replace("{from}", from);
+        let query = format!(
+            "INSERT INTO sla_breach (job_id, project_id, task, kind, limit_secs, elapsed_secs)
+             SELECT job_id, project_id, task_name, $3, $4, {elapsed}
+             FROM job
+             WHERE project_id = $1 AND task_name = $2 AND {from} IS NOT NULL
+               AND (end_datetime IS NULL OR end_datetime >= $5)
+               AND {elapsed} > $4
+             ON CONFLICT (job_id, kind) DO NOTHING
+             RETURNING job_id, elapsed_secs, (SELECT end_datetime FROM job WHERE job.job_id = sla_breach.job_id) AS end_datetime"
+        );
+        let breaches = with_pool!(&self.pool, pool => sqlx::query_as(&query)
+            .bind(project_id)
+            .bind(task)
+            .bind(kind)
+            .bind(limit_secs)
+            .bind(since)
+            .fetch_all(pool)
+            .await)?;
+        Ok(breaches)
+    }
+
+    /// Breaches detected since `since`, newest first.
+    pub async fn get_breaches(&self, since: DateTime<Utc>, project_id: Option<&str>, task: Option<&str>, limit: i64) -> Result<Vec<SlaBreach>, Error> {
+        let breaches = with_pool!(&self.pool, pool => sqlx::query_as(
+            "SELECT b.breach_id, b.job_id, b.project_id, b.task, b.kind, b.limit_secs, b.elapsed_secs, b.detected, j.status AS job_status
+             FROM sla_breach b
+             JOIN job j ON j.job_id = b.job_id
+             WHERE b.detected >= $1 AND ($2 IS NULL OR b.project_id = $2) AND ($3 IS NULL OR b.task = $3)
+             ORDER BY b.breach_id DESC
+             LIMIT $4",
+        )
+        .bind(since)
+        .bind(project_id)
+        .bind(task)
+        .bind(limit)
+        .fetch_all(pool)
+        .await)?;
+        Ok(breaches)
+    }
+
+    /// Breaches per task detected since `since`, in buckets of `bucket_secs` seconds.
+    pub async fn get_breach_trend(&self, since: DateTime<Utc>, bucket_secs: i64) -> Result<Vec<SlaBreachTrend>, Error> {
+        let query = self.pool.sql(
+            "SELECT
+                project_id, task,
+                to_timestamp(FLOOR(EXTRACT(EPOCH FROM detected)::float8 / $2) * $2) AS bucket,
+                COUNT(*) AS breaches
+             FROM sla_breach
+             WHERE detected >= $1
+             GROUP BY 1, 2, 3
+             ORDER BY 3, 1, 2",
+            "SELECT
+                project_id, task,
+                strftime('%Y-%m-%dT%H:%M:%S+00:00', CAST(strftime('%s', detected) AS INTEGER) / $2 * $2, 'unixepoch') AS bucket,
+                COUNT(*) AS breaches
+             FROM sla_breach
+             WHERE detected >= $1
+             GROUP BY 1, 2, 3
+             ORDER BY 3, 1, 2",
+        );
+        let trend = with_pool!(&self.pool, pool => sqlx::query_as(query)
+            .bind(since)
+            .bind(bucket_secs)
+            .fetch_all(pool)
+            .await)?;
+        Ok(trend)
+    }
+}
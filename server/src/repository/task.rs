@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use stroem_common::rfc3339;
+use tracing::info;
+use super::{with_pool, DbPool};
+
+/// A task paused by an operator; its triggers don't fire and manual runs are refused.
+#[derive(sqlx::FromRow, Debug, Clone, Serialize, Deserialize)]
+pub struct TaskPause {
+    pub task_id: String,
+    pub paused_by: Option<String>,
+    #[serde(with = "rfc3339")]
+    pub paused_at: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub struct TaskRepository {
+    pool: DbPool,
+}
+
+impl TaskRepository {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    /// Pausing an already paused task keeps the original pause.
+    pub async fn pause(&self, task_id: &str, paused_by: &str) -> Result<TaskPause, Error> {
+        let pause = with_pool!(&self.pool, pool => sqlx::query_as(
+            "INSERT INTO task_pause (task_id, paused_by) VALUES ($1, $2)
+             ON CONFLICT (task_id) DO UPDATE SET task_id = EXCLUDED.task_id
+             RETURNING task_id, paused_by, paused_at",
+        )
+        .bind(task_id)
+        .bind(paused_by)
+        .fetch_one(pool)
+        .await)?;
+        info!("Task '{}' paused by {}", task_id, paused_by);
+        Ok(pause)
+    }
+
+    /// Returns false if the task wasn't paused.
+    pub async fn resume(&self, task_id: &str, resumed_by: &str) -> Result<bool, Error> {
+        let rows_affected = with_pool!(&self.pool, pool => sqlx::query("DELETE FROM task_pause WHERE task_id = $1")
+            .bind(task_id)
+            .execute(pool)
+            .await?
+            .rows_affected());
+        if rows_affected > 0 {
+            info!("Task '{}' resumed by {}", task_id, resumed_by);
+        }
+        Ok(rows_affected > 0)
+    }
+
+    pub async fn get_pause(&self, task_id: &str) -> Result<Option<TaskPause>, Error> {
+        let pause = with_pool!(&self.pool, pool => sqlx::query_as("SELECT task_id, paused_by, paused_at FROM task_pause WHERE task_id = $1")
+            .bind(task_id)
+            .fetch_optional(pool)
+            .await)?;
+        Ok(pause)
+    }
+
+    pub async fn get_pauses(&self) -> Result<HashMap<String, TaskPause>, Error> {
+        let pauses: Vec<TaskPause> = with_pool!(&self.pool, pool => sqlx::query_as("SELECT task_id, paused_by, paused_at FROM task_pause")
+            .fetch_all(pool)
+            .await)?;
+        Ok(pauses.into_iter().map(|pause| (pause.task_id.clone(), pause)).collect())
+    }
+
+    pub async fn is_paused(&self, task_id: &str) -> Result<bool, Error> {
+        Ok(self.get_pause(task_id).await?.is_some())
+    }
+}
@@ -0,0 +1,42 @@
+use std::collections::HashMap;
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use sqlx::Row;
+use super::{with_pool, DbPool};
+
+/// Remembers when each scheduled trigger last fired, so missed runs can be caught up after a restart.
+#[derive(Clone)]
+pub struct TriggerRepository {
+    pool: DbPool,
+}
+
+impl TriggerRepository {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn get_last_runs(&self) -> Result<HashMap<String, DateTime<Utc>>, Error> {
+        with_pool!(&self.pool, pool => {
+            let rows = sqlx::query("SELECT trigger_name, last_run FROM trigger_state")
+                .fetch_all(pool)
+                .await?;
+            rows.iter()
+                .map(|row| Ok((row.try_get("trigger_name")?, row.try_get("last_run")?)))
+                .collect()
+        })
+    }
+
+    pub async fn set_last_run(&self, trigger_name: &str, last_run: DateTime<Utc>) -> Result<(), Error> {
+        with_pool!(&self.pool, pool => {
+            sqlx::query(
+                "INSERT INTO trigger_state (trigger_name, last_run) VALUES ($1, $2)
+                 ON CONFLICT (trigger_name) DO UPDATE SET last_run = EXCLUDED.last_run",
+            )
+            .bind(trigger_name)
+            .bind(last_run)
+            .execute(pool)
+            .await?;
+        });
+        Ok(())
+    }
+}
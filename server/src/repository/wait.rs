@@ -0,0 +1,217 @@
+use std::collections::HashSet;
+use std::time::Duration;
+use anyhow::{anyhow, Error};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::Value;
+use stroem_common::rfc3339;
+use uuid::Uuid;
+use super::{with_pool, DbPool};
+
+const WAIT_COLUMNS: &str = "job_id, step_name, event_key, resume_at, status, payload, created, resolved";
+
+/// What a `wait` or `wait_for_event` step of a job waits for.
+#[derive(sqlx::FromRow, Debug, Clone, Serialize, JsonSchema)]
+pub struct JobWait {
+    pub job_id: Uuid,
+    pub step_name: String,
+    /// Event the step waits for, unset for a `wait`
+    pub event_key: Option<String>,
+    /// When a `wait` is over or a `wait_for_event` times out
+    #[serde(with = "rfc3339::option")]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    pub resume_at: Option<DateTime<Utc>>,
+    /// `waiting`, `elapsed`, `received` or `timed_out`
+    pub status: String,
+    /// Body of the received event
+    pub payload: Option<Value>,
+    #[serde(with = "rfc3339")]
+    #[schemars(with = "DateTime<Utc>")]
+    pub created: DateTime<Utc>,
+    #[serde(with = "rfc3339::option")]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    pub resolved: Option<DateTime<Utc>>,
+}
+
+/// An event kept for steps that start waiting for it after it was posted.
+#[derive(sqlx::FromRow, Debug)]
+pub struct ExternalEvent {
+    pub payload: Option<Value>,
+    pub posted_by: Option<Uuid>,
+}
+
+#[derive(Clone)]
+pub struct WaitRepository {
+    pool: DbPool,
+    /// How long events are kept for steps that start waiting for them later
+    event_retention: Duration,
+}
+
+impl WaitRepository {
+    pub fn new(pool: DbPool, event_retention: Duration) -> Self {
+        Self { pool, event_retention }
+    }
+
+    /// Records what the step waits for, or keeps the wait recorded when the step ran before, and resolves it if
+    /// `resume_at` passed. Events posted before are matched by [`WaitRepository::get_event`].
+    pub async fn register(&self, job_id: &Uuid, step_name: &str, event_key: Option<&str>, timeout: Option<Duration>) -> Result<JobWait, Error> {
+        let insert = self.pool.sql(
+            "INSERT INTO job_wait (job_id, step_name, event_key, resume_at)
+             VALUES ($1, $2, $3, NOW() + make_interval(secs => $4))
+             ON CONFLICT (job_id, step_name) DO NOTHING",
+            "INSERT INTO job_wait (job_id, step_name, event_key, resume_at)
+             VALUES ($1, $2, $3, strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now', '+' || $4 || ' seconds'))
+             ON CONFLICT (job_id, step_name) DO NOTHING",
+        );
+        let due = self.pool.sql(
+            "UPDATE job_wait SET status = CASE WHEN event_key IS NULL THEN 'elapsed' ELSE 'timed_out' END, resolved = NOW()
+             WHERE job_id = $1 AND step_name = $2 AND status = 'waiting' AND resume_at <= NOW()",
+            "UPDATE job_wait SET status = CASE WHEN event_key IS NULL THEN 'elapsed' ELSE 'timed_out' END,
+                 resolved = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')
+             WHERE job_id = $1 AND step_name = $2 AND status = 'waiting' AND julianday(resume_at) <= julianday('now')",
+        );
+        with_pool!(&self.pool, pool => {
+            sqlx::query(insert)
+                .bind(job_id)
+                .bind(step_name)
+                .bind(event_key)
+                .bind(timeout.map(|timeout| timeout.as_secs_f64()))
+                .execute(pool)
+                .await?;
+            sqlx::query(due)
+                .bind(job_id)
+                .bind(step_name)
+                .execute(pool)
+                .await?;
+        });
+        self.get_wait(job_id, step_name).await?
+            .ok_or_else(|| anyhow!("Wait of step {} of job {} not found", step_name, job_id))
+    }
+
+    pub async fn get_wait(&self, job_id: &Uuid, step_name: &str) -> Result<Option<JobWait>, Error> {
+        let query = format!("SELECT {WAIT_COLUMNS} FROM job_wait WHERE job_id = $1 AND step_name = $2");
+        let wait = with_pool!(&self.pool, pool => sqlx::query_as(&query)
+            .bind(job_id)
+            .bind(step_name)
+            .fetch_optional(pool)
+            .await)?;
+        Ok(wait)
+    }
+
+    /// Waits of a job, in the order they started.
+    pub async fn get_waits(&self, job_id: &Uuid) -> Result<Vec<JobWait>, Error> {
+        let query = format!("SELECT {WAIT_COLUMNS} FROM job_wait WHERE job_id = $1 ORDER BY created");
+        let waits = with_pool!(&self.pool, pool => sqlx::query_as(&query)
+            .bind(job_id)
+            .fetch_all(pool)
+            .await)?;
+        Ok(waits)
+    }
+
+    /// Stores the event as the last one of its key, for steps that start waiting for it later.
+    pub async fn store_event(&self, event_key: &str, payload: &Option<Value>, posted_by: &Uuid) -> Result<(), Error> {
+        let query = self.pool.sql(
+            "INSERT INTO external_event (event_key, payload, posted_by) VALUES ($1, $2, $3)
+             ON CONFLICT (event_key) DO UPDATE SET payload = excluded.payload, posted_by = excluded.posted_by, received = NOW()",
+            "INSERT INTO external_event (event_key, payload, posted_by) VALUES ($1, $2, $3)
+             ON CONFLICT (event_key) DO UPDATE SET payload = excluded.payload, posted_by = excluded.posted_by,
+                 received = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')",
+        );
+        with_pool!(&self.pool, pool => {
+            sqlx::query(query)
+                .bind(event_key)
+                .bind(payload)
+                .bind(posted_by)
+                .execute(pool)
+                .await?;
+        });
+        Ok(())
+    }
+
+    /// The last event of the key, if it was posted since the job was queued and is still kept.
+    pub async fn get_event(&self, job_id: &Uuid, event_key: &str) -> Result<Option<ExternalEvent>, Error> {
+        let query = self.pool.sql(
+            "SELECT e.payload, e.posted_by FROM external_event e, job
+             WHERE e.event_key = $2 AND job.job_id = $1 AND e.received >= job.queued
+               AND e.received > NOW() - make_interval(secs => $3)",
+            "SELECT e.payload, e.posted_by FROM external_event e, job
+             WHERE e.event_key = $2 AND job.job_id = $1 AND julianday(e.received) >= julianday(job.queued)
+               AND julianday(e.received) > julianday('now', '-' || $3 || ' seconds')",
+        );
+        let event = with_pool!(&self.pool, pool => sqlx::query_as(query)
+            .bind(job_id)
+            .bind(event_key)
+            .bind(self.event_retention.as_secs_f64())
+            .fetch_optional(pool)
+            .await)?;
+        Ok(event)
+    }
+
+    /// Jobs with a step waiting for the event.
+    pub async fn get_waiting_jobs(&self, event_key: &str) -> Result<Vec<Uuid>, Error> {
+        let job_ids = with_pool!(&self.pool, pool => sqlx::query_scalar(
+            "SELECT job_id FROM job_wait WHERE event_key = $1 AND status = 'waiting'",
+        )
+        .bind(event_key)
+        .fetch_all(pool)
+        .await)?;
+        Ok(distinct(job_ids))
+    }
+
+    /// Resolves the waits of the job for the event as `received`, returns false if none was waiting.
+    pub async fn receive(&self, job_id: &Uuid, event_key: &str, payload: &Option<Value>) -> Result<bool, Error> {
+        let query = self.pool.sql(
+            "UPDATE job_wait SET status = 'received', payload = $3, resolved = NOW()
+             WHERE job_id = $1 AND event_key = $2 AND status = 'waiting'",
+            "UPDATE job_wait SET status = 'received', payload = $3, resolved = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')
+             WHERE job_id = $1 AND event_key = $2 AND status = 'waiting'",
+        );
+        let rows = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(job_id)
+            .bind(event_key)
+            .bind(payload)
+            .execute(pool)
+            .await?
+            .rows_affected());
+        Ok(rows > 0)
+    }
+
+    /// Resolves the waits whose `resume_at` passed, as `elapsed` or `timed_out`, returns the jobs that waited.
+    pub async fn resolve_due(&self) -> Result<Vec<Uuid>, Error> {
+        let query = self.pool.sql(
+            "UPDATE job_wait SET status = CASE WHEN event_key IS NULL THEN 'elapsed' ELSE 'timed_out' END, resolved = NOW()
+             WHERE status = 'waiting' AND resume_at <= NOW()
+             RETURNING job_id",
+            "UPDATE job_wait SET status = CASE WHEN event_key IS NULL THEN 'elapsed' ELSE 'timed_out' END,
+                 resolved = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')
+             WHERE status = 'waiting' AND julianday(resume_at) <= julianday('now')
+             RETURNING job_id",
+        );
+        let job_ids = with_pool!(&self.pool, pool => sqlx::query_scalar(query)
+            .fetch_all(pool)
+            .await)?;
+        Ok(distinct(job_ids))
+    }
+
+    /// Deletes the events kept longer than the retention, steps no longer take them.
+    pub async fn prune_events(&self) -> Result<u64, Error> {
+        let before = Utc::now() - self.event_retention;
+        let query = self.pool.sql(
+            "DELETE FROM external_event WHERE received < $1",
+            "DELETE FROM external_event WHERE julianday(received) < julianday($1)",
+        );
+        let rows = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(before)
+            .execute(pool)
+            .await?
+            .rows_affected());
+        Ok(rows)
+    }
+}
+
+/// A job with several waits resolved at once is listed once.
+fn distinct(job_ids: Vec<Uuid>) -> Vec<Uuid> {
+    let mut seen = HashSet::new();
+    job_ids.into_iter().filter(|job_id| seen.insert(*job_id)).collect()
+}
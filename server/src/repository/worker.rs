@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha3::{Digest, Sha3_256};
+use stroem_common::rfc3339;
+use tracing::{debug, info};
+use uuid::Uuid;
+use super::{with_pool, DbPool};
+
+/// Worker tokens start with this, to recognize them in configs and secret scanners.
+const WORKER_TOKEN_PREFIX: &str = "stroem_worker_";
+
+#[derive(sqlx::FromRow, Debug, Serialize, Deserialize)]
+pub struct RegisteredWorker {
+    pub worker_id: String,
+    pub labels: Option<Value>,
+    pub capacity: i32,
+    pub running: i32,
+    #[serde(with = "rfc3339")]
+    pub registered_at: DateTime<Utc>,
+    #[serde(with = "rfc3339")]
+    pub last_heartbeat: DateTime<Utc>,
+    /// One of `active`, `idle` or `stale`
+    pub status: String,
+}
+
+/// A token workers authenticate with; only its hash is stored.
+#[derive(sqlx::FromRow, Debug, Serialize, Deserialize)]
+pub struct WorkerCredential {
+    pub credential_id: Uuid,
+    pub name: String,
+    #[serde(with = "rfc3339")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "rfc3339::option")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(with = "rfc3339::option")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(with = "rfc3339::option")]
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+fn hash_worker_token(token: &str) -> String {
+    // Tokens are random, so an unsalted hash is enough
+    format!("{:x}", Sha3_256::digest(token.as_bytes()))
+}
+
+#[derive(Clone)]
+pub struct WorkerRepository {
+    pool: DbPool,
+}
+
+impl WorkerRepository {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn register(&self, worker_id: &str, capacity: u32, labels: &HashMap<String, String>) -> Result<(), Error> {
+        let query = self.pool.sql(
+            "INSERT INTO worker (worker_id, labels, capacity, running, registered_at, last_heartbeat)
+             VALUES ($1, $2, $3, 0, NOW(), NOW())
+             ON CONFLICT (worker_id) DO UPDATE
+             SET labels = $2, capacity = $3, running = 0, last_heartbeat = NOW()",
+            "INSERT INTO worker (worker_id, labels, capacity, running)
+             VALUES ($1, $2, $3, 0)
+             ON CONFLICT (worker_id) DO UPDATE
+             SET labels = $2, capacity = $3, running = 0, last_heartbeat = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')",
+        );
+        let labels = serde_json::to_value(labels)?;
+        with_pool!(&self.pool, pool => {
+            sqlx::query(query)
+                .bind(worker_id)
+                .bind(&labels)
+                .bind(capacity as i32)
+                .execute(pool)
+                .await?;
+        });
+
+        info!("Registered worker {} with capacity {}", worker_id, capacity);
+        Ok(())
+    }
+
+    /// Returns false if the worker isn't registered, e.g. after the database was reset.
+    pub async fn heartbeat(&self, worker_id: &str, running: u32) -> Result<bool, Error> {
+        let query = self.pool.sql(
+            "UPDATE worker SET running = $1, last_heartbeat = NOW() WHERE worker_id = $2",
+            "UPDATE worker SET running = $1, last_heartbeat = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now') WHERE worker_id = $2",
+        );
+        let rows_affected = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(running as i32)
+            .bind(worker_id)
+            .execute(pool)
+            .await?
+            .rows_affected());
+
+        if rows_affected == 0 {
+            return Ok(false);
+        }
+
+        debug!("Heartbeat from worker {}: {} running", worker_id, running);
+        Ok(true)
+    }
+
+    /// Lists workers, flagging the ones without a heartbeat within `stale_after` as stale.
+    pub async fn get_workers(&self, stale_after: Duration) -> Result<Vec<RegisteredWorker>, Error> {
+        let query = self.pool.sql(
+            "SELECT
+                worker_id, labels, capacity, running, registered_at, last_heartbeat,
+                CASE
+                    WHEN last_heartbeat < NOW() - make_interval(secs => $1) THEN 'stale'
+                    WHEN running > 0 THEN 'active'
+                    ELSE 'idle'
+                END AS status
+             FROM worker
+             ORDER BY worker_id",
+            "SELECT
+                worker_id, labels, capacity, running, registered_at, last_heartbeat,
+                CASE
+                    WHEN last_heartbeat < strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now', '-' || $1 || ' seconds') THEN 'stale'
+                    WHEN running > 0 THEN 'active'
+                    ELSE 'idle'
+                END AS status
+             FROM worker
+             ORDER BY worker_id",
+        );
+        let list = with_pool!(&self.pool, pool => sqlx::query_as(query)
+            .bind(stale_after.as_secs_f64())
+            .fetch_all(pool)
+            .await)?;
+        Ok(list)
+    }
+
+    /// Mints a worker token. The token itself is only returned here.
+    pub async fn create_credential(&self, name: &str, expires_at: Option<DateTime<Utc>>) -> Result<(WorkerCredential, String), Error> {
+        let token = format!("{}{}{}", WORKER_TOKEN_PREFIX, Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let credential = with_pool!(&self.pool, pool => sqlx::query_as(
+            "INSERT INTO worker_credential (credential_id, name, token_hash, expires_at)
+             VALUES ($1, $2, $3, $4)
+             RETURNING credential_id, name, created_at, expires_at, last_used_at, revoked_at",
+        )
+        .bind(Uuid::new_v4())
+        .bind(name)
+        .bind(hash_worker_token(&token))
+        .bind(expires_at)
+        .fetch_one(pool)
+        .await)?;
+        info!("Created worker credential '{}'", name);
+        Ok((credential, token))
+    }
+
+    pub async fn get_credentials(&self) -> Result<Vec<WorkerCredential>, Error> {
+        let list = with_pool!(&self.pool, pool => sqlx::query_as(
+            "SELECT credential_id, name, created_at, expires_at, last_used_at, revoked_at
+             FROM worker_credential
+             ORDER BY created_at DESC",
+        )
+        .fetch_all(pool)
+        .await)?;
+        Ok(list)
+    }
+
+    /// Returns false if there is no such active credential.
+    pub async fn revoke_credential(&self, credential_id: &Uuid) -> Result<bool, Error> {
+        let query = self.pool.sql(
+            "UPDATE worker_credential SET revoked_at = NOW() WHERE credential_id = $1 AND revoked_at IS NULL",
+            "UPDATE worker_credential SET revoked_at = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now') WHERE credential_id = $1 AND revoked_at IS NULL",
+        );
+        let rows_affected = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(credential_id)
+            .execute(pool)
+            .await?
+            .rows_affected());
+        Ok(rows_affected > 0)
+    }
+
+    /// Mints a replacement token with the same name. The old one keeps working for `grace`,
+    /// so workers can be moved over one by one.
+    pub async fn rotate_credential(&self, credential_id: &Uuid, grace: Duration) -> Result<Option<(WorkerCredential, String)>, Error> {
+        let query = self.pool.sql(
+            "UPDATE worker_credential
+             SET expires_at = LEAST(COALESCE(expires_at, 'infinity'), NOW() + make_interval(secs => $2))
+             WHERE credential_id = $1 AND revoked_at IS NULL AND (expires_at IS NULL OR expires_at > NOW())
+             RETURNING name",
+            "UPDATE worker_credential
+             SET expires_at = MIN(
+                 COALESCE(expires_at, strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now', '+' || $2 || ' seconds')),
+                 strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now', '+' || $2 || ' seconds')
+             )
+             WHERE credential_id = $1 AND revoked_at IS NULL
+               AND (expires_at IS NULL OR expires_at > strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'))
+             RETURNING name",
+        );
+        let name: Option<String> = with_pool!(&self.pool, pool => sqlx::query_scalar(query)
+            .bind(credential_id)
+            .bind(grace.as_secs_f64())
+            .fetch_optional(pool)
+            .await)?;
+        match name {
+            Some(name) => Ok(Some(self.create_credential(&name, None).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Checks a worker token against the active credentials.
+    pub async fn authenticate(&self, token: &str) -> Result<bool, Error> {
+        // Workers poll constantly, so last_used_at is only refreshed once a minute
+        let token_hash = hash_worker_token(token);
+        let credential_id: Option<Uuid> = match &self.pool {
+            DbPool::Postgres(pool) => sqlx::query_scalar(
+                "WITH valid AS (
+                    SELECT credential_id, last_used_at FROM worker_credential
+                    WHERE token_hash = $1 AND revoked_at IS NULL AND (expires_at IS NULL OR expires_at > NOW())
+                 ), touched AS (
+                    UPDATE worker_credential c SET last_used_at = NOW()
+                    FROM valid v
+                    WHERE c.credential_id = v.credential_id
+                      AND (v.last_used_at IS NULL OR v.last_used_at < NOW() - INTERVAL '1 minute')
+                 )
+                 SELECT credential_id FROM valid",
+            )
+            .bind(&token_hash)
+            .fetch_optional(pool)
+            .await?,
+            // No data-modifying CTEs in SQLite
+            DbPool::Sqlite(pool) => {
+                let credential_id = sqlx::query_scalar(
+                    "SELECT credential_id FROM worker_credential
+                     WHERE token_hash = $1 AND revoked_at IS NULL
+                       AND (expires_at IS NULL OR expires_at > strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'))",
+                )
+                .bind(&token_hash)
+                .fetch_optional(pool)
+                .await?;
+                sqlx::query(
+                    "UPDATE worker_credential SET last_used_at = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')
+                     WHERE credential_id = $1
+                       AND (last_used_at IS NULL OR last_used_at < strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now', '-1 minute'))",
+                )
+                .bind(credential_id)
+                .execute(pool)
+                .await?;
+                credential_id
+            }
+        };
+        Ok(credential_id.is_some())
+    }
+}
@@ -0,0 +1,153 @@
+// workflow-server/src/retention.rs
+use std::sync::Arc;
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::watch;
+use tokio::time;
+use tracing::{debug, error, info};
+use stroem_common::rfc3339;
+use crate::repository::{ArtifactRepository, JobRepository, LogRepository};
+use crate::server_config::RetentionConfig;
+
+/// What pruning cleaned up, for the last run and since the server started.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RetentionStats {
+    #[serde(with = "rfc3339::option")]
+    pub last_run: Option<DateTime<Utc>>,
+    pub last_run_jobs: u64,
+    pub last_run_steps: u64,
+    pub last_run_logs: u64,
+    pub total_jobs: u64,
+    pub total_steps: u64,
+    pub total_logs: u64,
+    /// Logs that could not be deleted; their jobs are gone regardless
+    pub total_log_failures: u64,
+}
+
+/// Periodically deletes finished jobs past the retention policy, with their steps, logs and artifacts.
+pub struct Retention {
+    job_repository: JobRepository,
+    log_repository: Arc<dyn LogRepository + Send + Sync>,
+    artifact_repository: ArtifactRepository,
+    config: RetentionConfig,
+    task: Option<tokio::task::JoinHandle<()>>,
+    cancel_tx: watch::Sender<bool>,
+    stats_tx: watch::Sender<RetentionStats>,
+}
+
+impl Retention {
+    pub fn new(job_repository: JobRepository, log_repository: Arc<dyn LogRepository + Send + Sync>, artifact_repository: ArtifactRepository, config: RetentionConfig) -> Self {
+        let (cancel_tx, _) = watch::channel(false);
+        let (stats_tx, _) = watch::channel(RetentionStats::default());
+        Self {
+            job_repository,
+            log_repository,
+            artifact_repository,
+            config,
+            task: None,
+            cancel_tx,
+            stats_tx,
+        }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<RetentionStats> {
+        self.stats_tx.subscribe()
+    }
+
+    pub async fn run(&mut self) {
+        if self.config.max_age.is_none() && self.config.max_jobs_per_task.is_none() {
+            debug!("No retention policy configured");
+            return;
+        }
+        if self.task.is_some() {
+            info!("Retention already running");
+            return;
+        }
+
+        let mut cancel_rx = self.cancel_tx.subscribe();
+        let job_repository = self.job_repository.clone();
+        let log_repository = self.log_repository.clone();
+        let artifact_repository = self.artifact_repository.clone();
+        let config = self.config.clone();
+        let stats_tx = self.stats_tx.clone();
+
+        let task = tokio::spawn(async move {
+            let mut interval = time::interval(config.interval);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {},
+                    _ = cancel_rx.changed() => {
+                        if *cancel_rx.borrow() {
+                            info!("Retention stopping due to cancellation signal");
+                            break;
+                        }
+                    }
+                }
+
+                let mut stats = stats_tx.borrow().clone();
+                if let Err(e) = Self::prune(&job_repository, log_repository.as_ref(), &artifact_repository, &config, &mut stats).await {
+                    error!("Failed to prune jobs: {}", e);
+                }
+                stats_tx.send_replace(stats);
+            }
+        });
+
+        self.task = Some(task);
+        info!("Retention started");
+    }
+
+    /// Prunes in batches until no job is left past the policy.
+    async fn prune(job_repository: &JobRepository, log_repository: &(dyn LogRepository + Send + Sync), artifact_repository: &ArtifactRepository, config: &RetentionConfig, stats: &mut RetentionStats) -> Result<(), Error> {
+        let before = config.max_age
+            .map(chrono::Duration::from_std)
+            .transpose()?
+            .map(|max_age| Utc::now() - max_age);
+        stats.last_run = Some(Utc::now());
+        stats.last_run_jobs = 0;
+        stats.last_run_steps = 0;
+        stats.last_run_logs = 0;
+        loop {
+            let pruned = job_repository.prune_jobs(before, config.max_jobs_per_task, config.batch_size).await?;
+            for job in &pruned {
+                stats.last_run_jobs += 1;
+                stats.total_jobs += 1;
+                stats.last_run_steps += job.steps as u64;
+                stats.total_steps += job.steps as u64;
+                if let Err(e) = artifact_repository.delete_artifacts(&job.job_id).await {
+                    error!("Failed to delete artifacts of pruned job {}: {}", job.job_id, e);
+                }
+                if !config.delete_logs {
+                    continue;
+                }
+                match log_repository.delete_logs(&job.job_id).await {
+                    Ok(()) => {
+                        stats.last_run_logs += 1;
+                        stats.total_logs += 1;
+                    }
+                    Err(e) => {
+                        error!("Failed to delete logs of pruned job {}: {}", job.job_id, e);
+                        stats.total_log_failures += 1;
+                    }
+                }
+            }
+            if (pruned.len() as i64) < config.batch_size {
+                break;
+            }
+        }
+        if stats.last_run_jobs > 0 {
+            info!("Pruned {} jobs, {} steps and the logs of {} jobs", stats.last_run_jobs, stats.last_run_steps, stats.last_run_logs);
+        }
+        Ok(())
+    }
+
+    pub async fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            if let Err(e) = self.cancel_tx.send(true) {
+                error!("Failed to send cancellation signal: {}", e);
+            }
+            let _ = task.await;
+            info!("Retention stopped");
+        }
+    }
+}
@@ -1,27 +1,127 @@
 // workflow-server/src/scheduler.rs
-use stroem_common::JobRequest;
-use stroem_common::workflows_configuration::{TriggerType, WorkflowsConfiguration};
+use anyhow::Error;
+use serde_json::{json, Value};
+use stroem_common::{JobRequest, JobSpec};
+use stroem_common::parameter_renderer::ParameterRenderer;
+use stroem_common::workflows_configuration::{CatchupPolicy, TriggerType, WorkflowsConfiguration};
 use tokio::sync::watch;
-use tracing::{info, error, debug};
+use tracing::{info, error, debug, warn};
 use cron::Schedule;
 use std::str::FromStr;
 use tokio::time::{self, Duration};
-use std::collections::HashMap;
-use chrono::{Utc, DateTime};
-use crate::repository::JobRepository;
+use std::collections::{HashMap, VecDeque};
+use chrono::{Utc, DateTime, SecondsFormat};
+use chrono_tz::Tz;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use crate::metrics;
+use crate::projects::Project;
+use crate::repository::{JobRepository, TaskRepository, TriggerRepository};
+use std::sync::Arc;
+use serde::Serialize;
 
 pub struct Scheduler {
     job_repository: JobRepository,
+    task_repository: TaskRepository,
+    trigger_repository: TriggerRepository,
+    project: Arc<Project>,
     task: Option<tokio::task::JoinHandle<()>>,
     cancel_tx: watch::Sender<bool>,
     config_rx: watch::Receiver<Option<WorkflowsConfiguration>>,
+    enforce_action_sunset: bool,
+    upcoming_tx: watch::Sender<Vec<UpcomingRun>>,
+}
+
+/// Next run of a cron trigger, as shown on the dashboard.
+#[derive(Debug, Clone, Serialize)]
+pub struct UpcomingRun {
+    pub project: String,
+    pub trigger: String,
+    pub task: String,
+    pub cron: String,
+    pub timezone: String,
+    /// Includes the trigger's jitter
+    #[serde(with = "stroem_common::rfc3339")]
+    pub next_run: DateTime<Utc>,
+}
+
+/// Upper bound of missed runs enqueued by `CatchupPolicy::FireAll`, the oldest ones are skipped.
+const MAX_CATCHUP_RUNS: usize = 100;
+
+struct ScheduledTrigger {
+    cron: String,
+    schedule: Schedule,
+    timezone: Tz,
+    jitter: Option<Duration>,
+    job: JobRequest,
+    last_run: Option<DateTime<Utc>>,
+    next_run: Option<DateTime<Utc>>,
+}
+
+impl ScheduledTrigger {
+    /// Next cron occurrence after `time`, evaluated in the trigger's time zone
+    fn after(&self, time: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.schedule.after(&time.with_timezone(&self.timezone)).next()
+            .map(|next| next.with_timezone(&Utc))
+    }
+
+    /// When the run scheduled for `time` actually fires. The jitter is derived from the trigger
+    /// name and occurrence, so it spreads triggers out but stays stable across config reloads.
+    fn fire_time(&self, trigger_name: &str, time: DateTime<Utc>) -> DateTime<Utc> {
+        let Some(jitter) = self.jitter.filter(|jitter| !jitter.is_zero()) else { return time };
+        let mut hasher = DefaultHasher::new();
+        (trigger_name, time.timestamp()).hash(&mut hasher);
+        let offset = hasher.finish() % jitter.as_millis() as u64;
+        time + chrono::Duration::milliseconds(offset as i64)
+    }
+
+    /// Lines up the runs missed since `last_run` according to the catch-up policy.
+    fn catch_up(&mut self, trigger_name: &str, policy: CatchupPolicy, last_run: DateTime<Utc>, now: DateTime<Utc>) {
+        let mut missed = VecDeque::new();
+        let mut count = 0;
+        for time in self.schedule.after(&last_run.with_timezone(&self.timezone)) {
+            let time = time.with_timezone(&Utc);
+            if time > now {
+                break;
+            }
+            if missed.len() == MAX_CATCHUP_RUNS {
+                missed.pop_front();
+            }
+            missed.push_back(time);
+            count += 1;
+        }
+        if count == 0 {
+            return;
+        }
+
+        match policy {
+            CatchupPolicy::None => {
+                info!("Trigger '{}' missed {} run(s) since {}, skipping them", trigger_name, count, last_run);
+            }
+            CatchupPolicy::FireOnce => {
+                info!("Trigger '{}' missed {} run(s) since {}, catching up once", trigger_name, count, last_run);
+                self.last_run = Some(last_run);
+                self.next_run = missed.back().copied();
+            }
+            CatchupPolicy::FireAll => {
+                if count > missed.len() {
+                    warn!("Trigger '{}' missed {} runs since {}, only catching up the last {}", trigger_name, count, last_run, missed.len());
+                } else {
+                    info!("Trigger '{}' missed {} run(s) since {}, catching up all of them", trigger_name, count, last_run);
+                }
+                self.last_run = Some(last_run);
+                self.next_run = missed.front().copied();
+            }
+        }
+    }
 }
 
 impl Scheduler {
     fn load_config(
+        project: &Project,
         config: Option<WorkflowsConfiguration>,
-        old_schedules: Option<&HashMap<String, (Schedule, JobRequest, Option<DateTime<Utc>>, Option<DateTime<Utc>>)>>,
-    ) -> HashMap<String, (Schedule, JobRequest, Option<DateTime<Utc>>, Option<DateTime<Utc>>)> {
+        old_schedules: Option<&HashMap<String, ScheduledTrigger>>,
+        stored_runs: Option<&HashMap<String, DateTime<Utc>>>,
+    ) -> HashMap<String, ScheduledTrigger> {
         let mut schedules = HashMap::new();
         let Some(config) = config else { return schedules };
 
@@ -32,12 +132,18 @@ impl Scheduler {
                 }
 
                 match &trigger.trigger_type {
-                    TriggerType::Scheduler { cron } => {
+                    TriggerType::Scheduler { cron, timezone, jitter, catchup } => {
+                        let timezone = match timezone.as_deref().map(Tz::from_str).transpose() {
+                            Ok(timezone) => timezone.unwrap_or(Tz::UTC),
+                            Err(_) => {
+                                error!("Invalid timezone for trigger '{}': {}", trigger_name, timezone.as_deref().unwrap_or_default());
+                                continue;
+                            }
+                        };
                         match Schedule::from_str(&cron) {
                             Ok(schedule) => {
                                 let job = JobRequest {
-                                    task: Some(trigger.task.clone()),
-                                    action: None,
+                                    spec: JobSpec::Task { name: trigger.task.clone() },
                                     input: trigger.input.clone()
                                         .map(|inputs| {
                                             let mut map = serde_json::Map::new();
@@ -47,34 +153,93 @@ impl Scheduler {
                                             serde_json::Value::Object(map)
                                         }),
                                     uuid: None,
+                                    priority: trigger.priority,
+                                    resume: None,
+                                    traceparent: None,
+                                    project: project.job_project(),
                                 };
-                                // Use last_run from old_schedules if available, otherwise None
-                                let last_run = old_schedules
-                                    .and_then(|old| old.get(trigger_name))
-                                    .and_then(|(_, _, last, _)| *last);
-                                info!("Added trigger '{}' to scheduler: {}", trigger_name, &cron);
-                                schedules.insert(trigger_name.clone(), (schedule, job, last_run, None));
+                                let mut scheduled = ScheduledTrigger {
+                                    cron: cron.clone(),
+                                    schedule,
+                                    timezone,
+                                    jitter: *jitter,
+                                    job,
+                                    last_run: None,
+                                    next_run: None,
+                                };
+                                // Use last_run from old_schedules if available, otherwise catch up from the stored one
+                                match old_schedules.and_then(|old| old.get(trigger_name)) {
+                                    Some(old) => scheduled.last_run = old.last_run,
+                                    None => if let Some(last_run) = stored_runs.and_then(|runs| runs.get(&project.scoped_name(trigger_name))) {
+                                        scheduled.catch_up(trigger_name, *catchup, *last_run, Utc::now());
+                                    }
+                                }
+                                info!("Added trigger '{}' to scheduler: {} ({})", trigger_name, &cron, timezone);
+                                schedules.insert(trigger_name.clone(), scheduled);
                             }
                             Err(e) => error!("Invalid cron expression for trigger '{}': {}", trigger_name, e),
                         }
 
                     }
+                    TriggerType::Webhook { .. } | TriggerType::Nats { .. } => {}
                 }
             }
         }
         schedules
     }
 
-    pub fn new(job_repository: JobRepository, config_rx: watch::Receiver<Option<WorkflowsConfiguration>>) -> Self {
+    /// Lists the next run of every scheduled trigger, soonest first.
+    fn upcoming(schedules: &HashMap<String, ScheduledTrigger>) -> Vec<UpcomingRun> {
+        let mut upcoming: Vec<UpcomingRun> = schedules.iter()
+            .filter_map(|(trigger_name, trigger)| trigger.next_run.map(|next_run| UpcomingRun {
+                project: trigger.job.project().to_string(),
+                trigger: trigger_name.clone(),
+                task: trigger.job.spec.name().to_string(),
+                cron: trigger.cron.clone(),
+                timezone: trigger.timezone.to_string(),
+                next_run: trigger.fire_time(trigger_name, next_run),
+            }))
+            .collect();
+        upcoming.sort_by(|a, b| a.next_run.cmp(&b.next_run).then_with(|| a.trigger.cmp(&b.trigger)));
+        upcoming
+    }
+
+    /// Fires the cron triggers of the project.
+    pub fn new(job_repository: JobRepository, task_repository: TaskRepository, trigger_repository: TriggerRepository, project: Arc<Project>, enforce_action_sunset: bool) -> Self {
         let (cancel_tx, _) = watch::channel(false);
+        let (upcoming_tx, _) = watch::channel(Vec::new());
+        let config_rx = project.workspace.subscribe();
         Self {
             job_repository,
+            task_repository,
+            trigger_repository,
+            project,
             task: None,
             cancel_tx,
             config_rx,
+            enforce_action_sunset,
+            upcoming_tx,
         }
     }
 
+    /// Upcoming runs, updated whenever the scheduler wakes up.
+    pub fn subscribe(&self) -> watch::Receiver<Vec<UpcomingRun>> {
+        self.upcoming_tx.subscribe()
+    }
+
+    /// Renders the templates in a trigger's input for its run at `scheduled_time`, e.g. `{{ scheduled_time }}` or
+    /// `{{ trigger.name }}`. Secrets are left to the runner, the input is stored with the job.
+    fn render_input(trigger_name: &str, scheduled_time: DateTime<Utc>, input: Option<Value>, config: Option<&WorkflowsConfiguration>) -> Result<Option<Value>, Error> {
+        let Some(input) = input else { return Ok(None) };
+        let mut renderer = ParameterRenderer::new();
+        renderer.add_to_context(json!({
+            "trigger": {"name": trigger_name},
+            "scheduled_time": scheduled_time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            "globals": config.and_then(|config| config.globals.as_ref()),
+        }))?;
+        Ok(Some(renderer.render(input)?))
+    }
+
     pub async fn run(&mut self) {
         if self.task.is_some() {
             info!("Scheduler already running");
@@ -84,35 +249,75 @@ impl Scheduler {
         let mut cancel_rx = self.cancel_tx.subscribe();
         let mut config_rx = self.config_rx.clone();
         let job_repo = self.job_repository.clone();
+        let task_repo = self.task_repository.clone();
+        let trigger_repo = self.trigger_repository.clone();
+        let enforce_action_sunset = self.enforce_action_sunset;
+        let project = self.project.clone();
+        let upcoming_tx = self.upcoming_tx.clone();
 
         let task = tokio::spawn(async move {
-            let mut schedules = Self::load_config(config_rx.borrow().clone(), None);
+            let stored_runs = trigger_repo.get_last_runs().await.unwrap_or_else(|e| {
+                error!("Failed to load last trigger runs, missed runs won't be caught up: {}", e);
+                HashMap::new()
+            });
+            let mut schedules = Self::load_config(&project, config_rx.borrow().clone(), None, Some(&stored_runs));
             loop {
                 let now = Utc::now();
                 let mut next_wakeup = None;
 
-                for (trigger_name, (schedule, job, last_run, next_run)) in &mut schedules {
+                for (trigger_name, trigger) in &mut schedules {
                     debug!("Processing trigger '{}'", trigger_name);
-                    if next_run.is_none() {
-                        *next_run = schedule.after(&last_run.unwrap_or(now)).next();
+                    if trigger.next_run.is_none() {
+                        trigger.next_run = trigger.after(&trigger.last_run.unwrap_or(now));
                     }
 
-                    if let Some(next_time) = *next_run {
-                        if now >= next_time {
-                            let job = JobRequest {
-                                task: job.task.clone(),
-                                action: None,
-                                input: job.input.clone(),
-                                uuid: None,
+                    if let Some(next_time) = trigger.next_run {
+                        let fire_time = trigger.fire_time(trigger_name, next_time);
+                        if now >= fire_time {
+                            metrics::SCHEDULER_TICK_LAG.observe((now - fire_time).num_milliseconds() as f64 / 1000.0);
+                            let (input, sunset) = {
+                                let config = config_rx.borrow();
+                                let input = Self::render_input(trigger_name, next_time, trigger.job.input.clone(), config.as_ref());
+                                let sunset = match config.as_ref() {
+                                    Some(config) if enforce_action_sunset => config.sunset_actions(trigger.job.task(), None, now.date_naive()),
+                                    _ => vec![],
+                                };
+                                (input, sunset)
                             };
-                            if let Err(e) = job_repo.enqueue_job(&job, "trigger", Some(&trigger_name)).await {
-                                error!("Failed to enqueue job for trigger '{}': {}", trigger_name, e);
-                            } else {
-                                info!("Enqueued job for trigger '{}'", trigger_name);
+                            match input {
+                                _ if !sunset.is_empty() => error!("Skipping trigger '{}', refusing to run past sunset date: {}", trigger_name, sunset.join("; ")),
+                                Err(e) => error!("Skipping trigger '{}', failed to render its input: {}", trigger_name, e),
+                                Ok(input) => {
+                                    let job = JobRequest {
+                                        spec: trigger.job.spec.clone(),
+                                        input,
+                                        uuid: None,
+                                        priority: trigger.job.priority,
+                                        resume: None,
+                                        traceparent: None,
+                                        project: trigger.job.project.clone(),
+                                    };
+                                    // Runs missed while paused are skipped, not caught up on resume
+                                    match async {
+                                        if task_repo.is_paused(&project.scoped_name(job.spec.name())).await? {
+                                            return Ok(None);
+                                        }
+                                        let rate_limit = project.workspace.rate_limit_for(&job)?;
+                                        job_repo.enqueue_job(&job, "trigger", Some(trigger_name), project.workspace.get_revision().as_deref(), rate_limit.as_ref(), None).await.map(Some)
+                                    }.await {
+                                        Ok(Some(_)) => info!("Enqueued job for trigger '{}'", trigger_name),
+                                        Ok(None) => info!("Skipping trigger '{}', task is paused", trigger_name),
+                                        Err(e) => error!("Failed to enqueue job for trigger '{}': {}", trigger_name, e),
+                                    }
+                                }
                             }
-                            *last_run = Some(next_time);
-                            *next_run = schedule.after(&next_time).next();
-                            if let Some(new_next) = *next_run {
+                            trigger.last_run = Some(next_time);
+                            if let Err(e) = trigger_repo.set_last_run(&project.scoped_name(trigger_name), next_time).await {
+                                error!("Failed to store last run of trigger '{}': {}", trigger_name, e);
+                            }
+                            trigger.next_run = trigger.after(&next_time);
+                            if let Some(new_next) = trigger.next_run {
+                                let new_next = trigger.fire_time(trigger_name, new_next);
                                 let new_duration = (new_next - now).to_std()
                                     .unwrap_or_else(|_| Duration::from_secs(1));
                                 debug!("Trigger '{}': next run at {:?}, sleep duration {:?}", trigger_name, new_next, new_duration);
@@ -123,9 +328,9 @@ impl Scheduler {
                                 );
                             }
                         } else {
-                            let duration = (next_time - now).to_std()
+                            let duration = (fire_time - now).to_std()
                                 .unwrap_or_else(|_| Duration::from_secs(1));
-                            debug!("Trigger '{}': next run at {:?}, sleep duration {:?}", trigger_name, next_time, duration);
+                            debug!("Trigger '{}': next run at {:?}, sleep duration {:?}", trigger_name, fire_time, duration);
                             next_wakeup = Some(
                                 next_wakeup
                                     .map(|d: Duration| d.min(duration))
@@ -137,6 +342,8 @@ impl Scheduler {
                     }
                 }
 
+                upcoming_tx.send_replace(Self::upcoming(&schedules));
+
                 match next_wakeup {
                     Some(duration) => {
                         debug!("Sleeping for {:?}", duration);
@@ -151,7 +358,7 @@ impl Scheduler {
                             _ = config_rx.changed() => {
                                 info!("Reloading scheduler due to workspace config change");
                                 let new_config = config_rx.borrow().clone();
-                                schedules = Self::load_config(new_config, Some(&schedules));
+                                schedules = Self::load_config(&project, new_config, Some(&schedules), None);
                             }
                         }
                     }
@@ -160,7 +367,7 @@ impl Scheduler {
                         tokio::select! {
                                 _ = config_rx.changed() => {
                                     info!("Config reloaded, checking for new schedules");
-                                    schedules = Self::load_config(config_rx.borrow().clone(), Some(&schedules));
+                                    schedules = Self::load_config(&project, config_rx.borrow().clone(), Some(&schedules), None);
                                 }
                                 _ = cancel_rx.changed() => {
                                     if *cancel_rx.borrow() {
@@ -175,7 +382,7 @@ impl Scheduler {
         });
 
         self.task = Some(task);
-        info!("Scheduler started");
+        info!("Scheduler of project '{}' started", self.project.name);
     }
 
     pub async fn stop(&mut self) {
@@ -184,7 +391,7 @@ impl Scheduler {
                 error!("Failed to send cancellation signal: {}", e);
             }
             let _ = task.await;
-            info!("Scheduler stopped");
+            info!("Scheduler of project '{}' stopped", self.project.name);
         } else {
             info!("Scheduler not running");
         }
@@ -0,0 +1,128 @@
+use anyhow::{anyhow, Error};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+use stroem_common::rfc3339;
+use crate::auth::User;
+use crate::web::WebState;
+
+/// A typed hit for the UI omnibox.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SearchResult {
+    Task {
+        project: String,
+        id: String,
+        name: Option<String>,
+        description: Option<String>,
+    },
+    Action {
+        project: String,
+        id: String,
+        name: Option<String>,
+        description: Option<String>,
+    },
+    Job {
+        project: String,
+        job_id: Uuid,
+        task: Option<String>,
+        action: Option<String>,
+        status: Option<String>,
+        source_id: Option<String>,
+    },
+    Log {
+        job_id: Uuid,
+        step_name: Option<String>,
+        #[serde(with = "rfc3339")]
+        timestamp: DateTime<Utc>,
+        message: String,
+    },
+}
+
+/// Maximum number of results returned per result type, at most `MAX_LIMIT` each.
+#[derive(Debug, Clone, Copy)]
+pub struct SearchLimits {
+    pub tasks: usize,
+    pub actions: usize,
+    pub jobs: usize,
+    pub logs: usize,
+}
+
+impl SearchLimits {
+    /// Most results of one type returned, whatever the client asks for
+    pub const MAX_LIMIT: usize = 50;
+
+    /// Limits of `limit` results per type, overridden per type and capped at `MAX_LIMIT`.
+    pub fn new(limit: usize, tasks: Option<usize>, actions: Option<usize>, jobs: Option<usize>, logs: Option<usize>) -> Self {
+        let capped = |value: Option<usize>| value.unwrap_or(limit).min(Self::MAX_LIMIT);
+        Self { tasks: capped(tasks), actions: capped(actions), jobs: capped(jobs), logs: capped(logs) }
+    }
+}
+
+fn matches(query: &str, fields: &[Option<&str>]) -> bool {
+    fields.iter().flatten().any(|field| field.to_lowercase().contains(query))
+}
+
+/// Searches tasks, actions, jobs and the log lines of jobs queued within the default window of the job list.
+/// Tasks, jobs and logs the user may not view are left out, as are the tasks and actions of projects they may not view.
+pub async fn search(api: &WebState, user: &User, query: &str, limits: SearchLimits) -> Result<Vec<SearchResult>, Error> {
+    let needle = query.to_lowercase();
+    let mut results = Vec::new();
+
+    // Projects are iterated by name, so hits are sorted by project, then id
+    let mut tasks = Vec::new();
+    let mut actions = Vec::new();
+    for project in api.projects.iter().filter(|project| user.can_view_project(project.acl.as_ref())) {
+        let workflows_guard = project.workspace.workflows.read().map_err(|_| anyhow!("Could not read workspace"))?;
+        let Some(workflows) = workflows_guard.as_ref() else { continue };
+        let mut project_tasks: Vec<_> = workflows.tasks.iter().flatten()
+            .filter(|(id, task)| matches(&needle, &[Some(id.as_str()), task.name.as_deref(), task.description.as_deref()]))
+            .filter(|(_, task)| user.can_view_task(Some(task)))
+            .collect();
+        project_tasks.sort_by_key(|(id, _)| id.as_str());
+        tasks.extend(project_tasks.into_iter().map(|(id, task)| SearchResult::Task {
+            project: project.name.clone(),
+            id: id.clone(),
+            name: task.name.clone(),
+            description: task.description.clone(),
+        }));
+
+        let mut project_actions: Vec<_> = workflows.actions.iter().flatten()
+            .filter(|(id, action)| matches(&needle, &[Some(id.as_str()), action.name.as_deref(), action.description.as_deref()]))
+            .collect();
+        project_actions.sort_by_key(|(id, _)| id.as_str());
+        actions.extend(project_actions.into_iter().map(|(id, action)| SearchResult::Action {
+            project: project.name.clone(),
+            id: id.clone(),
+            name: action.name.clone(),
+            description: action.description.clone(),
+        }));
+    }
+    results.extend(tasks.into_iter().take(limits.tasks));
+    results.extend(actions.into_iter().take(limits.actions));
+
+    if limits.jobs > 0 {
+        let jobs = api.job_repository.search_jobs(query, &api.visible_jobs(user), limits.jobs as i64).await?;
+        results.extend(jobs.into_iter().map(|job| SearchResult::Job {
+            project: job.project_id,
+            job_id: job.job_id,
+            task: job.task,
+            action: job.action,
+            status: job.status,
+            source_id: job.source_id,
+        }));
+    }
+
+    if limits.logs > 0 {
+        let from = Utc::now() - api.job_list.default_window;
+        let lines = api.job_repository.search_logs(query, &api.visible_jobs(user), from, limits.logs as i64).await?;
+        results.extend(lines.into_iter().map(|line| SearchResult::Log {
+            job_id: line.job_id,
+            step_name: line.step_name,
+            timestamp: line.timestamp,
+            message: line.message,
+        }));
+    }
+
+    Ok(results)
+}
@@ -1,31 +1,572 @@
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use config::{Config, Environment, File};
-use anyhow::{Context, Error, anyhow};
+use anyhow::{Context, Error, anyhow, bail};
 use reqwest::Url;
 use strum::AsRefStr;
 use std::time::Duration;
-use duration_str::deserialize_duration;
+use duration_str::{deserialize_duration, deserialize_option_duration};
+use stroem_common::DEFAULT_PROJECT;
+use stroem_common::workflows_configuration::{TaskAcl, TaskNotify};
 
 #[derive(Debug, Deserialize)]
 pub struct ServerConfig {
     pub public_url: Url,
+    /// Address the web server binds to
+    #[serde(default = "default_listen_addr")]
+    pub listen_addr: SocketAddr,
+    /// Serves HTTPS directly, instead of leaving TLS to a proxy in front
+    pub tls: Option<TlsConfig>,
     pub db: DbConfig,
     pub log_storage: LogStorageConfig,
+    /// Where the files actions upload as artifacts are kept; uploads are refused without it
+    pub artifact_storage: Option<ArtifactStorageConfig>,
+    /// Workspace of the default project
     pub workspace: WorkspaceSourceConfig,
+    /// Projects served next to the default one, by name
+    #[serde(default)]
+    pub projects: HashMap<String, ProjectConfig>,
     pub auth: AuthConfig,
-    pub worker_token: String
+    /// Deprecated shared worker token, still accepted next to the worker credentials minted through the API
+    pub worker_token: Option<String>,
+    #[serde(default = "default_true")]
+    pub enforce_action_sunset: bool,
+    #[serde(default)]
+    pub job_list: JobListConfig,
+    /// Workers without a heartbeat for this long are reported as stale
+    #[serde(default = "default_worker_stale_after", deserialize_with = "deserialize_duration")]
+    pub worker_stale_after: Duration,
+    #[serde(default)]
+    pub queue: QueueConfig,
+    /// How the live events of jobs reach the streams on other servers
+    #[serde(default)]
+    pub events: EventsConfig,
+    #[serde(default)]
+    pub autoscale: AutoscaleConfig,
+    #[serde(default)]
+    pub accounting: AccountingConfig,
+    /// Hooks called with a summary of every finished job
+    #[serde(default)]
+    pub post_processors: Vec<PostProcessorConfig>,
+    #[serde(default)]
+    pub access_log: AccessLogConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    /// Receives OpenLineage run events for every job
+    pub lineage: Option<LineageConfig>,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    /// Exports trace spans, which the worker and runner of a job join
+    pub otlp: Option<OtlpConfig>,
+    /// Delivery of the webhooks tasks declare
+    #[serde(default)]
+    pub task_webhooks: TaskWebhooksConfig,
+    /// Messages about finished jobs, to the channels configured here
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+    #[serde(default)]
+    pub alerts: AlertsConfig,
+    #[serde(default)]
+    pub sla: SlaConfig,
+    #[serde(default)]
+    pub waits: WaitsConfig,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct DbConfig {
+/// Rules checked periodically, raising the alerts listed under `/api/alerts`
+#[derive(Debug, Deserialize, Clone)]
+pub struct AlertsConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_alert_check_interval", deserialize_with = "deserialize_duration")]
+    pub check_interval: Duration,
+    /// Jobs finished within this window count towards failure rates
+    #[serde(default = "default_alert_window", deserialize_with = "deserialize_duration")]
+    pub window: Duration,
+    /// Replaces the default rules: fewer than one worker, and tasks failing more than 20% of their jobs
+    #[serde(default = "default_alert_rules")]
+    pub rules: Vec<AlertRule>,
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        AlertsConfig {
+            enabled: true,
+            check_interval: default_alert_check_interval(),
+            window: default_alert_window(),
+            rules: default_alert_rules(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AlertRule {
+    pub name: String,
+    #[serde(flatten)]
+    pub metric: AlertMetric,
+    pub threshold: f64,
+    /// Thresholds of single tasks, for the metrics evaluated per task; `null` leaves the task out.
+    /// Tasks of other projects than the default one are named `<project>/<task>`
+    #[serde(default)]
+    pub tasks: HashMap<String, Option<f64>>,
+}
+
+#[derive(Debug, Deserialize, Clone, AsRefStr)]
+#[strum(serialize_all = "snake_case")]
+#[serde(tag = "metric", rename_all = "snake_case")]
+pub enum AlertMetric {
+    /// Per task, the share of the jobs finished in the window that failed, fires above the threshold
+    FailureRate {
+        /// Tasks with fewer finished jobs in the window are left alone
+        #[serde(default = "default_alert_min_jobs")]
+        min_jobs: i64,
+    },
+    /// Per task, seconds its longest running job has been running, fires above the threshold
+    JobDuration,
+    /// Jobs waiting for a worker, fires above the threshold
+    QueueDepth,
+    /// Workers that aren't stale, fires below the threshold
+    WorkerCount,
+}
+
+/// Checks of jobs against the `sla` their task declares, recording the breaches listed under `/api/sla/breaches`
+#[derive(Debug, Deserialize, Clone)]
+pub struct SlaConfig {
+    #[serde(default = "default_sla_check_interval", deserialize_with = "deserialize_duration")]
+    pub check_interval: Duration,
+}
+
+impl Default for SlaConfig {
+    fn default() -> Self {
+        SlaConfig {
+            check_interval: default_sla_check_interval(),
+        }
+    }
+}
+
+/// Checks of the suspended jobs, queuing them again once their `wait` is over or their `wait_for_event` timed out
+#[derive(Debug, Deserialize, Clone)]
+pub struct WaitsConfig {
+    #[serde(default = "default_wait_check_interval", deserialize_with = "deserialize_duration")]
+    pub check_interval: Duration,
+    /// How long posted events are kept for steps that start waiting for them later
+    #[serde(default = "default_event_retention", deserialize_with = "deserialize_duration")]
+    pub event_retention: Duration,
+}
+
+impl Default for WaitsConfig {
+    fn default() -> Self {
+        WaitsConfig {
+            check_interval: default_wait_check_interval(),
+            event_retention: default_event_retention(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct NotificationsConfig {
+    /// Channels by name, which notification targets refer to
+    #[serde(default)]
+    pub channels: HashMap<String, NotificationChannelConfig>,
+    /// Targets notified for the jobs of every task, next to the ones a task declares under `notify:`
+    #[serde(default)]
+    pub notify: TaskNotify,
+    /// Rendered with `job` (the summary webhooks get), `name` (task or action) and `duration`
+    #[serde(default = "default_notification_subject")]
+    pub subject: String,
+    #[serde(default = "default_notification_template")]
+    pub template: String,
+    /// Rendered for SLA breaches, with `sla` (`kind`, `limit` and `elapsed`) next to the above
+    #[serde(default = "default_sla_breach_subject")]
+    pub sla_breach_subject: String,
+    #[serde(default = "default_sla_breach_template")]
+    pub sla_breach_template: String,
+    #[serde(default = "default_notification_timeout", deserialize_with = "deserialize_duration")]
+    pub timeout: Duration,
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        NotificationsConfig {
+            channels: HashMap::new(),
+            notify: TaskNotify::default(),
+            subject: default_notification_subject(),
+            template: default_notification_template(),
+            sla_breach_subject: default_sla_breach_subject(),
+            sla_breach_template: default_sla_breach_template(),
+            timeout: default_notification_timeout(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, AsRefStr)]
+#[strum(serialize_all = "snake_case")]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NotificationChannelConfig {
+    /// Slack incoming webhook; the recipient of a target overrides its channel, e.g. `slack:#ops`
+    Slack { webhook_url: Url },
+    /// Microsoft Teams incoming webhook
+    Teams { webhook_url: Url },
+    /// POSTs the message with the job summary as JSON
+    Webhook {
+        url: Url,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+    /// Mails through an SMTP server; the recipient of a target replaces `to`, e.g. `email:ops@example.com`
+    Email(SmtpConfig),
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SmtpConfig {
     pub host: String,
-    #[serde(default = "default_db_port")]
+    #[serde(default = "default_smtp_port")]
     pub port: u16,
-    pub database: String,
-    pub username: String,
-    pub password: String,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Sender, e.g. `Stroem <stroem@example.com>`
+    pub from: String,
+    #[serde(default)]
+    pub to: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+    /// Plain connection, refused with a `username`
+    None,
+    /// Upgrades the plain connection, usually on port 587
+    #[default]
+    Starttls,
+    /// TLS from the start, usually on port 465
+    Tls,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TaskWebhooksConfig {
+    /// Key of the HMAC-SHA256 signature in the `X-Stroem-Signature` header; unsigned without it
+    pub secret: Option<String>,
+    #[serde(default = "default_task_webhook_timeout", deserialize_with = "deserialize_duration")]
+    pub timeout: Duration,
+    #[serde(default = "default_task_webhook_max_attempts")]
+    pub max_attempts: u32,
+}
+
+impl Default for TaskWebhooksConfig {
+    fn default() -> Self {
+        TaskWebhooksConfig {
+            secret: None,
+            timeout: default_task_webhook_timeout(),
+            max_attempts: default_task_webhook_max_attempts(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first
+    pub cert: PathBuf,
+    /// PEM private key of the certificate
+    pub key: PathBuf,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct OtlpConfig {
+    /// OTLP/HTTP base URL of the collector, e.g. `http://tempo:4318`
+    pub endpoint: Url,
+}
+
+/// Prometheus metrics at `/metrics`, unauthenticated like the health checks.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct MetricsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LineageConfig {
+    /// OpenLineage HTTP endpoint, e.g. `http://marquez:5000/api/v1/lineage`
+    pub url: Url,
+    /// Namespace of the jobs, tasks and actions become the job names
+    #[serde(default = "default_lineage_namespace")]
+    pub namespace: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default = "default_lineage_timeout", deserialize_with = "deserialize_duration")]
+    pub timeout: Duration,
+}
+
+/// Requests allowed per client, keyed by bearer token or else client IP. A `null` limit disables it.
+#[derive(Debug, Deserialize, Clone)]
+pub struct RateLimitConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Applies to every request, on top of the limit of its route class, keyed by the authenticated user or worker, else the client IP
+    #[serde(default = "default_rate_limit_global")]
+    pub global: Option<RateLimit>,
+    /// Logins and OIDC callbacks, always keyed by client IP
+    #[serde(default = "default_rate_limit_auth")]
+    pub auth: Option<RateLimit>,
+    /// Requests that enqueue jobs: runs, re-runs, resumes and webhooks
+    #[serde(default = "default_rate_limit_run")]
+    pub run: Option<RateLimit>,
+    /// Worker and runner endpoints
+    #[serde(default = "default_rate_limit_worker")]
+    pub worker: Option<RateLimit>,
+    /// Key clients by the first `X-Forwarded-For` address, only safe behind a proxy that sets it
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_true(),
+            global: default_rate_limit_global(),
+            auth: default_rate_limit_auth(),
+            run: default_rate_limit_run(),
+            worker: default_rate_limit_worker(),
+            trust_forwarded_for: false,
+        }
+    }
+}
+
+/// At most `requests` per `per`, refilled evenly, so bursts up to `requests` are allowed.
+#[derive(Debug, Deserialize, Clone)]
+pub struct RateLimit {
+    pub requests: u32,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub per: Duration,
+}
+
+/// How long finished jobs are kept. Without `max_age` or `max_jobs_per_task` nothing is pruned.
+#[derive(Debug, Deserialize, Clone)]
+pub struct RetentionConfig {
+    /// Finished jobs queued longer ago than this are deleted
+    #[serde(default, deserialize_with = "deserialize_option_duration")]
+    pub max_age: Option<Duration>,
+    /// Only the most recent finished jobs of every task or action are kept
+    pub max_jobs_per_task: Option<i64>,
+    /// Also delete the job's logs; otherwise the archives stay in log storage, e.g. for a bucket lifecycle rule
+    #[serde(default = "default_true")]
+    pub delete_logs: bool,
+    #[serde(default = "default_retention_interval", deserialize_with = "deserialize_duration")]
+    pub interval: Duration,
+    /// Jobs deleted per statement, so pruning a large backlog doesn't hold long locks
+    #[serde(default = "default_retention_batch_size")]
+    pub batch_size: i64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            max_age: None,
+            max_jobs_per_task: None,
+            delete_logs: default_true(),
+            interval: default_retention_interval(),
+            batch_size: default_retention_batch_size(),
+        }
+    }
+}
+
+/// Access logs of API, worker and webhook requests.
+#[derive(Debug, Deserialize, Clone)]
+pub struct AccessLogConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Requests taking longer than this are logged at WARN
+    #[serde(default = "default_slow_request_threshold", deserialize_with = "deserialize_duration")]
+    pub slow_request_threshold: Duration,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_true(),
+            slow_request_threshold: default_slow_request_threshold(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PostProcessorConfig {
+    /// Only call the hook for jobs with one of these statuses (`completed`, `failed`)
+    pub on: Option<Vec<String>>,
+    /// Only call the hook for jobs of these tasks
+    pub tasks: Option<Vec<String>>,
+    #[serde(flatten)]
+    pub post_processor_type: PostProcessorType,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, AsRefStr)]
+#[strum(serialize_all = "snake_case")]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PostProcessorType {
+    /// POSTs the job summary as JSON
+    Http {
+        url: Url,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        #[serde(default = "default_post_processor_timeout", deserialize_with = "deserialize_duration")]
+        timeout: Duration,
+    },
+}
+
+/// Cost rates used for usage reports, per hour of job compute time.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct AccountingConfig {
+    #[serde(default)]
+    pub default_rate: f64,
+    /// The first rate whose labels all match the worker's labels applies
+    #[serde(default)]
+    pub rates: Vec<CostRate>,
+    pub currency: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CostRate {
+    pub labels: HashMap<String, String>,
+    pub rate: f64,
+}
+
+/// Inputs for the worker count recommendation served to external autoscalers.
+#[derive(Debug, Deserialize, Clone)]
+pub struct AutoscaleConfig {
+    /// Queued jobs should start within this time (SLA target)
+    #[serde(default = "default_autoscale_target_wait", deserialize_with = "deserialize_duration")]
+    pub target_wait: Duration,
+    #[serde(default)]
+    pub min_workers: u32,
+    pub max_workers: Option<u32>,
+    /// Receives a POST whenever the recommended worker count changes
+    pub webhook_url: Option<Url>,
+    #[serde(default = "default_autoscale_check_interval", deserialize_with = "deserialize_duration")]
+    pub check_interval: Duration,
+}
+
+impl Default for AutoscaleConfig {
+    fn default() -> Self {
+        Self {
+            target_wait: default_autoscale_target_wait(),
+            min_workers: 0,
+            max_workers: None,
+            webhook_url: None,
+            check_interval: default_autoscale_check_interval(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, AsRefStr)]
+#[strum(serialize_all = "snake_case")]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum QueueConfig {
+    /// Dispatch directly from the job table
+    #[default]
+    Postgres,
+    /// Dispatch from a Redis sorted set, requires the `redis-queue` feature
+    Redis {
+        url: String,
+        #[serde(default = "default_redis_queue_key")]
+        key: String,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, AsRefStr)]
+#[strum(serialize_all = "snake_case")]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventsConfig {
+    /// Only streams on the server a worker reports to get the events, enough for a single server
+    #[default]
+    Local,
+    /// Relay through Postgres LISTEN/NOTIFY
+    Postgres,
+    /// Relay through a Redis pub/sub channel, requires the `redis-events` feature
+    Redis {
+        url: String,
+        #[serde(default = "default_redis_events_channel")]
+        channel: String,
+    },
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct JobListConfig {
+    /// Jobs queued earlier than this are only listed with `include_archived=true`
+    #[serde(default = "default_job_list_window", deserialize_with = "deserialize_duration")]
+    pub default_window: Duration,
+    #[serde(default = "default_job_list_limit")]
+    pub default_limit: i64,
+}
+
+impl Default for JobListConfig {
+    fn default() -> Self {
+        Self {
+            default_window: default_job_list_window(),
+            default_limit: default_job_list_limit(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "DbConfigFields")]
+pub enum DbConfig {
+    Postgres {
+        host: String,
+        port: u16,
+        database: String,
+        username: String,
+        password: String,
+    },
+    /// A single database file, created if missing; for evaluations and edge deployments
+    Sqlite {
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum DbType {
+    /// Configs predating the SQLite option have no `type`
+    #[default]
+    Postgres,
+    Sqlite,
+}
+
+#[derive(Deserialize)]
+struct DbConfigFields {
+    #[serde(rename = "type", default)]
+    db_type: DbType,
+    host: Option<String>,
+    #[serde(default = "default_db_port")]
+    port: u16,
+    database: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    path: Option<PathBuf>,
+}
+
+impl TryFrom<DbConfigFields> for DbConfig {
+    type Error = String;
+
+    fn try_from(fields: DbConfigFields) -> Result<Self, Self::Error> {
+        let required = |value: Option<String>, name: &str| value.ok_or_else(|| format!("db.{} is required for a Postgres database", name));
+        match fields.db_type {
+            DbType::Postgres => Ok(DbConfig::Postgres {
+                host: required(fields.host, "host")?,
+                port: fields.port,
+                database: required(fields.database, "database")?,
+                username: required(fields.username, "username")?,
+                password: required(fields.password, "password")?,
+            }),
+            DbType::Sqlite => Ok(DbConfig::Sqlite {
+                path: fields.path.ok_or("db.path is required for a SQLite database")?,
+            }),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -33,6 +574,46 @@ pub struct LogStorageConfig {
     pub cache_folder: PathBuf,
     #[serde(flatten)]
     pub log_storage_type: LogStorageType,
+    /// External log tooling that also receives every log entry as it comes in
+    #[serde(default)]
+    pub sinks: Vec<LogSinkConfig>,
+}
+
+/// Artifacts are kept on the same kinds of storage as the logs, under `<job_id>/<step_name>/<name>`.
+#[derive(Debug, Deserialize)]
+pub struct ArtifactStorageConfig {
+    #[serde(flatten)]
+    pub storage_type: LogStorageType,
+    /// Uploads larger than this many bytes are refused
+    #[serde(default = "default_max_artifact_size")]
+    pub max_size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, AsRefStr)]
+#[strum(serialize_all = "snake_case")]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LogSinkConfig {
+    /// Grafana Loki push API, e.g. `http://loki:3100`
+    Loki {
+        url: Url,
+        /// Stream labels; `level` is added per entry
+        #[serde(default = "default_loki_labels")]
+        labels: HashMap<String, String>,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        #[serde(default = "default_log_sink_timeout", deserialize_with = "deserialize_duration")]
+        timeout: Duration,
+    },
+    /// Elasticsearch (or OpenSearch) bulk API, e.g. `http://elasticsearch:9200`
+    Elasticsearch {
+        url: Url,
+        #[serde(default = "default_elasticsearch_index")]
+        index: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        #[serde(default = "default_log_sink_timeout", deserialize_with = "deserialize_duration")]
+        timeout: Duration,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, AsRefStr)]
@@ -50,13 +631,22 @@ pub enum LogStorageType {
     },
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct WorkspaceSourceConfig {
     pub folder: PathBuf,
     #[serde(flatten)]
     pub workspace_source_type: WorkspaceSourceType,
 }
 
+/// A project with its own workspace, so its own workflows and triggers.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ProjectConfig {
+    #[serde(flatten)]
+    pub workspace: WorkspaceSourceConfig,
+    /// Who can see and run the project's tasks, on top of the ACL of each task
+    pub acl: Option<TaskAcl>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, AsRefStr)]
 #[strum(serialize_all = "snake_case")]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -139,7 +729,16 @@ pub enum AuthProviderType {
 
 fn default_id() -> String { "".to_string() }
 
+fn default_redis_queue_key() -> String {
+    "stroem:queue".to_string()
+}
+
+fn default_redis_events_channel() -> String {
+    "stroem:job_events".to_string()
+}
+
 fn default_true() -> bool { true }
+fn default_listen_addr() -> SocketAddr { SocketAddr::from(([0, 0, 0, 0], 8080)) }
 fn default_false() -> bool { false }
 
 fn default_db_port() -> u16 { 5432 }
@@ -150,19 +749,76 @@ fn default_scopes() -> String { "openid email profile".to_string() }
 fn default_name_claim() -> String { "name".to_string() }
 fn default_email_claim() -> String { "email".to_string() }
 
+fn default_worker_stale_after() -> Duration { Duration::from_secs(60) }
+fn default_autoscale_target_wait() -> Duration { Duration::from_secs(5 * 60) }
+fn default_autoscale_check_interval() -> Duration { Duration::from_secs(30) }
+fn default_post_processor_timeout() -> Duration { Duration::from_secs(10) }
+
+fn default_log_sink_timeout() -> Duration { Duration::from_secs(10) }
+fn default_loki_labels() -> HashMap<String, String> { HashMap::from([("app".to_string(), "stroem".to_string())]) }
+fn default_elasticsearch_index() -> String { "stroem-logs".to_string() }
+fn default_max_artifact_size() -> u64 { 1024 * 1024 * 1024 }
+
+fn default_lineage_namespace() -> String { "stroem".to_string() }
+
+fn default_lineage_timeout() -> Duration { Duration::from_secs(10) }
+fn default_task_webhook_timeout() -> Duration { Duration::from_secs(10) }
+fn default_task_webhook_max_attempts() -> u32 { 5 }
+
+fn default_notification_subject() -> String { "{{ name }} {{ job.status }}".to_string() }
+fn default_notification_template() -> String { "{{ name }} {{ job.status }} after {{ duration }}: {{ job.url }}".to_string() }
+fn default_sla_breach_subject() -> String { "{{ name }} breached its SLA".to_string() }
+fn default_sla_breach_template() -> String { "{{ name }} took longer than its {{ sla.kind }} of {{ sla.limit }} ({{ sla.elapsed }}, {{ job.status }}): {{ job.url }}".to_string() }
+fn default_notification_timeout() -> Duration { Duration::from_secs(30) }
+fn default_smtp_port() -> u16 { 587 }
+
+fn default_sla_check_interval() -> Duration { Duration::from_secs(30) }
+fn default_wait_check_interval() -> Duration { Duration::from_secs(5) }
+
+fn default_event_retention() -> Duration { Duration::from_secs(7 * 24 * 60 * 60) }
+fn default_alert_check_interval() -> Duration { Duration::from_secs(60) }
+fn default_alert_window() -> Duration { Duration::from_secs(60 * 60) }
+fn default_alert_min_jobs() -> i64 { 5 }
+fn default_alert_rules() -> Vec<AlertRule> {
+    vec![
+        AlertRule { name: "no_workers".to_string(), metric: AlertMetric::WorkerCount, threshold: 1.0, tasks: HashMap::new() },
+        AlertRule {
+            name: "failure_rate".to_string(),
+            metric: AlertMetric::FailureRate { min_jobs: default_alert_min_jobs() },
+            threshold: 0.2,
+            tasks: HashMap::new(),
+        },
+    ]
+}
+
+fn default_rate_limit_global() -> Option<RateLimit> { Some(RateLimit { requests: 1200, per: Duration::from_secs(60) }) }
+
+fn default_rate_limit_auth() -> Option<RateLimit> { Some(RateLimit { requests: 10, per: Duration::from_secs(60) }) }
+
+fn default_rate_limit_run() -> Option<RateLimit> { Some(RateLimit { requests: 60, per: Duration::from_secs(60) }) }
+
+fn default_rate_limit_worker() -> Option<RateLimit> { Some(RateLimit { requests: 1200, per: Duration::from_secs(60) }) }
+
+fn default_retention_interval() -> Duration { Duration::from_secs(60 * 60) }
+
+fn default_retention_batch_size() -> i64 { 1000 }
+
+fn default_slow_request_threshold() -> Duration { Duration::from_secs(1) }
+
+fn default_job_list_window() -> Duration { Duration::from_secs(7 * 24 * 3600) }
+fn default_job_list_limit() -> i64 { 20 }
+
 fn default_jwt_expiration() -> Duration { Duration::from_secs(15*60) }
 fn default_refresh_token_expiration() -> Duration { Duration::from_secs(30 * 24 * 3600) }
 
 
 
-impl ServerConfig {
-    pub fn new(path: PathBuf) -> Result<Self, Error> {
+/// Config keys whose values are masked when printing the effective config; webhook URLs embed their token
+const SECRET_KEYS: [&str; 5] = ["password", "secret", "token", "key", "webhook_url"];
 
-        let mut cfg_builder = Config::builder();
-        cfg_builder = cfg_builder.add_source(File::with_name(path.to_str().unwrap()));
-        cfg_builder = cfg_builder.add_source(Environment::with_prefix("STROEM").separator("__").prefix_separator("__"));
-        let cfg = cfg_builder.build()
-            .with_context(|| format!("Failed to build config from file: {:?}", path))?;
+impl ServerConfig {
+    pub fn new(path: PathBuf, environment: Option<&str>) -> Result<Self, Error> {
+        let cfg = Self::sources(&path, environment)?;
 
         let mut cfg = cfg.try_deserialize::<Self>()
             .map_err(|e| anyhow!("Failed to deserialize config: {}", e))?;
@@ -172,6 +828,68 @@ impl ServerConfig {
             provider.id = id.clone();
         }
 
+        // Project names end up in URLs, query parameters and folder names
+        for name in cfg.projects.keys() {
+            if name == DEFAULT_PROJECT || name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                bail!("Invalid project name '{}', use letters, digits, '-' and '_', other than '{}'", name, DEFAULT_PROJECT);
+            }
+        }
+
+        // Open alerts are matched to their rule by name
+        let mut rule_names = std::collections::HashSet::new();
+        if let Some(rule) = cfg.alerts.rules.iter().find(|rule| !rule_names.insert(rule.name.as_str())) {
+            bail!("Duplicate alert rule name '{}'", rule.name);
+        }
+
         Ok(cfg)
     }
-}
\ No newline at end of file
+
+    /// The merged config as JSON, with secret values masked.
+    pub fn effective(path: PathBuf, environment: Option<&str>) -> Result<String, Error> {
+        let mut value = Self::sources(&path, environment)?
+            .try_deserialize::<serde_json::Value>()
+            .map_err(|e| anyhow!("Failed to read config: {}", e))?;
+        mask_secrets(&mut value);
+        Ok(serde_json::to_string_pretty(&value)?)
+    }
+
+    /// Layers the base file, the overlay for `environment` next to it (`config.yaml` -> `config.prod.yaml`)
+    /// and `STROEM__` environment variables, later sources overriding earlier ones key by key.
+    fn sources(path: &Path, environment: Option<&str>) -> Result<Config, Error> {
+        let mut cfg_builder = Config::builder();
+        cfg_builder = cfg_builder.add_source(File::with_name(path.to_str().unwrap()));
+        if let Some(environment) = environment {
+            let overlay = overlay_path(path, environment)?;
+            cfg_builder = cfg_builder.add_source(File::with_name(overlay.to_str().unwrap()).required(true));
+        }
+        cfg_builder = cfg_builder.add_source(Environment::with_prefix("STROEM").separator("__").prefix_separator("__"));
+        cfg_builder.build()
+            .with_context(|| format!("Failed to build config from file: {:?}", path))
+    }
+}
+
+fn overlay_path(path: &Path, environment: &str) -> Result<PathBuf, Error> {
+    let stem = path.file_stem().ok_or_else(|| anyhow!("Invalid config path: {:?}", path))?.to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, environment, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, environment),
+    };
+    Ok(path.with_file_name(name))
+}
+
+fn mask_secrets(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                let key = key.to_lowercase();
+                if !value.is_object() && !value.is_null() && SECRET_KEYS.iter().any(|secret| key.contains(secret)) {
+                    *value = serde_json::Value::String("********".to_string());
+                } else {
+                    mask_secrets(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(mask_secrets),
+        _ => {}
+    }
+}
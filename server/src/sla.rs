@@ -0,0 +1,120 @@
+// workflow-server/src/sla.rs
+//! Checks the jobs of tasks that declare an `sla` periodically. A job over its task's `max_duration` or
+//! `deadline` is recorded as a breach as soon as it is, while still running or queued too, and notified about.
+use std::time::Duration;
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use tokio::sync::watch;
+use tokio::time;
+use tracing::{error, info, warn};
+use crate::notifications::Notifications;
+use crate::projects::Projects;
+use crate::repository::SlaRepository;
+use crate::server_config::SlaConfig;
+
+/// How far back the first check after a start looks for jobs that finished late
+const FIRST_CHECK_LOOKBACK: Duration = Duration::from_secs(24 * 60 * 60);
+
+pub struct SlaMonitor {
+    projects: Projects,
+    sla_repository: SlaRepository,
+    notifications: Notifications,
+    config: SlaConfig,
+    task: Option<tokio::task::JoinHandle<()>>,
+    cancel_tx: watch::Sender<bool>,
+}
+
+impl SlaMonitor {
+    pub fn new(projects: Projects, sla_repository: SlaRepository, notifications: Notifications, config: SlaConfig) -> Self {
+        let (cancel_tx, _) = watch::channel(false);
+        Self {
+            projects,
+            sla_repository,
+            notifications,
+            config,
+            task: None,
+            cancel_tx,
+        }
+    }
+
+    pub async fn run(&mut self) {
+        if self.task.is_some() {
+            info!("SLA monitor already running");
+            return;
+        }
+
+        let mut cancel_rx = self.cancel_tx.subscribe();
+        let check = SlaCheck {
+            started: Utc::now(),
+            projects: self.projects.clone(),
+            sla_repository: self.sla_repository.clone(),
+            notifications: self.notifications.clone(),
+        };
+        let check_interval = self.config.check_interval;
+
+        let task = tokio::spawn(async move {
+            let mut interval = time::interval(check_interval);
+            let mut since = Utc::now() - FIRST_CHECK_LOOKBACK;
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {},
+                    _ = cancel_rx.changed() => {
+                        if *cancel_rx.borrow() {
+                            info!("SLA monitor stopping due to cancellation signal");
+                            break;
+                        }
+                    }
+                }
+                // Overlaps the previous check, so jobs finishing during one aren't missed; breaches are recorded once
+                let checked = Utc::now();
+                match check.check(since).await {
+                    Ok(()) => since = checked - check_interval,
+                    Err(e) => error!("Failed to check SLAs: {}", e),
+                }
+            }
+        });
+
+        self.task = Some(task);
+        info!("SLA monitor started");
+    }
+
+    pub async fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            if let Err(e) = self.cancel_tx.send(true) {
+                error!("Failed to send cancellation signal: {}", e);
+            }
+            let _ = task.await;
+            info!("SLA monitor stopped");
+        }
+    }
+}
+
+struct SlaCheck {
+    /// Late jobs that finished before this are recorded without notifying, since a previous server run did
+    /// or the SLA wasn't declared yet
+    started: DateTime<Utc>,
+    projects: Projects,
+    sla_repository: SlaRepository,
+    notifications: Notifications,
+}
+
+impl SlaCheck {
+    /// Records the breaches of jobs still running or queued, or finished since `since`.
+    async fn check(&self, since: DateTime<Utc>) -> Result<(), Error> {
+        for project in self.projects.iter() {
+            for (task, sla) in project.workspace.task_slas()? {
+                let limits = [("max_duration", sla.max_duration), ("deadline", sla.deadline)];
+                for (kind, limit) in limits.into_iter().filter_map(|(kind, limit)| limit.map(|limit| (kind, limit))) {
+                    let limit_secs = limit.as_secs_f64();
+                    for breach in self.sla_repository.record_breaches(&project.name, &task, kind, limit_secs, since).await? {
+                        warn!("Job {} of task {} breached its {} of {:?}", breach.job_id, task, kind, limit);
+                        if breach.end_datetime.is_none_or(|end| end >= self.started) {
+                            self.notifications.sla_breach(&breach.job_id.to_string(), kind, limit_secs, breach.elapsed_secs);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+// workflow-server/src/task_webhooks.rs
+//! Calls the webhooks a task declares when its jobs start, succeed or fail. Every attempt is
+//! recorded, so deliveries can be inspected per job.
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::Error;
+use hmac::{Hmac, Mac};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, Url};
+use serde_json::json;
+use sha2::Sha256;
+use stroem_common::workflows_configuration::TaskWebhooks;
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+use crate::post_process::JobSummary;
+use crate::projects::Projects;
+use crate::repository::{JobRepository, WebhookDelivery, WebhookDeliveryRepository};
+use crate::server_config::TaskWebhooksConfig;
+
+/// `sha256=<hex>` HMAC of the body, keyed with the configured secret
+pub const SIGNATURE_HEADER: &str = "X-Stroem-Signature";
+pub const EVENT_HEADER: &str = "X-Stroem-Event";
+/// Stays the same across retries, so receivers can drop duplicates
+pub const DELIVERY_HEADER: &str = "X-Stroem-Delivery";
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug)]
+enum Event {
+    Start,
+    Success,
+    Failure,
+}
+
+impl Event {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Event::Start => "on_start",
+            Event::Success => "on_success",
+            Event::Failure => "on_failure",
+        }
+    }
+
+    fn url<'a>(&self, webhooks: &'a TaskWebhooks) -> Option<&'a String> {
+        match self {
+            Event::Start => webhooks.on_start.as_ref(),
+            Event::Success => webhooks.on_success.as_ref(),
+            Event::Failure => webhooks.on_failure.as_ref(),
+        }
+    }
+}
+
+/// Sends the webhooks of task jobs; cheap to clone.
+#[derive(Clone)]
+pub struct TaskWebhookSender {
+    client: Client,
+    config: Arc<TaskWebhooksConfig>,
+    projects: Projects,
+    job_repository: JobRepository,
+    delivery_repository: WebhookDeliveryRepository,
+    public_url: Url,
+}
+
+impl TaskWebhookSender {
+    pub fn new(config: &TaskWebhooksConfig, projects: Projects, job_repository: JobRepository, delivery_repository: WebhookDeliveryRepository, public_url: Url) -> Self {
+        TaskWebhookSender {
+            client: Client::new(),
+            config: Arc::new(config.clone()),
+            projects,
+            job_repository,
+            delivery_repository,
+            public_url,
+        }
+    }
+
+    pub async fn get_deliveries(&self, job_id: &Uuid) -> Result<Vec<WebhookDelivery>, Error> {
+        self.delivery_repository.get_deliveries(job_id).await
+    }
+
+    pub fn job_started(&self, job_id: &str) {
+        self.send(job_id, Event::Start);
+    }
+
+    pub fn job_done(&self, job_id: &str, success: bool) {
+        self.send(job_id, if success { Event::Success } else { Event::Failure });
+    }
+
+    /// Delivers in the background, so the worker reporting the job isn't held up.
+    fn send(&self, job_id: &str, event: Event) {
+        let this = self.clone();
+        let job_id = job_id.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = this.deliver(&job_id, event).await {
+                error!("Failed to deliver {} webhook for job {}: {}", event.as_str(), job_id, e);
+            }
+        });
+    }
+
+    async fn deliver(&self, job_id: &str, event: Event) -> Result<(), Error> {
+        let job = self.job_repository.get_job(job_id).await?;
+        let (Some(task), Some(project)) = (job.task.as_deref(), self.projects.get(&job.project_id)) else { return Ok(()) };
+        let Some(url) = project.workspace.task_webhooks(task)?.and_then(|webhooks| event.url(&webhooks).cloned()) else {
+            return Ok(());
+        };
+        let delivery_id = self.delivery_repository.create(&job.job_id, event.as_str(), &url).await?;
+        let body = serde_json::to_vec(&json!({
+            "event": event.as_str(),
+            "delivery_id": delivery_id,
+            "job": JobSummary::new(job, &self.public_url),
+        }))?;
+        let signature = self.config.secret.as_deref().map(|secret| sign(secret, &body)).transpose()?;
+
+        let max_attempts = self.config.max_attempts.max(1);
+        let mut backoff = INITIAL_BACKOFF;
+        for attempt in 1..=max_attempts {
+            let mut request = self.client.post(&url)
+                .timeout(self.config.timeout)
+                .header(CONTENT_TYPE, "application/json")
+                .header(EVENT_HEADER, event.as_str())
+                .header(DELIVERY_HEADER, delivery_id.to_string());
+            if let Some(signature) = &signature {
+                request = request.header(SIGNATURE_HEADER, signature);
+            }
+            let (response_status, error) = match request.body(body.clone()).send().await {
+                Ok(response) if response.status().is_success() => (Some(response.status().as_u16() as i32), None),
+                Ok(response) => (Some(response.status().as_u16() as i32), Some(format!("Responded {}", response.status()))),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            let status = match (&error, attempt < max_attempts) {
+                (None, _) => "delivered",
+                (Some(_), true) => "pending",
+                (Some(_), false) => "failed",
+            };
+            self.delivery_repository.record_attempt(delivery_id, status, attempt as i32, response_status, error.as_deref()).await?;
+            match error {
+                None => {
+                    debug!("Delivered {} webhook for job {} to {}", event.as_str(), job_id, url);
+                    return Ok(());
+                }
+                Some(error) if attempt < max_attempts => {
+                    warn!("{} webhook for job {} to {} failed: {}, retrying in {:?} (attempt {}/{})", event.as_str(), job_id, url, error, backoff, attempt, max_attempts);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+                Some(error) => error!("{} webhook for job {} to {} failed after {} attempts: {}", event.as_str(), job_id, url, attempt, error),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn sign(secret: &str, body: &[u8]) -> Result<String, Error> {
+    let mut mac: Hmac<Sha256> = Hmac::new_from_slice(secret.as_bytes())?;
+    mac.update(body);
+    Ok(format!("sha256={:x}", mac.finalize().into_bytes()))
+}
@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use anyhow::{anyhow, Error};
+use serde::Serialize;
+use serde_json::Value;
+use crate::repository::{UsageGroup, UsageRow};
+use crate::server_config::AccountingConfig;
+
+impl UsageGroup {
+    /// Parses `task`, `action`, `worker`, `source` or `label:<name>`.
+    pub fn parse(group_by: &str) -> Result<UsageGroup, Error> {
+        match group_by {
+            "task" => Ok(UsageGroup::Task),
+            "action" => Ok(UsageGroup::Action),
+            "worker" => Ok(UsageGroup::Worker),
+            "source" => Ok(UsageGroup::Source),
+            label => match label.strip_prefix("label:") {
+                Some(name) if !name.is_empty() => Ok(UsageGroup::Label(name.to_string())),
+                _ => Err(anyhow!("Invalid group_by '{}', expected task, action, worker, source or label:<name>", group_by)),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageEntry {
+    /// `None` for jobs without a value for the group, e.g. workers without the label
+    pub key: Option<String>,
+    pub jobs: i64,
+    pub compute_seconds: f64,
+    pub cost: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageReport {
+    pub group_by: String,
+    pub range_secs: u64,
+    pub currency: Option<String>,
+    pub total_jobs: i64,
+    pub total_compute_seconds: f64,
+    pub total_cost: f64,
+    pub entries: Vec<UsageEntry>,
+}
+
+impl AccountingConfig {
+    /// Hourly rate for jobs run on a worker with these labels.
+    pub fn rate_for(&self, labels: Option<&Value>) -> f64 {
+        let label = |name: &str| labels.and_then(|labels| labels.get(name)).and_then(Value::as_str);
+        self.rates.iter()
+            .find(|rate| rate.labels.iter().all(|(name, value)| label(name) == Some(value.as_str())))
+            .map(|rate| rate.rate)
+            .unwrap_or(self.default_rate)
+    }
+}
+
+/// Prices the per-worker rows and folds them into one entry per group, most expensive first.
+pub fn build_report(rows: Vec<UsageRow>, config: &AccountingConfig, group_by: &str, range_secs: u64) -> UsageReport {
+    let mut groups: HashMap<Option<String>, UsageEntry> = HashMap::new();
+    for row in rows {
+        let cost = row.compute_seconds / 3600.0 * config.rate_for(row.worker_labels.as_ref());
+        let entry = groups.entry(row.key.clone()).or_insert_with(|| UsageEntry {
+            key: row.key,
+            jobs: 0,
+            compute_seconds: 0.0,
+            cost: 0.0,
+        });
+        entry.jobs += row.jobs;
+        entry.compute_seconds += row.compute_seconds;
+        entry.cost += cost;
+    }
+
+    let mut entries: Vec<UsageEntry> = groups.into_values().collect();
+    entries.sort_by(|a, b| b.cost.total_cmp(&a.cost).then(b.compute_seconds.total_cmp(&a.compute_seconds)));
+    UsageReport {
+        group_by: group_by.to_string(),
+        range_secs,
+        currency: config.currency.clone(),
+        total_jobs: entries.iter().map(|e| e.jobs).sum(),
+        total_compute_seconds: entries.iter().map(|e| e.compute_seconds).sum(),
+        total_cost: entries.iter().map(|e| e.cost).sum(),
+        entries,
+    }
+}
@@ -0,0 +1,101 @@
+// workflow-server/src/waits.rs
+//! Checks the waits of suspended jobs periodically. A `wait` whose duration passed or a `wait_for_event` that
+//! timed out is resolved, and its job queued again, as are jobs whose approval timed out; the runner of the job
+//! then continues with the step it was suspended on. Jobs whose events were posted or approvals decided are queued
+//! by the API right away, the check only catches the ones it missed.
+use anyhow::Error;
+use tokio::sync::watch;
+use tokio::time;
+use tracing::{debug, error, info};
+use crate::repository::{JobRepository, WaitRepository};
+use crate::server_config::WaitsConfig;
+
+pub struct WaitMonitor {
+    job_repository: JobRepository,
+    wait_repository: WaitRepository,
+    config: WaitsConfig,
+    task: Option<tokio::task::JoinHandle<()>>,
+    cancel_tx: watch::Sender<bool>,
+}
+
+impl WaitMonitor {
+    pub fn new(job_repository: JobRepository, wait_repository: WaitRepository, config: WaitsConfig) -> Self {
+        let (cancel_tx, _) = watch::channel(false);
+        Self {
+            job_repository,
+            wait_repository,
+            config,
+            task: None,
+            cancel_tx,
+        }
+    }
+
+    pub async fn run(&mut self) {
+        if self.task.is_some() {
+            info!("Wait monitor already running");
+            return;
+        }
+
+        let mut cancel_rx = self.cancel_tx.subscribe();
+        let check = WaitCheck {
+            job_repository: self.job_repository.clone(),
+            wait_repository: self.wait_repository.clone(),
+        };
+        let check_interval = self.config.check_interval;
+
+        let task = tokio::spawn(async move {
+            let mut interval = time::interval(check_interval);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {},
+                    _ = cancel_rx.changed() => {
+                        if *cancel_rx.borrow() {
+                            info!("Wait monitor stopping due to cancellation signal");
+                            break;
+                        }
+                    }
+                }
+                if let Err(e) = check.check().await {
+                    error!("Failed to check waits: {}", e);
+                }
+            }
+        });
+
+        self.task = Some(task);
+        info!("Wait monitor started");
+    }
+
+    pub async fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            if let Err(e) = self.cancel_tx.send(true) {
+                error!("Failed to send cancellation signal: {}", e);
+            }
+            let _ = task.await;
+            info!("Wait monitor stopped");
+        }
+    }
+}
+
+struct WaitCheck {
+    job_repository: JobRepository,
+    wait_repository: WaitRepository,
+}
+
+impl WaitCheck {
+    /// Resolves the waits that are over and queues the suspended jobs that no longer wait for anything, including
+    /// the ones an earlier check or event resolved without queuing them, e.g. because the server stopped.
+    async fn check(&self) -> Result<(), Error> {
+        let resolved = self.wait_repository.resolve_due().await?;
+        if !resolved.is_empty() {
+            debug!("Waits of {} jobs are over", resolved.len());
+        }
+        for job_id in self.job_repository.get_ready_suspended().await? {
+            self.job_repository.requeue_suspended(&job_id).await?;
+        }
+        let pruned = self.wait_repository.prune_events().await?;
+        if pruned > 0 {
+            debug!("Pruned {} events", pruned);
+        }
+        Ok(())
+    }
+}
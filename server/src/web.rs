@@ -1,35 +1,61 @@
 
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 use axum::body::Body;
-use axum::extract::State;
-use axum::http::{StatusCode, Uri};
+use axum::extract::{FromRequestParts, RawPathParams, State};
+use axum::http::request::Parts;
+use axum::http::{header, StatusCode, Uri};
 use axum::response::{IntoResponse, Response};
 
 use axum::Router;
 use axum::routing::get;
+use axum_server::tls_rustls::RustlsConfig;
 
 use mime_guess::from_path;
 use reqwest::Url;
 use rust_embed::RustEmbed;
 
 use tokio::net::TcpListener;
-use tokio::sync::broadcast::Sender;
+use tokio::sync::watch;
+use tokio_util::io::ReaderStream;
 use tracing::{debug, info};
-use crate::repository::{JobRepository, LogRepository};
-use crate::workspace_server::WorkspaceServer;
+use crate::repository::{AlertRepository, ApprovalRepository, ArtifactRepository, AuditRepository, Job, JobFilter, JobGroupRepository, JobRepository, LogRepository, SlaRepository, TaskPause, TaskRepository, WaitRepository, WorkerRepository};
+use crate::projects::{Project, Projects};
+use crate::server_config::{AccessLogConfig, AccountingConfig, AutoscaleConfig, JobListConfig, MetricsConfig, RateLimitConfig};
+use anyhow::{anyhow, bail, Error};
+use chrono::Utc;
+use stroem_common::{JobRequest, JobSpec};
+use uuid::Uuid;
+use stroem_common::log_level::LogLevelHandle;
+use crate::auth::User;
+use crate::post_process::PostProcessors;
+use crate::lineage::Lineage;
+use crate::task_webhooks::TaskWebhookSender;
+use crate::notifications::Notifications;
+use crate::dispatcher::Dispatcher;
+use crate::job_events::JobEvents;
+use crate::log_sink::LogSinks;
+use crate::scheduler::UpcomingRun;
+use crate::retention::RetentionStats;
 
 mod api;
 use api::get_routes as api_get_routes;
-use api::JobEvent;
 
 mod worker;
 mod auth;
+mod access_log;
+mod rate_limit;
+mod metrics;
 mod api_response;
+mod webhook;
+mod openapi;
 
 use worker::get_routes as worker_get_routes;
 use auth::get_routes as auth_get_routes;
+use webhook::get_routes as webhook_get_routes;
 use crate::auth::AuthService;
+use api_response::ApiError;
 
 #[derive(RustEmbed)]
 #[folder = "static/"]
@@ -38,54 +64,307 @@ struct StaticAssets;
 
 #[derive(Clone)]
 pub struct WebState {
-    pub workspace: Arc<WorkspaceServer>,
+    pub projects: Projects,
     pub job_repository: JobRepository,
     pub log_repository: Arc<dyn LogRepository + Send + Sync>,
-    pub job_channels: Arc<Mutex<HashMap<String, Sender<JobEvent>>>>,
+    pub job_events: JobEvents,
     pub auth_service: AuthService,
     pub public_url: Url,
-    pub worker_token: String,
+    pub worker_token: Option<String>,
+    pub enforce_action_sunset: bool,
+    pub job_list: JobListConfig,
+    pub worker_repository: WorkerRepository,
+    pub task_repository: TaskRepository,
+    pub worker_stale_after: Duration,
+    pub log_level: LogLevelHandle,
+    pub autoscale: AutoscaleConfig,
+    pub accounting: AccountingConfig,
+    pub post_processors: PostProcessors,
+    pub log_sinks: LogSinks,
+    pub lineage: Lineage,
+    /// One receiver per project
+    pub upcoming_runs: Vec<watch::Receiver<Vec<UpcomingRun>>>,
+    pub retention_stats: watch::Receiver<RetentionStats>,
+    pub recent_requests: worker::RecentRequests,
+    pub audit_repository: AuditRepository,
+    pub group_repository: JobGroupRepository,
+    pub artifact_repository: ArtifactRepository,
+    pub task_webhooks: TaskWebhookSender,
+    pub notifications: Notifications,
+    pub alert_repository: AlertRepository,
+    pub sla_repository: SlaRepository,
+    pub approval_repository: ApprovalRepository,
+    pub wait_repository: WaitRepository,
+    pub dispatcher: Dispatcher,
 }
 
 
 impl WebState {
     pub fn new(
-        workspace: Arc<WorkspaceServer>,
+        projects: Projects,
         job_repository: JobRepository,
         log_repository: Arc<dyn LogRepository + Send + Sync>,
         auth: AuthService,
         public_url: Url,
-        worker_token: String,
+        worker_token: Option<String>,
+        enforce_action_sunset: bool,
+        job_list: JobListConfig,
+        worker_repository: WorkerRepository,
+        task_repository: TaskRepository,
+        worker_stale_after: Duration,
+        log_level: LogLevelHandle,
+        autoscale: AutoscaleConfig,
+        accounting: AccountingConfig,
+        post_processors: PostProcessors,
+        log_sinks: LogSinks,
+        lineage: Lineage,
+        upcoming_runs: Vec<watch::Receiver<Vec<UpcomingRun>>>,
+        retention_stats: watch::Receiver<RetentionStats>,
+        audit_repository: AuditRepository,
+        group_repository: JobGroupRepository,
+        artifact_repository: ArtifactRepository,
+        task_webhooks: TaskWebhookSender,
+        job_events: JobEvents,
+        notifications: Notifications,
+        alert_repository: AlertRepository,
+        sla_repository: SlaRepository,
+        approval_repository: ApprovalRepository,
+        wait_repository: WaitRepository,
     ) -> Self {
         Self {
-            workspace,
+            projects,
+            dispatcher: Dispatcher::new(job_repository.enqueued()),
             job_repository,
             log_repository,
-            job_channels: Arc::new(Mutex::new(HashMap::new())),
+            job_events,
             auth_service: auth,
             public_url,
-            worker_token
+            worker_token,
+            enforce_action_sunset,
+            job_list,
+            worker_repository,
+            task_repository,
+            worker_stale_after,
+            log_level,
+            autoscale,
+            accounting,
+            post_processors,
+            log_sinks,
+            lineage,
+            upcoming_runs,
+            retention_stats,
+            recent_requests: Default::default(),
+            audit_repository,
+            group_repository,
+            artifact_repository,
+            task_webhooks,
+            notifications,
+            alert_repository,
+            sla_repository,
+            approval_repository,
+            wait_repository,
         }
     }
+
+    /// Refuses new runs that would use actions past their sunset date, if enforcement is enabled.
+    pub fn check_action_sunset(&self, job: &JobRequest) -> Result<(), Error> {
+        if !self.enforce_action_sunset {
+            return Ok(());
+        }
+        let project = self.projects.of_job(job)?;
+        let workflows_guard = project.workspace.workflows.read().map_err(|_| anyhow!("Could not read workspace"))?;
+        let Some(workflows) = workflows_guard.as_ref() else { return Ok(()) };
+        let sunset = workflows.sunset_actions(job.task(), job.action(), Utc::now().date_naive());
+        if !sunset.is_empty() {
+            bail!("Refusing to run past sunset date: {}", sunset.join("; "));
+        }
+        Ok(())
+    }
+
+    /// Validates the job input against the task or action input fields, converting values to the field types.
+    pub fn validate_input(&self, job: &mut JobRequest) -> Result<(), Error> {
+        let project = self.projects.of_job(job)?;
+        let workflows_guard = project.workspace.workflows.read().map_err(|_| anyhow!("Could not read workspace"))?;
+        let Some(workflows) = workflows_guard.as_ref() else { return Ok(()) };
+        workflows.validate_run_input(job.spec.task(), job.spec.action(), &mut job.input)
+    }
+
+    /// The pause of the job's task, if it is paused.
+    pub async fn task_pause(&self, job: &JobRequest) -> Result<Option<TaskPause>, Error> {
+        match &job.spec {
+            JobSpec::Task { name } => self.task_repository.get_pause(&self.projects.of_job(job)?.scoped_name(name)).await,
+            JobSpec::Action { .. } => Ok(None),
+        }
+    }
+
+    /// Masks the values of `secret` input fields, which are only redacted by the worker once the job starts.
+    pub fn mask_secret_inputs(&self, job: &mut Job) {
+        self.projects.mask_secret_inputs(job);
+    }
+
+    /// Whether the user may see jobs and logs of the task in the project; jobs without a (known) task are visible
+    /// to everyone who may see the project, jobs of removed projects to admins only.
+    pub fn can_view_task(&self, user: &User, project: &str, task: Option<&str>) -> bool {
+        let Some(project) = self.projects.get(project) else { return user.is_admin() };
+        if !user.can_view_project(project.acl.as_ref()) {
+            return false;
+        }
+        let Ok(workflows_guard) = project.workspace.workflows.read() else { return user.is_admin() };
+        let task = workflows_guard.as_ref().zip(task).and_then(|(workflows, task)| workflows.get_task(task));
+        user.can_view_task(task)
+    }
+
+    /// A job filter leaving out the jobs the user may not see, by the same rules as `can_view_task`.
+    pub fn visible_jobs(&self, user: &User) -> JobFilter {
+        if user.is_admin() {
+            return JobFilter::default();
+        }
+        let mut projects = Vec::new();
+        let mut hidden_tasks = Vec::new();
+        for project in self.projects.iter().filter(|project| user.can_view_project(project.acl.as_ref())) {
+            let Ok(workflows_guard) = project.workspace.workflows.read() else { continue };
+            projects.push(project.name.clone());
+            for (id, task) in workflows_guard.as_ref().and_then(|workflows| workflows.tasks.as_ref()).into_iter().flatten() {
+                if !user.can_view_task(Some(task)) {
+                    hidden_tasks.push(format!("{}/{}", project.name, id));
+                }
+            }
+        }
+        JobFilter { projects: Some(projects), hidden_tasks, ..Default::default() }
+    }
+
+    pub fn can_run(&self, user: &User, job: &JobRequest) -> bool {
+        let Ok(project) = self.projects.of_job(job) else { return false };
+        if !user.can_run_project(project.acl.as_ref()) {
+            return false;
+        }
+        match &job.spec {
+            JobSpec::Task { name } => {
+                let Ok(workflows_guard) = project.workspace.workflows.read() else { return user.is_admin() };
+                user.can_run_task(workflows_guard.as_ref().and_then(|workflows| workflows.get_task(name)))
+            }
+            JobSpec::Action { .. } => user.can_run_action(),
+        }
+    }
+
+    /// Who may decide on an approval of a job: its `approvers`, or without them anyone who may run the job again.
+    pub fn can_approve(&self, user: &User, job: &Job, approvers: Option<&[String]>) -> bool {
+        if let Some(approvers) = approvers {
+            return self.can_view_task(user, &job.project_id, job.task.as_deref()) && user.can_approve(approvers);
+        }
+        self.can_rerun(user, job)
+    }
+
+    /// Whether the user may run the task or action of a job, e.g. to run it again.
+    pub fn can_rerun(&self, user: &User, job: &Job) -> bool {
+        let Some(project) = self.projects.get(&job.project_id) else { return user.is_admin() };
+        if !user.can_run_project(project.acl.as_ref()) {
+            return false;
+        }
+        match &job.task {
+            Some(task) => {
+                let Ok(workflows_guard) = project.workspace.workflows.read() else { return user.is_admin() };
+                user.can_run_task(workflows_guard.as_ref().and_then(|workflows| workflows.get_task(task)))
+            }
+            None => user.can_run_action(),
+        }
+    }
+
+    /// The file of an artifact as a download, or 404 if the step uploaded none by that name.
+    pub async fn artifact_response(&self, job_id: &Uuid, step_name: &str, name: &str) -> Result<Response, Error> {
+        let Some(artifact) = self.artifact_repository.get_artifact(job_id, step_name, name).await? else {
+            return Ok((StatusCode::NOT_FOUND, "Artifact not found").into_response());
+        };
+        let reader = self.artifact_repository.open_artifact(&artifact).await?;
+        let file_name = name.rsplit('/').next().unwrap_or(name).replace('"', "");
+        let headers = [
+            (header::CONTENT_TYPE, from_path(name).first_or_octet_stream().to_string()),
+            (header::CONTENT_LENGTH, artifact.size.to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", file_name)),
+        ];
+        Ok((headers, Body::from_stream(ReaderStream::new(reader))).into_response())
+    }
 }
 
+/// The project named by the `{project}` segment of the route, the default project on routes without one.
+pub struct SelectedProject(pub Arc<Project>);
 
-pub async fn run(state: WebState, addr: &str) {
-    let app = Router::new()
-        .route("/healthz", get(health_check))
-        .route("/readyz", get(ready_check))
+impl FromRequestParts<WebState> for SelectedProject {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &WebState) -> Result<Self, Self::Rejection> {
+        let params = RawPathParams::from_request_parts(parts, state).await
+            .map_err(|e| ApiError::bad_request(&e.body_text()))?;
+        let project = match params.iter().find(|(key, _)| *key == "project") {
+            Some((_, name)) => state.projects.get(name).ok_or_else(|| ApiError::not_found(&format!("Project '{}' not found", name)))?,
+            None => state.projects.default_project(),
+        };
+        Ok(SelectedProject(project.clone()))
+    }
+}
+
+
+/// Operations of all routes, as documented in the OpenAPI document.
+fn get_operations() -> Vec<openapi::Operation> {
+    [auth::get_operations(), api::get_operations(), worker::get_operations(), webhook::get_operations(), openapi::get_operations()]
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+/// Serves until `shutdown` resolves, then stops accepting connections and waits for the open ones.
+/// Serves HTTPS when `tls` is given.
+pub async fn run(state: WebState, addr: SocketAddr, tls: Option<RustlsConfig>, access_log: &AccessLogConfig, rate_limit: &RateLimitConfig, metrics: &MetricsConfig, shutdown: impl Future<Output = ()> + Send + 'static) {
+    // Health checks, metrics and static files are left out of the access log
+    let mut routes = Router::new()
         .merge(auth_get_routes())
         .merge(api_get_routes())
         .merge(worker_get_routes())
+        .merge(webhook_get_routes())
+        .merge(openapi::get_routes());
+    if metrics.enabled {
+        routes = metrics::layer(routes);
+    }
+    let mut app = Router::new()
+        .route("/healthz", get(health_check))
+        .route("/readyz", get(ready_check))
+        .merge(access_log::layer(rate_limit::layer(routes, rate_limit, &state), access_log));
+    if metrics.enabled {
+        app = app.merge(metrics::get_routes());
+    }
+    let app = app
         .route("/{*path}", get(serve_static))
         .route("/", get(serve_static))
         .with_state(state);
 
-    let listener = TcpListener::bind(addr).await.unwrap();
-    info!("Server starting on {}", addr);
-    axum::serve(listener, app.into_make_service())
-        .await
-        .unwrap();
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    match tls {
+        Some(tls) => {
+            let handle = axum_server::Handle::new();
+            tokio::spawn({
+                let handle = handle.clone();
+                async move {
+                    shutdown.await;
+                    handle.graceful_shutdown(None);
+                }
+            });
+            info!("Server starting on https://{}", addr);
+            axum_server::bind_rustls(addr, tls)
+                .handle(handle)
+                .serve(app)
+                .await
+                .unwrap();
+        }
+        None => {
+            let listener = TcpListener::bind(addr).await.unwrap();
+            info!("Server starting on http://{}", addr);
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown)
+                .await
+                .unwrap();
+        }
+    }
+    info!("Server stopped accepting requests");
 }
 
 
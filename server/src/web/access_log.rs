@@ -0,0 +1,73 @@
+//! Access logs with a request id, and a warning for slow requests to spot API hot spots.
+//! The auth extractors record the `user` or `worker` of the request on its span.
+use std::time::Duration;
+use axum::http::{Request, Response};
+use axum::Router;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::{MakeSpan, OnResponse, TraceLayer};
+use tracing::field::Empty;
+use tracing::{info, info_span, warn, Span};
+use stroem_common::telemetry::{self, TRACEPARENT_HEADER};
+use crate::server_config::AccessLogConfig;
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Wraps the routes with access logging, reusing the caller's `X-Request-Id` or generating one.
+pub fn layer<S: Clone + Send + Sync + 'static>(router: Router<S>, config: &AccessLogConfig) -> Router<S> {
+    if !config.enabled {
+        return router;
+    }
+    let trace = TraceLayer::new_for_http()
+        .make_span_with(RequestSpan)
+        .on_request(())
+        .on_response(AccessLog { slow_request_threshold: config.slow_request_threshold })
+        .on_failure(());
+    router
+        .layer(trace)
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+}
+
+/// Records who made the request, for the access log.
+pub fn record_user(email: &str) {
+    Span::current().record("user", email);
+}
+
+pub fn record_worker(worker_id: &str) {
+    Span::current().record("worker", worker_id);
+}
+
+#[derive(Clone)]
+struct RequestSpan;
+
+impl<B> MakeSpan<B> for RequestSpan {
+    fn make_span(&mut self, request: &Request<B>) -> Span {
+        let request_id = request.headers().get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        // The query is left out, it may carry tokens
+        let span = info_span!("request", request_id, method = %request.method(), path = request.uri().path(), user = Empty, worker = Empty);
+        // Workers send the trace context of their job, so its start, logs and result show up in the job's trace
+        if let Some(traceparent) = request.headers().get(TRACEPARENT_HEADER).and_then(|value| value.to_str().ok()) {
+            telemetry::set_parent(&span, traceparent);
+        }
+        span
+    }
+}
+
+#[derive(Clone)]
+struct AccessLog {
+    slow_request_threshold: Duration,
+}
+
+impl<B> OnResponse<B> for AccessLog {
+    fn on_response(self, response: &Response<B>, latency: Duration, _span: &Span) {
+        let status = response.status().as_u16();
+        let latency_ms = latency.as_millis() as u64;
+        if latency >= self.slow_request_threshold {
+            warn!(status, latency_ms, "Slow request");
+        } else {
+            info!(status, latency_ms, "Request");
+        }
+    }
+}
@@ -1,58 +1,235 @@
 use std::collections::HashMap;
+use std::cmp::Ordering;
 use axum::{
     extract::{
         Path, Query, State
     },
-    response::sse::{Event, Sse},
-    routing::{get, post},
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    http::HeaderMap,
+    response::{sse::{Event, Sse}, Response},
+    routing::{delete, get, post},
     Json, Router
 };
-use tracing::{error, debug};
-use stroem_common::{JobRequest, log_collector::LogEntry};
-use serde_json::{Value};
+use tracing::{error, debug, info};
+use stroem_common::{JobRequest, JobSpec, ResumeState, DEFAULT_PROJECT, log_collector::{LogEntry, LogLevel}};
+use stroem_common::runner::STATUS_SKIPPED;
+use stroem_common::workflows_configuration::Action;
+use serde_json::{json, Value};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use chrono::{DateTime, Utc};
 use anyhow::{anyhow, Error};
-use crate::error::{AppError};
 use crate::web::api_response::{ApiResponse, ApiError};
-use std::sync::{Arc, Mutex};
-use tokio::sync::broadcast::{self, Sender};
+use crate::web::auth::{ReadAccess, RunAccess};
+use std::time::Duration;
+use uuid::Uuid;
+use tokio::sync::broadcast;
 use futures_util::stream::Stream;
 use std::convert::Infallible;
 use tokio_stream::wrappers::BroadcastStream;
 use tokio_stream::StreamExt;
 use std::{pin::Pin, task::{Context, Poll}};
 use crate::auth::User;
-use crate::web::WebState;
+use crate::job_diff::JobDiff;
+use crate::job_events::{JobEvent, JobEvents};
+use crate::job_state::JobState;
+use crate::search::SearchLimits;
+use crate::repository::{Alert, Job, JobApproval, JobArtifact, JobWait, JobFilter, JobGroup, JobGroupStatus, SlaBreach, SlaBreachTrend, TaskPause, UsageGroup, WorkerThroughput};
+use crate::web::{SelectedProject, WebState};
+use crate::web::openapi::{op, Auth, Operation};
+use crate::scheduler::UpcomingRun;
+use crate::workspace_server::WorkspaceServer;
+use crate::workspace_source::CommitAuthor;
 
 pub fn get_routes() -> Router<WebState> {
     Router::new()
+        .route("/api/projects", get(get_projects))
+        .route("/api/projects/{project}/tasks", get(get_tasks))
+        .route("/api/projects/{project}/tasks/{task_id}", get(get_task).patch(patch_task))
+        .route("/api/projects/{project}/actions", get(get_actions))
+        .route("/api/projects/{project}/actions/{action_id}", get(get_action))
+        .route("/api/projects/{project}/run", post(put_project_job))
+        .route("/api/projects/{project}/workspace/validate", post(post_workspace_validate))
         .route("/api/tasks", get(get_tasks))
-        .route("/api/tasks/{:task_id}", get(get_task))
+        .route("/api/tasks/{task_id}", get(get_task).patch(patch_task))
+        .route("/api/actions", get(get_actions))
+        .route("/api/actions/{action_id}", get(get_action))
         .route("/api/jobs", get(get_jobs))
-        .route("/api/jobs/{:job_id}", get(get_job))
+        .route("/api/jobs/{:job_id}", get(get_job).delete(delete_job))
+        .route("/api/jobs/{:job_id}/as-run-request", get(get_job_as_run_request))
+        .route("/api/jobs/{:job_id}/rerun", post(post_job_rerun))
+        .route("/api/jobs/{:job_id}/resume", post(post_job_resume))
+        .route("/api/jobs/{:job_id}/diff/{:other_job_id}", get(get_job_diff))
+        .route("/api/jobs/{:job_id}/state", get(get_job_state))
+        .route("/api/jobs/{:job_id}/deliveries", get(get_job_deliveries))
+        .route("/api/jobs/{:job_id}/approvals", get(get_job_approvals))
+        .route("/api/jobs/{:job_id}/approvals/{:step_name}/approve", post(post_approval_approve))
+        .route("/api/jobs/{:job_id}/approvals/{:step_name}/reject", post(post_approval_reject))
+        .route("/api/jobs/{:job_id}/waits", get(get_job_waits))
+        .route("/api/events/{:key}", post(post_event))
+        .route("/api/jobs/{:job_id}/artifacts", get(get_job_artifacts))
+        .route("/api/jobs/{:job_id}/artifacts/{:step_name}/{*name}", get(get_job_artifact))
         .route("/api/jobs/{:job_id}/logs", get(get_job_logs))
         .route("/api/jobs/{:job_id}/steps/{:step_name}/logs", get(get_job_step_logs))
         .route("/api/jobs/{:job_id}/sse", get(get_job_sse))
+        .route("/api/jobs/{:job_id}/ws", get(get_job_ws))
+        .route("/api/workers", get(get_workers))
+        .route("/api/schedule", get(get_schedule))
+        .route("/api/search", get(get_search))
+        .route("/api/retention", get(get_retention))
+        .route("/api/autoscale/recommendation", get(get_autoscale_recommendation))
+        .route("/api/reports/usage", get(get_usage_report))
+        .route("/api/dashboard/queue", get(get_dashboard_queue))
+        .route("/api/dashboard/queue-wait", get(get_dashboard_queue_wait))
+        .route("/api/dashboard/throughput", get(get_dashboard_throughput))
+        .route("/api/dashboard/sla", get(get_dashboard_sla))
+        .route("/api/sla/breaches", get(get_sla_breaches))
+        .route("/api/alerts", get(get_alerts))
+        .route("/api/alerts/{:alert_id}/acknowledge", post(post_alert_acknowledge))
+        .route("/api/alerts/{:alert_id}/resolve", post(post_alert_resolve))
         .route("/api/run", post(put_job))
+        .route("/api/groups", post(post_group))
+        .route("/api/groups/{:group_id}", get(get_group))
+        .route("/api/admin/log-level", get(get_log_level).put(put_log_level))
+        .route("/api/admin/worker-credentials", get(get_worker_credentials).post(post_worker_credential))
+        .route("/api/admin/worker-credentials/{:credential_id}", delete(delete_worker_credential))
+        .route("/api/admin/worker-credentials/{:credential_id}/rotate", post(rotate_worker_credential))
+        .route("/api/workspace/validate", post(post_workspace_validate))
+        .route("/api/admin/workspace/files", get(get_workspace_files))
+        .route("/api/admin/workspace/files/{*path}", get(get_workspace_file).put(put_workspace_file))
+        .route("/api/admin/jobs/{:job_id}", delete(purge_job))
+        .route("/api/admin/audit", get(get_audit_log))
 }
 
-
-#[derive(Clone)]
-pub struct JobEvent {
-    pub event_name: String,
-    pub data: Value,
+/// The routes above, as documented in the OpenAPI document.
+pub fn get_operations() -> Vec<Operation> {
+    const TASKS: &str = "Tasks, each with a `paused` flag and the `pause` that set it";
+    const TOKEN: &str = "The new token, only shown in this response, as `token` and the credential as `info`";
+    let mut operations = vec![
+        op("get", "/api/projects", "Projects", "The projects the user may see, with the revision their workspace is at")
+            .auth(Auth::Read).data_described("Projects as `name` and `revision`"),
+    ];
+    // Task and action routes of the default project, and of the project in the path
+    for prefix in ["/api", "/api/projects/{project}"] {
+        let path = |suffix: &str| format!("{}{}", prefix, suffix);
+        operations.extend([
+            op("get", path("/tasks"), "Tasks", "Tasks the user may see").auth(Auth::Read).data_described(TASKS),
+            op("get", path("/tasks/{task_id}"), "Tasks", "A task").auth(Auth::Read)
+                .data_described("The task, with a `paused` flag and the `pause` that set it"),
+            op("patch", path("/tasks/{task_id}"), "Tasks", "Pauses or resumes a task, e.g. during an incident")
+                .auth(Auth::Run).body::<TaskPatch>().data_described("`paused` and the `pause` that set it"),
+            op("get", path("/actions"), "Actions", "Actions of the workspace").auth(Auth::Read).data::<Vec<Action>>(),
+            op("get", path("/actions/{action_id}"), "Actions", "An action").auth(Auth::Read).data::<Option<Action>>(),
+            op("post", path("/workspace/validate"), "Workspace", "Validates the workflows, or as they would be with the uploaded files")
+                .auth(Auth::Run).optional_body::<WorkspaceValidation>()
+                .data_described("`valid`, the `errors` with the file and entry they are in, and the `revision`"),
+        ]);
+    }
+    operations.extend([
+        op("post", "/api/run", "Jobs", "Queues a run of a task or action").auth(Auth::Run)
+            .query::<RunParams>().body::<JobRequest>().data::<String>(),
+        op("post", "/api/projects/{project}/run", "Jobs", "Queues a run of a task or action of the project").auth(Auth::Run)
+            .query::<RunParams>().body::<JobRequest>().data::<String>(),
+        op("get", "/api/jobs", "Jobs", "Jobs the user may see, newest first, a page at a time").auth(Auth::Read)
+            .query::<JobListParams>().data::<JobPage>(),
+        op("get", "/api/jobs/{:job_id}", "Jobs", "A job with its steps").auth(Auth::Read).data::<Job>(),
+        op("delete", "/api/jobs/{:job_id}", "Jobs", "Hides a finished job from the API, for users who may run its task or action").auth(Auth::User).data_described("Empty object"),
+        op("get", "/api/jobs/{:job_id}/as-run-request", "Jobs", "The request that runs the job again").auth(Auth::Read).data::<JobRequest>(),
+        op("post", "/api/jobs/{:job_id}/rerun", "Jobs", "Queues a new run with the task or action and input of a finished job")
+            .auth(Auth::Run).query::<RunParams>().data::<String>(),
+        op("post", "/api/jobs/{:job_id}/resume", "Jobs", "Queues a new run of a failed task that starts at the step that failed")
+            .auth(Auth::Run).query::<RunParams>().data::<String>(),
+        op("get", "/api/jobs/{:job_id}/diff/{:other_job_id}", "Jobs", "Compares two runs of the same task or action")
+            .auth(Auth::Read).data_described("Differences in input, output, revision and steps"),
+        op("get", "/api/jobs/{:job_id}/state", "Jobs", "Which steps of a job were running or done at a given moment")
+            .auth(Auth::Read).query::<JobStateParams>().data_described("Steps by state at the moment"),
+        op("get", "/api/jobs/{:job_id}/deliveries", "Jobs", "Calls of the task's webhooks for the job")
+            .auth(Auth::Read).data_described("Deliveries with the outcome of their latest attempt"),
+        op("get", "/api/jobs/{:job_id}/approvals", "Jobs", "Approvals the approval steps of the job asked for").auth(Auth::Read)
+            .data::<Vec<JobApproval>>(),
+        op("post", "/api/jobs/{:job_id}/approvals/{:step_name}/approve", "Jobs", "Approves a pending approval, the step waiting for it succeeds")
+            .auth(Auth::Run).optional_body::<ApprovalDecision>().data::<JobApproval>(),
+        op("post", "/api/jobs/{:job_id}/approvals/{:step_name}/reject", "Jobs", "Rejects a pending approval, the step waiting for it fails")
+            .auth(Auth::Run).optional_body::<ApprovalDecision>().data::<JobApproval>(),
+        op("get", "/api/jobs/{:job_id}/waits", "Jobs", "What the wait and wait_for_event steps of the job waited for").auth(Auth::Read)
+            .data::<Vec<JobWait>>(),
+        op("post", "/api/events/{:key}", "Jobs", "Posts an event, the jobs waiting for its key continue with the body as output of the step")
+            .auth(Auth::Run).optional_body::<Value>().data_described("Jobs that waited for the event"),
+        op("get", "/api/jobs/{:job_id}/artifacts", "Jobs", "Files the steps of the job uploaded").auth(Auth::Read).data::<Vec<JobArtifact>>(),
+        op("get", "/api/jobs/{:job_id}/artifacts/{:step_name}/{*name}", "Jobs", "Downloads an artifact")
+            .auth(Auth::Read).content("application/octet-stream", "The file, with a content type guessed from its name"),
+        op("get", "/api/jobs/{:job_id}/logs", "Jobs", "Logs of a job").auth(Auth::Read).query::<LogFilterParams>().data::<Vec<LogEntry>>(),
+        op("get", "/api/jobs/{:job_id}/steps/{:step_name}/logs", "Jobs", "Logs of a step of a job")
+            .auth(Auth::Read).query::<LogFilterParams>().data::<Vec<LogEntry>>(),
+        op("get", "/api/jobs/{:job_id}/sse", "Jobs", "The job and its stored logs, followed by live events").auth(Auth::Read)
+            .header("Last-Event-ID", "Id of the last log event received, to only be sent the lines after it on reconnect")
+            .content("text/event-stream", "Server-sent events as the job runs and logs"),
+        op("get", "/api/jobs/{:job_id}/ws", "Jobs", "Stored logs followed by live events of a job, over a WebSocket")
+            .auth(Auth::Read).status("101", "Switching to the WebSocket, with `{\"event\": ..., \"data\": ...}` messages"),
+        op("post", "/api/groups", "Groups", "Starts a job group that runs can join with `?group=`")
+            .auth(Auth::Run).body::<JobGroupRequest>().data::<JobGroup>(),
+        op("get", "/api/groups/{:group_id}", "Groups", "The jobs of a group, with their counts by status")
+            .auth(Auth::Read).data_described("The `group`, its `status` and its `jobs`"),
+        op("get", "/api/workers", "Server", "Registered workers").auth(Auth::Read).data_described("Workers with their capacity and last heartbeat"),
+        op("get", "/api/schedule", "Server", "Next run of every enabled cron trigger, soonest first")
+            .auth(Auth::Read).data_described("Upcoming runs"),
+        op("get", "/api/search", "Server", "Searches tasks, actions, jobs and logs").auth(Auth::Read)
+            .query::<SearchParams>().data_described("Results by type"),
+        op("get", "/api/retention", "Server", "What job retention pruned").auth(Auth::Read)
+            .data_described("Pruned jobs of the last run and since the server started"),
+        op("get", "/api/autoscale/recommendation", "Server", "Number of workers recommended for the queue")
+            .auth(Auth::Read).data_described("The recommendation and the queue it is based on"),
+        op("get", "/api/reports/usage", "Server", "Compute time and cost of finished jobs").auth(Auth::Read)
+            .query::<UsageParams>().data_described("Usage by group"),
+        op("get", "/api/dashboard/queue", "Dashboard", "Queued and running jobs per task").auth(Auth::Read)
+            .data_described("`queued` and `running` in all, and the `tasks` with theirs, the most queued first"),
+        op("get", "/api/dashboard/queue-wait", "Dashboard", "How long jobs started in the range waited for a worker").auth(Auth::Read)
+            .query::<DashboardParams>().data_described("p50, p95 and max wait of `all` jobs and of the `tasks`"),
+        op("get", "/api/dashboard/throughput", "Dashboard", "Jobs each worker finished, per bucket of the range").auth(Auth::Read)
+            .query::<DashboardParams>().data::<Vec<WorkerThroughput>>(),
+        op("get", "/api/dashboard/sla", "Dashboard", "SLA breaches per task, per bucket of the range").auth(Auth::Read)
+            .query::<DashboardParams>().data::<Vec<SlaBreachTrend>>(),
+        op("get", "/api/sla/breaches", "SLA", "Jobs that took longer than the SLA of their task, newest first").auth(Auth::Read)
+            .query::<SlaBreachParams>().data::<Vec<SlaBreach>>(),
+        op("get", "/api/alerts", "Alerts", "Alerts raised by the alert rules, newest first").auth(Auth::Read)
+            .query::<AlertListParams>().data::<Vec<Alert>>(),
+        op("post", "/api/alerts/{:alert_id}/acknowledge", "Alerts", "Acknowledges a firing alert, which stays open while the rule is breached")
+            .auth(Auth::Run).data::<Alert>(),
+        op("post", "/api/alerts/{:alert_id}/resolve", "Alerts", "Resolves an open alert; a rule still breached fires a new one")
+            .auth(Auth::Run).data::<Alert>(),
+        op("get", "/api/admin/log-level", "Admin", "Log level of the server").auth(Auth::User).data_described("The `level`"),
+        op("put", "/api/admin/log-level", "Admin", "Changes the log level of the server").auth(Auth::User)
+            .body::<LogLevelRequest>().data_described("The new `level`"),
+        op("get", "/api/admin/worker-credentials", "Admin", "Worker credentials").auth(Auth::User).data_described("Credentials, without tokens"),
+        op("post", "/api/admin/worker-credentials", "Admin", "Mints a worker token").auth(Auth::User)
+            .body::<WorkerCredentialRequest>().data_described(TOKEN),
+        op("delete", "/api/admin/worker-credentials/{:credential_id}", "Admin", "Revokes a worker credential")
+            .auth(Auth::User).data_described("Empty object"),
+        op("post", "/api/admin/worker-credentials/{:credential_id}/rotate", "Admin", "Replaces the token of a worker credential")
+            .auth(Auth::User).optional_body::<RotateWorkerCredentialRequest>().data_described(TOKEN),
+        op("get", "/api/admin/workspace/files", "Workspace", "Editable files of the workspace").auth(Auth::User)
+            .data_described("The `files` and the `revision`"),
+        op("get", "/api/admin/workspace/files/{*path}", "Workspace", "A workspace file").auth(Auth::User)
+            .data_described("The `path`, its `content` and the `revision`"),
+        op("put", "/api/admin/workspace/files/{*path}", "Workspace", "Writes, commits and pushes a workspace file")
+            .auth(Auth::User).body::<WorkspaceFileEdit>().data_described("The new `revision`"),
+        op("delete", "/api/admin/jobs/{:job_id}", "Admin", "Deletes a finished job with its steps, logs and artifacts")
+            .auth(Auth::User).data_described("Empty object"),
+        op("get", "/api/admin/audit", "Admin", "Audit log, newest first").auth(Auth::User)
+            .query::<AuditLogParams>().data_described("Audit entries"),
+    ]);
+    operations
 }
 
+
 struct JobChannel<S> {
     inner: Pin<Box<S>>,
     job_id: String,
-    channels: Arc<Mutex<HashMap<String, Sender<JobEvent>>>>,
+    events: JobEvents,
 }
 
-impl<S> Stream for JobChannel<S>
-where
-    S: Stream<Item = Result<Event, Infallible>> + 'static,
-{
-    type Item = Result<Event, Infallible>;
+impl<S: Stream> Stream for JobChannel<S> {
+    type Item = S::Item;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         self.inner.as_mut().poll_next(cx)
@@ -61,24 +238,131 @@ where
 
 impl<S> Drop for JobChannel<S> {
     fn drop(&mut self) {
-        let mut channels = self.channels.lock().unwrap();
-        if let Some(tx) = channels.get(&self.job_id) {
-            if tx.receiver_count() <= 1 {
-                // current one is about to drop, so it's the last
-                channels.remove(&self.job_id);
-                debug!("Removed channel for job_id: {}", self.job_id);
+        // The receiver of this one is about to drop
+        self.events.release(&self.job_id, 1);
+    }
+}
+
+/// How far an SSE client got in the logs of a job, for the job itself and per step. It's the id of log events,
+/// so a reconnecting client hands it back in `Last-Event-ID`.
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct LogCursor {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    job: Option<LogPosition>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    steps: HashMap<String, LogPosition>,
+}
+
+/// The timestamp of the last line sent of a log, and how many lines of that timestamp were sent: lines
+/// sharing a timestamp are told apart by their order.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct LogPosition {
+    timestamp: DateTime<Utc>,
+    sent: usize,
+}
+
+impl LogCursor {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        headers.get("last-event-id")
+            .and_then(|id| id.to_str().ok())
+            .and_then(|id| serde_json::from_str(id).ok())
+            .unwrap_or_default()
+    }
+
+    fn position(&mut self, step_name: Option<&str>) -> Option<&mut LogPosition> {
+        match step_name {
+            Some(step_name) => self.steps.get_mut(step_name),
+            None => self.job.as_mut(),
+        }
+    }
+
+    /// Drops the lines up to the cursor. The lines of its timestamp dropped are counted off, so a later
+    /// batch of the same log only drops the ones not met yet.
+    fn skip_seen(&mut self, step_name: Option<&str>, logs: &mut Vec<LogEntry>) {
+        if let Some(position) = self.position(step_name) {
+            logs.retain(|entry| match entry.timestamp.cmp(&position.timestamp) {
+                Ordering::Less => false,
+                Ordering::Equal if position.sent > 0 => {
+                    position.sent -= 1;
+                    false
+                }
+                _ => true,
+            });
+        }
+    }
+
+    fn forget(&mut self, step_name: Option<&str>) {
+        match step_name {
+            Some(step_name) => { self.steps.remove(step_name); }
+            None => self.job = None,
+        }
+    }
+
+    fn advance(&mut self, step_name: Option<&str>, logs: &[LogEntry]) {
+        let Some(timestamp) = logs.last().map(|entry| entry.timestamp) else { return };
+        let at_timestamp = logs.iter().rev().take_while(|entry| entry.timestamp == timestamp).count();
+        match self.position(step_name) {
+            Some(position) if position.timestamp == timestamp => position.sent += at_timestamp,
+            _ => {
+                let position = LogPosition { timestamp, sent: at_timestamp };
+                match step_name {
+                    Some(step_name) => { self.steps.insert(step_name.to_string(), position); }
+                    None => self.job = Some(position),
+                }
             }
         }
     }
+
+    fn log_event(&self, step_name: Option<&str>, logs: Vec<LogEntry>) -> Event {
+        let (name, data) = match step_name {
+            Some(step_name) => ("step_logs", json!({"step_name": step_name, "logs": logs})),
+            None => ("logs", json!({"logs": logs})),
+        };
+        Event::default().event(name).id(serde_json::to_string(self).unwrap_or_default()).data(data.to_string())
+    }
 }
 
 
+/// The projects the user may see, with the revision their workspace is at.
+#[axum::debug_handler]
+async fn get_projects(
+    State(api): State<WebState>,
+    ReadAccess(user): ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let projects: Vec<Value> = api.projects.iter()
+        .filter(|project| user.can_view_project(project.acl.as_ref()))
+        .map(|project| json!({
+            "name": project.name,
+            "revision": project.workspace.get_revision(),
+        }))
+        .collect();
+    Ok(ApiResponse::data(Value::Array(projects)))
+}
+
+/// Path of the task routes, the project segment is read by `SelectedProject`.
+#[derive(Deserialize)]
+struct TaskPath {
+    task_id: String,
+}
+
+#[derive(Deserialize)]
+struct ActionPath {
+    action_id: String,
+}
+
+const PROJECT_VIEW_FORBIDDEN: &str = "You are not allowed to view this project";
+
 #[axum::debug_handler]
 async fn get_tasks(
     State(api): State<WebState>,
-    _user: User,
+    SelectedProject(project): SelectedProject,
+    ReadAccess(user): ReadAccess,
 ) -> Result<ApiResponse, ApiError> {
-    let workflows_guard = api.workspace.workflows.read().map_err(|_| anyhow!("Could not read workspace"))?;
+    if !user.can_view_project(project.acl.as_ref()) {
+        return Err(ApiError::forbidden(PROJECT_VIEW_FORBIDDEN));
+    }
+    let pauses = api.task_repository.get_pauses().await?;
+    let workflows_guard = project.workspace.workflows.read().map_err(|_| anyhow!("Could not read workspace"))?;
     let workflows = workflows_guard.as_ref().unwrap();
     let _tasks = workflows.tasks.as_ref();
 
@@ -86,7 +370,10 @@ async fn get_tasks(
 
     let tasks_json = match &workflows.tasks {
         Some(tasks) => {
-            let task_array: Vec<Value> = tasks.iter().map(|(_name, task)| serde_json::to_value(task).unwrap()).collect();
+            let task_array: Vec<Value> = tasks.iter()
+                .filter(|(_name, task)| user.can_view_task(Some(task)))
+                .map(|(name, task)| with_pause(serde_json::to_value(task).unwrap(), pauses.get(&project.scoped_name(name))))
+                .collect();
             _total = task_array.len();
             // task_array.sort_by(|a, b| a.get("name").unwrap().as_str().cmp(&b.get("name").unwrap().as_str()));
             serde_json::to_value(task_array)?
@@ -100,47 +387,414 @@ async fn get_tasks(
 #[axum::debug_handler]
 async fn get_task(
     State(api): State<WebState>,
-    Path(task_id): Path<String>,
-    _user: User,
+    SelectedProject(project): SelectedProject,
+    Path(TaskPath { task_id }): Path<TaskPath>,
+    ReadAccess(user): ReadAccess,
 ) -> Result<ApiResponse, ApiError> {
-    let workflows_guard = api.workspace.workflows.read().map_err(|_| anyhow!("Could not read workspace"))?;
+    if !user.can_view_project(project.acl.as_ref()) {
+        return Err(ApiError::forbidden(PROJECT_VIEW_FORBIDDEN));
+    }
+    let pause = api.task_repository.get_pause(&project.scoped_name(&task_id)).await?;
+    let workflows_guard = project.workspace.workflows.read().map_err(|_| anyhow!("Could not read workspace"))?;
     let workflows = workflows_guard.as_ref().unwrap();
-    let task = serde_json::to_value(workflows.get_task(task_id.as_str()))?;
-    
+    let task = workflows.get_task(task_id.as_str());
+    if !user.can_view_task(task) {
+        return Err(ApiError::forbidden("You are not allowed to view this task"));
+    }
+    let task = with_pause(serde_json::to_value(task)?, pause.as_ref());
+
     Ok(ApiResponse::data(task))
 }
 
+/// Adds the server-side pause state to a serialized task.
+fn with_pause(mut task: Value, pause: Option<&TaskPause>) -> Value {
+    if let Value::Object(task) = &mut task {
+        task.insert("paused".to_string(), Value::Bool(pause.is_some()));
+        task.insert("pause".to_string(), serde_json::to_value(pause).unwrap_or_default());
+    }
+    task
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct TaskPatch {
+    paused: Option<bool>,
+}
+
+/// Pauses or resumes a task, e.g. during an incident; allowed to anyone who may run it.
+#[axum::debug_handler]
+async fn patch_task(
+    State(api): State<WebState>,
+    SelectedProject(project): SelectedProject,
+    Path(TaskPath { task_id }): Path<TaskPath>,
+    RunAccess(user): RunAccess,
+    Json(patch): Json<TaskPatch>,
+) -> Result<ApiResponse, ApiError> {
+    {
+        let workflows_guard = project.workspace.workflows.read().map_err(|_| anyhow!("Could not read workspace"))?;
+        let task = workflows_guard.as_ref().and_then(|workflows| workflows.get_task(&task_id));
+        if task.is_none() {
+            return Err(ApiError::not_found("Task not found"));
+        }
+        if !user.can_run_project(project.acl.as_ref()) || !user.can_run_task(task) {
+            return Err(ApiError::forbidden("You are not allowed to pause this task"));
+        }
+    }
+    let task_id = project.scoped_name(&task_id);
+    match patch.paused {
+        Some(true) => { api.task_repository.pause(&task_id, &user.email).await?; }
+        Some(false) => { api.task_repository.resume(&task_id, &user.email).await?; }
+        None => {}
+    }
+    let pause = api.task_repository.get_pause(&task_id).await?;
+    Ok(ApiResponse::data(json!({
+        "paused": pause.is_some(),
+        "pause": pause,
+    })))
+}
+
+#[axum::debug_handler(state = WebState)]
+async fn get_actions(
+    SelectedProject(project): SelectedProject,
+    ReadAccess(user): ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    if !user.can_view_project(project.acl.as_ref()) {
+        return Err(ApiError::forbidden(PROJECT_VIEW_FORBIDDEN));
+    }
+    let workflows_guard = project.workspace.workflows.read().map_err(|_| anyhow!("Could not read workspace"))?;
+    let workflows = workflows_guard.as_ref().unwrap();
+
+    let actions_json = match &workflows.actions {
+        Some(actions) => serde_json::to_value(actions.values().collect::<Vec<_>>())?,
+        None => Value::Array(vec![]),
+    };
+
+    Ok(ApiResponse::data(actions_json))
+}
+
+#[axum::debug_handler(state = WebState)]
+async fn get_action(
+    SelectedProject(project): SelectedProject,
+    Path(ActionPath { action_id }): Path<ActionPath>,
+    ReadAccess(user): ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    if !user.can_view_project(project.acl.as_ref()) {
+        return Err(ApiError::forbidden(PROJECT_VIEW_FORBIDDEN));
+    }
+    let workflows_guard = project.workspace.workflows.read().map_err(|_| anyhow!("Could not read workspace"))?;
+    let workflows = workflows_guard.as_ref().unwrap();
+    let action = serde_json::to_value(workflows.get_action(action_id.as_str()))?;
+
+    Ok(ApiResponse::data(action))
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct JobListParams {
+    /// List jobs queued before the default window of the job list too
+    #[serde(default)]
+    include_archived: bool,
+    /// Page of `limit` jobs, starting at 1
+    page: Option<i64>,
+    limit: Option<i64>,
+    /// `queued`, `running`, `completed` or `failed`
+    status: Option<String>,
+    task: Option<String>,
+    /// What queued the job: `user`, `trigger` or `webhook`
+    source_type: Option<String>,
+    worker_id: Option<String>,
+    /// Jobs queued at or after this RFC 3339 timestamp, in place of the default window
+    #[serde(default, with = "stroem_common::rfc3339::option")]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    from: Option<DateTime<Utc>>,
+    /// Jobs queued before this RFC 3339 timestamp
+    #[serde(default, with = "stroem_common::rfc3339::option")]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    to: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct JobPage {
+    jobs: Vec<Job>,
+    /// Number of jobs matching the filters, on all pages
+    total: i64,
+    page: i64,
+    limit: i64,
+}
+
+/// Jobs the user may see, newest first, a page at a time.
 #[axum::debug_handler]
 async fn get_jobs(
     State(api): State<WebState>,
-    Query(_params): Query<HashMap<String, String>>,
-    _user: User,
-) -> Result<ApiResponse, AppError> {
-    let jobs = api.job_repository.get_jobs().await?;
-    Ok(ApiResponse::data(serde_json::to_value(jobs)?))
+    Query(params): Query<JobListParams>,
+    ReadAccess(user): ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let page = params.page.unwrap_or(1);
+    let limit = params.limit.unwrap_or(api.job_list.default_limit);
+    if page < 1 || limit < 1 {
+        return Err(ApiError::bad_request("page and limit must be at least 1"));
+    }
+    let from = match (params.from, params.include_archived) {
+        (Some(from), _) => Some(from),
+        (None, true) => None,
+        (None, false) => Some(Utc::now() - api.job_list.default_window),
+    };
+    let filter = JobFilter {
+        from,
+        to: params.to,
+        status: params.status,
+        task: params.task,
+        source_type: params.source_type,
+        worker_id: params.worker_id,
+        ..api.visible_jobs(&user)
+    };
+    let (mut jobs, total) = api.job_repository.get_jobs(&filter, limit, (page - 1).saturating_mul(limit)).await?;
+    jobs.iter_mut().for_each(|job| api.mask_secret_inputs(job));
+    Ok(ApiResponse::data(serde_json::to_value(JobPage { jobs, total, page, limit })?))
 }
 
 #[axum::debug_handler]
 async fn get_job(
     State(api): State<WebState>,
     Path(job_id): Path<String>,
-    _user: User,
+    ReadAccess(user): ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let mut job = get_visible_job(&api, &user, &job_id).await?;
+    add_revision_info(&api, &mut job);
+    Ok(ApiResponse::data(serde_json::to_value(job)?))
+}
+
+/// The revision the project is at now, and the commit the job ran with.
+fn add_revision_info(api: &WebState, job: &mut Job) {
+    if let Some(project) = api.projects.get(&job.project_id) {
+        job.current_revision = project.workspace.get_revision();
+        job.commit = job.revision.as_deref().and_then(|revision| project.workspace.commit_info(revision));
+    }
+}
+
+#[axum::debug_handler]
+async fn get_job_as_run_request(
+    State(api): State<WebState>,
+    Path(job_id): Path<String>,
+    ReadAccess(user): ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let job = get_visible_job(&api, &user, &job_id).await?;
+    let request = JobRequest {
+        spec: JobSpec::new(job.task, job.action).map_err(Error::msg)?,
+        input: job.input,
+        uuid: None,
+        priority: Some(job.priority),
+        resume: None,
+        traceparent: None,
+        project: (job.project_id != DEFAULT_PROJECT).then_some(job.project_id),
+    };
+    Ok(ApiResponse::data(serde_json::to_value(request)?))
+}
+
+/// Compares two runs of the same task (or action), e.g. a failing run against the last green one.
+#[axum::debug_handler]
+async fn get_job_diff(
+    State(api): State<WebState>,
+    Path((job_id, other_job_id)): Path<(String, String)>,
+    ReadAccess(user): ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let left = get_visible_job(&api, &user, &job_id).await?;
+    let right = get_visible_job(&api, &user, &other_job_id).await?;
+    if left.task != right.task || left.action != right.action {
+        return Err(ApiError::bad_request("Jobs are not runs of the same task or action"));
+    }
+    Ok(ApiResponse::data(serde_json::to_value(JobDiff::between(&left, &right))?))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct JobStateParams {
+    /// RFC 3339 timestamp, defaults to now
+    #[serde(default, with = "stroem_common::rfc3339::option")]
+    #[schemars(with = "Option<DateTime<Utc>>")]
+    at: Option<DateTime<Utc>>,
+}
+
+/// Which steps of a job were running or done at a given moment, e.g. to line it up with an incident timeline.
+#[axum::debug_handler]
+async fn get_job_state(
+    State(api): State<WebState>,
+    Path(job_id): Path<String>,
+    Query(params): Query<JobStateParams>,
+    ReadAccess(user): ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    get_visible_job(&api, &user, &job_id).await?;
+    let timeline = api.job_repository.get_job_timeline(&job_id).await?;
+    let state = JobState::at(&timeline, params.at.unwrap_or_else(Utc::now));
+    Ok(ApiResponse::data(serde_json::to_value(state)?))
+}
+
+/// Calls of the task's webhooks for this job, with the outcome of their latest attempt.
+#[axum::debug_handler]
+async fn get_job_deliveries(
+    State(api): State<WebState>,
+    Path(job_id): Path<String>,
+    ReadAccess(user): ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let job = get_visible_job(&api, &user, &job_id).await?;
+    let deliveries = api.task_webhooks.get_deliveries(&job.job_id).await?;
+    Ok(ApiResponse::data(serde_json::to_value(deliveries)?))
+}
+
+#[axum::debug_handler]
+async fn get_job_approvals(
+    State(api): State<WebState>,
+    Path(job_id): Path<String>,
+    ReadAccess(user): ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let job = get_visible_job(&api, &user, &job_id).await?;
+    let approvals = api.approval_repository.get_approvals(&job.job_id).await?;
+    Ok(ApiResponse::data(serde_json::to_value(approvals)?))
+}
+
+#[derive(Deserialize, JsonSchema, Default)]
+struct ApprovalDecision {
+    /// Shown with the decision, and passed to the step as its output
+    comment: Option<String>,
+}
+
+#[axum::debug_handler]
+async fn post_approval_approve(
+    State(api): State<WebState>,
+    Path((job_id, step_name)): Path<(String, String)>,
+    RunAccess(user): RunAccess,
+    decision: Option<Json<ApprovalDecision>>,
+) -> Result<ApiResponse, ApiError> {
+    let decision = decision.map(|Json(decision)| decision).unwrap_or_default();
+    decide_approval(&api, &user, &job_id, &step_name, true, decision).await
+}
+
+#[axum::debug_handler]
+async fn post_approval_reject(
+    State(api): State<WebState>,
+    Path((job_id, step_name)): Path<(String, String)>,
+    RunAccess(user): RunAccess,
+    decision: Option<Json<ApprovalDecision>>,
+) -> Result<ApiResponse, ApiError> {
+    let decision = decision.map(|Json(decision)| decision).unwrap_or_default();
+    decide_approval(&api, &user, &job_id, &step_name, false, decision).await
+}
+
+/// Approves or rejects the approval of a step and queues the job suspended on it again, which then picks up the decision.
+async fn decide_approval(api: &WebState, user: &User, job_id: &str, step_name: &str, approved: bool, decision: ApprovalDecision) -> Result<ApiResponse, ApiError> {
+    let job = get_visible_job(api, user, job_id).await?;
+    let Some(approval) = api.approval_repository.get_approval(&job.job_id, step_name).await? else {
+        return Err(ApiError::not_found("Approval not found"));
+    };
+    if !api.can_approve(user, &job, approval.approvers().as_deref()) {
+        return Err(ApiError::forbidden("You are not allowed to decide on this approval"));
+    }
+    if !api.approval_repository.decide(&job.job_id, step_name, approved, &user.email, decision.comment.as_deref()).await? {
+        let status = api.approval_repository.get_approval(&job.job_id, step_name).await?.map(|approval| approval.status);
+        return Err(match status.as_deref() {
+            Some("pending") => ApiError::conflict("Job has finished"),
+            Some("timed_out") => ApiError::conflict("Approval timed out"),
+            Some(status) => ApiError::conflict(&format!("Approval is {} already", status)),
+            None => ApiError::not_found("Approval not found"),
+        });
+    }
+    let action = if approved { "approval.approve" } else { "approval.reject" };
+    api.audit_repository.record(&user.email, action, &format!("{}/{}", job_id, step_name), decision.comment.map(|comment| json!({"comment": comment}))).await?;
+    info!("User {} {} step {} of job {}", user.email, if approved { "approved" } else { "rejected" }, step_name, job_id);
+    api.job_repository.requeue_suspended(&job.job_id).await?;
+    let approval = api.approval_repository.get_approval(&job.job_id, step_name).await?;
+    Ok(ApiResponse::data(serde_json::to_value(approval)?))
+}
+
+#[axum::debug_handler]
+async fn get_job_waits(
+    State(api): State<WebState>,
+    Path(job_id): Path<String>,
+    ReadAccess(user): ReadAccess,
 ) -> Result<ApiResponse, ApiError> {
-    let task = api.job_repository.get_job(job_id.as_str()).await?;
-    Ok(ApiResponse::data(serde_json::to_value(task)?))
+    let job = get_visible_job(&api, &user, &job_id).await?;
+    let waits = api.wait_repository.get_waits(&job.job_id).await?;
+    Ok(ApiResponse::data(serde_json::to_value(waits)?))
+}
+
+/// Resolves the waits for the event of the jobs whose task the user may run, and queues them again. The event is
+/// kept for steps that start waiting for it later, such as a callback arriving before the job got to its
+/// `wait_for_event` step; they take it by the same rule.
+#[axum::debug_handler]
+async fn post_event(
+    State(api): State<WebState>,
+    Path(key): Path<String>,
+    RunAccess(user): RunAccess,
+    payload: Option<Json<Value>>,
+) -> Result<ApiResponse, ApiError> {
+    let payload = payload.map(|Json(payload)| payload);
+    // Stored first, so a step that starts waiting meanwhile either finds it or is found below
+    api.wait_repository.store_event(&key, &payload, &user.user_id).await?;
+    let mut job_ids = Vec::new();
+    for job_id in api.wait_repository.get_waiting_jobs(&key).await? {
+        let job = api.job_repository.get_job(&job_id.to_string()).await?;
+        if !api.can_rerun(&user, &job) || !api.wait_repository.receive(&job_id, &key, &payload).await? {
+            continue;
+        }
+        api.job_repository.requeue_suspended(&job_id).await?;
+        job_ids.push(job_id);
+    }
+    api.audit_repository.record(&user.email, "event.post", &key, Some(json!({"jobs": job_ids}))).await?;
+    info!("User {} posted event {}, {} jobs waited for it", user.email, key, job_ids.len());
+    Ok(ApiResponse::data(json!({"jobs": job_ids})))
+}
+
+/// Loads a job, refusing users that may not see its task.
+async fn get_visible_job(api: &WebState, user: &User, job_id: &str) -> Result<Job, ApiError> {
+    let mut job = api.job_repository.get_job(job_id).await?;
+    if job.deleted.is_some() {
+        return Err(ApiError::not_found("Job not found"));
+    }
+    if !api.can_view_task(user, &job.project_id, job.task.as_deref()) {
+        return Err(ApiError::forbidden("You are not allowed to view this job"));
+    }
+    api.mask_secret_inputs(&mut job);
+    Ok(job)
+}
+
+#[axum::debug_handler]
+async fn get_job_artifacts(
+    State(api): State<WebState>,
+    Path(job_id): Path<String>,
+    ReadAccess(user): ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let job = get_visible_job(&api, &user, &job_id).await?;
+    let artifacts = api.artifact_repository.get_artifacts(&job.job_id).await?;
+    Ok(ApiResponse::data(serde_json::to_value(artifacts)?))
+}
+
+#[axum::debug_handler]
+async fn get_job_artifact(
+    State(api): State<WebState>,
+    Path((job_id, step_name, name)): Path<(String, String, String)>,
+    ReadAccess(user): ReadAccess,
+) -> Result<Response, ApiError> {
+    let job = get_visible_job(&api, &user, &job_id).await?;
+    Ok(api.artifact_response(&job.job_id, &step_name, &name).await?)
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct LogFilterParams {
+    /// Only return entries of this level or more severe
+    level: Option<LogLevel>,
 }
 
 #[axum::debug_handler]
 async fn get_job_logs(
     State(api): State<WebState>,
     Path(job_id): Path<String>,
-    _user: User,
+    Query(params): Query<LogFilterParams>,
+    ReadAccess(user): ReadAccess,
 ) -> Result<ApiResponse, ApiError> {
+    get_visible_job(&api, &user, &job_id).await?;
     let log_stream = api.log_repository.get_logs(job_id.as_str(), None).await?;
     let logs: Vec<LogEntry> = log_stream
         .collect::<Vec<Result<LogEntry, Error>>>()
         .await
         .into_iter()
+        .filter(|entry| filter_log_level(entry, params.level))
         .collect::<Result<Vec<_>, _>>()?;
 
     Ok(ApiResponse::data(serde_json::to_value(logs)?))
@@ -150,84 +804,969 @@ async fn get_job_logs(
 async fn get_job_step_logs(
     State(api): State<WebState>,
     Path((job_id, step_name)): Path<(String, String)>,
-    _user: User,
+    Query(params): Query<LogFilterParams>,
+    ReadAccess(user): ReadAccess,
 ) -> Result<ApiResponse, ApiError> {
+    get_visible_job(&api, &user, &job_id).await?;
     let log_stream = api.log_repository.get_logs(job_id.as_str(), Some(step_name.as_str())).await?;
     let logs: Vec<LogEntry> = log_stream
         .collect::<Vec<Result<LogEntry, Error>>>()
         .await
         .into_iter()
+        .filter(|entry| filter_log_level(entry, params.level))
         .collect::<Result<Vec<_>, _>>()?;
 
     Ok(ApiResponse::data(serde_json::to_value(logs)?))
 }
 
+fn filter_log_level(entry: &Result<LogEntry, Error>, level: Option<LogLevel>) -> bool {
+    match (entry, level) {
+        (Ok(entry), Some(level)) => entry.effective_level() >= level,
+        _ => true,
+    }
+}
+
 
 #[axum::debug_handler]
-async fn put_job(
+async fn get_workers(
+    State(api): State<WebState>,
+    _user: ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let workers = api.worker_repository.get_workers(api.worker_stale_after).await?;
+    Ok(ApiResponse::data(serde_json::to_value(workers)?))
+}
+
+/// Next run of every enabled cron trigger, soonest first.
+#[axum::debug_handler]
+async fn get_schedule(
+    State(api): State<WebState>,
+    ReadAccess(user): ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let mut upcoming: Vec<UpcomingRun> = api.upcoming_runs.iter()
+        .flat_map(|runs| runs.borrow().clone())
+        .filter(|run| api.can_view_task(&user, &run.project, Some(&run.task)))
+        .collect();
+    upcoming.sort_by_key(|run| run.next_run);
+    Ok(ApiResponse::data(serde_json::to_value(upcoming)?))
+}
+
+/// What job retention pruned, in the last run and since the server started.
+#[axum::debug_handler]
+async fn get_retention(
+    State(api): State<WebState>,
+    _user: ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let stats = api.retention_stats.borrow().clone();
+    Ok(ApiResponse::data(serde_json::to_value(stats)?))
+}
+
+#[axum::debug_handler]
+async fn get_autoscale_recommendation(
+    State(api): State<WebState>,
+    _user: ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let recommendation = crate::autoscale::recommend(&api.job_repository, &api.worker_repository, &api.autoscale, api.worker_stale_after).await?;
+    Ok(ApiResponse::data(serde_json::to_value(recommendation)?))
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct UsageParams {
+    #[serde(default = "default_usage_group_by")]
+    group_by: String,
+    #[serde(default = "default_usage_range")]
+    range: String,
+}
+
+fn default_usage_group_by() -> String { "task".to_string() }
+fn default_usage_range() -> String { "30d".to_string() }
+
+/// Compute time and cost of finished jobs, e.g. `?group_by=label:team&range=30d`.
+#[axum::debug_handler]
+async fn get_usage_report(
+    State(api): State<WebState>,
+    Query(params): Query<UsageParams>,
+    _user: ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let group = UsageGroup::parse(&params.group_by).map_err(|e| ApiError::bad_request(&e.to_string()))?;
+    let range = duration_str::parse(&params.range)
+        .map_err(|e| ApiError::bad_request(&format!("Invalid range '{}': {}", params.range, e)))?;
+    let since = Utc::now() - chrono::Duration::from_std(range).map_err(|e| ApiError::bad_request(&e.to_string()))?;
+    let rows = api.job_repository.get_usage(since, &group).await?;
+    let report = crate::usage::build_report(rows, &api.accounting, &params.group_by, range.as_secs());
+    Ok(ApiResponse::data(serde_json::to_value(report)?))
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct DashboardParams {
+    /// How far back to look, e.g. `24h`
+    #[serde(default = "default_dashboard_range")]
+    range: String,
+    /// Width of the throughput buckets, e.g. `1h`
+    #[serde(default = "default_dashboard_bucket")]
+    bucket: String,
+}
+
+fn default_dashboard_range() -> String { "24h".to_string() }
+fn default_dashboard_bucket() -> String { "1h".to_string() }
+
+/// Throughput buckets per worker a single response may have
+const MAX_DASHBOARD_BUCKETS: u64 = 1000;
+
+fn parse_dashboard_duration(name: &str, value: &str) -> Result<std::time::Duration, String> {
+    match duration_str::parse(value) {
+        Ok(duration) if !duration.is_zero() => Ok(duration),
+        Ok(_) => Err(format!("Invalid {} '{}': must be more than zero", name, value)),
+        Err(e) => Err(format!("Invalid {} '{}': {}", name, value, e)),
+    }
+}
+
+/// Start of the range the dashboard looks at
+fn dashboard_since(params: &DashboardParams) -> Result<DateTime<Utc>, String> {
+    let range = parse_dashboard_duration("range", &params.range)?;
+    Ok(Utc::now() - chrono::Duration::from_std(range).map_err(|e| e.to_string())?)
+}
+
+/// Seconds per bucket of the range
+fn dashboard_bucket(params: &DashboardParams) -> Result<i64, String> {
+    let range = parse_dashboard_duration("range", &params.range)?;
+    let bucket = parse_dashboard_duration("bucket", &params.bucket)?;
+    if bucket.as_secs() == 0 || range.as_secs() / bucket.as_secs() > MAX_DASHBOARD_BUCKETS {
+        return Err(format!(
+            "Bucket '{}' must be at least a second and split the range into at most {} buckets", params.bucket, MAX_DASHBOARD_BUCKETS
+        ));
+    }
+    Ok(bucket.as_secs() as i64)
+}
+
+/// What's waiting for a worker now; tasks with a deep queue and nothing running lack workers.
+#[axum::debug_handler]
+async fn get_dashboard_queue(
+    State(api): State<WebState>,
+    ReadAccess(user): ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let tasks = api.job_repository.get_queue_depth(&api.visible_jobs(&user)).await?;
+    Ok(ApiResponse::data(json!({
+        "queued": tasks.iter().map(|task| task.queued).sum::<i64>(),
+        "running": tasks.iter().map(|task| task.running).sum::<i64>(),
+        "tasks": tasks,
+    })))
+}
+
+#[axum::debug_handler]
+async fn get_dashboard_queue_wait(
+    State(api): State<WebState>,
+    Query(params): Query<DashboardParams>,
+    ReadAccess(user): ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let since = dashboard_since(&params).map_err(|e| ApiError::bad_request(&e))?;
+    let filter = api.visible_jobs(&user);
+    let all = api.job_repository.get_queue_wait(since, false, &filter).await?.pop();
+    let tasks = api.job_repository.get_queue_wait(since, true, &filter).await?;
+    Ok(ApiResponse::data(json!({"all": all, "tasks": tasks})))
+}
+
+#[axum::debug_handler]
+async fn get_dashboard_throughput(
+    State(api): State<WebState>,
+    Query(params): Query<DashboardParams>,
+    ReadAccess(user): ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let bucket = dashboard_bucket(&params).map_err(|e| ApiError::bad_request(&e))?;
+    let since = dashboard_since(&params).map_err(|e| ApiError::bad_request(&e))?;
+    let throughput = api.job_repository.get_worker_throughput(since, bucket, &api.visible_jobs(&user)).await?;
+    Ok(ApiResponse::data(serde_json::to_value(throughput)?))
+}
+
+#[axum::debug_handler]
+async fn get_dashboard_sla(
+    State(api): State<WebState>,
+    Query(params): Query<DashboardParams>,
+    _user: ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let bucket = dashboard_bucket(&params).map_err(|e| ApiError::bad_request(&e))?;
+    let since = dashboard_since(&params).map_err(|e| ApiError::bad_request(&e))?;
+    let trend = api.sla_repository.get_breach_trend(since, bucket).await?;
+    Ok(ApiResponse::data(serde_json::to_value(trend)?))
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct SlaBreachParams {
+    /// How far back to look, e.g. `7d`
+    #[serde(default = "default_sla_breach_range")]
+    range: String,
+    project: Option<String>,
+    task: Option<String>,
+    limit: Option<i64>,
+}
+
+fn default_sla_breach_range() -> String { "7d".to_string() }
+
+#[axum::debug_handler]
+async fn get_sla_breaches(
+    State(api): State<WebState>,
+    Query(params): Query<SlaBreachParams>,
+    _user: ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let range = parse_dashboard_duration("range", &params.range).map_err(|e| ApiError::bad_request(&e))?;
+    let since = Utc::now() - chrono::Duration::from_std(range).map_err(|e| ApiError::bad_request(&e.to_string()))?;
+    let breaches = api.sla_repository.get_breaches(since, params.project.as_deref(), params.task.as_deref(), params.limit.unwrap_or(100)).await?;
+    Ok(ApiResponse::data(serde_json::to_value(breaches)?))
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct SearchParams {
+    q: String,
+    /// Maximum number of results per type, can be overridden per type, at most 50 each
+    limit: Option<usize>,
+    task_limit: Option<usize>,
+    action_limit: Option<usize>,
+    job_limit: Option<usize>,
+    log_limit: Option<usize>,
+}
+
+const DEFAULT_SEARCH_LIMIT: usize = 5;
+
+#[axum::debug_handler]
+async fn get_search(
+    State(api): State<WebState>,
+    Query(params): Query<SearchParams>,
+    ReadAccess(user): ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let query = params.q.trim();
+    if query.is_empty() {
+        return Err(ApiError::bad_request("Missing search query"));
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+    let limits = SearchLimits::new(limit, params.task_limit, params.action_limit, params.job_limit, params.log_limit);
+    let results = crate::search::search(&api, &user, query, limits).await?;
+    Ok(ApiResponse::data(serde_json::to_value(results)?))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct LogLevelRequest {
+    level: String,
+}
+
+#[axum::debug_handler]
+async fn get_log_level(
     State(api): State<WebState>,
     _user: User,
+) -> Result<ApiResponse, ApiError> {
+    Ok(ApiResponse::data(json!({"level": api.log_level.current().to_string()})))
+}
+
+#[axum::debug_handler]
+async fn put_log_level(
+    State(api): State<WebState>,
+    user: User,
+    Json(request): Json<LogLevelRequest>,
+) -> Result<ApiResponse, ApiError> {
+    if !user.is_admin() {
+        return Err(ApiError::forbidden("Only admins can change the log level"));
+    }
+    let level = api.log_level.set_from_str(&request.level)
+        .map_err(|e| ApiError::bad_request(&e.to_string()))?;
+    Ok(ApiResponse::data(json!({"level": level.to_string()})))
+}
+
+/// How long a rotated worker token keeps working by default.
+const DEFAULT_ROTATION_GRACE: Duration = Duration::from_secs(3600);
+
+const WORKER_CREDENTIALS_ADMIN_ONLY: &str = "Only admins can manage worker credentials";
+
+fn parse_duration_param(name: &str, value: &str) -> Result<Duration, String> {
+    duration_str::parse(value).map_err(|e| format!("Invalid {}: {}", name, e))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct WorkerCredentialRequest {
+    name: String,
+    /// e.g. `90d`; credentials without it never expire
+    expires_in: Option<String>,
+}
+
+#[derive(Deserialize, Default, JsonSchema)]
+struct RotateWorkerCredentialRequest {
+    /// How long the old token keeps working, e.g. `30m`
+    grace: Option<String>,
+}
+
+#[axum::debug_handler]
+async fn get_worker_credentials(
+    State(api): State<WebState>,
+    user: User,
+) -> Result<ApiResponse, ApiError> {
+    if !user.is_admin() {
+        return Err(ApiError::forbidden(WORKER_CREDENTIALS_ADMIN_ONLY));
+    }
+    let credentials = api.worker_repository.get_credentials().await?;
+    Ok(ApiResponse::data(serde_json::to_value(credentials)?))
+}
+
+/// Mints a worker token; it is only shown in this response.
+#[axum::debug_handler]
+async fn post_worker_credential(
+    State(api): State<WebState>,
+    user: User,
+    Json(request): Json<WorkerCredentialRequest>,
+) -> Result<ApiResponse, ApiError> {
+    if !user.is_admin() {
+        return Err(ApiError::forbidden(WORKER_CREDENTIALS_ADMIN_ONLY));
+    }
+    let name = request.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::bad_request("Missing credential name"));
+    }
+    let expires_at = match request.expires_in.as_deref() {
+        Some(expires_in) => Some(Utc::now() + parse_duration_param("expires_in", expires_in).map_err(|e| ApiError::bad_request(&e))?),
+        None => None,
+    };
+    let (credential, token) = api.worker_repository.create_credential(name, expires_at).await?;
+    Ok(ApiResponse::data(json!({"token": token, "info": credential})))
+}
+
+#[axum::debug_handler]
+async fn rotate_worker_credential(
+    State(api): State<WebState>,
+    Path(credential_id): Path<Uuid>,
+    user: User,
+    request: Option<Json<RotateWorkerCredentialRequest>>,
+) -> Result<ApiResponse, ApiError> {
+    if !user.is_admin() {
+        return Err(ApiError::forbidden(WORKER_CREDENTIALS_ADMIN_ONLY));
+    }
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+    let grace = match request.grace.as_deref() {
+        Some(grace) => parse_duration_param("grace", grace).map_err(|e| ApiError::bad_request(&e))?,
+        None => DEFAULT_ROTATION_GRACE,
+    };
+    let Some((credential, token)) = api.worker_repository.rotate_credential(&credential_id, grace).await? else {
+        return Err(ApiError::not_found("Worker credential not found"));
+    };
+    Ok(ApiResponse::data(json!({"token": token, "info": credential})))
+}
+
+#[axum::debug_handler]
+async fn delete_worker_credential(
+    State(api): State<WebState>,
+    Path(credential_id): Path<Uuid>,
+    user: User,
+) -> Result<ApiResponse, ApiError> {
+    if !user.is_admin() {
+        return Err(ApiError::forbidden(WORKER_CREDENTIALS_ADMIN_ONLY));
+    }
+    if !api.worker_repository.revoke_credential(&credential_id).await? {
+        return Err(ApiError::not_found("Worker credential not found"));
+    }
+    Ok(ApiResponse::data(json!({})))
+}
+
+/// Hides a finished job from the API, e.g. one that captured sensitive data, for users who may run it again.
+/// It is kept with its logs until retention prunes it; admins can purge it right away.
+#[axum::debug_handler]
+async fn delete_job(
+    State(api): State<WebState>,
+    Path(job_id): Path<String>,
+    user: User,
+) -> Result<ApiResponse, ApiError> {
+    let job = get_visible_job(&api, &user, &job_id).await?;
+    if !api.can_rerun(&user, &job) {
+        return Err(ApiError::forbidden("Only users who may run the task can delete its jobs"));
+    }
+    if job.end_datetime.is_none() {
+        return Err(ApiError::conflict("Only finished jobs can be deleted"));
+    }
+    if !api.job_repository.soft_delete_job(&job.job_id, &user.email).await? {
+        return Err(ApiError::not_found("Job not found"));
+    }
+    api.audit_repository.record(&user.email, "job.delete", &job_id, Some(json!({"task": job.task, "action": job.action}))).await?;
+    Ok(ApiResponse::data(json!({})))
+}
+
+/// Deletes a finished job, soft-deleted or not, with its steps, logs and artifacts.
+#[axum::debug_handler]
+async fn purge_job(
+    State(api): State<WebState>,
+    Path(job_id): Path<String>,
+    user: User,
+) -> Result<ApiResponse, ApiError> {
+    if !user.is_admin() {
+        return Err(ApiError::forbidden("Only admins can purge jobs"));
+    }
+    let job = api.job_repository.get_job(&job_id).await?;
+    if job.end_datetime.is_none() {
+        return Err(ApiError::conflict("Only finished jobs can be purged"));
+    }
+    if !api.job_repository.purge_job(&job.job_id).await? {
+        return Err(ApiError::not_found("Job not found"));
+    }
+    let logs = api.log_repository.delete_logs(&job_id).await;
+    let artifacts = api.artifact_repository.delete_artifacts(&job_id).await;
+    api.audit_repository.record(&user.email, "job.purge", &job_id, Some(json!({
+        "task": job.task,
+        "action": job.action,
+        "logs_deleted": logs.is_ok(),
+        "artifacts_deleted": artifacts.is_ok(),
+    }))).await?;
+    logs.map_err(|e| anyhow!("Purged job {}, but failed to delete its logs: {}", job_id, e))?;
+    artifacts.map_err(|e| anyhow!("Purged job {}, but failed to delete its artifacts: {}", job_id, e))?;
+    Ok(ApiResponse::data(json!({})))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct AlertListParams {
+    /// Also list resolved alerts
+    #[serde(default)]
+    include_resolved: bool,
+    limit: Option<i64>,
+}
+
+#[axum::debug_handler]
+async fn get_alerts(
+    State(api): State<WebState>,
+    Query(params): Query<AlertListParams>,
+    _user: ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let alerts = api.alert_repository.get_alerts(params.include_resolved, params.limit.unwrap_or(100)).await?;
+    Ok(ApiResponse::data(serde_json::to_value(alerts)?))
+}
+
+#[axum::debug_handler]
+async fn post_alert_acknowledge(
+    State(api): State<WebState>,
+    Path(alert_id): Path<i64>,
+    RunAccess(user): RunAccess,
+) -> Result<ApiResponse, ApiError> {
+    if !api.alert_repository.acknowledge(alert_id, &user.email).await? {
+        return Err(alert_not_open(&api, alert_id, "firing").await?);
+    }
+    api.audit_repository.record(&user.email, "alert.acknowledge", &alert_id.to_string(), None).await?;
+    let alert = api.alert_repository.get_alert(alert_id).await?;
+    Ok(ApiResponse::data(serde_json::to_value(alert)?))
+}
+
+#[axum::debug_handler]
+async fn post_alert_resolve(
+    State(api): State<WebState>,
+    Path(alert_id): Path<i64>,
+    RunAccess(user): RunAccess,
+) -> Result<ApiResponse, ApiError> {
+    if !api.alert_repository.resolve(alert_id, Some(&user.email)).await? {
+        return Err(alert_not_open(&api, alert_id, "open").await?);
+    }
+    api.audit_repository.record(&user.email, "alert.resolve", &alert_id.to_string(), None).await?;
+    let alert = api.alert_repository.get_alert(alert_id).await?;
+    Ok(ApiResponse::data(serde_json::to_value(alert)?))
+}
+
+/// Why an alert couldn't be acknowledged or resolved: it doesn't exist, or it isn't `expected` anymore.
+async fn alert_not_open(api: &WebState, alert_id: i64, expected: &str) -> Result<ApiError, Error> {
+    Ok(match api.alert_repository.get_alert(alert_id).await? {
+        Some(alert) => ApiError::conflict(&format!("Alert is {}, not {}", alert.status, expected)),
+        None => ApiError::not_found("Alert not found"),
+    })
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct AuditLogParams {
+    limit: Option<i64>,
+}
+
+#[axum::debug_handler]
+async fn get_audit_log(
+    State(api): State<WebState>,
+    Query(params): Query<AuditLogParams>,
+    user: User,
+) -> Result<ApiResponse, ApiError> {
+    if !user.is_admin() {
+        return Err(ApiError::forbidden("Only admins can read the audit log"));
+    }
+    let entries = api.audit_repository.get_entries(params.limit.unwrap_or(100)).await?;
+    Ok(ApiResponse::data(serde_json::to_value(entries)?))
+}
+
+const WORKSPACE_EDIT_ADMIN_ONLY: &str = "Only admins can edit the workspace";
+
+#[axum::debug_handler]
+async fn get_workspace_files(
+    State(api): State<WebState>,
+    user: User,
+) -> Result<ApiResponse, ApiError> {
+    if !user.is_admin() {
+        return Err(ApiError::forbidden(WORKSPACE_EDIT_ADMIN_ONLY));
+    }
+    let workspace = &api.projects.default_project().workspace;
+    Ok(ApiResponse::data(json!({
+        "files": workspace.list_files(),
+        "revision": workspace.get_revision(),
+    })))
+}
+
+#[axum::debug_handler]
+async fn get_workspace_file(
+    State(api): State<WebState>,
+    Path(path): Path<String>,
+    user: User,
+) -> Result<ApiResponse, ApiError> {
+    if !user.is_admin() {
+        return Err(ApiError::forbidden(WORKSPACE_EDIT_ADMIN_ONLY));
+    }
+    let workspace = &api.projects.default_project().workspace;
+    let file_path = WorkspaceServer::editable_path(&path).map_err(|e| ApiError::bad_request(&e.to_string()))?;
+    let content = workspace.read_file(&file_path).map_err(|_| ApiError::not_found("File not found"))?;
+    Ok(ApiResponse::data(json!({
+        "path": path,
+        "content": content,
+        "revision": workspace.get_revision(),
+    })))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct WorkspaceFileEdit {
+    content: String,
+    message: Option<String>,
+    /// The revision the edit is based on; refused if the workspace moved on since
+    revision: Option<String>,
+}
+
+/// Writes a workspace file and commits and pushes it as the user, if the workflows still validate.
+#[axum::debug_handler]
+async fn put_workspace_file(
+    State(api): State<WebState>,
+    Path(path): Path<String>,
+    user: User,
+    Json(edit): Json<WorkspaceFileEdit>,
+) -> Result<ApiResponse, ApiError> {
+    if !user.is_admin() {
+        return Err(ApiError::forbidden(WORKSPACE_EDIT_ADMIN_ONLY));
+    }
+    let workspace = &api.projects.default_project().workspace;
+    let file_path = WorkspaceServer::editable_path(&path).map_err(|e| ApiError::bad_request(&e.to_string()))?;
+    if edit.revision.is_some() && edit.revision != workspace.get_revision() {
+        return Err(ApiError::conflict("The workspace changed since it was loaded, reload and try again"));
+    }
+    workspace.validate_edit(&file_path, &edit.content)
+        .map_err(|e| ApiError::bad_request(&format!("Invalid workflows: {:#}", e)))?;
+
+    let message = edit.message.filter(|m| !m.trim().is_empty()).unwrap_or_else(|| format!("Update {}", path));
+    let author = CommitAuthor {
+        name: user.name.clone().unwrap_or_else(|| user.email.clone()),
+        email: user.email.clone(),
+    };
+    let revision = workspace.commit_file(file_path, edit.content, message, author, edit.revision).await?;
+    Ok(ApiResponse::data(json!({"revision": revision})))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct WorkspaceValidation {
+    /// Workflow files to validate in place of the current ones, by path relative to the workspace
+    #[serde(default)]
+    files: HashMap<String, String>,
+}
+
+/// Validates the workflows of the workspace, or as they would be with the uploaded files, e.g. in CI before a push.
+/// Problems are returned with the file and entry they are in, the workspace itself is untouched.
+#[axum::debug_handler(state = WebState)]
+async fn post_workspace_validate(
+    SelectedProject(project): SelectedProject,
+    RunAccess(user): RunAccess,
+    validation: Option<Json<WorkspaceValidation>>,
+) -> Result<ApiResponse, ApiError> {
+    // Loading the workflows decrypts their sops files, so it takes as much as running an action
+    if !user.can_run_project(project.acl.as_ref()) || !user.can_run_action() {
+        return Err(ApiError::forbidden("Only users who may run actions of the project can validate its workflows"));
+    }
+    let mut files = Vec::new();
+    for (path, content) in validation.map(|Json(validation)| validation.files).unwrap_or_default() {
+        let file_path = WorkspaceServer::editable_path(&path).map_err(|e| ApiError::bad_request(&e.to_string()))?;
+        if !file_path.starts_with(".workflows") {
+            return Err(ApiError::bad_request(&format!("'{}' is not a workflow file", path)));
+        }
+        files.push((file_path, content));
+    }
+    let workspace = project.workspace.clone();
+    let errors = tokio::task::spawn_blocking(move || workspace.validate_files(&files)).await??;
+    Ok(ApiResponse::data(json!({
+        "valid": errors.is_empty(),
+        "errors": errors,
+        "revision": project.workspace.get_revision(),
+    })))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct RunParams {
+    /// Lets admins run a paused task anyway
+    #[serde(default)]
+    override_pause: bool,
+    /// Adds the job to an existing job group, e.g. one batch of a fan-out
+    group: Option<Uuid>,
+}
+
+/// Job group a run requested by a user joins.
+enum RunGroup {
+    /// The group named by the request, if any
+    Requested(Option<Uuid>),
+    /// The group of the job being re-run or resumed, started for it if it has none
+    Rerun { job_id: Uuid, group_id: Option<Uuid>, name: String },
+}
+
+impl RunGroup {
+    fn rerun_of(job: &Job) -> Self {
+        RunGroup::Rerun {
+            job_id: job.job_id,
+            group_id: job.group_id,
+            name: job.task.clone().or_else(|| job.action.clone()).unwrap_or_default(),
+        }
+    }
+
+    async fn resolve(self, api: &WebState) -> Result<Option<Uuid>, ApiError> {
+        match self {
+            RunGroup::Requested(None) => Ok(None),
+            RunGroup::Requested(Some(group_id)) => match api.group_repository.get_group(&group_id).await? {
+                Some(_) => Ok(Some(group_id)),
+                None => Err(ApiError::bad_request("Job group not found")),
+            },
+            RunGroup::Rerun { group_id: Some(group_id), .. } => Ok(Some(group_id)),
+            RunGroup::Rerun { job_id, group_id: None, name } => {
+                let group = api.group_repository.create_group(&name, "rerun").await?;
+                // A concurrent re-run may have grouped the job first
+                Ok(Some(api.group_repository.join_group(&job_id, &group.group_id).await?.unwrap_or(group.group_id)))
+            }
+        }
+    }
+}
+
+#[axum::debug_handler]
+async fn put_job(
+    State(api): State<WebState>,
+    Query(params): Query<RunParams>,
+    RunAccess(user): RunAccess,
     Json(job): Json<JobRequest>,
 ) -> Result<ApiResponse, ApiError> {
-    let job_id = api.job_repository.enqueue_job(&job, "user", None).await?;
+    let job_id = enqueue_for_user(&api, &user, &params, job, "user", None, RunGroup::Requested(params.group)).await?;
     Ok(ApiResponse::data(serde_json::to_value(job_id)?))
 }
 
+/// Runs a task or action of the project in the path, whatever project the request names.
 #[axum::debug_handler]
-async fn get_job_sse(
+async fn put_project_job(
+    State(api): State<WebState>,
+    SelectedProject(project): SelectedProject,
+    Query(params): Query<RunParams>,
+    RunAccess(user): RunAccess,
+    Json(mut job): Json<JobRequest>,
+) -> Result<ApiResponse, ApiError> {
+    job.project = project.job_project();
+    let job_id = enqueue_for_user(&api, &user, &params, job, "user", None, RunGroup::Requested(params.group)).await?;
+    Ok(ApiResponse::data(serde_json::to_value(job_id)?))
+}
+
+/// Queues a new job with the task (or action) and input of a finished one, e.g. to retry a failed run.
+#[axum::debug_handler]
+async fn post_job_rerun(
     State(api): State<WebState>,
     Path(job_id): Path<String>,
-    _user: User,
-) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    Query(params): Query<RunParams>,
+    RunAccess(user): RunAccess,
+) -> Result<ApiResponse, ApiError> {
+    // The stored input, secrets included, is reused as is
+    let original = api.job_repository.get_job(&job_id).await?;
+    if original.deleted.is_some() {
+        return Err(ApiError::not_found("Job not found"));
+    }
+    if !api.can_view_task(&user, &original.project_id, original.task.as_deref()) {
+        return Err(ApiError::forbidden("You are not allowed to view this job"));
+    }
+    if original.end_datetime.is_none() {
+        return Err(ApiError::conflict("Only finished jobs can be re-run"));
+    }
+    let group = RunGroup::rerun_of(&original);
+    let job = JobRequest {
+        spec: JobSpec::new(original.task, original.action).map_err(|e| ApiError::bad_request(&e))?,
+        input: original.input,
+        uuid: None,
+        priority: Some(original.priority),
+        resume: None,
+        traceparent: None,
+        project: (original.project_id != DEFAULT_PROJECT).then_some(original.project_id),
+    };
+    let new_job_id = enqueue_for_user(&api, &user, &params, job, "rerun", Some(&job_id), group).await?;
+    info!("User {} re-ran job {} as {}", user.email, job_id, new_job_id);
+    Ok(ApiResponse::data(serde_json::to_value(new_job_id)?))
+}
+
+/// Queues a new run of a failed task that takes over the outputs of the steps that completed,
+/// so it starts at the step that failed.
+#[axum::debug_handler]
+async fn post_job_resume(
+    State(api): State<WebState>,
+    Path(job_id): Path<String>,
+    Query(params): Query<RunParams>,
+    RunAccess(user): RunAccess,
+) -> Result<ApiResponse, ApiError> {
+    let original = api.job_repository.get_job(&job_id).await?;
+    if original.deleted.is_some() {
+        return Err(ApiError::not_found("Job not found"));
+    }
+    if !api.can_view_task(&user, &original.project_id, original.task.as_deref()) {
+        return Err(ApiError::forbidden("You are not allowed to view this job"));
+    }
+    let group = RunGroup::rerun_of(&original);
+    let Some(task) = original.task else {
+        return Err(ApiError::bad_request("Only task jobs can be resumed, re-run the action instead"));
+    };
+    if original.success != Some(false) {
+        return Err(ApiError::conflict("Only failed jobs can be resumed"));
+    }
+    // Skipped steps are evaluated again, their condition may depend on the failed step
+    let steps = original.steps.into_iter()
+        .filter(|step| step.success && step.status.as_deref() != Some(STATUS_SKIPPED))
+        .map(|step| (step.name, step.output))
+        .collect();
+    let job = JobRequest {
+        spec: JobSpec::Task { name: task },
+        input: original.input,
+        uuid: None,
+        priority: Some(original.priority),
+        resume: Some(ResumeState { job_id: job_id.clone(), steps }),
+        traceparent: None,
+        project: (original.project_id != DEFAULT_PROJECT).then_some(original.project_id),
+    };
+    let new_job_id = enqueue_for_user(&api, &user, &params, job, "resume", Some(&job_id), group).await?;
+    info!("User {} resumed job {} as {}", user.email, job_id, new_job_id);
+    Ok(ApiResponse::data(serde_json::to_value(new_job_id)?))
+}
 
+/// Checks a run requested by a user the same way for new runs and re-runs, then queues it.
+async fn enqueue_for_user(api: &WebState, user: &User, params: &RunParams, mut job: JobRequest, source_type: &str, source_id: Option<&str>, group: RunGroup) -> Result<String, ApiError> {
+    if !api.can_run(user, &job) {
+        return Err(ApiError::forbidden("You are not allowed to run this"));
+    }
+    if let Some(pause) = api.task_pause(&job).await? {
+        if !(params.override_pause && user.is_admin()) {
+            return Err(ApiError::conflict(&format!("Task '{}' is paused", pause.task_id)));
+        }
+        info!("User {} is running paused task '{}'", user.email, pause.task_id);
+    }
+    api.check_action_sunset(&job).map_err(|e| ApiError::bad_request(&e.to_string()))?;
+    api.validate_input(&mut job).map_err(|e| ApiError::bad_request(&e.to_string()))?;
+    let workspace = &api.projects.of_job(&job).map_err(|e| ApiError::bad_request(&e.to_string()))?.workspace;
+    let rate_limit = workspace.rate_limit_for(&job).map_err(|e| ApiError::bad_request(&e.to_string()))?;
+    let group_id = group.resolve(api).await?;
+    Ok(api.job_repository.enqueue_job(&job, source_type, source_id, workspace.get_revision().as_deref(), rate_limit.as_ref(), group_id.as_ref()).await?)
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct JobGroupRequest {
+    name: String,
+}
+
+/// Starts a job group that runs can then join with `?group=`, e.g. to follow a fan-out as one run.
+#[axum::debug_handler]
+async fn post_group(
+    State(api): State<WebState>,
+    RunAccess(_user): RunAccess,
+    Json(request): Json<JobGroupRequest>,
+) -> Result<ApiResponse, ApiError> {
+    let name = request.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::bad_request("Missing group name"));
+    }
+    let group = api.group_repository.create_group(name, "user").await?;
+    Ok(ApiResponse::data(serde_json::to_value(group)?))
+}
+
+/// The jobs of a group the user may view, with their counts by status and the status of the group as a whole.
+#[axum::debug_handler]
+async fn get_group(
+    State(api): State<WebState>,
+    Path(group_id): Path<Uuid>,
+    ReadAccess(user): ReadAccess,
+) -> Result<ApiResponse, ApiError> {
+    let Some(group) = api.group_repository.get_group(&group_id).await? else {
+        return Err(ApiError::not_found("Job group not found"));
+    };
+    let mut jobs = api.group_repository.get_group_jobs(&group_id).await?;
+    jobs.retain(|job| api.can_view_task(&user, &job.project_id, job.task.as_deref()));
+    jobs.iter_mut().for_each(|job| api.mask_secret_inputs(job));
+    let status = JobGroupStatus::of(&jobs);
+    Ok(ApiResponse::data(json!({"group": group, "status": status, "jobs": jobs})))
+}
+
+/// Sends the job itself and its stored logs first, then live events. A client reconnecting with
+/// `Last-Event-ID` is only sent the log lines it missed.
+#[axum::debug_handler]
+async fn get_job_sse(
+    State(api): State<WebState>,
+    Path(job_id): Path<String>,
+    ReadAccess(user): ReadAccess,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
     debug!("Received SSE connection for job {}", job_id);
 
+    // Subscribe before reading the job and its logs, so nothing is missed in between. The channel is
+    // released when this is dropped, also when the job can't be read.
+    let live = JobChannel {
+        inner: Box::pin(BroadcastStream::new(api.job_events.subscribe(&job_id))),
+        job_id: job_id.clone(),
+        events: api.job_events.clone(),
+    };
 
-    let rx = {
-        let mut channels = api.job_channels.lock().unwrap();
-        if let Some(tx) = channels.get(&job_id) {
-            tx.subscribe()
-        } else {
-            let (tx, rx) = broadcast::channel(100);
-            channels.insert(job_id.clone(), tx);
-            rx
+    let mut job = get_visible_job(&api, &user, &job_id).await?;
+    add_revision_info(&api, &mut job);
+    let mut cursor = LogCursor::from_headers(&headers);
+    // Counted off while skipping, apart from the cursor that ends up in the event ids
+    let mut seen = cursor.clone();
+    let mut replay = vec![Event::default().event("job").data(serde_json::to_string(&job)?)];
+    let stream_names = std::iter::once(None).chain(job.steps.iter().map(|step| Some(step.name.as_str())));
+    for step_name in stream_names {
+        let mut logs = replay_logs(&api, &job, step_name).await?;
+        seen.skip_seen(step_name, &mut logs);
+        cursor.advance(step_name, &logs);
+        if !logs.is_empty() {
+            replay.push(cursor.log_event(step_name, logs));
         }
-    };
+    }
 
-    let stream = BroadcastStream::new(rx).then(|result| async move {
-        match result {
-            Ok(msg) => {
-                // Perform async operations here if needed (e.g., async serialization in the future)
-                let data = serde_json::to_string(&msg.data).unwrap(); // Currently sync, but could be async
-                Ok(Event::default().event(msg.event_name).data(data))
-            }
-            Err(e) => {
-                error!("BroadcastStream error: {:?}", e); // Log for debugging
-                // Instead of dropping, you could return an error event or retry logic here
-                Ok(Event::default().data(format!("Error: {:?}", e))) // Example: Send error as an event
-            }
+    // Live lines the replay already covered are dropped
+    let mut replayed = cursor.clone();
+    let live = live.filter_map(move |result| match result {
+        Ok(JobEvent { event_name, data }) => {
+            let Some(logs) = data.get("logs") else {
+                return Some(Ok(Event::default().event(event_name).data(data.to_string())));
+            };
+            let step_name = data.get("step_name").and_then(|s| s.as_str());
+            let mut logs: Vec<LogEntry> = serde_json::from_value(logs.clone()).unwrap_or_default();
+            replayed.skip_seen(step_name, &mut logs);
+            cursor.advance(step_name, &logs);
+            (!logs.is_empty()).then(|| Ok(cursor.log_event(step_name, logs)))
+        }
+        Err(e) => {
+            error!("BroadcastStream error: {:?}", e);
+            Some(Ok(Event::default().data(format!("Error: {:?}", e))))
         }
     });
 
-    let pinned = Box::pin(stream);
+    Ok(Sse::new(futures_util::stream::iter(replay.into_iter().map(Ok)).chain(live))
+        .keep_alive(axum::response::sse::KeepAlive::default()))
+}
 
-    let wrapped_stream = JobChannel {
-        inner: pinned,
-        job_id: job_id.clone(),
-        channels: Arc::clone(&api.job_channels),
-    };
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum JobWsRequest {
+    /// Re-sends the stored logs of the job, or of one of its steps
+    Backfill { step_name: Option<String> },
+}
 
-    Sse::new(wrapped_stream).keep_alive(axum::response::sse::KeepAlive::default())
+/// Streams the same events as the SSE endpoint over a WebSocket, as `{"event": ..., "data": ...}` messages.
+/// On connect the job itself and all stored logs are sent first, followed by a `backfill_done` event,
+/// after which live events are forwarded. Live log lines already covered by the backfill are dropped.
+#[axum::debug_handler]
+async fn get_job_ws(
+    State(api): State<WebState>,
+    Path(job_id): Path<String>,
+    ReadAccess(user): ReadAccess,
+    ws: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    get_visible_job(&api, &user, &job_id).await?;
+    Ok(ws.on_upgrade(move |socket| async move {
+        if let Err(e) = stream_job_ws(&api, &job_id, socket).await {
+            debug!("WebSocket for job {} closed: {}", job_id, e);
+        }
+        api.job_events.release(&job_id, 0);
+    }))
 }
 
-pub async fn send_sse_event(api: &WebState, job_id: &str, name: &str, data: Value) -> Result<(), Error> {
-    let channels = api.job_channels.lock().map_err(|_| anyhow!("Could not lock job channels"))?;
-    if let Some(tx) = channels.get(job_id) {
-        let event = JobEvent {
-            event_name: name.to_string(),
-            data
-        };
-        let _ = tx.send(event);
+async fn stream_job_ws(api: &WebState, job_id: &str, mut socket: WebSocket) -> Result<(), Error> {
+    // Subscribe before reading the stored logs, so nothing is missed in between
+    let mut rx = api.job_events.subscribe(job_id);
+
+    let mut job = api.job_repository.get_job(job_id).await?;
+    api.mask_secret_inputs(&mut job);
+    add_revision_info(api, &mut job);
+    send_ws_event(&mut socket, "job", serde_json::to_value(&job)?).await?;
+
+    let mut backfilled = LogCursor::default();
+    backfill_ws_logs(api, &job, None, &mut socket, &mut backfilled).await?;
+    for step in &job.steps {
+        backfill_ws_logs(api, &job, Some(step.name.clone()), &mut socket, &mut backfilled).await?;
+    }
+    send_ws_event(&mut socket, "backfill_done", json!({})).await?;
+
+    loop {
+        tokio::select! {
+            event = rx.recv() => match event {
+                Ok(JobEvent { event_name, mut data }) => {
+                    if let Some(logs) = data.get("logs").cloned() {
+                        let step_name = data.get("step_name").and_then(|s| s.as_str());
+                        let mut logs: Vec<LogEntry> = serde_json::from_value(logs)?;
+                        backfilled.skip_seen(step_name, &mut logs);
+                        if logs.is_empty() {
+                            continue;
+                        }
+                        data["logs"] = serde_json::to_value(logs)?;
+                    }
+                    send_ws_event(&mut socket, &event_name, data).await?;
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    send_ws_event(&mut socket, "lagged", json!({"skipped": skipped})).await?;
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<JobWsRequest>(&text) {
+                    Ok(JobWsRequest::Backfill { step_name }) => {
+                        backfilled.forget(step_name.as_deref());
+                        backfill_ws_logs(api, &job, step_name, &mut socket, &mut backfilled).await?;
+                    }
+                    Err(e) => send_ws_event(&mut socket, "error", json!({"message": e.to_string()})).await?,
+                },
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+            },
+        }
     }
+}
+
+async fn backfill_ws_logs(
+    api: &WebState,
+    job: &Job,
+    step_name: Option<String>,
+    socket: &mut WebSocket,
+    backfilled: &mut LogCursor,
+) -> Result<(), Error> {
+    let logs = replay_logs(api, job, step_name.as_deref()).await?;
+    backfilled.advance(step_name.as_deref(), &logs);
+    match step_name {
+        Some(step_name) => send_ws_event(socket, "step_logs", json!({"step_name": step_name, "logs": logs})).await,
+        None => send_ws_event(socket, "logs", json!({"logs": logs})).await,
+    }
+}
+
+/// The stored logs of a job or one of its steps, none yet for a job still queued or running.
+async fn replay_logs(api: &WebState, job: &Job, step_name: Option<&str>) -> Result<Vec<LogEntry>, Error> {
+    match stored_logs(api, &job.job_id.to_string(), step_name).await {
+        Ok(logs) => Ok(logs),
+        // Logs are only archived once the job ends, before that a missing file means nothing was logged yet
+        Err(e) if job.end_datetime.is_none() => {
+            debug!("No stored logs for job {}, step {:?}: {}", job.job_id, step_name, e);
+            Ok(Vec::new())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn stored_logs(api: &WebState, job_id: &str, step_name: Option<&str>) -> Result<Vec<LogEntry>, Error> {
+    api.log_repository.get_logs(job_id, step_name).await?
+        .collect::<Vec<Result<LogEntry, Error>>>()
+        .await
+        .into_iter()
+        .collect()
+}
+
+async fn send_ws_event(socket: &mut WebSocket, event: &str, data: Value) -> Result<(), Error> {
+    let message = json!({"event": event, "data": data});
+    socket.send(Message::Text(message.to_string().into())).await?;
     Ok(())
 }
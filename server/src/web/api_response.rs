@@ -1,6 +1,7 @@
 use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
 use serde_json::{json, Value};
+use std::time::Duration;
 
 pub struct ApiResponse {
     pub status: StatusCode,
@@ -72,6 +73,45 @@ impl ApiError {
         }
     }
 
+    pub fn bad_request(msg: &str) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            success: false,
+            error: Some(anyhow::anyhow!(msg.to_string())),
+            ..Default::default()
+        }
+    }
+
+    pub fn forbidden(msg: &str) -> Self {
+        Self {
+            status: StatusCode::FORBIDDEN,
+            success: false,
+            error: Some(anyhow::anyhow!(msg.to_string())),
+            ..Default::default()
+        }
+    }
+
+    pub fn conflict(msg: &str) -> Self {
+        Self {
+            status: StatusCode::CONFLICT,
+            success: false,
+            error: Some(anyhow::anyhow!(msg.to_string())),
+            ..Default::default()
+        }
+    }
+
+    pub fn too_many_requests(msg: &str, retry_after: Duration) -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RETRY_AFTER, HeaderValue::from((retry_after.as_secs_f64().ceil() as u64).max(1)));
+        Self {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            success: false,
+            error: Some(anyhow::anyhow!(msg.to_string())),
+            headers,
+            ..Default::default()
+        }
+    }
+
     pub fn not_found(msg: &str) -> Self {
         Self {
             status: StatusCode::NOT_FOUND,
@@ -2,7 +2,7 @@ use axum_cookie::prelude::*;
 use axum_cookie::cookie::Cookie;
 use std::collections::HashMap;
 use anyhow::{anyhow, Error};
-use axum::routing::{get, post};
+use axum::routing::{delete, get, post};
 use serde_json::{json, Value};
 use axum::{
     extract::{Path, State},
@@ -14,10 +14,12 @@ use axum::http::request::Parts;
 use axum::response::IntoResponse;
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
-use crate::auth::{AuthResponse, User};
+use crate::auth::{AuthResponse, TokenScope, User, API_TOKEN_PREFIX};
 use crate::web::api_response::{ApiResponse, ApiError};
-use crate::web::WebState;
+use crate::web::{access_log, WebState};
+use crate::web::openapi::{op, Auth, Operation};
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 use tracing::{error, info};
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -37,9 +39,31 @@ pub fn get_routes() -> Router<WebState> {
         .route("/api/auth/refresh", post(refresh_token))
         .route("/api/auth/logout", get(logout))
         .route("/api/auth/info", get(user_info))
+        .route("/api/tokens", get(get_api_tokens).post(post_api_token))
+        .route("/api/tokens/{:token_id}", delete(delete_api_token))
         .layer(CookieLayer::default())
 }
 
+pub fn get_operations() -> Vec<Operation> {
+    vec![
+        op("get", "/api/auth/providers", "Auth", "Login providers").data_described("Providers by id"),
+        op("post", "/api/auth/{:provider_id}/login", "Auth", "Logs in with the credentials the provider asks for, e.g. `email` and `password`")
+            .body::<HashMap<String, String>>()
+            .data_described("The `access_token` and the `user`, with a refresh token cookie; or a `redirect` URL for OIDC providers"),
+        op("get", "/auth/{:provider_id}/callback", "Auth", "Where OIDC providers send the user back to after login")
+            .status("307", "Redirect to the UI, with a refresh token cookie"),
+        op("post", "/api/auth/refresh", "Auth", "A new access token for the refresh token cookie")
+            .data_described("The `access_token` and the `user`"),
+        op("get", "/api/auth/logout", "Auth", "Logs out and clears the refresh token cookie").auth(Auth::User).data_described("Empty object"),
+        op("get", "/api/auth/info", "Auth", "The logged in user and what they may do").auth(Auth::User)
+            .data_described("The user as `data` and their `permissions`"),
+        op("get", "/api/tokens", "Tokens", "API tokens of the user").auth(Auth::User).data_described("Tokens, without their secret"),
+        op("post", "/api/tokens", "Tokens", "Creates an API token").auth(Auth::User).body::<ApiTokenRequest>()
+            .data_described("The secret, only shown in this response, as `token` and the token as `info`"),
+        op("delete", "/api/tokens/{:token_id}", "Tokens", "Revokes an API token").auth(Auth::User).data_described("Empty object"),
+    ]
+}
+
 #[axum::debug_handler]
 async fn get_providers(
     State(state): State<WebState>,
@@ -60,7 +84,7 @@ async fn post_login(
 
     match result {
         AuthResponse::Success(user) => {
-            let jwt = state.auth_service.issue_jwt(&user.user_id, &user.email).await?;
+            let jwt = state.auth_service.issue_jwt(&user).await?;
             let (refresh_token, expiration) = state.auth_service.issue_refresh_token(&provider_id, &user.user_id).await?;
 
             let headers = refresh_token_cookie(state.public_url.scheme() == "https", refresh_token, expiration)?;
@@ -179,12 +203,38 @@ async fn refresh_token(
 
 #[axum::debug_handler]
 async fn user_info(
-    State(_state): State<WebState>,
+    State(state): State<WebState>,
     user: User,
 ) -> Result<ApiResponse, ApiError> {
+    // Lets the UI hide what the user can't do; the API enforces it regardless
+    let mut run_tasks = Vec::new();
+    let mut view_tasks = Vec::new();
+    // Tasks of other projects than the default one are listed as `<project>/<task>`
+    for project in state.projects.iter() {
+        let view_project = user.can_view_project(project.acl.as_ref());
+        let run_project = user.can_run_project(project.acl.as_ref());
+        let workflows_guard = project.workspace.workflows.read().map_err(|_| anyhow!("Could not read workspace"))?;
+        let Some(workflows) = workflows_guard.as_ref() else { continue };
+        for (id, task) in workflows.tasks.iter().flatten() {
+            if view_project && user.can_view_task(Some(task)) {
+                view_tasks.push(project.scoped_name(id));
+            }
+            if run_project && user.can_run_task(Some(task)) {
+                run_tasks.push(project.scoped_name(id));
+            }
+        }
+    }
+    run_tasks.sort();
+    view_tasks.sort();
     Ok(ApiResponse::data(json!({
         "success": true,
-        "data": user
+        "data": user,
+        "permissions": {
+            "admin": user.is_admin(),
+            "run_actions": user.can_run_action(),
+            "run_tasks": run_tasks,
+            "view_tasks": view_tasks,
+        }
     })))
 }
 
@@ -214,24 +264,129 @@ async fn logout(
 
 
 
-impl FromRequestParts<WebState> for User {
+#[derive(Deserialize, JsonSchema)]
+struct ApiTokenRequest {
+    name: String,
+    scope: TokenScope,
+    /// e.g. `90d`; tokens without it never expire
+    expires_in: Option<String>,
+}
+
+#[axum::debug_handler]
+async fn post_api_token(
+    State(state): State<WebState>,
+    user: User,
+    Json(request): Json<ApiTokenRequest>,
+) -> Result<ApiResponse, ApiError> {
+    let name = request.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::bad_request("Missing token name"));
+    }
+    let expires_at = match request.expires_in.as_deref() {
+        Some(expires_in) => {
+            let expires_in = duration_str::parse(expires_in)
+                .map_err(|e| ApiError::bad_request(&format!("Invalid expires_in: {}", e)))?;
+            Some(Utc::now() + expires_in)
+        }
+        None => None,
+    };
+    let (token, secret) = state.auth_service.create_api_token(&user.user_id, name, request.scope, expires_at).await?;
+    info!("User {} created {:?} API token '{}'", user.email, token.scope, token.name);
+    Ok(ApiResponse::data(json!({
+        "token": secret,
+        "info": token,
+    })))
+}
+
+#[axum::debug_handler]
+async fn get_api_tokens(
+    State(state): State<WebState>,
+    user: User,
+) -> Result<ApiResponse, ApiError> {
+    let tokens = state.auth_service.list_api_tokens(&user.user_id).await?;
+    Ok(ApiResponse::data(serde_json::to_value(tokens)?))
+}
+
+#[axum::debug_handler]
+async fn delete_api_token(
+    State(state): State<WebState>,
+    Path(token_id): Path<Uuid>,
+    user: User,
+) -> Result<ApiResponse, ApiError> {
+    if !state.auth_service.revoke_api_token(&user.user_id, &token_id).await? {
+        return Err(ApiError::not_found("Token not found"));
+    }
+    Ok(ApiResponse::data(json!({})))
+}
+
+fn bearer_token(parts: &Parts) -> Result<&str, &'static str> {
+    let auth_header = parts.headers
+        .get("authorization")
+        .ok_or("Missing Authorization header")?
+        .to_str()
+        .map_err(|_| "Invalid Authorization header")?;
+
+    if !auth_header.to_lowercase().starts_with("bearer ") {
+        return Err("Invalid token format");
+    }
+
+    Ok(auth_header[7..].trim())
+}
+
+/// Accepts a user JWT, or an API token with the given scope.
+async fn authenticate(parts: &mut Parts, state: &WebState, scope: TokenScope) -> Result<User, ApiError> {
+    let token = bearer_token(parts).map_err(ApiError::unauthorized)?;
+    if !token.starts_with(API_TOKEN_PREFIX) {
+        return User::from_request_parts(parts, state).await;
+    }
+    let (user, token_scope) = state.auth_service
+        .authenticate_api_token(token)
+        .await
+        .map_err(|e| ApiError::unauthorized(&format!("Invalid token: {}", e)))?;
+    access_log::record_user(&user.email);
+    if token_scope != scope {
+        return Err(ApiError::forbidden("API token does not have the required scope"));
+    }
+    Ok(user)
+}
+
+/// A user allowed on read endpoints: logged in, or using a read-only API token.
+pub struct ReadAccess(pub User);
+
+impl FromRequestParts<WebState> for ReadAccess {
     type Rejection = ApiError;
 
     async fn from_request_parts(
         parts: &mut Parts,
         state: &WebState,
     ) -> Result<Self, Self::Rejection> {
-        let auth_header = parts.headers
-            .get("authorization")
-            .ok_or(ApiError::unauthorized("Missing Authorization header"))?
-            .to_str()
-            .map_err(|_| ApiError::unauthorized("Invalid Authorization header"))?;
-
-        if !auth_header.to_lowercase().starts_with("bearer ") {
-            return Err(ApiError::unauthorized("Invalid token format"));
-        }
+        Ok(ReadAccess(authenticate(parts, state, TokenScope::Read).await?))
+    }
+}
 
-        let token = auth_header[7..].trim();
+/// A user allowed to start jobs: logged in, or using a run-only API token.
+pub struct RunAccess(pub User);
+
+impl FromRequestParts<WebState> for RunAccess {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &WebState,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(RunAccess(authenticate(parts, state, TokenScope::Run).await?))
+    }
+}
+
+/// Only accepts user JWTs, so API tokens can't manage tokens, users or server settings.
+impl FromRequestParts<WebState> for User {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &WebState,
+    ) -> Result<Self, Self::Rejection> {
+        let token = bearer_token(parts).map_err(ApiError::unauthorized)?;
 
         let claims = state.auth_service
             .decode_jwt(token)
@@ -239,12 +394,14 @@ impl FromRequestParts<WebState> for User {
 
         let user_id = Uuid::parse_str(&claims.sub)
             .map_err(|_| (ApiError::unauthorized("Invalid user ID in token")))?;
+        access_log::record_user(&claims.email);
 
 
         Ok(User {
             user_id,
             name: None,
             email: claims.email,
+            role: claims.role,
         })
     }
 }
\ No newline at end of file
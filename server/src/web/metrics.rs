@@ -0,0 +1,68 @@
+//! Prometheus scrape endpoint, and the request latency middleware feeding it.
+use std::time::Instant;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use prometheus::{Encoder, TextEncoder};
+use tracing::error;
+use crate::metrics::{DB_POOL_CONNECTIONS, DISPATCH_FREE_SLOTS, DISPATCH_WAITING_WORKERS, HTTP_REQUEST_DURATION, JOBS_QUEUED, JOBS_RUNNING, WORKERS};
+use crate::web::WebState;
+
+pub fn get_routes() -> Router<WebState> {
+    crate::metrics::init();
+    Router::new()
+        .route("/metrics", get(get_metrics))
+}
+
+/// Records the latency of the routes' requests.
+pub fn layer<S: Clone + Send + Sync + 'static>(router: Router<S>) -> Router<S> {
+    router.layer(middleware::from_fn(record_latency))
+}
+
+async fn record_latency(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request.extensions().get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_default();
+    let started = Instant::now();
+    let response = next.run(request).await;
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[method.as_str(), route.as_str(), response.status().as_str()])
+        .observe(started.elapsed().as_secs_f64());
+    response
+}
+
+async fn get_metrics(State(api): State<WebState>) -> Response {
+    if let Err(e) = refresh_gauges(&api).await {
+        error!("Failed to refresh metrics: {}", e);
+    }
+    let mut buffer = vec![];
+    if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        error!("Failed to encode metrics: {}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], buffer).into_response()
+}
+
+async fn refresh_gauges(api: &WebState) -> Result<(), anyhow::Error> {
+    let (size, idle) = api.job_repository.pool_stats();
+    DB_POOL_CONNECTIONS.with_label_values(&["idle"]).set(idle as i64);
+    DB_POOL_CONNECTIONS.with_label_values(&["in_use"]).set(size as i64 - idle as i64);
+
+    let stats = api.job_repository.get_queue_stats().await?;
+    JOBS_QUEUED.set(stats.queued);
+    JOBS_RUNNING.set(stats.running);
+
+    let workers = api.worker_repository.get_workers(api.worker_stale_after).await?;
+    for status in ["active", "idle", "stale"] {
+        WORKERS.with_label_values(&[status]).set(workers.iter().filter(|worker| worker.status == status).count() as i64);
+    }
+
+    let waiting = api.dispatcher.waiting();
+    DISPATCH_WAITING_WORKERS.set(waiting.len() as i64);
+    DISPATCH_FREE_SLOTS.set(waiting.values().sum::<usize>() as i64);
+    Ok(())
+}
@@ -0,0 +1,388 @@
+//! OpenAPI 3 document of the HTTP API, served at `/api/openapi.json` with a Swagger UI at `/api/docs`.
+//! Swagger UI is vendored in `server/swagger-ui`, the page shares its origin with the session cookies.
+//! Each route module lists its operations next to its routes; the schemas of request bodies, query parameters
+//! and responses are generated from the types the handlers use.
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    Json, Router
+};
+use schemars::{JsonSchema, Schema, SchemaGenerator};
+use schemars::generate::SchemaSettings;
+use serde_json::{json, Map, Value};
+use crate::web::WebState;
+
+type SchemaFn = fn(&mut SchemaGenerator) -> Schema;
+
+pub fn get_routes() -> Router<WebState> {
+    Router::new()
+        .route("/api/openapi.json", get(get_openapi))
+        .route("/api/docs", get(get_docs))
+        .route("/api/docs/{:name}", get(get_docs_asset))
+}
+
+/// Operations of the routes outside the route modules.
+pub fn get_operations() -> Vec<Operation> {
+    vec![
+        op("get", "/healthz", "Health", "Whether the server is up").status("200", "Up"),
+        op("get", "/readyz", "Health", "Whether the server is ready to serve requests").status("200", "Ready"),
+        op("get", "/metrics", "Health", "Prometheus metrics, if enabled in the server config")
+            .content("text/plain", "Metrics in the Prometheus text format"),
+        op("get", "/api/openapi.json", "Health", "This document").content("application/json", "OpenAPI 3 document"),
+        op("get", "/api/docs", "Health", "Swagger UI of this document").content("text/html", "Swagger UI page"),
+        op("get", "/api/docs/{:name}", "Health", "Script and stylesheet of the Swagger UI page")
+            .param("name", "`swagger-ui-bundle.js` or `swagger-ui.css`").content("application/javascript", "The asset"),
+    ]
+}
+
+/// Credentials an operation accepts, see the extractors of `web::auth` and `web::worker`.
+#[derive(Clone, Copy)]
+pub enum Auth {
+    None,
+    /// A user JWT, or an API token with the `read` scope
+    Read,
+    /// A user JWT, or an API token with the `run` scope
+    Run,
+    /// A user JWT only
+    User,
+    /// The shared worker token or a worker credential
+    Worker,
+    /// The secret of the webhook trigger, for triggers that have one
+    Webhook,
+}
+
+impl Auth {
+    fn security(self) -> Option<Value> {
+        let schemes: &[&str] = match self {
+            Auth::None => return None,
+            Auth::Read => &["userToken", "readToken"],
+            Auth::Run => &["userToken", "runToken"],
+            Auth::User => &["userToken"],
+            Auth::Worker => &["workerToken"],
+            // Triggers without a secret accept any request
+            Auth::Webhook => &["webhookTokenHeader", "webhookTokenQuery", ""],
+        };
+        Some(Value::Array(schemes.iter()
+            .map(|scheme| match scheme.is_empty() {
+                true => json!({}),
+                false => json!({ *scheme: [] }),
+            })
+            .collect()))
+    }
+}
+
+/// What an operation responds with when it succeeds.
+enum Reply {
+    /// The `{"success": true, "data": ...}` envelope of the `/api` routes, with the schema of `data`,
+    /// or a description of it when the handler builds it ad hoc
+    Data(Option<SchemaFn>, &'static str),
+    /// A bare JSON body, as the worker routes send
+    Json(SchemaFn),
+    /// Any other content type
+    Content(&'static str, &'static str),
+    /// A status without a body
+    Status(&'static str, &'static str),
+}
+
+/// An operation of the API, built with [`op`].
+pub struct Operation {
+    method: &'static str,
+    path: String,
+    tag: &'static str,
+    summary: &'static str,
+    auth: Auth,
+    query: Option<SchemaFn>,
+    params: Vec<(&'static str, &'static str, &'static str)>,
+    body: Vec<(&'static str, Option<SchemaFn>)>,
+    body_required: bool,
+    reply: Reply,
+}
+
+/// An operation on `path`, given as the axum route path.
+pub fn op(method: &'static str, path: impl Into<String>, tag: &'static str, summary: &'static str) -> Operation {
+    Operation {
+        method,
+        path: path.into(),
+        tag,
+        summary,
+        auth: Auth::None,
+        query: None,
+        params: Vec::new(),
+        body: Vec::new(),
+        body_required: true,
+        reply: Reply::Status("200", "OK"),
+    }
+}
+
+impl Operation {
+    pub fn auth(mut self, auth: Auth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Query parameters deserialized into `T`, one per field.
+    pub fn query<T: JsonSchema>(mut self) -> Self {
+        self.query = Some(T::json_schema);
+        self
+    }
+
+    /// An optional query parameter read by the handler itself.
+    pub fn param(mut self, name: &'static str, description: &'static str) -> Self {
+        self.params.push(("query", name, description));
+        self
+    }
+
+    pub fn header(mut self, name: &'static str, description: &'static str) -> Self {
+        self.params.push(("header", name, description));
+        self
+    }
+
+    /// A JSON body deserialized into `T`.
+    pub fn body<T: JsonSchema>(self) -> Self {
+        self.body_as::<T>("application/json")
+    }
+
+    /// A JSON body deserialized into `T`, which may be left out.
+    pub fn optional_body<T: JsonSchema>(mut self) -> Self {
+        self.body_required = false;
+        self.body::<T>()
+    }
+
+    /// A body of `T` sent as `content_type`, in addition to the other content types accepted.
+    pub fn body_as<T: JsonSchema>(mut self, content_type: &'static str) -> Self {
+        self.body.push((content_type, Some(SchemaGenerator::subschema_for::<T>)));
+        self
+    }
+
+    /// A body of any shape sent as `content_type`.
+    pub fn raw_body(mut self, content_type: &'static str) -> Self {
+        self.body.push((content_type, None));
+        self
+    }
+
+    /// Responds with `T` in the `/api` envelope.
+    pub fn data<T: JsonSchema>(mut self) -> Self {
+        self.reply = Reply::Data(Some(SchemaGenerator::subschema_for::<T>), "OK");
+        self
+    }
+
+    /// Responds with a value built by the handler in the `/api` envelope.
+    pub fn data_described(mut self, description: &'static str) -> Self {
+        self.reply = Reply::Data(None, description);
+        self
+    }
+
+    pub fn json<T: JsonSchema>(mut self) -> Self {
+        self.reply = Reply::Json(SchemaGenerator::subschema_for::<T>);
+        self
+    }
+
+    pub fn content(mut self, content_type: &'static str, description: &'static str) -> Self {
+        self.reply = Reply::Content(content_type, description);
+        self
+    }
+
+    pub fn status(mut self, status: &'static str, description: &'static str) -> Self {
+        self.reply = Reply::Status(status, description);
+        self
+    }
+
+    /// Path with the axum parameters, `{:job_id}` or `{*path}`, written the OpenAPI way.
+    fn openapi_path(&self) -> (String, Vec<String>) {
+        let mut names = Vec::new();
+        let segments: Vec<String> = self.path.split('/')
+            .map(|segment| match segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                Some(name) => {
+                    let name = name.trim_start_matches([':', '*']).to_string();
+                    let segment = format!("{{{}}}", name);
+                    names.push(name);
+                    segment
+                }
+                None => segment.to_string(),
+            })
+            .collect();
+        (segments.join("/"), names)
+    }
+
+    fn render(&self, generator: &mut SchemaGenerator, names: Vec<String>) -> Value {
+        let mut parameters: Vec<Value> = names.into_iter()
+            .map(|name| json!({"name": name, "in": "path", "required": true, "schema": {"type": "string"}}))
+            .collect();
+        if let Some(query) = self.query {
+            parameters.extend(query_parameters(inline(generator, query)));
+        }
+        for (location, name, description) in &self.params {
+            parameters.push(json!({"name": name, "in": location, "description": description, "schema": {"type": "string"}}));
+        }
+
+        let mut operation = json!({
+            "tags": [self.tag],
+            "summary": self.summary,
+            "responses": self.responses(generator),
+        });
+        if !parameters.is_empty() {
+            operation["parameters"] = Value::Array(parameters);
+        }
+        if !self.body.is_empty() {
+            let content: Map<String, Value> = self.body.iter()
+                .map(|(content_type, schema)| {
+                    let schema = schema.map(|schema| inline(generator, schema)).unwrap_or_else(|| json!({}));
+                    (content_type.to_string(), json!({"schema": schema}))
+                })
+                .collect();
+            operation["requestBody"] = json!({"required": self.body_required, "content": content});
+        }
+        if let Some(security) = self.auth.security() {
+            operation["security"] = security;
+        }
+        operation
+    }
+
+    fn responses(&self, generator: &mut SchemaGenerator) -> Value {
+        let plain_error = json!({
+            "description": "Error",
+            "content": {"text/plain": {"schema": {"type": "string"}}},
+        });
+        match &self.reply {
+            Reply::Data(schema, description) => {
+                let data = schema.map(|schema| inline(generator, schema)).unwrap_or_else(|| json!({}));
+                json!({
+                    "200": {
+                        "description": description,
+                        "content": {"application/json": {"schema": {
+                            "type": "object",
+                            "required": ["success", "data"],
+                            "properties": {"success": {"type": "boolean"}, "data": data},
+                        }}},
+                    },
+                    "default": {
+                        "description": "Error",
+                        "content": {"application/json": {"schema": {"$ref": "#/components/schemas/ApiError"}}},
+                    },
+                })
+            }
+            Reply::Json(schema) => json!({
+                "200": {"description": "OK", "content": {"application/json": {"schema": inline(generator, *schema)}}},
+                "default": plain_error,
+            }),
+            Reply::Content(content_type, description) => json!({
+                "200": {"description": description, "content": {*content_type: {}}},
+                "default": plain_error,
+            }),
+            Reply::Status(status, description) => json!({
+                *status: {"description": description},
+                "default": plain_error,
+            }),
+        }
+    }
+}
+
+/// Schema of a type, with the OpenAPI 3.0 transforms of the generator applied as they are to its definitions.
+fn inline(generator: &mut SchemaGenerator, schema: SchemaFn) -> Value {
+    let mut schema = schema(generator);
+    for transform in generator.transforms_mut() {
+        transform.transform(&mut schema);
+    }
+    schema.to_value()
+}
+
+/// A query parameter per property of a query struct's schema.
+fn query_parameters(schema: Value) -> Vec<Value> {
+    let required: Vec<&str> = schema["required"].as_array().into_iter().flatten().filter_map(Value::as_str).collect();
+    let Some(properties) = schema["properties"].as_object() else { return Vec::new() };
+    properties.iter()
+        .map(|(name, property)| {
+            let mut property = property.clone();
+            let description = property.as_object_mut().and_then(|property| property.remove("description"));
+            let mut parameter = json!({"name": name, "in": "query", "required": required.contains(&name.as_str()), "schema": property});
+            if let Some(description) = description {
+                parameter["description"] = description;
+            }
+            parameter
+        })
+        .collect()
+}
+
+/// The OpenAPI document of the given operations.
+pub fn document(operations: &[Operation], server_url: &str) -> Value {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    let mut paths = Map::new();
+    for operation in operations {
+        let (path, names) = operation.openapi_path();
+        let rendered = operation.render(&mut generator, names);
+        if let Value::Object(item) = paths.entry(path).or_insert_with(|| json!({})) {
+            item.insert(operation.method.to_string(), rendered);
+        }
+    }
+    let mut schemas = generator.take_definitions(true);
+    schemas.insert("ApiError".to_string(), json!({
+        "type": "object",
+        "required": ["success", "error"],
+        "properties": {"success": {"type": "boolean"}, "error": {"type": "string"}},
+    }));
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Strøm API",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "The `/api` routes are used by the UI and integrations and answer with `{\"success\": true, \"data\": ...}`. \
+                The worker routes are used by workers and runners to fetch jobs and report on them.",
+        },
+        "servers": [{"url": server_url.trim_end_matches('/')}],
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": {
+                "userToken": {"type": "http", "scheme": "bearer", "bearerFormat": "JWT", "description": "Access token of a logged in user, from `/api/auth/{provider_id}/login`"},
+                "readToken": {"type": "http", "scheme": "bearer", "description": "API token with the `read` scope, from `/api/tokens`"},
+                "runToken": {"type": "http", "scheme": "bearer", "description": "API token with the `run` scope, from `/api/tokens`"},
+                "workerToken": {"type": "http", "scheme": "bearer", "description": "The worker token of the server config, or a worker credential"},
+                "webhookTokenHeader": {"type": "apiKey", "in": "header", "name": "X-Webhook-Token", "description": "Secret of the webhook trigger"},
+                "webhookTokenQuery": {"type": "apiKey", "in": "query", "name": "token", "description": "Secret of the webhook trigger"},
+            },
+        },
+    })
+}
+
+#[axum::debug_handler]
+async fn get_openapi(State(api): State<WebState>) -> Json<Value> {
+    Json(document(&super::get_operations(), api.public_url.as_str()))
+}
+
+/// Release of the vendored Swagger UI
+const SWAGGER_UI_VERSION: &str = "5.17.14";
+const SWAGGER_UI_BUNDLE: &[u8] = include_bytes!("../../swagger-ui/swagger-ui-bundle.js");
+const SWAGGER_UI_CSS: &[u8] = include_bytes!("../../swagger-ui/swagger-ui.css");
+
+#[axum::debug_handler(state = WebState)]
+async fn get_docs() -> Html<String> {
+    Html(format!(r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>Strøm API</title>
+  <link rel="stylesheet" href="/api/docs/swagger-ui.css?v={version}">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="/api/docs/swagger-ui-bundle.js?v={version}"></script>
+  <script>
+    window.ui = SwaggerUIBundle({{ url: "/api/openapi.json", dom_id: "#swagger-ui" }});
+  </script>
+</body>
+</html>
+"##, version = SWAGGER_UI_VERSION))
+}
+
+#[axum::debug_handler(state = WebState)]
+async fn get_docs_asset(Path(name): Path<String>) -> Response {
+    let (content_type, body) = match name.as_str() {
+        "swagger-ui-bundle.js" => ("application/javascript", SWAGGER_UI_BUNDLE),
+        "swagger-ui.css" => ("text/css", SWAGGER_UI_CSS),
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+    ([(header::CONTENT_TYPE, content_type), (header::CACHE_CONTROL, "public, max-age=86400")], body).into_response()
+}
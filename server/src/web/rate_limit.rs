@@ -0,0 +1,289 @@
+//! Token bucket rate limits per client and route class, against runaway clients and password guessing.
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, HeaderMap, Uri};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use tracing::warn;
+use uuid::Uuid;
+use crate::auth::API_TOKEN_PREFIX;
+use crate::server_config::{RateLimit, RateLimitConfig};
+use crate::web::api_response::ApiError;
+use crate::web::WebState;
+
+/// Buckets are only swept once there are this many, dropping the ones that refilled completely
+const SWEEP_THRESHOLD: usize = 10_000;
+/// Tokens checked against the database are trusted this long, so requests aren't checked twice each
+const IDENTITY_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum RouteClass {
+    Global,
+    Auth,
+    Run,
+    Worker,
+}
+
+impl RouteClass {
+    fn of(path: &str) -> Option<RouteClass> {
+        let segments: Vec<&str> = path.split('/').collect();
+        if matches!(segments.as_slice(), ["", "api", "auth", _, "login"] | ["", "auth", _, "callback"]) {
+            Some(RouteClass::Auth)
+        } else if path == "/api/run" || path.starts_with("/hooks/")
+            || (path.starts_with("/api/jobs/") && (path.ends_with("/rerun") || path.ends_with("/resume"))) {
+            Some(RouteClass::Run)
+        } else if path.starts_with("/jobs") || path.starts_with("/workers/") || path.starts_with("/files/") {
+            Some(RouteClass::Worker)
+        } else {
+            None
+        }
+    }
+}
+
+/// Who a request counts against: the authenticated user or worker, or the client IP for requests whose token
+/// doesn't authenticate, so made-up tokens don't buy fresh buckets.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum ClientKey {
+    User(Uuid),
+    /// A worker using the shared worker token, by the id it sends; the fleet sharing the token doesn't share buckets
+    Worker(String),
+    /// Hash of a worker credential's token, so tokens aren't kept around
+    WorkerCredential(u64),
+    Ip(IpAddr),
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let rate = limit.requests as f64 / limit.per.as_secs_f64();
+        self.tokens = (self.tokens + now.duration_since(self.updated).as_secs_f64() * rate).min(limit.requests as f64);
+        self.updated = now;
+    }
+
+    /// Takes a token, or tells how long until one is available.
+    fn take(&mut self, limit: &RateLimit) -> Result<(), Duration> {
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            let rate = limit.requests as f64 / limit.per.as_secs_f64();
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+}
+
+/// The token buckets of all clients and route classes.
+#[derive(Clone)]
+struct Buckets {
+    config: Arc<RateLimitConfig>,
+    buckets: Arc<Mutex<HashMap<(RouteClass, ClientKey), Bucket>>>,
+}
+
+impl Buckets {
+    fn new(config: &RateLimitConfig) -> Self {
+        Self { config: Arc::new(config.clone()), buckets: Default::default() }
+    }
+
+    fn limit(&self, class: RouteClass) -> Option<&RateLimit> {
+        match class {
+            RouteClass::Global => self.config.global.as_ref(),
+            RouteClass::Auth => self.config.auth.as_ref(),
+            RouteClass::Run => self.config.run.as_ref(),
+            RouteClass::Worker => self.config.worker.as_ref(),
+        }
+        .filter(|limit| limit.requests > 0 && !limit.per.is_zero())
+    }
+
+    /// Takes a token from every bucket that applies, or none if one of them is empty.
+    fn check(&self, limits: &[(RouteClass, ClientKey)]) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= SWEEP_THRESHOLD {
+            buckets.retain(|(class, _), bucket| self.limit(*class).is_some_and(|limit| {
+                bucket.refill(limit, now);
+                bucket.tokens < limit.requests as f64
+            }));
+        }
+
+        let mut applicable = vec![];
+        for (class, key) in limits {
+            let Some(limit) = self.limit(*class) else { continue };
+            let bucket = buckets.entry((*class, key.clone()))
+                .or_insert_with(|| Bucket { tokens: limit.requests as f64, updated: now });
+            bucket.refill(limit, now);
+            if bucket.tokens < 1.0 {
+                return bucket.take(limit);
+            }
+            applicable.push((*class, key, limit));
+        }
+        for (class, key, limit) in applicable {
+            if let Some(bucket) = buckets.get_mut(&(class, key.clone())) {
+                let _ = bucket.take(limit);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct RateLimiter {
+    buckets: Buckets,
+    state: WebState,
+    /// Clients of the tokens checked against the database lately, by token hash
+    identities: Arc<Mutex<HashMap<u64, (ClientKey, Instant)>>>,
+}
+
+impl RateLimiter {
+    /// The user or worker a bearer token authenticates, if any. Workers on the shared token that don't send
+    /// their id are left to be keyed by IP.
+    async fn identify(&self, token: &str, uri: &Uri) -> Option<ClientKey> {
+        if self.state.worker_token.as_deref() == Some(token) {
+            return shared_worker_id(uri).map(ClientKey::Worker);
+        }
+        if !token.starts_with(API_TOKEN_PREFIX) && let Ok(claims) = self.state.auth_service.decode_jwt(token) {
+            return Uuid::parse_str(&claims.sub).ok().map(ClientKey::User);
+        }
+
+        let mut hasher = DefaultHasher::new();
+        token.hash(&mut hasher);
+        let token_hash = hasher.finish();
+        let now = Instant::now();
+        {
+            let mut identities = self.identities.lock().unwrap();
+            if identities.len() >= SWEEP_THRESHOLD {
+                identities.retain(|_, (_, checked)| now.duration_since(*checked) < IDENTITY_TTL);
+            }
+            if let Some((client, checked)) = identities.get(&token_hash) && now.duration_since(*checked) < IDENTITY_TTL {
+                return Some(client.clone());
+            }
+        }
+        let client = if token.starts_with(API_TOKEN_PREFIX) {
+            let (user, _) = self.state.auth_service.authenticate_api_token(token).await.ok()?;
+            ClientKey::User(user.user_id)
+        } else if self.state.worker_repository.authenticate(token).await.unwrap_or(false) {
+            ClientKey::WorkerCredential(token_hash)
+        } else {
+            return None;
+        };
+        self.identities.lock().unwrap().insert(token_hash, (client.clone(), now));
+        Some(client)
+    }
+
+    fn client_ip(&self, headers: &HeaderMap, peer: Option<SocketAddr>) -> Option<IpAddr> {
+        let forwarded = self.buckets.config.trust_forwarded_for.then(|| {
+            headers.get("x-forwarded-for")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.split(',').next())
+                .and_then(|ip| ip.trim().parse().ok())
+        }).flatten();
+        forwarded.or(peer.map(|peer| peer.ip()))
+    }
+}
+
+/// Id a worker sends with its requests, in the `worker_id` parameter or the path of its heartbeat.
+fn shared_worker_id(uri: &Uri) -> Option<String> {
+    let from_query = uri.query().into_iter()
+        .flat_map(|query| query.split('&'))
+        .find_map(|pair| pair.strip_prefix("worker_id="))
+        .filter(|id| !id.is_empty())
+        .map(|id| id.to_string());
+    from_query.or_else(|| match uri.path().split('/').collect::<Vec<_>>().as_slice() {
+        ["", "workers", id, "heartbeat"] => Some(id.to_string()),
+        _ => None,
+    })
+}
+
+/// Wraps the routes with the configured rate limits.
+pub fn layer(router: Router<WebState>, config: &RateLimitConfig, state: &WebState) -> Router<WebState> {
+    if !config.enabled {
+        return router;
+    }
+    let limiter = RateLimiter {
+        buckets: Buckets::new(config),
+        state: state.clone(),
+        identities: Default::default(),
+    };
+    router.layer(middleware::from_fn_with_state(limiter, rate_limit))
+}
+
+async fn rate_limit(State(limiter): State<RateLimiter>, request: Request, next: Next) -> Response {
+    let peer = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(peer)| *peer);
+    let ip = limiter.client_ip(request.headers(), peer);
+    let token = request.headers().get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let identity = match token {
+        Some(token) => limiter.identify(token, request.uri()).await,
+        None => None,
+    };
+    let Some(client) = identity.or(ip.map(ClientKey::Ip)) else {
+        return next.run(request).await;
+    };
+
+    let mut limits = vec![(RouteClass::Global, client.clone())];
+    match RouteClass::of(request.uri().path()) {
+        // Login attempts carry no token, and a token mustn't buy extra attempts
+        Some(RouteClass::Auth) => {
+            if let Some(ip) = ip {
+                limits.push((RouteClass::Auth, ClientKey::Ip(ip)));
+            }
+        }
+        Some(class) => limits.push((class, client)),
+        None => {}
+    }
+
+    match limiter.buckets.check(&limits) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            warn!("Rate limit exceeded for {} {}", request.method(), request.uri().path());
+            ApiError::too_many_requests("Too many requests", retry_after).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server_config::RateLimit;
+
+    fn buckets(requests: u32) -> Buckets {
+        let limit = Some(RateLimit { requests, per: Duration::from_secs(60) });
+        Buckets::new(&RateLimitConfig { global: limit.clone(), worker: limit, ..RateLimitConfig::default() })
+    }
+
+    fn worker_request(buckets: &Buckets, uri: &str) -> Result<(), Duration> {
+        let uri: Uri = uri.parse().unwrap();
+        let client = ClientKey::Worker(shared_worker_id(&uri).unwrap());
+        buckets.check(&[(RouteClass::Global, client.clone()), (RouteClass::Worker, client)])
+    }
+
+    #[test]
+    fn test_shared_token_workers_have_own_buckets() {
+        let buckets = buckets(3);
+        for worker in ["worker-1", "worker-2", "worker-3", "worker-4"] {
+            assert!(worker_request(&buckets, &format!("/jobs/next?worker_id={}&count=1", worker)).is_ok());
+            assert!(worker_request(&buckets, &format!("/workers/{}/heartbeat", worker)).is_ok());
+            let result = format!("/jobs/0b7e7e52-8d07-4a35-9b3e-2d1b8c8e4f10/results?worker_id={}", worker);
+            assert!(worker_request(&buckets, &result).is_ok());
+        }
+        assert!(worker_request(&buckets, "/jobs/next?worker_id=worker-1").is_err());
+        assert!(worker_request(&buckets, "/jobs/next?worker_id=worker-5").is_ok());
+    }
+
+    #[test]
+    fn test_shared_worker_id() {
+        assert_eq!(shared_worker_id(&"/jobs/next?count=2&worker_id=w1".parse().unwrap()), Some("w1".to_string()));
+        assert_eq!(shared_worker_id(&"/workers/w2/heartbeat".parse().unwrap()), Some("w2".to_string()));
+        assert_eq!(shared_worker_id(&"/files/workspace.tar.gz".parse().unwrap()), None);
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::http::HeaderMap;
+use axum::routing::post;
+use axum::Router;
+use anyhow::anyhow;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use subtle::ConstantTimeEq;
+use tracing::info;
+use stroem_common::{JobRequest, JobSpec};
+use stroem_common::parameter_renderer::ParameterRenderer;
+use stroem_common::workflows_configuration::TriggerType;
+use crate::web::api_response::{ApiError, ApiResponse};
+use crate::web::{SelectedProject, WebState};
+use crate::web::openapi::{op, Auth, Operation};
+
+pub fn get_routes() -> Router<WebState> {
+    Router::new()
+        .route("/hooks/{trigger_id}", post(post_webhook))
+        .route("/projects/{project}/hooks/{trigger_id}", post(post_webhook))
+}
+
+pub fn get_operations() -> Vec<Operation> {
+    ["/hooks/{trigger_id}", "/projects/{project}/hooks/{trigger_id}"].into_iter()
+        .map(|path| op("post", path, "Webhooks", "Runs the task of a webhook trigger with the body as input, JSON or not")
+            .auth(Auth::Webhook).raw_body("application/json").raw_body("text/plain")
+            .data_described("The `job_id` of the queued job"))
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct HookPath {
+    trigger_id: String,
+}
+
+/// Body as JSON, or as a string if it isn't JSON.
+fn parse_payload(body: &Bytes) -> Value {
+    if body.is_empty() {
+        return Value::Null;
+    }
+    serde_json::from_slice(body).unwrap_or_else(|_| Value::String(String::from_utf8_lossy(body).to_string()))
+}
+
+/// Builds the job input from the trigger's `input` mapping, or passes the payload through when there is none.
+fn map_input(mapping: Option<&HashMap<String, String>>, payload: Value, headers: &HeaderMap) -> Result<Map<String, Value>, anyhow::Error> {
+    let Some(mapping) = mapping else {
+        return Ok(match payload {
+            Value::Object(map) => map,
+            Value::Null => Map::new(),
+            payload => Map::from_iter([("payload".to_string(), payload)]),
+        });
+    };
+
+    // Template paths can't contain `-`, so `X-GitHub-Event` becomes `headers.x_github_event`
+    let headers: Map<String, Value> = headers.iter()
+        .filter_map(|(name, value)| Some((name.as_str().to_lowercase().replace('-', "_"), Value::String(value.to_str().ok()?.to_string()))))
+        .collect();
+    let mut renderer = ParameterRenderer::new();
+    renderer.add_to_context(json!({"payload": payload, "headers": headers}))?;
+    match renderer.render(serde_json::to_value(mapping)?)? {
+        Value::Object(input) => Ok(input),
+        _ => Err(anyhow!("Webhook input mapping must render to an object")),
+    }
+}
+
+#[axum::debug_handler]
+async fn post_webhook(
+    State(api): State<WebState>,
+    SelectedProject(project): SelectedProject,
+    Path(HookPath { trigger_id }): Path<HookPath>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<ApiResponse, ApiError> {
+    let job = {
+        let workflows_guard = project.workspace.workflows.read().map_err(|_| anyhow!("Could not read workspace"))?;
+        let workflows = workflows_guard.as_ref().ok_or_else(|| anyhow!("Workspace not loaded"))?;
+        let trigger = workflows.get_trigger(&trigger_id)
+            .filter(|trigger| trigger.enabled.unwrap_or(true))
+            .ok_or_else(|| ApiError::not_found("Webhook not found"))?;
+        let TriggerType::Webhook { secret } = &trigger.trigger_type else {
+            return Err(ApiError::not_found("Webhook not found"));
+        };
+        if let Some(secret) = secret {
+            let token = headers.get("x-webhook-token")
+                .and_then(|value| value.to_str().ok())
+                .or(params.get("token").map(String::as_str));
+            // Compared in constant time, so the response time doesn't give the secret away
+            if !token.is_some_and(|token| bool::from(token.as_bytes().ct_eq(secret.as_bytes()))) {
+                return Err(ApiError::unauthorized("Invalid webhook token"));
+            }
+        }
+
+        let mut input = map_input(trigger.input.as_ref(), parse_payload(&body), &headers)
+            .map_err(|e| ApiError::bad_request(&e.to_string()))?;
+        let task = workflows.get_task(&trigger.task)
+            .ok_or_else(|| anyhow!("Trigger '{}' references non-existent task '{}'", trigger_id, trigger.task))?;
+        task.validate_input(&mut input)
+            .map_err(|e| ApiError::bad_request(&e.to_string()))?;
+
+        JobRequest {
+            spec: JobSpec::Task { name: trigger.task.clone() },
+            input: Some(Value::Object(input)),
+            uuid: None,
+            priority: trigger.priority,
+            resume: None,
+            traceparent: None,
+            project: project.job_project(),
+        }
+    };
+
+    if api.task_pause(&job).await?.is_some() {
+        return Err(ApiError::conflict("Task is paused"));
+    }
+    api.check_action_sunset(&job).map_err(|e| ApiError::bad_request(&e.to_string()))?;
+    let rate_limit = project.workspace.rate_limit_for(&job).map_err(|e| ApiError::bad_request(&e.to_string()))?;
+    let job_id = api.job_repository.enqueue_job(&job, "webhook", Some(&trigger_id), project.workspace.get_revision().as_deref(), rate_limit.as_ref(), None).await?;
+    info!("Enqueued job {} for webhook '{}' of project '{}'", job_id, trigger_id, project.name);
+    Ok(ApiResponse::data(json!({"job_id": job_id})))
+}
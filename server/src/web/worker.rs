@@ -1,23 +1,42 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use axum::{
     extract::{
         Path, Query, State
     },
+    body::{Body, BodyDataStream},
+    extract::{FromRequest, Request},
     http::StatusCode,
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router
 };
-use tracing::{debug};
-use stroem_common::{JobRequest, JobResult, log_collector::LogEntry};
+use tracing::{debug, error, warn};
+use stroem_common::workspace_client::WorkspaceManifest;
+use stroem_common::{artifacts, rfc3339, JobRequest, JobResult, ResumeState, WorkerHeartbeat, DEFAULT_PROJECT, REASON_WORKER_OUTDATED, WorkerRegistration, log_collector::{LogEntry, NDJSON_CONTENT_TYPE}};
+use futures_util::StreamExt;
+use stroem_common::http_retry::IDEMPOTENCY_KEY_HEADER;
+use stroem_common::wait::STATUS_SUSPENDED;
+use stroem_common::protocol::{self, ALL_PROJECTS, PROTOCOL_VERSION_HEADER, PROTOCOL_VERSION_PARAM, PROJECT_PARAM, PROJECTS_PARAM, REVISION_HEADER, WAIT_HEADER, WAIT_PARAM};
 use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use serde::Deserialize;
+use schemars::JsonSchema;
 use serde_json::{Value, json};
+use tokio::io::AsyncWriteExt;
 use crate::error::AppError;
+use anyhow::{anyhow, bail, Error};
 use axum::extract::FromRequestParts;
-use axum::http::header;
+use axum::http::{header, HeaderMap, HeaderValue};
 use axum::http::request::Parts;
 
-use crate::web::WebState;
+use crate::dispatcher;
+use crate::projects::Project;
+use crate::repository::{CachedStepOutput, JobApproval, JobArtifact, JobWait};
+use crate::web::{access_log, WebState};
+use crate::web::openapi::{op, Auth, Operation};
 
 pub fn get_routes() -> Router<WebState> {
     Router::new()
@@ -26,54 +45,262 @@ pub fn get_routes() -> Router<WebState> {
         .route("/jobs/{:job_id}/start", post(update_job_start))
         .route("/jobs/{:job_id}/logs", post(save_job_logs))
         .route("/jobs/{:job_id}/results", post(update_job_result))
+        .route("/jobs/{:job_id}/artifacts", get(get_job_artifacts).post(save_job_artifact))
+        .route("/jobs/{:job_id}/artifacts/{:step_name}/{*name}", get(get_job_artifact))
+        .route("/cache/{:cache_key}", get(get_cached_output).post(save_cached_output))
+        .route("/jobs/{:job_id}/approvals/{:step_name}", get(get_step_approval).post(request_step_approval))
+        .route("/jobs/{:job_id}/waits/{:step_name}", post(register_step_wait))
+        .route("/jobs/{:job_id}/suspend", post(suspend_job))
         .route("/jobs/{:job_id}/steps/{:step_name}/start", post(update_step_start))
         .route("/jobs/{:job_id}/steps/{:step_name}/logs", post(save_step_logs))
         .route("/jobs/{:job_id}/steps/{:step_name}/results", post(update_step_result))
+        .route("/workers/register", post(register_worker))
+        .route("/workers/{:worker_id}/heartbeat", post(worker_heartbeat))
         .route("/files/workspace.tar.gz", get(serve_workspace_tarball))
+        .route("/files/workspace.manifest", get(serve_workspace_manifest))
+        .route("/files/workspace/{*name}", get(serve_workspace_file))
 }
 
+pub fn get_operations() -> Vec<Operation> {
+    const IDEMPOTENCY: &str = "Requests retried with the same key are applied once";
+    const PROJECT: &str = "Project of the workspace, the default project without it";
+    vec![
+        op("post", "/jobs", "Worker", "Queues a job, e.g. a sub-task of a running job").auth(Auth::Worker).body::<JobRequest>()
+            .param("group", "Job group the job joins").content("text/plain", "Id of the queued job"),
+        op("get", "/jobs/next", "Worker", "Leases the next queued job to the worker, or with `count` a list of jobs").auth(Auth::Worker)
+            .param("worker_id", "Id of the polling worker")
+            .param("count", "Number of jobs to lease at once, up to 100; a list is returned with it")
+            .param(WAIT_PARAM, "Seconds to hold the poll until a job is queued")
+            .param(PROJECTS_PARAM, "Comma separated projects to take jobs of")
+            .param(PROTOCOL_VERSION_PARAM, "Highest protocol version the worker supports")
+            .content("application/json", "The job, `null` if none is queued, or a list of jobs with `count`"),
+        op("post", "/jobs/{:job_id}/start", "Worker", "Marks a job as started").auth(Auth::Worker)
+            .param("worker_id", "Id of the worker").header(IDEMPOTENCY_KEY_HEADER, IDEMPOTENCY).raw_body("application/json"),
+        op("post", "/jobs/{:job_id}/logs", "Worker", "Saves log lines of a job").auth(Auth::Worker)
+            .header(IDEMPOTENCY_KEY_HEADER, IDEMPOTENCY).body::<Vec<LogEntry>>().body_as::<LogEntry>(NDJSON_CONTENT_TYPE),
+        op("post", "/jobs/{:job_id}/results", "Worker", "Saves the result of a job").auth(Auth::Worker)
+            .param("worker_id", "Id of the worker").header(IDEMPOTENCY_KEY_HEADER, IDEMPOTENCY).body::<JobResult>(),
+        op("get", "/jobs/{:job_id}/artifacts", "Worker", "Artifacts of a job").auth(Auth::Worker).json::<Vec<JobArtifact>>(),
+        op("post", "/jobs/{:job_id}/artifacts", "Worker", "Uploads a file as an artifact of a step").auth(Auth::Worker)
+            .query::<ArtifactParams>().raw_body("application/octet-stream"),
+        op("get", "/jobs/{:job_id}/artifacts/{:step_name}/{*name}", "Worker", "Downloads an artifact").auth(Auth::Worker)
+            .content("application/octet-stream", "The file"),
+        op("get", "/cache/{:cache_key}", "Worker", "Output a cached step of the job's project stored under the key").auth(Auth::Worker)
+            .param("worker_id", "Id of the worker running the job").query::<CacheParams>().json::<Option<CachedStepOutput>>(),
+        op("post", "/cache/{:cache_key}", "Worker", "Stores the output of a cached step of a job running on the worker").auth(Auth::Worker)
+            .param("worker_id", "Id of the worker running the job").body::<CachedOutputPayload>(),
+        op("get", "/jobs/{:job_id}/approvals/{:step_name}", "Worker", "The approval an approval step waits for").auth(Auth::Worker)
+            .json::<Option<JobApproval>>(),
+        op("post", "/jobs/{:job_id}/approvals/{:step_name}", "Worker", "Requests the approval of an approval step, or returns the one requested before")
+            .auth(Auth::Worker).body::<ApprovalRequestPayload>().json::<JobApproval>(),
+        op("post", "/jobs/{:job_id}/waits/{:step_name}", "Worker", "Records what a wait step waits for, or returns the wait recorded before")
+            .auth(Auth::Worker).body::<WaitRequestPayload>().json::<JobWait>(),
+        op("post", "/jobs/{:job_id}/suspend", "Worker", "Hands a job back until the wait of a step is over, it is queued again then")
+            .auth(Auth::Worker).param("worker_id", "Id of the worker").body::<SuspendPayload>(),
+        op("post", "/jobs/{:job_id}/steps/{:step_name}/start", "Worker", "Marks a step as started").auth(Auth::Worker)
+            .param("worker_id", "Id of the worker").header(IDEMPOTENCY_KEY_HEADER, IDEMPOTENCY).raw_body("application/json"),
+        op("post", "/jobs/{:job_id}/steps/{:step_name}/logs", "Worker", "Saves log lines of a step").auth(Auth::Worker)
+            .header(IDEMPOTENCY_KEY_HEADER, IDEMPOTENCY).body::<Vec<LogEntry>>().body_as::<LogEntry>(NDJSON_CONTENT_TYPE),
+        op("post", "/jobs/{:job_id}/steps/{:step_name}/results", "Worker", "Saves the result of a step").auth(Auth::Worker)
+            .param("worker_id", "Id of the worker").header(IDEMPOTENCY_KEY_HEADER, IDEMPOTENCY).body::<JobResult>(),
+        op("post", "/workers/register", "Worker", "Registers a worker with its capacity and labels").auth(Auth::Worker)
+            .param("worker_id", "Id of the worker").body::<WorkerRegistration>(),
+        op("post", "/workers/{:worker_id}/heartbeat", "Worker", "Reports that a worker is alive").auth(Auth::Worker)
+            .body::<WorkerHeartbeat>(),
+        op("get", "/files/workspace.tar.gz", "Worker", "The workspace as a tarball").auth(Auth::Worker)
+            .param(PROJECT_PARAM, PROJECT).content("application/gzip", "The tarball, with its revision in a header"),
+        op("get", "/files/workspace.manifest", "Worker", "Files of the workspace tarball with their hashes").auth(Auth::Worker)
+            .param(PROJECT_PARAM, PROJECT).json::<WorkspaceManifest>(),
+        op("get", "/files/workspace/{*name}", "Worker", "A single file of the workspace tarball").auth(Auth::Worker)
+            .param(PROJECT_PARAM, PROJECT).content("application/octet-stream", "The file"),
+    ]
+}
+
+/// With `group=<id>`, the job joins an existing job group, e.g. as a sub-task of the job enqueuing it.
 #[axum::debug_handler]
 async fn enqueue_job(
     State(api): State<WebState>,
-    Json(job): Json<JobRequest>,
+    Query(params): Query<HashMap<String, String>>,
+    _worker: Worker,
+    Json(mut job): Json<JobRequest>,
 ) -> Result<String, AppError> {
-    Ok(api.job_repository.enqueue_job(&job, "user", None).await?)
+    if let Some(pause) = api.task_pause(&job).await? {
+        return Err(anyhow!("Task '{}' is paused", pause.task_id).into());
+    }
+    api.check_action_sunset(&job)?;
+    api.validate_input(&mut job)?;
+    let workspace = &api.projects.of_job(&job)?.workspace;
+    let rate_limit = workspace.rate_limit_for(&job)?;
+    let group_id = match params.get("group") {
+        Some(group) => {
+            let group_id = Uuid::parse_str(group)?;
+            if api.group_repository.get_group(&group_id).await?.is_none() {
+                return Err(anyhow!("Job group {} not found", group_id).into());
+            }
+            Some(group_id)
+        }
+        None => None,
+    };
+    Ok(api.job_repository.enqueue_job(&job, "user", None, workspace.get_revision().as_deref(), rate_limit.as_ref(), group_id.as_ref()).await?)
 }
 
+/// Upper bound for `count` on `/jobs/next`, so a single worker can't drain the whole queue.
+const MAX_JOBS_PER_POLL: usize = 100;
+
+/// Returns the next job, or with `count=N` a list of up to N jobs leased to the worker at once.
+/// With `wait=S` as well, the poll is held up to S seconds until a job is queued (push mode).
+/// With `projects=a,b`, only jobs of those projects are handed out.
+/// The workspace revision of the default project is sent along, so workers in warm standby notice deploys.
 #[axum::debug_handler]
 async fn get_next_job(
     State(api): State<WebState>,
     Query(params): Query<HashMap<String, String>>,
     _worker: Worker,
-) -> Result<Json<Option<JobRequest>>, AppError> {
-    let worker_id = params.get("worker_id").unwrap();
-    let job = api.job_repository.get_next_job(worker_id).await?;
-    Ok(Json(job))
+    WorkerId(worker_id): WorkerId,
+) -> Result<(HeaderMap, Json<Value>), AppError> {
+    let requested = params.get(PROTOCOL_VERSION_PARAM)
+        .map(|version| version.parse().map_err(|_| anyhow!("Invalid protocol version '{}'", version)))
+        .transpose()?;
+    let version = protocol::negotiate(requested).map_err(Error::msg)?;
+    let wait = params.get(WAIT_PARAM)
+        .map(|wait| wait.parse().map(Duration::from_secs).map_err(|_| anyhow!("Invalid wait '{}'", wait)))
+        .transpose()?
+        .map(|wait| wait.min(dispatcher::MAX_WAIT));
+    let projects = poll_projects(params.get(PROJECTS_PARAM), version);
+    let mut headers = HeaderMap::new();
+    let body = match params.get("count") {
+        Some(count) => {
+            let count: usize = count.parse().map_err(|_| anyhow!("Invalid count '{}'", count))?;
+            let count = count.clamp(1, MAX_JOBS_PER_POLL);
+            let jobs = match wait {
+                Some(wait) => {
+                    headers.insert(WAIT_HEADER, wait.as_secs().into());
+                    api.dispatcher.next_jobs(&api.job_repository, &worker_id, &projects, count, wait).await?
+                }
+                None => api.job_repository.get_next_jobs(&worker_id, &projects, count).await?,
+            };
+            let jobs = fail_outdated(&api, &worker_id, version, jobs).await?;
+            Value::Array(jobs.iter().map(|job| protocol::encode_job(job, version)).collect::<Result<_, _>>()?)
+        }
+        None => {
+            let jobs = api.job_repository.get_next_job(&worker_id, &projects).await?.into_iter().collect();
+            match fail_outdated(&api, &worker_id, version, jobs).await?.pop() {
+                Some(job) => protocol::encode_job(&job, version)?,
+                None => Value::Null,
+            }
+        }
+    };
+    let revision = api.projects.default_project().workspace.get_revision().unwrap_or("unknown".to_string());
+    headers.insert(PROTOCOL_VERSION_HEADER, version.into());
+    headers.insert(REVISION_HEADER, HeaderValue::from_str(&revision).map_err(Error::msg)?);
+    Ok((headers, Json(body)))
+}
+
+/// Projects whose jobs a poll leases, empty for every project. Workers from before projects, or that name none,
+/// only run the default project's jobs; they'd run any other against the default workspace.
+fn poll_projects(param: Option<&String>, version: u32) -> Vec<String> {
+    let projects: Vec<String> = param
+        .map(|projects| projects.split(',').filter(|p| !p.is_empty()).map(str::to_string).collect())
+        .unwrap_or_default();
+    if version < 3 || projects.is_empty() {
+        return vec![DEFAULT_PROJECT.to_string()];
+    }
+    if projects.iter().any(|project| project == ALL_PROJECTS) {
+        return vec![];
+    }
+    projects
+}
+
+/// Fails the leased jobs the worker can't run as intended at the protocol version it speaks, e.g. resumed jobs it
+/// would run every step of again, and returns the others.
+async fn fail_outdated(api: &WebState, worker_id: &str, version: u32, jobs: Vec<JobRequest>) -> Result<Vec<JobRequest>, Error> {
+    let mut runnable = Vec::with_capacity(jobs.len());
+    for job in jobs {
+        let required = protocol::required_version(&job);
+        if required <= version {
+            runnable.push(job);
+            continue;
+        }
+        let job_id = job.uuid.ok_or_else(|| anyhow!("Leased job without an id"))?.to_string();
+        warn!("Failing job {}: it needs protocol version {}, worker {} speaks {}", job_id, required, worker_id, version);
+        let now = Utc::now();
+        let result = JobResult {
+            protocol_version: protocol::PROTOCOL_VERSION,
+            success: false,
+            start_datetime: now,
+            end_datetime: now,
+            input: job.input,
+            output: None,
+            revision: None,
+            attempts: None,
+            status: None,
+            links: None,
+            environment: None,
+            reason: Some(REASON_WORKER_OUTDATED.to_string()),
+        };
+        job_finished(api, &job_id, &result).await?;
+    }
+    Ok(runnable)
+}
+
+/// Stores the result of a job and runs what follows a finished job, returns false if a result was stored before.
+async fn job_finished(api: &WebState, job_id: &str, result: &JobResult) -> Result<bool, Error> {
+    if !api.job_repository.update_job_result(job_id, result).await? {
+        return Ok(false);
+    }
+
+    api.log_repository
+        .job_done(job_id)
+        .await?;
+
+    api.post_processors.job_done(job_id);
+    api.lineage.job_done(job_id, result.success);
+    api.task_webhooks.job_done(job_id, result.success);
+    api.notifications.job_done(job_id, result.success);
+
+    api.job_events.send(job_id, "result", json!({
+        "result": result
+    })).await;
+    Ok(true)
+}
+
+/// Results from workers newer than the server are refused, so they stay in the worker's outbox until the server is upgraded.
+fn check_result_version(result: &JobResult) -> Result<(), Error> {
+    if result.protocol_version > protocol::PROTOCOL_VERSION {
+        bail!("Result uses protocol version {}, this server supports up to {}", result.protocol_version, protocol::PROTOCOL_VERSION);
+    }
+    Ok(())
 }
 
 #[axum::debug_handler]
 async fn update_job_start(
     State(api): State<WebState>,
     Path(job_id): Path<String>,
-    Query(params): Query<HashMap<String, String>>,
     _worker: Worker,
+    WorkerId(worker_id): WorkerId,
+    key: IdempotencyKey,
     Json(payload): Json<Value>,
 ) -> Result<(), AppError> {
-    let worker_id = params.get("worker_id").unwrap();
+    if key.seen() {
+        return Ok(());
+    }
 
     let start_datetime_str = payload.get("start_datetime").and_then(|v| v.as_str()).unwrap();
     let start_datetime = DateTime::parse_from_rfc3339(start_datetime_str).map(|dt| dt.with_timezone(&Utc))?;
 
     let input = payload.get("input").cloned();
     api.job_repository
-        .update_start_time(&job_id, worker_id, start_datetime, &input)
+        .update_start_time(&job_id, &worker_id, start_datetime, &input)
         .await?;
+    api.lineage.job_started(&job_id);
+    api.task_webhooks.job_started(&job_id);
 
-    crate::web::api::send_sse_event(&api, &job_id, "start", json!({
-        "start_datetime": &start_datetime,
+    api.job_events.send(&job_id, "start", json!({
+        "start_datetime": rfc3339::format(&start_datetime),
         "input": &input,
-    })).await?;
+    })).await;
 
+    key.applied();
     Ok(())
 }
 
@@ -82,26 +309,26 @@ async fn update_job_start(
 async fn update_job_result(
     State(api): State<WebState>,
     Path(job_id): Path<String>,
-    Query(params): Query<HashMap<String, String>>,
     _worker: Worker,
+    WorkerId(worker_id): WorkerId,
+    key: IdempotencyKey,
     Json(payload): Json<JobResult>,
 ) -> Result<(), AppError> {
+    if key.seen() {
+        return Ok(());
+    }
+    check_result_version(&payload)?;
     debug!("Payload: {:?}", payload);
-    let worker_id = params.get("worker_id").unwrap();
+    // The job handed itself back through `suspend_job`, another worker may have picked it up again by now
+    if payload.status.as_deref() == Some(STATUS_SUSPENDED) {
+        key.applied();
+        return Ok(());
+    }
     let output = payload.output.as_ref();
     debug!("Worker id: {}", worker_id);
     debug!("Output: {:?}", output);
-    api.job_repository
-        .update_job_result(&job_id, &payload)
-        .await?;
-
-    api.log_repository
-        .job_done(&job_id)
-        .await?;
-
-    crate::web::api::send_sse_event(&api, &job_id, "result", json!({
-        "result": &payload
-    })).await?;
+    job_finished(&api, &job_id, &payload).await?;
+    key.applied();
     Ok(())
 }
 
@@ -109,11 +336,14 @@ async fn update_job_result(
 async fn update_step_start(
     State(api): State<WebState>,
     Path((job_id, step_name)): Path<(String, String)>,
-    Query(params): Query<HashMap<String, String>>,
     _worker: Worker,
+    WorkerId(worker_id): WorkerId,
+    key: IdempotencyKey,
     Json(payload): Json<Value>,
 ) -> Result<(), AppError> {
-    let worker_id = params.get("worker_id").unwrap();
+    if key.seen() {
+        return Ok(());
+    }
     let start_datetime_str = payload.get("start_datetime").and_then(|v| v.as_str()).unwrap();
     let start_datetime = DateTime::parse_from_rfc3339(start_datetime_str).map(|dt| dt.with_timezone(&Utc))?;
 
@@ -123,11 +353,12 @@ async fn update_step_start(
         .update_step_start_time(&job_id, &step_name, &worker_id, start_datetime, &input)
         .await?;
 
-    crate::web::api::send_sse_event(&api, &job_id, "step_start", json!({
+    api.job_events.send(&job_id, "step_start", json!({
         "step_name": &step_name,
-        "start_datetime": &start_datetime,
+        "start_datetime": rfc3339::format(&start_datetime),
         "input": &input,
-    })).await?;
+    })).await;
+    key.applied();
     Ok(())
 }
 
@@ -135,21 +366,27 @@ async fn update_step_start(
 async fn update_step_result(
     State(api): State<WebState>,
     Path((job_id, step_name)): Path<(String, String)>,
-    Query(params): Query<HashMap<String, String>>,
     _worker: Worker,
+    _worker_id: WorkerId,
+    key: IdempotencyKey,
     Json(payload): Json<JobResult>,
 ) -> Result<(), AppError> {
-    let _worker_id = params.get("worker_id").unwrap();
+    if key.seen() {
+        return Ok(());
+    }
+    check_result_version(&payload)?;
     debug!("Payload: {:?}", payload);
-    api.job_repository
+    let applied = api.job_repository
         .update_step_result(&job_id, &step_name, &payload)
         .await?;
+    if applied {
+        api.job_events.send(&job_id, "step_result", json!({
+            "step_name": &step_name,
+            "result": &payload
+        })).await;
+    }
 
-    crate::web::api::send_sse_event(&api, &job_id, "step_result", json!({
-        "step_name": &step_name,
-        "result": &payload
-    })).await?;
-
+    key.applied();
     Ok(())
 }
 
@@ -158,14 +395,28 @@ async fn save_job_logs(
     State(api): State<WebState>,
     Path(job_id): Path<String>,
     _worker: Worker,
-    Json(logs): Json<Vec<LogEntry>>,
+    mut key: IdempotencyKey,
+    mut body: LogBody,
 ) -> Result<(), AppError> {
-    api.log_repository.save_logs(&job_id, None, &logs).await?;
+    if key.seen() {
+        return Ok(());
+    }
+    while let Some(logs) = body.next_batch().await? {
+        let logs = key.unsaved(logs);
+        if logs.is_empty() {
+            continue;
+        }
+        api.log_repository.save_logs(&job_id, None, &logs).await?;
+        api.job_repository.index_logs(&job_id, None, &logs).await?;
+        key.saved(logs.len());
+        api.log_sinks.send(&job_id, None, &logs);
 
-    crate::web::api::send_sse_event(&api, &job_id, "logs", json!({
-        "logs": &logs
-    })).await?;
+        api.job_events.send(&job_id, "logs", json!({
+            "logs": &logs
+        })).await;
+    }
 
+    key.applied();
     Ok(())
 }
 
@@ -174,41 +425,362 @@ async fn save_step_logs(
     State(api): State<WebState>,
     Path((job_id, step_name)): Path<(String, String)>,
     _worker: Worker,
-    Json(logs): Json<Vec<LogEntry>>,
+    mut key: IdempotencyKey,
+    mut body: LogBody,
 ) -> Result<(), AppError> {
-    api.log_repository.save_logs(&job_id, Some(&step_name), &logs).await?;
+    if key.seen() {
+        return Ok(());
+    }
+    while let Some(logs) = body.next_batch().await? {
+        let mut logs = key.unsaved(logs);
+        if logs.is_empty() {
+            continue;
+        }
+        // Older runners don't tag their entries
+        for entry in logs.iter_mut().filter(|entry| entry.step_name.is_none()) {
+            entry.step_name = Some(step_name.clone());
+        }
+        api.log_repository.save_logs(&job_id, Some(&step_name), &logs).await?;
+        api.job_repository.index_logs(&job_id, Some(&step_name), &logs).await?;
+        key.saved(logs.len());
+        api.log_sinks.send(&job_id, Some(&step_name), &logs);
 
-    crate::web::api::send_sse_event(&api, &job_id, "step_logs", json!({
-        "step_name": &step_name,
-        "logs": &logs
-    })).await?;
+        api.job_events.send(&job_id, "step_logs", json!({
+            "step_name": &step_name,
+            "logs": &logs
+        })).await;
+    }
 
+    key.applied();
     Ok(())
 }
 
+#[derive(Deserialize, JsonSchema)]
+struct ArtifactParams {
+    step: String,
+    name: String,
+}
+
+/// Stores the request body as an artifact of the step, see `stroem_common::artifacts`.
+#[axum::debug_handler]
+async fn save_job_artifact(
+    State(api): State<WebState>,
+    Path(job_id): Path<Uuid>,
+    Query(params): Query<ArtifactParams>,
+    _worker: Worker,
+    body: Body,
+) -> Result<(), AppError> {
+    if !api.artifact_repository.is_enabled() {
+        return Err(anyhow!("No artifact storage configured").into());
+    }
+    if params.step.contains('/') || !artifacts::is_valid_name(&params.step) || !artifacts::is_valid_name(&params.name) {
+        return Err(anyhow!("Invalid artifact {} of step {}", params.name, params.step).into());
+    }
+    let upload = StagedArtifact::receive(body, api.artifact_repository.max_size()).await?;
+    api.artifact_repository.save_artifact(&job_id, &params.step, &params.name, &upload.path, upload.size as i64).await?;
+    debug!("Saved artifact {} of step {} of job {} ({} bytes)", params.name, params.step, job_id, upload.size);
+    Ok(())
+}
+
+#[axum::debug_handler]
+async fn get_job_artifacts(
+    State(api): State<WebState>,
+    Path(job_id): Path<Uuid>,
+    _worker: Worker,
+) -> Result<Json<Vec<JobArtifact>>, AppError> {
+    Ok(Json(api.artifact_repository.get_artifacts(&job_id).await?))
+}
+
+#[axum::debug_handler]
+async fn get_job_artifact(
+    State(api): State<WebState>,
+    Path((job_id, step_name, name)): Path<(Uuid, String, String)>,
+    _worker: Worker,
+) -> Result<Response, AppError> {
+    Ok(api.artifact_response(&job_id, &step_name, &name).await?)
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct CacheParams {
+    /// Job of the step looking up its output
+    job_id: Uuid,
+}
+
+/// Output a step with `cache: true` stored under the key, see `stroem_common::step_cache`.
+#[axum::debug_handler]
+async fn get_cached_output(
+    State(api): State<WebState>,
+    Path(cache_key): Path<String>,
+    Query(params): Query<CacheParams>,
+    _worker: Worker,
+    WorkerId(worker_id): WorkerId,
+) -> Result<Response, AppError> {
+    let Some(project) = cache_project(&api, &params.job_id, &worker_id).await? else {
+        return Ok(not_running(&params.job_id, &worker_id));
+    };
+    Ok(Json(api.job_repository.get_cached_output(&project.scoped_name(&cache_key)).await?).into_response())
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct CachedOutputPayload {
+    job_id: Uuid,
+    step_name: String,
+    output: Option<Value>,
+}
+
+#[axum::debug_handler]
+async fn save_cached_output(
+    State(api): State<WebState>,
+    Path(cache_key): Path<String>,
+    _worker: Worker,
+    WorkerId(worker_id): WorkerId,
+    Json(payload): Json<CachedOutputPayload>,
+) -> Result<Response, AppError> {
+    let Some(project) = cache_project(&api, &payload.job_id, &worker_id).await? else {
+        return Ok(not_running(&payload.job_id, &worker_id));
+    };
+    api.job_repository.save_cached_output(&project.scoped_name(&cache_key), &payload.job_id, &payload.step_name, &payload.output).await?;
+    Ok(().into_response())
+}
+
+/// Project whose cached outputs a step of the job uses, so same-named steps of other projects don't share them.
+/// `None` unless the job is running on the worker.
+async fn cache_project(api: &WebState, job_id: &Uuid, worker_id: &str) -> Result<Option<Arc<Project>>, Error> {
+    let job = api.job_repository.get_job(&job_id.to_string()).await?;
+    if job.worker_id.as_deref() != Some(worker_id) || job.end_datetime.is_some() {
+        return Ok(None);
+    }
+    Ok(api.projects.get(&job.project_id).cloned())
+}
+
+fn not_running(job_id: &Uuid, worker_id: &str) -> Response {
+    (StatusCode::FORBIDDEN, format!("Job {} is not running on worker {}", job_id, worker_id)).into_response()
+}
+
+/// The approval an approval step waits for, see `stroem_common::approval`.
+#[axum::debug_handler]
+async fn get_step_approval(
+    State(api): State<WebState>,
+    Path((job_id, step_name)): Path<(Uuid, String)>,
+    _worker: Worker,
+) -> Result<Json<Option<JobApproval>>, AppError> {
+    Ok(Json(api.approval_repository.get_approval(&job_id, &step_name).await?))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct ApprovalRequestPayload {
+    approvers: Option<Vec<String>>,
+    message: Option<String>,
+    /// How long the approval may stay pending, from its first request; a week without it
+    timeout_secs: Option<f64>,
+}
+
+/// Approvals time out after this long unless their step sets a timeout
+const DEFAULT_APPROVAL_TIMEOUT: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[axum::debug_handler]
+async fn request_step_approval(
+    State(api): State<WebState>,
+    Path((job_id, step_name)): Path<(Uuid, String)>,
+    _worker: Worker,
+    Json(payload): Json<ApprovalRequestPayload>,
+) -> Result<Json<JobApproval>, AppError> {
+    let timeout = payload.timeout_secs.map(Duration::from_secs_f64).unwrap_or(DEFAULT_APPROVAL_TIMEOUT);
+    let approval = api.approval_repository.request(&job_id, &step_name, payload.approvers.as_deref(), payload.message.as_deref(), timeout).await?;
+    debug!("Step {} of job {} waits for approval, {}", step_name, job_id, approval.status);
+    Ok(Json(approval))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct WaitRequestPayload {
+    /// Event a `wait_for_event` step waits for, unset for a `wait`
+    event_key: Option<String>,
+    /// How long a `wait` lasts or a `wait_for_event` waits at most, from its first registration
+    timeout_secs: Option<f64>,
+}
+
+/// Records what a wait step waits for, see `stroem_common::wait`.
+#[axum::debug_handler]
+async fn register_step_wait(
+    State(api): State<WebState>,
+    Path((job_id, step_name)): Path<(Uuid, String)>,
+    _worker: Worker,
+    Json(payload): Json<WaitRequestPayload>,
+) -> Result<Json<JobWait>, AppError> {
+    let timeout = payload.timeout_secs.map(Duration::try_from_secs_f64).transpose()?;
+    let mut wait = api.wait_repository.register(&job_id, &step_name, payload.event_key.as_deref(), timeout).await?;
+    if let (Some(key), "waiting") = (&wait.event_key, wait.status.as_str())
+        && let Some(event) = api.wait_repository.get_event(&job_id, key).await? {
+        // Only events of users who may run the job take it further, as when they're posted while it waits
+        let job = api.job_repository.get_job(&job_id.to_string()).await?;
+        let poster = match event.posted_by {
+            Some(user_id) => api.auth_service.get_user(&user_id).await?,
+            None => None,
+        };
+        if poster.is_some_and(|poster| api.can_rerun(&poster, &job)) && api.wait_repository.receive(&job_id, key, &event.payload).await? {
+            wait = api.wait_repository.get_wait(&job_id, &step_name).await?
+                .ok_or_else(|| anyhow!("Wait of step {} of job {} not found", step_name, job_id))?;
+        }
+    }
+    debug!("Step {} of job {} waits, {}", step_name, job_id, wait.status);
+    Ok(Json(wait))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct SuspendPayload {
+    /// Step whose wait isn't over yet
+    step_name: String,
+    /// Steps the runner takes over once the job runs again
+    resume: ResumeState,
+}
+
+/// Frees the worker of a job that waits; the job is queued again right away if the wait ended meanwhile.
+#[axum::debug_handler]
+async fn suspend_job(
+    State(api): State<WebState>,
+    Path(job_id): Path<Uuid>,
+    _worker: Worker,
+    WorkerId(worker_id): WorkerId,
+    Json(payload): Json<SuspendPayload>,
+) -> Result<(), AppError> {
+    if !api.job_repository.suspend(&job_id, &worker_id, &payload.resume).await? {
+        return Err(anyhow!("Job {} is not running on worker {}", job_id, worker_id).into());
+    }
+    api.job_events.send(&job_id.to_string(), "suspended", json!({
+        "step_name": &payload.step_name,
+    })).await;
+    api.job_repository.requeue_suspended(&job_id).await?;
+    Ok(())
+}
+
+/// An uploaded artifact, kept in a temporary file until it's stored and removed once dropped.
+struct StagedArtifact {
+    path: PathBuf,
+    size: u64,
+}
+
+impl StagedArtifact {
+    async fn receive(body: Body, max_size: u64) -> Result<Self, Error> {
+        let mut upload = StagedArtifact {
+            path: std::env::temp_dir().join(format!("stroem-artifact-{}", Uuid::new_v4())),
+            size: 0,
+        };
+        let mut file = tokio::fs::File::create(&upload.path).await?;
+        let mut stream = body.into_data_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            upload.size += chunk.len() as u64;
+            if upload.size > max_size {
+                bail!("Artifact larger than {} bytes", max_size);
+            }
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        Ok(upload)
+    }
+}
+
+impl Drop for StagedArtifact {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[axum::debug_handler]
+async fn register_worker(
+    State(api): State<WebState>,
+    _worker: Worker,
+    WorkerId(worker_id): WorkerId,
+    Json(registration): Json<WorkerRegistration>,
+) -> Result<(), AppError> {
+    api.worker_repository
+        .register(&worker_id, registration.capacity, &registration.labels)
+        .await?;
+    Ok(())
+}
+
+#[axum::debug_handler]
+async fn worker_heartbeat(
+    State(api): State<WebState>,
+    Path(worker_id): Path<String>,
+    _worker: Worker,
+    Json(heartbeat): Json<WorkerHeartbeat>,
+) -> Result<Response, AppError> {
+    let known = api.worker_repository
+        .heartbeat(&worker_id, heartbeat.running)
+        .await?;
+    if !known {
+        return Ok((StatusCode::NOT_FOUND, "Worker not registered").into_response());
+    }
+    Ok(().into_response())
+}
+
+/// The project named by the `project` query parameter of the workspace routes, the default project without it.
+fn requested_project<'a>(api: &'a WebState, params: &HashMap<String, String>) -> Option<&'a Arc<Project>> {
+    match params.get(PROJECT_PARAM) {
+        Some(name) => api.projects.get(name),
+        None => Some(api.projects.default_project()),
+    }
+}
+
+const PROJECT_NOT_FOUND: &str = "Project not found";
 
 #[axum::debug_handler]
 async fn serve_workspace_tarball(
     State(api): State<WebState>,
+    Query(params): Query<HashMap<String, String>>,
     _worker: Worker,
-) -> Result<impl IntoResponse, AppError> {
+) -> Result<Response, AppError> {
+    let Some(project) = requested_project(&api, &params) else {
+        return Ok((StatusCode::NOT_FOUND, PROJECT_NOT_FOUND).into_response());
+    };
 
-    let gzipped = api.workspace.build_tarball().await?;
+    let gzipped = project.workspace.build_tarball().await?;
 
-    let revision = api.workspace.get_revision().unwrap_or("unknown".to_string());
+    let revision = project.workspace.get_revision().unwrap_or("unknown".to_string());
     debug!("Revision: {}", revision);
 
     let headers = [
         ("Content-Type", "application/gzip".to_string()),
         ("Content-Disposition", "attachment; filename=\"workspace.tar.gz\"".to_string()),
-        ("X-Revision", revision.to_string()),
+        (REVISION_HEADER, revision.to_string()),
     ];
 
     Ok((
         StatusCode::OK,
         headers,
         gzipped,
-    ))
+    ).into_response())
+}
+
+#[axum::debug_handler]
+async fn serve_workspace_manifest(
+    State(api): State<WebState>,
+    Query(params): Query<HashMap<String, String>>,
+    _worker: Worker,
+) -> Result<Response, AppError> {
+    let Some(project) = requested_project(&api, &params) else {
+        return Ok((StatusCode::NOT_FOUND, PROJECT_NOT_FOUND).into_response());
+    };
+    Ok(Json::<WorkspaceManifest>(project.workspace.build_manifest().await?).into_response())
+}
+
+/// A single file of the tarball, by its name in the manifest.
+#[axum::debug_handler]
+async fn serve_workspace_file(
+    State(api): State<WebState>,
+    Path(name): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    _worker: Worker,
+) -> Result<Response, AppError> {
+    let Some(project) = requested_project(&api, &params) else {
+        return Ok((StatusCode::NOT_FOUND, PROJECT_NOT_FOUND).into_response());
+    };
+    let Some(path) = project.workspace.tarball_file(&name) else {
+        return Ok((StatusCode::NOT_FOUND, format!("Workspace file {} not found", name)).into_response());
+    };
+    let content = tokio::fs::read(&path).await?;
+    Ok(([(header::CONTENT_TYPE, "application/octet-stream")], content).into_response())
 }
 
 pub struct Worker {}
@@ -229,10 +801,248 @@ impl FromRequestParts<WebState> for Worker {
             .strip_prefix("Bearer ")
             .ok_or((StatusCode::UNAUTHORIZED, "Invalid Authorization format"))?;
 
-        if token != state.worker_token {
+        if let Some(worker_id) = Query::<HashMap<String, String>>::try_from_uri(&parts.uri).ok()
+            .and_then(|Query(params)| params.get("worker_id").cloned()) {
+            access_log::record_worker(&worker_id);
+        }
+
+        if state.worker_token.as_deref() == Some(token) {
+            return Ok(Worker{});
+        }
+
+        let valid = state.worker_repository.authenticate(token).await.map_err(|e| {
+            error!("Failed to check worker token: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to check worker token")
+        })?;
+        if !valid {
             return Err((StatusCode::UNAUTHORIZED, "Invalid worker token"));
         }
 
         Ok(Worker{})
     }
-}
\ No newline at end of file
+}
+
+/// The `worker_id` query parameter workers identify themselves with, requests without it are rejected.
+pub struct WorkerId(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for WorkerId {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Query::<HashMap<String, String>>::try_from_uri(&parts.uri).ok()
+            .and_then(|Query(mut params)| params.remove("worker_id"))
+            .map(WorkerId)
+            .ok_or((StatusCode::BAD_REQUEST, "Missing worker_id"))
+    }
+}
+
+/// Number of idempotency keys remembered, enough to cover the retry window of all workers.
+const RECENT_REQUESTS: usize = 10_000;
+
+/// Idempotency keys of recently applied worker requests, so retries of a request whose response
+/// got lost aren't applied twice, and how far log requests that failed midway got. Kept in memory;
+/// results are also deduplicated in the database.
+#[derive(Clone, Default)]
+pub struct RecentRequests {
+    keys: Arc<Mutex<RecentKeys>>,
+}
+
+#[derive(Default)]
+struct RecentKeys {
+    applied: HashSet<String>,
+    order: VecDeque<String>,
+    /// Keys of requests being applied, so a retry arriving meanwhile isn't applied alongside
+    in_flight: HashSet<String>,
+    /// Entries saved by attempts of a log request that failed midway, skipped by its retries
+    saved: HashMap<String, usize>,
+    saved_order: VecDeque<String>,
+}
+
+enum Claim {
+    /// Claimed, with the number of entries earlier attempts saved
+    Claimed(usize),
+    Applied,
+    InFlight,
+}
+
+impl RecentRequests {
+    /// Checks the key and claims it for the request in one go.
+    fn claim(&self, key: &str) -> Claim {
+        let Ok(mut keys) = self.keys.lock() else { return Claim::Claimed(0) };
+        if keys.applied.contains(key) {
+            Claim::Applied
+        } else if !keys.in_flight.insert(key.to_string()) {
+            Claim::InFlight
+        } else {
+            Claim::Claimed(keys.saved.get(key).copied().unwrap_or(0))
+        }
+    }
+
+    fn release(&self, key: String, saved: usize) {
+        let Ok(mut keys) = self.keys.lock() else { return };
+        let RecentKeys { in_flight, saved: saved_keys, saved_order, .. } = &mut *keys;
+        in_flight.remove(&key);
+        if saved > 0 && saved_keys.insert(key.clone(), saved).is_none() {
+            saved_order.push_back(key);
+        }
+        while saved_order.len() > RECENT_REQUESTS {
+            let Some(oldest) = saved_order.pop_front() else { break };
+            saved_keys.remove(&oldest);
+        }
+    }
+
+    fn insert(&self, key: String) {
+        let Ok(mut keys) = self.keys.lock() else { return };
+        let RecentKeys { applied, order, in_flight, saved, .. } = &mut *keys;
+        in_flight.remove(&key);
+        saved.remove(&key);
+        if applied.insert(key.clone()) {
+            order.push_back(key);
+        }
+        while order.len() > RECENT_REQUESTS {
+            let Some(oldest) = order.pop_front() else { break };
+            applied.remove(&oldest);
+        }
+    }
+}
+
+/// The `Idempotency-Key` header of a worker request, if any, claimed until the request is applied or fails.
+/// Retries of a request still being applied are refused with a 503, which workers retry.
+pub struct IdempotencyKey {
+    key: Option<String>,
+    seen: bool,
+    recent: RecentRequests,
+    /// Log entries of the request saved so far, by this attempt and earlier ones
+    saved: usize,
+    /// Log entries of the request read so far by this attempt
+    read: usize,
+}
+
+impl IdempotencyKey {
+    /// True if a request with this key was already applied.
+    fn seen(&self) -> bool {
+        self.seen
+    }
+
+    /// The entries of the next batch of a log request that earlier attempts didn't save yet.
+    fn unsaved(&mut self, mut logs: Vec<LogEntry>) -> Vec<LogEntry> {
+        let skipped = self.saved.saturating_sub(self.read).min(logs.len());
+        self.read += logs.len();
+        logs.split_off(skipped)
+    }
+
+    /// Records that `count` more entries of a log request were saved, for retries after a failure midway.
+    fn saved(&mut self, count: usize) {
+        self.saved += count;
+    }
+
+    /// Records the key once the request took effect; a request failing before leaves it for retries.
+    fn applied(mut self) {
+        if let Some(key) = self.key.take() {
+            self.recent.insert(key);
+        }
+    }
+}
+
+impl Drop for IdempotencyKey {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.recent.release(key, self.saved);
+        }
+    }
+}
+
+impl FromRequestParts<WebState> for IdempotencyKey {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, state: &WebState) -> Result<Self, Self::Rejection> {
+        let recent = state.recent_requests.clone();
+        let Some(key) = parts.headers.get(IDEMPOTENCY_KEY_HEADER).and_then(|value| value.to_str().ok()) else {
+            return Ok(IdempotencyKey { key: None, seen: false, recent, saved: 0, read: 0 });
+        };
+        match recent.claim(key) {
+            Claim::Claimed(saved) => Ok(IdempotencyKey { key: Some(key.to_string()), seen: false, recent, saved, read: 0 }),
+            Claim::Applied => {
+                debug!("Skipping already applied request {}", key);
+                Ok(IdempotencyKey { key: None, seen: true, recent, saved: 0, read: 0 })
+            }
+            Claim::InFlight => Err((StatusCode::SERVICE_UNAVAILABLE, "A request with this idempotency key is being applied")),
+        }
+    }
+}
+
+/// Entries saved per batch of a streamed log body
+const LOG_STREAM_BATCH: usize = 100;
+/// Longest line accepted in a streamed log body, the collector truncates long messages well below this
+const MAX_LOG_LINE: usize = 16 * 1024 * 1024;
+
+/// Log entries posted by a worker or runner: a JSON array, or with an NDJSON content type a stream
+/// of one entry per line that is saved as it arrives instead of being read into memory at once.
+pub enum LogBody {
+    Array(Option<Vec<LogEntry>>),
+    Stream {
+        stream: BodyDataStream,
+        buffer: Vec<u8>,
+        done: bool,
+    },
+}
+
+impl LogBody {
+    /// The next entries to save, `None` once the body is consumed.
+    async fn next_batch(&mut self) -> Result<Option<Vec<LogEntry>>, Error> {
+        let (stream, buffer, done) = match self {
+            LogBody::Array(logs) => return Ok(logs.take()),
+            LogBody::Stream { stream, buffer, done } => (stream, buffer, done),
+        };
+        let mut batch = Vec::new();
+        loop {
+            while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                batch.extend(Self::parse_line(&line)?);
+                if batch.len() >= LOG_STREAM_BATCH {
+                    return Ok(Some(batch));
+                }
+            }
+            if *done {
+                // The last line may lack its newline
+                batch.extend(Self::parse_line(&std::mem::take(buffer))?);
+                return Ok((!batch.is_empty()).then_some(batch));
+            }
+            // Save what arrived so far before waiting for more
+            if !batch.is_empty() {
+                return Ok(Some(batch));
+            }
+            match stream.next().await {
+                Some(chunk) => buffer.extend_from_slice(&chunk?),
+                None => *done = true,
+            }
+            if buffer.len() > MAX_LOG_LINE {
+                bail!("Log line longer than {} bytes", MAX_LOG_LINE);
+            }
+        }
+    }
+
+    fn parse_line(line: &[u8]) -> Result<Option<LogEntry>, Error> {
+        let line = line.trim_ascii();
+        if line.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(line)?))
+    }
+}
+
+impl FromRequest<WebState> for LogBody {
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &WebState) -> Result<Self, Self::Rejection> {
+        let is_ndjson = request.headers().get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with(NDJSON_CONTENT_TYPE));
+        if is_ndjson {
+            return Ok(LogBody::Stream { stream: request.into_body().into_data_stream(), buffer: Vec::new(), done: false });
+        }
+        let Json(logs) = Json::<Vec<LogEntry>>::from_request(request, state).await
+            .map_err(IntoResponse::into_response)?;
+        Ok(LogBody::Array(Some(logs)))
+    }
+}
@@ -1,17 +1,21 @@
 
-use std::path::{PathBuf};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
 use std::fs;
-use anyhow::{anyhow, Error};
+use anyhow::{anyhow, bail, Error};
 use tracing::{error, info};
 use tokio::sync::watch; // For watcher task loop
 use std::sync::{Arc, RwLock};
 use tokio::fs::File;
 use async_compression::tokio::write::GzipEncoder;
 use tokio::io::AsyncWriteExt;
-use stroem_common::workflows_configuration::WorkflowsConfiguration;
+use stroem_common::workflows_configuration::{TaskNotify, TaskSla, TaskWebhooks, ValidationError, WorkflowsConfiguration};
 use crate::server_config::WorkspaceSourceConfig;
-use crate::workspace_source::{WorkspaceSource, WorkspaceSourceFactory};
-use stroem_common::walk_workspace_files;
+use crate::workspace_source::{CommitAuthor, CommitInfo, WorkspaceSource, WorkspaceSourceFactory};
+use stroem_common::{artifacts, walk_workspace_files, JobRequest};
+use stroem_common::workspace_client::{file_mode, hash_file, WorkspaceManifest};
+use crate::repository::JobRateLimit;
+use uuid::Uuid;
 
 
 
@@ -68,8 +72,26 @@ impl WorkspaceServer {
         });
     }
 
+    /// Loads and validates the workflows and makes them active. Once workflows are active, a reload that fails
+    /// to load or validate is refused and the last good workflows stay active. The first load falls back
+    /// to workflows that don't validate, or to none, so the server still starts.
     pub fn read_workflows(&self) -> Result<(), Error> {
-        let new_workflows = WorkflowsConfiguration::try_new_or_empty(PathBuf::from(self.path.clone()));
+        let active = self.workflows.read().map(|workflows| workflows.is_some()).unwrap_or(false);
+        let new_workflows = match WorkflowsConfiguration::new(self.path.clone()) {
+            Ok(workflows) => match workflows.validate() {
+                Ok(()) => workflows,
+                Err(e) if active => bail!("Invalid workflows, keeping the active ones: {}", e),
+                Err(e) => {
+                    error!("Invalid workflows, using them as there are no others: {}", e);
+                    workflows
+                }
+            },
+            Err(e) if active => bail!("Failed to load workflows, keeping the active ones: {:#}", e),
+            Err(e) => {
+                error!("Failed to load config, using empty configuration: {:#}", e);
+                WorkflowsConfiguration::default()
+            }
+        };
         info!("Loaded workspace configurations: {:?}", &new_workflows);
 
         if let Ok(mut workflows_guard) = self.workflows.write() {
@@ -103,6 +125,122 @@ impl WorkspaceServer {
         self.source.get_revision()
     }
 
+    pub fn commit_info(&self, revision: &str) -> Option<CommitInfo> {
+        self.source.commit_info(revision)
+    }
+
+    /// Resolves the rate limit of the job's task, with the key rendered from the job input.
+    pub fn rate_limit_for(&self, job: &JobRequest) -> Result<Option<JobRateLimit>, Error> {
+        let Some(task_id) = job.task() else { return Ok(None) };
+        let workflows_guard = self.workflows.read().map_err(|_| anyhow!("Could not read workspace"))?;
+        let Some(rate_limit) = workflows_guard.as_ref()
+            .and_then(|workflows| workflows.get_task(task_id))
+            .and_then(|task| task.rate_limit.as_ref()) else { return Ok(None) };
+        Ok(Some(JobRateLimit {
+            key: rate_limit.key_for(task_id, job.input.as_ref())?,
+            per: rate_limit.per,
+            max: rate_limit.max,
+        }))
+    }
+
+    pub fn task_webhooks(&self, task_id: &str) -> Result<Option<TaskWebhooks>, Error> {
+        let workflows_guard = self.workflows.read().map_err(|_| anyhow!("Could not read workspace"))?;
+        Ok(workflows_guard.as_ref()
+            .and_then(|workflows| workflows.get_task(task_id))
+            .and_then(|task| task.webhooks.clone()))
+    }
+
+    pub fn task_notify(&self, task_id: &str) -> Result<Option<TaskNotify>, Error> {
+        let workflows_guard = self.workflows.read().map_err(|_| anyhow!("Could not read workspace"))?;
+        Ok(workflows_guard.as_ref()
+            .and_then(|workflows| workflows.get_task(task_id))
+            .and_then(|task| task.notify.clone()))
+    }
+
+    /// Tasks that declare an SLA, with it.
+    pub fn task_slas(&self) -> Result<Vec<(String, TaskSla)>, Error> {
+        let workflows_guard = self.workflows.read().map_err(|_| anyhow!("Could not read workspace"))?;
+        Ok(workflows_guard.as_ref()
+            .and_then(|workflows| workflows.tasks.as_ref())
+            .map(|tasks| tasks.iter()
+                .filter_map(|(task_id, task)| task.sla.clone().map(|sla| (task_id.clone(), sla)))
+                .collect())
+            .unwrap_or_default())
+    }
+
+    /// Checks a workspace-relative path for the file editing API: no absolute paths, `..` or git internals.
+    pub fn editable_path(path: &str) -> Result<PathBuf, Error> {
+        let path = PathBuf::from(path);
+        let valid = path.components().all(|c| matches!(c, Component::Normal(_)))
+            && path.components().next().is_some_and(|c| c.as_os_str() != ".git");
+        if !valid {
+            bail!("Invalid path '{}'", path.display());
+        }
+        if path.to_string_lossy().ends_with(".sops.yaml") {
+            bail!("Encrypted files can't be edited");
+        }
+        Ok(path)
+    }
+
+    pub fn list_files(&self) -> Vec<String> {
+        walk_workspace_files(&self.path).into_iter()
+            .filter(|entry| entry.path().is_file())
+            .filter_map(|entry| entry.path().strip_prefix(&self.path).ok().map(|p| p.to_string_lossy().to_string()))
+            .filter(|path| !path.starts_with(".git/"))
+            .collect()
+    }
+
+    pub fn read_file(&self, path: &Path) -> Result<String, Error> {
+        Ok(fs::read_to_string(self.path.join(path))?)
+    }
+
+    /// Loads and validates the workflows as they would be with `content` written to `path`.
+    pub fn validate_edit(&self, path: &Path, content: &str) -> Result<(), Error> {
+        if !path.starts_with(".workflows") {
+            return Ok(());
+        }
+        match self.validate_files(&[(path.to_path_buf(), content.to_string())])?.into_iter().next() {
+            Some(error) => Err(error.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Every problem with the workflows as they would be with `files` (workspace-relative path and content)
+    /// written over the current ones, using a copy of `.workflows` so the live workspace is untouched.
+    pub fn validate_files(&self, files: &[(PathBuf, String)]) -> Result<Vec<ValidationError>, Error> {
+        let scratch = std::env::temp_dir().join(format!("stroem-edit-{}", Uuid::new_v4()));
+        let result = (|| {
+            let source = self.path.join(".workflows");
+            for entry in walk_workspace_files(&source) {
+                if entry.path().is_file() {
+                    let target = scratch.join(".workflows").join(entry.path().strip_prefix(&source)?);
+                    fs::create_dir_all(target.parent().unwrap_or(&scratch))?;
+                    fs::copy(entry.path(), target)?;
+                }
+            }
+            for (path, content) in files {
+                let target = scratch.join(path);
+                fs::create_dir_all(target.parent().unwrap_or(&scratch))?;
+                fs::write(target, content)?;
+            }
+            Ok(match WorkflowsConfiguration::new(scratch.clone()) {
+                Ok(workflows) => workflows.validation_errors(),
+                Err(e) => vec![ValidationError::of_load_error(e)],
+            })
+        })();
+        let _ = fs::remove_dir_all(&scratch);
+        result
+    }
+
+    /// Commits an edit through the workspace source and reloads the workflows, returning the new revision.
+    pub async fn commit_file(&self, path: PathBuf, content: String, message: String, author: CommitAuthor, base_revision: Option<String>) -> Result<String, Error> {
+        let source = self.source.clone();
+        let revision = tokio::task::spawn_blocking(move || {
+            source.commit_file(&path, &content, &message, &author, base_revision.as_deref())
+        }).await??;
+        self.read_workflows()?;
+        Ok(revision)
+    }
 
     pub async fn build_tarball(&self) -> Result<Vec<u8>, Error> {
         let tarball = Vec::new();
@@ -127,4 +265,40 @@ impl WorkspaceServer {
         Ok(tarball)
     }
 
+    /// Digests of the files of the tarball, for workers to download only the ones that changed.
+    pub async fn build_manifest(&self) -> Result<WorkspaceManifest, Error> {
+        let path = self.path.clone();
+        let revision = self.get_revision().unwrap_or("unknown".to_string());
+        tokio::task::spawn_blocking(move || {
+            let mut files = BTreeMap::new();
+            let mut modes = BTreeMap::new();
+            for entry in walk_workspace_files(&path) {
+                let file_path = entry.path();
+                if file_path.is_file() {
+                    let name = file_path.strip_prefix(&path)?
+                        .components()
+                        .map(|component| component.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/");
+                    if let Some(mode) = file_mode(file_path)? {
+                        modes.insert(name.clone(), mode);
+                    }
+                    files.insert(name, hash_file(file_path)?);
+                }
+            }
+            Ok(WorkspaceManifest { revision, files, modes })
+        }).await?
+    }
+
+    /// Path of a file of the tarball by its manifest name, `None` for anything else.
+    pub fn tarball_file(&self, name: &str) -> Option<PathBuf> {
+        if !artifacts::is_valid_name(name) {
+            return None;
+        }
+        let path = self.path.join(name);
+        walk_workspace_files(&self.path).into_iter()
+            .any(|entry| entry.path() == path && path.is_file())
+            .then_some(path)
+    }
+
 }
\ No newline at end of file
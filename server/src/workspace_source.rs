@@ -4,18 +4,49 @@ use folder::WorkspaceSourceFolder;
 mod git;
 use git::WorkspaceSourceGit;
 
+use std::path::Path;
 use std::sync::Arc;
-use anyhow::Error;
+use anyhow::{bail, Error};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 use crate::server_config::{WorkspaceSourceConfig, WorkspaceSourceType};
 
 pub trait WorkspaceSource: Send + Sync {
     fn get_revision(&self) -> Option<String>;
     fn sync(&self) -> Result<Option<String>, Error>;
     fn watch(self: Arc<Self>, callback: Box<dyn Fn() + Send + Sync>) -> Result<(), Error>;
+    /// Writes `path` (relative to the workspace) and publishes the change, returning the new revision.
+    /// Fails if the workspace moved on from `base_revision`.
+    fn commit_file(&self, _path: &Path, _content: &str, _message: &str, _author: &CommitAuthor, _base_revision: Option<&str>) -> Result<String, Error> {
+        bail!("This workspace source does not support editing files")
+    }
+    /// Author and message of a revision, for sources with a history
+    fn commit_info(&self, _revision: &str) -> Option<CommitInfo> {
+        None
+    }
     // async fn subscribe(&self) -> Result<watch::Receiver<bool>, Error>;
     // fn get_revision(&self) -> Result<String, Error>;
 }
 
+/// A revision of a git workspace, as shown with the jobs that ran against it.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CommitInfo {
+    pub hash: String,
+    pub author_name: String,
+    pub author_email: String,
+    pub message: String,
+    #[serde(with = "stroem_common::rfc3339")]
+    #[schemars(with = "DateTime<Utc>")]
+    pub time: DateTime<Utc>,
+}
+
+/// Who made an edit through the API.
+pub struct CommitAuthor {
+    pub name: String,
+    pub email: String,
+}
+
 pub struct WorkspaceSourceFactory {}
 impl WorkspaceSourceFactory {
     pub async fn new(config: &WorkspaceSourceConfig) -> Result<Arc<dyn WorkspaceSource>, Error> {
@@ -1,11 +1,13 @@
+use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock};
-use anyhow::{Context, Error};
-use git2::{Cred, FetchOptions, RemoteCallbacks, Repository, ResetType, Oid};
+use std::sync::{Arc, Mutex, RwLock};
+use anyhow::{anyhow, bail, Context, Error};
+use chrono::DateTime;
+use git2::{Cred, FetchOptions, PushOptions, RemoteCallbacks, Repository, ResetType, Oid, Signature};
 use tokio::time::{sleep, Duration};
-use tracing::{debug, error};
+use tracing::{debug, error, info};
 use crate::server_config::GitAuth;
-use crate::workspace_source::WorkspaceSource;
+use crate::workspace_source::{CommitAuthor, CommitInfo, WorkspaceSource};
 
 pub struct WorkspaceSourceGit {
     pub path: PathBuf,
@@ -14,6 +16,8 @@ pub struct WorkspaceSourceGit {
     pub branch: String,
     pub poll_interval: Duration,
     pub auth: Option<GitAuth>,
+    /// Keeps polling from resetting the checkout while an edit is being committed
+    checkout_lock: Mutex<()>,
 }
 
 impl WorkspaceSourceGit {
@@ -24,7 +28,8 @@ impl WorkspaceSourceGit {
             url,
             branch,
             poll_interval,
-            auth
+            auth,
+            checkout_lock: Mutex::new(()),
         }
     }
 
@@ -92,9 +97,15 @@ impl WorkspaceSourceGit {
     }
 
     fn configure_git_callbacks(&self, fetch_options: &mut FetchOptions) -> Result<(), Error> {
-        if let Some(auth) = &self.auth {
-            let mut callbacks = RemoteCallbacks::new();
+        if self.auth.is_some() {
+            fetch_options.remote_callbacks(self.git_callbacks());
+        }
+        Ok(())
+    }
 
+    fn git_callbacks(&self) -> RemoteCallbacks<'static> {
+        let mut callbacks = RemoteCallbacks::new();
+        if let Some(auth) = &self.auth {
             if let Some(ssh_key_path) = auth.ssh_key_path.clone() {
                 let username = auth.username.clone().unwrap_or_else(|| "git".to_string());
                 callbacks.credentials(move |_url, _username_from_url, _allowed_types| {
@@ -123,13 +134,26 @@ impl WorkspaceSourceGit {
                     Cred::userpass_plaintext(&username, &token)
                 });
             }
-
-            fetch_options.remote_callbacks(callbacks);
         }
+        callbacks
+    }
+
+    fn push(&self, repo: &Repository) -> Result<(), Error> {
+        let mut callbacks = self.git_callbacks();
+        // The remote reports rejections (e.g. non-fast-forward) per ref, not as a push error
+        callbacks.push_update_reference(|refname, status| match status {
+            Some(status) => Err(git2::Error::from_str(&format!("{} was rejected: {}", refname, status))),
+            None => Ok(()),
+        });
+        let mut push_options = PushOptions::new();
+        push_options.remote_callbacks(callbacks);
+        let mut remote = repo.find_remote("origin")?;
+        remote.push(&[format!("refs/heads/{0}:refs/heads/{0}", self.branch)], Some(&mut push_options))?;
         Ok(())
     }
 
     fn sync_repo(&self) -> Result<Oid, Error> {
+        let _guard = self.checkout_lock.lock().map_err(|_| anyhow!("Checkout lock poisoned"))?;
         match self.update_repo() {
             Ok(commit_hash) => Ok(commit_hash),
             Err(_) => self.clone_repo(),
@@ -151,6 +175,64 @@ impl WorkspaceSource for WorkspaceSourceGit {
         self.revision.read().ok().and_then(|r| r.clone())
     }
 
+    fn commit_file(&self, path: &Path, content: &str, message: &str, author: &CommitAuthor, base_revision: Option<&str>) -> Result<String, Error> {
+        let _guard = self.checkout_lock.lock().map_err(|_| anyhow!("Checkout lock poisoned"))?;
+        let repo = Repository::open(&self.path)?;
+        let head = repo.head()?.peel_to_commit()?;
+        if base_revision.is_some_and(|base| base != head.id().to_string()) {
+            bail!("The workspace changed since revision {}, reload and try again", base_revision.unwrap_or_default());
+        }
+
+        let file_path = self.path.join(path);
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&file_path, content)?;
+
+        let result = (|| -> Result<Oid, Error> {
+            let mut index = repo.index()?;
+            index.add_path(path)?;
+            index.write()?;
+            let tree = repo.find_tree(index.write_tree()?)?;
+            let signature = Signature::now(&author.name, &author.email)?;
+            let commit_id = repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &[&head])?;
+            self.push(&repo).context("Failed to push the change")?;
+            Ok(commit_id)
+        })();
+
+        match result {
+            Ok(commit_id) => {
+                info!("{} committed {} as {}", author.email, path.display(), commit_id);
+                self.set_revision(&Some(commit_id))?;
+                Ok(commit_id.to_string())
+            }
+            Err(e) => {
+                // Back to the last published state, including the index
+                repo.reset(head.as_object(), ResetType::Hard, None)?;
+                if head.tree()?.get_path(path).is_err() {
+                    let _ = fs::remove_file(&file_path);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn commit_info(&self, revision: &str) -> Option<CommitInfo> {
+        let result = (|| -> Result<CommitInfo, Error> {
+            let repo = Repository::open(&self.path)?;
+            let commit = repo.find_commit(Oid::from_str(revision)?)?;
+            let author = commit.author();
+            Ok(CommitInfo {
+                hash: commit.id().to_string(),
+                author_name: author.name().unwrap_or_default().to_string(),
+                author_email: author.email().unwrap_or_default().to_string(),
+                message: commit.message().unwrap_or_default().trim_end().to_string(),
+                time: DateTime::from_timestamp(commit.time().seconds(), 0).unwrap_or_default(),
+            })
+        })();
+        result.inspect_err(|e| debug!("No commit info for revision {}: {}", revision, e)).ok()
+    }
+
     fn sync(&self) -> Result<Option<String>, Error> {
         let latest_commit = self.sync_repo();
         let revision = match latest_commit {
@@ -4,6 +4,7 @@ export interface Stores {
 	user_id: string;
 	email: string;
 	name: string | null;
+	role?: 'viewer' | 'operator' | 'admin';
 }
 
 export const authUser = writable<Stores | null>(null);
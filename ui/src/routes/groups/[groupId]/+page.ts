@@ -0,0 +1,11 @@
+import type { PageLoad } from './$types';
+import { callApi } from '$lib/auth';
+
+export const load: PageLoad = async ({ fetch, params }) => {
+	const response = await callApi('/api/groups/' + params.groupId, undefined, fetch);
+	const res = await response?.json();
+
+	return {
+		"group": res,
+	};
+};
@@ -5,10 +5,9 @@ export const load: PageLoad = async ({ fetch, params }) => {
 	const response = await callApi('/api/tasks/' + params.taskId, undefined, fetch);
 	const res = await response?.json();
 
-	// const jobs_response = await fetch('/api/jobs?taskId=' + params.taskId);
-
 	return {
 		"task": res,
-		"jobs": callApi('/api/jobs?taskId=' + params.taskId, undefined, fetch).then(response => response?.json()),
+		"permissions": callApi('/api/auth/info', undefined, fetch).then(response => response?.json()).then(res => res?.data?.permissions),
+		"jobs": callApi('/api/jobs?task=' + encodeURIComponent(params.taskId), undefined, fetch).then(response => response?.json()),
 	};
 };
\ No newline at end of file
@@ -1,19 +1,35 @@
 // workflow-worker/src/main.rs
 use clap::Parser;
-use tracing::{info, error, debug};
-use tracing_subscriber;
+use tracing::{info, error, debug, warn, info_span, Instrument};
+use tracing_subscriber::filter::LevelFilter;
+use stroem_common::log_level::init_reloadable_tracing;
+use stroem_common::telemetry::{self, Telemetry, OTLP_ENDPOINT_ENV};
 use tokio::time::{self, Duration};
 use reqwest::{header, Client};
-use stroem_common::{JobRequest, JobResult};
+use stroem_common::{JobRequest, JobResult, Secret, WorkerHeartbeat, WorkerRegistration, resolve_token, shutdown, REASON_WORKER_SHUTDOWN, WORKSPACE_ENV};
+use stroem_common::log_collector::{LogCollector, LogEntry};
+use std::path::PathBuf;
 use uuid::Uuid;
 use chrono::{Utc};
 use std::sync::Arc;
-use tokio::sync::Semaphore;
-use anyhow::{bail, Error};
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinSet;
+use anyhow::{anyhow, bail, Error};
 use serde_json::json;
-use stroem_common::log_collector::LogCollectorServer;
+use stroem_common::log_collector::{LogCollectorServer, LogLimits, DEFAULT_MAX_LOG_ENTRY_SIZE, MAX_LOG_ENTRY_SIZE_ENV, STREAM_LOGS_ENV};
+use stroem_common::step_hook::{PUSHGATEWAY_ENV, STATSD_ENV, STATSD_PREFIX_ENV};
+use stroem_common::http_retry::send_with_retry;
+use stroem_common::wait::STATUS_SUSPENDED;
+use stroem_common::protocol::{MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, PROTOCOL_VERSION_HEADER, PROJECTS_PARAM, PROTOCOL_VERSION_PARAM, REVISION_HEADER, WAIT_HEADER, WAIT_PARAM};
+use stroem_common::tls::{TlsConfig, CA_CERT_ENV, CLIENT_CERT_ENV, CLIENT_KEY_ENV, TLS_INSECURE_ENV};
+use std::collections::HashMap;
 
 mod runner_local;
+mod outbox;
+mod standby;
+
+use outbox::Outbox;
+use runner_local::RunOutcome;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -22,66 +38,349 @@ struct Args {
     server: String,
     #[arg(short, long)]
     verbose: bool,
+    /// Log level (off, error, warn, info, debug, trace), overrides --verbose. Send SIGHUP to toggle TRACE at runtime.
+    #[arg(long)]
+    log_level: Option<LevelFilter>,
     #[arg(long, default_value = "5")]
     max_runners: usize,
-    #[arg(short, long, required = true)]
-    token: String
+    #[arg(short, long, env = "STROEM_WORKER_TOKEN", hide_env_values = true)]
+    token: Option<Secret>,
+    /// File containing the worker token
+    #[arg(long, conflicts_with = "token")]
+    token_file: Option<PathBuf>,
+    /// Worker label in the form key=value, can be repeated
+    #[arg(long = "label", value_parser = parse_label)]
+    labels: Vec<(String, String)>,
+    /// Take jobs of this project, can be repeated; `*` takes jobs of every project, the default project's without it
+    #[arg(long = "project")]
+    projects: Vec<String>,
+    /// Interval between heartbeats sent to the server, in seconds
+    #[arg(long, default_value = "15")]
+    heartbeat_interval: u64,
+    /// StatsD address (host:port) runners send per-step metrics to
+    #[arg(long, env = STATSD_ENV)]
+    statsd: Option<String>,
+    #[arg(long, env = STATSD_PREFIX_ENV, default_value = "stroem")]
+    statsd_prefix: String,
+    /// Prometheus Pushgateway URL runners push per-step metrics to
+    #[arg(long, env = PUSHGATEWAY_ENV)]
+    pushgateway: Option<String>,
+    /// PEM bundle of extra CA certificates to trust for the server
+    #[arg(long, env = CA_CERT_ENV)]
+    ca_cert: Option<PathBuf>,
+    /// PEM client certificate for mutual TLS, may include the key
+    #[arg(long, env = CLIENT_CERT_ENV)]
+    client_cert: Option<PathBuf>,
+    /// PEM private key of the client certificate
+    #[arg(long, env = CLIENT_KEY_ENV, requires = "client_cert")]
+    client_key: Option<PathBuf>,
+    /// Skip verifying the server certificate, for air-gapped labs only
+    #[arg(long, env = TLS_INSECURE_ENV)]
+    tls_insecure: bool,
+    /// Log messages longer than this many bytes are truncated by the runners
+    #[arg(long, env = MAX_LOG_ENTRY_SIZE_ENV, default_value_t = DEFAULT_MAX_LOG_ENTRY_SIZE)]
+    max_log_entry_size: usize,
+    /// Have runners stream logs to the server as NDJSON instead of posting them in batches
+    #[arg(long, env = STREAM_LOGS_ENV)]
+    stream_logs: bool,
+    /// Directory job results are kept in until the server can be reached
+    #[arg(long, default_value = "/tmp/stroem-outbox")]
+    outbox: PathBuf,
+    /// OTLP/HTTP base URL of the collector the worker and its runners export trace spans to
+    #[arg(long, env = OTLP_ENDPOINT_ENV)]
+    otlp_endpoint: Option<String>,
+    /// On SIGTERM or Ctrl-C, seconds to wait for running jobs before interrupting them
+    #[arg(long, default_value = "300")]
+    drain_timeout: u64,
+    /// Folder the runners keep the workspace revisions in, and a copy of one per running job
+    #[arg(long, env = WORKSPACE_ENV, default_value = "/tmp/workspace")]
+    workspace: PathBuf,
+    /// Sync the workspace and pull the docker images of the actions on start and on every new revision,
+    /// instead of when the next job starts
+    #[arg(long)]
+    warm_standby: bool,
+    /// Hold job polls open so the server hands out jobs as soon as they're queued, instead of polling every 2 seconds
+    #[arg(long)]
+    push: bool,
+}
+
+fn parse_label(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .ok_or_else(|| format!("invalid label '{}', expected key=value", s))
 }
 
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
-    let log_level = if args.verbose { tracing::Level::TRACE } else { tracing::Level::INFO };
-    tracing_subscriber::fmt()
-        .with_max_level(log_level)
-        .init();
+    let log_level = args.log_level.unwrap_or(if args.verbose { LevelFilter::TRACE } else { LevelFilter::INFO });
+    let telemetry = Telemetry::new(args.otlp_endpoint.as_deref(), "stroem-worker").unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let log_level = init_reloadable_tracing(log_level, &telemetry);
+    if let Err(e) = log_level.watch_sighup() {
+        error!("Failed to listen for SIGHUP: {}", e);
+    }
 
-    let client = Client::new();
+    let tls = TlsConfig {
+        ca_cert: args.ca_cert.clone(),
+        client_cert: args.client_cert.clone(),
+        client_key: args.client_key.clone(),
+        insecure: args.tls_insecure,
+    };
+    let client = tls.client().unwrap_or_else(|e| {
+        error!("Failed to set up HTTP client: {}", e);
+        std::process::exit(1);
+    });
     let worker_id = Uuid::new_v4().to_string();
-    let token = args.token;
+    let token = match resolve_token(args.token, args.token_file.as_deref()) {
+        Ok(token) => token.expose().to_string(),
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
     info!("Worker started with ID: {}, polling jobs from {}, max runners: {}", worker_id, args.server, args.max_runners);
 
+    let outbox = Outbox::new(args.outbox.clone()).unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(1);
+    });
+    let outbox = Arc::new(outbox);
+
     let semaphore = Arc::new(Semaphore::new(args.max_runners));
 
+    let mut runner_envs = HashMap::new();
+    if let Some(statsd) = &args.statsd {
+        runner_envs.insert(STATSD_ENV.to_string(), statsd.clone());
+        runner_envs.insert(STATSD_PREFIX_ENV.to_string(), args.statsd_prefix.clone());
+    }
+    if let Some(pushgateway) = &args.pushgateway {
+        runner_envs.insert(PUSHGATEWAY_ENV.to_string(), pushgateway.clone());
+    }
+    runner_envs.extend(tls.envs());
+    runner_envs.extend(LogLimits {
+        max_entry_size: args.max_log_entry_size,
+    }.envs());
+    if args.stream_logs {
+        runner_envs.insert(STREAM_LOGS_ENV.to_string(), "true".to_string());
+    }
+    if let Some(otlp_endpoint) = &args.otlp_endpoint {
+        runner_envs.insert(OTLP_ENDPOINT_ENV.to_string(), otlp_endpoint.clone());
+    }
+    runner_envs.insert(WORKSPACE_ENV.to_string(), args.workspace.to_string_lossy().to_string());
+    let runner_envs = Arc::new(runner_envs);
+
+    let registration = WorkerRegistration {
+        capacity: args.max_runners as u32,
+        labels: args.labels.iter().cloned().collect(),
+    };
+    if let Err(e) = register(&client, &args.server, &worker_id, &token, &registration).await {
+        error!("Failed to register worker: {}", e);
+    }
+    tokio::spawn(heartbeat_loop(
+        client.clone(),
+        args.server.clone(),
+        worker_id.clone(),
+        token.clone(),
+        registration,
+        semaphore.clone(),
+        Duration::from_secs(args.heartbeat_interval),
+    ));
+    tokio::spawn(outbox.clone().resubmit_loop(
+        client.clone(),
+        args.server.clone(),
+        worker_id.clone(),
+        token.clone(),
+        Duration::from_secs(args.heartbeat_interval),
+    ));
+
+    let (revision_tx, revision_rx) = watch::channel(None);
+    if args.warm_standby {
+        tokio::spawn(standby::run(
+            client.clone(),
+            args.server.clone(),
+            token.clone(),
+            args.workspace.clone(),
+            revision_rx,
+        ));
+    }
+
+    let (interrupt_tx, interrupt_rx) = watch::channel(false);
+    let mut running = JoinSet::new();
+    let shutdown = shutdown::signal();
+    tokio::pin!(shutdown);
+
+    // Polls are never cancelled halfway, the server may already have leased jobs to this worker
     loop {
-        let permit = match semaphore.clone().acquire_owned().await {
-            Ok(permit) => permit,
+        while running.try_join_next().is_some() {}
+        // Biased, so shutdown is noticed even when a permit is free, as after a held poll
+        let permit = tokio::select! {
+            biased;
+            _ = &mut shutdown => break,
+            permit = semaphore.clone().acquire_owned() => permit,
+        };
+        let mut permits = match permit {
+            Ok(permit) => vec![permit],
             Err(e) => {
                 error!("Semaphore acquire failed: {}", e);
                 time::sleep(Duration::from_secs(5)).await;
                 continue;
             }
         };
+        // Ask for as many jobs as there are free runners
+        while let Ok(permit) = semaphore.clone().try_acquire_owned() {
+            permits.push(permit);
+        }
 
-        match poll_job(&client, &args.server, &worker_id, &token).await {
-            Ok(Some(job)) => {
-                let client_clone = client.clone();
-                let server = args.server.clone();
-                let worker_id_clone = worker_id.clone();
-                let token_clone = token.clone();
-                tokio::spawn(async move {
-                    let _permit = permit;  // Hold the permit until this task completes
-                    if let Err(e) = execute_job(&client_clone, &job, &server, &worker_id_clone, &token_clone).await {
-                        error!("Failed to execute job {:?}: {}", job, e);
+        let wait = args.push.then_some(PUSH_WAIT);
+        let poll = poll_jobs(&client, &args.server, &worker_id, &token, permits.len(), &args.projects, wait).await
+            .inspect(|poll| {
+                if poll.revision.is_some() {
+                    revision_tx.send_replace(poll.revision.clone());
+                }
+            });
+        match poll {
+            Ok(Poll { jobs, .. }) if !jobs.is_empty() => {
+                // Permits left over when fewer jobs were returned are released with the iterator
+                for (job, permit) in jobs.into_iter().zip(permits) {
+                    let client_clone = client.clone();
+                    let server = args.server.clone();
+                    let worker_id_clone = worker_id.clone();
+                    let token_clone = token.clone();
+                    let runner_envs = runner_envs.clone();
+                    let outbox = outbox.clone();
+                    let interrupt = interrupt_rx.clone();
+                    let span = info_span!("job", job_id = %job.uuid.unwrap_or_default(), name = job.spec.name());
+                    if let Some(traceparent) = &job.traceparent {
+                        telemetry::set_parent(&span, traceparent);
                     }
-                });
+                    running.spawn(async move {
+                        let _permit = permit;  // Hold the permit until this task completes
+                        let result = execute_job(&client_clone, &job, &server, &worker_id_clone, &token_clone, &runner_envs, interrupt).await;
+                        let result = match result {
+                            Ok(result) => outbox.submit(&client_clone, &server, &worker_id_clone, &token_clone, &job.uuid.unwrap_or_default().to_string(), result).await,
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = result {
+                            error!("Failed to execute job {:?}: {}", job, e);
+                        }
+                    }.instrument(span));
+                }
             }
-            Ok(None) => {
+            // The server already waited for a job to be queued, poll again right away
+            Ok(Poll { held: true, .. }) => drop(permits),
+            // Servers without push mode answer right away, those are polled like before
+            Ok(_) => {
                 debug!("No jobs available, waiting...");
-                drop(permit);  // Release the permit if no job is available
-                time::sleep(Duration::from_secs(2)).await;
+                drop(permits);  // Release the permits if no job is available
+                tokio::select! {
+                    _ = &mut shutdown => break,
+                    _ = time::sleep(Duration::from_secs(2)) => {}
+                }
             }
             Err(e) => {
                 error!("Error polling job: {}", e);
-                drop(permit);  // Release the permit on error
-                time::sleep(Duration::from_secs(5)).await;
+                drop(permits);  // Release the permits on error
+                tokio::select! {
+                    _ = &mut shutdown => break,
+                    _ = time::sleep(Duration::from_secs(5)) => {}
+                }
             }
         }
     }
+
+    drain(running, interrupt_tx, Duration::from_secs(args.drain_timeout)).await;
+    telemetry.shutdown();
+    info!("Worker stopped");
 }
 
-async fn poll_job(client: &Client, server: &str, worker_id: &str, token: &str) -> Result<Option<JobRequest>, Error> {
-    let url = format!("{}/jobs/next?worker_id={}", server, worker_id);
+/// How long interrupted jobs get to report their result before the worker exits anyway.
+/// Results that can't be delivered in time stay in the outbox.
+const INTERRUPT_GRACE: Duration = Duration::from_secs(30);
+
+/// Waits for the running jobs to finish, interrupting the ones still running after `timeout`.
+async fn drain(mut running: JoinSet<()>, interrupt: watch::Sender<bool>, timeout: Duration) {
+    if running.is_empty() {
+        return;
+    }
+    info!("Stopped polling, waiting up to {:?} for {} running jobs", timeout, running.len());
+    let finished = time::timeout(timeout, async {
+        while running.join_next().await.is_some() {}
+    }).await;
+    if finished.is_ok() {
+        return;
+    }
+
+    warn!("Interrupting {} jobs still running", running.len());
+    let _ = interrupt.send(true);
+    let reported = time::timeout(INTERRUPT_GRACE, async {
+        while running.join_next().await.is_some() {}
+    }).await;
+    if reported.is_err() {
+        error!("{} interrupted jobs did not report back in time", running.len());
+    }
+}
+
+async fn register(client: &Client, server: &str, worker_id: &str, token: &str, registration: &WorkerRegistration) -> Result<(), Error> {
+    let url = format!("{}/workers/register?worker_id={}", server, worker_id);
+    send_with_retry(client.post(&url)
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .json(registration))
+        .await?
+        .error_for_status()?;
+    info!("Registered worker {} with capacity {}", worker_id, registration.capacity);
+    Ok(())
+}
+
+async fn heartbeat_loop(client: Client, server: String, worker_id: String, token: String, registration: WorkerRegistration, semaphore: Arc<Semaphore>, interval: Duration) {
+    let url = format!("{}/workers/{}/heartbeat", server, worker_id);
+    loop {
+        time::sleep(interval).await;
+        let heartbeat = WorkerHeartbeat {
+            running: (registration.capacity as usize).saturating_sub(semaphore.available_permits()) as u32,
+        };
+        let response = send_with_retry(client.post(&url)
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .json(&heartbeat))
+            .await;
+        match response {
+            Ok(resp) if resp.status() == reqwest::StatusCode::NOT_FOUND => {
+                // The server lost track of us (e.g. database reset), register again
+                if let Err(e) = register(&client, &server, &worker_id, &token, &registration).await {
+                    error!("Failed to re-register worker: {}", e);
+                }
+            }
+            Ok(resp) if !resp.status().is_success() => error!("Heartbeat rejected: {}", resp.status()),
+            Ok(_) => debug!("Heartbeat sent: {} running", heartbeat.running),
+            Err(e) => error!("Failed to send heartbeat: {}", e),
+        }
+    }
+}
+
+/// How long the server is asked to hold polls in push mode. Shutdown waits for the poll in flight.
+const PUSH_WAIT: Duration = Duration::from_secs(20);
+
+struct Poll {
+    jobs: Vec<JobRequest>,
+    /// Workspace revision, if the server sends it
+    revision: Option<String>,
+    /// Whether the server held the poll until a job was queued, servers without push mode answer right away
+    held: bool,
+}
+
+/// Returns the jobs leased to this worker, of `projects` if any, asking the server to hold the poll up to `wait`
+/// for one to be queued.
+async fn poll_jobs(client: &Client, server: &str, worker_id: &str, token: &str, count: usize, projects: &[String], wait: Option<Duration>) -> Result<Poll, Error> {
+    let mut url = format!("{}/jobs/next?worker_id={}&count={}&{}={}", server, worker_id, count, PROTOCOL_VERSION_PARAM, PROTOCOL_VERSION);
+    if !projects.is_empty() {
+        url.push_str(&format!("&{}={}", PROJECTS_PARAM, projects.join(",")));
+    }
+    if let Some(wait) = wait {
+        url.push_str(&format!("&{}={}", WAIT_PARAM, wait.as_secs()));
+    }
+    // Sent once: a poll leases the jobs it returns, a retry after a lost response would lease others
     let response = client.get(&url)
         .header(header::AUTHORIZATION, format!("Bearer {}", token))
         .send()
@@ -89,20 +388,35 @@ async fn poll_job(client: &Client, server: &str, worker_id: &str, token: &str) -
         // .map_err(|e| format!("Failed to poll job: {}", e))?;
 
     if response.status().is_success() {
-        let job = response.json::<Option<JobRequest>>()
+        // Servers from before versioning don't send the header
+        let version = match response.headers().get(PROTOCOL_VERSION_HEADER) {
+            Some(version) => version.to_str().ok().and_then(|version| version.parse().ok())
+                .ok_or_else(|| anyhow!("Invalid protocol version from server: {:?}", version))?,
+            None => MIN_PROTOCOL_VERSION,
+        };
+        if version < MIN_PROTOCOL_VERSION {
+            bail!("Server speaks protocol version {}, this worker needs at least {}", version, MIN_PROTOCOL_VERSION);
+        }
+        let revision = response.headers().get(REVISION_HEADER)
+            .and_then(|revision| revision.to_str().ok())
+            .map(|revision| revision.to_string());
+        let held = response.headers().contains_key(WAIT_HEADER);
+        let jobs = response.json::<Vec<JobRequest>>()
             .await?;
             //.map_err(|e| format!("Failed to parse job: {}", e))?;
-        Ok(job)
+        Ok(Poll { jobs, revision, held })
     } else {
         bail!("Server error: {}", response.status())
     }
 }
 
-async fn execute_job(client: &Client, job: &JobRequest, server: &str, worker_id: &str, token: &str) -> Result<(), Error> {
+/// Runs the job, returning the result to submit.
+async fn execute_job(client: &Client, job: &JobRequest, server: &str, worker_id: &str, token: &str, runner_envs: &HashMap<String, String>, mut interrupt: watch::Receiver<bool>) -> Result<JobResult, Error> {
     let uuid = job.uuid.as_ref().unwrap();
     let start_time = Utc::now();
 
     let log_collector = Arc::new(LogCollectorServer::new(
+        client.clone(),
         server.to_string(),
         job.uuid.as_ref().unwrap().to_string(),
         worker_id.to_string(),
@@ -111,54 +425,67 @@ async fn execute_job(client: &Client, job: &JobRequest, server: &str, worker_id:
         Some(10),
     ));
 
-    // TODO: Render input variables
-
     let payload = json!({
         "start_datetime": start_time,
         "input": &job.input,
     });
 
-    client.post(format!("{}/jobs/{}/start?worker_id={}", server, uuid, worker_id))
+    send_with_retry(telemetry::propagate(client.post(format!("{}/jobs/{}/start?worker_id={}", server, uuid, worker_id)))
         .header(header::AUTHORIZATION, format!("Bearer {}", token))
-        .json(&payload)
-        .send()
+        .json(&payload))
         .await?;
         //.map_err(|e| format!("Failed to update job start: {}", e))?
         //.error_for_status()
         //.map_err(|e| format!("Job start update failed: {}", e))?;
 
-    let (exit_success, output) = runner_local::start(job, server, token, worker_id, log_collector).await?;
+    let interrupted = async move {
+        if interrupt.wait_for(|interrupt| *interrupt).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+    // Dropping the runner's future kills its process
+    let (outcome, reason) = tokio::select! {
+        result = runner_local::start(job, server, token, worker_id, runner_envs, log_collector.clone()) => (result?, None),
+        _ = interrupted => {
+            warn!("Job {} interrupted, the worker is shutting down", uuid);
+            log_collector.log(LogEntry::from_line("Job interrupted, the worker is shutting down".to_string(), true)).await?;
+            log_collector.flush().await?;
+            (RunOutcome::Completed { success: false, output: None }, Some(REASON_WORKER_SHUTDOWN.to_string()))
+        }
+    };
+    let (exit_success, output, status) = match outcome {
+        RunOutcome::Completed { success, output } => (success, output, None),
+        RunOutcome::Suspended => (false, None, Some(STATUS_SUSPENDED.to_string())),
+    };
     let end_time = Utc::now();
 
     let result = JobResult {
+        protocol_version: PROTOCOL_VERSION,
         success: exit_success,
             start_datetime: start_time,
             end_datetime: end_time,
             input: job.input.clone(), // probably also not needed
             output,
             revision: None,
+            attempts: None,
+            status,
+            links: None,
+            environment: None,
+            reason,
     };
 
-    let url = format!("{}/jobs/{}/results?worker_id={}", server, uuid, worker_id);
-    debug!("{}", url);
-    let result = client.post(&url)
-        .header(header::AUTHORIZATION, format!("Bearer {}", token))
-        .json(&result)
-        .send()
-        .await?;
-    debug!("{:?}", result);
-    debug!("{:?}", result.text().await?);
-
     // common::send_result(client, server, &result).await?;
     //    .map_err(|e| {
     //        error!("Failed to send result for job {}: {}", uuid, e);
     //        e
     // })?;
 
-    if exit_success {
+    if result.status.is_some() {
+        info!("Runner suspended the job");
+    } else if exit_success {
         info!("Runner completed successfully");
-        Ok(())
     } else {
-        bail!("Runner failed".to_string())
+        error!("Runner failed");
     }
+    Ok(result)
 }
\ No newline at end of file
@@ -0,0 +1,119 @@
+// workflow-worker/src/outbox.rs
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{anyhow, Error};
+use reqwest::{header, Client};
+use serde::{Deserialize, Serialize};
+use stroem_common::JobResult;
+use stroem_common::http_retry::{is_retryable_status, send_with_key};
+use stroem_common::telemetry;
+use tracing::{error, info, warn};
+
+/// A job result the server couldn't be reached for, kept on disk until it's delivered.
+#[derive(Serialize, Deserialize)]
+struct PendingResult {
+    job_id: String,
+    /// Reused on every resubmission, so the server applies the result once
+    idempotency_key: String,
+    result: JobResult,
+}
+
+pub struct Outbox {
+    dir: PathBuf,
+}
+
+impl Outbox {
+    pub fn new(dir: PathBuf) -> Result<Self, Error> {
+        fs::create_dir_all(&dir).map_err(|e| anyhow!("Failed to create outbox {}: {}", dir.display(), e))?;
+        Ok(Self { dir })
+    }
+
+    pub async fn submit(&self, client: &Client, server: &str, worker_id: &str, token: &str, job_id: &str, result: JobResult) -> Result<(), Error> {
+        let pending = PendingResult {
+            job_id: job_id.to_string(),
+            idempotency_key: uuid::Uuid::new_v4().to_string(),
+            result,
+        };
+        match Self::send(client, server, worker_id, token, &pending).await {
+            Ok(()) => Ok(()),
+            Err(SendError::Rejected(e)) => Err(anyhow!("Server rejected result of job {}: {}", job_id, e)),
+            Err(SendError::Unavailable(e)) => {
+                warn!("Failed to submit result of job {}, keeping it for later: {}", job_id, e);
+                self.save(&pending)
+            }
+        }
+    }
+
+    /// Resubmits stored results, starting with the ones left over from before a restart.
+    pub async fn resubmit_loop(self: Arc<Self>, client: Client, server: String, worker_id: String, token: String, interval: Duration) {
+        loop {
+            self.flush(&client, &server, &worker_id, &token).await;
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Resubmits the stored results; ones the server rejects outright are dropped.
+    async fn flush(&self, client: &Client, server: &str, worker_id: &str, token: &str) {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("Failed to read outbox {}: {}", self.dir.display(), e);
+                return;
+            }
+        };
+        for path in entries.flatten().map(|entry| entry.path()).filter(|path| path.extension().is_some_and(|ext| ext == "json")) {
+            let pending: PendingResult = match fs::read(&path).map_err(Error::from).and_then(|data| Ok(serde_json::from_slice(&data)?)) {
+                Ok(pending) => pending,
+                Err(e) => {
+                    error!("Failed to read pending result {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            match Self::send(client, server, worker_id, token, &pending).await {
+                Ok(()) => info!("Submitted pending result of job {}", pending.job_id),
+                Err(SendError::Rejected(e)) => error!("Server rejected pending result of job {}, dropping it: {}", pending.job_id, e),
+                Err(SendError::Unavailable(e)) => {
+                    warn!("Server still unavailable for pending results: {}", e);
+                    return;
+                }
+            }
+            if let Err(e) = fs::remove_file(&path) {
+                error!("Failed to remove pending result {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    fn save(&self, pending: &PendingResult) -> Result<(), Error> {
+        let path = self.dir.join(format!("{}.json", pending.job_id));
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(pending)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    async fn send(client: &Client, server: &str, worker_id: &str, token: &str, pending: &PendingResult) -> Result<(), SendError> {
+        let url = format!("{}/jobs/{}/results?worker_id={}", server, pending.job_id, worker_id);
+        let response = send_with_key(telemetry::propagate(client.post(&url))
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .json(&pending.result), &pending.idempotency_key)
+            .await
+            .map_err(|e| SendError::Unavailable(e.into()))?;
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else if status.is_client_error() && !is_retryable_status(status) {
+            Err(SendError::Rejected(anyhow!("{}", status)))
+        } else {
+            Err(SendError::Unavailable(anyhow!("{}", status)))
+        }
+    }
+}
+
+enum SendError {
+    /// Resubmitting won't help
+    Rejected(Error),
+    /// The server couldn't be reached, failed, or asked to come back later, e.g. when rate limited
+    Unavailable(Error),
+}
@@ -1,40 +1,41 @@
 // workflow-worker/src/runner_local.rs
 use std::env;
 use std::sync::Arc;
-use stroem_common::{run, JobRequest, log_collector::LogCollector, log_collector::LogEntry};
-use chrono::Utc;
+use std::collections::HashMap;
+use stroem_common::{run_with_status, JobRequest, JobSpec, WORKER_TOKEN_ENV, log_collector::LogCollector, log_collector::LogEntry};
+use stroem_common::telemetry::{self, TRACEPARENT_ENV};
+use stroem_common::wait::EXIT_SUSPENDED;
 use tracing::{info, error};
 use tracing::log::debug;
 use anyhow::Error;
 use serde_json::Value;
 
-pub async fn start(job: &JobRequest, server: &str, token: &str, worker_id: &str, log_collector: Arc<(dyn LogCollector + Send + Sync)>) -> Result<(bool, Option<Value>), Error> {
+/// How the runner of a job ended
+pub enum RunOutcome {
+    Completed { success: bool, output: Option<Value> },
+    /// The runner handed the job back to the server until its wait is over
+    Suspended,
+}
+
+pub async fn start(job: &JobRequest, server: &str, token: &str, worker_id: &str, runner_envs: &HashMap<String, String>, log_collector: Arc<dyn LogCollector + Send + Sync>) -> Result<RunOutcome, Error> {
     let worker_path = match env::current_exe() {
         Ok(path) => path,
         Err(e) => {
             let msg = format!("Failed to get current executable path: {}", e);
             error!(msg);
-            let entry = LogEntry {
-                timestamp: Utc::now(),
-                is_stderr: true,
-                message: msg,
-            };
+            let entry = LogEntry::new(msg, true);
             log_collector.log(entry).await?;
-            return Ok((false, None));
+            return Ok(RunOutcome::Completed { success: false, output: None });
         }
     };
     let runner_path = match worker_path.parent() {
-        Some(path) => path.join("stroem-runner"),
+        Some(path) => path.join(format!("stroem-runner{}", env::consts::EXE_SUFFIX)),
         None => {
             let msg = "Failed to get parent directory of worker binary".to_string();
             error!(msg);
-            let entry = LogEntry {
-                timestamp: Utc::now(),
-                is_stderr: true,
-                message: msg,
-            };
+            let entry = LogEntry::new(msg, true);
             log_collector.log(entry).await?;
-            return Ok((false, None));
+            return Ok(RunOutcome::Completed { success: false, output: None });
         }
     };
 
@@ -43,28 +44,16 @@ pub async fn start(job: &JobRequest, server: &str, token: &str, worker_id: &str,
 
     let mut runner_args = vec![
         "--server".to_string(), server.to_string(),
-        "--token".to_string(), token.to_string(),
         "--job-id".to_string(), uuid.to_string(),
         "--worker-id".to_string(), worker_id.to_string(),
         "--verbose".to_string(),
     ];
 
-    if let Some(task) = &job.task {
-        runner_args.push("--task".to_string());
-        runner_args.push(task.clone());
-    } else if let Some(action) = &job.action {
-        runner_args.push("--action".to_string());
-        runner_args.push(action.clone());
-    } else {
-        let msg = "Job must specify either task or action".to_string();
-        let entry = LogEntry {
-            timestamp: Utc::now(),
-            is_stderr: true,
-            message: msg,
-        };
-        log_collector.log(entry).await?;
-        return Ok((false, None));
-    }
+    runner_args.push(match &job.spec {
+        JobSpec::Task { .. } => "--task".to_string(),
+        JobSpec::Action { .. } => "--action".to_string(),
+    });
+    runner_args.push(job.spec.name().to_string());
 
     if let Some(input) = &job.input {
         match serde_json::to_string(input) {
@@ -75,18 +64,34 @@ pub async fn start(job: &JobRequest, server: &str, token: &str, worker_id: &str,
             Err(e) => {
                 let msg = format!("Failed to serialize input: {}", e);
                 error!(msg);
-                let entry = LogEntry {
-                    timestamp: Utc::now(),
-                    is_stderr: true,
-                    message: msg,
-                };
+                let entry = LogEntry::new(msg, true);
                 log_collector.log(entry).await?;
-                return Ok((false, None));
+                return Ok(RunOutcome::Completed { success: false, output: None });
             }
         }
     }
 
+    if let Some(project) = &job.project {
+        runner_args.push("--project".to_string());
+        runner_args.push(project.clone());
+    }
+
+    // Outputs of many steps can outgrow the limits on arguments, they go through stdin
+    let resume = job.resume.as_ref().map(serde_json::to_string).transpose()?;
+    if resume.is_some() {
+        runner_args.push("--resume-stdin".to_string());
+    }
+
     debug!("Executing: {:?} {:?}", runner_path, runner_args);
 
-    run(runner_path.to_str().unwrap(), Some(runner_args), None, None, log_collector).await
+    let mut envs = runner_envs.clone();
+    envs.insert(WORKER_TOKEN_ENV.to_string(), token.to_string());
+    if let Some(traceparent) = telemetry::current_traceparent() {
+        envs.insert(TRACEPARENT_ENV.to_string(), traceparent);
+    }
+    let (status, output, _links) = run_with_status(runner_path.to_str().unwrap(), Some(runner_args), resume, None, Some(envs), log_collector).await?;
+    if status.code() == Some(EXIT_SUSPENDED) {
+        return Ok(RunOutcome::Suspended);
+    }
+    Ok(RunOutcome::Completed { success: status.success(), output })
 }
\ No newline at end of file
@@ -0,0 +1,56 @@
+// workflow-worker/src/standby.rs
+//! Warm standby: keeps the runners' workspace synced and the docker images of the actions pulled,
+//! so the first job after a deploy doesn't wait for them.
+use std::path::PathBuf;
+use anyhow::Error;
+use reqwest::Client;
+use tokio::process::Command;
+use tokio::sync::watch;
+use tokio::time::{self, Duration};
+use tracing::{error, info};
+use stroem_common::workspace_client::WorkspaceClient;
+
+/// Delay before trying again after a failed sync
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Warms up on start, then whenever job polls report a revision other than the one synced.
+pub async fn run(client: Client, server: String, token: String, workspace: PathBuf, mut revisions: watch::Receiver<Option<String>>) {
+    let mut workspace = WorkspaceClient::new(workspace).await;
+    let mut synced = None;
+    loop {
+        match warm_up(&mut workspace, &client, &server, &token).await {
+            Ok(revision) => {
+                info!("Warm standby ready for revision {}", revision);
+                synced = Some(revision);
+            }
+            Err(e) => {
+                error!("Warm standby failed: {}", e);
+                time::sleep(RETRY_DELAY).await;
+            }
+        }
+        if revisions.wait_for(|revision| revision.is_some() && *revision != synced).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn warm_up(workspace: &mut WorkspaceClient, client: &Client, server: &str, token: &str) -> Result<String, Error> {
+    let revision = workspace.sync(client, server, token).await?;
+    workspace.read_workflows()?;
+    let images: Vec<String> = workspace.workflows.as_ref()
+        .map(|workflows| workflows.docker_images().into_iter().map(String::from).collect())
+        .unwrap_or_default();
+    for image in images {
+        pull(&image).await;
+    }
+    Ok(revision)
+}
+
+/// Failed pulls are only logged, the job using the image will pull it again.
+async fn pull(image: &str) {
+    match Command::new("docker").args(["pull", "--quiet", image]).output().await {
+        Ok(output) if output.status.success() => info!("Pulled docker image {}", image),
+        Ok(output) => error!("Failed to pull docker image {}: {}", image, String::from_utf8_lossy(&output.stderr).trim()),
+        Err(e) => error!("Failed to run docker pull for {}: {}", image, e),
+    }
+}